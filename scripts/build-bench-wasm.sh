@@ -0,0 +1,5 @@
+set -e
+# Builds the token canister used by the benchmarks in `src/benchmarks`. The wasm has the
+# benchmark-only endpoints, so it must never be deployed.
+cargo build --target wasm32-unknown-unknown --package is20-token-canister --features export-api,benchmarks --release
+ic-wasm target/wasm32-unknown-unknown/release/is20-token-canister.wasm -o target/wasm32-unknown-unknown/release/token-bench.wasm shrink
@@ -0,0 +1,7 @@
+set -e
+# Builds the canisters used by the integration tests in `src/tests`. The factory is built with the
+# test-only endpoints, so it must never be deployed from these artifacts.
+cargo build --target wasm32-unknown-unknown --package is20-token-canister --features export-api --release
+ic-wasm target/wasm32-unknown-unknown/release/is20-token-canister.wasm -o target/wasm32-unknown-unknown/release/token.wasm shrink
+cargo build --target wasm32-unknown-unknown --package token-factory --features export-api,test-endpoints --release
+ic-wasm target/wasm32-unknown-unknown/release/token-factory.wasm -o target/wasm32-unknown-unknown/release/factory-test.wasm shrink
@@ -0,0 +1,212 @@
+//! Instruction count benchmarks of the token canister.
+//!
+//! The benchmarks run the token wasm built with the `benchmarks` feature in PocketIC. Every
+//! benchmark calls a `bench_*` endpoint of the token, which returns the number of instructions
+//! the measured operation took inside the canister. The results are compared to the baseline in
+//! `results.json`, and the run fails if any benchmark takes more than `TOLERANCE_PERCENT` more
+//! instructions than the baseline. To run them:
+//!
+//! ```sh
+//! ./scripts/build-bench-wasm.sh
+//! POCKET_IC_BIN=/path/to/pocket-ic cargo run -p benchmarks --release
+//! ```
+//!
+//! Pass `--save` to write the results as the new baseline, e.g. after an intended change of the
+//! performance.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use candid::{encode_args, CandidType, Nat, Principal};
+use integration_tests::env::{admin, load_wasm, metadata, CANISTER_CYCLES};
+use integration_tests::types::{Account, TransferArgs};
+use pocket_ic::PocketIc;
+use serde::Deserialize;
+
+const BENCH_WASM: &str = "token-bench.wasm";
+
+/// Number of the holders (and the ledger records) created before the benchmarks run.
+const HOLDERS: u32 = 100_000;
+
+/// Number of the holders created by one `bench_setup` call, small enough to fit in the
+/// instruction limit of a call.
+const SETUP_CHUNK: u32 = 5_000;
+
+/// Difference from the baseline allowed without failing the run, as the instruction counts of the
+/// stable structures depend slightly on the layout of the memory.
+const TOLERANCE_PERCENT: u64 = 2;
+
+/// Must be the same as `benchmarks::bench_holder` of the token canister.
+fn bench_holder(id: u32) -> Principal {
+    let mut bytes = [0xbe; 29];
+    bytes[..4].copy_from_slice(&id.to_be_bytes());
+    Principal::from_slice(&bytes)
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct BatchTransferArgs {
+    receiver: Account,
+    amount: Nat,
+}
+
+type Results = BTreeMap<String, u64>;
+
+struct Bench {
+    pic: PocketIc,
+    token: Principal,
+    wasm: Vec<u8>,
+}
+
+impl Bench {
+    fn new(wasm: Vec<u8>) -> Self {
+        let pic = PocketIc::new();
+        let token = pic.create_canister_with_settings(Some(admin()), None);
+        pic.add_cycles(token, CANISTER_CYCLES);
+        let args = encode_args((metadata("Bench", admin(), 0), Nat::from(u64::MAX))).unwrap();
+        pic.install_canister(token, wasm.clone(), args, Some(admin()));
+
+        Self { pic, token, wasm }
+    }
+
+    fn update<A: candid::utils::ArgumentEncoder>(&self, method: &str, args: A) -> Vec<u8> {
+        let result = self
+            .pic
+            .update_call(self.token, admin(), method, encode_args(args).unwrap());
+        reply(method, result)
+    }
+
+    fn query<A: candid::utils::ArgumentEncoder>(&self, method: &str, args: A) -> Vec<u8> {
+        let result = self
+            .pic
+            .query_call(self.token, admin(), method, encode_args(args).unwrap());
+        reply(method, result)
+    }
+
+    fn setup(&self) {
+        for first in (0..HOLDERS).step_by(SETUP_CHUNK as usize) {
+            self.update("bench_setup", (first, SETUP_CHUNK));
+        }
+    }
+
+    fn run(&self) -> Results {
+        let mut results = Results::new();
+
+        let transfer = TransferArgs::new(bench_holder(0), 1_000);
+        let bytes = self.update("bench_icrc1_transfer", (transfer,));
+        results.insert("icrc1_transfer".into(), decode_count(&bytes));
+
+        for count in [100, 1_000] {
+            let transfers: Vec<_> = (0..count)
+                .map(|id| BatchTransferArgs {
+                    receiver: bench_holder(id).into(),
+                    amount: Nat::from(1_000u64),
+                })
+                .collect();
+            let bytes = self.update("bench_batch_transfer", (transfers,));
+            results.insert(format!("batch_transfer_{count}"), decode_count(&bytes));
+        }
+
+        // The first page returns the latest transactions, the second one starts in the middle of
+        // the history.
+        let bytes = self.query("bench_get_transactions", (100usize, None::<u64>));
+        results.insert("get_transactions_first_page".into(), decode_count(&bytes));
+        let middle = Some(u64::from(HOLDERS / 2));
+        let bytes = self.query("bench_get_transactions", (100usize, middle));
+        results.insert("get_transactions_middle_page".into(), decode_count(&bytes));
+
+        self.pic
+            .upgrade_canister(
+                self.token,
+                self.wasm.clone(),
+                encode_args(()).unwrap(),
+                Some(admin()),
+            )
+            .expect("failed to upgrade the token");
+        let bytes = self.query("bench_post_upgrade", ());
+        results.insert("post_upgrade".into(), decode_count(&bytes));
+
+        results
+    }
+}
+
+fn reply(method: &str, result: Result<pocket_ic::WasmResult, pocket_ic::UserError>) -> Vec<u8> {
+    match result {
+        Ok(pocket_ic::WasmResult::Reply(bytes)) => bytes,
+        Ok(pocket_ic::WasmResult::Reject(message)) => panic!("{method} was rejected: {message}"),
+        Err(err) => panic!("{method} failed: {err:?}"),
+    }
+}
+
+fn decode_count(bytes: &[u8]) -> u64 {
+    candid::decode_one(bytes).expect("failed to decode the instruction count")
+}
+
+fn baseline_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("results.json")
+}
+
+fn load_baseline() -> Option<Results> {
+    let bytes = std::fs::read(baseline_path()).ok()?;
+    Some(serde_json::from_slice(&bytes).expect("failed to parse the baseline"))
+}
+
+/// Prints the results next to the baseline, and returns the names of the benchmarks which exceed
+/// the baseline by more than the tolerance.
+fn compare(results: &Results, baseline: Option<&Results>) -> Vec<String> {
+    let mut regressions = vec![];
+    println!(
+        "{:<32} {:>16} {:>16} {:>9}",
+        "benchmark", "instructions", "baseline", "change"
+    );
+    for (name, &count) in results {
+        let Some(&base) = baseline.and_then(|baseline| baseline.get(name)) else {
+            println!("{name:<32} {count:>16} {:>16} {:>9}", "-", "new");
+            continue;
+        };
+
+        let change = (count as f64 - base as f64) / base.max(1) as f64 * 100.0;
+        println!("{name:<32} {count:>16} {base:>16} {change:>+8.2}%");
+        if count.saturating_mul(100) > base.saturating_mul(100 + TOLERANCE_PERCENT) {
+            regressions.push(name.clone());
+        }
+    }
+
+    regressions
+}
+
+fn main() -> ExitCode {
+    let save = std::env::args().any(|arg| arg == "--save");
+
+    let wasm = match load_wasm(BENCH_WASM) {
+        Some(wasm) if std::env::var_os("POCKET_IC_BIN").is_some() => wasm,
+        _ => {
+            eprintln!(
+                "set POCKET_IC_BIN and run scripts/build-bench-wasm.sh to run the benchmarks"
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let bench = Bench::new(wasm);
+    bench.setup();
+    let results = bench.run();
+
+    let regressions = compare(&results, load_baseline().as_ref());
+    if save {
+        let json = serde_json::to_string_pretty(&results).unwrap();
+        std::fs::write(baseline_path(), json + "\n").expect("failed to write the baseline");
+        println!("baseline saved to {}", baseline_path().display());
+        return ExitCode::SUCCESS;
+    }
+
+    if regressions.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        eprintln!(
+            "instruction count regressed by more than {TOLERANCE_PERCENT}%: {}",
+            regressions.join(", ")
+        );
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,1745 @@
+use std::collections::HashMap;
+
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::{decode_args, encode_args, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_agent::Agent;
+use token::account::{Account, Subaccount};
+use token::canister::account_purge::PurgeReport;
+use token::canister::certified_balances::CertifiedBalance;
+use token::canister::exchange_deposits::{SweepResult, SweepSelection};
+use token::canister::export::{ExportFormat, HoldersExport, TransactionsExport};
+use token::canister::handover::HandoverSummary;
+use token::canister::ledger_compat::{
+    GetBlocksArgs, QueryBlocksResponse, QueryEncodedBlocksResponse,
+};
+use token::canister::permit::Permit;
+use token::canister::signed_transfer::SignedTransfer;
+use token::canister::status_report::CanisterStatusReport;
+use token::error::{TransferError, TxError};
+use token::state::account_tags::TaggedSubaccount;
+use token::state::admin_log::AdminLogEntry;
+use token::state::allowances::{
+    AllowanceEntry, AllowanceSweepStats, ApprovalNotification, ApprovalNotificationId,
+};
+use token::state::budgets::{Budget, BudgetReport};
+use token::state::config::{
+    AuctionStrategy, BurnPolicy, InputLimits, LocalizedMetadata, RenounceOwnershipArgs,
+    StandardRecord, Timestamp, TokenInfo, TxWindow, Value,
+};
+use token::state::decimals::DecimalsMigration;
+use token::state::dust::{DustPolicy, DustReport};
+use token::state::expiring_transfers::{ExpiringTransfer, ExpiringTransferId};
+use token::state::export_sessions::{ExportSession, ExportSessionId};
+use token::state::faucet::{FaucetPolicy, FaucetStatus};
+use token::state::fee_recipients::{FeeDistribution, FeeRecipient};
+use token::state::fees::FeePolicy;
+use token::state::frozen::FreezeMode;
+use token::state::guardians::{Guardian, Recovery, RecoveryLogEntry};
+use token::state::icp_bridge::{BlockIndex, BridgeOperation, DepositStatus, IcpAccountId};
+use token::state::invariants::InvariantsReport;
+use token::state::large_transfers::{LargeTransferPolicy, PendingTransfer, PendingTransferId};
+use token::state::ledger::{
+    BatchOperation, BatchTransferArgs, ChainVerification, CompactionReport, GetAccountTransactions,
+    GetAccountTransactionsArgs, HistoryInfo, LedgerTip, MaintenanceStatus, Memo, PaginatedResult,
+    RetentionPolicy, TransferArgs, TxReceipt,
+};
+use token::state::memory::MemoryReport;
+use token::state::merchants::MerchantAuthorization;
+use token::state::metrics::{EndpointStatsReport, TokenMetrics};
+use token::state::migrations::StateMigrationStatus;
+use token::state::outbound::{MessageId, OutboundMessage, OutboundStatus};
+use token::state::payment_requests::{PaymentRequest, PaymentRequestId};
+use token::state::payment_subscriptions::{PaymentSubscription, SubscriptionId};
+use token::state::snapshots::{Snapshot, SnapshotId};
+use token::state::standing_orders::{StandingOrder, StandingOrderId};
+use token::state::subscriptions::{EventFilter, Subscription};
+use token::state::swaps::{Swap, SwapId};
+use token::state::timelock::{PendingChange, ProposalId};
+use token::state::upgrade_chunks::UpgradeModuleStatus;
+use token::state::wrapper::WrapperOperation;
+use token::tx_record::{TxId, TxRecord};
+
+use crate::error::{ClientError, ClientResult};
+
+/// Maximum number of transactions the token returns for a `get_transactions` request without
+/// the account filter.
+pub const TRANSACTIONS_PAGE_SIZE: usize = 2000;
+
+/// Maximum number of transactions the token returns for a `get_transactions` request filtered
+/// by the account.
+pub const ACCOUNT_TRANSACTIONS_PAGE_SIZE: usize = 1000;
+
+/// Client of a single IS20 token canister. The calls are made with the identity of the `agent`.
+#[derive(Clone)]
+pub struct Is20Client {
+    agent: Agent,
+    canister_id: Principal,
+}
+
+impl Is20Client {
+    pub fn new(agent: Agent, canister_id: Principal) -> Self {
+        Self { agent, canister_id }
+    }
+
+    pub fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    pub fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    async fn query<R: for<'de> ArgumentDecoder<'de>>(
+        &self,
+        method: &'static str,
+        args: impl ArgumentEncoder,
+    ) -> ClientResult<R> {
+        let args = encode_args(args).map_err(|source| ClientError::Encode { method, source })?;
+        let reply = self
+            .agent
+            .query(&self.canister_id, method)
+            .with_arg(args)
+            .call()
+            .await?;
+        decode_args(&reply).map_err(|source| ClientError::Decode { method, source })
+    }
+
+    async fn update<R: for<'de> ArgumentDecoder<'de>>(
+        &self,
+        method: &'static str,
+        args: impl ArgumentEncoder,
+    ) -> ClientResult<R> {
+        let args = encode_args(args).map_err(|source| ClientError::Encode { method, source })?;
+        let reply = self
+            .agent
+            .update(&self.canister_id, method)
+            .with_arg(args)
+            .call_and_wait()
+            .await?;
+        decode_args(&reply).map_err(|source| ClientError::Decode { method, source })
+    }
+
+    /********************** METADATA ***********************/
+
+    pub async fn is_test_token(&self) -> ClientResult<bool> {
+        self.query("is_test_token", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn owner(&self) -> ClientResult<Principal> {
+        self.query("owner", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_token_info(&self) -> ClientResult<TokenInfo> {
+        self.query("get_token_info", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_name(&self) -> ClientResult<String> {
+        self.query("icrc1_name", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_symbol(&self) -> ClientResult<String> {
+        self.query("icrc1_symbol", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_decimals(&self) -> ClientResult<u8> {
+        self.query("icrc1_decimals", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn rescale_decimals(
+        &self,
+        new_decimals: u8,
+    ) -> ClientResult<Result<Option<DecimalsMigration>, TxError>> {
+        self.update("rescale_decimals", (new_decimals,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_decimals_migration(&self) -> ClientResult<Option<DecimalsMigration>> {
+        self.query("get_decimals_migration", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_fee(&self) -> ClientResult<Tokens128> {
+        self.query("icrc1_fee", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn parse_amount(&self, text: String) -> ClientResult<Result<Tokens128, TxError>> {
+        self.query("parse_amount", (text,)).await.map(|(r,)| r)
+    }
+
+    pub async fn format_amount(&self, amount: Tokens128) -> ClientResult<String> {
+        self.query("format_amount", (amount,)).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_total_supply(&self) -> ClientResult<Tokens128> {
+        self.query("icrc1_total_supply", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_metadata(&self) -> ClientResult<Vec<(String, Value)>> {
+        self.query("icrc1_metadata", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_supported_standards(&self) -> ClientResult<Vec<StandardRecord>> {
+        self.query("icrc1_supported_standards", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn icrc1_minting_account(&self) -> ClientResult<Option<Account>> {
+        self.query("icrc1_minting_account", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_burn_account(&self) -> ClientResult<Account> {
+        self.query("get_burn_account", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_burned_total(&self) -> ClientResult<Tokens128> {
+        self.query("get_burned_total", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_state_version(&self) -> ClientResult<u32> {
+        self.query("get_state_version", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_state_migration_status(&self) -> ClientResult<StateMigrationStatus> {
+        self.query("get_state_migration_status", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn run_state_migrations(
+        &self,
+    ) -> ClientResult<Result<StateMigrationStatus, TxError>> {
+        self.update("run_state_migrations", ()).await.map(|(r,)| r)
+    }
+
+    /********************** OWNER ***********************/
+
+    pub async fn set_name(&self, name: String) -> ClientResult<Result<(), TxError>> {
+        self.update("set_name", (name,)).await.map(|(r,)| r)
+    }
+
+    pub async fn set_symbol(&self, symbol: String) -> ClientResult<Result<(), TxError>> {
+        self.update("set_symbol", (symbol,)).await.map(|(r,)| r)
+    }
+
+    pub async fn set_fee(&self, fee: Tokens128) -> ClientResult<Result<(), TxError>> {
+        self.update("set_fee", (fee,)).await.map(|(r,)| r)
+    }
+
+    pub async fn set_fee_to(&self, fee_to: Principal) -> ClientResult<Result<(), TxError>> {
+        self.update("set_fee_to", (fee_to,)).await.map(|(r,)| r)
+    }
+
+    pub async fn set_tx_window(&self, window: TxWindow) -> ClientResult<Result<(), TxError>> {
+        self.update("set_tx_window", (window,)).await.map(|(r,)| r)
+    }
+
+    pub async fn set_input_limits(&self, limits: InputLimits) -> ClientResult<Result<(), TxError>> {
+        self.update("set_input_limits", (limits,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_input_limits(&self) -> ClientResult<InputLimits> {
+        self.query("get_input_limits", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_exchange_deposit_account(&self, index: u64) -> ClientResult<Account> {
+        self.query("get_exchange_deposit_account", (index,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn sweep_subaccounts(
+        &self,
+        selection: SweepSelection,
+        to: Account,
+    ) -> ClientResult<Result<Vec<SweepResult>, TxError>> {
+        self.update("sweep_subaccounts", (selection, to))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_owner(&self, owner: Principal) -> ClientResult<Result<(), TxError>> {
+        self.update("set_owner", (owner,)).await.map(|(r,)| r)
+    }
+
+    pub async fn set_timelock_delay(
+        &self,
+        delay_nanos: Option<u64>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_timelock_delay", (delay_nanos,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_timelock_delay(&self) -> ClientResult<Option<u64>> {
+        self.query("get_timelock_delay", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn list_pending_changes(&self) -> ClientResult<Vec<PendingChange>> {
+        self.query("list_pending_changes", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn cancel_proposal(&self, id: ProposalId) -> ClientResult<Result<(), TxError>> {
+        self.update("cancel_proposal", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn renounce_ownership(
+        &self,
+        args: RenounceOwnershipArgs,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("renounce_ownership", (args,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn list_controllers(&self) -> ClientResult<Result<Vec<Principal>, TxError>> {
+        self.update("list_controllers", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_canister_status_report(&self) -> ClientResult<Option<CanisterStatusReport>> {
+        self.query("get_canister_status_report", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn add_controller(
+        &self,
+        controller: Principal,
+    ) -> ClientResult<Result<Vec<Principal>, TxError>> {
+        self.update("add_controller", (controller,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn remove_controller(
+        &self,
+        controller: Principal,
+    ) -> ClientResult<Result<Vec<Principal>, TxError>> {
+        self.update("remove_controller", (controller,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn handover(
+        &self,
+        new_owner: Principal,
+    ) -> ClientResult<Result<HandoverSummary, TxError>> {
+        self.update("handover", (new_owner,)).await.map(|(r,)| r)
+    }
+
+    pub async fn upload_upgrade_chunk(
+        &self,
+        chunk: Vec<u8>,
+    ) -> ClientResult<Result<UpgradeModuleStatus, TxError>> {
+        self.update("upload_upgrade_chunk", (chunk,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn clear_upgrade_chunks(&self) -> ClientResult<Result<(), TxError>> {
+        self.update("clear_upgrade_chunks", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_upgrade_module_status(&self) -> ClientResult<UpgradeModuleStatus> {
+        self.query("get_upgrade_module_status", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn commit_upgrade(
+        &self,
+        expected_module_hash: Vec<u8>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("commit_upgrade", (expected_module_hash,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_metadata_entry(
+        &self,
+        key: String,
+        value: Value,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_metadata_entry", (key, value))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn remove_metadata_entry(&self, key: String) -> ClientResult<Result<(), TxError>> {
+        self.update("remove_metadata_entry", (key,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_localized_metadata(
+        &self,
+        locale: String,
+        metadata: Option<LocalizedMetadata>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_localized_metadata", (locale, metadata))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_minting_account(&self, account: Account) -> ClientResult<Result<(), TxError>> {
+        self.update("set_minting_account", (account,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_auction_strategy(
+        &self,
+        strategy: AuctionStrategy,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_auction_strategy", (strategy,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_auction_strategy(&self) -> ClientResult<AuctionStrategy> {
+        self.query("get_auction_strategy", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn set_auction_retention(
+        &self,
+        policy: Option<RetentionPolicy>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_auction_retention", (policy,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_min_bid_cycles(&self, cycles: u64) -> ClientResult<Result<(), TxError>> {
+        self.update("set_min_bid_cycles", (cycles,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_min_bid_cycles(&self) -> ClientResult<u64> {
+        self.query("get_min_bid_cycles", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn set_bidder_blacklisted(
+        &self,
+        bidder: Principal,
+        blacklisted: bool,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_bidder_blacklisted", (bidder, blacklisted))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_bidder_blacklist(
+        &self,
+        offset: u64,
+        limit: u64,
+    ) -> ClientResult<Vec<(Principal, Timestamp)>> {
+        self.query("get_bidder_blacklist", (offset, limit))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_bid_delegate(
+        &self,
+        delegate: Option<Principal>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_bid_delegate", (delegate,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn accept_bid_delegation(
+        &self,
+        delegator: Principal,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("accept_bid_delegation", (delegator,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn resign_bid_delegation(&self) -> ClientResult<Result<(), TxError>> {
+        self.update("resign_bid_delegation", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_admin_log(
+        &self,
+        offset: u64,
+        count: usize,
+    ) -> ClientResult<Vec<AdminLogEntry>> {
+        self.query("get_admin_log", (offset, count))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn freeze_account(
+        &self,
+        account: Account,
+        mode: FreezeMode,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("freeze_account", (account, mode))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn unfreeze_account(&self, account: Account) -> ClientResult<Result<(), TxError>> {
+        self.update("unfreeze_account", (account,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn is_frozen(&self, account: Account) -> ClientResult<Option<FreezeMode>> {
+        self.query("is_frozen", (account,)).await.map(|(r,)| r)
+    }
+
+    pub async fn purge_account_data(
+        &self,
+        principal: Principal,
+    ) -> ClientResult<Result<PurgeReport, TxError>> {
+        self.update("purge_account_data", (principal,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** BALANCES ***********************/
+
+    pub async fn icrc1_balance_of(&self, account: Account) -> ClientResult<Tokens128> {
+        self.query("icrc1_balance_of", (account,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /// Returns the certified balance of the `account` with the certificate and the witness to check
+    /// it against the root key of the network.
+    pub async fn get_certified_balance(
+        &self,
+        account: Account,
+    ) -> ClientResult<Result<CertifiedBalance, TxError>> {
+        self.query("get_certified_balance", (account,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /// Returns the balances of up to 1000 `accounts` in the same order.
+    pub async fn get_balances(&self, accounts: Vec<Account>) -> ClientResult<Vec<Tokens128>> {
+        self.query("get_balances", (accounts,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_holders(
+        &self,
+        start: usize,
+        limit: usize,
+    ) -> ClientResult<Vec<(Account, Tokens128)>> {
+        self.query("get_holders", (start, limit))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /// Returns the balances of the subaccounts of the agent identity.
+    pub async fn list_subaccounts(&self) -> ClientResult<HashMap<Subaccount, Tokens128>> {
+        self.query("list_subaccounts", ()).await.map(|(r,)| r)
+    }
+
+    /// Returns the balances and the tags of the subaccounts of the agent identity.
+    pub async fn list_tagged_subaccounts(
+        &self,
+    ) -> ClientResult<HashMap<Subaccount, TaggedSubaccount>> {
+        self.query("list_tagged_subaccounts", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_account_tags(&self) -> ClientResult<HashMap<Subaccount, String>> {
+        self.query("get_account_tags", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn set_account_tag(
+        &self,
+        subaccount: Option<Subaccount>,
+        label: String,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_account_tag", (subaccount, label))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** ACCOUNT IDENTIFIERS ***********************/
+
+    pub async fn get_account_identifier(
+        &self,
+        principal: Principal,
+        subaccount: Option<Subaccount>,
+    ) -> ClientResult<IcpAccountId> {
+        self.query("get_account_identifier", (principal, subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn resolve_account_identifier(
+        &self,
+        account_id: IcpAccountId,
+    ) -> ClientResult<Option<Account>> {
+        self.query("resolve_account_identifier", (account_id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** CLAIMS ***********************/
+
+    pub async fn get_claimable_amount(
+        &self,
+        holder: Principal,
+        subaccount: Option<Subaccount>,
+    ) -> ClientResult<Tokens128> {
+        self.query("get_claimable_amount", (holder, subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_claim_subaccount(
+        &self,
+        claimer: Principal,
+        claimer_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Subaccount> {
+        self.query("get_claim_subaccount", (claimer, claimer_subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn claim(
+        &self,
+        holder: Principal,
+        subaccount: Option<Subaccount>,
+    ) -> ClientResult<TxReceipt> {
+        self.update("claim", (holder, subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** ICP BRIDGE ***********************/
+
+    pub async fn set_icp_ledger(
+        &self,
+        ledger: Option<Principal>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_icp_ledger", (ledger,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_icp_deposit_address(&self, owner: Principal) -> ClientResult<IcpAccountId> {
+        self.query("get_icp_deposit_address", (owner,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn deposit_icp(&self, block: BlockIndex) -> ClientResult<TxReceipt> {
+        self.update("deposit_icp", (block,)).await.map(|(r,)| r)
+    }
+
+    pub async fn withdraw_icp(
+        &self,
+        amount: Tokens128,
+        to: IcpAccountId,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<BridgeOperation, TxError>> {
+        self.update("withdraw_icp", (amount, to, from_subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn retry_bridge_operations(
+        &self,
+    ) -> ClientResult<Result<Vec<BridgeOperation>, TxError>> {
+        self.update("retry_bridge_operations", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_bridge_operations(
+        &self,
+        offset: u64,
+        count: usize,
+    ) -> ClientResult<Vec<BridgeOperation>> {
+        self.query("get_bridge_operations", (offset, count))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_icp_deposit(&self, block: BlockIndex) -> ClientResult<Option<DepositStatus>> {
+        self.query("get_icp_deposit", (block,)).await.map(|(r,)| r)
+    }
+
+    /********************** ICRC-1 WRAPPER ***********************/
+
+    pub async fn set_wrapped_token(
+        &self,
+        token: Option<Principal>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_wrapped_token", (token,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_wrapper_deposit_account(&self, owner: Principal) -> ClientResult<Account> {
+        self.query("get_wrapper_deposit_account", (owner,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn deposit(
+        &self,
+        amount: Tokens128,
+    ) -> ClientResult<Result<WrapperOperation, TxError>> {
+        self.update("deposit", (amount,)).await.map(|(r,)| r)
+    }
+
+    pub async fn withdraw(
+        &self,
+        amount: Tokens128,
+        to: Option<Account>,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<WrapperOperation, TxError>> {
+        self.update("withdraw", (amount, to, from_subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn retry_wrapper_operations(
+        &self,
+    ) -> ClientResult<Result<Vec<WrapperOperation>, TxError>> {
+        self.update("retry_wrapper_operations", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_wrapper_operations(
+        &self,
+        offset: u64,
+        count: usize,
+    ) -> ClientResult<Vec<WrapperOperation>> {
+        self.query("get_wrapper_operations", (offset, count))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** TRANSACTIONS HISTORY ***********************/
+
+    pub async fn history_size(&self) -> ClientResult<u64> {
+        self.query("history_size", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_history_info(&self) -> ClientResult<HistoryInfo> {
+        self.query("get_history_info", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn set_history_retention(
+        &self,
+        policy: Option<RetentionPolicy>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_history_retention", (policy,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn prune_transactions(&self) -> ClientResult<Result<u64, TxError>> {
+        self.update("prune_transactions", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn compact_ledger(&self) -> ClientResult<Result<CompactionReport, TxError>> {
+        self.update("compact_ledger", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_metrics(&self) -> ClientResult<TokenMetrics> {
+        self.query("get_metrics", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_endpoint_stats(&self) -> ClientResult<EndpointStatsReport> {
+        self.query("get_endpoint_stats", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn reset_endpoint_stats(&self) -> ClientResult<Result<(), TxError>> {
+        self.update("reset_endpoint_stats", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_memory_report(&self) -> ClientResult<MemoryReport> {
+        self.query("get_memory_report", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_maintenance_status(&self) -> ClientResult<MaintenanceStatus> {
+        self.query("get_maintenance_status", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn verify_invariants(&self) -> ClientResult<InvariantsReport> {
+        self.query("verify_invariants", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn repair_invariants(&self) -> ClientResult<Result<Option<TxId>, TxError>> {
+        self.update("repair_invariants", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_transaction(&self, id: TxId) -> ClientResult<TxRecord> {
+        self.query("get_transaction", (id,)).await.map(|(r,)| r)
+    }
+
+    /// Returns a single page of the history, newest transactions first. See
+    /// [`Self::get_all_transactions`] to fetch the whole history.
+    pub async fn get_transactions(
+        &self,
+        who: Option<Principal>,
+        count: usize,
+        transaction_id: Option<TxId>,
+    ) -> ClientResult<PaginatedResult> {
+        self.query("get_transactions", (who, count, transaction_id))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /// Fetches the whole transaction history of the token, or of the `who` principal if given,
+    /// following the `next` cursor of `get_transactions` page by page. Newest transactions first.
+    pub async fn get_all_transactions(
+        &self,
+        who: Option<Principal>,
+    ) -> ClientResult<Vec<TxRecord>> {
+        let page_size = match who {
+            Some(_) => ACCOUNT_TRANSACTIONS_PAGE_SIZE,
+            None => TRANSACTIONS_PAGE_SIZE,
+        };
+
+        let mut transactions = vec![];
+        let mut cursor = None;
+        loop {
+            let page = self.get_transactions(who, page_size, cursor).await?;
+            transactions.extend(page.result);
+            match page.next {
+                Some(next) => cursor = Some(next),
+                None => return Ok(transactions),
+            }
+        }
+    }
+
+    /// Returns a page of the `account` history, newest transactions first, with the ids smaller
+    /// than `start`.
+    pub async fn get_account_transactions(
+        &self,
+        account: Account,
+        start: Option<TxId>,
+        max_results: u64,
+    ) -> ClientResult<GetAccountTransactions> {
+        let args = GetAccountTransactionsArgs {
+            account,
+            start,
+            max_results,
+        };
+        self.query("get_account_transactions", (args,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_subaccount_transactions(
+        &self,
+        principal: Principal,
+        subaccount: Option<Subaccount>,
+        count: usize,
+        offset: usize,
+    ) -> ClientResult<Vec<TxRecord>> {
+        self.query(
+            "get_subaccount_transactions",
+            (principal, subaccount, count, offset),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn get_ledger_tip_hash(&self) -> ClientResult<LedgerTip> {
+        self.query("get_ledger_tip_hash", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn verify_chain(&self, start: TxId, length: u64) -> ClientResult<ChainVerification> {
+        self.query("verify_chain", (start, length))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_user_transaction_count(&self, who: Principal) -> ClientResult<usize> {
+        self.query("get_user_transaction_count", (who,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn export_transactions(
+        &self,
+        format: ExportFormat,
+        from_ts: Timestamp,
+        to_ts: Timestamp,
+        cursor: Option<TxId>,
+    ) -> ClientResult<TransactionsExport> {
+        self.query("export_transactions", (format, from_ts, to_ts, cursor))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn start_export(&self) -> ClientResult<Result<ExportSession, TxError>> {
+        self.update("start_export", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn release_export(
+        &self,
+        session: ExportSessionId,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("release_export", (session,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn export_holders(
+        &self,
+        session: ExportSessionId,
+        cursor: Option<Account>,
+    ) -> ClientResult<Result<HoldersExport, TxError>> {
+        self.query("export_holders", (session, cursor))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn export_session_transactions(
+        &self,
+        session: ExportSessionId,
+        format: ExportFormat,
+        from_ts: Timestamp,
+        to_ts: Timestamp,
+        cursor: Option<TxId>,
+    ) -> ClientResult<Result<TransactionsExport, TxError>> {
+        self.query(
+            "export_session_transactions",
+            (session, format, from_ts, to_ts, cursor),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn query_blocks(&self, args: GetBlocksArgs) -> ClientResult<QueryBlocksResponse> {
+        self.query("query_blocks", (args,)).await.map(|(r,)| r)
+    }
+
+    pub async fn query_encoded_blocks(
+        &self,
+        args: GetBlocksArgs,
+    ) -> ClientResult<QueryEncodedBlocksResponse> {
+        self.query("query_encoded_blocks", (args,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_transactions_by_memo(&self, memo: Memo) -> ClientResult<Vec<TxRecord>> {
+        self.query("get_transactions_by_memo", (memo,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_memo_index(&self, enabled: bool) -> ClientResult<Result<(), TxError>> {
+        self.update("set_memo_index", (enabled,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** EVENT SUBSCRIPTIONS ***********************/
+
+    pub async fn subscribe_to_events(
+        &self,
+        filter: EventFilter,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("subscribe_to_events", (filter,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn unsubscribe_from_events(&self) -> ClientResult<bool> {
+        self.update("unsubscribe_from_events", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_event_subscription(&self) -> ClientResult<Option<Subscription>> {
+        self.query("get_event_subscription", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_outbound_status(&self) -> ClientResult<OutboundStatus> {
+        self.query("get_outbound_status", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_dead_letters(
+        &self,
+        offset: u64,
+        count: u64,
+    ) -> ClientResult<Vec<OutboundMessage>> {
+        self.query("get_dead_letters", (offset, count))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn retry_outbound_message(&self, id: MessageId) -> ClientResult<Result<(), TxError>> {
+        self.update("retry_outbound_message", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn drop_outbound_message(&self, id: MessageId) -> ClientResult<Result<(), TxError>> {
+        self.update("drop_outbound_message", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** PAYMENT SUBSCRIPTIONS ***********************/
+
+    pub async fn create_subscription(
+        &self,
+        spender: Principal,
+        amount: Tokens128,
+        interval_nanos: u64,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<SubscriptionId, TxError>> {
+        self.update(
+            "create_subscription",
+            (spender, amount, interval_nanos, from_subaccount),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn cancel_subscription(
+        &self,
+        id: SubscriptionId,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("cancel_subscription", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn collect_subscription(
+        &self,
+        id: SubscriptionId,
+        amount: Tokens128,
+    ) -> ClientResult<TxReceipt> {
+        self.update("collect_subscription", (id, amount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_subscription(
+        &self,
+        id: SubscriptionId,
+    ) -> ClientResult<Option<PaymentSubscription>> {
+        self.query("get_subscription", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_subscriptions(
+        &self,
+        who: Principal,
+    ) -> ClientResult<Vec<PaymentSubscription>> {
+        self.query("get_subscriptions", (who,)).await.map(|(r,)| r)
+    }
+
+    /********************** MERCHANT PAYMENTS ***********************/
+
+    pub async fn authorize_merchant(
+        &self,
+        merchant: Principal,
+        per_tx_cap: Tokens128,
+        monthly_cap: Tokens128,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<MerchantAuthorization, TxError>> {
+        self.update(
+            "authorize_merchant",
+            (merchant, per_tx_cap, monthly_cap, from_subaccount),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn revoke_merchant(
+        &self,
+        merchant: Principal,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("revoke_merchant", (merchant, from_subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn pull_payment(
+        &self,
+        payer: Account,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> ClientResult<TxReceipt> {
+        self.update("pull_payment", (payer, amount, memo))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_merchant_authorizations(
+        &self,
+        who: Principal,
+    ) -> ClientResult<Vec<MerchantAuthorization>> {
+        self.query("get_merchant_authorizations", (who,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn approve_and_notify(
+        &self,
+        spender: Principal,
+        amount: Tokens128,
+        payload: Vec<u8>,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<ApprovalNotification, TxError>> {
+        self.update(
+            "approve_and_notify",
+            (spender, amount, payload, from_subaccount),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn retry_approval_notification(
+        &self,
+        id: ApprovalNotificationId,
+    ) -> ClientResult<Result<ApprovalNotification, TxError>> {
+        self.update("retry_approval_notification", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /// Submits the allowance signed by the owner offline.
+    pub async fn permit(&self, permit: Permit) -> ClientResult<Result<(), TxError>> {
+        self.update("permit", (permit,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_permit_nonce(&self, owner: Principal) -> ClientResult<u64> {
+        self.query("get_permit_nonce", (owner,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_approval_notification(
+        &self,
+        id: ApprovalNotificationId,
+    ) -> ClientResult<Option<ApprovalNotification>> {
+        self.query("get_approval_notification", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn transfer_from_allowance(
+        &self,
+        from: Account,
+        to: Account,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> ClientResult<TxReceipt> {
+        self.update("transfer_from_allowance", (from, to, amount, memo))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn create_budget(
+        &self,
+        subaccount: Subaccount,
+        name: String,
+        parent: Option<Subaccount>,
+        spending_limit: Tokens128,
+    ) -> ClientResult<Result<Budget, TxError>> {
+        self.update("create_budget", (subaccount, name, parent, spending_limit))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_budget_limit(
+        &self,
+        subaccount: Subaccount,
+        spending_limit: Tokens128,
+    ) -> ClientResult<Result<Budget, TxError>> {
+        self.update("set_budget_limit", (subaccount, spending_limit))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_budget_delegates(
+        &self,
+        subaccount: Subaccount,
+        delegates: Vec<Principal>,
+    ) -> ClientResult<Result<Budget, TxError>> {
+        self.update("set_budget_delegates", (subaccount, delegates))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn remove_budget(&self, subaccount: Subaccount) -> ClientResult<Result<(), TxError>> {
+        self.update("remove_budget", (subaccount,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn budget_transfer(
+        &self,
+        organization: Principal,
+        budget: Subaccount,
+        to: Account,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> ClientResult<TxReceipt> {
+        self.update("budget_transfer", (organization, budget, to, amount, memo))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_budgets(&self, organization: Principal) -> ClientResult<Vec<BudgetReport>> {
+        self.query("get_budgets", (organization,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** VOTING SNAPSHOTS ***********************/
+
+    pub async fn set_voting_exclusions(
+        &self,
+        excluded: Vec<Principal>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_voting_exclusions", (excluded,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_voting_exclusions(&self) -> ClientResult<Vec<Principal>> {
+        self.query("get_voting_exclusions", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn take_snapshot(&self) -> ClientResult<Result<Snapshot, TxError>> {
+        self.update("take_snapshot", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_snapshot(
+        &self,
+        snapshot_id: SnapshotId,
+    ) -> ClientResult<Result<Snapshot, TxError>> {
+        self.query("get_snapshot", (snapshot_id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_voting_power(
+        &self,
+        account: Account,
+        snapshot_id: SnapshotId,
+    ) -> ClientResult<Result<Tokens128, TxError>> {
+        self.query("get_voting_power", (account, snapshot_id))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn total_voting_power(
+        &self,
+        snapshot_id: SnapshotId,
+    ) -> ClientResult<Result<Tokens128, TxError>> {
+        self.query("total_voting_power", (snapshot_id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** ACCOUNT RECOVERY ***********************/
+
+    pub async fn set_guardian(
+        &self,
+        guardian: Option<Guardian>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_guardian", (guardian,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_guardian(&self, owner: Principal) -> ClientResult<Option<Guardian>> {
+        self.query("get_guardian", (owner,)).await.map(|(r,)| r)
+    }
+
+    pub async fn initiate_recovery(
+        &self,
+        owner: Principal,
+        new_owner: Principal,
+    ) -> ClientResult<Result<Recovery, TxError>> {
+        self.update("initiate_recovery", (owner, new_owner))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn veto_recovery(&self) -> ClientResult<Result<(), TxError>> {
+        self.update("veto_recovery", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn complete_recovery(
+        &self,
+        owner: Principal,
+    ) -> ClientResult<Result<Vec<TxId>, TxError>> {
+        self.update("complete_recovery", (owner,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_recovery(&self, owner: Principal) -> ClientResult<Option<Recovery>> {
+        self.query("get_recovery", (owner,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_recovery_log(
+        &self,
+        offset: u64,
+        count: usize,
+    ) -> ClientResult<Vec<RecoveryLogEntry>> {
+        self.query("get_recovery_log", (offset, count))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** STANDING ORDERS ***********************/
+
+    pub async fn create_standing_order(
+        &self,
+        to: Account,
+        amount: Tokens128,
+        interval_nanos: u64,
+        max_executions: Option<u64>,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<StandingOrderId, TxError>> {
+        self.update(
+            "create_standing_order",
+            (to, amount, interval_nanos, max_executions, from_subaccount),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn pause_standing_order(
+        &self,
+        id: StandingOrderId,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("pause_standing_order", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn resume_standing_order(
+        &self,
+        id: StandingOrderId,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("resume_standing_order", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn cancel_standing_order(
+        &self,
+        id: StandingOrderId,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("cancel_standing_order", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_standing_order(
+        &self,
+        id: StandingOrderId,
+    ) -> ClientResult<Option<StandingOrder>> {
+        self.query("get_standing_order", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_standing_orders(&self, who: Principal) -> ClientResult<Vec<StandingOrder>> {
+        self.query("get_standing_orders", (who,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** PAYMENT REQUESTS ***********************/
+
+    pub async fn create_payment_request(
+        &self,
+        amount: Tokens128,
+        memo: Option<Memo>,
+        expires_at: Timestamp,
+        to_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<PaymentRequestId, TxError>> {
+        self.update(
+            "create_payment_request",
+            (amount, memo, expires_at, to_subaccount),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn pay_request(
+        &self,
+        id: PaymentRequestId,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<TxReceipt> {
+        self.update("pay_request", (id, from_subaccount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn cancel_payment_request(
+        &self,
+        id: PaymentRequestId,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("cancel_payment_request", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_payment_request(
+        &self,
+        id: PaymentRequestId,
+    ) -> ClientResult<Option<PaymentRequest>> {
+        self.query("get_payment_request", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_payment_requests(
+        &self,
+        merchant: Principal,
+    ) -> ClientResult<Vec<PaymentRequest>> {
+        self.query("get_payment_requests", (merchant,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** SWAPS ***********************/
+
+    pub async fn create_swap(
+        &self,
+        counterparty: Principal,
+        amount: Tokens128,
+        other_token: Principal,
+        other_amount: Tokens128,
+        deadline: Timestamp,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<SwapId, TxError>> {
+        self.update(
+            "create_swap",
+            (
+                counterparty,
+                amount,
+                other_token,
+                other_amount,
+                deadline,
+                from_subaccount,
+            ),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn accept_swap(&self, id: SwapId) -> ClientResult<Result<(), TxError>> {
+        self.update("accept_swap", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn refund_swap(&self, id: SwapId) -> ClientResult<Result<(), TxError>> {
+        self.update("refund_swap", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_swap(&self, id: SwapId) -> ClientResult<Option<Swap>> {
+        self.query("get_swap", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_swaps(&self, who: Principal) -> ClientResult<Vec<Swap>> {
+        self.query("get_swaps", (who,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_swap_escrow(&self, id: SwapId) -> ClientResult<Account> {
+        self.query("get_swap_escrow", (id,)).await.map(|(r,)| r)
+    }
+
+    /********************** EXPIRING TRANSFERS ***********************/
+
+    pub async fn transfer_with_expiry(
+        &self,
+        to: Account,
+        amount: Tokens128,
+        expires_at: Timestamp,
+        memo: Option<Memo>,
+        from_subaccount: Option<Subaccount>,
+    ) -> ClientResult<Result<ExpiringTransferId, TxError>> {
+        self.update(
+            "transfer_with_expiry",
+            (to, amount, expires_at, memo, from_subaccount),
+        )
+        .await
+        .map(|(r,)| r)
+    }
+
+    pub async fn accept_transfer(&self, id: ExpiringTransferId) -> ClientResult<TxReceipt> {
+        self.update("accept_transfer", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn reclaim_transfer(&self, id: ExpiringTransferId) -> ClientResult<TxReceipt> {
+        self.update("reclaim_transfer", (id,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_expiring_transfer(
+        &self,
+        id: ExpiringTransferId,
+    ) -> ClientResult<Option<ExpiringTransfer>> {
+        self.query("get_expiring_transfer", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_expiring_transfers(
+        &self,
+        who: Principal,
+    ) -> ClientResult<Vec<ExpiringTransfer>> {
+        self.query("get_expiring_transfers", (who,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    /********************** TRANSFERS ***********************/
+
+    pub async fn transfer(&self, transfer: TransferArgs) -> ClientResult<Result<u128, TxError>> {
+        self.update("transfer", (transfer,)).await.map(|(r,)| r)
+    }
+
+    pub async fn icrc1_transfer(
+        &self,
+        transfer: TransferArgs,
+    ) -> ClientResult<Result<u128, TransferError>> {
+        self.update("icrc1_transfer", (transfer,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn batch_transfer(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        transfers: Vec<BatchTransferArgs>,
+    ) -> ClientResult<Result<Vec<TxId>, TxError>> {
+        self.update("batch_transfer", (from_subaccount, transfers))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn atomic_batch(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> ClientResult<Result<Vec<TxId>, TxError>> {
+        self.update("atomic_batch", (operations,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_large_transfer_policy(
+        &self,
+        policy: Option<LargeTransferPolicy>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_large_transfer_policy", (policy,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_large_transfer_policy(&self) -> ClientResult<Option<LargeTransferPolicy>> {
+        self.query("get_large_transfer_policy", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn initiate_large_transfer(
+        &self,
+        transfer: TransferArgs,
+        cosigner: Option<Principal>,
+    ) -> ClientResult<Result<PendingTransferId, TxError>> {
+        self.update("initiate_large_transfer", (transfer, cosigner))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn confirm_large_transfer(&self, id: PendingTransferId) -> ClientResult<TxReceipt> {
+        self.update("confirm_large_transfer", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn cancel_large_transfer(
+        &self,
+        id: PendingTransferId,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("cancel_large_transfer", (id,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_pending_transfers(
+        &self,
+        who: Principal,
+    ) -> ClientResult<Vec<PendingTransfer>> {
+        self.query("get_pending_transfers", (who,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn set_faucet_policy(
+        &self,
+        policy: Option<FaucetPolicy>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_faucet_policy", (policy,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn faucet_status(&self, who: Principal) -> ClientResult<FaucetStatus> {
+        self.query("faucet_status", (who,)).await.map(|(r,)| r)
+    }
+
+    pub async fn set_dust_policy(
+        &self,
+        policy: Option<DustPolicy>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_dust_policy", (policy,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_dust_report(&self) -> ClientResult<DustReport> {
+        self.query("get_dust_report", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn set_burn_policy(
+        &self,
+        policy: Option<BurnPolicy>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_burn_policy", (policy,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_burn_policy(&self) -> ClientResult<Option<BurnPolicy>> {
+        self.query("get_burn_policy", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn set_fee_policy(
+        &self,
+        policy: Option<FeePolicy>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_fee_policy", (policy,)).await.map(|(r,)| r)
+    }
+
+    pub async fn get_fee_policy(&self) -> ClientResult<Option<FeePolicy>> {
+        self.query("get_fee_policy", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn set_fee_recipients(
+        &self,
+        recipients: Vec<FeeRecipient>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("set_fee_recipients", (recipients,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_fee_distribution(&self) -> ClientResult<FeeDistribution> {
+        self.query("get_fee_distribution", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_transfer_fee(
+        &self,
+        from: Account,
+        to: Account,
+        amount: Tokens128,
+    ) -> ClientResult<Tokens128> {
+        self.query("get_transfer_fee", (from, to, amount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn transfer_signed(
+        &self,
+        signed: SignedTransfer,
+    ) -> ClientResult<Result<u128, TransferError>> {
+        self.update("transfer_signed", (signed,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_transfer_nonce(&self, signer: Principal) -> ClientResult<u64> {
+        self.query("get_transfer_nonce", (signer,))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn approve(
+        &self,
+        spender: Principal,
+        amount: Tokens128,
+        from_subaccount: Option<Subaccount>,
+        expires_at: Option<Timestamp>,
+    ) -> ClientResult<Result<(), TxError>> {
+        self.update("approve", (spender, amount, from_subaccount, expires_at))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_allowance(
+        &self,
+        owner: Account,
+        spender: Principal,
+    ) -> ClientResult<Tokens128> {
+        self.query("get_allowance", (owner, spender))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_allowance_count(&self) -> ClientResult<u64> {
+        self.query("get_allowance_count", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_user_approvals(
+        &self,
+        owner: Account,
+        offset: u64,
+        limit: u64,
+    ) -> ClientResult<Vec<AllowanceEntry>> {
+        self.query("get_user_approvals", (owner, offset, limit))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_spender_allowances(
+        &self,
+        spender: Principal,
+        offset: u64,
+        limit: u64,
+    ) -> ClientResult<Vec<AllowanceEntry>> {
+        self.query("get_spender_allowances", (spender, offset, limit))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_allowance_sweep_stats(&self) -> ClientResult<AllowanceSweepStats> {
+        self.query("get_allowance_sweep_stats", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn mint(
+        &self,
+        to: Principal,
+        to_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+    ) -> ClientResult<TxReceipt> {
+        self.update("mint", (to, to_subaccount, amount))
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn burn(
+        &self,
+        from: Option<Principal>,
+        from_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+    ) -> ClientResult<TxReceipt> {
+        self.update("burn", (from, from_subaccount, amount))
+            .await
+            .map(|(r,)| r)
+    }
+}
@@ -0,0 +1,23 @@
+use candid::Error as CandidError;
+use ic_agent::AgentError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("agent error: {0}")]
+    Agent(#[from] AgentError),
+
+    #[error("failed to encode arguments of `{method}`: {source}")]
+    Encode {
+        method: &'static str,
+        source: CandidError,
+    },
+
+    #[error("failed to decode reply of `{method}`: {source}")]
+    Decode {
+        method: &'static str,
+        source: CandidError,
+    },
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
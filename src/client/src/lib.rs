@@ -0,0 +1,23 @@
+//! Typed client for the IS20 token canisters.
+//!
+//! [`Is20Client`] wraps an [`ic_agent::Agent`] and exposes every endpoint of the
+//! `TokenCanisterAPI` trait as an async method with the argument and result types of the
+//! `is20-token` crate, so the callers don't have to encode and decode candid by hand:
+//!
+//! ```ignore
+//! let client = Is20Client::new(agent, token_canister_id);
+//! let balance = client.icrc1_balance_of(Account::new(user, None)).await?;
+//! let history = client.get_all_transactions(Some(user)).await?;
+//! ```
+//!
+//! Endpoints enabled by the optional features of the token (`claim`, `icp_bridge`,
+//! `icrc1_wrapper`) are always present in the client. Calling them on a token built without the
+//! feature returns an [`ClientError::Agent`] error from the replica.
+
+mod client;
+mod error;
+
+pub use canister_sdk::ic_helpers::tokens::Tokens128;
+pub use client::{Is20Client, ACCOUNT_TRANSACTIONS_PAGE_SIZE, TRANSACTIONS_PAGE_SIZE};
+pub use error::{ClientError, ClientResult};
+pub use token;
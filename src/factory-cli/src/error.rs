@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use candid::parser::value::IDLValue;
+use is20_client::ClientError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error(transparent)]
+    Client(#[from] ClientError),
+
+    #[error("failed to read `{path}`: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("invalid token spec: {0}")]
+    Spec(String),
+
+    #[error("the factory canister id is not set, use `--factory` or `TOKEN_FACTORY`")]
+    MissingFactory,
+
+    #[error("invalid identity `{path}`: {reason}")]
+    Identity { path: PathBuf, reason: String },
+
+    #[error("`{method}` failed: {error}")]
+    Rejected {
+        method: &'static str,
+        error: IDLValue,
+    },
+}
+
+pub type CliResult<T> = Result<T, CliError>;
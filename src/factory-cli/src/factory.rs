@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use candid::parser::value::IDLValue;
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::{decode_args, encode_args, Principal};
+use ic_agent::Agent;
+use is20_client::token::state::config::Metadata;
+use is20_client::{ClientError, Tokens128};
+use token_factory::state::{ForceUpgrade, TokenStatus, TokenUpgradeStatus, UpgradeSchedule};
+
+use crate::error::{CliError, CliResult};
+
+/// Client of the token factory canister, covering the endpoints used by the CLI.
+///
+/// The factory errors are decoded as raw candid values, so the CLI only prints them.
+pub struct FactoryClient {
+    agent: Agent,
+    canister_id: Principal,
+}
+
+impl FactoryClient {
+    pub fn new(agent: Agent, canister_id: Principal) -> Self {
+        Self { agent, canister_id }
+    }
+
+    async fn query<R: for<'de> ArgumentDecoder<'de>>(
+        &self,
+        method: &'static str,
+        args: impl ArgumentEncoder,
+    ) -> CliResult<R> {
+        let args = encode_args(args).map_err(|source| ClientError::Encode { method, source })?;
+        let reply = self
+            .agent
+            .query(&self.canister_id, method)
+            .with_arg(args)
+            .call()
+            .await
+            .map_err(ClientError::from)?;
+        Ok(decode_args(&reply).map_err(|source| ClientError::Decode { method, source })?)
+    }
+
+    async fn update<R: for<'de> ArgumentDecoder<'de>>(
+        &self,
+        method: &'static str,
+        args: impl ArgumentEncoder,
+    ) -> CliResult<R> {
+        let args = encode_args(args).map_err(|source| ClientError::Encode { method, source })?;
+        let reply = self
+            .agent
+            .update(&self.canister_id, method)
+            .with_arg(args)
+            .call_and_wait()
+            .await
+            .map_err(ClientError::from)?;
+        Ok(decode_args(&reply).map_err(|source| ClientError::Decode { method, source })?)
+    }
+
+    pub async fn create_token(
+        &self,
+        info: Metadata,
+        amount: Tokens128,
+        controller: Option<Principal>,
+    ) -> CliResult<Principal> {
+        let (result,): (Result<Principal, IDLValue>,) = self
+            .update("create_token", (info, amount, controller))
+            .await?;
+        result.map_err(|error| CliError::Rejected {
+            method: "create_token",
+            error,
+        })
+    }
+
+    /// Upgrades the fleet and returns the result of the upgrade of every token.
+    pub async fn upgrade(
+        &self,
+        force: Option<ForceUpgrade>,
+    ) -> CliResult<HashMap<Principal, IDLValue>> {
+        let (result,): (Result<HashMap<Principal, IDLValue>, IDLValue>,) =
+            self.update("upgrade", (force,)).await?;
+        result.map_err(|error| CliError::Rejected {
+            method: "upgrade",
+            error,
+        })
+    }
+
+    /// Schedules the upgrade of the fleet in the maintenance window from `start_time` to
+    /// `end_time`.
+    pub async fn schedule_upgrade(
+        &self,
+        version: String,
+        start_time: u64,
+        end_time: u64,
+        batch_size: u32,
+        max_failures: u32,
+    ) -> CliResult<UpgradeSchedule> {
+        let (result,): (Result<UpgradeSchedule, IDLValue>,) = self
+            .update(
+                "schedule_upgrade",
+                (version, start_time, end_time, batch_size, max_failures),
+            )
+            .await?;
+        result.map_err(|error| CliError::Rejected {
+            method: "schedule_upgrade",
+            error,
+        })
+    }
+
+    pub async fn get_upgrade_progress(&self) -> CliResult<Option<UpgradeSchedule>> {
+        self.query("get_upgrade_progress", ()).await.map(|(r,)| r)
+    }
+
+    pub async fn get_token_upgrade_statuses(&self) -> CliResult<Vec<TokenUpgradeStatus>> {
+        self.query("get_token_upgrade_statuses", ())
+            .await
+            .map(|(r,)| r)
+    }
+
+    pub async fn get_fleet_status(&self) -> CliResult<Vec<TokenStatus>> {
+        self.query("get_fleet_status", ()).await.map(|(r,)| r)
+    }
+}
@@ -0,0 +1,351 @@
+//! Command line tool for the operators of the token factory and the tokens it deploys.
+//!
+//! Covers the routine tasks without the dfx scripts: deploying a token from a TOML spec (see
+//! [`spec`]), upgrading the fleet now or in a maintenance window, checking the fleet health,
+//! exporting the token holders and transactions, and printing the candid interfaces. Run
+//! `factory-cli --help` for the usage.
+
+mod error;
+mod factory;
+mod spec;
+
+use std::path::{Path, PathBuf};
+
+use candid::Principal;
+use clap::{Parser, Subcommand, ValueEnum};
+use ic_agent::identity::{AnonymousIdentity, BasicIdentity, Secp256k1Identity};
+use ic_agent::{Agent, Identity};
+use is20_client::token::amount::format_amount;
+use is20_client::token::canister::export::ExportFormat;
+use is20_client::Is20Client;
+use token_factory::state::ForceUpgrade;
+
+use crate::error::{CliError, CliResult};
+use crate::factory::FactoryClient;
+use crate::spec::TokenSpec;
+
+/// Number of holders requested per `get_holders` call.
+const HOLDERS_PAGE_SIZE: usize = 1000;
+
+#[derive(Parser)]
+#[command(version, about = "Operate the IS20 token factory and its tokens")]
+struct Cli {
+    /// URL of the replica.
+    #[arg(long, env = "IC_URL", default_value = "http://127.0.0.1:4943")]
+    url: String,
+
+    /// PEM file of the identity (Ed25519 or secp256k1) to sign the calls with. The calls are
+    /// anonymous if not set.
+    #[arg(long, env = "IC_IDENTITY")]
+    identity: Option<PathBuf>,
+
+    /// Id of the token factory canister.
+    #[arg(long, env = "TOKEN_FACTORY")]
+    factory: Option<Principal>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Deploy a token described by a TOML spec.
+    Deploy { spec: PathBuf },
+    /// Upgrade all the tokens of the factory to its current token wasm.
+    Upgrade {
+        /// Force the upgrade over the state compatibility check, recording the id of the
+        /// migration that makes it safe.
+        #[arg(long)]
+        migration_id: Option<String>,
+    },
+    /// Schedule the upgrade of all the tokens in a maintenance window, run by the factory in
+    /// batches.
+    ScheduleUpgrade {
+        /// Version the tokens must report after the upgrade.
+        version: String,
+        /// Start of the window, in nanoseconds since the epoch.
+        #[arg(long)]
+        start: u64,
+        /// End of the window, in nanoseconds since the epoch. The rollout not finished by then
+        /// is paused.
+        #[arg(long)]
+        end: u64,
+        #[arg(long, default_value_t = 10)]
+        batch_size: u32,
+        /// Number of the failed upgrades tolerated before the rollout is paused.
+        #[arg(long, default_value_t = 0)]
+        max_failures: u32,
+    },
+    /// Print the progress of the scheduled upgrade and the status of every token in it.
+    UpgradeProgress,
+    /// Print the status of every token as of the last health probe of the factory.
+    FleetStatus {
+        /// Exit with an error if any token is unhealthy.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Export the holders of a token.
+    ExportHolders {
+        token: Principal,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: HoldersFormat,
+    },
+    /// Export the transactions of a token made in a time range, for the accounting.
+    ExportTransactions {
+        token: Principal,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: TransactionsFormat,
+        /// Start of the range, in nanoseconds since the epoch.
+        #[arg(long, default_value_t = 0)]
+        from: u64,
+        /// End of the range (exclusive), in nanoseconds since the epoch.
+        #[arg(long, default_value_t = u64::MAX)]
+        to: u64,
+    },
+    /// Print the candid interface of a canister.
+    Candid { canister: CanisterKind },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HoldersFormat {
+    Csv,
+    Tsv,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TransactionsFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CanisterKind {
+    Token,
+    Factory,
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> CliResult<()> {
+    if let Command::Candid { canister } = cli.command {
+        match canister {
+            CanisterKind::Token => print!("{}", is20_token_canister::idl()),
+            CanisterKind::Factory => print!("{}", token_factory::idl()),
+        }
+        return Ok(());
+    }
+
+    let agent = build_agent(&cli.url, cli.identity.as_deref()).await?;
+    let factory = || {
+        cli.factory
+            .map(|id| FactoryClient::new(agent.clone(), id))
+            .ok_or(CliError::MissingFactory)
+    };
+
+    match cli.command {
+        Command::Deploy { spec } => {
+            let deployment = TokenSpec::read(&spec)?.into_deployment()?;
+            let token = factory()?
+                .create_token(
+                    deployment.metadata,
+                    deployment.initial_supply,
+                    deployment.controller,
+                )
+                .await?;
+            println!("{token}");
+        }
+        Command::Upgrade { migration_id } => {
+            let force = migration_id.map(|migration_id| ForceUpgrade { migration_id });
+            for (token, result) in factory()?.upgrade(force).await? {
+                println!("{token}\t{result}");
+            }
+        }
+        Command::ScheduleUpgrade {
+            version,
+            start,
+            end,
+            batch_size,
+            max_failures,
+        } => {
+            let schedule = factory()?
+                .schedule_upgrade(version, start, end, batch_size, max_failures)
+                .await?;
+            println!(
+                "scheduled the upgrade of {} tokens to {}",
+                schedule.total, schedule.version
+            );
+        }
+        Command::UpgradeProgress => {
+            let factory = factory()?;
+            let Some(schedule) = factory.get_upgrade_progress().await? else {
+                println!("no upgrade is scheduled");
+                return Ok(());
+            };
+            println!(
+                "{:?}: {} upgraded, {} skipped, {} failed of {} tokens",
+                schedule.status,
+                schedule.upgraded,
+                schedule.skipped,
+                schedule.failed,
+                schedule.total
+            );
+
+            println!("token\tname\tstate");
+            for status in factory.get_token_upgrade_statuses().await? {
+                println!("{}\t{}\t{:?}", status.token, status.name, status.state);
+            }
+        }
+        Command::FleetStatus { check } => {
+            let fleet = factory()?.get_fleet_status().await?;
+            println!("token\tname\thealthy\tcycles\tversion\terror");
+            for status in &fleet {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    status.token,
+                    status.name,
+                    status.healthy,
+                    status.cycles.map(|c| c.to_string()).unwrap_or_default(),
+                    status.wasm_version.as_deref().unwrap_or_default(),
+                    status.error.as_deref().unwrap_or_default(),
+                );
+            }
+
+            let unhealthy = fleet.iter().filter(|status| !status.healthy).count();
+            if check && unhealthy > 0 {
+                eprintln!("{unhealthy} of {} tokens are unhealthy", fleet.len());
+                std::process::exit(2);
+            }
+        }
+        Command::ExportHolders { token, format } => {
+            export_holders(&Is20Client::new(agent, token), format).await?;
+        }
+        Command::ExportTransactions {
+            token,
+            format,
+            from,
+            to,
+        } => {
+            export_transactions(&Is20Client::new(agent, token), format, from, to).await?;
+        }
+        Command::Candid { .. } => unreachable!(),
+    }
+
+    Ok(())
+}
+
+async fn build_agent(url: &str, identity: Option<&Path>) -> CliResult<Agent> {
+    let identity: Box<dyn Identity> = match identity {
+        Some(path) => read_identity(path)?,
+        None => Box::new(AnonymousIdentity),
+    };
+    let agent = Agent::builder()
+        .with_url(url)
+        .with_boxed_identity(identity)
+        .build()
+        .map_err(is20_client::ClientError::from)?;
+
+    // Only a local replica has a root key different from the mainnet one.
+    if !url.contains("ic0.app") && !url.contains("icp0.io") {
+        agent
+            .fetch_root_key()
+            .await
+            .map_err(is20_client::ClientError::from)?;
+    }
+
+    Ok(agent)
+}
+
+fn read_identity(path: &Path) -> CliResult<Box<dyn Identity>> {
+    if let Ok(identity) = BasicIdentity::from_pem_file(path) {
+        return Ok(Box::new(identity));
+    }
+
+    Secp256k1Identity::from_pem_file(path)
+        .map(|identity| Box::new(identity) as Box<dyn Identity>)
+        .map_err(|e| CliError::Identity {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        })
+}
+
+/// Prints the holders as `owner, subaccount, balance, units` rows, where the balance is in the
+/// decimal notation and the units are the raw amount.
+async fn export_holders(client: &Is20Client, format: HoldersFormat) -> CliResult<()> {
+    let separator = match format {
+        HoldersFormat::Csv => ",",
+        HoldersFormat::Tsv => "\t",
+    };
+    let decimals = client.icrc1_decimals().await?;
+
+    println!("owner{separator}subaccount{separator}balance{separator}units");
+    let mut start = 0;
+    loop {
+        let page = client.get_holders(start, HOLDERS_PAGE_SIZE).await?;
+        for (account, amount) in &page {
+            let subaccount = account
+                .subaccount
+                .map(|subaccount| subaccount.iter().map(|b| format!("{b:02x}")).collect())
+                .unwrap_or_else(String::new);
+            println!(
+                "{}{separator}{subaccount}{separator}{}{separator}{}",
+                account.owner,
+                format_amount(*amount, decimals),
+                amount.amount,
+            );
+        }
+
+        if page.len() < HOLDERS_PAGE_SIZE {
+            return Ok(());
+        }
+        start += page.len();
+    }
+}
+
+/// Prints the chunks of `export_transactions` one after another. The JSON chunks are merged into
+/// one array.
+async fn export_transactions(
+    client: &Is20Client,
+    format: TransactionsFormat,
+    from: u64,
+    to: u64,
+) -> CliResult<()> {
+    let export_format = match format {
+        TransactionsFormat::Csv => ExportFormat::Csv,
+        TransactionsFormat::Json => ExportFormat::Json,
+    };
+
+    let mut cursor = None;
+    let mut rows = Vec::new();
+    loop {
+        let chunk = client
+            .export_transactions(export_format, from, to, cursor)
+            .await?;
+        match format {
+            TransactionsFormat::Csv => print!("{}", chunk.data),
+            TransactionsFormat::Json => {
+                // Every chunk is a JSON array.
+                let inner = &chunk.data[1..chunk.data.len() - 1];
+                if !inner.is_empty() {
+                    rows.push(inner.to_string());
+                }
+            }
+        }
+
+        cursor = chunk.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    if let TransactionsFormat::Json = format {
+        println!("[{}]", rows.join(","));
+    }
+
+    Ok(())
+}
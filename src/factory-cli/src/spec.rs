@@ -0,0 +1,197 @@
+//! Token spec read by the `deploy` command.
+//!
+//! ```toml
+//! name = "Test Token"
+//! symbol = "TST"
+//! decimals = 8
+//! owner = "2vxsx-fae"
+//! fee = "0.0001"
+//! # Optional, defaults to the owner.
+//! fee_to = "2vxsx-fae"
+//! initial_supply = "1000000"
+//! is_test_token = false
+//! # Optional controller of the token canister, defaults to the factory.
+//! controller = "2vxsx-fae"
+//! # Optional, index the transactions by memo.
+//! memo_index = true
+//!
+//! # Optional initial balances, which must sum up to the initial supply. If not set, the whole
+//! # initial supply is minted to the owner.
+//! [[distribution]]
+//! owner = "2vxsx-fae"
+//! amount = "1000000"
+//! ```
+//!
+//! The amounts are in the decimal notation accepted by `token::amount::parse_amount`.
+
+use std::path::Path;
+
+use candid::Principal;
+use is20_client::token::account::Account;
+use is20_client::token::amount::parse_amount;
+use is20_client::token::state::config::{InitSpec, Metadata, TokenFeatures};
+use is20_client::Tokens128;
+use serde::Deserialize;
+
+use crate::error::{CliError, CliResult};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenSpec {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub owner: String,
+    pub fee: String,
+    pub fee_to: Option<String>,
+    pub initial_supply: String,
+    #[serde(default)]
+    pub is_test_token: bool,
+    pub controller: Option<String>,
+    #[serde(default)]
+    pub memo_index: bool,
+    #[serde(default)]
+    pub distribution: Vec<DistributionSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DistributionSpec {
+    pub owner: String,
+    pub amount: String,
+}
+
+/// Arguments of the factory `create_token` call.
+#[derive(Debug)]
+pub struct Deployment {
+    pub metadata: Metadata,
+    pub initial_supply: Tokens128,
+    pub controller: Option<Principal>,
+}
+
+impl TokenSpec {
+    pub fn read(path: &Path) -> CliResult<Self> {
+        let text = std::fs::read_to_string(path).map_err(|source| CliError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> CliResult<Self> {
+        toml::from_str(text).map_err(|e| CliError::Spec(e.to_string()))
+    }
+
+    pub fn into_deployment(self) -> CliResult<Deployment> {
+        let owner = principal("owner", &self.owner)?;
+        let fee_to = match &self.fee_to {
+            Some(fee_to) => principal("fee_to", fee_to)?,
+            None => owner,
+        };
+        let controller = match &self.controller {
+            Some(controller) => Some(principal("controller", controller)?),
+            None => None,
+        };
+
+        let distribution = self
+            .distribution
+            .iter()
+            .map(|entry| {
+                Ok((
+                    Account::new(principal("distribution.owner", &entry.owner)?, None),
+                    amount("distribution.amount", &entry.amount, self.decimals)?,
+                ))
+            })
+            .collect::<CliResult<Vec<_>>>()?;
+        let init_spec = (self.memo_index || !distribution.is_empty()).then(|| InitSpec {
+            distribution,
+            fee_policy: None,
+            features: TokenFeatures {
+                memo_index: self.memo_index,
+                ..Default::default()
+            },
+        });
+
+        Ok(Deployment {
+            metadata: Metadata {
+                name: self.name,
+                symbol: self.symbol,
+                decimals: self.decimals,
+                owner,
+                fee: amount("fee", &self.fee, self.decimals)?,
+                fee_to,
+                is_test_token: Some(self.is_test_token),
+                init_spec,
+            },
+            initial_supply: amount("initial_supply", &self.initial_supply, self.decimals)?,
+            controller,
+        })
+    }
+}
+
+fn principal(field: &str, text: &str) -> CliResult<Principal> {
+    Principal::from_text(text).map_err(|e| CliError::Spec(format!("`{field}`: {e}")))
+}
+
+fn amount(field: &str, text: &str, decimals: u8) -> CliResult<Tokens128> {
+    parse_amount(text, decimals).map_err(|e| CliError::Spec(format!("`{field}`: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_to_deployment() {
+        let spec = TokenSpec::parse(
+            r#"
+            name = "Test Token"
+            symbol = "TST"
+            decimals = 8
+            owner = "2vxsx-fae"
+            fee = "0.0001"
+            initial_supply = "1000"
+            "#,
+        )
+        .unwrap();
+
+        let deployment = spec.into_deployment().unwrap();
+        assert_eq!(deployment.metadata.fee, 10_000.into());
+        assert_eq!(deployment.metadata.fee_to, Principal::anonymous());
+        assert_eq!(deployment.metadata.is_test_token, Some(false));
+        assert_eq!(deployment.initial_supply, 100_000_000_000.into());
+        assert_eq!(deployment.controller, None);
+        assert_eq!(deployment.metadata.init_spec, None);
+
+        assert!(TokenSpec::parse("name = \"Test\"\nunknown = 1").is_err());
+    }
+
+    #[test]
+    fn spec_with_distribution() {
+        let spec = TokenSpec::parse(
+            r#"
+            name = "Test Token"
+            symbol = "TST"
+            decimals = 2
+            owner = "2vxsx-fae"
+            fee = "0"
+            initial_supply = "10"
+
+            [[distribution]]
+            owner = "aaaaa-aa"
+            amount = "10"
+            "#,
+        )
+        .unwrap();
+
+        let init_spec = spec.into_deployment().unwrap().metadata.init_spec.unwrap();
+        assert_eq!(
+            init_spec.distribution,
+            vec![(
+                Account::new(Principal::management_canister(), None),
+                1_000.into()
+            )]
+        );
+        assert!(!init_spec.features.memo_index);
+    }
+}
@@ -6,6 +6,15 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+use crate::events::{self, EventSubscriber, FactoryEvent, FactoryEventKind, FactoryEvents};
+use crate::state::{
+    BillingReport, ControllerRelease, DeployPolicy, DeploymentFee, DeploymentRecovery,
+    DeploymentStage, ForceUpgrade, MetadataUpdateResult, PendingDeployment, PendingMetadataUpdate,
+    PendingRegistration, SubnetSelection, TokenOverrides, TokenStatus, TokenTombstone,
+    TokenUpgradeStatus, UpgradeSchedule, WasmCompatibility, MAX_PROBE_ERROR_LEN,
+    MAX_TOKEN_LEN_IN_BYTES,
+};
+use crate::validation::SymbolRules;
 use crate::{error::TokenFactoryError, state};
 use candid::Principal;
 use canister_sdk::ic_factory::DEFAULT_ICP_FEE;
@@ -22,12 +31,42 @@ use canister_sdk::{
     ic_helpers::tokens::Tokens128,
     ic_storage,
 };
-use token::state::config::Metadata;
+use token::account::Subaccount;
+use token::error::truncate_message;
+use token::state::config::{Metadata, MetadataPatch, MAX_LOGO_SIZE};
 
 const DEFAULT_LEDGER_PRINCIPAL: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, 1, 1]);
+#[cfg(feature = "test-endpoints")]
+const SELF_FUNDED_CANISTER_CYCLES: u64 = 2_000_000_000_000;
+/// Minimum cycles attached to `create_token` to create the canister on the selected subnet.
+const MIN_SUBNET_DEPLOYMENT_CYCLES: u64 = 1_000_000_000_000;
+
+/// Methods callable by the controller of the factory only.
+pub static CONTROLLER_METHODS: &[&str] = &[
+    "set_token_bytecode",
+    "set_deployment_fee",
+    "set_deploy_policy",
+    "add_allowed_deployers",
+    "remove_allowed_deployers",
+    "set_deploy_quota",
+    "set_symbol_rules",
+    "set_wasm_compatibility",
+    "decommission_token",
+    "recover_failed_deployments",
+    "set_registry",
+    "schedule_upgrade",
+    "resume_upgrade",
+    "cancel_upgrade",
+];
 
+mod deployment_fee;
+mod fleet;
 #[cfg(feature = "export-api")]
 mod inspect_message;
+mod management;
+mod metadata;
+mod registry;
+mod rollout;
 
 #[derive(Clone, Canister)]
 #[canister_no_upgrade_methods]
@@ -61,7 +100,12 @@ impl TokenFactoryCanister {
 
     #[post_upgrade]
     fn post_upgrade(&self) {
-        // All state is stored in stable storage, so nothing to do here
+        // All state is stored in stable storage, only the timers must be restarted.
+        fleet::start_health_probes();
+        metadata::start_metadata_retries();
+        registry::start_registration_retries();
+        rollout::start_upgrade_rollout();
+        events::start_event_retries();
     }
 
     #[init]
@@ -73,6 +117,11 @@ impl TokenFactoryCanister {
 
         FactoryState::default().reset(factory_configuration);
         state::get_state().reset();
+        fleet::start_health_probes();
+        metadata::start_metadata_retries();
+        registry::start_registration_retries();
+        rollout::start_upgrade_rollout();
+        events::start_event_retries();
     }
 
     /// Returns the token, or None if it does not exist.
@@ -87,6 +136,179 @@ impl TokenFactoryCanister {
         self.set_canister_code(bytecode)
     }
 
+    /// Sets the state layout compatibility of the token wasm. If set, `upgrade` checks the state
+    /// version of every deployed token before upgrading them. Must be updated together with the
+    /// token bytecode.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn set_wasm_compatibility(
+        &self,
+        compatibility: Option<WasmCompatibility>,
+    ) -> Result<(), TokenFactoryError> {
+        self.check_controller()?;
+        state::get_state().set_wasm_compatibility(compatibility);
+        Ok(())
+    }
+
+    #[query]
+    pub async fn get_wasm_compatibility(&self) -> Option<WasmCompatibility> {
+        state::get_state().get_wasm_compatibility()
+    }
+
+    /// Sets the fee charged for token deployment. If `None`, no deployment fee is charged.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn set_deployment_fee(&self, fee: Option<DeploymentFee>) -> Result<(), FactoryError> {
+        self.check_controller()?;
+        state::get_state().set_deployment_fee(fee);
+        Ok(())
+    }
+
+    /// Returns the fee charged for token deployment, or None if deployment is free.
+    #[query]
+    pub async fn get_deployment_fee(&self) -> Option<DeploymentFee> {
+        state::get_state().get_deployment_fee()
+    }
+
+    /// Sets who can deploy tokens. With `DeployPolicy::Allowlist` only the factory controller and
+    /// the principals added with `add_allowed_deployers` can create tokens.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn set_deploy_policy(&self, policy: DeployPolicy) -> Result<(), TokenFactoryError> {
+        self.check_controller()?;
+        state::get_state().set_deploy_policy(policy);
+        Ok(())
+    }
+
+    #[query]
+    pub async fn get_deploy_policy(&self) -> DeployPolicy {
+        state::get_state().get_deploy_policy()
+    }
+
+    /// Adds the `deployers` to the allowlist used with `DeployPolicy::Allowlist`.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn add_allowed_deployers(
+        &self,
+        deployers: Vec<Principal>,
+    ) -> Result<(), TokenFactoryError> {
+        self.check_controller()?;
+        let now = canister_sdk::ic_kit::ic::time();
+        let mut state = state::get_state();
+        for deployer in deployers {
+            state.add_allowed_deployer(deployer, now);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the `deployers` from the allowlist used with `DeployPolicy::Allowlist`.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn remove_allowed_deployers(
+        &self,
+        deployers: Vec<Principal>,
+    ) -> Result<(), TokenFactoryError> {
+        self.check_controller()?;
+        let mut state = state::get_state();
+        for deployer in deployers {
+            state.remove_allowed_deployer(deployer);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the principals on the deployers allowlist with the time they were added.
+    #[query]
+    pub async fn get_allowed_deployers(&self) -> Vec<(Principal, u64)> {
+        state::get_state().allowed_deployers()
+    }
+
+    /// Sets the maximum number of the registered tokens one principal can deploy. The factory
+    /// controller and the principals on the deployers allowlist are not limited. `None` removes
+    /// the limit.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn set_deploy_quota(&self, quota: Option<u64>) -> Result<(), TokenFactoryError> {
+        self.check_controller()?;
+        state::get_state().set_deploy_quota(quota);
+        Ok(())
+    }
+
+    #[query]
+    pub async fn get_deploy_quota(&self) -> Option<u64> {
+        state::get_state().get_deploy_quota()
+    }
+
+    /// Returns the deployments and the paid fees of the `deployer`, and the cycles consumed by
+    /// their tokens as of the last health probes.
+    ///
+    /// This method can be called only by the `deployer` and by the factory controller.
+    #[query]
+    pub async fn get_billing_report(
+        &self,
+        deployer: Principal,
+    ) -> Result<BillingReport, TokenFactoryError> {
+        if canister_sdk::ic_kit::ic::caller() != deployer {
+            self.check_controller()?;
+        }
+
+        Ok(state::get_state().billing_report(deployer))
+    }
+
+    /// Sets the rules the symbols of the new tokens must satisfy. The tokens already deployed are
+    /// not affected.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn set_symbol_rules(&self, rules: SymbolRules) -> Result<(), TokenFactoryError> {
+        self.check_controller()?;
+        state::get_state().set_symbol_rules(rules);
+        Ok(())
+    }
+
+    #[query]
+    pub async fn get_symbol_rules(&self) -> SymbolRules {
+        state::get_state().get_symbol_rules()
+    }
+
+    /// Sets the shared registry canister the symbols of the new tokens are reserved and the
+    /// deployed tokens are registered on, see `api::registry`. `None` stops the registration. The
+    /// tokens already deployed are not registered.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn set_registry(&self, registry: Option<Principal>) -> Result<(), TokenFactoryError> {
+        self.check_controller()?;
+        state::get_state().set_registry(registry);
+        Ok(())
+    }
+
+    #[query]
+    pub async fn get_registry(&self) -> Option<Principal> {
+        state::get_state().get_registry()
+    }
+
+    /// Returns the registrations of the deployed tokens which could not reach the registry,
+    /// waiting for a retry.
+    #[query]
+    pub async fn get_pending_registrations(&self) -> Vec<PendingRegistration> {
+        state::get_state().pending_registrations()
+    }
+
+    /// Returns the subaccount of the factory account the `payer` must transfer the deployment fee
+    /// to before calling `create_token`.
+    #[query]
+    pub async fn get_deployment_fee_subaccount(&self, payer: Principal) -> Subaccount {
+        deployment_fee::deployment_fee_subaccount(payer)
+    }
+
     /// Creates a new token.
     ///
     /// Creating a token canister with the factory requires one of the following:
@@ -112,13 +334,528 @@ impl TokenFactoryCanister {
     /// If the provided ICP amount is greater than required by the factory, extra ICP will not be
     /// consumed and can be used to create more canisters, or can be reclaimed by calling `refund_icp`
     /// method.
+    ///
+    /// If the deployment fee is set (see `get_deployment_fee`), the caller must also deposit the
+    /// fee to the factory account in the fee ledger with the subaccount returned by
+    /// `get_deployment_fee_subaccount`. The fee is charged before the canister is created, and is
+    /// refunded (minus the ledger transfer fee) if the creation fails.
+    ///
+    /// If the deploy policy is `DeployPolicy::Allowlist`, the caller must be on the deployers
+    /// allowlist or be the factory controller.
+    ///
+    /// If the registry is set (see `get_registry`), the symbol is reserved on the registry first,
+    /// and the token is not created if the registry refuses it or cannot be reached.
+    ///
+    /// If the `subnet` is selected, the canister is created on that subnet by the cycles minting
+    /// canister. This requires the call to be made through a cycles wallet with at least `10^12`
+    /// cycles, all of which are added to the new canister balance; the ICP payment is not
+    /// supported then. The subnet is recorded, see `get_token_subnet` and `get_subnet_usage`.
     #[update]
     pub async fn create_token(
         &self,
         info: Metadata,
         amount: Tokens128,
         controller: Option<Principal>,
+        subnet: Option<SubnetSelection>,
     ) -> Result<Principal, TokenFactoryError> {
+        self.validate_metadata(&info)?;
+        let funding = match subnet {
+            Some(selection) => Funding::CallerOnSubnet(selection),
+            None => Funding::Caller,
+        };
+        self.deploy_token(info, amount, controller, funding).await
+    }
+
+    /// Creates a new token with the same configuration as the `source` token, except for the fields
+    /// set in the `overrides`. The configuration is read from the `source` canister, which doesn't
+    /// have to be deployed by this factory. The new token is paid for and registered the same way
+    /// as by `create_token`, so its name and symbol must not be taken.
+    #[update]
+    pub async fn clone_token(
+        &self,
+        source: Principal,
+        overrides: TokenOverrides,
+        amount: Tokens128,
+        controller: Option<Principal>,
+    ) -> Result<Principal, TokenFactoryError> {
+        let info = overrides.apply(management::token_metadata(source).await?);
+        self.validate_metadata(&info)?;
+        self.deploy_token(info, amount, controller, Funding::Caller)
+            .await
+    }
+
+    /// Creates a new token paying for the canister with the factory's own cycles instead of the
+    /// caller's ICP or cycles. The deployment fee is charged the same way as by `create_token`.
+    ///
+    /// Only available with the `test-endpoints` feature, for the environments without the ICP
+    /// ledger and cycles wallets (e.g. PocketIC). Tokens created this way are not upgraded by the
+    /// `upgrade` method.
+    #[cfg(feature = "test-endpoints")]
+    #[update]
+    pub async fn create_token_for_tests(
+        &self,
+        info: Metadata,
+        amount: Tokens128,
+        controller: Option<Principal>,
+    ) -> Result<Principal, TokenFactoryError> {
+        self.validate_metadata(&info)?;
+        self.deploy_token(info, amount, controller, Funding::Factory)
+            .await
+    }
+
+    #[update]
+    pub async fn forget_token(&self, name: String) -> Result<(), TokenFactoryError> {
+        let canister_id = self
+            .get_token(name.clone())
+            .await
+            .ok_or(TokenFactoryError::FactoryError(FactoryError::NotFound))?;
+
+        self.drop_canister(canister_id, None).await?;
+        state::get_state().remove_token(name.clone());
+        FactoryEvents::record(
+            canister_sdk::ic_kit::ic::caller(),
+            FactoryEventKind::TokenForgotten {
+                token: canister_id,
+                name,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Hands over the control of the token canister from the factory to the token owner.
+    ///
+    /// The controllers of the token canister are replaced with the token owner principal and,
+    /// optionally, the `blackhole` canister (e.g. to allow monitoring of the canister status).
+    /// After this the factory is no longer able to upgrade the token.
+    ///
+    /// This method can be called only by the token owner, and only once for each token.
+    #[update]
+    pub async fn release_controller(
+        &self,
+        name: String,
+        blackhole: Option<Principal>,
+    ) -> Result<ControllerRelease, TokenFactoryError> {
+        let token = self
+            .get_token(name)
+            .await
+            .ok_or(TokenFactoryError::FactoryError(FactoryError::NotFound))?;
+
+        if state::get_state().get_controller_release(token).is_some() {
+            return Err(TokenFactoryError::ControllerAlreadyReleased);
+        }
+
+        let caller = canister_sdk::ic_kit::ic::caller();
+        let owner = management::token_owner(token).await?;
+        if caller != owner || owner == Principal::anonymous() {
+            return Err(FactoryError::AccessDenied.into());
+        }
+
+        let mut controllers = vec![owner];
+        if let Some(blackhole) = blackhole {
+            if blackhole == token || blackhole == Principal::anonymous() {
+                return Err(TokenFactoryError::InvalidConfiguration(
+                    "blackhole",
+                    "cannot be the token itself or anonymous",
+                ));
+            }
+
+            if blackhole != owner {
+                controllers.push(blackhole);
+            }
+        }
+
+        management::set_controllers(token, controllers.clone()).await?;
+
+        let release = ControllerRelease {
+            token,
+            released_by: caller,
+            controllers,
+            timestamp: canister_sdk::ic_kit::ic::time(),
+        };
+        state::get_state().insert_controller_release(release.clone());
+        FactoryEvents::record(caller, FactoryEventKind::ControllerReleased { token });
+
+        Ok(release)
+    }
+
+    /// Deletes the token canister and returns its cycles to the factory.
+    ///
+    /// The token is asked to send its cycles to the factory first, as they cannot be withdrawn
+    /// from a stopped canister. Then the canister is stopped and deleted, the token is removed from
+    /// the registry and a tombstone is recorded. If the withdrawal fails (e.g. the token was
+    /// installed by an older factory version), the token is deleted anyway and the error is stored
+    /// in the tombstone.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn decommission_token(
+        &self,
+        token: Principal,
+    ) -> Result<TokenTombstone, TokenFactoryError> {
+        self.check_controller()?;
+
+        let (name, _) = state::get_state()
+            .list_tokens()
+            .into_iter()
+            .find(|(_, principal)| *principal == token)
+            .ok_or(TokenFactoryError::FactoryError(FactoryError::NotFound))?;
+
+        if state::get_state().get_controller_release(token).is_some() {
+            return Err(TokenFactoryError::ControllerAlreadyReleased);
+        }
+
+        let (cycles_reclaimed, withdrawal_error) =
+            match management::withdraw_token_cycles(token).await {
+                Ok(cycles) => (cycles, None),
+                Err(e) => {
+                    let mut error = e.to_string();
+                    truncate_message(&mut error, MAX_PROBE_ERROR_LEN);
+                    (0, Some(error))
+                }
+            };
+
+        self.drop_canister(token, None).await?;
+
+        let tombstone = TokenTombstone {
+            token,
+            name,
+            decommissioned_by: canister_sdk::ic_kit::ic::caller(),
+            cycles_reclaimed,
+            withdrawal_error,
+            timestamp: canister_sdk::ic_kit::ic::time(),
+        };
+        state::get_state().retire_token(tombstone.clone());
+        FactoryEvents::record(
+            tombstone.decommissioned_by,
+            FactoryEventKind::TokenDecommissioned {
+                token,
+                cycles_reclaimed,
+            },
+        );
+
+        Ok(tombstone)
+    }
+
+    /// Returns the tombstones of the decommissioned tokens.
+    #[query]
+    pub async fn get_token_tombstones(&self) -> Vec<TokenTombstone> {
+        state::get_state().list_tombstones()
+    }
+
+    /// Returns the deployments which are in flight or failed midway, of the `deployer` or of all
+    /// deployers if not set.
+    ///
+    /// This method can be called only by the `deployer` and by the factory controller.
+    #[query]
+    pub async fn get_pending_deployments(
+        &self,
+        deployer: Option<Principal>,
+    ) -> Result<Vec<PendingDeployment>, TokenFactoryError> {
+        if deployer != Some(canister_sdk::ic_kit::ic::caller()) {
+            self.check_controller()?;
+        }
+
+        Ok(state::get_state().pending_deployments(deployer))
+    }
+
+    /// Recovers the deployments which failed or stalled midway, see `get_pending_deployments`.
+    ///
+    /// If `retry_install` is set, the token code is installed again into the canisters created by
+    /// the failed deployments, and the tokens are registered. Otherwise, or if the canister of the
+    /// deployment is not known, the canister is deleted and the deployment fee is refunded to the
+    /// deployer. The deployments which cannot be recovered stay pending with the new error.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn recover_failed_deployments(
+        &self,
+        retry_install: bool,
+    ) -> Result<Vec<DeploymentRecovery>, TokenFactoryError> {
+        self.check_controller()?;
+
+        let now = canister_sdk::ic_kit::ic::time();
+        let deployments = state::get_state()
+            .pending_deployments(None)
+            .into_iter()
+            .filter(|deployment| deployment.is_recoverable(now))
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::with_capacity(deployments.len());
+        for deployment in deployments {
+            results.push(self.recover_deployment(deployment, retry_install).await);
+        }
+
+        Ok(results)
+    }
+
+    /// Returns up to 100 events of the factory log starting from the `offset`.
+    #[query]
+    pub async fn get_factory_events(&self, offset: u64, limit: u64) -> Vec<FactoryEvent> {
+        FactoryEvents::list(offset, limit)
+    }
+
+    /// Registers the calling canister to be notified about the new factory events with the
+    /// `on_factory_events : (vec FactoryEvent) -> ()` method. Calling it again resumes the
+    /// suspended subscription.
+    ///
+    /// A new subscription of a principal other than the controller must attach
+    /// `EVENT_SUBSCRIPTION_FEE_CYCLES`, which are kept by the factory.
+    #[update]
+    pub async fn subscribe_factory_events(&self) -> Result<EventSubscriber, TokenFactoryError> {
+        let caller = canister_sdk::ic_kit::ic::caller();
+        let fee = if caller == FactoryState::default().controller()
+            || FactoryEvents::get_subscriber(caller).is_some()
+        {
+            0
+        } else {
+            events::EVENT_SUBSCRIPTION_FEE_CYCLES
+        };
+        if canister_sdk::ic_kit::ic::msg_cycles_available() < fee {
+            return Err(TokenFactoryError::InvalidConfiguration(
+                "cycles",
+                "are not enough to subscribe to the factory events",
+            ));
+        }
+
+        let subscriber = FactoryEvents::subscribe(caller)?;
+        canister_sdk::ic_kit::ic::msg_cycles_accept(fee);
+        Ok(subscriber)
+    }
+
+    #[update]
+    pub async fn unsubscribe_factory_events(&self) -> Option<EventSubscriber> {
+        FactoryEvents::unsubscribe(canister_sdk::ic_kit::ic::caller())
+    }
+
+    /// Accepts the cycles sent by the tokens being decommissioned.
+    #[update]
+    pub async fn receive_cycles(&self) -> u64 {
+        canister_sdk::ic_kit::ic::msg_cycles_accept(u64::MAX)
+    }
+
+    /// Returns the record of the token controllers release, or None if the factory is still the
+    /// controller of the token.
+    #[query]
+    pub async fn get_controller_release(&self, token: Principal) -> Option<ControllerRelease> {
+        state::get_state().get_controller_release(token)
+    }
+
+    /// Returns the subnet the `token` was created on, if it was selected on deployment.
+    #[query]
+    pub async fn get_token_subnet(&self, token: Principal) -> Option<Principal> {
+        state::get_state().get_token_subnet(token)
+    }
+
+    /// Returns the number of the tokens deployed on every subnet selected on deployment, to
+    /// balance the fleet across the subnets.
+    #[query]
+    pub async fn get_subnet_usage(&self) -> Vec<(Principal, u64)> {
+        state::get_state().subnet_usage()
+    }
+
+    /// Returns the status of every deployed token as of the last health probe. The tokens are
+    /// probed in the background every hour.
+    #[query]
+    pub async fn get_fleet_status(&self) -> Vec<TokenStatus> {
+        state::get_state().fleet_status()
+    }
+
+    /// Updates the name, logo or fee of several tokens of the caller at once, e.g. to rebrand a
+    /// family of tokens. Every token applies the patch only if the caller is its current owner,
+    /// and the fee change is subject to the timelock of the token. The tokens which cannot be
+    /// reached are retried in the background, see `get_pending_metadata_updates`. The factory
+    /// registry keeps the tokens under the names they were deployed with.
+    #[update]
+    pub async fn update_token_metadata(
+        &self,
+        tokens: Vec<Principal>,
+        patch: MetadataPatch,
+    ) -> Result<Vec<MetadataUpdateResult>, TokenFactoryError> {
+        self.validate_metadata_patch(&patch)?;
+        if tokens.len() > metadata::MAX_TOKENS_PER_METADATA_UPDATE {
+            return Err(TokenFactoryError::InvalidConfiguration(
+                "tokens",
+                "too many tokens in one update",
+            ));
+        }
+
+        let deployed = state::get_state().list_tokens();
+        if tokens
+            .iter()
+            .any(|token| !deployed.iter().any(|(_, principal)| principal == token))
+        {
+            return Err(FactoryError::NotFound.into());
+        }
+
+        let owner = canister_sdk::ic_kit::ic::caller();
+        Ok(metadata::update_tokens(owner, tokens, patch).await)
+    }
+
+    /// Returns the metadata updates of the tokens which could not be reached, waiting for a retry.
+    #[query]
+    pub async fn get_pending_metadata_updates(&self) -> Vec<PendingMetadataUpdate> {
+        state::get_state().pending_metadata_updates()
+    }
+
+    /// Upgrades all the tokens to the current token wasm. If the wasm compatibility is set, the
+    /// upgrade is refused when any of the tokens has a state version the wasm doesn't support,
+    /// unless it's forced with the id of the migration that makes it safe.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn upgrade(
+        &mut self,
+        force: Option<ForceUpgrade>,
+    ) -> Result<HashMap<Principal, UpgradeResult>, TokenFactoryError> {
+        self.check_controller()?;
+
+        let migration_id = match force {
+            Some(ForceUpgrade { migration_id }) if migration_id.is_empty() => {
+                return Err(TokenFactoryError::InvalidConfiguration(
+                    "migration_id",
+                    "cannot be empty",
+                ))
+            }
+            Some(ForceUpgrade { migration_id }) => Some(migration_id),
+            None => {
+                self.check_state_versions().await?;
+                None
+            }
+        };
+
+        let results = self.upgrade_canister().await?;
+
+        let now = canister_sdk::ic_kit::ic::time();
+        let mut state = state::get_state();
+        for (name, token) in state.list_tokens() {
+            if matches!(results.get(&token), Some(UpgradeResult::Upgraded)) {
+                state.record_upgrade(token, name, now, migration_id.clone());
+                FactoryEvents::record(
+                    canister_sdk::ic_kit::ic::caller(),
+                    FactoryEventKind::TokenUpgraded {
+                        token,
+                        migration_id: migration_id.clone(),
+                    },
+                );
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Schedules the upgrade of all the tokens to the current token wasm in a maintenance window
+    /// from `start_time` to `end_time` (nanoseconds since the epoch). The tokens are upgraded in
+    /// batches of `batch_size` every minute, and each must report the `version` with
+    /// `pkg_version` after the upgrade. The rollout is paused when more than `max_failures` tokens
+    /// fail to upgrade, or when it is not finished by `end_time`.
+    /// Only one rollout can be scheduled at a time.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn schedule_upgrade(
+        &self,
+        version: String,
+        start_time: u64,
+        end_time: u64,
+        batch_size: u32,
+        max_failures: u32,
+    ) -> Result<UpgradeSchedule, TokenFactoryError> {
+        self.check_controller()?;
+        rollout::schedule(
+            canister_sdk::ic_kit::ic::caller(),
+            version,
+            start_time,
+            end_time,
+            batch_size,
+            max_failures,
+        )
+    }
+
+    /// Continues the rollout paused over the failure budget, with the budget reset.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn resume_upgrade(&self) -> Result<UpgradeSchedule, TokenFactoryError> {
+        self.check_controller()?;
+        rollout::resume()
+    }
+
+    /// Stops the scheduled rollout. The tokens already upgraded are not rolled back.
+    ///
+    /// This method can be called only by the factory controller.
+    #[update]
+    pub async fn cancel_upgrade(&self) -> Result<UpgradeSchedule, TokenFactoryError> {
+        self.check_controller()?;
+        rollout::cancel()
+    }
+
+    /// Returns the progress of the last scheduled rollout, or None if no rollout was scheduled.
+    #[query]
+    pub async fn get_upgrade_progress(&self) -> Option<UpgradeSchedule> {
+        state::get_state().get_upgrade_schedule()
+    }
+
+    /// Returns the upgrade status of every token of the last scheduled rollout.
+    #[query]
+    pub async fn get_token_upgrade_statuses(&self) -> Vec<TokenUpgradeStatus> {
+        state::get_state().token_upgrade_statuses()
+    }
+}
+
+impl TokenFactoryCanister {
+    /// Checks that the current token wasm can be installed over every token still controlled by
+    /// the factory.
+    async fn check_state_versions(&self) -> Result<(), TokenFactoryError> {
+        let state = state::get_state();
+        let Some(compatibility) = state.get_wasm_compatibility() else {
+            return Ok(());
+        };
+
+        for (_, token) in state.list_tokens() {
+            if state.get_controller_release(token).is_some() {
+                continue;
+            }
+
+            let current = management::token_state_version(token).await;
+            if !compatibility.accepts(current) {
+                return Err(TokenFactoryError::IncompatibleStateVersion {
+                    token,
+                    current,
+                    supported: compatibility.supported_versions(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the `deployer` can deploy one more token under the deploy quota.
+    fn check_deploy_quota(&self, deployer: Principal) -> Result<(), TokenFactoryError> {
+        let state = state::get_state();
+        let Some(quota) = state.get_deploy_quota() else {
+            return Ok(());
+        };
+        if FactoryState::default().controller() == deployer || state.is_on_allowlist(deployer) {
+            return Ok(());
+        }
+
+        if state.tokens_of_deployer(deployer).len() as u64 >= quota {
+            return Err(TokenFactoryError::DeployQuotaExceeded { deployer, quota });
+        }
+
+        Ok(())
+    }
+
+    fn check_controller(&self) -> Result<(), FactoryError> {
+        if FactoryState::default().controller() != canister_sdk::ic_kit::ic::caller() {
+            return Err(FactoryError::AccessDenied);
+        }
+
+        Ok(())
+    }
+
+    fn validate_metadata(&self, info: &Metadata) -> Result<(), TokenFactoryError> {
         if info.name.is_empty() {
             return Err(TokenFactoryError::InvalidConfiguration(
                 "name",
@@ -133,6 +870,13 @@ impl TokenFactoryCanister {
             ));
         }
 
+        if info.name.chars().any(char::is_control) {
+            return Err(TokenFactoryError::InvalidConfiguration(
+                "name",
+                "cannot contain control characters",
+            ));
+        }
+
         if info.symbol.is_empty() {
             return Err(TokenFactoryError::InvalidConfiguration(
                 "symbol",
@@ -140,37 +884,375 @@ impl TokenFactoryCanister {
             ));
         }
 
-        let key = info.name.clone();
-        if state::get_state().get_token(key.clone()).is_some() {
+        let state = state::get_state();
+        state.get_symbol_rules().validate(&info.symbol)?;
+        if state.get_token_by_symbol(&info.symbol).is_some() {
+            return Err(TokenFactoryError::InvalidTokenSymbol(
+                "a token with the same symbol is already registered".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_metadata_patch(&self, patch: &MetadataPatch) -> Result<(), TokenFactoryError> {
+        if patch.is_empty() {
+            return Err(TokenFactoryError::InvalidConfiguration(
+                "patch",
+                "cannot be empty",
+            ));
+        }
+
+        if let Some(name) = &patch.name {
+            if name.is_empty()
+                || name.as_bytes().len() > MAX_TOKEN_LEN_IN_BYTES
+                || name.chars().any(char::is_control)
+            {
+                return Err(TokenFactoryError::InvalidConfiguration(
+                    "name",
+                    "should be a non-empty text of less than 1024 bytes",
+                ));
+            }
+        }
+
+        if patch
+            .logo
+            .as_ref()
+            .map_or(false, |logo| logo.len() > MAX_LOGO_SIZE)
+        {
+            return Err(TokenFactoryError::InvalidConfiguration(
+                "logo",
+                "is too large",
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn deploy_token(
+        &self,
+        info: Metadata,
+        amount: Tokens128,
+        controller: Option<Principal>,
+        funding: Funding,
+    ) -> Result<Principal, TokenFactoryError> {
+        if state::get_state().get_token(info.name.clone()).is_some() {
             return Err(TokenFactoryError::AlreadyExists);
         }
 
+        // Checked here as well as by the token init, so an invalid spec doesn't cost a canister.
+        if let Some(spec) = &info.init_spec {
+            spec.validate(amount)
+                .map_err(|e| TokenFactoryError::InvalidInitSpec(e.to_string()))?;
+        }
+
         let caller = canister_sdk::ic_kit::ic::caller();
-        let principal = self
-            .create_canister((info, amount), controller, Some(caller))
-            .await?;
-        state::get_state().insert_token(key, principal);
+        if !state::get_state().is_allowed_deployer(caller)
+            && FactoryState::default().controller() != caller
+        {
+            return Err(TokenFactoryError::DeployerNotAllowed(caller));
+        }
+
+        self.check_deploy_quota(caller)?;
 
-        Ok(principal)
+        let subnet = match &funding {
+            Funding::CallerOnSubnet(selection) => {
+                if canister_sdk::ic_kit::ic::msg_cycles_available() < MIN_SUBNET_DEPLOYMENT_CYCLES {
+                    return Err(TokenFactoryError::InvalidConfiguration(
+                        "cycles",
+                        "are not enough to create the canister on the subnet",
+                    ));
+                }
+                Some(management::resolve_subnet(selection.clone()).await?)
+            }
+            _ => None,
+        };
+
+        registry::reserve_symbol(&info.symbol, &info.name).await?;
+
+        let deployment_fee = state::get_state().get_deployment_fee();
+        if let Some(fee) = &deployment_fee {
+            deployment_fee::charge(fee, caller).await?;
+        }
+
+        // From now on the deployment is tracked, so the fee and the canister are not lost if the
+        // deployment fails midway.
+        let deployment_id = state::get_state().begin_deployment(
+            caller,
+            info.clone(),
+            amount,
+            controller,
+            deployment_fee.clone(),
+            subnet,
+            canister_sdk::ic_kit::ic::time(),
+        );
+
+        let created = match funding {
+            Funding::Caller => self
+                .create_canister((info, amount), controller, Some(caller))
+                .await
+                .map_err(TokenFactoryError::from),
+            Funding::CallerOnSubnet(_) => self.create_on_subnet(deployment_id).await,
+            #[cfg(feature = "test-endpoints")]
+            Funding::Factory => self.create_self_funded(deployment_id).await,
+        };
+
+        let principal = match created {
+            Ok(principal) => principal,
+            Err(e) => {
+                let mut state = state::get_state();
+                let mut deployment = state
+                    .get_pending_deployment(deployment_id)
+                    .expect("the deployment is tracked");
+                deployment.fail(e.to_string(), canister_sdk::ic_kit::ic::time());
+                state.update_deployment(deployment.clone());
+
+                // If no canister was created, only the fee is left to recover, so it is refunded
+                // right away. If the refund fails, the deployment stays pending with both errors,
+                // and the refund is retried by `recover_failed_deployments`.
+                if deployment.canister.is_none() {
+                    let refunded = match &deployment_fee {
+                        Some(fee) => deployment_fee::refund(fee, caller).await,
+                        None => Ok(()),
+                    };
+                    match refunded {
+                        Ok(()) => state.finish_deployment(deployment_id),
+                        Err(refund_error) => {
+                            deployment.fail(
+                                format!("{e}; the refund failed: {refund_error}"),
+                                canister_sdk::ic_kit::ic::time(),
+                            );
+                            state.update_deployment(deployment);
+                        }
+                    }
+                }
+
+                return Err(e);
+            }
+        };
+
+        let deployment = state::get_state()
+            .get_pending_deployment(deployment_id)
+            .expect("the deployment is tracked");
+        Ok(self.complete_deployment(deployment, principal).await)
     }
 
-    #[update]
-    pub async fn forget_token(&self, name: String) -> Result<(), TokenFactoryError> {
-        let canister_id = self
-            .get_token(name.clone())
-            .await
-            .ok_or(TokenFactoryError::FactoryError(FactoryError::NotFound))?;
+    /// Registers the token of the `deployment` and passes the deployment fee to its recipient.
+    async fn complete_deployment(
+        &self,
+        deployment: PendingDeployment,
+        principal: Principal,
+    ) -> Principal {
+        let name = deployment.metadata.name;
+        let mut state = state::get_state();
+        state.insert_token_symbol(&deployment.metadata.symbol, name.clone());
+        state.insert_token(name.clone(), principal);
+        let fee_paid = deployment
+            .fee
+            .as_ref()
+            .map_or(Tokens128::ZERO, |fee| fee.amount);
+        state.record_deployment(
+            deployment.deployer,
+            principal,
+            fee_paid,
+            canister_sdk::ic_kit::ic::time(),
+        );
+        if let Some(subnet) = deployment.subnet {
+            state.record_token_subnet(principal, subnet);
+        }
+        state.finish_deployment(deployment.id);
+        FactoryEvents::record(
+            deployment.deployer,
+            FactoryEventKind::TokenDeployed {
+                token: principal,
+                name: name.clone(),
+            },
+        );
 
-        self.drop_canister(canister_id, None).await?;
-        state::get_state().remove_token(name);
+        registry::register_token(deployment.metadata.symbol, name, principal).await;
+
+        if let Some(fee) = &deployment.fee {
+            // The token is already created at this point, so failing to pass the fee to the
+            // recipient must not fail the call. The fee stays on the factory account then, and
+            // the failure is recorded in the factory log for the operators.
+            if let Err(e) = deployment_fee::collect(fee).await {
+                let mut error = e.to_string();
+                truncate_message(&mut error, MAX_PROBE_ERROR_LEN);
+                FactoryEvents::record(
+                    deployment.deployer,
+                    FactoryEventKind::DeploymentFeeNotCollected {
+                        token: principal,
+                        error,
+                    },
+                );
+            }
+        }
+
+        principal
+    }
+
+    async fn recover_deployment(
+        &self,
+        mut deployment: PendingDeployment,
+        retry_install: bool,
+    ) -> DeploymentRecovery {
+        let id = deployment.id;
+        // Marks the deployment as in progress, so a concurrent call doesn't recover it again.
+        deployment.stage = DeploymentStage::Recovering;
+        deployment.updated_at = canister_sdk::ic_kit::ic::time();
+        deployment.recovery_attempts += 1;
+        state::get_state().update_deployment(deployment.clone());
+
+        let result = match deployment.canister {
+            Some(canister) if retry_install => self
+                .install_token(&deployment, canister)
+                .await
+                .map(|_| Some(canister)),
+            _ => self.undo_deployment(&mut deployment).await.map(|_| None),
+        };
+
+        match result {
+            Ok(Some(token)) => {
+                self.complete_deployment(deployment, token).await;
+                DeploymentRecovery::Installed { id, token }
+            }
+            Ok(None) => {
+                state::get_state().finish_deployment(id);
+                DeploymentRecovery::Refunded { id }
+            }
+            Err(e) => {
+                let error = e.to_string();
+                deployment.fail(error.clone(), canister_sdk::ic_kit::ic::time());
+                state::get_state().update_deployment(deployment);
+                DeploymentRecovery::Failed { id, error }
+            }
+        }
+    }
+
+    /// Deletes the canister of the `deployment` if it was created, and refunds the deployment
+    /// fee.
+    async fn undo_deployment(
+        &self,
+        deployment: &mut PendingDeployment,
+    ) -> Result<(), TokenFactoryError> {
+        if let Some(canister) = deployment.canister {
+            self.drop_canister(canister, None).await?;
+            // Stored before the refund, so a failed refund doesn't try to delete it again.
+            deployment.canister = None;
+            state::get_state().update_deployment(deployment.clone());
+        }
+
+        if let Some(fee) = &deployment.fee {
+            deployment_fee::refund(fee, deployment.deployer).await?;
+        }
 
         Ok(())
     }
 
-    #[update]
-    pub async fn upgrade(&mut self) -> Result<HashMap<Principal, UpgradeResult>, FactoryError> {
-        self.upgrade_canister().await
+    /// Installs the token wasm into the `canister` created for the `deployment`.
+    async fn install_token(
+        &self,
+        deployment: &PendingDeployment,
+        canister: Principal,
+    ) -> Result<(), TokenFactoryError> {
+        // The name or the symbol could be taken by another token since the deployment started.
+        let state = state::get_state();
+        if state.get_token(deployment.metadata.name.clone()).is_some() {
+            return Err(TokenFactoryError::AlreadyExists);
+        }
+        if state
+            .get_token_by_symbol(&deployment.metadata.symbol)
+            .is_some()
+        {
+            return Err(TokenFactoryError::InvalidTokenSymbol(
+                "a token with the same symbol is already registered".to_string(),
+            ));
+        }
+
+        let (wasm, arg) = self.install_args(deployment)?;
+        management::install_code(canister, wasm, arg).await
+    }
+
+    /// Returns the token wasm and the init arguments of the token of the `deployment`.
+    fn install_args(
+        &self,
+        deployment: &PendingDeployment,
+    ) -> Result<(Vec<u8>, Vec<u8>), TokenFactoryError> {
+        let wasm =
+            state::get_state()
+                .get_token_wasm()
+                .ok_or(TokenFactoryError::InvalidConfiguration(
+                    "token bytecode",
+                    "is not set",
+                ))?;
+        let arg = candid::encode_args((deployment.metadata.clone(), deployment.amount))
+            .map_err(|_| TokenFactoryError::InvalidConfiguration("metadata", "not encodable"))?;
+        Ok((wasm, arg))
+    }
+
+    /// Creates the canister of the deployment with the factory cycles and installs the token into
+    /// it. If the installation fails, the canister is kept with the deployment for
+    /// `recover_failed_deployments`.
+    #[cfg(feature = "test-endpoints")]
+    async fn create_self_funded(&self, deployment_id: u64) -> Result<Principal, TokenFactoryError> {
+        let mut deployment = state::get_state()
+            .get_pending_deployment(deployment_id)
+            .expect("the deployment is tracked");
+        // Checked before the canister is created, so a missing bytecode doesn't leave it behind.
+        self.install_args(&deployment)?;
+
+        let mut controllers = vec![canister_sdk::ic_kit::ic::id()];
+        controllers.extend(deployment.controller);
+        let canister =
+            management::create_canister(controllers, SELF_FUNDED_CANISTER_CYCLES).await?;
+
+        deployment.canister = Some(canister);
+        deployment.stage = DeploymentStage::Installing;
+        deployment.updated_at = canister_sdk::ic_kit::ic::time();
+        state::get_state().update_deployment(deployment.clone());
+
+        self.install_token(&deployment, canister).await?;
+        Ok(canister)
     }
+
+    /// Creates the canister of the deployment on its subnet with the cycles attached to the call
+    /// and installs the token into it. If the installation fails, the canister is kept with the
+    /// deployment for `recover_failed_deployments`.
+    async fn create_on_subnet(&self, deployment_id: u64) -> Result<Principal, TokenFactoryError> {
+        let mut deployment = state::get_state()
+            .get_pending_deployment(deployment_id)
+            .expect("the deployment is tracked");
+        let subnet = deployment
+            .subnet
+            .expect("the subnet is set for the deployments on a subnet");
+        // Checked before the canister is created, so a missing bytecode doesn't leave it behind.
+        self.install_args(&deployment)?;
+
+        let mut controllers = vec![canister_sdk::ic_kit::ic::id()];
+        controllers.extend(deployment.controller);
+        let cycles = canister_sdk::ic_kit::ic::msg_cycles_accept(u64::MAX);
+        let canister = management::create_canister_on_subnet(controllers, subnet, cycles).await?;
+
+        deployment.canister = Some(canister);
+        deployment.stage = DeploymentStage::Installing;
+        deployment.updated_at = canister_sdk::ic_kit::ic::time();
+        state::get_state().update_deployment(deployment.clone());
+
+        self.install_token(&deployment, canister).await?;
+        Ok(canister)
+    }
+}
+
+/// Who pays for the token canister creation.
+enum Funding {
+    /// The caller, with the ICP or the cycles attached to the call.
+    Caller,
+    /// The caller, with the cycles attached to the call. The canister is created on the selected
+    /// subnet.
+    CallerOnSubnet(SubnetSelection),
+    /// The factory, with its own cycles.
+    #[cfg(feature = "test-endpoints")]
+    Factory,
 }
 
 impl FactoryCanister for TokenFactoryCanister {}
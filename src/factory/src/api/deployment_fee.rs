@@ -0,0 +1,108 @@
+//! Charging of the token deployment fee in ICP or any other ICRC-1 token.
+
+use candid::Principal;
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use token::account::{Account, Subaccount};
+use token::error::TransferError;
+use token::state::ledger::TransferArgs;
+
+use crate::error::TokenFactoryError;
+use crate::state::DeploymentFee;
+
+/// Returns the subaccount of the factory account the `payer` must deposit the deployment fee to.
+pub fn deployment_fee_subaccount(payer: Principal) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    let principal_id = payer.as_slice();
+    subaccount[0] = principal_id.len() as u8;
+    subaccount[1..1 + principal_id.len()].copy_from_slice(principal_id);
+    subaccount
+}
+
+/// Moves the deployment fee from the `payer` deposit subaccount to the factory default account.
+pub async fn charge(fee: &DeploymentFee, payer: Principal) -> Result<(), TokenFactoryError> {
+    let transfer = TransferArgs {
+        from_subaccount: Some(deployment_fee_subaccount(payer)),
+        to: ic::id().into(),
+        amount: fee.amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    match icrc1_transfer(fee.ledger, transfer).await? {
+        Ok(_) => Ok(()),
+        Err(TransferError::InsufficientFunds { balance }) => {
+            Err(TokenFactoryError::DeploymentFeeNotPaid {
+                required: fee.amount,
+                deposited: balance,
+            })
+        }
+        Err(e) => Err(TokenFactoryError::LedgerError(format!("{e:?}"))),
+    }
+}
+
+/// Returns the charged deployment fee (minus the ledger transfer fee) to the `payer`.
+pub async fn refund(fee: &DeploymentFee, payer: Principal) -> Result<(), TokenFactoryError> {
+    send_from_factory(fee, payer).await
+}
+
+/// Sends the charged deployment fee (minus the ledger transfer fee) to the fee recipient.
+pub async fn collect(fee: &DeploymentFee) -> Result<(), TokenFactoryError> {
+    send_from_factory(fee, fee.fee_to).await
+}
+
+async fn send_from_factory(fee: &DeploymentFee, to: Principal) -> Result<(), TokenFactoryError> {
+    let ledger_fee = icrc1_fee(fee.ledger).await?;
+    let amount = fee.amount.saturating_sub(ledger_fee);
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let transfer = TransferArgs {
+        from_subaccount: None,
+        to: Account::from(to),
+        amount,
+        fee: Some(ledger_fee),
+        memo: None,
+        created_at_time: None,
+    };
+
+    icrc1_transfer(fee.ledger, transfer)
+        .await?
+        .map(|_| ())
+        .map_err(|e| TokenFactoryError::LedgerError(format!("{e:?}")))
+}
+
+async fn icrc1_fee(ledger: Principal) -> Result<Tokens128, TokenFactoryError> {
+    ic::call::<_, (Tokens128,), _>(ledger, "icrc1_fee", ())
+        .await
+        .map(|(fee,)| fee)
+        .map_err(|(_, msg)| TokenFactoryError::LedgerError(msg))
+}
+
+async fn icrc1_transfer(
+    ledger: Principal,
+    transfer: TransferArgs,
+) -> Result<Result<u128, TransferError>, TokenFactoryError> {
+    ic::call::<_, (Result<u128, TransferError>,), _>(ledger, "icrc1_transfer", (transfer,))
+        .await
+        .map(|(result,)| result)
+        .map_err(|(_, msg)| TokenFactoryError::LedgerError(msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deployment_fee_subaccount_encodes_principal() {
+        let principal = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let subaccount = deployment_fee_subaccount(principal);
+
+        let len = principal.as_slice().len();
+        assert_eq!(subaccount[0] as usize, len);
+        assert_eq!(&subaccount[1..1 + len], principal.as_slice());
+        assert!(subaccount[1 + len..].iter().all(|b| *b == 0));
+    }
+}
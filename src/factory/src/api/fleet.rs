@@ -0,0 +1,79 @@
+//! Periodic health probe of the deployed token canisters.
+//!
+//! The probe runs in a background timer and stores the results in the factory state, so the
+//! `get_fleet_status` query only reads the stored records.
+
+use std::time::Duration;
+
+use candid::Principal;
+use canister_sdk::ic_kit::ic;
+use token::error::truncate_message;
+
+use super::management;
+use crate::state::{self, TokenStatus, MAX_PROBE_ERROR_LEN};
+
+pub const HEALTH_PROBE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Starts the periodic health probe. Timers are not preserved on upgrade, so this must be called
+/// both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_health_probes() {
+    ic_exports::ic_cdk_timers::set_timer_interval(HEALTH_PROBE_INTERVAL, || {
+        canister_sdk::ic_cdk::spawn(probe_fleet())
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_health_probes() {}
+
+/// Probes every deployed token and stores its status.
+pub async fn probe_fleet() {
+    for (name, token) in state::get_state().list_tokens() {
+        let status = probe_token(name, token).await;
+        state::get_state().set_token_status(status);
+    }
+}
+
+async fn probe_token(name: String, token: Principal) -> TokenStatus {
+    let mut status = state::get_state()
+        .get_token_status(token)
+        .unwrap_or_else(|| TokenStatus::new(token, name.clone()));
+    status.name = name;
+    status.last_probe = ic::time();
+
+    // The factory is not a controller of the released tokens, so their canister status is not
+    // available.
+    if state::get_state().get_controller_release(token).is_none() {
+        match management::canister_status(token).await {
+            Ok(canister_status) => {
+                status.record_cycles(u128::try_from(canister_status.cycles.0).ok());
+                status.module_hash = canister_status.module_hash;
+            }
+            Err(e) => {
+                status.cycles = None;
+                status.module_hash = None;
+                return failed(status, e.to_string());
+            }
+        }
+    } else {
+        status.cycles = None;
+        status.module_hash = None;
+    }
+
+    match management::token_pkg_version(token).await {
+        Ok(version) => {
+            status.wasm_version = Some(version);
+            status.healthy = true;
+            status.error = None;
+            status
+        }
+        Err(e) => failed(status, e.to_string()),
+    }
+}
+
+fn failed(mut status: TokenStatus, mut error: String) -> TokenStatus {
+    truncate_message(&mut error, MAX_PROBE_ERROR_LEN);
+    status.healthy = false;
+    status.error = Some(error);
+    status
+}
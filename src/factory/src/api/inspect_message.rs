@@ -1,3 +1,4 @@
+use super::CONTROLLER_METHODS;
 use crate::state;
 use canister_sdk::{ic_cdk, ic_cdk_macros::inspect_message, ic_factory::FactoryState};
 
@@ -6,7 +7,7 @@ fn inspect_message() {
     let state = state::get_state();
     let factory = FactoryState::default();
 
-    if ic_cdk::api::call::method_name() == "set_token_bytecode" {
+    if CONTROLLER_METHODS.contains(&ic_cdk::api::call::method_name().as_str()) {
         if factory.controller() == canister_sdk::ic_kit::ic::caller() {
             return ic_cdk::api::call::accept_message();
         }
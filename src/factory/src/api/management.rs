@@ -0,0 +1,270 @@
+//! Helpers for the calls to the deployed token canisters and the management canister.
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use canister_sdk::ic_cdk::api::management_canister::main::{
+    CanisterIdRecord as StatusRequest, CanisterInstallMode, CanisterSettings,
+    CanisterStatusResponse, InstallCodeArgument, UpdateSettingsArgument,
+};
+#[cfg(feature = "test-endpoints")]
+use canister_sdk::ic_cdk::api::management_canister::main::{
+    CanisterIdRecord, CreateCanisterArgument,
+};
+use canister_sdk::ic_kit::ic;
+
+use token::error::TxError;
+use token::state::config::{Metadata, MetadataPatch, TokenInfo};
+
+use crate::error::TokenFactoryError;
+use crate::state::SubnetSelection;
+
+/// The cycles minting canister, which creates the canisters on the selected subnets.
+const CYCLES_MINTING_CANISTER: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 4, 1, 1]);
+
+/// The NNS registry canister, which knows the subnet of every canister.
+const REGISTRY_CANISTER: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 1, 1]);
+
+/// Queries the owner of the token canister.
+pub async fn token_owner(token: Principal) -> Result<Principal, TokenFactoryError> {
+    ic::call::<_, (Principal,), _>(token, "owner", ())
+        .await
+        .map(|(owner,)| owner)
+        .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(token, msg))
+}
+
+/// Queries the metadata the token canister was created with, updated with the later changes.
+pub async fn token_metadata(token: Principal) -> Result<Metadata, TokenFactoryError> {
+    ic::call::<_, (TokenInfo,), _>(token, "get_token_info", ())
+        .await
+        .map(|(info,)| info.metadata)
+        .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(token, msg))
+}
+
+/// Queries the crate version of the token canister.
+pub async fn token_pkg_version(token: Principal) -> Result<String, TokenFactoryError> {
+    ic::call::<_, (String,), _>(token, "pkg_version", ())
+        .await
+        .map(|(version,)| version)
+        .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(token, msg))
+}
+
+/// Queries the version of the stable state layout of the token canister. The tokens built before
+/// the version was introduced don't have the method and are reported as version 0.
+pub async fn token_state_version(token: Principal) -> u32 {
+    ic::call::<_, (u32,), _>(token, "get_state_version", ())
+        .await
+        .map(|(version,)| version)
+        .unwrap_or(0)
+}
+
+/// Requests the token canister to send its cycles to the factory. Returns the amount of the
+/// received cycles.
+pub async fn withdraw_token_cycles(token: Principal) -> Result<u64, TokenFactoryError> {
+    ic::call::<_, (Result<u64, TxError>,), _>(token, "withdraw_cycles_to_deployer", ())
+        .await
+        .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(token, msg))?
+        .0
+        .map_err(|err| TokenFactoryError::CanisterCallFailed(token, err.to_string()))
+}
+
+/// Requests the token canister to apply the metadata `patch` on behalf of its `owner`. The outer
+/// error means the token could not be reached, the inner one that the token refused the patch.
+pub async fn update_token_metadata(
+    token: Principal,
+    owner: Principal,
+    patch: MetadataPatch,
+) -> Result<Result<(), TxError>, TokenFactoryError> {
+    ic::call::<_, (Result<(), TxError>,), _>(token, "update_metadata_from_deployer", (owner, patch))
+        .await
+        .map(|(result,)| result)
+        .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(token, msg))
+}
+
+/// Returns the status of the `canister`. The factory must be its controller.
+pub async fn canister_status(
+    canister: Principal,
+) -> Result<CanisterStatusResponse, TokenFactoryError> {
+    let args = StatusRequest {
+        canister_id: canister,
+    };
+    ic::call::<_, (CanisterStatusResponse,), _>(
+        Principal::management_canister(),
+        "canister_status",
+        (args,),
+    )
+    .await
+    .map(|(status,)| status)
+    .map_err(|(_, msg)| {
+        TokenFactoryError::CanisterCallFailed(Principal::management_canister(), msg)
+    })
+}
+
+/// Replaces the controllers of the `canister` with the given list.
+pub async fn set_controllers(
+    canister: Principal,
+    controllers: Vec<Principal>,
+) -> Result<(), TokenFactoryError> {
+    let args = UpdateSettingsArgument {
+        canister_id: canister,
+        settings: CanisterSettings {
+            controllers: Some(controllers),
+            compute_allocation: None,
+            memory_allocation: None,
+            freezing_threshold: None,
+        },
+    };
+
+    ic::call::<_, (), _>(Principal::management_canister(), "update_settings", (args,))
+        .await
+        .map_err(|(_, msg)| {
+            TokenFactoryError::CanisterCallFailed(Principal::management_canister(), msg)
+        })
+}
+
+/// Creates an empty canister paid with the factory cycles.
+#[cfg(feature = "test-endpoints")]
+pub async fn create_canister(
+    controllers: Vec<Principal>,
+    cycles: u64,
+) -> Result<Principal, TokenFactoryError> {
+    let management = Principal::management_canister();
+    let args = CreateCanisterArgument {
+        settings: Some(CanisterSettings {
+            controllers: Some(controllers),
+            compute_allocation: None,
+            memory_allocation: None,
+            freezing_threshold: None,
+        }),
+    };
+
+    ic::call_with_payment::<_, (CanisterIdRecord,), _>(
+        management,
+        "create_canister",
+        (args,),
+        cycles,
+    )
+    .await
+    .map(|(CanisterIdRecord { canister_id },)| canister_id)
+    .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(management, msg))
+}
+
+/// Installs the `wasm` into the empty `canister`.
+pub async fn install_code(
+    canister: Principal,
+    wasm: Vec<u8>,
+    arg: Vec<u8>,
+) -> Result<(), TokenFactoryError> {
+    let management = Principal::management_canister();
+    let args = InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: canister,
+        wasm_module: wasm,
+        arg,
+    };
+    ic::call::<_, (), _>(management, "install_code", (args,))
+        .await
+        .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(management, msg))
+}
+
+/// Upgrades the token `canister` to the `wasm`. The token `post_upgrade` takes no arguments.
+pub async fn upgrade_code(canister: Principal, wasm: Vec<u8>) -> Result<(), TokenFactoryError> {
+    let management = Principal::management_canister();
+    let args = InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade,
+        canister_id: canister,
+        wasm_module: wasm,
+        arg: candid::encode_args(()).expect("failed to encode empty arguments"),
+    };
+    ic::call::<_, (), _>(management, "install_code", (args,))
+        .await
+        .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(management, msg))
+}
+
+#[derive(CandidType)]
+struct CmcCreateCanisterArg {
+    settings: Option<CanisterSettings>,
+    subnet_selection: Option<CmcSubnetSelection>,
+    subnet_type: Option<String>,
+}
+
+#[derive(CandidType)]
+enum CmcSubnetSelection {
+    Subnet { subnet: Principal },
+}
+
+#[derive(CandidType, Deserialize)]
+enum CmcCreateCanisterError {
+    Refunded {
+        refund_amount: Nat,
+        create_error: String,
+    },
+}
+
+#[derive(CandidType)]
+struct GetSubnetForCanisterRequest {
+    principal: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SubnetForCanister {
+    subnet_id: Option<Principal>,
+}
+
+/// Returns the id of the subnet selected by the `selection`. The subnet of a canister is looked
+/// up in the NNS registry.
+pub async fn resolve_subnet(selection: SubnetSelection) -> Result<Principal, TokenFactoryError> {
+    let canister = match selection {
+        SubnetSelection::Subnet { subnet } => return Ok(subnet),
+        SubnetSelection::Canister { canister } => canister,
+    };
+
+    let args = GetSubnetForCanisterRequest {
+        principal: Some(canister),
+    };
+    let (result,) = ic::call::<_, (Result<SubnetForCanister, String>,), _>(
+        REGISTRY_CANISTER,
+        "get_subnet_for_canister",
+        (args,),
+    )
+    .await
+    .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(REGISTRY_CANISTER, msg))?;
+
+    result
+        .map_err(|msg| TokenFactoryError::CanisterCallFailed(REGISTRY_CANISTER, msg))?
+        .subnet_id
+        .ok_or(TokenFactoryError::InvalidConfiguration(
+            "subnet",
+            "is not found for the canister",
+        ))
+}
+
+/// Creates an empty canister on the `subnet` through the cycles minting canister, paid with the
+/// `cycles` of the factory.
+pub async fn create_canister_on_subnet(
+    controllers: Vec<Principal>,
+    subnet: Principal,
+    cycles: u64,
+) -> Result<Principal, TokenFactoryError> {
+    let args = CmcCreateCanisterArg {
+        settings: Some(CanisterSettings {
+            controllers: Some(controllers),
+            compute_allocation: None,
+            memory_allocation: None,
+            freezing_threshold: None,
+        }),
+        subnet_selection: Some(CmcSubnetSelection::Subnet { subnet }),
+        subnet_type: None,
+    };
+
+    let (result,) = ic::call_with_payment::<_, (Result<Principal, CmcCreateCanisterError>,), _>(
+        CYCLES_MINTING_CANISTER,
+        "create_canister",
+        (args,),
+        cycles,
+    )
+    .await
+    .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(CYCLES_MINTING_CANISTER, msg))?;
+
+    result.map_err(|CmcCreateCanisterError::Refunded { create_error, .. }| {
+        TokenFactoryError::CanisterCallFailed(CYCLES_MINTING_CANISTER, create_error)
+    })
+}
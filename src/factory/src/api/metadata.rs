@@ -0,0 +1,112 @@
+//! Metadata updates of the deployed tokens requested by their owners through the factory.
+//!
+//! The factory relays the update to every token with the `update_metadata_from_deployer` call,
+//! which the token accepts only from its deployer and only on behalf of its current owner. The
+//! updates of the tokens which could not be reached are stored and retried in a background timer,
+//! up to `MAX_METADATA_UPDATE_ATTEMPTS` times.
+
+use std::time::Duration;
+
+use candid::Principal;
+use canister_sdk::ic_kit::ic;
+use token::error::truncate_message;
+use token::state::config::MetadataPatch;
+
+use super::management;
+use crate::events::{FactoryEventKind, FactoryEvents};
+use crate::state::{
+    self, MetadataUpdateResult, MetadataUpdateStatus, PendingMetadataUpdate,
+    MAX_METADATA_UPDATE_ATTEMPTS, MAX_PROBE_ERROR_LEN,
+};
+
+pub const METADATA_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Maximum number of the tokens updated by one `update_token_metadata` call.
+pub const MAX_TOKENS_PER_METADATA_UPDATE: usize = 100;
+
+/// Starts the periodic retries of the pending updates. Timers are not preserved on upgrade, so
+/// this must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_metadata_retries() {
+    ic_exports::ic_cdk_timers::set_timer_interval(METADATA_RETRY_INTERVAL, || {
+        canister_sdk::ic_cdk::spawn(retry_pending_updates())
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_metadata_retries() {}
+
+/// Applies the `patch` to every token of the `tokens` on behalf of the `owner`.
+pub async fn update_tokens(
+    owner: Principal,
+    tokens: Vec<Principal>,
+    patch: MetadataPatch,
+) -> Vec<MetadataUpdateResult> {
+    let mut results = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        // The new request replaces the update of the token waiting for a retry.
+        state::get_state().remove_pending_metadata_update(token);
+        let update = PendingMetadataUpdate {
+            token,
+            owner,
+            patch: patch.clone(),
+            attempts: 0,
+            last_error: String::new(),
+            updated_at: ic::time(),
+        };
+        let status = apply(update).await;
+        results.push(MetadataUpdateResult { token, status });
+    }
+
+    results
+}
+
+/// Retries every pending update once.
+pub async fn retry_pending_updates() {
+    for update in state::get_state().pending_metadata_updates() {
+        apply(update).await;
+    }
+}
+
+async fn apply(mut update: PendingMetadataUpdate) -> MetadataUpdateStatus {
+    let token = update.token;
+    let result = management::update_token_metadata(token, update.owner, update.patch.clone()).await;
+
+    // The update might have been replaced by a newer one while the call was in flight.
+    let mut state = state::get_state();
+    let superseded = state
+        .get_pending_metadata_update(token)
+        .map_or(false, |pending| pending.patch != update.patch);
+    if !superseded {
+        state.remove_pending_metadata_update(token);
+    }
+
+    match result {
+        Ok(Ok(())) => {
+            FactoryEvents::record(
+                update.owner,
+                FactoryEventKind::TokenMetadataUpdated { token },
+            );
+            MetadataUpdateStatus::Updated
+        }
+        Ok(Err(e)) => MetadataUpdateStatus::Rejected {
+            error: e.to_string(),
+        },
+        Err(e) => {
+            let mut error = e.to_string();
+            truncate_message(&mut error, MAX_PROBE_ERROR_LEN);
+            update.attempts += 1;
+            if update.attempts >= MAX_METADATA_UPDATE_ATTEMPTS {
+                return MetadataUpdateStatus::Failed { error };
+            }
+
+            let attempts = update.attempts;
+            update.last_error = error.clone();
+            update.updated_at = ic::time();
+            if !superseded {
+                state.set_pending_metadata_update(update);
+            }
+            MetadataUpdateStatus::Retrying { attempts, error }
+        }
+    }
+}
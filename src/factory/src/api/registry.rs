@@ -0,0 +1,111 @@
+//! Registration of the deployed tokens on a shared registry canister, which keeps the token
+//! symbols unique across the factories.
+//!
+//! If the registry is set with `set_registry`, the symbol of every new token is reserved on the
+//! registry before the deployment fee is charged, and the deployment is refused if the registry
+//! rejects the symbol or cannot be reached. Once the token is created, it is registered with its
+//! principal. The registrations which could not reach the registry are stored and retried in a
+//! background timer until they succeed.
+//!
+//! The registry must implement the following methods:
+//!
+//! ```text
+//! reserve_symbol : (symbol : text, name : text) -> (variant { Ok; Err : text });
+//! register_token : (RegistryEntry) -> (variant { Ok; Err : text });
+//! ```
+//!
+//! Reserving the symbol already reserved for the same name by the same factory must succeed, so
+//! the failed deployments can be retried.
+
+use std::time::Duration;
+
+use candid::Principal;
+use canister_sdk::ic_kit::ic;
+use token::error::truncate_message;
+
+use crate::error::TokenFactoryError;
+use crate::state::{self, PendingRegistration, RegistryEntry, MAX_PROBE_ERROR_LEN};
+
+pub const REGISTRATION_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Starts the periodic retries of the pending registrations. Timers are not preserved on upgrade,
+/// so this must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_registration_retries() {
+    ic_exports::ic_cdk_timers::set_timer_interval(REGISTRATION_RETRY_INTERVAL, || {
+        canister_sdk::ic_cdk::spawn(retry_pending_registrations())
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_registration_retries() {}
+
+/// Reserves the `symbol` of the new token on the registry, if it's set.
+pub async fn reserve_symbol(symbol: &str, name: &str) -> Result<(), TokenFactoryError> {
+    let Some(registry) = state::get_state().get_registry() else {
+        return Ok(());
+    };
+
+    ic::call::<_, (Result<(), String>,), _>(
+        registry,
+        "reserve_symbol",
+        (symbol.to_string(), name.to_string()),
+    )
+    .await
+    .map_err(|(_, msg)| TokenFactoryError::CanisterCallFailed(registry, msg))?
+    .0
+    .map_err(TokenFactoryError::SymbolRejectedByRegistry)
+}
+
+/// Registers the deployed `token` on the registry, if it's set. If the registry cannot be reached,
+/// the registration is retried in the background.
+pub async fn register_token(symbol: String, name: String, token: Principal) {
+    let Some(registry) = state::get_state().get_registry() else {
+        return;
+    };
+
+    register(PendingRegistration {
+        entry: RegistryEntry {
+            symbol,
+            name,
+            token,
+        },
+        registry,
+        attempts: 0,
+        last_error: String::new(),
+        updated_at: ic::time(),
+    })
+    .await;
+}
+
+/// Retries every pending registration once.
+pub async fn retry_pending_registrations() {
+    for registration in state::get_state().pending_registrations() {
+        register(registration).await;
+    }
+}
+
+async fn register(mut registration: PendingRegistration) {
+    let result = ic::call::<_, (Result<(), String>,), _>(
+        registration.registry,
+        "register_token",
+        (registration.entry.clone(),),
+    )
+    .await;
+
+    let mut state = state::get_state();
+    let error = match result {
+        Ok((Ok(()),)) => {
+            state.remove_pending_registration(registration.entry.token);
+            return;
+        }
+        Ok((Err(e),)) => e,
+        Err((_, msg)) => msg,
+    };
+
+    registration.attempts += 1;
+    registration.last_error = error;
+    truncate_message(&mut registration.last_error, MAX_PROBE_ERROR_LEN);
+    registration.updated_at = ic::time();
+    state.set_pending_registration(registration);
+}
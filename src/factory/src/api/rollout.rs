@@ -0,0 +1,324 @@
+//! Scheduled upgrades of the fleet in a maintenance window.
+//!
+//! `schedule_upgrade` stores the rollout with every deployed token waiting for the upgrade. From
+//! the start of the window, a background timer upgrades the next `batch_size` tokens on every
+//! run and checks that they report the version of the rollout afterwards. The rollout is paused
+//! when more than `max_failures` tokens fail, until the controller resumes or cancels it. The
+//! progress and the status of every token are stored, so the operators can check them in the
+//! morning. The rollout not finished by the end of the window is paused as well.
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use candid::Principal;
+use canister_sdk::ic_kit::ic;
+use token::error::truncate_message;
+
+use super::management;
+use crate::error::TokenFactoryError;
+use crate::events::{FactoryEventKind, FactoryEvents};
+use crate::state::{
+    self, TokenUpgradeState, UpgradeRolloutStatus, UpgradeSchedule, MAX_PROBE_ERROR_LEN,
+    MAX_UPGRADE_BATCH_SIZE,
+};
+
+pub const UPGRADE_ROLLOUT_INTERVAL: Duration = Duration::from_secs(60);
+
+thread_local! {
+    // Set while a batch is upgraded, so the next run of the timer doesn't start another one.
+    static BATCH_IN_PROGRESS: Cell<bool> = Cell::new(false);
+}
+
+/// Starts the periodic runs of the scheduled rollout. Timers are not preserved on upgrade, so
+/// this must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_upgrade_rollout() {
+    ic_exports::ic_cdk_timers::set_timer_interval(UPGRADE_ROLLOUT_INTERVAL, || {
+        canister_sdk::ic_cdk::spawn(run_upgrade_batch())
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_upgrade_rollout() {}
+
+/// Schedules the upgrade of every deployed token to the current token wasm in the window from
+/// `start_time` to `end_time`.
+pub fn schedule(
+    caller: Principal,
+    version: String,
+    start_time: u64,
+    end_time: u64,
+    batch_size: u32,
+    max_failures: u32,
+) -> Result<UpgradeSchedule, TokenFactoryError> {
+    if version.is_empty() {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "version",
+            "cannot be empty",
+        ));
+    }
+    if end_time <= start_time {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "end_time",
+            "must be after start_time",
+        ));
+    }
+    if batch_size == 0 || batch_size > MAX_UPGRADE_BATCH_SIZE {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "batch_size",
+            "must be between 1 and MAX_UPGRADE_BATCH_SIZE",
+        ));
+    }
+
+    let mut state = state::get_state();
+    if state.get_token_wasm().is_none() {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "token bytecode",
+            "is not set",
+        ));
+    }
+    if state
+        .get_upgrade_schedule()
+        .map_or(false, |schedule| schedule.is_active())
+    {
+        return Err(TokenFactoryError::UpgradeAlreadyScheduled);
+    }
+
+    let tokens = state.list_tokens();
+    let now = ic::time();
+    let schedule = UpgradeSchedule {
+        version,
+        start_time,
+        end_time,
+        batch_size,
+        max_failures,
+        scheduled_by: caller,
+        scheduled_at: now,
+        status: UpgradeRolloutStatus::Scheduled,
+        total: tokens.len() as u64,
+        upgraded: 0,
+        skipped: 0,
+        failed: 0,
+        failures_since_resume: 0,
+        updated_at: now,
+    };
+    state.begin_upgrade_rollout(schedule.clone(), tokens);
+    Ok(schedule)
+}
+
+/// Continues the paused rollout with the failure budget reset.
+pub fn resume() -> Result<UpgradeSchedule, TokenFactoryError> {
+    let mut state = state::get_state();
+    let mut schedule = state
+        .get_upgrade_schedule()
+        .filter(|schedule| schedule.status == UpgradeRolloutStatus::Paused)
+        .ok_or(TokenFactoryError::InvalidConfiguration(
+            "upgrade rollout",
+            "is not paused",
+        ))?;
+
+    schedule.status = UpgradeRolloutStatus::Running;
+    schedule.failures_since_resume = 0;
+    schedule.updated_at = ic::time();
+    state.set_upgrade_schedule(schedule.clone());
+    Ok(schedule)
+}
+
+/// Stops the rollout. The tokens already upgraded keep the new wasm.
+pub fn cancel() -> Result<UpgradeSchedule, TokenFactoryError> {
+    let mut state = state::get_state();
+    let mut schedule = state
+        .get_upgrade_schedule()
+        .filter(UpgradeSchedule::is_active)
+        .ok_or(TokenFactoryError::InvalidConfiguration(
+            "upgrade rollout",
+            "is not active",
+        ))?;
+
+    schedule.status = UpgradeRolloutStatus::Cancelled;
+    schedule.updated_at = ic::time();
+    state.set_upgrade_schedule(schedule.clone());
+    Ok(schedule)
+}
+
+/// Upgrades the next batch of the tokens if the rollout is running.
+pub async fn run_upgrade_batch() {
+    if BATCH_IN_PROGRESS.with(Cell::get) {
+        return;
+    }
+
+    let now = ic::time();
+    let mut state = state::get_state();
+    let Some(mut schedule) = state.get_upgrade_schedule() else {
+        return;
+    };
+    if schedule.pause_after_window(now) {
+        state.set_upgrade_schedule(schedule);
+        return;
+    }
+    match schedule.status {
+        UpgradeRolloutStatus::Scheduled if now >= schedule.start_time => {
+            schedule.status = UpgradeRolloutStatus::Running;
+            schedule.updated_at = now;
+            state.set_upgrade_schedule(schedule.clone());
+        }
+        UpgradeRolloutStatus::Running => {}
+        _ => return,
+    }
+
+    let batch = state.pending_token_upgrades(schedule.batch_size as usize);
+    if batch.is_empty() {
+        schedule.status = UpgradeRolloutStatus::Completed;
+        schedule.updated_at = now;
+        state.set_upgrade_schedule(schedule);
+        return;
+    }
+
+    BATCH_IN_PROGRESS.with(|flag| flag.set(true));
+    let _guard = BatchGuard;
+    for mut status in batch {
+        let upgrade_state = upgrade_token(status.token, &schedule.version).await;
+
+        // The rollout could be cancelled or replaced while the token was upgraded.
+        let mut state = state::get_state();
+        let Some(mut current) = state
+            .get_upgrade_schedule()
+            .filter(|current| current.scheduled_at == schedule.scheduled_at)
+        else {
+            break;
+        };
+
+        let now = ic::time();
+        if upgrade_state == TokenUpgradeState::Upgraded {
+            state.record_upgrade(status.token, status.name.clone(), now, None);
+            FactoryEvents::record(
+                current.scheduled_by,
+                FactoryEventKind::TokenUpgraded {
+                    token: status.token,
+                    migration_id: None,
+                },
+            );
+        }
+
+        current.record(&upgrade_state, now);
+        current.pause_after_window(now);
+        status.state = upgrade_state;
+        status.updated_at = now;
+        state.set_token_upgrade_status(status);
+        state.set_upgrade_schedule(current.clone());
+        if current.status != UpgradeRolloutStatus::Running {
+            break;
+        }
+    }
+}
+
+/// Clears `BATCH_IN_PROGRESS` when the batch ends, also if the run traps after an await, so the
+/// later runs of the timer are not blocked.
+struct BatchGuard;
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        BATCH_IN_PROGRESS.with(|flag| flag.set(false));
+    }
+}
+
+async fn upgrade_token(token: Principal, version: &str) -> TokenUpgradeState {
+    let state = state::get_state();
+    // The factory is not a controller of the released tokens.
+    if state.get_controller_release(token).is_some() {
+        return TokenUpgradeState::Skipped {
+            reason: "the controllers of the token are released".into(),
+        };
+    }
+    if management::token_pkg_version(token).await.ok().as_deref() == Some(version) {
+        return TokenUpgradeState::Skipped {
+            reason: "the token is already at the version".into(),
+        };
+    }
+
+    if let Some(compatibility) = state.get_wasm_compatibility() {
+        let current = management::token_state_version(token).await;
+        if !compatibility.accepts(current) {
+            return failed(TokenFactoryError::IncompatibleStateVersion {
+                token,
+                current,
+                supported: compatibility.supported_versions(),
+            });
+        }
+    }
+
+    let Some(wasm) = state.get_token_wasm() else {
+        return failed(TokenFactoryError::InvalidConfiguration(
+            "token bytecode",
+            "is not set",
+        ));
+    };
+    if let Err(e) = management::upgrade_code(token, wasm).await {
+        return failed(e);
+    }
+
+    // The token wasm could be replaced with another version after the rollout was scheduled.
+    match management::token_pkg_version(token).await {
+        Ok(current) if current == version => TokenUpgradeState::Upgraded,
+        Ok(current) => failed(format!(
+            "the token reports version {current} after the upgrade"
+        )),
+        Err(e) => failed(e),
+    }
+}
+
+fn failed(error: impl ToString) -> TokenUpgradeState {
+    let mut error = error.to_string();
+    truncate_message(&mut error, MAX_PROBE_ERROR_LEN);
+    TokenUpgradeState::Failed { error }
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::MockContext;
+
+    use super::*;
+
+    #[test]
+    fn schedule_refuses_second_rollout() {
+        MockContext::new().inject();
+        let mut state = state::get_state();
+        state.reset();
+        let controller = Principal::anonymous();
+
+        let result = schedule(controller, "1.2.0".into(), 100, 200, 10, 0);
+        assert!(matches!(
+            result,
+            Err(TokenFactoryError::InvalidConfiguration("token bytecode", _))
+        ));
+
+        state.set_token_wasm(Some(vec![1, 2, 3]));
+        let result = schedule(
+            controller,
+            "1.2.0".into(),
+            100,
+            200,
+            MAX_UPGRADE_BATCH_SIZE + 1,
+            0,
+        );
+        assert!(matches!(
+            result,
+            Err(TokenFactoryError::InvalidConfiguration("batch_size", _))
+        ));
+        assert!(matches!(
+            schedule(controller, "1.2.0".into(), 100, 100, 10, 0),
+            Err(TokenFactoryError::InvalidConfiguration("end_time", _))
+        ));
+
+        let scheduled = schedule(controller, "1.2.0".into(), 100, 200, 10, 0).unwrap();
+        assert_eq!(scheduled.status, UpgradeRolloutStatus::Scheduled);
+        assert!(matches!(
+            schedule(controller, "1.3.0".into(), 100, 200, 10, 0),
+            Err(TokenFactoryError::UpgradeAlreadyScheduled)
+        ));
+        assert!(resume().is_err());
+
+        assert_eq!(cancel().unwrap().status, UpgradeRolloutStatus::Cancelled);
+        schedule(controller, "1.3.0".into(), 100, 200, 10, 0).unwrap();
+    }
+}
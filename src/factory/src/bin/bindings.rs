@@ -0,0 +1,16 @@
+//! Prints the interface of the factory in the format given by the first argument: `did`
+//! (default), `js`, `ts` or `json`.
+
+use token_factory::BindingsFormat;
+
+fn main() {
+    let format = match std::env::args().nth(1) {
+        Some(format) => format.parse().unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }),
+        None => BindingsFormat::Candid,
+    };
+
+    println!("{}", token_factory::bindings(format));
+}
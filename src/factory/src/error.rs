@@ -1,5 +1,6 @@
-use candid::CandidType;
+use candid::{CandidType, Principal};
 use canister_sdk::ic_factory::error::FactoryError;
+use canister_sdk::ic_helpers::tokens::Tokens128;
 use thiserror::Error;
 
 #[derive(Debug, Error, CandidType)]
@@ -10,6 +11,49 @@ pub enum TokenFactoryError {
     #[error("a token with the same name is already registered")]
     AlreadyExists,
 
+    #[error("deployment fee of {required} is not paid, the deposit is {deposited}")]
+    DeploymentFeeNotPaid {
+        required: Tokens128,
+        deposited: Tokens128,
+    },
+
+    #[error("ledger call failed: {0}")]
+    LedgerError(String),
+
+    #[error("call to canister {0} failed: {1}")]
+    CanisterCallFailed(Principal, String),
+
+    #[error("the controllers of the token canister are already released")]
+    ControllerAlreadyReleased,
+
+    #[error("{0} is not allowed to deploy tokens")]
+    DeployerNotAllowed(Principal),
+
+    #[error("{deployer} reached the quota of {quota} tokens")]
+    DeployQuotaExceeded { deployer: Principal, quota: u64 },
+
+    #[error("invalid token symbol: {0}")]
+    InvalidTokenSymbol(String),
+
+    #[error("token {token} has state version {current}, the new wasm supports {supported:?}")]
+    IncompatibleStateVersion {
+        token: Principal,
+        current: u32,
+        supported: Vec<u32>,
+    },
+
+    #[error("the limit of the event subscribers is reached")]
+    SubscribersLimitReached,
+
+    #[error("the registry refused the token symbol: {0}")]
+    SymbolRejectedByRegistry(String),
+
+    #[error("the init spec is rejected: {0}")]
+    InvalidInitSpec(String),
+
+    #[error("an upgrade rollout is already scheduled")]
+    UpgradeAlreadyScheduled,
+
     #[error(transparent)]
     FactoryError(#[from] FactoryError),
 }
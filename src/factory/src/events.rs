@@ -0,0 +1,352 @@
+//! Log of the factory actions and its subscribers.
+//!
+//! Every action changing the set of the deployed tokens is appended to the event log. Canisters
+//! registered as subscribers are notified about the new events by one-way calls of the
+//! `on_factory_events : (vec FactoryEvent) -> ()` method, scheduled right after the message that
+//! recorded the events. Each subscriber has a cursor in the log, which is advanced only after a
+//! successful notification. The failed notifications are retried by a timer task with a backoff,
+//! and the subscriber is suspended after too many failures in a row, see `token::state::delivery`.
+//!
+//! A new subscription of a principal other than the controller must attach
+//! `EVENT_SUBSCRIPTION_FEE_CYCLES`.
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+use serde::Deserialize;
+use token::state::delivery::{self, DeliveryRetries};
+
+use crate::error::TokenFactoryError;
+use crate::state::PrincipalValue;
+
+/// Name of the method called on the subscriber canister with the batch of new events.
+pub const EVENTS_CALLBACK_METHOD: &str = "on_factory_events";
+
+pub const MAX_SUBSCRIBERS: u64 = 100;
+/// Maximum number of the events returned by `get_factory_events` or sent in one notification.
+pub const MAX_EVENTS_BATCH_SIZE: u64 = 100;
+
+pub const EVENTS_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Cycles charged for a new subscription of a principal other than the controller.
+pub const EVENT_SUBSCRIPTION_FEE_CYCLES: u64 = 10_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FactoryEventKind {
+    TokenDeployed {
+        token: Principal,
+        name: String,
+    },
+    TokenUpgraded {
+        token: Principal,
+        migration_id: Option<String>,
+    },
+    ControllerReleased {
+        token: Principal,
+    },
+    TokenForgotten {
+        token: Principal,
+        name: String,
+    },
+    TokenDecommissioned {
+        token: Principal,
+        cycles_reclaimed: u64,
+    },
+    TokenMetadataUpdated {
+        token: Principal,
+    },
+    /// The deployment fee of the token could not be passed to the fee recipient and is left on
+    /// the factory account.
+    DeploymentFeeNotCollected {
+        token: Principal,
+        error: String,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FactoryEvent {
+    pub id: u64,
+    pub timestamp: u64,
+    /// Principal that requested the action.
+    pub caller: Principal,
+    pub kind: FactoryEventKind,
+}
+
+impl Storable for FactoryEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode factory event for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode factory event from stable storage")
+    }
+}
+
+impl BoundedStorable for FactoryEvent {
+    // The token name is limited by `MAX_TOKEN_LEN_IN_BYTES`, the rest are a few principals and
+    // numbers.
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventSubscriber {
+    /// Id of the next event to be sent to the subscriber.
+    pub next_event: u64,
+    /// Number of failed notification attempts since the last successful one. The subscriber is
+    /// suspended after `delivery::MAX_FAILED_DELIVERIES` of them, until it subscribes again.
+    pub failed_attempts: u32,
+}
+
+impl EventSubscriber {
+    pub fn is_suspended(&self) -> bool {
+        delivery::is_suspended(self.failed_attempts)
+    }
+}
+
+impl Storable for EventSubscriber {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode event subscriber for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode event subscriber from stable storage")
+    }
+}
+
+impl BoundedStorable for EventSubscriber {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct FactoryEvents;
+
+impl FactoryEvents {
+    /// Appends the event to the log and schedules the notification of the subscribers.
+    pub fn record(caller: Principal, kind: FactoryEventKind) -> u64 {
+        let id = EVENTS.with(|map| {
+            let mut map = map.borrow_mut();
+            let id = map.len();
+            map.insert(
+                id,
+                FactoryEvent {
+                    id,
+                    timestamp: canister_sdk::ic_kit::ic::time(),
+                    caller,
+                    kind,
+                },
+            );
+            id
+        });
+
+        if SUBSCRIBERS.with(|map| map.borrow().len() > 0) {
+            schedule_flush();
+        }
+
+        id
+    }
+
+    pub fn list(offset: u64, limit: u64) -> Vec<FactoryEvent> {
+        EVENTS.with(|map| {
+            map.borrow()
+                .range(offset..)
+                .take(limit.min(MAX_EVENTS_BATCH_SIZE) as usize)
+                .map(|(_, event)| event)
+                .collect()
+        })
+    }
+
+    pub fn len() -> u64 {
+        EVENTS.with(|map| map.borrow().len())
+    }
+
+    /// Registers the `subscriber`, which is notified about the events recorded from now on. An
+    /// existing subscriber keeps its cursor and is resumed if it was suspended.
+    pub fn subscribe(subscriber: Principal) -> Result<EventSubscriber, TokenFactoryError> {
+        RETRIES.with(|retries| retries.borrow_mut().remove(subscriber));
+        SUBSCRIBERS.with(|map| {
+            let mut map = map.borrow_mut();
+            let key = PrincipalValue(subscriber);
+            if let Some(existing) = map.get(&key) {
+                let resumed = EventSubscriber {
+                    failed_attempts: 0,
+                    ..existing
+                };
+                map.insert(key, resumed);
+                return Ok(resumed);
+            }
+
+            if map.len() >= MAX_SUBSCRIBERS {
+                return Err(TokenFactoryError::SubscribersLimitReached);
+            }
+
+            let subscription = EventSubscriber {
+                next_event: Self::len(),
+                failed_attempts: 0,
+            };
+            map.insert(key, subscription);
+            Ok(subscription)
+        })
+    }
+
+    pub fn unsubscribe(subscriber: Principal) -> Option<EventSubscriber> {
+        RETRIES.with(|retries| retries.borrow_mut().remove(subscriber));
+        SUBSCRIBERS.with(|map| map.borrow_mut().remove(&PrincipalValue(subscriber)))
+    }
+
+    pub fn get_subscriber(subscriber: Principal) -> Option<EventSubscriber> {
+        SUBSCRIBERS.with(|map| map.borrow().get(&PrincipalValue(subscriber)))
+    }
+
+    /// Sends the next batch of the undelivered events to every subscriber due at the time `now`.
+    /// If some batches were sent and more events are undelivered, the next flush is scheduled
+    /// right away. The failed subscribers are retried by the timer task.
+    pub fn flush(now: u64) {
+        let subscribers = SUBSCRIBERS.with(|map| map.borrow().iter().collect::<Vec<_>>());
+        let len = Self::len();
+
+        let mut has_more = false;
+        for (key, mut subscriber) in subscribers {
+            let is_due = RETRIES.with(|retries| {
+                retries
+                    .borrow()
+                    .is_due(key.0, subscriber.failed_attempts, now)
+            });
+            if subscriber.next_event >= len || !is_due {
+                continue;
+            }
+
+            let events = Self::list(subscriber.next_event, MAX_EVENTS_BATCH_SIZE);
+            let sent = events.len() as u64;
+            let delivered = send_events(key.0, events);
+            if delivered {
+                subscriber.next_event += sent;
+                has_more |= subscriber.next_event < len;
+            }
+
+            subscriber.failed_attempts = RETRIES.with(|retries| {
+                retries
+                    .borrow_mut()
+                    .record(key.0, subscriber.failed_attempts, delivered, now)
+            });
+            SUBSCRIBERS.with(|map| map.borrow_mut().insert(key, subscriber));
+        }
+
+        if has_more {
+            schedule_flush();
+        }
+    }
+
+    pub fn clear() {
+        EVENTS.with(|map| map.borrow_mut().clear());
+        SUBSCRIBERS.with(|map| map.borrow_mut().clear());
+        RETRIES.with(|retries| retries.borrow_mut().clear());
+    }
+}
+
+/// Starts the periodic retries of the failed notifications. Timers are not preserved on upgrade,
+/// so this must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_event_retries() {
+    ic_exports::ic_cdk_timers::set_timer_interval(EVENTS_RETRY_INTERVAL, || {
+        FactoryEvents::flush(canister_sdk::ic_kit::ic::time())
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_event_retries() {}
+
+#[cfg(target_family = "wasm")]
+fn send_events(subscriber: Principal, events: Vec<FactoryEvent>) -> bool {
+    canister_sdk::ic_cdk::api::call::notify(subscriber, EVENTS_CALLBACK_METHOD, (events,)).is_ok()
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn send_events(_subscriber: Principal, _events: Vec<FactoryEvent>) -> bool {
+    // There are no inter-canister calls outside of the IC, so the events are considered delivered.
+    true
+}
+
+fn schedule_flush() {
+    delivery::schedule_flush(&FLUSH_SCHEDULED, || {
+        FactoryEvents::flush(canister_sdk::ic_kit::ic::time())
+    });
+}
+
+const EVENTS_MEMORY_ID: MemoryId = MemoryId::new(21);
+const SUBSCRIBERS_MEMORY_ID: MemoryId = MemoryId::new(22);
+
+thread_local! {
+    static EVENTS: RefCell<StableBTreeMap<u64, FactoryEvent>> =
+        RefCell::new(StableBTreeMap::new(EVENTS_MEMORY_ID));
+
+    static SUBSCRIBERS: RefCell<StableBTreeMap<PrincipalValue, EventSubscriber>> =
+        RefCell::new(StableBTreeMap::new(SUBSCRIBERS_MEMORY_ID));
+
+    static RETRIES: RefCell<DeliveryRetries> = RefCell::default();
+    static FLUSH_SCHEDULED: Cell<bool> = Cell::new(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::MockContext;
+
+    use super::*;
+
+    fn deployed(id: u8) -> FactoryEventKind {
+        FactoryEventKind::TokenDeployed {
+            token: Principal::from_slice(&[id; 29]),
+            name: format!("token {id}"),
+        }
+    }
+
+    #[test]
+    fn events_are_listed() {
+        MockContext::new().inject();
+        FactoryEvents::clear();
+
+        for id in 0..5 {
+            assert_eq!(
+                FactoryEvents::record(Principal::anonymous(), deployed(id)),
+                id as u64
+            );
+        }
+
+        let events = FactoryEvents::list(3, 10);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].id, 3);
+        assert_eq!(events[0].kind, deployed(3));
+    }
+
+    #[test]
+    fn subscribers_receive_new_events() {
+        MockContext::new().inject();
+        FactoryEvents::clear();
+        let subscriber = Principal::from_slice(&[1; 29]);
+
+        FactoryEvents::record(Principal::anonymous(), deployed(0));
+        assert_eq!(FactoryEvents::subscribe(subscriber).unwrap().next_event, 1);
+
+        for id in 1..=(MAX_EVENTS_BATCH_SIZE as u8 + 1) {
+            FactoryEvents::record(Principal::anonymous(), deployed(id));
+        }
+
+        FactoryEvents::flush(0);
+        let state = FactoryEvents::get_subscriber(subscriber).unwrap();
+        assert_eq!(state.next_event, MAX_EVENTS_BATCH_SIZE + 1);
+
+        FactoryEvents::flush(0);
+        let state = FactoryEvents::get_subscriber(subscriber).unwrap();
+        assert_eq!(state.next_event, FactoryEvents::len());
+
+        assert!(FactoryEvents::unsubscribe(subscriber).is_some());
+        assert_eq!(FactoryEvents::get_subscriber(subscriber), None);
+    }
+}
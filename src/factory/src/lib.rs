@@ -1,6 +1,8 @@
 pub mod api;
 mod error;
+pub mod events;
 pub mod state;
+pub mod validation;
 
 pub use self::api::*;
 pub use state::State;
@@ -10,8 +12,24 @@ pub use state::State;
 #[no_mangle]
 pub static TOKEN_FACTORY_CANISTER_MARKER: &str = "IS20_FACTORY_CANISTER";
 
+pub use token::bindings::BindingsFormat;
+
 pub fn idl() -> String {
+    bindings(BindingsFormat::Candid)
+}
+
+/// Renders the interface of the factory in the `format`, see `token::bindings`.
+pub fn bindings(format: BindingsFormat) -> String {
+    use crate::api::CONTROLLER_METHODS;
     use crate::error::TokenFactoryError;
+    use crate::events::{EventSubscriber, FactoryEvent};
+    use crate::state::{
+        BillingReport, ControllerRelease, DeployPolicy, DeploymentFee, DeploymentRecovery,
+        ForceUpgrade, MetadataUpdateResult, PendingDeployment, PendingMetadataUpdate,
+        PendingRegistration, SubnetSelection, TokenOverrides, TokenStatus, TokenTombstone,
+        TokenUpgradeStatus, UpgradeSchedule, WasmCompatibility,
+    };
+    use crate::validation::SymbolRules;
     use canister_sdk::{
         ic_canister::{generate_idl, Idl},
         ic_factory::{
@@ -22,11 +40,21 @@ pub fn idl() -> String {
     };
     use ic_exports::Principal;
     use std::collections::HashMap;
-    use token::state::config::Metadata;
+    use token::account::Subaccount;
+    use token::state::config::{Metadata, MetadataPatch};
 
     let canister_idl = generate_idl!();
     let mut factory_idl = <TokenFactoryCanister as FactoryCanister>::get_idl();
     factory_idl.merge(&canister_idl);
 
-    candid::bindings::candid::compile(&factory_idl.env.env, &Some(factory_idl.actor))
+    let annotations = [token::bindings::Annotation {
+        name: "controller_only",
+        methods: CONTROLLER_METHODS,
+    }];
+    token::bindings::render(
+        &factory_idl.env.env,
+        factory_idl.actor,
+        format,
+        &annotations,
+    )
 }
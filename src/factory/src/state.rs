@@ -1,75 +1,1313 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 
 use candid::{CandidType, Decode, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
 use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
 use serde::Deserialize;
+use token::error::truncate_message;
+use token::state::config::{Metadata, MetadataPatch};
+
+use crate::events::FactoryEvents;
+use crate::validation::{normalize_symbol, SymbolRules};
 
 #[derive(CandidType, Deserialize, Default, Debug)]
 pub struct State {}
 
-impl State {
-    pub fn reset(&mut self) {
-        TOKENS_MAP.with(|map| map.borrow_mut().clear());
-        WASM_CELL.with(|cell| {
-            cell.borrow_mut()
-                .set(StorableWasm::default())
-                .expect("failed to reset token wasm in stable memory")
-        });
+impl State {
+    pub fn reset(&mut self) {
+        TOKENS_MAP.with(|map| map.borrow_mut().clear());
+        WASM_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableWasm::default())
+                .expect("failed to reset token wasm in stable memory")
+        });
+        DEPLOYMENT_FEE_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableDeploymentFee::default())
+                .expect("failed to reset deployment fee in stable memory")
+        });
+        RELEASES_MAP.with(|map| map.borrow_mut().clear());
+        FLEET_STATUS_MAP.with(|map| map.borrow_mut().clear());
+        DEPLOY_POLICY_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(DeployPolicy::default())
+                .expect("failed to reset deploy policy in stable memory")
+        });
+        DEPLOY_ALLOWLIST_MAP.with(|map| map.borrow_mut().clear());
+        SYMBOLS_MAP.with(|map| map.borrow_mut().clear());
+        SYMBOL_RULES_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(SymbolRules::default())
+                .expect("failed to reset symbol rules in stable memory")
+        });
+        WASM_COMPATIBILITY_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableWasmCompatibility::default())
+                .expect("failed to reset wasm compatibility in stable memory")
+        });
+        TOMBSTONES_MAP.with(|map| map.borrow_mut().clear());
+        TOKEN_DEPLOYERS_MAP.with(|map| map.borrow_mut().clear());
+        TOKEN_SUBNETS_MAP.with(|map| map.borrow_mut().clear());
+        DEPLOYER_STATS_MAP.with(|map| map.borrow_mut().clear());
+        DEPLOY_QUOTA_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableQuota::default())
+                .expect("failed to reset deploy quota in stable memory")
+        });
+        PENDING_DEPLOYMENTS_MAP.with(|map| map.borrow_mut().clear());
+        NEXT_DEPLOYMENT_ID_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to reset next deployment id in stable memory")
+        });
+        PENDING_METADATA_UPDATES_MAP.with(|map| map.borrow_mut().clear());
+        REGISTRY_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableRegistry::default())
+                .expect("failed to reset registry in stable memory")
+        });
+        PENDING_REGISTRATIONS_MAP.with(|map| map.borrow_mut().clear());
+        UPGRADE_SCHEDULE_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableUpgradeSchedule::default())
+                .expect("failed to reset upgrade schedule in stable memory")
+        });
+        TOKEN_UPGRADES_MAP.with(|map| map.borrow_mut().clear());
+        FactoryEvents::clear();
+    }
+
+    pub fn get_token(&self, name: String) -> Option<Principal> {
+        Self::check_name(&name).then_some(())?;
+
+        TOKENS_MAP
+            .with(|map| map.borrow().get(&StringKey(name)))
+            .map(|principal| principal.0)
+    }
+
+    pub fn remove_token(&self, name: String) -> Option<Principal> {
+        Self::check_name(&name).then_some(())?;
+
+        SYMBOLS_MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            let symbols = map
+                .iter()
+                .filter(|(_, token_name)| token_name.0 == name)
+                .map(|(symbol, _)| symbol)
+                .collect::<Vec<_>>();
+            for symbol in symbols {
+                map.remove(&symbol);
+            }
+        });
+
+        let principal = TOKENS_MAP
+            .with(|map| map.borrow_mut().remove(&StringKey(name)))
+            .map(|principal| principal.0)?;
+        TOKEN_DEPLOYERS_MAP.with(|map| map.borrow_mut().remove(&PrincipalValue(principal)));
+        TOKEN_SUBNETS_MAP.with(|map| map.borrow_mut().remove(&PrincipalValue(principal)));
+        Some(principal)
+    }
+
+    pub fn insert_token(&mut self, name: String, principal: Principal) {
+        TOKENS_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(StringKey(name), PrincipalValue(principal))
+        });
+    }
+
+    /// Returns the name of the deployed token with the given symbol, compared case-insensitively.
+    /// Only the tokens deployed after the symbols registration was introduced are known.
+    pub fn get_token_by_symbol(&self, symbol: &str) -> Option<String> {
+        let symbol = normalize_symbol(symbol);
+        Self::check_name(&symbol).then_some(())?;
+
+        SYMBOLS_MAP
+            .with(|map| map.borrow().get(&StringKey(symbol)))
+            .map(|name| name.0)
+    }
+
+    pub fn insert_token_symbol(&mut self, symbol: &str, name: String) {
+        SYMBOLS_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(StringKey(normalize_symbol(symbol)), StringKey(name))
+        });
+    }
+
+    pub fn get_symbol_rules(&self) -> SymbolRules {
+        SYMBOL_RULES_CELL.with(|cell| cell.borrow().get().clone())
+    }
+
+    pub fn set_symbol_rules(&mut self, rules: SymbolRules) {
+        SYMBOL_RULES_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(rules)
+                .expect("failed to set symbol rules to stable storage");
+        });
+    }
+
+    pub fn get_token_wasm(&self) -> Option<Vec<u8>> {
+        WASM_CELL.with(|cell| cell.borrow().get().0.clone())
+    }
+
+    pub fn set_token_wasm(&mut self, wasm: Option<Vec<u8>>) {
+        WASM_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableWasm(wasm))
+                .expect("failed to set token canister wasm to stable storage");
+        });
+    }
+
+    pub fn get_deployment_fee(&self) -> Option<DeploymentFee> {
+        DEPLOYMENT_FEE_CELL.with(|cell| cell.borrow().get().0.clone())
+    }
+
+    pub fn set_deployment_fee(&mut self, fee: Option<DeploymentFee>) {
+        DEPLOYMENT_FEE_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableDeploymentFee(fee))
+                .expect("failed to set deployment fee to stable storage");
+        });
+    }
+
+    pub fn get_controller_release(&self, token: Principal) -> Option<ControllerRelease> {
+        RELEASES_MAP.with(|map| map.borrow().get(&PrincipalValue(token)))
+    }
+
+    pub fn insert_controller_release(&mut self, release: ControllerRelease) {
+        RELEASES_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(release.token), release)
+        });
+    }
+
+    /// Returns the names and principals of all tokens deployed by the factory.
+    pub fn list_tokens(&self) -> Vec<(String, Principal)> {
+        TOKENS_MAP.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(name, principal)| (name.0, principal.0))
+                .collect()
+        })
+    }
+
+    pub fn get_token_status(&self, token: Principal) -> Option<TokenStatus> {
+        FLEET_STATUS_MAP.with(|map| map.borrow().get(&PrincipalValue(token)))
+    }
+
+    pub fn set_token_status(&mut self, status: TokenStatus) {
+        FLEET_STATUS_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(status.token), status)
+        });
+    }
+
+    /// Returns the last probed status of every deployed token.
+    pub fn fleet_status(&self) -> Vec<TokenStatus> {
+        FLEET_STATUS_MAP.with(|map| map.borrow().iter().map(|(_, status)| status).collect())
+    }
+
+    /// Records the time the token canister was upgraded by the factory.
+    pub fn record_upgrade(
+        &mut self,
+        token: Principal,
+        name: String,
+        timestamp: u64,
+        migration_id: Option<String>,
+    ) {
+        let mut status = self
+            .get_token_status(token)
+            .unwrap_or_else(|| TokenStatus::new(token, name));
+        status.last_upgrade = Some(timestamp);
+        if migration_id.is_some() {
+            status.last_migration_id = migration_id;
+        }
+        self.set_token_status(status);
+    }
+
+    /// Removes the decommissioned token from the registry and records its tombstone.
+    pub fn retire_token(&mut self, tombstone: TokenTombstone) {
+        self.remove_token(tombstone.name.clone());
+        FLEET_STATUS_MAP.with(|map| map.borrow_mut().remove(&PrincipalValue(tombstone.token)));
+        TOMBSTONES_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(tombstone.token), tombstone)
+        });
+    }
+
+    pub fn get_tombstone(&self, token: Principal) -> Option<TokenTombstone> {
+        TOMBSTONES_MAP.with(|map| map.borrow().get(&PrincipalValue(token)))
+    }
+
+    pub fn list_tombstones(&self) -> Vec<TokenTombstone> {
+        TOMBSTONES_MAP.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, tombstone)| tombstone)
+                .collect()
+        })
+    }
+
+    pub fn get_wasm_compatibility(&self) -> Option<WasmCompatibility> {
+        WASM_COMPATIBILITY_CELL.with(|cell| cell.borrow().get().0.clone())
+    }
+
+    pub fn set_wasm_compatibility(&mut self, compatibility: Option<WasmCompatibility>) {
+        WASM_COMPATIBILITY_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableWasmCompatibility(compatibility))
+                .expect("failed to set wasm compatibility to stable storage");
+        });
+    }
+
+    pub fn get_deploy_policy(&self) -> DeployPolicy {
+        DEPLOY_POLICY_CELL.with(|cell| *cell.borrow().get())
+    }
+
+    pub fn set_deploy_policy(&mut self, policy: DeployPolicy) {
+        DEPLOY_POLICY_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(policy)
+                .expect("failed to set deploy policy to stable storage");
+        });
+    }
+
+    pub fn is_allowed_deployer(&self, principal: Principal) -> bool {
+        match self.get_deploy_policy() {
+            DeployPolicy::Open => true,
+            DeployPolicy::Allowlist => DEPLOY_ALLOWLIST_MAP
+                .with(|map| map.borrow().get(&PrincipalValue(principal)))
+                .is_some(),
+        }
+    }
+
+    /// Adds the `principal` to the deployers allowlist, remembering the time it was added.
+    pub fn add_allowed_deployer(&mut self, principal: Principal, timestamp: u64) {
+        DEPLOY_ALLOWLIST_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(principal), timestamp)
+        });
+    }
+
+    /// Removes the `principal` from the deployers allowlist. Returns false if it wasn't there.
+    pub fn remove_allowed_deployer(&mut self, principal: Principal) -> bool {
+        DEPLOY_ALLOWLIST_MAP
+            .with(|map| map.borrow_mut().remove(&PrincipalValue(principal)))
+            .is_some()
+    }
+
+    /// Returns the principals on the deployers allowlist with the time they were added.
+    pub fn allowed_deployers(&self) -> Vec<(Principal, u64)> {
+        DEPLOY_ALLOWLIST_MAP.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(principal, timestamp)| (principal.0, timestamp))
+                .collect()
+        })
+    }
+
+    /// Returns true if the `principal` is on the deployers allowlist, whatever the deploy policy.
+    pub fn is_on_allowlist(&self, principal: Principal) -> bool {
+        DEPLOY_ALLOWLIST_MAP
+            .with(|map| map.borrow().get(&PrincipalValue(principal)))
+            .is_some()
+    }
+
+    /// Maximum number of the registered tokens deployed by one principal, or None if unlimited.
+    pub fn get_deploy_quota(&self) -> Option<u64> {
+        DEPLOY_QUOTA_CELL.with(|cell| cell.borrow().get().0)
+    }
+
+    pub fn set_deploy_quota(&mut self, quota: Option<u64>) {
+        DEPLOY_QUOTA_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableQuota(quota))
+                .expect("failed to set deploy quota to stable storage");
+        });
+    }
+
+    /// Records the token deployed by the `deployer`, who paid the `fee` for it.
+    pub fn record_deployment(
+        &mut self,
+        deployer: Principal,
+        token: Principal,
+        fee: Tokens128,
+        timestamp: u64,
+    ) {
+        TOKEN_DEPLOYERS_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(token), PrincipalValue(deployer))
+        });
+        let mut stats = self.deployer_stats(deployer);
+        stats.deployments += 1;
+        stats.fees_paid = (stats.fees_paid + fee).unwrap_or(Tokens128::from(u128::MAX));
+        stats.last_deployment = Some(timestamp);
+        DEPLOYER_STATS_MAP.with(|map| map.borrow_mut().insert(PrincipalValue(deployer), stats));
+    }
+
+    pub fn deployer_stats(&self, deployer: Principal) -> DeployerStats {
+        DEPLOYER_STATS_MAP
+            .with(|map| map.borrow().get(&PrincipalValue(deployer)))
+            .unwrap_or_default()
+    }
+
+    /// Returns the registered tokens deployed by the `deployer`. Only the tokens deployed after
+    /// the deployers tracking was introduced are known.
+    pub fn tokens_of_deployer(&self, deployer: Principal) -> Vec<Principal> {
+        TOKEN_DEPLOYERS_MAP.with(|map| {
+            map.borrow()
+                .iter()
+                .filter(|(_, token_deployer)| token_deployer.0 == deployer)
+                .map(|(token, _)| token.0)
+                .collect()
+        })
+    }
+
+    /// Stores the `subnet` the `token` was deployed on.
+    pub fn record_token_subnet(&mut self, token: Principal, subnet: Principal) {
+        TOKEN_SUBNETS_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(token), PrincipalValue(subnet))
+        });
+    }
+
+    /// Returns the subnet the `token` was deployed on. Only the subnets selected by the deployers
+    /// are known, the other tokens are on the subnet of the factory.
+    pub fn get_token_subnet(&self, token: Principal) -> Option<Principal> {
+        TOKEN_SUBNETS_MAP
+            .with(|map| map.borrow().get(&PrincipalValue(token)))
+            .map(|subnet| subnet.0)
+    }
+
+    /// Returns the number of the registered tokens deployed on every selected subnet.
+    pub fn subnet_usage(&self) -> Vec<(Principal, u64)> {
+        let mut usage = BTreeMap::new();
+        TOKEN_SUBNETS_MAP.with(|map| {
+            for (_, subnet) in map.borrow().iter() {
+                *usage.entry(subnet.0).or_insert(0) += 1;
+            }
+        });
+        usage.into_iter().collect()
+    }
+
+    /// Returns the deployments, payments and cycles of the tokens of the `deployer`.
+    pub fn billing_report(&self, deployer: Principal) -> BillingReport {
+        let stats = self.deployer_stats(deployer);
+        let tokens = self
+            .tokens_of_deployer(deployer)
+            .into_iter()
+            .map(|token| {
+                let status = self.get_token_status(token);
+                TokenBilling {
+                    token,
+                    cycles: status.as_ref().and_then(|status| status.cycles),
+                    cycles_consumed: status
+                        .and_then(|status| status.cycles_consumed)
+                        .unwrap_or_default(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        BillingReport {
+            deployer,
+            deployments: stats.deployments,
+            fees_paid: stats.fees_paid,
+            last_deployment: stats.last_deployment,
+            cycles_consumed: tokens.iter().fold(0u128, |total, token| {
+                total.saturating_add(token.cycles_consumed)
+            }),
+            tokens,
+            quota: self.get_deploy_quota(),
+            quota_exempt: self.is_on_allowlist(deployer),
+        }
+    }
+
+    /// Starts tracking a deployment of the `deployer`, who was charged the `fee` for it. Returns
+    /// the id of the deployment.
+    pub fn begin_deployment(
+        &mut self,
+        deployer: Principal,
+        metadata: Metadata,
+        amount: Tokens128,
+        controller: Option<Principal>,
+        fee: Option<DeploymentFee>,
+        subnet: Option<Principal>,
+        timestamp: u64,
+    ) -> u64 {
+        let id = NEXT_DEPLOYMENT_ID_CELL.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1)
+                .expect("failed to set next deployment id to stable storage");
+            id
+        });
+
+        self.update_deployment(PendingDeployment {
+            id,
+            deployer,
+            metadata,
+            amount,
+            controller,
+            fee,
+            subnet,
+            canister: None,
+            stage: DeploymentStage::Creating,
+            started_at: timestamp,
+            updated_at: timestamp,
+            recovery_attempts: 0,
+        });
+        id
+    }
+
+    pub fn get_pending_deployment(&self, id: u64) -> Option<PendingDeployment> {
+        PENDING_DEPLOYMENTS_MAP.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns the deployments which are in flight or failed, optionally only of the `deployer`.
+    pub fn pending_deployments(&self, deployer: Option<Principal>) -> Vec<PendingDeployment> {
+        PENDING_DEPLOYMENTS_MAP.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, deployment)| deployment)
+                .filter(|deployment| deployer.map_or(true, |d| deployment.deployer == d))
+                .collect()
+        })
+    }
+
+    pub fn update_deployment(&mut self, deployment: PendingDeployment) {
+        PENDING_DEPLOYMENTS_MAP.with(|map| map.borrow_mut().insert(deployment.id, deployment));
+    }
+
+    /// Stops tracking the deployment, after the token is registered or the deployment is undone.
+    pub fn finish_deployment(&mut self, id: u64) -> Option<PendingDeployment> {
+        PENDING_DEPLOYMENTS_MAP.with(|map| map.borrow_mut().remove(&id))
+    }
+
+    pub fn get_pending_metadata_update(&self, token: Principal) -> Option<PendingMetadataUpdate> {
+        PENDING_METADATA_UPDATES_MAP.with(|map| map.borrow().get(&PrincipalValue(token)))
+    }
+
+    pub fn pending_metadata_updates(&self) -> Vec<PendingMetadataUpdate> {
+        PENDING_METADATA_UPDATES_MAP
+            .with(|map| map.borrow().iter().map(|(_, update)| update).collect())
+    }
+
+    /// Stores the update to be retried, replacing the previous pending update of the same token.
+    pub fn set_pending_metadata_update(&mut self, update: PendingMetadataUpdate) {
+        PENDING_METADATA_UPDATES_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(update.token), update)
+        });
+    }
+
+    pub fn remove_pending_metadata_update(
+        &mut self,
+        token: Principal,
+    ) -> Option<PendingMetadataUpdate> {
+        PENDING_METADATA_UPDATES_MAP.with(|map| map.borrow_mut().remove(&PrincipalValue(token)))
+    }
+
+    /// The shared registry canister the deployed tokens are registered on, or None if the tokens
+    /// are not registered.
+    pub fn get_registry(&self) -> Option<Principal> {
+        REGISTRY_CELL.with(|cell| cell.borrow().get().0)
+    }
+
+    pub fn set_registry(&mut self, registry: Option<Principal>) {
+        REGISTRY_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableRegistry(registry))
+                .expect("failed to set registry to stable storage");
+        });
+    }
+
+    pub fn pending_registrations(&self) -> Vec<PendingRegistration> {
+        PENDING_REGISTRATIONS_MAP
+            .with(|map| map.borrow().iter().map(|(_, pending)| pending).collect())
+    }
+
+    /// Stores the registration to be retried, replacing the previous one of the same token.
+    pub fn set_pending_registration(&mut self, registration: PendingRegistration) {
+        PENDING_REGISTRATIONS_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(registration.entry.token), registration)
+        });
+    }
+
+    pub fn remove_pending_registration(&mut self, token: Principal) -> Option<PendingRegistration> {
+        PENDING_REGISTRATIONS_MAP.with(|map| map.borrow_mut().remove(&PrincipalValue(token)))
+    }
+
+    /// The scheduled upgrade rollout, including the finished one until the next is scheduled.
+    pub fn get_upgrade_schedule(&self) -> Option<UpgradeSchedule> {
+        UPGRADE_SCHEDULE_CELL.with(|cell| cell.borrow().get().0.clone())
+    }
+
+    pub fn set_upgrade_schedule(&mut self, schedule: UpgradeSchedule) {
+        UPGRADE_SCHEDULE_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableUpgradeSchedule(Some(schedule)))
+                .expect("failed to set upgrade schedule to stable storage");
+        });
+    }
+
+    /// Stores the new rollout `schedule` with every token of the `tokens` waiting for the
+    /// upgrade, replacing the statuses of the previous rollout.
+    pub fn begin_upgrade_rollout(
+        &mut self,
+        schedule: UpgradeSchedule,
+        tokens: Vec<(String, Principal)>,
+    ) {
+        TOKEN_UPGRADES_MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            map.clear();
+            for (name, token) in tokens {
+                let status = TokenUpgradeStatus {
+                    token,
+                    name,
+                    state: TokenUpgradeState::Pending,
+                    updated_at: schedule.scheduled_at,
+                };
+                map.insert(PrincipalValue(token), status);
+            }
+        });
+        self.set_upgrade_schedule(schedule);
+    }
+
+    /// Returns the upgrade status of every token of the last rollout.
+    pub fn token_upgrade_statuses(&self) -> Vec<TokenUpgradeStatus> {
+        TOKEN_UPGRADES_MAP.with(|map| map.borrow().iter().map(|(_, status)| status).collect())
+    }
+
+    /// Returns up to `limit` tokens of the rollout which are not upgraded yet.
+    pub fn pending_token_upgrades(&self, limit: usize) -> Vec<TokenUpgradeStatus> {
+        TOKEN_UPGRADES_MAP.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, status)| status)
+                .filter(|status| status.state == TokenUpgradeState::Pending)
+                .take(limit)
+                .collect()
+        })
+    }
+
+    pub fn set_token_upgrade_status(&mut self, status: TokenUpgradeStatus) {
+        TOKEN_UPGRADES_MAP.with(|map| {
+            map.borrow_mut()
+                .insert(PrincipalValue(status.token), status)
+        });
+    }
+
+    fn check_name(name: &str) -> bool {
+        name.as_bytes().len() <= MAX_TOKEN_LEN_IN_BYTES
+    }
+}
+
+#[derive(Default, Deserialize, CandidType)]
+struct StorableWasm(Option<Vec<u8>>);
+
+impl Storable for StorableWasm {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode StorableWasm for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode StorableWasm from stable storage")
+    }
+}
+
+/// Fee charged by the factory for deploying a new token.
+///
+/// The fee can be paid with any ICRC-1 compatible ledger, including the ICP ledger. Before
+/// calling `create_token` the caller must transfer at least `amount` plus the ledger transfer fee
+/// to the factory account on the `ledger` with the caller deposit subaccount (see
+/// `get_deployment_fee_subaccount`).
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeploymentFee {
+    /// Ledger canister of the token the fee is paid in.
+    pub ledger: Principal,
+    /// Amount of tokens charged for a single deployment.
+    pub amount: Tokens128,
+    /// Principal which receives the collected fees.
+    pub fee_to: Principal,
+}
+
+#[derive(Default, Deserialize, CandidType)]
+struct StorableDeploymentFee(Option<DeploymentFee>);
+
+impl Storable for StorableDeploymentFee {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode deployment fee for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode deployment fee from stable storage")
+    }
+}
+
+/// State layout compatibility of the token wasm set with `set_token_bytecode`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WasmCompatibility {
+    /// Version of the stable state layout of the new wasm.
+    pub state_version: u32,
+    /// Older state versions the new wasm migrates on upgrade.
+    pub upgradable_from: Vec<u32>,
+}
+
+impl WasmCompatibility {
+    /// Whether a token with the given state version can be upgraded to the new wasm.
+    pub fn accepts(&self, version: u32) -> bool {
+        version == self.state_version || self.upgradable_from.contains(&version)
+    }
+
+    pub fn supported_versions(&self) -> Vec<u32> {
+        let mut versions = self.upgradable_from.clone();
+        versions.push(self.state_version);
+        versions
+    }
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct StorableWasmCompatibility(Option<WasmCompatibility>);
+
+impl Storable for StorableWasmCompatibility {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode wasm compatibility for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode wasm compatibility from stable storage")
+    }
+}
+
+/// Confirmation of an upgrade skipping the state compatibility check. The `migration_id` names
+/// the migration that makes the upgrade safe and is recorded in the status of every upgraded
+/// token.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ForceUpgrade {
+    pub migration_id: String,
+}
+
+/// Maximum number of the tokens upgraded by one run of the scheduled rollout.
+pub const MAX_UPGRADE_BATCH_SIZE: u32 = 50;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum UpgradeRolloutStatus {
+    /// Waiting for the start of the maintenance window.
+    Scheduled,
+    Running,
+    /// Stopped after more than `max_failures` tokens failed or at the end of the maintenance
+    /// window, until `resume_upgrade`.
+    Paused,
+    Completed,
+    Cancelled,
+}
+
+/// Upgrade of the fleet to the current token wasm, run in batches by a background timer from the
+/// start of the maintenance window, see `schedule_upgrade`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UpgradeSchedule {
+    /// Version of the token crate the tokens must report with `pkg_version` after the upgrade.
+    /// The tokens already reporting it are skipped.
+    pub version: String,
+    /// Start of the maintenance window, in nanoseconds since the epoch.
+    pub start_time: u64,
+    /// End of the maintenance window, in nanoseconds since the epoch. The rollout still running
+    /// after it is paused.
+    pub end_time: u64,
+    /// Number of the tokens upgraded by one run of the timer.
+    pub batch_size: u32,
+    /// Number of the failed upgrades tolerated before the rollout is paused.
+    pub max_failures: u32,
+    pub scheduled_by: Principal,
+    pub scheduled_at: u64,
+    pub status: UpgradeRolloutStatus,
+    /// Number of the tokens in the rollout.
+    pub total: u64,
+    pub upgraded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    /// Failed upgrades since the rollout started or was resumed, counted against `max_failures`.
+    pub failures_since_resume: u32,
+    pub updated_at: u64,
+}
+
+impl UpgradeSchedule {
+    /// Whether the rollout is not finished, so another one cannot be scheduled.
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.status,
+            UpgradeRolloutStatus::Scheduled
+                | UpgradeRolloutStatus::Running
+                | UpgradeRolloutStatus::Paused
+        )
+    }
+
+    /// Pauses the rollout not finished by the end of the maintenance window. Returns whether it
+    /// was paused.
+    pub fn pause_after_window(&mut self, now: u64) -> bool {
+        let running = matches!(
+            self.status,
+            UpgradeRolloutStatus::Scheduled | UpgradeRolloutStatus::Running
+        );
+        if !running || now <= self.end_time {
+            return false;
+        }
+
+        self.status = UpgradeRolloutStatus::Paused;
+        self.updated_at = now;
+        true
+    }
+
+    /// Counts the result of the upgrade of one token, pausing the rollout if the failure budget
+    /// is exceeded.
+    pub fn record(&mut self, state: &TokenUpgradeState, timestamp: u64) {
+        match state {
+            TokenUpgradeState::Pending => return,
+            TokenUpgradeState::Upgraded => self.upgraded += 1,
+            TokenUpgradeState::Skipped { .. } => self.skipped += 1,
+            TokenUpgradeState::Failed { .. } => {
+                self.failed += 1;
+                self.failures_since_resume += 1;
+                if self.failures_since_resume > self.max_failures {
+                    self.status = UpgradeRolloutStatus::Paused;
+                }
+            }
+        }
+        self.updated_at = timestamp;
+    }
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct StorableUpgradeSchedule(Option<UpgradeSchedule>);
+
+impl Storable for StorableUpgradeSchedule {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode upgrade schedule for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode upgrade schedule from stable storage")
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TokenUpgradeState {
+    Pending,
+    Upgraded,
+    /// The token was not upgraded, e.g. because it's already at the version of the rollout.
+    Skipped {
+        reason: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Upgrade status of one token in the scheduled rollout.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenUpgradeStatus {
+    pub token: Principal,
+    pub name: String,
+    pub state: TokenUpgradeState,
+    pub updated_at: u64,
+}
+
+impl Storable for TokenUpgradeStatus {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode token upgrade status for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode token upgrade status from stable storage")
+    }
+}
+
+impl BoundedStorable for TokenUpgradeStatus {
+    // The name is limited by `MAX_TOKEN_LEN_IN_BYTES` and the error by `MAX_PROBE_ERROR_LEN`.
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Fields of the cloned token which differ from the source token, see `clone_token`.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenOverrides {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub owner: Option<Principal>,
+}
+
+impl TokenOverrides {
+    /// Returns the metadata of the clone of the token with the `source` metadata. If the fees of
+    /// the source token are paid to its owner, the fees of the clone are paid to the new owner.
+    pub fn apply(self, source: Metadata) -> Metadata {
+        let owner = self.owner.unwrap_or(source.owner);
+        let fee_to = if source.fee_to == source.owner {
+            owner
+        } else {
+            source.fee_to
+        };
+
+        Metadata {
+            name: self.name.unwrap_or(source.name),
+            symbol: self.symbol.unwrap_or(source.symbol),
+            owner,
+            fee_to,
+            ..source
+        }
+    }
+}
+
+/// Audit record of the token canister controllers being handed over from the factory.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ControllerRelease {
+    /// Token canister principal.
+    pub token: Principal,
+    /// Principal that requested the release.
+    pub released_by: Principal,
+    /// Controllers set to the token canister instead of the factory.
+    pub controllers: Vec<Principal>,
+    /// Time of the release.
+    pub timestamp: u64,
+}
+
+impl Storable for ControllerRelease {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode controller release for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode controller release from stable storage")
+    }
+}
+
+impl BoundedStorable for ControllerRelease {
+    // The record contains at most 4 principals, so 512 bytes are enough for it.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Record of a token canister deleted by the factory.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenTombstone {
+    pub token: Principal,
+    pub name: String,
+    /// Principal that requested the decommission.
+    pub decommissioned_by: Principal,
+    /// Cycles returned by the token to the factory before it was deleted.
+    pub cycles_reclaimed: u64,
+    /// Error of the cycles withdrawal. The tokens installed by the older versions cannot return
+    /// their cycles.
+    pub withdrawal_error: Option<String>,
+    pub timestamp: u64,
+}
+
+impl Storable for TokenTombstone {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode token tombstone for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode token tombstone from stable storage")
+    }
+}
+
+impl BoundedStorable for TokenTombstone {
+    // The name is limited by `MAX_TOKEN_LEN_IN_BYTES` and the error by `MAX_PROBE_ERROR_LEN`.
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// State of a deployed token canister, updated by the periodic health probe.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenStatus {
+    pub token: Principal,
+    pub name: String,
+    /// Cycle balance of the canister. `None` if the factory is not a controller of the token.
+    pub cycles: Option<u128>,
+    /// Hash of the installed wasm module. `None` if the factory is not a controller of the token.
+    pub module_hash: Option<Vec<u8>>,
+    /// Version of the token crate reported by the `pkg_version` method.
+    pub wasm_version: Option<String>,
+    /// Time of the last upgrade of the token by the factory.
+    pub last_upgrade: Option<u64>,
+    /// Migration id of the last upgrade forced over the state compatibility check.
+    pub last_migration_id: Option<String>,
+    /// Time of the last health probe. Zero if the token was not probed yet.
+    pub last_probe: u64,
+    /// Whether the token responded to the last health probe.
+    pub healthy: bool,
+    /// Error of the last health probe.
+    pub error: Option<String>,
+    /// Cycles burned by the canister between the health probes, not counting the top-ups. Not
+    /// set until the cycle balance is probed twice.
+    pub cycles_consumed: Option<u128>,
+}
+
+impl TokenStatus {
+    pub fn new(token: Principal, name: String) -> Self {
+        Self {
+            token,
+            name,
+            cycles: None,
+            module_hash: None,
+            wasm_version: None,
+            last_upgrade: None,
+            last_migration_id: None,
+            last_probe: 0,
+            healthy: false,
+            error: None,
+            cycles_consumed: None,
+        }
+    }
+
+    /// Stores the probed cycle balance. A decrease since the previous probe is added to the
+    /// consumed cycles, an increase is a top-up and is not counted.
+    pub fn record_cycles(&mut self, cycles: Option<u128>) {
+        if let (Some(previous), Some(current)) = (self.cycles, cycles) {
+            let consumed = self.cycles_consumed.unwrap_or_default();
+            self.cycles_consumed = Some(consumed.saturating_add(previous.saturating_sub(current)));
+        }
+        self.cycles = cycles;
+    }
+}
+
+impl Storable for TokenStatus {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode token status for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode token status from stable storage")
+    }
+}
+
+impl BoundedStorable for TokenStatus {
+    // The name is limited by `MAX_TOKEN_LEN_IN_BYTES` and the error by `MAX_PROBE_ERROR_LEN`.
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub const MAX_PROBE_ERROR_LEN: usize = 256;
+
+/// Deployments and payments of one deployer.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeployerStats {
+    /// Number of the tokens deployed, including the ones removed later.
+    pub deployments: u64,
+    /// Sum of the deployment fees paid. The fees are summed as they were charged, even if the
+    /// fee ledger was changed in between.
+    pub fees_paid: Tokens128,
+    pub last_deployment: Option<u64>,
+}
+
+impl Storable for DeployerStats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode deployer stats for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode deployer stats from stable storage")
     }
+}
 
-    pub fn get_token(&self, name: String) -> Option<Principal> {
-        Self::check_name(&name).then_some(())?;
+impl BoundedStorable for DeployerStats {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
 
-        TOKENS_MAP
-            .with(|map| map.borrow().get(&StringKey(name)))
-            .map(|principal| principal.0)
+/// Cycles of a token in the `BillingReport`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenBilling {
+    pub token: Principal,
+    /// Cycle balance as of the last health probe.
+    pub cycles: Option<u128>,
+    /// Cycles burned by the token, see `TokenStatus::cycles_consumed`.
+    pub cycles_consumed: u128,
+}
+
+/// Summary of the deployments of one deployer returned by `get_billing_report`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BillingReport {
+    pub deployer: Principal,
+    pub deployments: u64,
+    pub fees_paid: Tokens128,
+    pub last_deployment: Option<u64>,
+    /// Registered tokens of the deployer.
+    pub tokens: Vec<TokenBilling>,
+    /// Sum of the cycles consumed by the registered tokens.
+    pub cycles_consumed: u128,
+    /// Maximum number of the registered tokens of one deployer.
+    pub quota: Option<u64>,
+    /// Whether the deployer is on the deployers allowlist, which lifts the quota.
+    pub quota_exempt: bool,
+}
+
+/// Time after which an in-flight deployment is considered stalled, e.g. because the call trapped
+/// after the canister was created, and can be recovered.
+pub const STALLED_DEPLOYMENT_TIMEOUT_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum DeploymentStage {
+    /// The deployment fee is charged, the canister is being created.
+    Creating,
+    /// The canister is created, the token code is being installed.
+    Installing,
+    /// The failed deployment is being recovered by `recover_failed_deployments`.
+    Recovering,
+    /// The deployment failed. The canister (if it was created) and the charged fee are kept until
+    /// `recover_failed_deployments` is called.
+    Failed { error: String },
+}
+
+/// Subnet to create the token canister on, see `create_token`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum SubnetSelection {
+    Subnet {
+        subnet: Principal,
+    },
+    /// The subnet hosting the `canister`, i.e. the canister is used as the effective canister id.
+    Canister {
+        canister: Principal,
+    },
+}
+
+/// Deployment of a token tracked from the moment the deployment fee is charged until the token is
+/// registered, so the canisters and the fees of the failed deployments are not lost.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingDeployment {
+    pub id: u64,
+    pub deployer: Principal,
+    pub metadata: Metadata,
+    pub amount: Tokens128,
+    pub controller: Option<Principal>,
+    /// The charged deployment fee, refunded if the deployment is undone.
+    pub fee: Option<DeploymentFee>,
+    /// The subnet selected by the deployer. The canister is created on the factory subnet if not
+    /// set.
+    pub subnet: Option<Principal>,
+    /// The created canister. Only known for the canisters the factory installs itself; the
+    /// canisters paid by the caller are created and installed by a single `ic-factory` call.
+    pub canister: Option<Principal>,
+    pub stage: DeploymentStage,
+    pub started_at: u64,
+    pub updated_at: u64,
+    pub recovery_attempts: u32,
+}
+
+impl PendingDeployment {
+    /// Whether the deployment failed or stalled at the time `now`.
+    pub fn is_recoverable(&self, now: u64) -> bool {
+        matches!(self.stage, DeploymentStage::Failed { .. })
+            || now.saturating_sub(self.updated_at) >= STALLED_DEPLOYMENT_TIMEOUT_NANOS
     }
 
-    pub fn remove_token(&self, name: String) -> Option<Principal> {
-        Self::check_name(&name).then_some(())?;
+    pub fn fail(&mut self, mut error: String, timestamp: u64) {
+        truncate_message(&mut error, MAX_PROBE_ERROR_LEN);
+        self.stage = DeploymentStage::Failed { error };
+        self.updated_at = timestamp;
+    }
+}
 
-        TOKENS_MAP
-            .with(|map| map.borrow_mut().remove(&StringKey(name)))
-            .map(|principal| principal.0)
+impl Storable for PendingDeployment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode pending deployment for stable storage")
+            .into()
     }
 
-    pub fn insert_token(&mut self, name: String, principal: Principal) {
-        TOKENS_MAP.with(|map| {
-            map.borrow_mut()
-                .insert(StringKey(name), PrincipalValue(principal))
-        });
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode pending deployment from stable storage")
     }
+}
 
-    pub fn get_token_wasm(&self) -> Option<Vec<u8>> {
-        WASM_CELL.with(|cell| cell.borrow().get().0.clone())
+impl BoundedStorable for PendingDeployment {
+    // The name is limited by `MAX_TOKEN_LEN_IN_BYTES`, the symbol by the symbol rules and the
+    // error by `MAX_PROBE_ERROR_LEN`, the rest are a few principals and numbers.
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Result of the recovery of one deployment returned by `recover_failed_deployments`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum DeploymentRecovery {
+    /// The token code was installed and the token registered.
+    Installed { id: u64, token: Principal },
+    /// The canister (if it was created) was deleted and the fee (if it was charged) refunded.
+    Refunded { id: u64 },
+    /// The recovery failed, the deployment stays pending.
+    Failed { id: u64, error: String },
+}
+
+/// Number of the attempts to reach a token before its metadata update is dropped.
+pub const MAX_METADATA_UPDATE_ATTEMPTS: u32 = 5;
+
+/// Metadata update of a token which could not be reached, retried in the background.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingMetadataUpdate {
+    pub token: Principal,
+    /// Owner of the token who requested the update.
+    pub owner: Principal,
+    pub patch: MetadataPatch,
+    pub attempts: u32,
+    pub last_error: String,
+    pub updated_at: u64,
+}
+
+impl Storable for PendingMetadataUpdate {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode pending metadata update for stable storage")
+            .into()
     }
 
-    pub fn set_token_wasm(&mut self, wasm: Option<Vec<u8>>) {
-        WASM_CELL.with(|cell| {
-            cell.borrow_mut()
-                .set(StorableWasm(wasm))
-                .expect("failed to set token canister wasm to stable storage");
-        });
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode pending metadata update from stable storage")
     }
+}
 
-    fn check_name(name: &str) -> bool {
-        name.as_bytes().len() <= MAX_TOKEN_LEN_IN_BYTES
+impl BoundedStorable for PendingMetadataUpdate {
+    // The logo is limited by `MAX_LOGO_SIZE` of the token, the name by `MAX_TOKEN_LEN_IN_BYTES`
+    // and the error by `MAX_PROBE_ERROR_LEN`.
+    const MAX_SIZE: u32 = 40 * 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Result of the metadata update of one token returned by `update_token_metadata`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum MetadataUpdateStatus {
+    Updated,
+    /// The token refused the update, e.g. because the caller is not its owner.
+    Rejected {
+        error: String,
+    },
+    /// The token could not be reached, the update is retried in the background.
+    Retrying {
+        attempts: u32,
+        error: String,
+    },
+    /// The token could not be reached `MAX_METADATA_UPDATE_ATTEMPTS` times, the update is dropped.
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MetadataUpdateResult {
+    pub token: Principal,
+    pub status: MetadataUpdateStatus,
+}
+
+/// Token registered on the shared registry canister.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RegistryEntry {
+    pub symbol: String,
+    pub name: String,
+    pub token: Principal,
+}
+
+/// Registration of a deployed token which could not reach the registry, retried in the
+/// background.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingRegistration {
+    pub entry: RegistryEntry,
+    pub registry: Principal,
+    pub attempts: u32,
+    pub last_error: String,
+    pub updated_at: u64,
+}
+
+impl Storable for PendingRegistration {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode pending registration for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode pending registration from stable storage")
     }
 }
 
-#[derive(Default, Deserialize, CandidType)]
-struct StorableWasm(Option<Vec<u8>>);
+impl BoundedStorable for PendingRegistration {
+    // The name is limited by `MAX_TOKEN_LEN_IN_BYTES`, the symbol by the symbol rules and the
+    // error by `MAX_PROBE_ERROR_LEN`.
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
 
-impl Storable for StorableWasm {
+#[derive(CandidType, Deserialize, Default)]
+struct StorableRegistry(Option<Principal>);
+
+impl Storable for StorableRegistry {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
         Encode!(self)
-            .expect("failed to encode StorableWasm for stable storage")
+            .expect("failed to encode registry for stable storage")
             .into()
     }
 
     fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
-        Decode!(&bytes, Self).expect("failed to decode StorableWasm from stable storage")
+        Decode!(&bytes, Self).expect("failed to decode registry from stable storage")
+    }
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct StorableQuota(Option<u64>);
+
+impl Storable for StorableQuota {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode deploy quota for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode deploy quota from stable storage")
+    }
+}
+
+/// Who can deploy tokens with the factory.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeployPolicy {
+    /// Anyone paying for the deployment.
+    Open,
+    /// Only the factory controller and the principals on the deployers allowlist.
+    Allowlist,
+}
+
+impl Default for DeployPolicy {
+    fn default() -> Self {
+        Self::Open
+    }
+}
+
+impl Storable for DeployPolicy {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode deploy policy for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode deploy policy from stable storage")
     }
 }
 
@@ -94,7 +1332,8 @@ impl BoundedStorable for StringKey {
     const IS_FIXED_SIZE: bool = false;
 }
 
-struct PrincipalValue(Principal);
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PrincipalValue(pub(crate) Principal);
 
 impl Storable for PrincipalValue {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
@@ -114,6 +1353,27 @@ impl BoundedStorable for PrincipalValue {
 // starts with 10 because 0..10 reserved for `ic-factory` state.
 const WASM_MEMORY_ID: MemoryId = MemoryId::new(10);
 const TOKENS_MEMORY_ID: MemoryId = MemoryId::new(11);
+const DEPLOYMENT_FEE_MEMORY_ID: MemoryId = MemoryId::new(12);
+const RELEASES_MEMORY_ID: MemoryId = MemoryId::new(13);
+const FLEET_STATUS_MEMORY_ID: MemoryId = MemoryId::new(14);
+const DEPLOY_POLICY_MEMORY_ID: MemoryId = MemoryId::new(15);
+const DEPLOY_ALLOWLIST_MEMORY_ID: MemoryId = MemoryId::new(16);
+const SYMBOLS_MEMORY_ID: MemoryId = MemoryId::new(17);
+const SYMBOL_RULES_MEMORY_ID: MemoryId = MemoryId::new(18);
+const WASM_COMPATIBILITY_MEMORY_ID: MemoryId = MemoryId::new(19);
+const TOMBSTONES_MEMORY_ID: MemoryId = MemoryId::new(20);
+// 21 and 22 are used by the factory events.
+const TOKEN_DEPLOYERS_MEMORY_ID: MemoryId = MemoryId::new(23);
+const DEPLOYER_STATS_MEMORY_ID: MemoryId = MemoryId::new(24);
+const DEPLOY_QUOTA_MEMORY_ID: MemoryId = MemoryId::new(25);
+const PENDING_DEPLOYMENTS_MEMORY_ID: MemoryId = MemoryId::new(26);
+const NEXT_DEPLOYMENT_ID_MEMORY_ID: MemoryId = MemoryId::new(27);
+const PENDING_METADATA_UPDATES_MEMORY_ID: MemoryId = MemoryId::new(28);
+const REGISTRY_MEMORY_ID: MemoryId = MemoryId::new(29);
+const PENDING_REGISTRATIONS_MEMORY_ID: MemoryId = MemoryId::new(30);
+const TOKEN_SUBNETS_MEMORY_ID: MemoryId = MemoryId::new(31);
+const UPGRADE_SCHEDULE_MEMORY_ID: MemoryId = MemoryId::new(32);
+const TOKEN_UPGRADES_MEMORY_ID: MemoryId = MemoryId::new(33);
 
 thread_local! {
     static WASM_CELL: RefCell<StableCell<StorableWasm>> = {
@@ -123,6 +1383,82 @@ thread_local! {
 
     static TOKENS_MAP: RefCell<StableBTreeMap<StringKey, PrincipalValue>> =
         RefCell::new(StableBTreeMap::new(TOKENS_MEMORY_ID));
+
+    static DEPLOYMENT_FEE_CELL: RefCell<StableCell<StorableDeploymentFee>> = {
+            RefCell::new(StableCell::new(DEPLOYMENT_FEE_MEMORY_ID, StorableDeploymentFee::default())
+                .expect("failed to initialize deployment fee stable storage"))
+    };
+
+    static RELEASES_MAP: RefCell<StableBTreeMap<PrincipalValue, ControllerRelease>> =
+        RefCell::new(StableBTreeMap::new(RELEASES_MEMORY_ID));
+
+    static FLEET_STATUS_MAP: RefCell<StableBTreeMap<PrincipalValue, TokenStatus>> =
+        RefCell::new(StableBTreeMap::new(FLEET_STATUS_MEMORY_ID));
+
+    static DEPLOY_POLICY_CELL: RefCell<StableCell<DeployPolicy>> = {
+            RefCell::new(StableCell::new(DEPLOY_POLICY_MEMORY_ID, DeployPolicy::default())
+                .expect("failed to initialize deploy policy stable storage"))
+    };
+
+    static DEPLOY_ALLOWLIST_MAP: RefCell<StableBTreeMap<PrincipalValue, u64>> =
+        RefCell::new(StableBTreeMap::new(DEPLOY_ALLOWLIST_MEMORY_ID));
+
+    static TOMBSTONES_MAP: RefCell<StableBTreeMap<PrincipalValue, TokenTombstone>> =
+        RefCell::new(StableBTreeMap::new(TOMBSTONES_MEMORY_ID));
+
+    static SYMBOLS_MAP: RefCell<StableBTreeMap<StringKey, StringKey>> =
+        RefCell::new(StableBTreeMap::new(SYMBOLS_MEMORY_ID));
+
+    static WASM_COMPATIBILITY_CELL: RefCell<StableCell<StorableWasmCompatibility>> = {
+            RefCell::new(StableCell::new(WASM_COMPATIBILITY_MEMORY_ID, StorableWasmCompatibility::default())
+                .expect("failed to initialize wasm compatibility stable storage"))
+    };
+
+    static TOKEN_DEPLOYERS_MAP: RefCell<StableBTreeMap<PrincipalValue, PrincipalValue>> =
+        RefCell::new(StableBTreeMap::new(TOKEN_DEPLOYERS_MEMORY_ID));
+
+    static TOKEN_SUBNETS_MAP: RefCell<StableBTreeMap<PrincipalValue, PrincipalValue>> =
+        RefCell::new(StableBTreeMap::new(TOKEN_SUBNETS_MEMORY_ID));
+
+    static DEPLOYER_STATS_MAP: RefCell<StableBTreeMap<PrincipalValue, DeployerStats>> =
+        RefCell::new(StableBTreeMap::new(DEPLOYER_STATS_MEMORY_ID));
+
+    static DEPLOY_QUOTA_CELL: RefCell<StableCell<StorableQuota>> = {
+            RefCell::new(StableCell::new(DEPLOY_QUOTA_MEMORY_ID, StorableQuota::default())
+                .expect("failed to initialize deploy quota stable storage"))
+    };
+
+    static PENDING_DEPLOYMENTS_MAP: RefCell<StableBTreeMap<u64, PendingDeployment>> =
+        RefCell::new(StableBTreeMap::new(PENDING_DEPLOYMENTS_MEMORY_ID));
+
+    static NEXT_DEPLOYMENT_ID_CELL: RefCell<StableCell<u64>> = {
+            RefCell::new(StableCell::new(NEXT_DEPLOYMENT_ID_MEMORY_ID, 0)
+                .expect("failed to initialize next deployment id stable storage"))
+    };
+
+    static PENDING_METADATA_UPDATES_MAP: RefCell<StableBTreeMap<PrincipalValue, PendingMetadataUpdate>> =
+        RefCell::new(StableBTreeMap::new(PENDING_METADATA_UPDATES_MEMORY_ID));
+
+    static REGISTRY_CELL: RefCell<StableCell<StorableRegistry>> = {
+            RefCell::new(StableCell::new(REGISTRY_MEMORY_ID, StorableRegistry::default())
+                .expect("failed to initialize registry stable storage"))
+    };
+
+    static PENDING_REGISTRATIONS_MAP: RefCell<StableBTreeMap<PrincipalValue, PendingRegistration>> =
+        RefCell::new(StableBTreeMap::new(PENDING_REGISTRATIONS_MEMORY_ID));
+
+    static UPGRADE_SCHEDULE_CELL: RefCell<StableCell<StorableUpgradeSchedule>> = {
+            RefCell::new(StableCell::new(UPGRADE_SCHEDULE_MEMORY_ID, StorableUpgradeSchedule::default())
+                .expect("failed to initialize upgrade schedule stable storage"))
+    };
+
+    static TOKEN_UPGRADES_MAP: RefCell<StableBTreeMap<PrincipalValue, TokenUpgradeStatus>> =
+        RefCell::new(StableBTreeMap::new(TOKEN_UPGRADES_MEMORY_ID));
+
+    static SYMBOL_RULES_CELL: RefCell<StableCell<SymbolRules>> = {
+            RefCell::new(StableCell::new(SYMBOL_RULES_MEMORY_ID, SymbolRules::default())
+                .expect("failed to initialize symbol rules stable storage"))
+    };
 }
 
 pub fn get_state() -> State {
@@ -135,7 +1471,14 @@ mod tests {
     use canister_sdk::ic_kit::MockContext;
     use ic_stable_structures::Storable;
 
-    use crate::state::{PrincipalValue, StorableWasm};
+    use token::state::config::{Metadata, MetadataPatch};
+
+    use crate::state::{
+        ControllerRelease, DeployPolicy, DeploymentFee, DeploymentStage, PendingMetadataUpdate,
+        PendingRegistration, PrincipalValue, RegistryEntry, StorableWasm, TokenBilling,
+        TokenOverrides, TokenStatus, TokenTombstone, TokenUpgradeState, UpgradeRolloutStatus,
+        UpgradeSchedule, WasmCompatibility, STALLED_DEPLOYMENT_TIMEOUT_NANOS,
+    };
     use crate::State;
 
     use super::StringKey;
@@ -223,4 +1566,417 @@ mod tests {
         state.set_token_wasm(Some(vec![123; 2048]));
         assert_eq!(state.get_token_wasm(), Some(vec![123; 2048]));
     }
+
+    #[test]
+    fn set_get_deployment_fee() {
+        let mut state = init_state();
+        assert_eq!(state.get_deployment_fee(), None);
+
+        let fee = DeploymentFee {
+            ledger: Principal::management_canister(),
+            amount: 100_000.into(),
+            fee_to: Principal::anonymous(),
+        };
+        state.set_deployment_fee(Some(fee.clone()));
+        assert_eq!(state.get_deployment_fee(), Some(fee));
+
+        state.reset();
+        assert_eq!(state.get_deployment_fee(), None);
+    }
+
+    #[test]
+    fn insert_get_controller_release() {
+        let mut state = init_state();
+        let token = Principal::management_canister();
+        assert_eq!(state.get_controller_release(token), None);
+
+        let release = ControllerRelease {
+            token,
+            released_by: Principal::anonymous(),
+            controllers: vec![Principal::anonymous(), Principal::management_canister()],
+            timestamp: 42,
+        };
+        state.insert_controller_release(release.clone());
+        assert_eq!(state.get_controller_release(token), Some(release.clone()));
+
+        let deserialized = ControllerRelease::from_bytes(release.to_bytes());
+        assert_eq!(deserialized, release);
+    }
+
+    #[test]
+    fn token_status_keeps_last_upgrade() {
+        let mut state = init_state();
+        let token = Principal::management_canister();
+        state.record_upgrade(token, "token".into(), 42, Some("v1-to-v2".into()));
+        state.record_upgrade(token, "token".into(), 43, None);
+
+        let mut status = state.get_token_status(token).unwrap();
+        assert_eq!(status.last_upgrade, Some(43));
+        assert_eq!(status.last_migration_id, Some("v1-to-v2".into()));
+
+        status.healthy = true;
+        status.last_probe = 100;
+        state.set_token_status(status.clone());
+        assert_eq!(state.fleet_status(), vec![status.clone()]);
+
+        let deserialized = TokenStatus::from_bytes(status.to_bytes());
+        assert_eq!(deserialized, status);
+    }
+
+    #[test]
+    fn retired_token_leaves_tombstone() {
+        let mut state = init_state();
+        let token = Principal::from_slice(&[1; 29]);
+        state.insert_token("token".into(), token);
+        state.insert_token_symbol("TKN", "token".into());
+        state.set_token_status(TokenStatus::new(token, "token".into()));
+
+        let tombstone = TokenTombstone {
+            token,
+            name: "token".into(),
+            decommissioned_by: Principal::anonymous(),
+            cycles_reclaimed: 1_000,
+            withdrawal_error: None,
+            timestamp: 42,
+        };
+        state.retire_token(tombstone.clone());
+
+        assert_eq!(state.get_token("token".into()), None);
+        assert_eq!(state.get_token_by_symbol("TKN"), None);
+        assert_eq!(state.get_token_status(token), None);
+        assert_eq!(state.get_tombstone(token), Some(tombstone.clone()));
+        assert_eq!(state.list_tombstones(), vec![tombstone]);
+    }
+
+    #[test]
+    fn wasm_compatibility() {
+        let mut state = init_state();
+        assert_eq!(state.get_wasm_compatibility(), None);
+
+        let compatibility = WasmCompatibility {
+            state_version: 3,
+            upgradable_from: vec![2],
+        };
+        assert!(compatibility.accepts(3));
+        assert!(compatibility.accepts(2));
+        assert!(!compatibility.accepts(1));
+        assert_eq!(compatibility.supported_versions(), vec![2, 3]);
+
+        state.set_wasm_compatibility(Some(compatibility.clone()));
+        assert_eq!(state.get_wasm_compatibility(), Some(compatibility));
+
+        state.reset();
+        assert_eq!(state.get_wasm_compatibility(), None);
+    }
+
+    #[test]
+    fn deploy_allowlist() {
+        let mut state = init_state();
+        let deployer = Principal::anonymous();
+        assert_eq!(state.get_deploy_policy(), DeployPolicy::Open);
+        assert!(state.is_allowed_deployer(deployer));
+
+        state.set_deploy_policy(DeployPolicy::Allowlist);
+        assert!(!state.is_allowed_deployer(deployer));
+
+        state.add_allowed_deployer(deployer, 42);
+        assert!(state.is_allowed_deployer(deployer));
+        assert_eq!(state.allowed_deployers(), vec![(deployer, 42)]);
+
+        assert!(state.remove_allowed_deployer(deployer));
+        assert!(!state.remove_allowed_deployer(deployer));
+        assert!(!state.is_allowed_deployer(deployer));
+
+        state.reset();
+        assert_eq!(state.get_deploy_policy(), DeployPolicy::Open);
+    }
+
+    #[test]
+    fn deployer_billing() {
+        let mut state = init_state();
+        let deployer = Principal::from_slice(&[1; 29]);
+        let first = Principal::from_slice(&[2; 29]);
+        let second = Principal::from_slice(&[3; 29]);
+        state.insert_token("first".into(), first);
+        state.record_deployment(deployer, first, 100.into(), 42);
+        state.insert_token("second".into(), second);
+        state.record_deployment(deployer, second, 0.into(), 43);
+
+        let mut status = TokenStatus::new(first, "first".into());
+        status.record_cycles(Some(1_000));
+        status.record_cycles(Some(800));
+        status.record_cycles(Some(2_000));
+        status.record_cycles(Some(1_900));
+        assert_eq!(status.cycles_consumed, Some(300));
+        state.set_token_status(status);
+
+        state.remove_token("second".into());
+        state.set_deploy_quota(Some(1));
+        let report = state.billing_report(deployer);
+        assert_eq!(report.deployments, 2);
+        assert_eq!(report.fees_paid, 100.into());
+        assert_eq!(report.last_deployment, Some(43));
+        assert_eq!(
+            report.tokens,
+            vec![TokenBilling {
+                token: first,
+                cycles: Some(1_900),
+                cycles_consumed: 300,
+            }]
+        );
+        assert_eq!(report.cycles_consumed, 300);
+        assert_eq!(report.quota, Some(1));
+        assert!(!report.quota_exempt);
+
+        state.add_allowed_deployer(deployer, 44);
+        assert!(state.billing_report(deployer).quota_exempt);
+
+        state.reset();
+        assert_eq!(state.get_deploy_quota(), None);
+        assert_eq!(state.billing_report(deployer).deployments, 0);
+    }
+
+    #[test]
+    fn token_subnets() {
+        let mut state = init_state();
+        let subnet = Principal::from_slice(&[9; 29]);
+        let first = Principal::from_slice(&[2; 29]);
+        let second = Principal::from_slice(&[3; 29]);
+        state.insert_token("first".into(), first);
+        state.record_token_subnet(first, subnet);
+        state.insert_token("second".into(), second);
+        state.record_token_subnet(second, subnet);
+
+        assert_eq!(state.get_token_subnet(first), Some(subnet));
+        assert_eq!(state.subnet_usage(), vec![(subnet, 2)]);
+
+        state.remove_token("second".into());
+        assert_eq!(state.get_token_subnet(second), None);
+        assert_eq!(state.subnet_usage(), vec![(subnet, 1)]);
+    }
+
+    #[test]
+    fn token_symbols_are_case_insensitive() {
+        let mut state = init_state();
+        state.insert_token("token".into(), Principal::anonymous());
+        state.insert_token_symbol("Tkn", "token".into());
+
+        assert_eq!(state.get_token_by_symbol("TKN"), Some("token".into()));
+        assert_eq!(state.get_token_by_symbol("tkn"), Some("token".into()));
+        assert_eq!(state.get_token_by_symbol("OTHER"), None);
+
+        state.remove_token("token".into());
+        assert_eq!(state.get_token_by_symbol("TKN"), None);
+    }
+
+    #[test]
+    fn token_overrides() {
+        let owner = Principal::from_slice(&[1; 29]);
+        let treasury = Principal::from_slice(&[2; 29]);
+        let new_owner = Principal::from_slice(&[3; 29]);
+        let source = Metadata {
+            name: "Staging".into(),
+            symbol: "STG".into(),
+            decimals: 6,
+            owner,
+            fee: 10.into(),
+            fee_to: owner,
+            is_test_token: Some(true),
+            init_spec: None,
+        };
+
+        let overrides = TokenOverrides {
+            name: Some("Production".into()),
+            symbol: None,
+            owner: Some(new_owner),
+        };
+        let clone = overrides.clone().apply(source.clone());
+        assert_eq!(clone.name, "Production");
+        assert_eq!(clone.symbol, "STG");
+        assert_eq!(clone.decimals, 6);
+        assert_eq!(clone.owner, new_owner);
+        assert_eq!(clone.fee_to, new_owner);
+        assert_eq!(clone.is_test_token, Some(true));
+
+        let clone = overrides.apply(Metadata {
+            fee_to: treasury,
+            ..source
+        });
+        assert_eq!(clone.fee_to, treasury);
+    }
+
+    #[test]
+    fn pending_deployments() {
+        let mut state = init_state();
+        let deployer = Principal::from_slice(&[1; 29]);
+        let metadata = Metadata {
+            name: "Token".into(),
+            symbol: "TKN".into(),
+            decimals: 8,
+            owner: deployer,
+            fee: 0.into(),
+            fee_to: deployer,
+            is_test_token: None,
+            init_spec: None,
+        };
+
+        let id =
+            state.begin_deployment(deployer, metadata.clone(), 100.into(), None, None, None, 10);
+        let other = state.begin_deployment(
+            Principal::anonymous(),
+            metadata,
+            1.into(),
+            None,
+            None,
+            None,
+            10,
+        );
+        assert_ne!(id, other);
+        assert_eq!(state.pending_deployments(Some(deployer)).len(), 1);
+        assert_eq!(state.pending_deployments(None).len(), 2);
+
+        let mut deployment = state.get_pending_deployment(id).unwrap();
+        assert_eq!(deployment.stage, DeploymentStage::Creating);
+        assert!(!deployment.is_recoverable(10));
+        assert!(deployment.is_recoverable(10 + STALLED_DEPLOYMENT_TIMEOUT_NANOS));
+
+        deployment.canister = Some(Principal::management_canister());
+        deployment.fail("install failed".into(), 20);
+        state.update_deployment(deployment);
+        let deployment = state.get_pending_deployment(id).unwrap();
+        assert!(deployment.is_recoverable(20));
+        assert_eq!(deployment.canister, Some(Principal::management_canister()));
+
+        assert!(state.finish_deployment(id).is_some());
+        assert!(state.get_pending_deployment(id).is_none());
+        state.reset();
+        assert!(state.pending_deployments(None).is_empty());
+    }
+
+    #[test]
+    fn pending_metadata_updates() {
+        let mut state = init_state();
+        let token = Principal::from_slice(&[2; 29]);
+        let update = PendingMetadataUpdate {
+            token,
+            owner: Principal::from_slice(&[1; 29]),
+            patch: MetadataPatch {
+                name: Some("Rebranded".into()),
+                ..MetadataPatch::default()
+            },
+            attempts: 1,
+            last_error: "unreachable".into(),
+            updated_at: 10,
+        };
+
+        state.set_pending_metadata_update(update.clone());
+        assert_eq!(
+            state.get_pending_metadata_update(token),
+            Some(update.clone())
+        );
+
+        let newer = PendingMetadataUpdate {
+            patch: MetadataPatch {
+                fee: Some(5.into()),
+                ..MetadataPatch::default()
+            },
+            ..update
+        };
+        state.set_pending_metadata_update(newer.clone());
+        assert_eq!(state.pending_metadata_updates(), vec![newer.clone()]);
+
+        assert_eq!(state.remove_pending_metadata_update(token), Some(newer));
+        assert!(state.pending_metadata_updates().is_empty());
+    }
+
+    #[test]
+    fn pending_registrations() {
+        let mut state = init_state();
+        assert_eq!(state.get_registry(), None);
+        let registry = Principal::from_slice(&[3; 29]);
+        state.set_registry(Some(registry));
+        assert_eq!(state.get_registry(), Some(registry));
+
+        let token = Principal::from_slice(&[2; 29]);
+        let registration = PendingRegistration {
+            entry: RegistryEntry {
+                symbol: "TKN".into(),
+                name: "Token".into(),
+                token,
+            },
+            registry,
+            attempts: 1,
+            last_error: "unreachable".into(),
+            updated_at: 10,
+        };
+        state.set_pending_registration(registration.clone());
+        let retried = PendingRegistration {
+            attempts: 2,
+            ..registration
+        };
+        state.set_pending_registration(retried.clone());
+        assert_eq!(state.pending_registrations(), vec![retried.clone()]);
+
+        assert_eq!(state.remove_pending_registration(token), Some(retried));
+        assert!(state.pending_registrations().is_empty());
+        state.reset();
+        assert_eq!(state.get_registry(), None);
+    }
+
+    #[test]
+    fn upgrade_rollout_pauses_over_failure_budget() {
+        let mut state = init_state();
+        let schedule = UpgradeSchedule {
+            version: "1.2.0".into(),
+            start_time: 100,
+            end_time: 1_000,
+            batch_size: 2,
+            max_failures: 1,
+            scheduled_by: Principal::anonymous(),
+            scheduled_at: 10,
+            status: UpgradeRolloutStatus::Scheduled,
+            total: 3,
+            upgraded: 0,
+            skipped: 0,
+            failed: 0,
+            failures_since_resume: 0,
+            updated_at: 10,
+        };
+        let tokens = (1..=3)
+            .map(|i| (format!("Token{i}"), Principal::from_slice(&[i; 29])))
+            .collect();
+        state.begin_upgrade_rollout(schedule.clone(), tokens);
+        assert_eq!(state.get_upgrade_schedule(), Some(schedule.clone()));
+        assert_eq!(state.token_upgrade_statuses().len(), 3);
+
+        let mut batch = state.pending_token_upgrades(2);
+        assert_eq!(batch.len(), 2);
+        batch[0].state = TokenUpgradeState::Upgraded;
+        state.set_token_upgrade_status(batch[0].clone());
+        assert_eq!(state.pending_token_upgrades(10).len(), 2);
+
+        let mut schedule = schedule;
+        schedule.status = UpgradeRolloutStatus::Running;
+        schedule.record(&TokenUpgradeState::Upgraded, 200);
+        let failed = TokenUpgradeState::Failed {
+            error: "trapped".into(),
+        };
+        schedule.record(&failed, 300);
+        assert_eq!(schedule.status, UpgradeRolloutStatus::Running);
+        schedule.record(&failed, 400);
+        assert_eq!(schedule.status, UpgradeRolloutStatus::Paused);
+        assert_eq!((schedule.upgraded, schedule.failed), (1, 2));
+        assert!(schedule.is_active());
+
+        let mut schedule = state.get_upgrade_schedule().unwrap();
+        assert!(!schedule.pause_after_window(1_000));
+        assert!(schedule.pause_after_window(1_001));
+        assert_eq!(schedule.status, UpgradeRolloutStatus::Paused);
+        assert_eq!(schedule.updated_at, 1_001);
+        assert!(!schedule.pause_after_window(1_002));
+
+        state.reset();
+        assert_eq!(state.get_upgrade_schedule(), None);
+        assert!(state.token_upgrade_statuses().is_empty());
+    }
 }
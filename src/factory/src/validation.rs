@@ -0,0 +1,116 @@
+//! Validation of the metadata of the tokens deployed by the factory.
+
+use std::borrow::Cow;
+
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::Storable;
+use serde::Deserialize;
+
+use crate::error::TokenFactoryError;
+
+/// Rules the symbols of the new tokens must satisfy, set by the factory controller.
+///
+/// Symbols must be non-empty printable ASCII strings in any case. The symbols are compared
+/// case-insensitively, both with the reserved symbols and with the symbols of the tokens already
+/// deployed by the factory.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SymbolRules {
+    /// Maximum length of the symbol in bytes.
+    pub max_length: u32,
+    /// Symbols which cannot be used by the new tokens.
+    pub reserved: Vec<String>,
+}
+
+impl Default for SymbolRules {
+    fn default() -> Self {
+        Self {
+            max_length: 16,
+            reserved: vec!["ICP".to_string()],
+        }
+    }
+}
+
+impl Storable for SymbolRules {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode symbol rules for stable storage")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode symbol rules from stable storage")
+    }
+}
+
+impl SymbolRules {
+    /// Checks the `symbol` against the rules. The check for the symbols of the deployed tokens is
+    /// done separately, see `State::get_token_by_symbol`.
+    pub fn validate(&self, symbol: &str) -> Result<(), TokenFactoryError> {
+        let invalid = |reason: &str| Err(TokenFactoryError::InvalidTokenSymbol(reason.to_string()));
+
+        if symbol.is_empty() {
+            return invalid("symbol is empty");
+        }
+
+        if symbol.len() > self.max_length as usize {
+            return invalid(&format!("symbol is longer than {} bytes", self.max_length));
+        }
+
+        if !symbol.is_ascii() {
+            return invalid("symbol contains non-ASCII characters");
+        }
+
+        if symbol.chars().any(|c| c.is_ascii_control()) {
+            return invalid("symbol contains control characters");
+        }
+
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(symbol))
+        {
+            return invalid("symbol is reserved");
+        }
+
+        Ok(())
+    }
+}
+
+/// Key the symbols are registered and compared with.
+pub fn normalize_symbol(symbol: &str) -> String {
+    symbol.to_ascii_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_rules() {
+        let rules = SymbolRules::default();
+        assert!(rules.validate("TKN").is_ok());
+
+        for symbol in ["", "VERYLONGTOKENSYMBOL", "TKN\u{e9}", "TK\nN", "icp"] {
+            assert!(
+                matches!(
+                    rules.validate(symbol),
+                    Err(TokenFactoryError::InvalidTokenSymbol(_))
+                ),
+                "{symbol:?}"
+            );
+        }
+
+        let rules = SymbolRules {
+            max_length: 32,
+            reserved: vec![],
+        };
+        assert!(rules.validate("VERYLONGTOKENSYMBOL").is_ok());
+        assert!(rules.validate("ICP").is_ok());
+    }
+
+    #[test]
+    fn storable_symbol_rules() {
+        let rules = SymbolRules::default();
+        assert_eq!(SymbolRules::from_bytes(rules.to_bytes()), rules);
+    }
+}
@@ -0,0 +1,188 @@
+//! PocketIC environment with the factory and token canisters.
+
+use std::path::PathBuf;
+
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::{decode_args, encode_args, CandidType, Nat, Principal};
+use pocket_ic::{PocketIc, WasmResult};
+use serde::de::DeserializeOwned;
+
+use crate::types::Metadata;
+
+/// Cycles added to every canister installed by the tests. The factory spends its cycles on the
+/// tokens created with `create_token_for_tests`.
+pub const CANISTER_CYCLES: u128 = 100_000_000_000_000;
+
+pub const TOKEN_WASM: &str = "token.wasm";
+pub const FACTORY_WASM: &str = "factory-test.wasm";
+
+/// Returns the bytes of the wasm module with the given file name.
+///
+/// The module is looked up in the directory set by the `WASM_DIR` environment variable, or in the
+/// output directory of `scripts/build-test-wasm.sh`.
+pub fn load_wasm(name: &str) -> Option<Vec<u8>> {
+    let dir = std::env::var("WASM_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("../../target/wasm32-unknown-unknown/release")
+        });
+    std::fs::read(dir.join(name)).ok()
+}
+
+/// Principal with the given id, used as a test user.
+pub fn user(id: u8) -> Principal {
+    Principal::from_slice(&[id; 29])
+}
+
+pub fn admin() -> Principal {
+    user(1)
+}
+
+pub fn alice() -> Principal {
+    user(2)
+}
+
+pub fn bob() -> Principal {
+    user(3)
+}
+
+pub fn metadata(name: &str, owner: Principal, fee: u128) -> Metadata {
+    Metadata {
+        name: name.into(),
+        symbol: name.to_uppercase(),
+        decimals: 8,
+        owner,
+        fee: fee.into(),
+        fee_to: owner,
+        is_test_token: None,
+    }
+}
+
+pub struct TestEnv {
+    pub pic: PocketIc,
+    pub token_wasm: Vec<u8>,
+    pub factory_wasm: Vec<u8>,
+}
+
+impl TestEnv {
+    /// Starts a new PocketIC instance, or returns `None` if the PocketIC server or the wasm modules
+    /// are not available, in which case the calling test should be skipped.
+    pub fn new() -> Option<Self> {
+        let wasm = (load_wasm(TOKEN_WASM), load_wasm(FACTORY_WASM));
+        let (token_wasm, factory_wasm) = match wasm {
+            (Some(token_wasm), Some(factory_wasm))
+                if std::env::var_os("POCKET_IC_BIN").is_some() =>
+            {
+                (token_wasm, factory_wasm)
+            }
+            _ => {
+                eprintln!(
+                    "skipping the test: set POCKET_IC_BIN and run scripts/build-test-wasm.sh to enable it"
+                );
+                return None;
+            }
+        };
+
+        Some(Self {
+            pic: PocketIc::new(),
+            token_wasm,
+            factory_wasm,
+        })
+    }
+
+    fn create_canister(&self) -> Principal {
+        let canister = self.pic.create_canister_with_settings(Some(admin()), None);
+        self.pic.add_cycles(canister, CANISTER_CYCLES);
+        canister
+    }
+
+    /// Installs the factory controlled by `admin()` with the token wasm set.
+    pub fn install_factory(&self) -> Principal {
+        let factory = self.create_canister();
+        let args = encode_args((admin(), None::<Principal>)).unwrap();
+        self.pic
+            .install_canister(factory, self.factory_wasm.clone(), args, Some(admin()));
+
+        let (result,): (Result<u32, candid::Reserved>,) = self.update(
+            factory,
+            admin(),
+            "set_token_bytecode",
+            (self.token_wasm.clone(),),
+        );
+        result.expect("failed to set the token bytecode");
+        factory
+    }
+
+    /// Installs a token canister directly, without the factory.
+    pub fn install_token(&self, metadata: Metadata, amount: u128) -> Principal {
+        let token = self.create_canister();
+        let args = encode_args((metadata, Nat::from(amount))).unwrap();
+        self.pic
+            .install_canister(token, self.token_wasm.clone(), args, Some(admin()));
+        token
+    }
+
+    pub fn upgrade(&self, canister: Principal, wasm: Vec<u8>) {
+        self.pic
+            .upgrade_canister(canister, wasm, encode_args(()).unwrap(), Some(admin()))
+            .expect("failed to upgrade the canister");
+    }
+
+    pub fn update<R: for<'de> ArgumentDecoder<'de>>(
+        &self,
+        canister: Principal,
+        sender: Principal,
+        method: &str,
+        args: impl ArgumentEncoder,
+    ) -> R {
+        let result = self
+            .pic
+            .update_call(canister, sender, method, encode_args(args).unwrap());
+        decode_reply(method, result)
+    }
+
+    pub fn query<R: for<'de> ArgumentDecoder<'de>>(
+        &self,
+        canister: Principal,
+        sender: Principal,
+        method: &str,
+        args: impl ArgumentEncoder,
+    ) -> R {
+        let result = self
+            .pic
+            .query_call(canister, sender, method, encode_args(args).unwrap());
+        decode_reply(method, result)
+    }
+
+    /// Shortcut for the single value queries.
+    pub fn query_one<R: CandidType + DeserializeOwned>(
+        &self,
+        canister: Principal,
+        method: &str,
+        args: impl ArgumentEncoder,
+    ) -> R {
+        let (value,): (R,) = self.query(canister, Principal::anonymous(), method, args);
+        value
+    }
+
+    pub fn balance_of(&self, token: Principal, owner: Principal) -> Nat {
+        self.query_one(
+            token,
+            "icrc1_balance_of",
+            (crate::types::Account::from(owner),),
+        )
+    }
+}
+
+fn decode_reply<R: for<'de> ArgumentDecoder<'de>>(
+    method: &str,
+    result: Result<WasmResult, pocket_ic::UserError>,
+) -> R {
+    match result {
+        Ok(WasmResult::Reply(bytes)) => decode_args(&bytes)
+            .unwrap_or_else(|err| panic!("failed to decode the reply of {method}: {err}")),
+        Ok(WasmResult::Reject(message)) => panic!("{method} was rejected: {message}"),
+        Err(err) => panic!("{method} failed: {err:?}"),
+    }
+}
@@ -0,0 +1,17 @@
+//! Integration tests of the token factory and token canisters.
+//!
+//! The tests run the release wasm of the canisters in PocketIC, so they cover the flows which
+//! cannot be checked with the mock context: inter-canister calls, upgrades and candid interfaces.
+//! To run them:
+//!
+//! ```sh
+//! ./scripts/build-test-wasm.sh
+//! POCKET_IC_BIN=/path/to/pocket-ic cargo test -p integration-tests --features pocket-ic
+//! ```
+//!
+//! If the PocketIC server or the wasm are not available, the tests are skipped.
+
+#[cfg(feature = "pocket-ic")]
+pub mod env;
+#[cfg(feature = "pocket-ic")]
+pub mod types;
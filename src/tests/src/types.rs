@@ -0,0 +1,114 @@
+//! Candid types of the canisters interfaces used by the tests.
+//!
+//! The canister crates are built with an older `candid` version than PocketIC uses, so the types
+//! are declared here instead of importing them from the canister crates.
+
+use candid::{CandidType, Int, Nat, Principal};
+use serde::Deserialize;
+
+pub type Subaccount = [u8; 32];
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Self {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Metadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub owner: Principal,
+    pub fee: Nat,
+    pub fee_to: Principal,
+    pub is_test_token: Option<bool>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct TransferArgs {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+impl TransferArgs {
+    pub fn new(to: impl Into<Account>, amount: u128) -> Self {
+        Self {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum BatchOperation {
+    Transfer {
+        from_subaccount: Option<Subaccount>,
+        to: Account,
+        amount: Nat,
+    },
+    Mint {
+        to: Account,
+        amount: Nat,
+    },
+    Burn {
+        from_subaccount: Option<Subaccount>,
+        amount: Nat,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum Value {
+    Nat(Nat),
+    Int(Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct DeploymentFee {
+    pub ledger: Principal,
+    pub amount: Nat,
+    pub fee_to: Principal,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum DeployPolicy {
+    Open,
+    Allowlist,
+}
@@ -0,0 +1,163 @@
+#![cfg(feature = "pocket-ic")]
+
+use candid::{Nat, Principal, Reserved};
+use integration_tests::env::{admin, alice, bob, metadata, TestEnv};
+use integration_tests::types::{Account, DeployPolicy, DeploymentFee, TransferArgs, TransferError};
+
+fn create_token(env: &TestEnv, factory: Principal, caller: Principal, name: &str) -> Principal {
+    let (result,): (Result<Principal, Reserved>,) = env.update(
+        factory,
+        caller,
+        "create_token_for_tests",
+        (
+            metadata(name, caller, 0),
+            Nat::from(1000u64),
+            None::<Principal>,
+        ),
+    );
+    result.unwrap_or_else(|_| panic!("failed to create token {name}"))
+}
+
+#[test]
+fn deploy_token() {
+    let Some(env) = TestEnv::new() else { return };
+    let factory = env.install_factory();
+
+    let token = create_token(&env, factory, alice(), "deployed");
+    assert_eq!(
+        env.query_one::<Option<Principal>>(factory, "get_token", ("deployed".to_string(),)),
+        Some(token)
+    );
+    assert_eq!(
+        env.query_one::<String>(token, "icrc1_name", ()),
+        "deployed".to_string()
+    );
+    assert_eq!(env.balance_of(token, alice()), Nat::from(1000u64));
+}
+
+#[test]
+fn deployment_fee_is_charged() {
+    let Some(env) = TestEnv::new() else { return };
+    let factory = env.install_factory();
+
+    // Another token canister is used as the fee ledger.
+    let ledger_fee = 10u64;
+    let ledger = env.install_token(metadata("ledger", alice(), ledger_fee as u128), 10_000);
+    let deployment_fee = DeploymentFee {
+        ledger,
+        amount: 1000u64.into(),
+        fee_to: bob(),
+    };
+    let (result,): (Result<(), Reserved>,) = env.update(
+        factory,
+        admin(),
+        "set_deployment_fee",
+        (Some(deployment_fee.clone()),),
+    );
+    assert!(result.is_ok());
+
+    // Without the deposit the token is not created.
+    let (result,): (Result<Principal, Reserved>,) = env.update(
+        factory,
+        alice(),
+        "create_token_for_tests",
+        (
+            metadata("unpaid", alice(), 0),
+            Nat::from(0u64),
+            None::<Principal>,
+        ),
+    );
+    assert!(result.is_err());
+
+    let subaccount: [u8; 32] = env.query_one(factory, "get_deployment_fee_subaccount", (alice(),));
+    let deposit = TransferArgs::new(
+        Account {
+            owner: factory,
+            subaccount: Some(subaccount),
+        },
+        1000 + ledger_fee as u128,
+    );
+    let (result,): (Result<Nat, TransferError>,) =
+        env.update(ledger, alice(), "icrc1_transfer", (deposit,));
+    result.unwrap();
+
+    create_token(&env, factory, alice(), "paid");
+
+    // The fee minus the ledger transfer fee goes to the fee recipient.
+    assert_eq!(env.balance_of(ledger, bob()), Nat::from(1000 - ledger_fee));
+    assert_eq!(env.balance_of(ledger, factory), Nat::from(0u64));
+}
+
+#[test]
+fn deploy_allowlist() {
+    let Some(env) = TestEnv::new() else { return };
+    let factory = env.install_factory();
+
+    let (result,): (Result<(), Reserved>,) = env.update(
+        factory,
+        alice(),
+        "set_deploy_policy",
+        (DeployPolicy::Allowlist,),
+    );
+    assert!(result.is_err());
+    let (result,): (Result<(), Reserved>,) = env.update(
+        factory,
+        admin(),
+        "set_deploy_policy",
+        (DeployPolicy::Allowlist,),
+    );
+    assert!(result.is_ok());
+
+    let (result,): (Result<Principal, Reserved>,) = env.update(
+        factory,
+        alice(),
+        "create_token_for_tests",
+        (
+            metadata("not allowed", alice(), 0),
+            Nat::from(0u64),
+            None::<Principal>,
+        ),
+    );
+    assert!(result.is_err());
+
+    let (result,): (Result<(), Reserved>,) =
+        env.update(factory, admin(), "add_allowed_deployers", (vec![alice()],));
+    assert!(result.is_ok());
+    create_token(&env, factory, alice(), "allowed");
+
+    let deployers: Vec<(Principal, u64)> = env.query_one(factory, "get_allowed_deployers", ());
+    assert_eq!(
+        deployers.into_iter().map(|(p, _)| p).collect::<Vec<_>>(),
+        vec![alice()]
+    );
+}
+
+#[test]
+fn factory_upgrade_preserves_state() {
+    let Some(env) = TestEnv::new() else { return };
+    let factory = env.install_factory();
+    let token = create_token(&env, factory, alice(), "persistent");
+    let deployment_fee = DeploymentFee {
+        ledger: token,
+        amount: 1u64.into(),
+        fee_to: bob(),
+    };
+    let (result,): (Result<(), Reserved>,) = env.update(
+        factory,
+        admin(),
+        "set_deployment_fee",
+        (Some(deployment_fee.clone()),),
+    );
+    assert!(result.is_ok());
+
+    env.upgrade(factory, env.factory_wasm.clone());
+
+    assert_eq!(
+        env.query_one::<Option<Principal>>(factory, "get_token", ("persistent".to_string(),)),
+        Some(token)
+    );
+    assert_eq!(
+        env.query_one::<Option<DeploymentFee>>(factory, "get_deployment_fee", ()),
+        Some(deployment_fee)
+    );
+}
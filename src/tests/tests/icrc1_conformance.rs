@@ -0,0 +1,201 @@
+#![cfg(feature = "pocket-ic")]
+
+//! ICRC-1 conformance scenarios run against the compiled token wasm. See the
+//! `canister::icrc1_conformance` module of the token crate for the same scenarios run against the
+//! mock canister.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use candid::{Nat, Principal};
+use integration_tests::env::{admin, alice, bob, metadata, TestEnv};
+use integration_tests::types::{Account, StandardRecord, TransferArgs, TransferError, Value};
+
+const FEE: u128 = 10;
+const INITIAL_BALANCE: u128 = 1000;
+/// Deduplication window of the token: `TX_WINDOW + PERMITTED_DRIFT`.
+const DEDUP_WINDOW: Duration = Duration::from_secs(180);
+
+fn conformance_token(env: &TestEnv) -> Principal {
+    let token = env.install_token(metadata("conformance", admin(), FEE), 0);
+    mint(env, token, alice(), INITIAL_BALANCE);
+    token
+}
+
+fn transfer(
+    env: &TestEnv,
+    token: Principal,
+    caller: Principal,
+    args: TransferArgs,
+) -> Result<Nat, TransferError> {
+    let (result,): (Result<Nat, TransferError>,) =
+        env.update(token, caller, "icrc1_transfer", (args,));
+    result
+}
+
+/// Mints tokens with a transfer from the minting account.
+fn mint(env: &TestEnv, token: Principal, to: Principal, amount: u128) {
+    transfer(env, token, admin(), TransferArgs::new(to, amount)).expect("failed to mint");
+}
+
+fn now_nanos(env: &TestEnv) -> u64 {
+    env.pic
+        .get_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+#[test]
+fn metadata_matches_token_info() {
+    let Some(env) = TestEnv::new() else { return };
+    let token = conformance_token(&env);
+
+    let metadata: Vec<(String, Value)> = env.query_one(token, "icrc1_metadata", ());
+    let entry = |key: &str| {
+        metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    };
+    assert_eq!(
+        entry("icrc1:name"),
+        Some(Value::Text(env.query_one(token, "icrc1_name", ())))
+    );
+    assert_eq!(
+        entry("icrc1:symbol"),
+        Some(Value::Text(env.query_one(token, "icrc1_symbol", ())))
+    );
+    assert_eq!(
+        entry("icrc1:decimals"),
+        Some(Value::Nat(Nat::from(env.query_one::<u8>(
+            token,
+            "icrc1_decimals",
+            ()
+        ))))
+    );
+    assert_eq!(entry("icrc1:fee"), Some(Value::Nat(Nat::from(FEE))));
+
+    let standards: Vec<StandardRecord> = env.query_one(token, "icrc1_supported_standards", ());
+    assert!(standards.iter().any(|standard| standard.name == "ICRC-1"));
+}
+
+#[test]
+fn transfer_charges_fee() {
+    let Some(env) = TestEnv::new() else { return };
+    let token = conformance_token(&env);
+
+    transfer(&env, token, alice(), TransferArgs::new(bob(), 100)).unwrap();
+    assert_eq!(
+        env.balance_of(token, alice()),
+        Nat::from(INITIAL_BALANCE - 100 - FEE)
+    );
+    assert_eq!(env.balance_of(token, bob()), Nat::from(100u64));
+
+    let mut args = TransferArgs::new(bob(), 100);
+    args.fee = Some(Nat::from(FEE));
+    transfer(&env, token, alice(), args).unwrap();
+}
+
+#[test]
+fn invalid_transfers_are_rejected() {
+    let Some(env) = TestEnv::new() else { return };
+    let token = conformance_token(&env);
+    let now = now_nanos(&env);
+
+    let mut bad_fee = TransferArgs::new(bob(), 100);
+    bad_fee.fee = Some(Nat::from(FEE + 1));
+    assert_eq!(
+        transfer(&env, token, alice(), bad_fee),
+        Err(TransferError::BadFee {
+            expected_fee: Nat::from(FEE)
+        })
+    );
+
+    assert_eq!(
+        transfer(
+            &env,
+            token,
+            alice(),
+            TransferArgs::new(bob(), INITIAL_BALANCE)
+        ),
+        Err(TransferError::InsufficientFunds {
+            balance: Nat::from(INITIAL_BALANCE)
+        })
+    );
+
+    let mut in_future = TransferArgs::new(bob(), 100);
+    in_future.created_at_time = Some(now + 2 * DEDUP_WINDOW.as_nanos() as u64);
+    assert!(matches!(
+        transfer(&env, token, alice(), in_future),
+        Err(TransferError::CreatedInFuture { .. })
+    ));
+
+    let mut long_memo = TransferArgs::new(bob(), 100);
+    long_memo.memo = Some(vec![0; 33]);
+    assert!(matches!(
+        transfer(&env, token, alice(), long_memo),
+        Err(TransferError::GenericError { .. })
+    ));
+
+    let mut too_old = TransferArgs::new(bob(), 100);
+    too_old.created_at_time = Some(now);
+    env.pic.advance_time(DEDUP_WINDOW);
+    assert_eq!(
+        transfer(&env, token, alice(), too_old),
+        Err(TransferError::TooOld)
+    );
+
+    assert_eq!(env.balance_of(token, alice()), Nat::from(INITIAL_BALANCE));
+}
+
+#[test]
+fn transfers_are_deduplicated() {
+    let Some(env) = TestEnv::new() else { return };
+    let token = conformance_token(&env);
+
+    let mut args = TransferArgs::new(bob(), 100);
+    args.created_at_time = Some(now_nanos(&env));
+    let id = transfer(&env, token, alice(), args.clone()).unwrap();
+    assert_eq!(
+        transfer(&env, token, alice(), args.clone()),
+        Err(TransferError::Duplicate { duplicate_of: id })
+    );
+
+    let mut with_memo = args.clone();
+    with_memo.memo = Some(vec![1]);
+    transfer(&env, token, alice(), with_memo).unwrap();
+
+    transfer(&env, token, alice(), TransferArgs::new(bob(), 100)).unwrap();
+    transfer(&env, token, alice(), TransferArgs::new(bob(), 100)).unwrap();
+    assert_eq!(env.balance_of(token, bob()), Nat::from(400u64));
+}
+
+#[test]
+fn minting_account_mints_and_burns() {
+    let Some(env) = TestEnv::new() else { return };
+    let token = conformance_token(&env);
+
+    let minter: Option<Account> = env.query_one(token, "icrc1_minting_account", ());
+    assert_eq!(minter, Some(Account::from(admin())));
+    let supply: Nat = env.query_one(token, "icrc1_total_supply", ());
+    assert_eq!(supply, Nat::from(INITIAL_BALANCE));
+
+    transfer(&env, token, alice(), TransferArgs::new(admin(), 40)).unwrap();
+    assert_eq!(
+        env.balance_of(token, alice()),
+        Nat::from(INITIAL_BALANCE - 40)
+    );
+    assert_eq!(
+        env.query_one::<Nat>(token, "icrc1_total_supply", ()),
+        Nat::from(INITIAL_BALANCE - 40)
+    );
+
+    let mut burn_with_fee = TransferArgs::new(admin(), 40);
+    burn_with_fee.fee = Some(Nat::from(FEE));
+    assert_eq!(
+        transfer(&env, token, alice(), burn_with_fee),
+        Err(TransferError::BadFee {
+            expected_fee: Nat::from(0u64)
+        })
+    );
+}
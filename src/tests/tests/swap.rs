@@ -0,0 +1,92 @@
+#![cfg(feature = "pocket-ic")]
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use candid::{Nat, Principal, Reserved};
+use integration_tests::env::{alice, bob, metadata, TestEnv};
+use integration_tests::types::{Account, TransferArgs, TransferError};
+
+fn now_nanos(env: &TestEnv) -> u64 {
+    env.pic
+        .get_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// Creates a swap of 100 tokens of `token_a` owned by alice for 200 tokens of `token_b`.
+fn create_swap(env: &TestEnv, token_a: Principal, token_b: Principal, deadline: u64) -> u64 {
+    let (result,): (Result<u64, Reserved>,) = env.update(
+        token_a,
+        alice(),
+        "create_swap",
+        (
+            bob(),
+            Nat::from(100u64),
+            token_b,
+            Nat::from(200u64),
+            deadline,
+            None::<[u8; 32]>,
+        ),
+    );
+    result.unwrap_or_else(|_| panic!("failed to create the swap"))
+}
+
+fn deposit(env: &TestEnv, token_a: Principal, token_b: Principal, id: u64) {
+    let escrow: Account = env.query_one(token_a, "get_swap_escrow", (id,));
+    let (result,): (Result<Nat, TransferError>,) = env.update(
+        token_b,
+        bob(),
+        "icrc1_transfer",
+        (TransferArgs::new(escrow, 200),),
+    );
+    result.unwrap();
+}
+
+#[test]
+fn accept_swap() {
+    let Some(env) = TestEnv::new() else { return };
+    let token_a = env.install_token(metadata("a", alice(), 0), 1000);
+    let token_b = env.install_token(metadata("b", bob(), 0), 1000);
+
+    let deadline = now_nanos(&env) + Duration::from_secs(3600).as_nanos() as u64;
+    let id = create_swap(&env, token_a, token_b, deadline);
+    assert_eq!(env.balance_of(token_a, alice()), Nat::from(900u64));
+
+    // The swap cannot be accepted before the deposit is made.
+    let (result,): (Result<(), Reserved>,) = env.update(token_a, bob(), "accept_swap", (id,));
+    assert!(result.is_err());
+
+    deposit(&env, token_a, token_b, id);
+    let (result,): (Result<(), Reserved>,) = env.update(token_a, bob(), "accept_swap", (id,));
+    assert!(result.is_ok());
+
+    assert_eq!(env.balance_of(token_a, bob()), Nat::from(100u64));
+    assert_eq!(env.balance_of(token_b, alice()), Nat::from(200u64));
+    assert_eq!(env.balance_of(token_b, bob()), Nat::from(800u64));
+}
+
+#[test]
+fn refund_swap_after_deadline() {
+    let Some(env) = TestEnv::new() else { return };
+    let token_a = env.install_token(metadata("a", alice(), 0), 1000);
+    let token_b = env.install_token(metadata("b", bob(), 0), 1000);
+
+    let deadline = now_nanos(&env) + Duration::from_secs(60).as_nanos() as u64;
+    let id = create_swap(&env, token_a, token_b, deadline);
+    deposit(&env, token_a, token_b, id);
+
+    let (result,): (Result<(), Reserved>,) = env.update(token_a, alice(), "refund_swap", (id,));
+    assert!(result.is_err());
+
+    env.pic.advance_time(Duration::from_secs(120));
+    let (result,): (Result<(), Reserved>,) = env.update(token_a, alice(), "refund_swap", (id,));
+    assert!(result.is_ok());
+
+    assert_eq!(env.balance_of(token_a, alice()), Nat::from(1000u64));
+    assert_eq!(env.balance_of(token_b, bob()), Nat::from(1000u64));
+
+    // The expired swap cannot be accepted.
+    let (result,): (Result<(), Reserved>,) = env.update(token_a, bob(), "accept_swap", (id,));
+    assert!(result.is_err());
+}
@@ -0,0 +1,124 @@
+#![cfg(feature = "pocket-ic")]
+
+use candid::{Nat, Reserved};
+use integration_tests::env::{alice, bob, metadata, user, TestEnv};
+use integration_tests::types::{Account, BatchOperation, TransferArgs, TransferError};
+
+#[test]
+fn icrc1_transfer() {
+    let Some(env) = TestEnv::new() else { return };
+    let token = env.install_token(metadata("icrc1", alice(), 10), 1000);
+
+    assert_eq!(
+        env.query_one::<Nat>(token, "icrc1_fee", ()),
+        Nat::from(10u64)
+    );
+    assert_eq!(
+        env.query_one::<Nat>(token, "icrc1_total_supply", ()),
+        Nat::from(1000u64)
+    );
+
+    let (result,): (Result<Nat, TransferError>,) = env.update(
+        token,
+        alice(),
+        "icrc1_transfer",
+        (TransferArgs::new(bob(), 100),),
+    );
+    result.unwrap();
+    assert_eq!(env.balance_of(token, bob()), Nat::from(100u64));
+
+    let mut transfer = TransferArgs::new(bob(), 100);
+    transfer.fee = Some(Nat::from(1u64));
+    let (result,): (Result<Nat, TransferError>,) =
+        env.update(token, alice(), "icrc1_transfer", (transfer,));
+    assert_eq!(
+        result,
+        Err(TransferError::BadFee {
+            expected_fee: Nat::from(10u64)
+        })
+    );
+
+    let (result,): (Result<Nat, TransferError>,) = env.update(
+        token,
+        bob(),
+        "icrc1_transfer",
+        (TransferArgs::new(alice(), 1000),),
+    );
+    assert_eq!(
+        result,
+        Err(TransferError::InsufficientFunds {
+            balance: Nat::from(100u64)
+        })
+    );
+
+    let subaccount = Account {
+        owner: bob(),
+        subaccount: Some([1; 32]),
+    };
+    assert_eq!(
+        env.query_one::<Nat>(token, "icrc1_balance_of", (subaccount,)),
+        Nat::from(0u64)
+    );
+}
+
+#[test]
+fn token_upgrade_preserves_state() {
+    let Some(env) = TestEnv::new() else { return };
+    let token = env.install_token(metadata("upgraded", alice(), 0), 1000);
+    let (result,): (Result<Nat, TransferError>,) = env.update(
+        token,
+        alice(),
+        "icrc1_transfer",
+        (TransferArgs::new(bob(), 300),),
+    );
+    result.unwrap();
+
+    env.upgrade(token, env.token_wasm.clone());
+
+    assert_eq!(
+        env.query_one::<String>(token, "icrc1_name", ()),
+        "upgraded".to_string()
+    );
+    assert_eq!(env.balance_of(token, alice()), Nat::from(700u64));
+    assert_eq!(env.balance_of(token, bob()), Nat::from(300u64));
+}
+
+/// Benchmark of an airdrop: 10k mints in one `atomic_batch` call must fit in the per-call
+/// instruction limit. The cycles spent by the call, which are proportional to the executed
+/// instructions, are printed to compare the changes of the ledger write path (run with
+/// `--nocapture`).
+#[test]
+fn airdrop_of_10k_mints_fits_in_one_call() {
+    const RECIPIENTS: u32 = 10_000;
+
+    let Some(env) = TestEnv::new() else { return };
+    let token = env.install_token(metadata("airdrop", alice(), 0), 0);
+    let operations = (0..RECIPIENTS)
+        .map(|index| {
+            let mut subaccount = [0; 32];
+            subaccount[..4].copy_from_slice(&index.to_be_bytes());
+            BatchOperation::Mint {
+                to: Account {
+                    owner: user(100),
+                    subaccount: Some(subaccount),
+                },
+                amount: Nat::from(1u64),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let cycles_before = env.pic.cycle_balance(token);
+    let (result,): (Result<Vec<Nat>, Reserved>,) =
+        env.update(token, alice(), "atomic_batch", (operations,));
+    let spent = cycles_before - env.pic.cycle_balance(token);
+
+    assert_eq!(result.unwrap().len(), RECIPIENTS as usize);
+    assert_eq!(
+        env.query_one::<Nat>(token, "icrc1_total_supply", ()),
+        Nat::from(RECIPIENTS)
+    );
+    println!(
+        "airdrop of {RECIPIENTS} mints: {spent} cycles, {} per mint",
+        spent / RECIPIENTS as u128
+    );
+}
@@ -0,0 +1,63 @@
+//! Benchmarks of the batch transfers over the stable balances storage with a large number of
+//! holders. The time of a batch transfer must depend on the batch size only, not on the number of
+//! token holders.
+
+use candid::Principal;
+use canister_sdk::ic_kit::mock_principals::alice;
+use canister_sdk::ic_kit::MockContext;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use is20_token::account::AccountInternal;
+use is20_token::canister::is20_transactions::batch_transfer;
+use is20_token::state::balances::{Balances, StableBalances};
+use is20_token::state::config::TokenConfig;
+use is20_token::state::ledger::{BatchTransferArgs, LedgerData};
+
+const HOLDERS: u64 = 10_000;
+
+fn holder(index: u64) -> Principal {
+    Principal::from_slice(&index.to_be_bytes())
+}
+
+fn setup() {
+    MockContext::new().with_caller(alice()).inject();
+    TokenConfig::set_stable(TokenConfig::default());
+    StableBalances.clear();
+    LedgerData::clear();
+
+    StableBalances.insert(AccountInternal::from(alice()), (u64::MAX as u128).into());
+    for index in 0..HOLDERS {
+        StableBalances.insert(AccountInternal::from(holder(index)), 10.into());
+    }
+}
+
+fn transfers(count: u64) -> Vec<BatchTransferArgs> {
+    (0..count)
+        .map(|index| BatchTransferArgs {
+            receiver: holder(index * (HOLDERS / count)).into(),
+            amount: 1.into(),
+        })
+        .collect()
+}
+
+fn bench_batch_transfer(c: &mut Criterion) {
+    setup();
+
+    let mut group = c.benchmark_group("batch_transfer");
+    for count in [100, 1000] {
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.iter_batched(
+                || {
+                    LedgerData::clear();
+                    transfers(count)
+                },
+                |transfers| batch_transfer(None, transfers, 0.0).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_batch_transfer);
+criterion_main!(benches);
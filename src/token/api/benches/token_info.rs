@@ -0,0 +1,35 @@
+//! Benchmarks of the holders count used by `get_token_info`. The time must not depend on the
+//! number of token holders.
+
+use candid::Principal;
+use canister_sdk::ic_kit::MockContext;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use is20_token::account::AccountInternal;
+use is20_token::state::balances::{Balances, StableBalances};
+
+fn holder(index: u64) -> Principal {
+    Principal::from_slice(&index.to_be_bytes())
+}
+
+fn setup(holders: u64) {
+    MockContext::new().inject();
+    StableBalances.clear();
+    for index in 0..holders {
+        StableBalances.insert(AccountInternal::from(holder(index)), 10.into());
+    }
+}
+
+fn bench_holders_count(c: &mut Criterion) {
+    let mut group = c.benchmark_group("holders_count");
+    for holders in [100, 10_000] {
+        setup(holders);
+        group.bench_with_input(BenchmarkId::from_parameter(holders), &holders, |b, _| {
+            b.iter(|| StableBalances.holders_count())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_holders_count);
+criterion_main!(benches);
@@ -89,6 +89,16 @@ impl Display for AccountInternal {
 
 pub type Subaccount = [u8; 32];
 
+/// Subaccount of the token canister the `owner` deposits to in other ledgers. The subaccount
+/// contains the length and the bytes of the owner principal, so it is unique for every owner.
+pub fn deposit_subaccount(owner: Principal) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    let principal_id = owner.as_slice();
+    subaccount[0] = principal_id.len() as u8;
+    subaccount[1..1 + principal_id.len()].copy_from_slice(principal_id);
+    subaccount
+}
+
 pub struct CheckedAccount<T>(AccountInternal, T);
 
 impl<T> CheckedAccount<T> {
@@ -106,14 +116,27 @@ impl CheckedAccount<WithRecipient> {
         recipient: AccountInternal,
         from_subaccount: Option<Subaccount>,
     ) -> Result<Self, TxError> {
-        let caller = canister_sdk::ic_kit::ic::caller();
-        let from = AccountInternal::new(caller, from_subaccount);
+        Self::with_recipient_of(
+            canister_sdk::ic_kit::ic::caller(),
+            recipient,
+            from_subaccount,
+        )
+    }
+
+    /// Same as `with_recipient`, but for the account of the given `owner` instead of the caller.
+    pub fn with_recipient_of(
+        owner: Principal,
+        recipient: AccountInternal,
+        from_subaccount: Option<Subaccount>,
+    ) -> Result<Self, TxError> {
+        let from = AccountInternal::new(owner, from_subaccount);
         if recipient == from {
             Err(TxError::SelfTransfer)
         } else {
             Ok(Self(from, WithRecipient { recipient }))
         }
     }
+
     pub fn recipient(&self) -> AccountInternal {
         self.1.recipient
     }
@@ -0,0 +1,107 @@
+//! Conversion between the token amounts and their decimal representation, e.g. `"1.5"` is
+//! `150_000_000` for a token with 8 decimals.
+
+use canister_sdk::ic_helpers::tokens::Tokens128;
+
+use crate::error::TxError;
+
+/// Parses the decimal representation of an amount of a token with `decimals` decimals.
+///
+/// Only the plain notation is accepted: ASCII digits with an optional dot followed by at most
+/// `decimals` digits. Signs, exponents, separators and whitespace are rejected, as well as the
+/// amounts exceeding `u128::MAX` units.
+pub fn parse_amount(text: &str, decimals: u8) -> Result<Tokens128, TxError> {
+    let invalid = |reason: &str| TxError::InvalidAmount {
+        reason: reason.into(),
+    };
+
+    let (integer, fraction) = match text.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (text, None),
+    };
+    if integer.is_empty() {
+        return Err(invalid("integer part is empty"));
+    }
+    if fraction == Some("") {
+        return Err(invalid("fractional part is empty"));
+    }
+
+    let fraction = fraction.unwrap_or_default();
+    if fraction.len() > decimals as usize {
+        return Err(invalid(&format!("more than {decimals} fractional digits")));
+    }
+
+    let padding = std::iter::repeat(b'0').take(decimals as usize - fraction.len());
+    let mut units: u128 = 0;
+    for digit in integer.bytes().chain(fraction.bytes()).chain(padding) {
+        if !digit.is_ascii_digit() {
+            return Err(invalid("only digits and one dot are allowed"));
+        }
+
+        units = units
+            .checked_mul(10)
+            .and_then(|units| units.checked_add((digit - b'0') as u128))
+            .ok_or(TxError::AmountOverflow)?;
+    }
+
+    Ok(units.into())
+}
+
+/// Formats the `amount` of a token with `decimals` decimals, without the trailing zeros of the
+/// fractional part. The result is accepted by `parse_amount`.
+pub fn format_amount(amount: Tokens128, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    let digits = format!("{:0>width$}", amount.amount, width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{integer}.{fraction}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_amount("1.5", 8), Ok(150_000_000.into()));
+        assert_eq!(parse_amount("0.00000001", 8), Ok(1.into()));
+        assert_eq!(parse_amount("42", 0), Ok(42.into()));
+        assert_eq!(parse_amount("007", 2), Ok(700.into()));
+        assert_eq!(
+            parse_amount(&u128::MAX.to_string(), 0),
+            Ok(u128::MAX.into())
+        );
+
+        for invalid in [
+            "", ".5", "1.", "1.2.3", "-1", "+1", "1e8", "1 000", "1,5", " 1",
+        ] {
+            assert!(
+                matches!(parse_amount(invalid, 8), Err(TxError::InvalidAmount { .. })),
+                "{invalid:?} is accepted"
+            );
+        }
+        assert!(matches!(
+            parse_amount("0.001", 2),
+            Err(TxError::InvalidAmount { .. })
+        ));
+        assert_eq!(parse_amount("1", 39), Err(TxError::AmountOverflow));
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_amount(150_000_000.into(), 8), "1.5");
+        assert_eq!(format_amount(1.into(), 8), "0.00000001");
+        assert_eq!(format_amount(0.into(), 8), "0");
+        assert_eq!(format_amount(100.into(), 2), "1");
+        assert_eq!(format_amount(42.into(), 0), "42");
+
+        let amount = Tokens128::from(u128::MAX);
+        assert_eq!(parse_amount(&format_amount(amount, 18), 18), Ok(amount));
+    }
+}
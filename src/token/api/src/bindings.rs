@@ -0,0 +1,173 @@
+//! Client bindings generated from the candid interface of the canisters.
+//!
+//! The token and the factory crates print their interface with the `*-bindings` binaries in one
+//! of the `BindingsFormat`s, so the front-end clients can be regenerated on every release instead
+//! of being maintained by hand. The JSON ABI lists the methods with their candid types and the
+//! annotations the candid interface can't express, e.g. that a method is callable by the owner
+//! only.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use candid::types::{FuncMode, Type};
+use candid::TypeEnv;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingsFormat {
+    Candid,
+    Javascript,
+    Typescript,
+    Json,
+}
+
+impl FromStr for BindingsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "did" | "candid" => Ok(Self::Candid),
+            "js" | "javascript" => Ok(Self::Javascript),
+            "ts" | "typescript" => Ok(Self::Typescript),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown bindings format \"{s}\", expected one of: did, js, ts, json"
+            )),
+        }
+    }
+}
+
+/// Methods of the interface sharing an annotation, e.g. the methods callable by the owner only.
+pub struct Annotation {
+    pub name: &'static str,
+    pub methods: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AbiMethod {
+    pub name: String,
+    /// `query`, `update` or `oneway`.
+    pub mode: &'static str,
+    /// Candid types of the arguments.
+    pub args: Vec<String>,
+    /// Candid types of the results.
+    pub rets: Vec<String>,
+    pub annotations: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Abi {
+    pub methods: Vec<AbiMethod>,
+    /// Candid definitions of the named types used by the methods.
+    pub types: BTreeMap<String, String>,
+}
+
+impl Abi {
+    pub fn new(env: &TypeEnv, actor: &Type, annotations: &[Annotation]) -> Self {
+        let methods = env
+            .as_service(actor)
+            .expect("actor must be a service")
+            .iter()
+            .map(|(name, func)| {
+                let func = env
+                    .as_func(func)
+                    .expect("service method must be a function");
+                let mode = if func.modes.contains(&FuncMode::Query) {
+                    "query"
+                } else if func.modes.contains(&FuncMode::Oneway) {
+                    "oneway"
+                } else {
+                    "update"
+                };
+
+                AbiMethod {
+                    name: name.clone(),
+                    mode,
+                    args: func.args.iter().map(ToString::to_string).collect(),
+                    rets: func.rets.iter().map(ToString::to_string).collect(),
+                    annotations: annotations
+                        .iter()
+                        .filter(|annotation| annotation.methods.contains(&name.as_str()))
+                        .map(|annotation| annotation.name)
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Self {
+            methods,
+            types: env
+                .0
+                .iter()
+                .map(|(name, ty)| (name.clone(), ty.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Renders the interface of the `actor` in the `format`. The `annotations` are used by the JSON
+/// ABI only.
+pub fn render(
+    env: &TypeEnv,
+    actor: Type,
+    format: BindingsFormat,
+    annotations: &[Annotation],
+) -> String {
+    match format {
+        BindingsFormat::Candid => candid::bindings::candid::compile(env, &Some(actor)),
+        BindingsFormat::Javascript => candid::bindings::javascript::compile(env, &Some(actor)),
+        BindingsFormat::Typescript => candid::bindings::typescript::compile(env, &Some(actor)),
+        BindingsFormat::Json => serde_json::to_string_pretty(&Abi::new(env, &actor, annotations))
+            .expect("failed to serialize abi"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::types::Function;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn abi_lists_annotated_methods() {
+        let mut env = TypeEnv::new();
+        env.0.insert("Amount".into(), Type::Nat);
+        let func = |modes: Vec<FuncMode>| {
+            Type::Func(Function {
+                modes,
+                args: vec![Type::Var("Amount".into())],
+                rets: vec![Type::Bool],
+            })
+        };
+        let actor = Type::Service(vec![
+            ("get".into(), func(vec![FuncMode::Query])),
+            ("set".into(), func(vec![])),
+        ]);
+
+        let abi = Abi::new(
+            &env,
+            &actor,
+            &[Annotation {
+                name: "owner_only",
+                methods: &["set"],
+            }],
+        );
+        assert_eq!(abi.types.get("Amount"), Some(&"nat".to_string()));
+        assert_eq!(
+            abi.methods[1],
+            AbiMethod {
+                name: "set".into(),
+                mode: "update",
+                args: vec!["Amount".into()],
+                rets: vec!["bool".into()],
+                annotations: vec!["owner_only"],
+            }
+        );
+        assert_eq!(abi.methods[0].mode, "query");
+        assert!(abi.methods[0].annotations.is_empty());
+
+        assert_eq!("ts".parse(), Ok(BindingsFormat::Typescript));
+        assert!("wasm".parse::<BindingsFormat>().is_err());
+    }
+}
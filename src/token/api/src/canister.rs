@@ -6,38 +6,142 @@ use canister_sdk::ic_auction::{
     state::{AuctionInfo, AuctionState},
 };
 use canister_sdk::ic_canister::{
-    generate_exports, generate_idl, query, update, Canister, Idl, PreUpdate,
+    generate_exports, generate_idl, query, update, AsyncReturn, Canister, Idl, PreUpdate,
 };
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use canister_sdk::ic_kit::ic;
 pub use inspect::AcceptReason;
 
 use self::is20_transactions::{
-    batch_transfer, burn_as_owner, burn_own_tokens, is20_transfer, mint_as_owner, mint_test_token,
+    atomic_batch, batch_transfer, burn_as_owner, burn_own_tokens, complete_recovery,
+    confirm_large_transfer, is20_transfer, mint_as_owner, mint_test_token, validate_memo,
 };
 #[cfg(feature = "claim")]
 use self::is20_transactions::{claim, get_claim_subaccount};
 use crate::account::{Account, AccountInternal, CheckedAccount, Subaccount};
+use crate::amount;
+use crate::canister::account_purge::PurgeReport;
+use crate::canister::certified_balances::{self, CertifiedBalance};
+use crate::canister::exchange_deposits::{SweepResult, SweepSelection};
+use crate::canister::export::{ExportFormat, HoldersExport, TransactionsExport};
+use crate::canister::handover::HandoverSummary;
+use crate::canister::http::{HttpRequest, HttpResponse};
 use crate::canister::icrc1_transfer::icrc1_transfer;
+#[cfg(feature = "auction")]
+use crate::canister::is20_auction::{AuctionProjection, BidStatus, CycleConsumption};
+use crate::canister::ledger_compat::{
+    GetBlocksArgs, QueryBlocksResponse, QueryEncodedBlocksResponse,
+};
+use crate::canister::permit::Permit;
+use crate::canister::signed_transfer::SignedTransfer;
+use crate::canister::status_report::CanisterStatusReport;
 use crate::error::{TransferError, TxError};
 use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::account_ids::AccountIds;
+use crate::state::account_tags::{AccountTags, TaggedSubaccount};
+use crate::state::admin_log::{AdminAction, AdminLog, AdminLogEntry};
+use crate::state::allowances::{
+    AllowanceEntry, AllowanceSweepStats, Allowances, ApprovalNotification, ApprovalNotificationId,
+};
+#[cfg(feature = "auction")]
+use crate::state::auction_history::{AuctionHistory, AuctionsPage, ResidualWithdrawal};
+#[cfg(feature = "auction")]
+use crate::state::auction_policy::AuctionPolicy;
 use crate::state::balances::{Balances, StableBalances};
-use crate::state::config::{StandardRecord, Timestamp, TokenConfig, TokenInfo, Value};
+#[cfg(feature = "auction")]
+use crate::state::bid_delegates::{BidDelegates, BidDelegation};
+use crate::state::budgets::{Budget, BudgetReport, Budgets};
+#[cfg(feature = "auction")]
+use crate::state::config::MAX_AUCTION_VESTING_DAYS;
+use crate::state::config::{
+    AuctionStrategy, BurnPolicy, InputLimits, LocalizedMetadata, MetadataPatch,
+    RenounceOwnershipArgs, StandardRecord, Timestamp, TokenConfig, TokenInfo, TxWindow, Value,
+    LOGO_METADATA_KEY,
+};
+use crate::state::decimals::DecimalsMigration;
+use crate::state::dust::{DustPolicy, DustReport, DustReports};
+use crate::state::expiring_transfers::{ExpiringTransfer, ExpiringTransferId, ExpiringTransfers};
+use crate::state::export_sessions::{ExportSession, ExportSessionId, ExportSessions};
+use crate::state::faucet::{Faucet, FaucetPolicy, FaucetStatus};
+use crate::state::fee_recipients::{FeeDistribution, FeeRecipient, FeeRecipients};
+use crate::state::fees::FeePolicy;
+use crate::state::frozen::{FreezeMode, FrozenAccounts};
+use crate::state::guardians::{
+    Guardian, Guardians, Recovery, RecoveryAction, RecoveryLog, RecoveryLogEntry,
+};
+use crate::state::icp_bridge::IcpAccountId;
+#[cfg(feature = "icp_bridge")]
+use crate::state::icp_bridge::{BlockIndex, BridgeOperation, DepositStatus, IcpBridge};
+use crate::state::invariants::{Invariants, InvariantsReport};
+use crate::state::large_transfers::{
+    LargeTransferPolicy, LargeTransfers, PendingTransfer, PendingTransferId,
+};
 use crate::state::ledger::{
-    BatchTransferArgs, LedgerData, PaginatedResult, TransferArgs, TxReceipt,
+    BatchOperation, BatchTransferArgs, ChainVerification, CompactionReport, GetAccountTransactions,
+    GetAccountTransactionsArgs, HistoryInfo, LedgerData, LedgerTip, MaintenanceStatus, Memo,
+    PaginatedResult, RetentionPolicy, TransferArgs, TxReceipt, MAX_VERIFIED_RECORDS,
+};
+use crate::state::memory::MemoryReport;
+use crate::state::merchants::{MerchantAuthorization, MerchantAuthorizations};
+use crate::state::metrics::{EndpointMetrics, EndpointStatsReport, TokenMetrics};
+use crate::state::migrations::{StateMigrationStatus, StateMigrations, CALL_INSTRUCTION_LIMIT};
+use crate::state::nonces::{PermitNonces, TransferNonces};
+use crate::state::outbound::{MessageId, Outbound, OutboundMessage, OutboundStatus};
+use crate::state::payment_requests::{PaymentRequest, PaymentRequestId, PaymentRequests};
+use crate::state::payment_subscriptions::{
+    PaymentSubscription, PaymentSubscriptions, SubscriptionId,
+};
+use crate::state::snapshots::{Snapshot, SnapshotId, Snapshots};
+use crate::state::standing_orders::{StandingOrder, StandingOrderId, StandingOrders};
+use crate::state::subscriptions::{
+    EventFilter, EventSubscriptions, Subscription, EVENT_SUBSCRIPTION_FEE_CYCLES,
 };
+use crate::state::swaps::{escrow_subaccount, Swap, SwapId, Swaps};
+use crate::state::timelock::{ConfigChange, PendingChange, ProposalId, Timelock};
+use crate::state::upgrade_chunks::{UpgradeChunks, UpgradeModuleStatus};
+#[cfg(feature = "auction")]
+use crate::state::vesting::{Vesting, VestingBalance};
+#[cfg(feature = "icrc1_wrapper")]
+use crate::state::wrapper::{IcrcWrapper, WrapperOperation};
 use crate::tx_record::{TxId, TxRecord};
 
-mod inspect;
+#[cfg(test)]
+mod icrc1_conformance;
 
+pub mod account_purge;
+pub mod approvals;
+pub mod certified_balances;
+pub mod controllers;
+pub mod cycles;
+pub mod dust;
+pub mod exchange_deposits;
+pub mod expiring_transfers;
+pub mod export;
+pub mod handover;
+pub mod http;
+#[cfg(feature = "icp_bridge")]
+pub mod icp_bridge;
 pub mod icrc1_transfer;
+pub mod inspect;
 
 #[cfg(feature = "auction")]
 pub mod is20_auction;
 pub mod is20_transactions;
+pub mod ledger_compat;
+pub mod outbound;
+pub mod permit;
+pub mod self_upgrade;
+pub mod signed_transfer;
+pub mod standing_orders;
+pub mod status_report;
+pub mod swaps;
+pub mod timelock;
+#[cfg(feature = "icrc1_wrapper")]
+pub mod wrapper;
 
 pub(crate) const MAX_TRANSACTION_REQUEST: usize = 2000;
 pub(crate) const MAX_ACCOUNT_TRANSACTION_REQUEST: usize = 1000;
+pub(crate) const MAX_BALANCES_REQUEST: usize = 1000;
 // 1 day in seconds.
 pub const DEFAULT_AUCTION_PERIOD_SECONDS: Timestamp = 60 * 60 * 24;
 
@@ -47,7 +151,28 @@ pub enum CanisterUpdate {
     Fee(Tokens128),
     FeeTo(Principal),
     Owner(Principal),
+    RenounceOwnership(Principal, bool),
     MinCycles(u64),
+    MetadataEntry(String, Value),
+    RemoveMetadataEntry(String),
+    LocalizedMetadata(String, Option<LocalizedMetadata>),
+    MintingAccount(Account),
+    HistoryRetention(Option<RetentionPolicy>),
+    IcpLedger(Option<Principal>),
+    WrappedToken(Option<Principal>),
+    LargeTransferPolicy(Option<LargeTransferPolicy>),
+    FaucetPolicy(Option<FaucetPolicy>),
+    DustPolicy(Option<DustPolicy>),
+    TxWindow(TxWindow),
+    AuctionStrategy(AuctionStrategy),
+    MemoIndex(bool),
+    AuctionRetention(Option<RetentionPolicy>),
+    AuctionVesting(Option<u32>),
+    TimelockDelay(Option<u64>),
+    BurnPolicy(Option<BurnPolicy>),
+    FeePolicy(Option<FeePolicy>),
+    VotingExclusions(Vec<Principal>),
+    InputLimits(InputLimits),
 }
 
 #[cfg(not(feature = "auction"))]
@@ -72,6 +197,19 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
         TokenConfig::get_stable().is_test_token
     }
 
+    /// Converts the decimal representation of an amount, e.g. `"1.5"`, to the token units using
+    /// the token decimals.
+    #[query(trait = true)]
+    fn parse_amount(&self, text: String) -> Result<Tokens128, TxError> {
+        amount::parse_amount(&text, TokenConfig::get_stable().decimals)
+    }
+
+    /// Returns the decimal representation of the `amount` of the token units.
+    #[query(trait = true)]
+    fn format_amount(&self, amount: Tokens128) -> String {
+        amount::format_amount(amount, TokenConfig::get_stable().decimals)
+    }
+
     #[query(trait = true)]
     fn icrc1_total_supply(&self) -> Tokens128 {
         StableBalances.total_supply()
@@ -82,6 +220,124 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
         TokenConfig::get_stable().owner
     }
 
+    /// Sends the cycles of the token, except for a small reserve, to the canister which installed
+    /// the token. Used by the factory before the token canister is deleted. Returns the amount of
+    /// the sent cycles.
+    #[update(trait = true)]
+    fn withdraw_cycles_to_deployer<'a>(&'a self) -> AsyncReturn<'a, Result<u64, TxError>> {
+        let caller = ic::caller();
+        Box::pin(async move { cycles::withdraw_to_deployer(caller).await })
+    }
+
+    /// Returns the controllers of the token canister. The token must be one of them.
+    #[update(trait = true)]
+    fn list_controllers<'a>(&'a self) -> AsyncReturn<'a, Result<Vec<Principal>, TxError>> {
+        Box::pin(async move { controllers::list_controllers().await })
+    }
+
+    /// Returns the module hash, the controllers, the freezing threshold and the memory size of the
+    /// token canister, as fetched by the token from the management canister at `fetched_at`.
+    #[query(trait = true)]
+    fn get_canister_status_report(&self) -> Option<CanisterStatusReport> {
+        status_report::status_report()
+    }
+
+    /// Adds a controller to the token canister. Returns the new list of the controllers.
+    #[update(trait = true)]
+    fn add_controller<'a>(
+        &'a self,
+        controller: Principal,
+    ) -> AsyncReturn<'a, Result<Vec<Principal>, TxError>> {
+        Box::pin(async move {
+            let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+            controllers::add_controller(caller.inner(), controller).await
+        })
+    }
+
+    /// Removes a controller of the token canister. The last controller cannot be removed. Returns
+    /// the new list of the controllers.
+    #[update(trait = true)]
+    fn remove_controller<'a>(
+        &'a self,
+        controller: Principal,
+    ) -> AsyncReturn<'a, Result<Vec<Principal>, TxError>> {
+        Box::pin(async move {
+            let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+            controllers::remove_controller(caller.inner(), controller).await
+        })
+    }
+
+    /// Hands the token over to the `new_owner`: transfers the ownership, the fees if they go to
+    /// the owner, and replaces the owner in the controllers, all or nothing. See
+    /// `canister::handover`.
+    #[update(trait = true)]
+    fn handover<'a>(
+        &'a self,
+        new_owner: Principal,
+    ) -> AsyncReturn<'a, Result<HandoverSummary, TxError>> {
+        Box::pin(async move {
+            let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+            handover::handover(caller.inner(), new_owner).await
+        })
+    }
+
+    /// Appends a chunk of the wasm module to be installed with `commit_upgrade`. Returns the size
+    /// and the hash of the module uploaded so far.
+    #[update(trait = true)]
+    fn upload_upgrade_chunk(&self, chunk: Vec<u8>) -> Result<UpgradeModuleStatus, TxError> {
+        CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        UpgradeChunks::upload(chunk)
+    }
+
+    /// Removes the uploaded chunks, e.g. to start the upload again after a failed one.
+    #[update(trait = true)]
+    fn clear_upgrade_chunks(&self) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        UpgradeChunks::clear();
+        AdminLog::record(caller.inner(), AdminAction::ClearUpgradeChunks, None, None);
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn get_upgrade_module_status(&self) -> UpgradeModuleStatus {
+        UpgradeChunks::status()
+    }
+
+    /// Upgrades the token canister with the uploaded module, without the factory. The hash of the
+    /// module must be the `expected_module_hash`, and the token must be one of its controllers.
+    #[update(trait = true)]
+    fn commit_upgrade<'a>(
+        &'a self,
+        expected_module_hash: Vec<u8>,
+    ) -> AsyncReturn<'a, Result<(), TxError>> {
+        Box::pin(async move {
+            let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+            self_upgrade::commit_upgrade(caller.inner(), expected_module_hash).await
+        })
+    }
+
+    /// Returns the version of the stored state. It's lower than `state::STATE_VERSION` of the
+    /// installed wasm while the state migrations are not finished.
+    #[query(trait = true)]
+    fn get_state_version(&self) -> u32 {
+        TokenConfig::get_stable().state_version()
+    }
+
+    /// Returns the versions of the stored state and of the installed wasm, and the progress of the
+    /// unfinished state migration.
+    #[query(trait = true)]
+    fn get_state_migration_status(&self) -> StateMigrationStatus {
+        StateMigrations::status()
+    }
+
+    /// Continues the state migrations interrupted in `post_upgrade` because of the instruction
+    /// limit. Must be called until the returned status has no running migration.
+    #[update(trait = true)]
+    fn run_state_migrations(&self) -> Result<StateMigrationStatus, TxError> {
+        CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(StateMigrations::run(CALL_INSTRUCTION_LIMIT))
+    }
+
     #[query(trait = true)]
     fn get_token_info(&self) -> TokenInfo {
         let TokenConfig {
@@ -94,8 +350,9 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
             fee_to,
             history_size: LedgerData::len(),
             deployTime: deploy_time,
-            holderNumber: StableBalances.get_holders().len(),
+            holderNumber: DustReports::holders_count(),
             cycles: canister_sdk::ic_kit::ic::balance(),
+            tx_window: Some(TokenConfig::get_stable().tx_window()),
         }
     }
 
@@ -113,11 +370,12 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
         Ok(())
     }
 
+    /// Sets the transfer fee. If the timelock is set, the change is applied only after the
+    /// timelock delay, see `list_pending_changes`.
     #[update(trait = true)]
     fn set_fee(&self, fee: Tokens128) -> Result<(), TxError> {
         let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-        self.update_stats(caller, CanisterUpdate::Fee(fee));
-        Ok(())
+        timelock::update_or_propose(caller, ConfigChange::Fee(fee))
     }
 
     #[update(trait = true)]
@@ -127,18 +385,185 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
         Ok(())
     }
 
+    /// Sets the time bounds of the `created_at_time` of the transfers, see `TxWindow`. The
+    /// window must be between `MIN_TX_WINDOW` and `MAX_TX_WINDOW`, and the drift at most
+    /// `MAX_PERMITTED_DRIFT`.
+    #[update(trait = true)]
+    fn set_tx_window(&self, window: TxWindow) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        window.validate()?;
+        self.update_stats(caller, CanisterUpdate::TxWindow(window));
+        Ok(())
+    }
+
+    /// Sets the limits of the sizes of the call arguments, see `InputLimits`.
+    #[update(trait = true)]
+    fn set_input_limits(&self, limits: InputLimits) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        limits.validate()?;
+        self.update_stats(caller, CanisterUpdate::InputLimits(limits));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn get_input_limits(&self) -> InputLimits {
+        TokenConfig::get_stable().input_limits()
+    }
+
+    /// Transfers the ownership. If the timelock is set, the change is applied only after the
+    /// timelock delay, see `list_pending_changes`.
     #[update(trait = true)]
     fn set_owner(&self, owner: Principal) -> Result<(), TxError> {
         let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-        self.update_stats(caller, CanisterUpdate::Owner(owner));
+        timelock::update_or_propose(caller, ConfigChange::Owner(owner))
+    }
+
+    /// Sets the delay of the changes of the fee, of the fee and burn policies, of the fee
+    /// recipients, of the owner and of the minting account, or removes the timelock if `None`.
+    /// Extending the delay is applied at once, while shortening or removing it waits for the
+    /// current delay.
+    #[update(trait = true)]
+    fn set_timelock_delay(&self, delay_nanos: Option<u64>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Timelock::validate_delay(delay_nanos)?;
+        timelock::update_or_propose(caller, ConfigChange::TimelockDelay(delay_nanos))
+    }
+
+    #[query(trait = true)]
+    fn get_timelock_delay(&self) -> Option<u64> {
+        TokenConfig::get_stable().timelock_delay_nanos
+    }
+
+    /// Returns the configuration changes waiting for the timelock delay, in the order they were
+    /// proposed.
+    #[query(trait = true)]
+    fn list_pending_changes(&self) -> Vec<PendingChange> {
+        Timelock::list()
+    }
+
+    /// Cancels the pending configuration change.
+    #[update(trait = true)]
+    fn cancel_proposal(&self, id: ProposalId) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        timelock::cancel(caller, id)
+    }
+
+    /// Transfers the ownership to the governance canister, or to the management canister to make
+    /// the token immutable. Unless `keep_admin_rights` is set, the owner methods are disabled for
+    /// good, including for the new owner.
+    #[update(trait = true)]
+    fn renounce_ownership(&self, args: RenounceOwnershipArgs) -> Result<(), TxError> {
+        let config = TokenConfig::get_stable();
+        let caller = CheckedPrincipal::owner(&config)?;
+        let not_confirmed = |reason: &str| {
+            Err(TxError::RenouncementNotConfirmed {
+                reason: reason.into(),
+            })
+        };
+        if args.confirm_symbol != config.symbol {
+            return not_confirmed("symbol doesn't match the token symbol");
+        }
+        if !args.confirm_irreversible {
+            return not_confirmed("irreversibility is not confirmed");
+        }
+        if args.new_owner == Principal::anonymous() {
+            return not_confirmed("new owner cannot be anonymous");
+        }
+        if args.keep_admin_rights && args.new_owner == Principal::management_canister() {
+            return not_confirmed("management canister cannot keep the admin rights");
+        }
+
+        self.update_stats(
+            caller,
+            CanisterUpdate::RenounceOwnership(args.new_owner, args.keep_admin_rights),
+        );
+        Ok(())
+    }
+
+    /// Adds or replaces an entry returned by `icrc1_metadata`. The `icrc1:logo` entry must be a
+    /// text value with the data URL of the logo image.
+    #[update(trait = true)]
+    fn set_metadata_entry(&self, key: String, value: Value) -> Result<(), TxError> {
+        let config = TokenConfig::get_stable();
+        let caller = CheckedPrincipal::owner(&config)?;
+        config.validate_metadata_entry(&key, &value)?;
+        self.update_stats(caller, CanisterUpdate::MetadataEntry(key, value));
+        Ok(())
+    }
+
+    /// Applies the `patch` requested by the `owner` through the canister which installed the
+    /// token, e.g. by the factory updating several tokens of the owner at once. The changes are
+    /// applied as if the owner called the setters, so the fee change is timelocked if the timelock
+    /// is set.
+    #[update(trait = true)]
+    fn update_metadata_from_deployer(
+        &self,
+        owner: Principal,
+        patch: MetadataPatch,
+    ) -> Result<(), TxError> {
+        let config = TokenConfig::get_stable();
+        let caller = CheckedPrincipal::owner_via_deployer(&config, owner)?;
+        let logo = patch.logo.map(Value::Text);
+        if let Some(logo) = &logo {
+            config.validate_metadata_entry(LOGO_METADATA_KEY, logo)?;
+        }
+
+        if let Some(name) = patch.name {
+            apply_update(caller.inner(), CanisterUpdate::Name(name));
+        }
+        if let Some(logo) = logo {
+            apply_update(
+                caller.inner(),
+                CanisterUpdate::MetadataEntry(LOGO_METADATA_KEY.to_string(), logo),
+            );
+        }
+        if let Some(fee) = patch.fee {
+            timelock::update_or_propose(caller, ConfigChange::Fee(fee))?;
+        }
+
+        Ok(())
+    }
+
+    #[update(trait = true)]
+    fn remove_metadata_entry(&self, key: String) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        self.update_stats(caller, CanisterUpdate::RemoveMetadataEntry(key));
+        Ok(())
+    }
+
+    /// Sets the translations of the token name, symbol and description for the `locale`, or
+    /// removes them if `metadata` is not given. They are returned by `icrc1_metadata` under the
+    /// `is20:localized:<locale>:<field>` keys.
+    #[update(trait = true)]
+    fn set_localized_metadata(
+        &self,
+        locale: String,
+        metadata: Option<LocalizedMetadata>,
+    ) -> Result<(), TxError> {
+        let config = TokenConfig::get_stable();
+        let caller = CheckedPrincipal::owner(&config)?;
+        if let Some(metadata) = &metadata {
+            config.validate_localized_metadata(&locale, metadata)?;
+        }
+        self.update_stats(caller, CanisterUpdate::LocalizedMetadata(locale, metadata));
         Ok(())
     }
 
+    /// Returns `count` entries of the administrative actions log starting from the `offset`.
+    #[query(trait = true)]
+    fn get_admin_log(&self, offset: u64, count: usize) -> Vec<AdminLogEntry> {
+        AdminLog::get(offset, count.min(MAX_TRANSACTION_REQUEST))
+    }
+
     /********************** BALANCES INFO ***********************/
 
-    /// This method retreieves holders of `Account` and their amounts.
+    /// This method retreieves holders of `Account` and their amounts. At most
+    /// `InputLimits::max_listed_accounts` holders are returned.
     #[query(trait = true)]
     fn get_holders(&self, start: usize, limit: usize) -> Vec<(Account, Tokens128)> {
+        let limit = TokenConfig::get_stable()
+            .input_limits()
+            .listed_accounts(limit);
         StableBalances
             .list_balances(start, limit)
             .into_iter()
@@ -152,9 +577,65 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
     /// It is intentional that the method does not accept the principal to list the subaccounts
     /// for, because in some cases the token holder want to keep some of his subaccounts a secret.
     /// So only own subaccounts can be listed safely.
+    ///
+    /// At most `InputLimits::max_listed_accounts` subaccounts are returned, the first ones in the
+    /// byte order.
     #[query(trait = true)]
     fn list_subaccounts(&self) -> std::collections::HashMap<Subaccount, Tokens128> {
-        StableBalances.get_subaccounts(ic::caller())
+        let limit = TokenConfig::get_stable()
+            .input_limits()
+            .listed_accounts(usize::MAX);
+        StableBalances::first_subaccounts(ic::caller(), limit)
+    }
+
+    /// Same as `list_subaccounts`, but also returns the tags of the subaccounts. The tagged
+    /// subaccounts without tokens are included with zero balance.
+    #[query(trait = true)]
+    fn list_tagged_subaccounts(&self) -> std::collections::HashMap<Subaccount, TaggedSubaccount> {
+        let limit = TokenConfig::get_stable()
+            .input_limits()
+            .listed_accounts(usize::MAX);
+        let mut subaccounts = AccountTags::list_subaccounts(ic::caller())
+            .into_iter()
+            .collect::<Vec<_>>();
+        subaccounts.sort_unstable_by_key(|(subaccount, _)| *subaccount);
+        subaccounts.into_iter().take(limit).collect()
+    }
+
+    /// Returns the private tags of the caller's subaccounts.
+    #[query(trait = true)]
+    fn get_account_tags(&self) -> std::collections::HashMap<Subaccount, String> {
+        AccountTags::get(ic::caller())
+    }
+
+    /// Sets a private tag of the caller's subaccount, or removes it if the `label` is empty.
+    #[update(trait = true)]
+    fn set_account_tag(
+        &self,
+        subaccount: Option<Subaccount>,
+        label: String,
+    ) -> Result<(), TxError> {
+        AccountTags::set(ic::caller(), subaccount.unwrap_or_default(), label)
+    }
+
+    /********************** ACCOUNT IDENTIFIERS ***********************/
+
+    /// Returns the ICP ledger account identifier of the account, for the services which address
+    /// the accounts by account identifiers.
+    #[query(trait = true)]
+    fn get_account_identifier(
+        &self,
+        principal: Principal,
+        subaccount: Option<Subaccount>,
+    ) -> IcpAccountId {
+        AccountIds::of(AccountInternal::new(principal, subaccount))
+    }
+
+    /// Returns the account of the `account_id`, if it's known to the token. The accounts are
+    /// known after they claim tokens.
+    #[query(trait = true)]
+    fn resolve_account_identifier(&self, account_id: IcpAccountId) -> Option<Account> {
+        AccountIds::resolve(account_id)
     }
 
     /********************** CLAIMS ***********************/
@@ -178,610 +659,3677 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
     #[cfg(feature = "claim")]
     #[update(trait = true)]
     fn claim(&self, holder: Principal, subaccount: Option<Subaccount>) -> TxReceipt {
-        claim(holder, subaccount)
+        EndpointMetrics::instrument("claim", || claim(holder, subaccount))
     }
 
-    /********************** TRANSACTION HISTORY ***********************/
+    /********************** ICP BRIDGE ***********************/
 
-    #[query(trait = true)]
-    fn history_size(&self) -> u64 {
-        LedgerData::len()
+    /// Sets the ICP ledger used by the bridge, or disables the bridge if `None`.
+    #[cfg(feature = "icp_bridge")]
+    #[update(trait = true)]
+    fn set_icp_ledger(&self, ledger: Option<Principal>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        self.update_stats(caller, CanisterUpdate::IcpLedger(ledger));
+        Ok(())
     }
 
+    /// Returns the ICP account identifier the `owner` must transfer ICP to before calling
+    /// `deposit_icp`.
+    #[cfg(feature = "icp_bridge")]
     #[query(trait = true)]
-    fn get_transaction(&self, id: TxId) -> TxRecord {
-        LedgerData::get(id).unwrap_or_else(|| {
-            canister_sdk::ic_kit::ic::trap(&format!("Transaction {} does not exist", id))
-        })
+    fn get_icp_deposit_address(&self, owner: Principal) -> IcpAccountId {
+        icp_bridge::deposit_address(owner)
     }
 
-    /// Returns a list of transactions in paginated form. The `who` is optional, if given, only transactions of the `who` are
-    /// returned. `count` is the number of transactions to return, `transaction_id` is the transaction index which is used as
-    /// the offset of the first transaction to return, any
-    ///
-    /// It returns `PaginatedResult` a struct, which contains `result` which is a list of transactions `Vec<TxRecord>` that meet the requirements of the query,
-    /// and `next_id` which is the index of the next transaction to return.
-    #[query(trait = true)]
-    fn get_transactions(
-        &self,
-        who: Option<Principal>,
-        count: usize,
-        transaction_id: Option<TxId>,
-    ) -> PaginatedResult {
-        let count = who
-            .map_or(MAX_TRANSACTION_REQUEST, |_| MAX_ACCOUNT_TRANSACTION_REQUEST)
-            .min(count);
+    /// Mints the tokens for the ICP transferred to the caller's deposit address in the ICP ledger
+    /// `block`. The ICP transfer fee is deducted from the minted amount.
+    #[cfg(feature = "icp_bridge")]
+    #[update(trait = true)]
+    fn deposit_icp<'a>(&'a self, block: BlockIndex) -> AsyncReturn<'a, TxReceipt> {
+        let caller = ic::caller();
+        Box::pin(async move { icp_bridge::deposit_icp(caller, block).await })
+    }
 
-        LedgerData::get_transactions(who, count, transaction_id)
+    /// Burns `amount` of the caller's tokens and sends the ICP, minus the ICP transfer fee, to the
+    /// `to` account identifier. If the ICP transfer fails, the returned operation is pending and
+    /// is retried by `retry_bridge_operations`.
+    #[cfg(feature = "icp_bridge")]
+    #[update(trait = true)]
+    fn withdraw_icp<'a>(
+        &'a self,
+        amount: Tokens128,
+        to: IcpAccountId,
+        from_subaccount: Option<Subaccount>,
+    ) -> AsyncReturn<'a, Result<BridgeOperation, TxError>> {
+        let from = AccountInternal::new(ic::caller(), from_subaccount);
+        Box::pin(async move { icp_bridge::withdraw_icp(from, amount, to).await })
     }
 
-    /// Returns the total number of transactions related to the user `who`.
+    /// Retries the ICP transfers which failed before. Returns the operations still pending.
+    #[cfg(feature = "icp_bridge")]
+    #[update(trait = true)]
+    fn retry_bridge_operations<'a>(
+        &'a self,
+    ) -> AsyncReturn<'a, Result<Vec<BridgeOperation>, TxError>> {
+        Box::pin(async move { icp_bridge::retry_operations().await })
+    }
+
+    #[cfg(feature = "icp_bridge")]
     #[query(trait = true)]
-    fn get_user_transaction_count(&self, who: Principal) -> usize {
-        LedgerData::get_len_user_history(who)
+    fn get_bridge_operations(&self, offset: u64, count: usize) -> Vec<BridgeOperation> {
+        IcpBridge::get_operations(offset, count)
     }
 
-    /********************** IS20 TRANSACTIONS ***********************/
+    /// Returns the status of the deposit made in the ICP ledger `block`.
+    #[cfg(feature = "icp_bridge")]
+    #[query(trait = true)]
+    fn get_icp_deposit(&self, block: BlockIndex) -> Option<DepositStatus> {
+        IcpBridge::get_deposit(block)
+    }
 
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn transfer(&self, transfer: TransferArgs) -> Result<u128, TxError> {
-        let account = CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)?;
-        is20_transfer(account, &transfer, self.fee_ratio())
+    /********************** ICRC-1 WRAPPER ***********************/
+
+    /// Sets the ICRC-1 token backing the supply in the wrapping mode, or disables the wrapping
+    /// mode if `None`.
+    #[cfg(feature = "icrc1_wrapper")]
+    #[update(trait = true)]
+    fn set_wrapped_token(&self, token: Option<Principal>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        self.update_stats(caller, CanisterUpdate::WrappedToken(token));
+        Ok(())
     }
 
-    /// Takes a list of transfers, each of which is a pair of `to` and `value` fields, it returns a `TxReceipt` which contains
-    /// a vec of transaction index or an error message. The list of transfers is processed in the order they are given. if the `fee`
-    /// is set, the `fee` amount is applied to each transfer.
-    /// The balance of the caller is reduced by sum of `value + fee` amount for each transfer. If the total sum of `value + fee` for all transfers,
-    /// is less than the `balance` of the caller, the transaction will fail with `TxError::InsufficientBalance` error.
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn batch_transfer(
-        &self,
-        from_subaccount: Option<Subaccount>,
-        transfers: Vec<BatchTransferArgs>,
-    ) -> Result<Vec<TxId>, TxError> {
-        for x in &transfers {
-            let recipient = x.receiver;
-            CheckedAccount::with_recipient(recipient.into(), from_subaccount)?;
-        }
-        batch_transfer(from_subaccount, transfers, self.fee_ratio())
+    /// Returns the account of the underlying token the `owner` must transfer the tokens to before
+    /// calling `deposit`.
+    #[cfg(feature = "icrc1_wrapper")]
+    #[query(trait = true)]
+    fn get_wrapper_deposit_account(&self, owner: Principal) -> Account {
+        wrapper::deposit_account(owner)
     }
 
-    #[cfg_attr(feature = "mint_burn", update(trait = true))]
-    fn mint(
-        &self,
-        to: Principal,
-        to_subaccount: Option<Subaccount>,
+    /// Moves `amount` of the underlying tokens from the caller's deposit account to the reserve
+    /// and mints the same amount of the tokens to the caller. The underlying transfer fee must be
+    /// deposited on top of the amount. If the underlying transfer fails, the returned operation
+    /// is pending, and the tokens are minted by `retry_wrapper_operations`.
+    #[cfg(feature = "icrc1_wrapper")]
+    #[update(trait = true)]
+    fn deposit<'a>(
+        &'a self,
         amount: Tokens128,
-    ) -> TxReceipt {
-        if self.is_test_token() {
-            let test_user = CheckedPrincipal::test_user(&TokenConfig::get_stable())?;
-            mint_test_token(test_user, to, to_subaccount, amount)
-        } else {
-            let owner = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-            mint_as_owner(owner, to, to_subaccount, amount)
-        }
+    ) -> AsyncReturn<'a, Result<WrapperOperation, TxError>> {
+        let caller = ic::caller();
+        Box::pin(async move { wrapper::deposit(caller, amount).await })
     }
 
-    /// Burn `amount` of tokens from `from` principal.
-    /// If `from` is None, then caller's tokens will be burned.
-    /// If `from` is Some(_) but method called not by owner, `TxError::Unauthorized` will be returned.
-    /// If owner calls this method and `from` is Some(who), then who's tokens will be burned.
-    #[cfg_attr(feature = "mint_burn", update(trait = true))]
-    fn burn(
-        &self,
-        from: Option<Principal>,
-        from_subaccount: Option<Subaccount>,
+    /// Burns `amount` of the caller's tokens and sends the underlying tokens, minus the
+    /// underlying transfer fee, to `to` or to the caller if not set. If the underlying transfer
+    /// fails, the returned operation is pending and is retried by `retry_wrapper_operations`.
+    #[cfg(feature = "icrc1_wrapper")]
+    #[update(trait = true)]
+    fn withdraw<'a>(
+        &'a self,
         amount: Tokens128,
-    ) -> TxReceipt {
-        match from {
-            None => burn_own_tokens(from_subaccount, amount),
-            Some(from) if from == canister_sdk::ic_kit::ic::caller() => {
-                burn_own_tokens(from_subaccount, amount)
-            }
-            Some(from) => {
-                let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-                burn_as_owner(caller, from, from_subaccount, amount)
-            }
-        }
+        to: Option<Account>,
+        from_subaccount: Option<Subaccount>,
+    ) -> AsyncReturn<'a, Result<WrapperOperation, TxError>> {
+        let caller = ic::caller();
+        let from = AccountInternal::new(caller, from_subaccount);
+        let to = to.unwrap_or_else(|| caller.into());
+        Box::pin(async move { wrapper::withdraw(from, amount, to).await })
     }
 
-    /********************** ICRC-1 METHODS ***********************/
+    /// Retries the underlying transfers which failed before. Returns the operations still
+    /// pending.
+    #[cfg(feature = "icrc1_wrapper")]
+    #[update(trait = true)]
+    fn retry_wrapper_operations<'a>(
+        &'a self,
+    ) -> AsyncReturn<'a, Result<Vec<WrapperOperation>, TxError>> {
+        Box::pin(async move { wrapper::retry_operations().await })
+    }
 
+    #[cfg(feature = "icrc1_wrapper")]
     #[query(trait = true)]
-    fn icrc1_balance_of(&self, account: Account) -> Tokens128 {
-        StableBalances.balance_of(&account.into())
+    fn get_wrapper_operations(&self, offset: u64, count: usize) -> Vec<WrapperOperation> {
+        IcrcWrapper::get_operations(offset, count)
     }
 
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn icrc1_transfer(&self, transfer: TransferArgs) -> Result<u128, TransferError> {
-        let account = CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)?;
-
-        Ok(icrc1_transfer(account, &transfer, self.fee_ratio())?)
-    }
+    /********************** TRANSACTION HISTORY ***********************/
 
     #[query(trait = true)]
-    fn icrc1_name(&self) -> String {
-        TokenConfig::get_stable().name
+    fn history_size(&self) -> u64 {
+        LedgerData::len()
     }
 
+    /// Returns the call and error counters since the last upgrade, and the current state gauges.
+    /// The same metrics are served in the Prometheus format on the `/metrics` HTTP route.
     #[query(trait = true)]
-    fn icrc1_symbol(&self) -> String {
-        TokenConfig::get_stable().symbol
+    fn get_metrics(&self) -> TokenMetrics {
+        EndpointMetrics::get()
     }
 
+    /// Returns the calls, errors and instructions used per transaction method since the last
+    /// upgrade or reset, and the last calls using more than `SLOW_CALL_INSTRUCTIONS`.
     #[query(trait = true)]
-    fn icrc1_decimals(&self) -> u8 {
-        TokenConfig::get_stable().decimals
+    fn get_endpoint_stats(&self) -> EndpointStatsReport {
+        EndpointMetrics::endpoint_stats()
     }
 
-    /// Returns the default transfer fee.
+    #[update(trait = true)]
+    fn reset_endpoint_stats(&self) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        EndpointMetrics::reset_endpoint_stats();
+        AdminLog::record(caller.inner(), AdminAction::ResetEndpointStats, None, None);
+
+        Ok(())
+    }
+
+    /// Returns the stable memory pages used by every structure of the token, and the pages which
+    /// can still be allocated.
     #[query(trait = true)]
-    fn icrc1_fee(&self) -> Tokens128 {
-        TokenConfig::get_stable().fee
+    fn get_memory_report(&self) -> MemoryReport {
+        MemoryReport::get()
+    }
+
+    /// Returns the heap memory left unused by the pruned transactions to the allocator, so it's
+    /// reused by the later allocations instead of growing the memory of the canister. The memory
+    /// size of the canister doesn't shrink. Returns the estimated number of the released bytes.
+    #[update(trait = true)]
+    fn compact_ledger(&self) -> Result<CompactionReport, TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        let report = LedgerData::compact();
+        AdminLog::record(
+            caller.inner(),
+            AdminAction::CompactLedger,
+            None,
+            Some(Value::Nat(report.spare_bytes_released.into())),
+        );
+
+        Ok(report)
     }
+
+    /// Returns the memory usage of the transaction history and the result of the last
+    /// compaction.
     #[query(trait = true)]
-    fn icrc1_metadata(&self) -> Vec<(String, Value)> {
-        TokenConfig::get_stable().icrc1_metadata()
+    fn get_maintenance_status(&self) -> MaintenanceStatus {
+        LedgerData::maintenance_status()
     }
 
+    /// Returns the length of the history and the number of the pruned transactions.
     #[query(trait = true)]
-    fn icrc1_supported_standards(&self) -> Vec<StandardRecord> {
-        TokenConfig::get_stable().supported_standards()
+    fn get_history_info(&self) -> HistoryInfo {
+        LedgerData::history_info()
     }
 
+    /// Checks that the total supply derived from the balances matches the mint and burn records in
+    /// the ledger. Intended as a sanity check after upgrades.
     #[query(trait = true)]
-    fn icrc1_minting_account(&self) -> Option<Account> {
-        Some(TokenConfig::get_stable().owner.into())
+    fn verify_invariants(&self) -> InvariantsReport {
+        Invariants::verify()
     }
 
-    /********************** INTERNAL METHODS ***********************/
+    /// Writes a corrective mint or burn record to the ledger if `verify_invariants` reports a
+    /// discrepancy. The balances are left as they are. Returns the id of the written record.
+    #[update(trait = true)]
+    fn repair_invariants(&self) -> Result<Option<TxId>, TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Invariants::repair(caller.inner())
+    }
 
-    // Important: This function *must* be defined to be the
-    // last one in the trait because it depends on the order
-    // of expansion of update/query(trait = true) methods.
-    fn get_idl() -> Idl {
-        generate_idl!()
+    /// Sets the limits of the transaction history. The history exceeding the limits is pruned
+    /// automatically in batches, `prune_transactions` can be used to prune it immediately.
+    #[update(trait = true)]
+    fn set_history_retention(&self, policy: Option<RetentionPolicy>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        self.update_stats(caller, CanisterUpdate::HistoryRetention(policy));
+        Ok(())
     }
 
-    fn update_stats(&self, _caller: CheckedPrincipal<Owner>, update: CanisterUpdate) {
-        use CanisterUpdate::*;
-        let mut stats = TokenConfig::get_stable();
-        match update {
-            Name(name) => stats.name = name,
-            Symbol(symbol) => stats.symbol = symbol,
-            Fee(fee) => stats.fee = fee,
-            FeeTo(fee_to) => stats.fee_to = fee_to,
-            Owner(owner) => stats.owner = owner,
-            MinCycles(min_cycles) => stats.min_cycles = min_cycles,
-        }
-        TokenConfig::set_stable(stats)
+    /// Removes the transactions exceeding the limits of the retention policy. Returns the number
+    /// of removed transactions.
+    #[update(trait = true)]
+    fn prune_transactions(&self) -> Result<u64, TxError> {
+        let config = TokenConfig::get_stable();
+        let caller = CheckedPrincipal::owner(&config)?;
+        let pruned = match &config.history_retention {
+            Some(policy) => LedgerData::prune(policy),
+            None => 0,
+        };
+
+        AdminLog::record(
+            caller.inner(),
+            AdminAction::PruneTransactions,
+            None,
+            Some(Value::Nat(pruned.into())),
+        );
+        Ok(pruned)
     }
 
-    fn fee_ratio(&self) -> f64 {
-        #[cfg(feature = "auction")]
-        return self.bidding_info().fee_ratio;
+    #[query(trait = true)]
+    fn get_transaction(&self, id: TxId) -> TxRecord {
+        LedgerData::get(id).unwrap_or_else(|| {
+            canister_sdk::ic_kit::ic::trap(&format!("Transaction {} does not exist", id))
+        })
+    }
 
-        #[cfg(not(feature = "auction"))]
-        0.0
+    /// Exports the transactions with the timestamps in the range `[from_ts, to_ts)` as a chunk of
+    /// CSV or JSON, oldest first. The next chunk is requested with the returned cursor until it's
+    /// `None`.
+    #[query(trait = true)]
+    fn export_transactions(
+        &self,
+        format: ExportFormat,
+        from_ts: Timestamp,
+        to_ts: Timestamp,
+        cursor: Option<TxId>,
+    ) -> TransactionsExport {
+        export::export_transactions(format, from_ts, to_ts, cursor)
     }
-}
 
-generate_exports!(TokenCanisterAPI, TokenCanisterExports);
+    /// Starts an export session pinning the current balances and transaction history, so the
+    /// chunks of `export_holders` and `export_session_transactions` don't change with the later
+    /// transfers or upgrades. The session expires in a day unless released earlier.
+    #[update(trait = true)]
+    fn start_export(&self) -> Result<ExportSession, TxError> {
+        ExportSessions::start(ic::caller(), ic::time())
+    }
 
-#[cfg(feature = "auction")]
-use canister_sdk::ic_storage::IcStorage;
+    /// Ends the export session of the caller.
+    #[update(trait = true)]
+    fn release_export(&self, session: ExportSessionId) -> Result<(), TxError> {
+        ExportSessions::release(ic::caller(), session, ic::time())
+    }
 
-#[cfg(feature = "auction")]
-impl Auction for TokenCanisterExports {
-    fn auction_state(&self) -> std::rc::Rc<std::cell::RefCell<AuctionState>> {
-        AuctionState::get()
+    /// Exports the balances at the start of the export session. The next chunk is requested with
+    /// the returned cursor until it's `None`.
+    #[query(trait = true)]
+    fn export_holders(
+        &self,
+        session: ExportSessionId,
+        cursor: Option<Account>,
+    ) -> Result<HoldersExport, TxError> {
+        export::export_holders(session, cursor, ic::time())
     }
 
-    fn disburse_rewards(&self) -> Result<AuctionInfo, AuctionError> {
-        is20_auction::disburse_rewards(&self.auction_state().borrow())
+    /// Same as `export_transactions`, but without the transactions made after the export session
+    /// started.
+    #[query(trait = true)]
+    fn export_session_transactions(
+        &self,
+        session: ExportSessionId,
+        format: ExportFormat,
+        from_ts: Timestamp,
+        to_ts: Timestamp,
+        cursor: Option<TxId>,
+    ) -> Result<TransactionsExport, TxError> {
+        export::export_session_transactions(session, format, from_ts, to_ts, cursor, ic::time())
     }
-}
 
-pub fn auction_account() -> AccountInternal {
-    // There are no sub accounts for the auction principal
-    AccountInternal::new(Principal::management_canister(), None)
-}
+    /// Returns the transactions as the blocks of the ICP ledger, for the indexers using the ICP
+    /// ledger interface. See `ledger_compat` for the operations which can't be mapped.
+    #[query(trait = true)]
+    fn query_blocks(&self, args: GetBlocksArgs) -> QueryBlocksResponse {
+        ledger_compat::query_blocks(args)
+    }
 
-#[cfg(test)]
-mod tests {
-    use canister_sdk::ic_canister::canister_call;
-    use canister_sdk::ic_kit::inject::get_context;
-    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
-    use canister_sdk::ic_kit::MockContext;
-    #[cfg(feature = "claim")]
-    use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
+    /// The same as `query_blocks`, with every block encoded with candid. Replaces `get_blocks_pb`
+    /// of the ICP ledger.
+    #[query(trait = true)]
+    fn query_encoded_blocks(&self, args: GetBlocksArgs) -> QueryEncodedBlocksResponse {
+        ledger_compat::query_encoded_blocks(args)
+    }
 
-    use crate::mock::TokenCanisterMock;
-    use crate::{account::DEFAULT_SUBACCOUNT, state::config::Metadata};
+    /// Returns a list of transactions in paginated form. The `who` is optional, if given, only transactions of the `who` are
+    /// returned. `count` is the number of transactions to return, `transaction_id` is the transaction index which is used as
+    /// the offset of the first transaction to return, any
+    ///
+    /// It returns `PaginatedResult` a struct, which contains `result` which is a list of transactions `Vec<TxRecord>` that meet the requirements of the query,
+    /// and `next_id` which is the index of the next transaction to return.
+    #[query(trait = true)]
+    fn get_transactions(
+        &self,
+        who: Option<Principal>,
+        count: usize,
+        transaction_id: Option<TxId>,
+    ) -> PaginatedResult {
+        let count = who
+            .map_or(MAX_TRANSACTION_REQUEST, |_| MAX_ACCOUNT_TRANSACTION_REQUEST)
+            .min(count);
 
-    use super::*;
+        LedgerData::get_transactions(who, count, transaction_id)
+    }
 
-    // Method for generating random Subaccount.
-    #[cfg(feature = "claim")]
-    #[cfg_attr(coverage_nightly, no_coverage)]
-    fn gen_subaccount() -> Subaccount {
-        use rand::{thread_rng, Rng};
+    /// Returns the transactions of the `args.account`, newest first, and its balance, in the form
+    /// of the `get_account_transactions` method of the ICRC index canister. Uses the per-user
+    /// index of the ledger, so the cost doesn't depend on the length of the global history.
+    #[query(trait = true)]
+    fn get_account_transactions(&self, args: GetAccountTransactionsArgs) -> GetAccountTransactions {
+        LedgerData::get_account_transactions(GetAccountTransactionsArgs {
+            max_results: args.max_results.min(MAX_ACCOUNT_TRANSACTION_REQUEST as u64),
+            ..args
+        })
+    }
 
-        let mut subaccount = [0u8; 32];
-        thread_rng().fill(&mut subaccount);
-        subaccount
+    /// Returns up to `count` transactions sent from or to the `subaccount` of the `principal`
+    /// (the default one if not given), newest first, skipping the `offset` latest ones.
+    #[query(trait = true)]
+    fn get_subaccount_transactions(
+        &self,
+        principal: Principal,
+        subaccount: Option<Subaccount>,
+        count: usize,
+        offset: usize,
+    ) -> Vec<TxRecord> {
+        let count = count.min(MAX_ACCOUNT_TRANSACTION_REQUEST);
+        LedgerData::get_subaccount_transactions(
+            AccountInternal::new(principal, subaccount),
+            count,
+            offset,
+        )
     }
 
-    #[cfg_attr(coverage_nightly, no_coverage)]
-    fn test_context() -> (&'static MockContext, TokenCanisterMock) {
-        let context = MockContext::new().with_caller(john()).inject();
+    /// Returns the length of the ledger and the hash of its last transaction. Each transaction
+    /// hash covers the hash of the previous one, see `TxRecord::compute_hash`, so the tip hash
+    /// commits to the whole history.
+    #[query(trait = true)]
+    fn get_ledger_tip_hash(&self) -> LedgerTip {
+        LedgerData::tip()
+    }
 
-        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
-        let canister = TokenCanisterMock::from_principal(principal);
+    /// Checks the hash chain of up to `length` stored transactions starting from the id `start`.
+    #[query(trait = true)]
+    fn verify_chain(&self, start: TxId, length: u64) -> ChainVerification {
+        LedgerData::verify_chain(start, length.min(MAX_VERIFIED_RECORDS as u64) as usize)
+    }
 
-        // Refresh canister's state.
-        TokenConfig::set_stable(TokenConfig::default());
-        StableBalances.clear();
-        LedgerData::clear();
+    /// Returns the total number of transactions related to the user `who`.
+    #[query(trait = true)]
+    fn get_user_transaction_count(&self, who: Principal) -> usize {
+        LedgerData::get_len_user_history(who)
+    }
 
-        // Due to this update, init() code will get actual
-        // principal of the canister from ic::id().
-        context.update_id(canister.principal());
+    /// Returns the transactions with the given `memo`, newest first. Without the memo index
+    /// (see `set_memo_index`) the whole stored history is scanned.
+    #[query(trait = true)]
+    fn get_transactions_by_memo(&self, memo: Memo) -> Vec<TxRecord> {
+        LedgerData::get_transactions_by_memo(&memo, MAX_TRANSACTION_REQUEST)
+    }
 
-        canister.init(
-            Metadata {
-                name: "".to_string(),
-                symbol: "".to_string(),
-                decimals: 8,
+    /// Enables or disables the index of the transactions by memo. On enabling, the index is
+    /// built from the stored history.
+    #[update(trait = true)]
+    fn set_memo_index(&self, enabled: bool) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        self.update_stats(caller, CanisterUpdate::MemoIndex(enabled));
+        LedgerData::set_memo_index(enabled);
+        Ok(())
+    }
 
-                owner: john(),
-                fee: Tokens128::from(0),
-                fee_to: john(),
-                is_test_token: None,
-            },
-            Tokens128::from(1000),
-        );
+    /********************** FROZEN ACCOUNTS ***********************/
 
-        // This is to make tests that don't rely on auction state
-        // pass, because since we are running auction state on each
-        // endpoint call, it affects `BiddingInfo.fee_ratio` that is
-        // used for charging fees in `approve` endpoint.
-        let mut stats = TokenConfig::get_stable();
-        stats.min_cycles = 0;
-        TokenConfig::set_stable(stats);
+    /// Freezes the `account`, so it cannot send tokens, receive tokens or both, depending on
+    /// the `mode`.
+    #[update(trait = true)]
+    fn freeze_account(&self, account: Account, mode: FreezeMode) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        FrozenAccounts::set(caller.inner(), account.into(), Some(mode));
+        Ok(())
+    }
 
-        canister.mint(alice(), None, 1000.into()).unwrap();
-        context.update_caller(alice());
+    #[update(trait = true)]
+    fn unfreeze_account(&self, account: Account) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        FrozenAccounts::set(caller.inner(), account.into(), None);
+        Ok(())
+    }
 
-        (context, canister)
+    /// Removes the claims, tags, standing orders and payment subscriptions of the `principal`,
+    /// whose accounts must be empty. The ledger is not changed, see `canister::account_purge`.
+    #[update(trait = true)]
+    fn purge_account_data(&self, principal: Principal) -> Result<PurgeReport, TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        account_purge::purge_account_data(caller.inner(), principal)
     }
 
-    fn test_canister() -> TokenCanisterMock {
-        let context = MockContext::new().with_caller(alice()).inject();
+    /// Returns the freeze mode of the `account`, or `None` if the account is not frozen.
+    #[query(trait = true)]
+    fn is_frozen(&self, account: Account) -> Option<FreezeMode> {
+        FrozenAccounts::get(account.into())
+    }
 
-        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
-        let canister = TokenCanisterMock::from_principal(principal);
-        context.update_id(canister.principal());
+    /********************** EVENT SUBSCRIPTIONS ***********************/
 
-        // Refresh canister's state.
-        TokenConfig::set_stable(TokenConfig::default());
-        StableBalances.clear();
-        LedgerData::clear();
+    /// Subscribes the calling canister to the token events matching the `filter`. The new events
+    /// are pushed to the subscriber in batches by one-way calls of its
+    /// `on_token_events : (vec TxRecord) -> ()` method. Calling this method again replaces the
+    /// filter of the existing subscription and resumes it if it was suspended.
+    ///
+    /// A new subscription of a principal other than the owner must attach
+    /// `EVENT_SUBSCRIPTION_FEE_CYCLES`, which are kept by the token.
+    #[update(trait = true)]
+    fn subscribe_to_events(&self, filter: EventFilter) -> Result<(), TxError> {
+        let caller = ic::caller();
+        if caller == Principal::anonymous() {
+            return Err(TxError::Unauthorized);
+        }
 
-        canister.init(
-            Metadata {
-                name: "".to_string(),
-                symbol: "".to_string(),
-                decimals: 8,
-                owner: alice(),
-                fee: Tokens128::from(0),
-                fee_to: alice(),
-                is_test_token: None,
-            },
-            Tokens128::from(1000),
-        );
+        let fee = if caller == TokenConfig::get_stable().owner
+            || EventSubscriptions::get(caller).is_some()
+        {
+            0
+        } else {
+            EVENT_SUBSCRIPTION_FEE_CYCLES
+        };
+        if ic::msg_cycles_available() < fee {
+            return Err(TxError::InsufficientCycles { required: fee });
+        }
 
-        let mut stats = TokenConfig::get_stable();
-        stats.min_cycles = 0;
-        TokenConfig::set_stable(stats);
+        EventSubscriptions::subscribe(caller, filter)?;
+        ic::msg_cycles_accept(fee);
+        Ok(())
+    }
 
-        canister
+    #[update(trait = true)]
+    fn unsubscribe_from_events(&self) -> bool {
+        EventSubscriptions::unsubscribe(ic::caller()).is_some()
     }
 
-    #[test]
-    fn transfer_to_same_account() {
-        let canister = test_canister();
-        let transfer = TransferArgs {
-            from_subaccount: None,
-            to: alice().into(),
-            amount: 100.into(),
-            fee: None,
-            memo: None,
-            created_at_time: None,
-        };
+    /// Returns the state of the caller's subscription, including the number of undelivered and
+    /// dropped events.
+    #[query(trait = true)]
+    fn get_event_subscription(&self) -> Option<Subscription> {
+        EventSubscriptions::get(ic::caller())
+    }
 
-        let res = canister.icrc1_transfer(transfer);
-        assert_eq!(
-            res,
-            Err(TransferError::GenericError {
-                error_code: 500,
-                message: "self transfer".into()
-            })
-        )
+    /********************** OUTBOUND NOTIFICATIONS ***********************/
+
+    /// Returns the number of the queued notifications to other canisters and of the dead letters.
+    #[query(trait = true)]
+    fn get_outbound_status(&self) -> OutboundStatus {
+        Outbound::status()
     }
 
-    #[test]
-    fn transfer_to_same_default_subaccount() {
-        let canister = test_canister();
-        let transfer = TransferArgs {
-            from_subaccount: Some(crate::account::DEFAULT_SUBACCOUNT),
-            to: alice().into(),
-            amount: 100.into(),
-            fee: None,
-            memo: None,
-            created_at_time: None,
-        };
+    /// Returns the notifications which failed `MAX_DELIVERY_ATTEMPTS` times and are not retried
+    /// anymore.
+    #[query(trait = true)]
+    fn get_dead_letters(&self, offset: u64, count: u64) -> Vec<OutboundMessage> {
+        Outbound::dead_letters(
+            offset as usize,
+            count.min(MAX_TRANSACTION_REQUEST as u64) as usize,
+        )
+    }
 
-        let res = canister.icrc1_transfer(transfer);
+    /// Sends the queued notification `id` again, with a new series of attempts.
+    #[update(trait = true)]
+    fn retry_outbound_message(&self, id: MessageId) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        outbound::retry_message(caller, id)
+    }
+
+    /// Removes the queued notification `id` without sending it.
+    #[update(trait = true)]
+    fn drop_outbound_message(&self, id: MessageId) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        outbound::drop_message(caller, id)
+    }
+
+    /********************** PAYMENT SUBSCRIPTIONS ***********************/
+
+    /// Allows the `spender` to collect up to `amount` tokens from the caller's account once per
+    /// `interval_nanos`. The first payment can be collected right away. Returns the id of the new
+    /// subscription.
+    #[update(trait = true)]
+    fn create_subscription(
+        &self,
+        spender: Principal,
+        amount: Tokens128,
+        interval_nanos: u64,
+        from_subaccount: Option<Subaccount>,
+    ) -> Result<SubscriptionId, TxError> {
+        let subscriber = AccountInternal::new(ic::caller(), from_subaccount);
+        PaymentSubscriptions::create(subscriber, spender, amount, interval_nanos, ic::time())
+    }
+
+    /// Cancels the subscription. Can be called by either the subscriber or the spender.
+    #[update(trait = true)]
+    fn cancel_subscription(&self, id: SubscriptionId) -> Result<(), TxError> {
+        PaymentSubscriptions::cancel(ic::caller(), id)
+    }
+
+    /// Transfers `amount` from the subscriber of the subscription `id` to the caller, who must be
+    /// the spender of the subscription. The transfer fee is paid by the subscriber.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn collect_subscription(&self, id: SubscriptionId, amount: Tokens128) -> TxReceipt {
+        EndpointMetrics::instrument("collect_subscription", || {
+            is20_transactions::collect_subscription(id, amount, self.fee_ratio())
+        })
+    }
+
+    #[query(trait = true)]
+    fn get_subscription(&self, id: SubscriptionId) -> Option<PaymentSubscription> {
+        PaymentSubscriptions::get(id)
+    }
+
+    /// Returns the payment subscriptions in which `who` is the subscriber or the spender.
+    #[query(trait = true)]
+    fn get_subscriptions(&self, who: Principal) -> Vec<PaymentSubscription> {
+        PaymentSubscriptions::list(who)
+    }
+
+    /********************** MERCHANT PAYMENTS ***********************/
+
+    /// Allows the `merchant` to pull payments from the caller's account with `pull_payment`, up
+    /// to `per_tx_cap` per payment and `monthly_cap` per 30 days. Replaces the caps if the merchant
+    /// is already authorized.
+    #[update(trait = true)]
+    fn authorize_merchant(
+        &self,
+        merchant: Principal,
+        per_tx_cap: Tokens128,
+        monthly_cap: Tokens128,
+        from_subaccount: Option<Subaccount>,
+    ) -> Result<MerchantAuthorization, TxError> {
+        let payer = AccountInternal::new(ic::caller(), from_subaccount);
+        MerchantAuthorizations::authorize(payer, merchant, per_tx_cap, monthly_cap, ic::time())
+    }
+
+    #[update(trait = true)]
+    fn revoke_merchant(
+        &self,
+        merchant: Principal,
+        from_subaccount: Option<Subaccount>,
+    ) -> Result<(), TxError> {
+        let payer = AccountInternal::new(ic::caller(), from_subaccount);
+        MerchantAuthorizations::revoke(payer, merchant)
+    }
+
+    /// Transfers `amount` from the `payer` account to the caller, who must be authorized by the
+    /// payer with `authorize_merchant`. The transfer fee is paid by the payer.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn pull_payment(&self, payer: Account, amount: Tokens128, memo: Option<Memo>) -> TxReceipt {
+        EndpointMetrics::instrument("pull_payment", || {
+            is20_transactions::pull_payment(payer.into(), amount, memo, self.fee_ratio())
+        })
+    }
+
+    /// Returns the merchant authorizations in which `who` is the payer or the merchant.
+    #[query(trait = true)]
+    fn get_merchant_authorizations(&self, who: Principal) -> Vec<MerchantAuthorization> {
+        MerchantAuthorizations::list(who)
+    }
+
+    /********************** BUDGETS ***********************/
+
+    /// Creates a budget on the caller's `subaccount`, optionally under the `parent` budget. The
+    /// delegates set with `set_budget_delegates` can spend up to `spending_limit` from it.
+    #[update(trait = true)]
+    fn create_budget(
+        &self,
+        subaccount: Subaccount,
+        name: String,
+        parent: Option<Subaccount>,
+        spending_limit: Tokens128,
+    ) -> Result<Budget, TxError> {
+        Budgets::create(
+            ic::caller(),
+            subaccount,
+            name,
+            parent,
+            spending_limit,
+            ic::time(),
+        )
+    }
+
+    #[update(trait = true)]
+    fn set_budget_limit(
+        &self,
+        subaccount: Subaccount,
+        spending_limit: Tokens128,
+    ) -> Result<Budget, TxError> {
+        Budgets::set_limit(ic::caller(), subaccount, spending_limit)
+    }
+
+    #[update(trait = true)]
+    fn set_budget_delegates(
+        &self,
+        subaccount: Subaccount,
+        delegates: Vec<Principal>,
+    ) -> Result<Budget, TxError> {
+        Budgets::set_delegates(ic::caller(), subaccount, delegates)
+    }
+
+    /// Removes the budget without its sub-budgets. The tokens stay on the subaccount.
+    #[update(trait = true)]
+    fn remove_budget(&self, subaccount: Subaccount) -> Result<(), TxError> {
+        Budgets::remove(ic::caller(), subaccount)
+    }
+
+    /// Transfers `amount` from the `budget` of the `organization` to the `to` account. The caller
+    /// must be a delegate of the budget, and the amount must fit in the limits of the budget and
+    /// its parents.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn budget_transfer(
+        &self,
+        organization: Principal,
+        budget: Subaccount,
+        to: Account,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxReceipt {
+        EndpointMetrics::instrument("budget_transfer", || {
+            is20_transactions::budget_transfer(
+                organization,
+                budget,
+                to.into(),
+                amount,
+                memo,
+                self.fee_ratio(),
+            )
+        })
+    }
+
+    /// Returns the budgets of the `organization` with their balances and spendings.
+    #[query(trait = true)]
+    fn get_budgets(&self, organization: Principal) -> Vec<BudgetReport> {
+        Budgets::list(organization)
+            .into_iter()
+            .map(|budget| BudgetReport {
+                balance: StableBalances.balance_of(&budget.account()),
+                remaining: budget.remaining(),
+                budget,
+            })
+            .collect()
+    }
+
+    /********************** VOTING SNAPSHOTS ***********************/
+
+    /// Sets the principals which accounts have no voting power at the snapshots taken from now
+    /// on, e.g. the treasury or the accounts of the exchanges.
+    #[update(trait = true)]
+    fn set_voting_exclusions(&self, excluded: Vec<Principal>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Snapshots::validate_exclusions(&excluded)?;
+
+        self.update_stats(caller, CanisterUpdate::VotingExclusions(excluded));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn get_voting_exclusions(&self) -> Vec<Principal> {
+        TokenConfig::get_stable()
+            .voting_exclusions
+            .unwrap_or_default()
+    }
+
+    /// Takes a snapshot of the balances, e.g. when a governance proposal is created. The voting
+    /// power of the accounts at the snapshot doesn't change with the later transfers.
+    #[update(trait = true)]
+    fn take_snapshot(&self) -> Result<Snapshot, TxError> {
+        let config = TokenConfig::get_stable();
+        let caller = CheckedPrincipal::owner(&config)?;
+        let snapshot = Snapshots::take(ic::time(), config.voting_exclusions.unwrap_or_default());
+        AdminLog::record(
+            caller.inner(),
+            AdminAction::TakeSnapshot { id: snapshot.id },
+            None,
+            Some(Value::Nat(snapshot.total_supply.amount.into())),
+        );
+        Ok(snapshot)
+    }
+
+    #[query(trait = true)]
+    fn get_snapshot(&self, snapshot_id: SnapshotId) -> Result<Snapshot, TxError> {
+        Snapshots::get(snapshot_id)
+    }
+
+    /// Returns the balance of the `account` at the snapshot, or zero if its owner was excluded
+    /// from the voting when the snapshot was taken.
+    #[query(trait = true)]
+    fn get_voting_power(
+        &self,
+        account: Account,
+        snapshot_id: SnapshotId,
+    ) -> Result<Tokens128, TxError> {
+        Snapshots::voting_power(account.into(), snapshot_id)
+    }
+
+    /// Returns the total supply at the snapshot without the balances excluded from the voting.
+    #[query(trait = true)]
+    fn total_voting_power(&self, snapshot_id: SnapshotId) -> Result<Tokens128, TxError> {
+        Snapshots::total_voting_power(snapshot_id)
+    }
+
+    /********************** ACCOUNT RECOVERY ***********************/
+
+    /// Sets the guardian who can recover the caller's accounts if the caller loses its key, or
+    /// removes it if `guardian` is `None`. Cancels the pending recovery of the caller's accounts.
+    #[update(trait = true)]
+    fn set_guardian(&self, guardian: Option<Guardian>) -> Result<(), TxError> {
+        let caller = ic::caller();
+        Guardians::set(caller, guardian)?;
+        RecoveryLog::record(caller, caller, RecoveryAction::SetGuardian { guardian });
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn get_guardian(&self, owner: Principal) -> Option<Guardian> {
+        Guardians::get(owner)
+    }
+
+    /// Starts the recovery of the `owner` accounts to the `new_owner`. Must be called by the
+    /// guardian of the owner. The recovery can be completed after the recovery delay set by the
+    /// owner, unless the owner vetoes it in the meantime.
+    #[update(trait = true)]
+    fn initiate_recovery(
+        &self,
+        owner: Principal,
+        new_owner: Principal,
+    ) -> Result<Recovery, TxError> {
+        let caller = ic::caller();
+        let recovery = Guardians::initiate(caller, owner, new_owner, ic::time())?;
+        RecoveryLog::record(
+            caller,
+            owner,
+            RecoveryAction::InitiateRecovery { new_owner },
+        );
+        Ok(recovery)
+    }
+
+    /// Cancels the pending recovery of the caller's accounts.
+    #[update(trait = true)]
+    fn veto_recovery(&self) -> Result<(), TxError> {
+        let caller = ic::caller();
+        Guardians::veto(caller)?;
+        RecoveryLog::record(caller, caller, RecoveryAction::VetoRecovery);
+        Ok(())
+    }
+
+    /// Moves the balances of all subaccounts of the `owner` to the same subaccounts of the new
+    /// owner of the recovery. Must be called by the guardian after the recovery delay. Returns the
+    /// ids of the recorded transactions.
+    #[update(trait = true)]
+    fn complete_recovery(&self, owner: Principal) -> Result<Vec<TxId>, TxError> {
+        complete_recovery(ic::caller(), owner)
+    }
+
+    #[query(trait = true)]
+    fn get_recovery(&self, owner: Principal) -> Option<Recovery> {
+        Guardians::get_recovery(owner)
+    }
+
+    /// Returns `count` entries of the guardian changes and recovery steps log starting from the
+    /// `offset`.
+    #[query(trait = true)]
+    fn get_recovery_log(&self, offset: u64, count: usize) -> Vec<RecoveryLogEntry> {
+        RecoveryLog::get(offset, count.min(MAX_TRANSACTION_REQUEST))
+    }
+
+    /********************** STANDING ORDERS ***********************/
+
+    /// Orders the token to transfer `amount` from the caller's account to `to` once per
+    /// `interval_nanos`, at most `max_executions` times (unlimited if not set). The first payment
+    /// is made at the next run of the timer task. The payments not covered by the balance are
+    /// skipped. Returns the id of the new order.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn create_standing_order(
+        &self,
+        to: Account,
+        amount: Tokens128,
+        interval_nanos: u64,
+        max_executions: Option<u64>,
+        from_subaccount: Option<Subaccount>,
+    ) -> Result<StandingOrderId, TxError> {
+        EndpointMetrics::instrument("create_standing_order", || {
+            let payer = AccountInternal::new(ic::caller(), from_subaccount);
+            LargeTransfers::check_amount(amount)?;
+            StandingOrders::create(
+                payer,
+                to.into(),
+                amount,
+                interval_nanos,
+                max_executions,
+                ic::time(),
+            )
+        })
+    }
+
+    /// Suspends the payments of the order. Can be called only by the payer.
+    #[update(trait = true)]
+    fn pause_standing_order(&self, id: StandingOrderId) -> Result<(), TxError> {
+        StandingOrders::pause(ic::caller(), id)
+    }
+
+    /// Resumes the payments of the paused order. Can be called only by the payer.
+    #[update(trait = true)]
+    fn resume_standing_order(&self, id: StandingOrderId) -> Result<(), TxError> {
+        StandingOrders::resume(ic::caller(), id)
+    }
+
+    /// Removes the order. Can be called by either the payer or the recipient.
+    #[update(trait = true)]
+    fn cancel_standing_order(&self, id: StandingOrderId) -> Result<(), TxError> {
+        StandingOrders::cancel(ic::caller(), id)
+    }
+
+    #[query(trait = true)]
+    fn get_standing_order(&self, id: StandingOrderId) -> Option<StandingOrder> {
+        StandingOrders::get(id)
+    }
+
+    /// Returns the standing orders in which `who` is the payer or the recipient.
+    #[query(trait = true)]
+    fn get_standing_orders(&self, who: Principal) -> Vec<StandingOrder> {
+        StandingOrders::list(who)
+    }
+
+    /********************** PAYMENT REQUESTS ***********************/
+
+    /// Creates a request for `amount` tokens to be paid to the caller's account with the
+    /// `to_subaccount` before `expires_at`. The caller is notified with the
+    /// `on_payment_request_paid` call once the request is paid. Returns the id of the request.
+    #[update(trait = true)]
+    fn create_payment_request(
+        &self,
+        amount: Tokens128,
+        memo: Option<Memo>,
+        expires_at: Timestamp,
+        to_subaccount: Option<Subaccount>,
+    ) -> Result<PaymentRequestId, TxError> {
+        let merchant = AccountInternal::new(ic::caller(), to_subaccount);
+        PaymentRequests::create(merchant, amount, memo, expires_at, ic::time())
+    }
+
+    /// Pays the request from the caller's account. The transfer fee is paid on top of the
+    /// requested amount.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn pay_request(&self, id: PaymentRequestId, from_subaccount: Option<Subaccount>) -> TxReceipt {
+        EndpointMetrics::instrument("pay_request", || {
+            let payer = AccountInternal::new(ic::caller(), from_subaccount);
+            let result = is20_transactions::pay_request(payer, id, self.fee_ratio());
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /// Cancels the pending request. Can be called only by the merchant.
+    #[update(trait = true)]
+    fn cancel_payment_request(&self, id: PaymentRequestId) -> Result<(), TxError> {
+        PaymentRequests::cancel(ic::caller(), id, ic::time())
+    }
+
+    #[query(trait = true)]
+    fn get_payment_request(&self, id: PaymentRequestId) -> Option<PaymentRequest> {
+        PaymentRequests::get(id, ic::time())
+    }
+
+    /// Returns the payment requests created by the `merchant`.
+    #[query(trait = true)]
+    fn get_payment_requests(&self, merchant: Principal) -> Vec<PaymentRequest> {
+        PaymentRequests::list(merchant, ic::time())
+    }
+
+    /********************** SWAPS ***********************/
+
+    /// Locks `amount` of the caller's tokens in escrow until the `counterparty` pays
+    /// `other_amount` of the `other_token` or the `deadline` passes. See `canister::swaps` module
+    /// for the swap flow.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn create_swap(
+        &self,
+        counterparty: Principal,
+        amount: Tokens128,
+        other_token: Principal,
+        other_amount: Tokens128,
+        deadline: Timestamp,
+        from_subaccount: Option<Subaccount>,
+    ) -> Result<SwapId, TxError> {
+        EndpointMetrics::instrument("create_swap", || {
+            let maker = AccountInternal::new(ic::caller(), from_subaccount);
+            swaps::create_swap(
+                maker,
+                counterparty,
+                amount,
+                other_token,
+                other_amount,
+                deadline,
+                self.fee_ratio(),
+            )
+        })
+    }
+
+    /// Completes the swap after the caller deposited the other token to the swap escrow account.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn accept_swap<'a>(&'a self, id: SwapId) -> AsyncReturn<'a, Result<(), TxError>> {
+        let caller = ic::caller();
+        Box::pin(async move { swaps::accept_swap(caller, id).await })
+    }
+
+    /// Returns the escrowed tokens to the maker and the other token deposit to the counterparty
+    /// after the deadline of the swap.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn refund_swap<'a>(&'a self, id: SwapId) -> AsyncReturn<'a, Result<(), TxError>> {
+        Box::pin(async move { swaps::refund_swap(id).await })
+    }
+
+    #[query(trait = true)]
+    fn get_swap(&self, id: SwapId) -> Option<Swap> {
+        Swaps::get(id)
+    }
+
+    /// Returns the swaps in which `who` is the maker or the counterparty.
+    #[query(trait = true)]
+    fn get_swaps(&self, who: Principal) -> Vec<Swap> {
+        Swaps::list(who)
+    }
+
+    /// Returns the account the counterparty must deposit the other token of the swap to.
+    #[query(trait = true)]
+    fn get_swap_escrow(&self, id: SwapId) -> Account {
+        Account::new(ic::id(), Some(escrow_subaccount(id)))
+    }
+
+    /********************** EXPIRING TRANSFERS ***********************/
+
+    /// Moves `amount` of the caller's tokens to escrow until the recipient accepts them with
+    /// `accept_transfer`. If the transfer is not accepted before `expires_at`, the caller can
+    /// take the tokens back with `reclaim_transfer`. The transfer fee is paid on creation.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer_with_expiry(
+        &self,
+        to: Account,
+        amount: Tokens128,
+        expires_at: Timestamp,
+        memo: Option<Memo>,
+        from_subaccount: Option<Subaccount>,
+    ) -> Result<ExpiringTransferId, TxError> {
+        EndpointMetrics::instrument("transfer_with_expiry", || {
+            let from = AccountInternal::new(ic::caller(), from_subaccount);
+            expiring_transfers::transfer_with_expiry(
+                from,
+                to.into(),
+                amount,
+                memo,
+                expires_at,
+                self.fee_ratio(),
+            )
+        })
+    }
+
+    /// Receives the tokens of the transfer `id`. Can be called only by the recipient before the
+    /// transfer expires.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn accept_transfer(&self, id: ExpiringTransferId) -> TxReceipt {
+        EndpointMetrics::instrument("accept_transfer", || {
+            expiring_transfers::accept_transfer(ic::caller(), id)
+        })
+    }
+
+    /// Returns the tokens of the expired transfer `id` to the sender. Can be called only by the
+    /// sender.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn reclaim_transfer(&self, id: ExpiringTransferId) -> TxReceipt {
+        EndpointMetrics::instrument("reclaim_transfer", || {
+            expiring_transfers::reclaim_transfer(ic::caller(), id)
+        })
+    }
+
+    #[query(trait = true)]
+    fn get_expiring_transfer(&self, id: ExpiringTransferId) -> Option<ExpiringTransfer> {
+        ExpiringTransfers::get(id)
+    }
+
+    /// Returns the expiring transfers in which `who` is the sender or the recipient.
+    #[query(trait = true)]
+    fn get_expiring_transfers(&self, who: Principal) -> Vec<ExpiringTransfer> {
+        ExpiringTransfers::list(who)
+    }
+
+    /********************** IS20 TRANSACTIONS ***********************/
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer(&self, transfer: TransferArgs) -> Result<u128, TxError> {
+        EndpointMetrics::instrument("transfer", || {
+            let result =
+                CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)
+                    .and_then(|account| is20_transfer(account, &transfer, self.fee_ratio()));
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /// Takes a list of transfers, each of which is a pair of `to` and `value` fields, it returns a `TxReceipt` which contains
+    /// a vec of transaction index or an error message. The list of transfers is processed in the order they are given. if the `fee`
+    /// is set, the `fee` amount is applied to each transfer.
+    /// The balance of the caller is reduced by sum of `value + fee` amount for each transfer. If the total sum of `value + fee` for all transfers,
+    /// is less than the `balance` of the caller, the transaction will fail with `TxError::InsufficientBalance` error.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn batch_transfer(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        transfers: Vec<BatchTransferArgs>,
+    ) -> Result<Vec<TxId>, TxError> {
+        EndpointMetrics::instrument("batch_transfer", || {
+            let result = transfers
+                .iter()
+                .try_for_each(|x| {
+                    CheckedAccount::with_recipient(x.receiver.into(), from_subaccount).map(|_| ())
+                })
+                .and_then(|_| batch_transfer(from_subaccount, transfers, self.fee_ratio()));
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /// Returns the account of the token canister the user of an exchange with the `index` deposits
+    /// the tokens to, see `sweep_subaccounts`.
+    #[query(trait = true)]
+    fn get_exchange_deposit_account(&self, index: u64) -> Account {
+        exchange_deposits::exchange_deposit_account(index)
+    }
+
+    /// Moves the balances of the selected exchange deposit subaccounts, minus the fee, to the `to`
+    /// account, e.g. the hot wallet of the exchange. Every subaccount is swept by a separate
+    /// transaction with the source subaccount as the memo. Returns the result of every subaccount.
+    ///
+    /// This method can be called only by the owner.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn sweep_subaccounts(
+        &self,
+        selection: SweepSelection,
+        to: Account,
+    ) -> Result<Vec<SweepResult>, TxError> {
+        EndpointMetrics::instrument("sweep_subaccounts", || {
+            let result = CheckedPrincipal::owner(&TokenConfig::get_stable()).and_then(|_| {
+                exchange_deposits::sweep_subaccounts(selection, to.into(), self.fee_ratio())
+            });
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /// Applies the transfers, mints and burns all-or-nothing: if any of the operations fails, no
+    /// balance is changed and nothing is written to the ledger. Returns the transaction ids of the
+    /// operations in the same order.
+    ///
+    /// Mints are allowed only for the owner, or for anyone if the token is a test token.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn atomic_batch(&self, operations: Vec<BatchOperation>) -> Result<Vec<TxId>, TxError> {
+        EndpointMetrics::instrument("atomic_batch", || {
+            let caller = ic::caller();
+            let can_mint =
+                self.is_test_token() || CheckedPrincipal::owner(&TokenConfig::get_stable()).is_ok();
+            let result = atomic_batch(caller, operations, can_mint, self.fee_ratio());
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /********************** LARGE TRANSFERS ***********************/
+
+    /// Sets the threshold above which the transfers must be confirmed in two steps, or disables
+    /// the confirmation if `None`.
+    #[update(trait = true)]
+    fn set_large_transfer_policy(
+        &self,
+        policy: Option<LargeTransferPolicy>,
+    ) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if let Some(policy) = &policy {
+            policy.validate()?;
+        }
+
+        self.update_stats(caller, CanisterUpdate::LargeTransferPolicy(policy));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn get_large_transfer_policy(&self) -> Option<LargeTransferPolicy> {
+        TokenConfig::get_stable().large_transfer_policy
+    }
+
+    /// Stores the transfer as pending until it's confirmed with `confirm_large_transfer` by the
+    /// caller or the `cosigner` within the confirmation window. The `fee` and `created_at_time` of
+    /// the `transfer` are ignored, the fee is charged on confirmation.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn initiate_large_transfer(
+        &self,
+        transfer: TransferArgs,
+        cosigner: Option<Principal>,
+    ) -> Result<PendingTransferId, TxError> {
+        EndpointMetrics::instrument("initiate_large_transfer", || {
+            let account =
+                CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)?;
+            validate_memo(&transfer)?;
+            LargeTransfers::initiate(
+                account.inner(),
+                account.recipient(),
+                transfer.amount,
+                transfer.memo,
+                cosigner,
+                ic::time(),
+            )
+        })
+    }
+
+    /// Executes the pending transfer `id`. Must be called by the sender or the co-signer of the
+    /// transfer before it expires.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn confirm_large_transfer(&self, id: PendingTransferId) -> TxReceipt {
+        EndpointMetrics::instrument("confirm_large_transfer", || {
+            let result = confirm_large_transfer(ic::caller(), id, self.fee_ratio());
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /// Removes the pending transfer `id`. Both the sender and the co-signer can cancel it.
+    #[update(trait = true)]
+    fn cancel_large_transfer(&self, id: PendingTransferId) -> Result<(), TxError> {
+        LargeTransfers::cancel(ic::caller(), id)
+    }
+
+    /// Returns the pending transfers in which `who` is the sender or the co-signer.
+    #[query(trait = true)]
+    fn get_pending_transfers(&self, who: Principal) -> Vec<PendingTransfer> {
+        LargeTransfers::list(who)
+    }
+
+    /************************** FAUCET ***************************/
+
+    /// Sets the limits of the minting of the test token by the users other than the owner, or
+    /// removes the limits if `None`.
+    #[update(trait = true)]
+    fn set_faucet_policy(&self, policy: Option<FaucetPolicy>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if let Some(policy) = &policy {
+            policy.validate()?;
+        }
+
+        self.update_stats(caller, CanisterUpdate::FaucetPolicy(policy));
+        Ok(())
+    }
+
+    /// Returns the amount `who` minted today and can still mint with the test token faucet.
+    #[query(trait = true)]
+    fn faucet_status(&self, who: Principal) -> FaucetStatus {
+        Faucet::status(who, ic::time())
+    }
+
+    /************************** DUST ***************************/
+
+    /// Sets the handling of the balances below the dust threshold by the maintenance task, or
+    /// disables it if `None`.
+    #[update(trait = true)]
+    fn set_dust_policy(&self, policy: Option<DustPolicy>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if matches!(policy, Some(policy) if policy.threshold.is_zero()) {
+            return Err(TxError::InvalidDustPolicy {
+                reason: "threshold must be greater than zero".into(),
+            });
+        }
+
+        self.update_stats(caller, CanisterUpdate::DustPolicy(policy));
+        Ok(())
+    }
+
+    /// Returns the totals of the swept dust and the number of the principals holding only dust.
+    #[query(trait = true)]
+    fn get_dust_report(&self) -> DustReport {
+        DustReports::get()
+    }
+
+    /// Sets the share of the amount burned on every transfer, or disables the burn if `None`. If
+    /// the timelock is set, the change is applied only after the timelock delay, see
+    /// `list_pending_changes`.
+    #[update(trait = true)]
+    fn set_burn_policy(&self, policy: Option<BurnPolicy>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if let Some(policy) = policy {
+            policy.validate()?;
+        }
+
+        timelock::update_or_propose(caller, ConfigChange::BurnPolicy(policy))
+    }
+
+    /// Returns the burn on transfer policy, so the explorers can display the burn rate.
+    #[query(trait = true)]
+    fn get_burn_policy(&self) -> Option<BurnPolicy> {
+        TokenConfig::get_stable().burn_policy
+    }
+
+    /// Sets the computation of the transfer fees, or restores the flat `fee` if `None`, see
+    /// `state::fees`. If the timelock is set, the change is applied only after the timelock
+    /// delay, see `list_pending_changes`.
+    #[update(trait = true)]
+    fn set_fee_policy(&self, policy: Option<FeePolicy>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if let Some(policy) = &policy {
+            policy.validate()?;
+        }
+
+        timelock::update_or_propose(caller, ConfigChange::FeePolicy(policy))
+    }
+
+    #[query(trait = true)]
+    fn get_fee_policy(&self) -> Option<FeePolicy> {
+        TokenConfig::get_stable().fee_policy
+    }
+
+    /// Splits the owner part of the transfer fees between the `recipients` by their weights, or
+    /// credits it to `fee_to` again if `recipients` is empty. At most `MAX_FEE_RECIPIENTS`
+    /// recipients can be set. If the timelock is set, the change is applied only after the
+    /// timelock delay, see `list_pending_changes`.
+    #[update(trait = true)]
+    fn set_fee_recipients(&self, recipients: Vec<FeeRecipient>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        FeeRecipients::validate(&recipients)?;
+        timelock::update_or_propose(caller, ConfigChange::FeeRecipients(recipients))
+    }
+
+    /// Returns the recipients of the owner fees and the total fees received by every account.
+    #[query(trait = true)]
+    fn get_fee_distribution(&self) -> FeeDistribution {
+        FeeRecipients::distribution()
+    }
+
+    /// Returns the fee of the transfer of the `amount` from the `from` account to the `to`
+    /// account, which may differ from `icrc1_fee` if a fee policy is set.
+    #[query(trait = true)]
+    fn get_transfer_fee(&self, from: Account, to: Account, amount: Tokens128) -> Tokens128 {
+        TokenConfig::get_stable()
+            .fee_info(from.into(), to.into(), amount)
+            .0
+    }
+
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn mint(
+        &self,
+        to: Principal,
+        to_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+    ) -> TxReceipt {
+        EndpointMetrics::instrument("mint", || {
+            let config = TokenConfig::get_stable();
+            let result = if self.is_test_token() {
+                CheckedPrincipal::test_user(&config)
+                    .and_then(|test_user| mint_test_token(test_user, to, to_subaccount, amount))
+            } else {
+                CheckedPrincipal::owner(&config)
+                    .and_then(|owner| mint_as_owner(owner, to, to_subaccount, amount))
+            };
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /// Burn `amount` of tokens from `from` principal.
+    /// If `from` is None, then caller's tokens will be burned.
+    /// If `from` is Some(_) but method called not by owner, `TxError::Unauthorized` will be returned.
+    /// If owner calls this method and `from` is Some(who), then who's tokens will be burned.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn burn(
+        &self,
+        from: Option<Principal>,
+        from_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+    ) -> TxReceipt {
+        EndpointMetrics::instrument("burn", || {
+            let result = match from {
+                None => burn_own_tokens(from_subaccount, amount),
+                Some(from) if from == canister_sdk::ic_kit::ic::caller() => {
+                    burn_own_tokens(from_subaccount, amount)
+                }
+                Some(from) => CheckedPrincipal::owner(&TokenConfig::get_stable())
+                    .and_then(|caller| burn_as_owner(caller, from, from_subaccount, amount)),
+            };
+            EndpointMetrics::record_result(&result);
+            result
+        })
+    }
+
+    /********************** ICRC-1 METHODS ***********************/
+
+    #[query(trait = true)]
+    fn icrc1_balance_of(&self, account: Account) -> Tokens128 {
+        StableBalances.balance_of(&account.into())
+    }
+
+    /// Returns the certified balance of the `account` with the certificate and the witness to check
+    /// it. The certified balance is updated a few seconds after the balance changes.
+    #[query(trait = true)]
+    fn get_certified_balance(&self, account: Account) -> Result<CertifiedBalance, TxError> {
+        certified_balances::get_certified_balance(account)
+    }
+
+    /// Returns the balances of the `accounts` in the same order. At most `MAX_BALANCES_REQUEST`
+    /// accounts are processed, the rest are ignored.
+    #[query(trait = true)]
+    fn get_balances(&self, accounts: Vec<Account>) -> Vec<Tokens128> {
+        accounts
+            .into_iter()
+            .take(MAX_BALANCES_REQUEST)
+            .map(|account| StableBalances.balance_of(&account.into()))
+            .collect()
+    }
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn icrc1_transfer(&self, transfer: TransferArgs) -> Result<u128, TransferError> {
+        EndpointMetrics::instrument("icrc1_transfer", || {
+            let result =
+                CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)
+                    .and_then(|account| icrc1_transfer(account, &transfer, self.fee_ratio()));
+            EndpointMetrics::record_result(&result);
+
+            Ok(result?)
+        })
+    }
+
+    #[query(trait = true)]
+    fn icrc1_name(&self) -> String {
+        TokenConfig::get_stable().name
+    }
+
+    #[query(trait = true)]
+    fn icrc1_symbol(&self) -> String {
+        TokenConfig::get_stable().symbol
+    }
+
+    #[query(trait = true)]
+    fn icrc1_decimals(&self) -> u8 {
+        TokenConfig::get_stable().decimals
+    }
+
+    /// Changes the number of decimals of the token, rescaling all the balances and the fee. The
+    /// balances are rescaled in batches, so the method must be called with the same
+    /// `new_decimals` until it returns `None`. Transfers, mints and burns are rejected until then.
+    #[update(trait = true)]
+    fn rescale_decimals(&self, new_decimals: u8) -> Result<Option<DecimalsMigration>, TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        let migration = DecimalsMigration::run(caller.inner(), new_decimals)?;
+        // The decimals and the fee are changed with the last batch.
+        if migration.is_none() {
+            http::update_certified_data();
+        }
+
+        Ok(migration)
+    }
+
+    /// Returns the state of the running decimals migration.
+    #[query(trait = true)]
+    fn get_decimals_migration(&self) -> Option<DecimalsMigration> {
+        DecimalsMigration::get()
+    }
+
+    /// Returns the default transfer fee.
+    #[query(trait = true)]
+    fn icrc1_fee(&self) -> Tokens128 {
+        TokenConfig::get_stable().fee
+    }
+    #[query(trait = true)]
+    fn icrc1_metadata(&self) -> Vec<(String, Value)> {
+        TokenConfig::get_stable().icrc1_metadata()
+    }
+
+    #[query(trait = true)]
+    fn icrc1_supported_standards(&self) -> Vec<StandardRecord> {
+        TokenConfig::get_stable().supported_standards()
+    }
+
+    #[query(trait = true)]
+    fn icrc1_minting_account(&self) -> Option<Account> {
+        Some(TokenConfig::get_stable().minting_account().into())
+    }
+
+    /// Returns the canonical burn address. Transfers to it are burns, as the transfers to the
+    /// minting account.
+    #[query(trait = true)]
+    fn get_burn_account(&self) -> Account {
+        burn_account().into()
+    }
+
+    /// Returns the total amount of the burned tokens since the token creation.
+    #[query(trait = true)]
+    fn get_burned_total(&self) -> Tokens128 {
+        LedgerData::burned_total()
+    }
+
+    /// Sets the account used to mint and burn tokens with `icrc1_transfer`. If the timelock is
+    /// set, the change is applied only after the timelock delay, see `list_pending_changes`.
+    #[update(trait = true)]
+    fn set_minting_account(&self, account: Account) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        timelock::update_or_propose(caller, ConfigChange::MintingAccount(account))
+    }
+
+    /********************** SIGNED TRANSFERS ***********************/
+
+    /// Executes a transfer signed by the owner of the tokens offline. The caller only relays the
+    /// signed payload and pays for the message. See `canister::signed_transfer` module for the
+    /// format of the signed message.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer_signed(&self, signed: SignedTransfer) -> Result<u128, TransferError> {
+        EndpointMetrics::instrument("transfer_signed", || {
+            let result = signed_transfer::transfer_signed(&signed, self.fee_ratio());
+            EndpointMetrics::record_result(&result);
+
+            Ok(result?)
+        })
+    }
+
+    /// Returns the nonce the next signed transfer of the `signer` must have.
+    #[query(trait = true)]
+    fn get_transfer_nonce(&self, signer: Principal) -> u64 {
+        TransferNonces::get(signer)
+    }
+
+    /********************** ALLOWANCES ***********************/
+
+    /// Sets the allowance of the `spender` to transfer from the caller's account until
+    /// `expires_at`, or removes it if `amount` is zero. The allowance without `expires_at` doesn't
+    /// expire. The caller's account must hold tokens to set an allowance.
+    #[update(trait = true)]
+    fn approve(
+        &self,
+        spender: Principal,
+        amount: Tokens128,
+        from_subaccount: Option<Subaccount>,
+        expires_at: Option<Timestamp>,
+    ) -> Result<(), TxError> {
+        approvals::approve(
+            AccountInternal::new(ic::caller(), from_subaccount),
+            spender,
+            amount,
+            expires_at,
+            ic::time(),
+        )
+    }
+
+    /// Sets the allowance of the `spender` canister like `approve` and calls its `on_approval`
+    /// method with the `payload`. The allowance stays set if the call fails, and the returned
+    /// notification has the result of the call.
+    #[update(trait = true)]
+    fn approve_and_notify<'a>(
+        &'a self,
+        spender: Principal,
+        amount: Tokens128,
+        payload: Vec<u8>,
+        from_subaccount: Option<Subaccount>,
+    ) -> AsyncReturn<'a, Result<ApprovalNotification, TxError>> {
+        let owner = AccountInternal::new(ic::caller(), from_subaccount);
+        Box::pin(
+            async move { approvals::approve_and_notify(owner, spender, amount, payload).await },
+        )
+    }
+
+    /// Calls the spender canister again for the caller's failed notification `id`.
+    #[update(trait = true)]
+    fn retry_approval_notification<'a>(
+        &'a self,
+        id: ApprovalNotificationId,
+    ) -> AsyncReturn<'a, Result<ApprovalNotification, TxError>> {
+        let caller = ic::caller();
+        Box::pin(async move { approvals::retry_approval_notification(caller, id).await })
+    }
+
+    /// Sets the allowance signed by the owner offline. The caller, usually the spender, only
+    /// relays the permit and pays for the message. See `canister::permit` module for the format of
+    /// the signed message.
+    #[update(trait = true)]
+    fn permit(&self, permit: Permit) -> Result<(), TxError> {
+        permit::permit(&permit, ic::time()).map(|_| ())
+    }
+
+    /// Returns the nonce the next permit of the `owner` must have.
+    #[query(trait = true)]
+    fn get_permit_nonce(&self, owner: Principal) -> u64 {
+        PermitNonces::get(owner)
+    }
+
+    #[query(trait = true)]
+    fn get_approval_notification(
+        &self,
+        id: ApprovalNotificationId,
+    ) -> Option<ApprovalNotification> {
+        Allowances::get_notification(id)
+    }
+
+    /// Returns the allowance of the `spender`, zero if it expired.
+    #[query(trait = true)]
+    fn get_allowance(&self, owner: Account, spender: Principal) -> Tokens128 {
+        Allowances::get(owner.into(), spender, ic::time())
+    }
+
+    /// Returns the number of the stored allowances, including the expired ones not removed by the
+    /// timer task yet.
+    #[query(trait = true)]
+    fn get_allowance_count(&self) -> u64 {
+        Allowances::count()
+    }
+
+    /// Returns up to `limit` allowances given by the `owner` account starting from `offset`. The
+    /// expired allowances are skipped.
+    #[query(trait = true)]
+    fn get_user_approvals(&self, owner: Account, offset: u64, limit: u64) -> Vec<AllowanceEntry> {
+        Allowances::list_by_owner(owner.into(), offset as usize, limit as usize, ic::time())
+    }
+
+    /// Returns up to `limit` allowances given to the `spender` starting from `offset`. The expired
+    /// allowances are skipped.
+    #[query(trait = true)]
+    fn get_spender_allowances(
+        &self,
+        spender: Principal,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<AllowanceEntry> {
+        Allowances::list_by_spender(spender, offset as usize, limit as usize, ic::time())
+    }
+
+    /// Returns the number of the expired allowances removed by the timer task.
+    #[query(trait = true)]
+    fn get_allowance_sweep_stats(&self) -> AllowanceSweepStats {
+        Allowances::sweep_stats()
+    }
+
+    /// Transfers `amount` from the `from` account to the `to` account on behalf of the caller, who
+    /// must be approved by the owner of the `from` account. The transfer fee is paid by the `from`
+    /// account.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer_from_allowance(
+        &self,
+        from: Account,
+        to: Account,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxReceipt {
+        EndpointMetrics::instrument("transfer_from_allowance", || {
+            approvals::transfer_from_allowance(
+                from.into(),
+                to.into(),
+                amount,
+                memo,
+                self.fee_ratio(),
+            )
+        })
+    }
+
+    /********************** AUCTION ***********************/
+
+    /// Sets the distribution of the auction rewards between the bidders.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_auction_strategy(&self, strategy: AuctionStrategy) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        strategy.validate()?;
+        self.update_stats(caller, CanisterUpdate::AuctionStrategy(strategy));
+        Ok(())
+    }
+
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_auction_strategy(&self) -> AuctionStrategy {
+        TokenConfig::get_stable()
+            .auction_strategy
+            .unwrap_or_default()
+    }
+
+    /// Returns up to `limit` stored auctions starting from `offset`, oldest first, and the totals
+    /// over all the auctions held.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn list_auctions(&self, offset: u64, limit: u64) -> AuctionsPage {
+        AuctionHistory::list(offset as usize, limit as usize)
+    }
+
+    /// Moves `amount` of the tokens left on the auction account (e.g. the rounding remainders of
+    /// the distributions), or all of them, to the `fee_to` account. Only allowed while the
+    /// auction in progress has no bids. The withdrawal is recorded in the ledger and in the
+    /// auction history.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn withdraw_auction_residual(
+        &self,
+        amount: Option<Tokens128>,
+    ) -> Result<ResidualWithdrawal, TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        is20_auction::withdraw_residual(&self.auction_state().borrow(), caller.inner(), amount)
+    }
+
+    /// Returns up to `limit` withdrawals of the auction residual starting from `offset`, oldest
+    /// first.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn list_auction_residual_withdrawals(
+        &self,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<ResidualWithdrawal> {
+        AuctionHistory::list_residual_withdrawals(offset as usize, limit as usize)
+    }
+
+    /// Sets the limits of the stored auction history. The auctions exceeding the limits are
+    /// pruned immediately and after every auction.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_auction_retention(&self, policy: Option<RetentionPolicy>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        self.update_stats(caller, CanisterUpdate::AuctionRetention(policy));
+        if let Some(policy) = &policy {
+            AuctionHistory::prune(policy, ic::time());
+        }
+        Ok(())
+    }
+
+    /// Sets the minimum amount of cycles of an auction bid. Zero removes the limit.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_min_bid_cycles(&self, cycles: u64) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        AuctionPolicy::set_min_bid_cycles(caller.inner(), cycles);
+        Ok(())
+    }
+
+    /// Estimates the reward of the caller if they bid `bid_cycles` more and the auction was held
+    /// now, with the current bids and the fees accumulated so far.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn auction_projection(&self, bid_cycles: u64) -> AuctionProjection {
+        is20_auction::project(&self.auction_state().borrow(), ic::caller(), bid_cycles)
+    }
+
+    /// Returns the bid of the caller in the auction in progress and the time of the next auction.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn my_bid_status(&self) -> BidStatus {
+        is20_auction::bid_status(&self.auction_state().borrow(), ic::caller())
+    }
+
+    /// Sets the number of days the auction rewards vest over, or credits them at once if `None`.
+    /// Applies to the rewards of the next auctions.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_auction_vesting(&self, days: Option<u32>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if matches!(days, Some(days) if days == 0 || days > MAX_AUCTION_VESTING_DAYS) {
+            return Err(TxError::InvalidVestingPeriod {
+                max_days: MAX_AUCTION_VESTING_DAYS,
+            });
+        }
+
+        self.update_stats(caller, CanisterUpdate::AuctionVesting(days));
+        Ok(())
+    }
+
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_auction_vesting(&self) -> Option<u32> {
+        TokenConfig::get_stable().auction_vesting_days
+    }
+
+    /// Returns the vested and the locked auction rewards of the `bidder`, and the part of them
+    /// which can be claimed with `claim_vested_rewards`.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_vested_rewards(&self, bidder: Principal) -> VestingBalance {
+        Vesting::balance(bidder, ic::time())
+    }
+
+    /// Transfers the vested auction rewards of the caller to their default account.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn claim_vested_rewards(&self) -> TxReceipt {
+        is20_auction::claim_vested_rewards(ic::caller())
+    }
+
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_min_bid_cycles(&self) -> u64 {
+        AuctionPolicy::min_bid_cycles()
+    }
+
+    /// Returns the cycles balance of the token, its average consumption and the auction fee ratio
+    /// derived from it.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_cycle_consumption(&self) -> CycleConsumption {
+        is20_auction::cycle_consumption(&self.auction_state().borrow(), ic::balance())
+    }
+
+    /// Adds the `bidder` to the auction blacklist, or removes it if `blacklisted` is false. The
+    /// blacklisted bidders can't bid, and don't receive the rewards of the running auction.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_bidder_blacklisted(&self, bidder: Principal, blacklisted: bool) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        AuctionPolicy::set_blacklisted(caller.inner(), bidder, blacklisted, ic::time());
+        Ok(())
+    }
+
+    /// Returns up to `limit` blacklisted bidders starting from `offset`, with the time they were
+    /// blacklisted.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_bidder_blacklist(&self, offset: u64, limit: u64) -> Vec<(Principal, Timestamp)> {
+        AuctionPolicy::blacklist(offset as usize, limit as usize)
+    }
+
+    /// Offers the `delegate`, e.g. a canister pooling the cycles of several bidders, to bid in the
+    /// auctions on behalf of the caller, or removes the delegation if `None`. Once the delegate
+    /// accepts the delegation, the rewards of its bids are distributed to the caller.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_bid_delegate(&self, delegate: Option<Principal>) -> Result<(), TxError> {
+        BidDelegates::set(ic::caller(), delegate)
+    }
+
+    /// Accepts the delegation offered to the caller by the `delegator`. A principal can bid on
+    /// behalf of one delegator at a time.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn accept_bid_delegation(&self, delegator: Principal) -> Result<(), TxError> {
+        BidDelegates::accept(ic::caller(), delegator)
+    }
+
+    /// Ends the delegation accepted by the caller, so the rewards of its bids are distributed to
+    /// the caller again.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn resign_bid_delegation(&self) -> Result<(), TxError> {
+        BidDelegates::resign(ic::caller())
+    }
+
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_bid_delegate(&self, delegator: Principal) -> Option<BidDelegation> {
+        BidDelegates::get(delegator)
+    }
+
+    /********************** HTTP GATEWAY ***********************/
+
+    /// Serves read-only JSON documents with the token data. See `canister::http` module for the
+    /// list of the routes.
+    #[query(trait = true)]
+    fn http_request(&self, request: HttpRequest) -> HttpResponse {
+        http::http_request(request)
+    }
+
+    /********************** INTERNAL METHODS ***********************/
+
+    // Important: This function *must* be defined to be the
+    // last one in the trait because it depends on the order
+    // of expansion of update/query(trait = true) methods.
+    fn get_idl() -> Idl {
+        generate_idl!()
+    }
+
+    fn update_stats(&self, caller: CheckedPrincipal<Owner>, update: CanisterUpdate) {
+        apply_update(caller.inner(), update);
+    }
+
+    fn fee_ratio(&self) -> f64 {
+        #[cfg(feature = "auction")]
+        return self.bidding_info().fee_ratio;
+
+        #[cfg(not(feature = "auction"))]
+        0.0
+    }
+}
+
+generate_exports!(TokenCanisterAPI, TokenCanisterExports);
+
+/// Applies the configuration `update` made by the owner `caller` and records it in the admin log.
+fn apply_update(caller: Principal, update: CanisterUpdate) {
+    use CanisterUpdate::*;
+    let mut stats = TokenConfig::get_stable();
+    let text = |value: &str| Some(Value::Text(value.to_string()));
+    let tokens = |value: Tokens128| Some(Value::Nat(value.amount.into()));
+    let principal = |value: Principal| Some(Value::Text(value.to_text()));
+
+    let (action, old_value, new_value) = match update {
+        Name(name) => {
+            let old = std::mem::replace(&mut stats.name, name);
+            (AdminAction::SetName, text(&old), text(&stats.name))
+        }
+        Symbol(symbol) => {
+            let old = std::mem::replace(&mut stats.symbol, symbol);
+            (AdminAction::SetSymbol, text(&old), text(&stats.symbol))
+        }
+        Fee(fee) => {
+            let old = std::mem::replace(&mut stats.fee, fee);
+            (AdminAction::SetFee, tokens(old), tokens(fee))
+        }
+        FeeTo(fee_to) => {
+            let old = std::mem::replace(&mut stats.fee_to, fee_to);
+            (AdminAction::SetFeeTo, principal(old), principal(fee_to))
+        }
+        Owner(owner) => {
+            let old = std::mem::replace(&mut stats.owner, owner);
+            (AdminAction::SetOwner, principal(old), principal(owner))
+        }
+        RenounceOwnership(owner, keep_admin_rights) => {
+            let old = std::mem::replace(&mut stats.owner, owner);
+            stats.ownership_renounced = Some(!keep_admin_rights);
+            (
+                AdminAction::RenounceOwnership { keep_admin_rights },
+                principal(old),
+                principal(owner),
+            )
+        }
+        MinCycles(min_cycles) => {
+            let old = std::mem::replace(&mut stats.min_cycles, min_cycles);
+            (
+                AdminAction::SetMinCycles,
+                Some(Value::Nat(old.into())),
+                Some(Value::Nat(min_cycles.into())),
+            )
+        }
+        MetadataEntry(key, value) => {
+            let old = stats
+                .metadata_entries
+                .get_or_insert_with(Default::default)
+                .insert(key.clone(), value.clone());
+            (AdminAction::SetMetadataEntry { key }, old, Some(value))
+        }
+        RemoveMetadataEntry(key) => {
+            let old = stats
+                .metadata_entries
+                .as_mut()
+                .and_then(|entries| entries.remove(&key));
+            (AdminAction::RemoveMetadataEntry { key }, old, None)
+        }
+        LocalizedMetadata(locale, metadata) => {
+            let locales = stats
+                .localized_metadata
+                .get_or_insert_with(Default::default);
+            let old = match &metadata {
+                Some(metadata) => locales.insert(locale.clone(), metadata.clone()),
+                None => locales.remove(&locale),
+            };
+            let localized = |metadata: Option<LocalizedMetadata>| {
+                metadata.map(|metadata| Value::Text(format!("{metadata:?}")))
+            };
+            (
+                AdminAction::SetLocalizedMetadata { locale },
+                localized(old),
+                localized(metadata),
+            )
+        }
+        MintingAccount(account) => {
+            let old = stats.minting_account();
+            stats.minting_account = Some(account);
+            (
+                AdminAction::SetMintingAccount,
+                text(&old.to_string()),
+                text(&AccountInternal::from(account).to_string()),
+            )
+        }
+        HistoryRetention(policy) => {
+            let old = std::mem::replace(&mut stats.history_retention, policy);
+            let retention = |policy: Option<RetentionPolicy>| {
+                policy.map(|policy| Value::Text(format!("{policy:?}")))
+            };
+            (
+                AdminAction::SetHistoryRetention,
+                retention(old),
+                retention(policy),
+            )
+        }
+        IcpLedger(ledger) => {
+            let old = std::mem::replace(&mut stats.icp_ledger, ledger);
+            (
+                AdminAction::SetIcpLedger,
+                old.and_then(principal),
+                ledger.and_then(principal),
+            )
+        }
+        WrappedToken(token) => {
+            let old = std::mem::replace(&mut stats.wrapped_token, token);
+            (
+                AdminAction::SetWrappedToken,
+                old.and_then(principal),
+                token.and_then(principal),
+            )
+        }
+        LargeTransferPolicy(policy) => {
+            let old = std::mem::replace(&mut stats.large_transfer_policy, policy);
+            (
+                AdminAction::SetLargeTransferPolicy,
+                old.map(|old| Value::Text(format!("{old:?}"))),
+                policy.map(|policy| Value::Text(format!("{policy:?}"))),
+            )
+        }
+        FaucetPolicy(policy) => {
+            let old = std::mem::replace(&mut stats.faucet_policy, policy);
+            (
+                AdminAction::SetFaucetPolicy,
+                old.map(|old| Value::Text(format!("{old:?}"))),
+                policy.map(|policy| Value::Text(format!("{policy:?}"))),
+            )
+        }
+        DustPolicy(policy) => {
+            let old = std::mem::replace(&mut stats.dust_policy, policy);
+            (
+                AdminAction::SetDustPolicy,
+                old.map(|old| Value::Text(format!("{old:?}"))),
+                policy.map(|policy| Value::Text(format!("{policy:?}"))),
+            )
+        }
+        InputLimits(limits) => {
+            let old = stats.input_limits.replace(limits).unwrap_or_default();
+            (
+                AdminAction::SetInputLimits,
+                text(&format!("{old:?}")),
+                text(&format!("{limits:?}")),
+            )
+        }
+        TxWindow(window) => {
+            let old = stats.tx_window.replace(window).unwrap_or_default();
+            (
+                AdminAction::SetTxWindow,
+                text(&format!("{old:?}")),
+                text(&format!("{window:?}")),
+            )
+        }
+        AuctionVesting(days) => {
+            let old = std::mem::replace(&mut stats.auction_vesting_days, days);
+            let value = |days: Option<u32>| days.map(|days| Value::Nat(days.into()));
+            (AdminAction::SetAuctionVesting, value(old), value(days))
+        }
+        AuctionStrategy(strategy) => {
+            let old = stats.auction_strategy.replace(strategy).unwrap_or_default();
+            (
+                AdminAction::SetAuctionStrategy,
+                text(&format!("{old:?}")),
+                text(&format!("{strategy:?}")),
+            )
+        }
+        MemoIndex(enabled) => {
+            let old = stats.memo_index.replace(enabled).unwrap_or(false);
+            (
+                AdminAction::SetMemoIndex,
+                text(&old.to_string()),
+                text(&enabled.to_string()),
+            )
+        }
+        AuctionRetention(policy) => {
+            let old = std::mem::replace(&mut stats.auction_retention, policy);
+            let retention = |policy: Option<RetentionPolicy>| {
+                policy.map(|policy| Value::Text(format!("{policy:?}")))
+            };
+            (
+                AdminAction::SetAuctionRetention,
+                retention(old),
+                retention(policy),
+            )
+        }
+        BurnPolicy(policy) => {
+            let old = std::mem::replace(&mut stats.burn_policy, policy);
+            let rate = |policy: Option<crate::state::config::BurnPolicy>| {
+                policy.map(|policy| Value::Nat(policy.rate_bps.into()))
+            };
+            (AdminAction::SetBurnPolicy, rate(old), rate(policy))
+        }
+        FeePolicy(policy) => {
+            let describe = |policy: &Option<crate::state::fees::FeePolicy>| {
+                policy
+                    .as_ref()
+                    .map(|policy| Value::Text(format!("{policy:?}")))
+            };
+            let new = describe(&policy);
+            let old = std::mem::replace(&mut stats.fee_policy, policy);
+            (AdminAction::SetFeePolicy, describe(&old), new)
+        }
+        VotingExclusions(excluded) => {
+            let principals = |principals: Vec<Principal>| {
+                Value::Text(
+                    principals
+                        .iter()
+                        .map(Principal::to_text)
+                        .collect::<Vec<_>>()
+                        .join(","),
+                )
+            };
+            let old = stats.voting_exclusions.replace(excluded.clone());
+            (
+                AdminAction::SetVotingExclusions,
+                old.map(principals),
+                Some(principals(excluded)),
+            )
+        }
+        TimelockDelay(delay) => {
+            let old = std::mem::replace(&mut stats.timelock_delay_nanos, delay);
+            let nanos = |delay: Option<u64>| delay.map(|delay| Value::Nat(delay.into()));
+            (AdminAction::SetTimelockDelay, nanos(old), nanos(delay))
+        }
+    };
+
+    TokenConfig::set_stable(stats);
+    AdminLog::record(caller, action, old_value, new_value);
+    http::update_certified_data();
+}
+
+#[cfg(feature = "auction")]
+use canister_sdk::ic_storage::IcStorage;
+
+#[cfg(feature = "auction")]
+impl Auction for TokenCanisterExports {
+    fn auction_state(&self) -> std::rc::Rc<std::cell::RefCell<AuctionState>> {
+        AuctionState::get()
+    }
+
+    fn disburse_rewards(&self) -> Result<AuctionInfo, AuctionError> {
+        is20_auction::disburse_rewards(&self.auction_state().borrow())
+    }
+}
+
+/// Accounts of the token canister itself (e.g. the swap escrows) and of the management canister
+/// (the auction account and the burn address) are never swept as dust and are exempt from the
+/// burn on transfer.
+pub(crate) fn is_service_account(owner: Principal) -> bool {
+    owner == ic::id() || owner == Principal::management_canister()
+}
+
+pub fn auction_account() -> AccountInternal {
+    // There are no sub accounts for the auction principal
+    AccountInternal::new(Principal::management_canister(), None)
+}
+
+/// Subaccount of the management canister used as the burn address.
+pub const BURN_SUBACCOUNT: Subaccount = {
+    let mut subaccount = [0; 32];
+    subaccount[30] = 0xde;
+    subaccount[31] = 0xad;
+    subaccount
+};
+
+/// Canonical burn address. The tokens sent to it with `icrc1_transfer` are burned.
+pub fn burn_account() -> AccountInternal {
+    AccountInternal::new(Principal::management_canister(), Some(BURN_SUBACCOUNT))
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_canister::canister_call;
+    use canister_sdk::ic_kit::inject::get_context;
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    #[cfg(feature = "claim")]
+    use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
+
+    use crate::mock::TokenCanisterMock;
+    use crate::state::expiring_transfers::ExpiringTransferStatus;
+    use crate::state::guardians::MIN_RECOVERY_DELAY_NANOS;
+    use crate::state::ledger::Operation;
+    use crate::{account::DEFAULT_SUBACCOUNT, state::config::Metadata};
+
+    use super::*;
+
+    // Method for generating random Subaccount.
+    #[cfg(feature = "claim")]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn gen_subaccount() -> Subaccount {
+        use rand::{thread_rng, Rng};
+
+        let mut subaccount = [0u8; 32];
+        thread_rng().fill(&mut subaccount);
+        subaccount
+    }
+
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn test_context() -> (&'static MockContext, TokenCanisterMock) {
+        let context = MockContext::new().with_caller(john()).inject();
+
+        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
+        let canister = TokenCanisterMock::from_principal(principal);
+
+        // Refresh canister's state.
+        TokenConfig::set_stable(TokenConfig::default());
+        StableBalances.clear();
+        LedgerData::clear();
+
+        // Due to this update, init() code will get actual
+        // principal of the canister from ic::id().
+        context.update_id(canister.principal());
+
+        canister.init(
+            Metadata {
+                name: "".to_string(),
+                symbol: "".to_string(),
+                decimals: 8,
+
+                owner: john(),
+                fee: Tokens128::from(0),
+                fee_to: john(),
+                is_test_token: None,
+                init_spec: None,
+            },
+            Tokens128::from(1000),
+        );
+
+        // This is to make tests that don't rely on auction state
+        // pass, because since we are running auction state on each
+        // endpoint call, it affects `BiddingInfo.fee_ratio` that is
+        // used for charging fees in `approve` endpoint.
+        let mut stats = TokenConfig::get_stable();
+        stats.min_cycles = 0;
+        TokenConfig::set_stable(stats);
+
+        canister.mint(alice(), None, 1000.into()).unwrap();
+        context.update_caller(alice());
+
+        (context, canister)
+    }
+
+    fn test_canister() -> TokenCanisterMock {
+        let context = MockContext::new().with_caller(alice()).inject();
+
+        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
+        let canister = TokenCanisterMock::from_principal(principal);
+        context.update_id(canister.principal());
+
+        // Refresh canister's state.
+        TokenConfig::set_stable(TokenConfig::default());
+        StableBalances.clear();
+        LedgerData::clear();
+
+        canister.init(
+            Metadata {
+                name: "".to_string(),
+                symbol: "".to_string(),
+                decimals: 8,
+                owner: alice(),
+                fee: Tokens128::from(0),
+                fee_to: alice(),
+                is_test_token: None,
+                init_spec: None,
+            },
+            Tokens128::from(1000),
+        );
+
+        let mut stats = TokenConfig::get_stable();
+        stats.min_cycles = 0;
+        TokenConfig::set_stable(stats);
+
+        canister
+    }
+
+    #[test]
+    fn transfer_to_same_account() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: alice().into(),
+            amount: 100.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+
+        let res = canister.icrc1_transfer(transfer);
+        assert_eq!(
+            res,
+            Err(TransferError::GenericError {
+                error_code: 500,
+                message: "self transfer".into()
+            })
+        )
+    }
+
+    #[test]
+    fn transfer_to_same_default_subaccount() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: Some(crate::account::DEFAULT_SUBACCOUNT),
+            to: alice().into(),
+            amount: 100.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+
+        let res = canister.icrc1_transfer(transfer);
+        assert_eq!(
+            res,
+            Err(TransferError::GenericError {
+                error_code: 500,
+                message: "self transfer".into()
+            })
+        );
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::new(alice(), Some(DEFAULT_SUBACCOUNT)),
+            amount: 100.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+
+        let res = canister.icrc1_transfer(transfer);
+        assert_eq!(
+            res,
+            Err(TransferError::GenericError {
+                error_code: 500,
+                message: "self transfer".into()
+            })
+        );
+    }
+
+    #[cfg(feature = "claim")]
+    #[test]
+    fn test_claim() {
+        let bob_sub = gen_subaccount();
+        let alice_sub = gen_subaccount();
+
+        let alice_aid =
+            AccountIdentifier::new(alice().into(), Some(SubaccountIdentifier(alice_sub)));
+        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
+
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+
+        assert!(canister
+            .mint(
+                canister.owner(),
+                Some(alice_aid.to_address()),
+                Tokens128::from(1000)
+            )
+            .is_ok());
+        assert!(canister
+            .mint(
+                canister.owner(),
+                Some(bob_aid.to_address()),
+                Tokens128::from(2000)
+            )
+            .is_ok());
+
+        ctx.update_caller(alice());
+        assert_eq!(
+            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
+            Tokens128::from(1000)
+        );
+
+        let balance_before = canister.icrc1_balance_of(alice().into());
+        canister.claim(canister.owner(), Some(alice_sub)).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(alice().into()),
+            (Tokens128::from(1000) + balance_before).unwrap()
+        );
+        assert_eq!(
+            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
+            0.into()
+        );
+
+        ctx.update_caller(bob());
+        assert_eq!(
+            canister.get_claimable_amount(canister.owner(), Some(bob_sub)),
+            Tokens128::from(2000)
+        );
+    }
+
+    // **** APIs tests ****
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_name() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.set_name("War and Piece".to_string()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.name, "War and Piece".to_string());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_name("Crime and Punishment".to_string()), Result<(), TxError>)
+            .await
+            .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.name, "War and Piece".to_string());
+        let name = canister_call!(canister.icrc1_name(), String).await.unwrap();
+        assert_eq!(name, "War and Piece".to_string());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_symbol() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.set_symbol("MAX".to_string()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.symbol, "MAX".to_string());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_symbol("BOB".to_string()), Result<(), TxError>)
+            .await
+            .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.symbol, "MAX".to_string());
+        let symbol = canister_call!(canister.icrc1_symbol(), String)
+            .await
+            .unwrap();
+        assert_eq!(symbol, "MAX".to_string());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_fee() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.set_fee(100500.into()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee, 100500.into());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_fee(0.into()), Result<(), TxError>)
+            .await
+            .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee, 100500.into());
+        let fee = canister_call!(canister.icrc1_fee(), Tokens128)
+            .await
+            .unwrap();
+        assert_eq!(fee, 100500.into());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn timelocked_changes() {
+        let (ctx, canister) = test_context();
+        Timelock::clear();
+        ctx.update_id(john());
+        const DELAY: u64 = 1_000;
+        canister_call!(canister.set_timelock_delay(Some(DELAY)), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(TokenConfig::get_stable().timelock_delay_nanos, Some(DELAY));
+
+        canister_call!(canister.set_fee(100.into()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        canister_call!(canister.set_owner(alice()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        canister_call!(canister.set_timelock_delay(None), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let pending = canister_call!(canister.list_pending_changes(), Vec<PendingChange>)
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 3);
+        assert_eq!(TokenConfig::get_stable().fee, 0.into());
+        assert_eq!(TokenConfig::get_stable().owner, john());
+
+        canister_call!(canister.cancel_proposal(pending[1].id), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(timelock::apply_due_changes(ic::time() + DELAY - 1), 0);
+        assert_eq!(timelock::apply_due_changes(ic::time() + DELAY), 2);
+
+        let config = TokenConfig::get_stable();
+        assert_eq!(config.fee, 100.into());
+        assert_eq!(config.owner, john());
+        assert_eq!(config.timelock_delay_nanos, None);
+        assert!(Timelock::list().is_empty());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_fee_to() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.set_fee_to(alice()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee_to, alice());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_fee_to(bob()), Result<(), TxError>)
+            .await
+            .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee_to, alice());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn fee_recipients() {
+        let (ctx, canister) = test_context();
+        let treasury = Account::new(john(), Some([1; 32]));
+        let dev_fund = Account::new(john(), Some([2; 32]));
+        let recipients = vec![
+            FeeRecipient {
+                account: treasury,
+                weight: 70,
+            },
+            FeeRecipient {
+                account: dev_fund,
+                weight: 30,
+            },
+        ];
+
+        ctx.update_id(bob());
+        let res = canister_call!(
+            canister.set_fee_recipients(recipients.clone()),
+            Result<(), TxError>
+        )
+        .await
+        .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+
+        ctx.update_id(john());
+        canister_call!(canister.set_fee(10.into()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        canister_call!(
+            canister.set_fee_recipients(recipients.clone()),
+            Result<(), TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        ctx.update_id(alice());
+        canister_call!(
+            canister.transfer(TransferArgs {
+                from_subaccount: None,
+                to: bob().into(),
+                amount: 100.into(),
+                fee: None,
+                memo: None,
+                created_at_time: None,
+            }),
+            TxReceipt
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(canister.icrc1_balance_of(treasury), 7.into());
+        assert_eq!(canister.icrc1_balance_of(dev_fund), 3.into());
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 890.into());
+
+        let distribution = canister_call!(canister.get_fee_distribution(), FeeDistribution)
+            .await
+            .unwrap();
+        assert_eq!(distribution.recipients, recipients);
+        assert_eq!(
+            distribution.received,
+            vec![(treasury, 7.into()), (dev_fund, 3.into())]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn timelocked_fee_recipients() {
+        let (ctx, canister) = test_context();
+        Timelock::clear();
+        FeeRecipients::clear();
+        ctx.update_id(john());
+        const DELAY: u64 = 1_000;
+        canister_call!(canister.set_timelock_delay(Some(DELAY)), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let recipients = vec![FeeRecipient {
+            account: alice().into(),
+            weight: 1,
+        }];
+        canister_call!(
+            canister.set_fee_recipients(recipients.clone()),
+            Result<(), TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(FeeRecipients::get().is_empty());
+
+        assert_eq!(timelock::apply_due_changes(ic::time() + DELAY), 1);
+        assert_eq!(FeeRecipients::get(), recipients);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_owner() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.set_owner(alice()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.owner, alice());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_owner(bob()), Result<(), TxError>)
+            .await
+            .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.owner, alice());
+        let owner = canister_call!(canister.owner(), Principal).await.unwrap();
+        assert_eq!(owner, alice());
+
+        let minting_account = canister_call!(canister.icrc1_minting_account(), Principal)
+            .await
+            .unwrap();
+        assert_eq!(minting_account, Some(alice().into()));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn renounce_ownership() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        let args = RenounceOwnershipArgs {
+            new_owner: Principal::management_canister(),
+            keep_admin_rights: false,
+            confirm_symbol: "".into(),
+            confirm_irreversible: false,
+        };
+        let res = canister_call!(canister.renounce_ownership(args.clone()), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TxError::RenouncementNotConfirmed { .. })));
+
+        let args = RenounceOwnershipArgs {
+            confirm_irreversible: true,
+            ..args
+        };
+        canister_call!(canister.renounce_ownership(args), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let owner = canister_call!(canister.owner(), Principal).await.unwrap();
+        assert_eq!(owner, Principal::management_canister());
+
+        let res = canister_call!(canister.set_fee(10.into()), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::OwnershipRenounced));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn list_subaccounts() {
+        let canister = test_canister();
+        let subaccount: Subaccount = [1; 32];
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: Account::new(alice(), Some(subaccount)),
+                amount: 100.into(),
+                fee: None,
+                memo: None,
+                created_at_time: None,
+            })
+            .unwrap();
+
+        get_context().update_id(alice());
+        let list = canister_call!(canister.list_subaccounts(), std::collections::HashMap<Subaccount, Tokens128>).await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[&DEFAULT_SUBACCOUNT], 900.into());
+        assert_eq!(list[&subaccount], 100.into());
+    }
+
+    #[test]
+    fn get_balances() {
+        let canister = test_canister();
+        let accounts = vec![
+            Account::new(alice(), None),
+            Account::new(bob(), None),
+            Account::new(alice(), None),
+        ];
+        assert_eq!(
+            canister.get_balances(accounts),
+            vec![1000.into(), 0.into(), 1000.into()]
+        );
+
+        let accounts = vec![Account::new(alice(), None); MAX_BALANCES_REQUEST + 1];
+        assert_eq!(canister.get_balances(accounts).len(), MAX_BALANCES_REQUEST);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_localized_metadata() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+
+        let french = LocalizedMetadata {
+            name: Some("Jeton".to_string()),
+            symbol: None,
+            description: Some("Un jeton".to_string()),
+        };
+        canister_call!(canister.set_localized_metadata("fr".to_string(), Some(french)), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let metadata = canister_call!(canister.icrc1_metadata(), Vec<(String, Value)>)
+            .await
+            .unwrap();
+        assert!(metadata.contains(&(
+            "is20:localized:fr:name".to_string(),
+            Value::Text("Jeton".to_string())
+        )));
+        assert!(metadata.contains(&(
+            "is20:localized:fr:description".to_string(),
+            Value::Text("Un jeton".to_string())
+        )));
+        assert_eq!(metadata.len(), 6);
+
+        let res = canister_call!(canister.set_localized_metadata("fr FR".to_string(), Some(LocalizedMetadata { name: Some("Jeton".to_string()), ..Default::default() })), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TxError::InvalidMetadataEntry { .. })));
+
+        let res = canister_call!(canister.set_localized_metadata("de".to_string(), Some(LocalizedMetadata::default())), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TxError::InvalidMetadataEntry { .. })));
+
+        let res = canister_call!(canister.set_metadata_entry("is20:localized:de:name".to_string(), Value::Text("Token".to_string())), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TxError::InvalidMetadataEntry { .. })));
+
+        canister_call!(canister.set_localized_metadata("fr".to_string(), None), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let metadata = canister_call!(canister.icrc1_metadata(), Vec<(String, Value)>)
+            .await
+            .unwrap();
+        assert_eq!(metadata.len(), 4);
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_localized_metadata("fr".to_string(), None), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_metadata_entry() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+
+        let logo = Value::Text("data:image/png;base64,iVBORw0KGgo=".to_string());
+        canister_call!(canister.set_metadata_entry("icrc1:logo".to_string(), logo.clone()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let metadata = canister_call!(canister.icrc1_metadata(), Vec<(String, Value)>)
+            .await
+            .unwrap();
+        assert!(metadata.contains(&("icrc1:logo".to_string(), logo)));
+
+        let res = canister_call!(canister.set_metadata_entry("icrc1:logo".to_string(), Value::Text("https://logo.png".to_string())), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TxError::InvalidMetadataEntry { .. })));
+
+        let res = canister_call!(canister.set_metadata_entry("icrc1:name".to_string(), Value::Text("name".to_string())), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TxError::InvalidMetadataEntry { .. })));
+
+        canister_call!(canister.remove_metadata_entry("icrc1:logo".to_string()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let metadata = canister_call!(canister.icrc1_metadata(), Vec<(String, Value)>)
+            .await
+            .unwrap();
+        assert_eq!(metadata.len(), 4);
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_metadata_entry("key".to_string(), Value::Text("value".to_string())), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn update_metadata_from_deployer() {
+        let (ctx, canister) = test_context();
+        TokenConfig::set_stable(TokenConfig {
+            deployer: Some(alice()),
+            ..TokenConfig::get_stable()
+        });
+        let logo = "data:image/png;base64,iVBORw0KGgo=".to_string();
+        let patch = MetadataPatch {
+            name: Some("Rebranded".to_string()),
+            logo: Some(logo.clone()),
+            fee: Some(10.into()),
+        };
+
+        ctx.update_caller(alice());
+        canister_call!(canister.update_metadata_from_deployer(john(), patch.clone()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let config = TokenConfig::get_stable();
+        assert_eq!(config.name, "Rebranded");
+        assert_eq!(config.fee, 10.into());
+        let metadata = canister_call!(canister.icrc1_metadata(), Vec<(String, Value)>)
+            .await
+            .unwrap();
+        assert!(metadata.contains(&(LOGO_METADATA_KEY.to_string(), Value::Text(logo))));
+
+        let res = canister_call!(canister.update_metadata_from_deployer(bob(), patch.clone()), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+
+        ctx.update_caller(bob());
+        let res = canister_call!(canister.update_metadata_from_deployer(john(), patch), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn freeze_account() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.freeze_account(alice().into(), FreezeMode::Outgoing), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let mode = canister_call!(canister.is_frozen(alice().into()), Option<FreezeMode>)
+            .await
+            .unwrap();
+        assert_eq!(mode, Some(FreezeMode::Outgoing));
+
+        ctx.update_id(alice());
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        let res = canister_call!(canister.icrc1_transfer(transfer), Result<u128, TransferError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TransferError::GenericError { .. })));
+
+        let res = canister_call!(canister.unfreeze_account(alice().into()), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+
+        ctx.update_id(john());
+        canister_call!(canister.unfreeze_account(alice().into()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let log = canister_call!(canister.get_admin_log(0, 10), Vec<AdminLogEntry>)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(
+            log[1].action,
+            AdminAction::UnfreezeAccount {
+                account: alice().into()
+            }
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn new_event_subscription_is_charged() {
+        let (ctx, canister) = test_context();
+        EventSubscriptions::clear();
+        let filter = EventFilter {
+            accounts: vec![],
+            transfers: true,
+            mints: false,
+            burns: false,
+        };
+
+        ctx.update_id(alice());
+        ctx.update_msg_cycles(EVENT_SUBSCRIPTION_FEE_CYCLES - 1);
+        let res = canister_call!(canister.subscribe_to_events(filter.clone()), Result<(), TxError>)
+            .await
+            .unwrap();
         assert_eq!(
             res,
-            Err(TransferError::GenericError {
-                error_code: 500,
-                message: "self transfer".into()
+            Err(TxError::InsufficientCycles {
+                required: EVENT_SUBSCRIPTION_FEE_CYCLES
             })
         );
 
+        ctx.update_msg_cycles(EVENT_SUBSCRIPTION_FEE_CYCLES);
+        canister_call!(canister.subscribe_to_events(filter.clone()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ic::msg_cycles_available(), 0);
+
+        // Changing the filter and the owner's subscription are free.
+        ctx.update_msg_cycles(0);
+        canister_call!(canister.subscribe_to_events(filter.clone()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        ctx.update_id(john());
+        canister_call!(canister.subscribe_to_events(filter), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn expired_allowance_is_swept() {
+        let (ctx, canister) = test_context();
+        Allowances::clear();
+
+        ctx.update_id(alice());
+        let expires_at = ic::time() + 1_000;
+        canister_call!(
+            canister.approve(bob(), 100.into(), None, Some(expires_at)),
+            Result<(), TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            canister.get_allowance(alice().into(), bob()),
+            Tokens128::from(100)
+        );
+        assert_eq!(canister.get_allowance_count(), 1);
+        assert_eq!(approvals::run_allowance_sweep(ic::time()), 0);
+
+        ctx.add_time(1_000);
+        assert_eq!(
+            canister.get_allowance(alice().into(), bob()),
+            Tokens128::from(0)
+        );
+        assert_eq!(approvals::run_allowance_sweep(ic::time()), 1);
+        assert_eq!(canister.get_allowance_count(), 0);
+
+        let stats = canister.get_allowance_sweep_stats();
+        assert_eq!((stats.last_removed, stats.total_removed), (1, 1));
+        assert_eq!(stats.last_run_at, Some(ic::time()));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_minting_account() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.set_minting_account(bob().into()), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let minting_account = canister_call!(canister.icrc1_minting_account(), Option<Account>)
+            .await
+            .unwrap();
+        assert_eq!(minting_account, Some(bob().into()));
+
+        // Transfers from the minting account mint new tokens.
+        ctx.update_id(bob());
         let transfer = TransferArgs {
             from_subaccount: None,
-            to: Account::new(alice(), Some(DEFAULT_SUBACCOUNT)),
+            to: alice().into(),
             amount: 100.into(),
             fee: None,
             memo: None,
             created_at_time: None,
         };
+        canister_call!(canister.icrc1_transfer(transfer), Result<u128, TransferError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let balance = canister_call!(canister.icrc1_balance_of(alice().into()), Tokens128)
+            .await
+            .unwrap();
+        assert_eq!(balance, Tokens128::from(1100));
+
+        let res = canister_call!(canister.set_minting_account(bob().into()), Result<(), TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn prune_transactions() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        for _ in 0..5 {
+            canister_call!(canister.mint(bob(), None, 10.into()), TxReceipt)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let length = canister_call!(canister.history_size(), u64).await.unwrap();
+
+        let policy = RetentionPolicy {
+            max_length: Some(2),
+            max_age_nanos: None,
+        };
+        canister_call!(canister.set_history_retention(Some(policy)), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let pruned = canister_call!(canister.prune_transactions(), Result<u64, TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pruned, length - 2);
+
+        let info = canister_call!(canister.get_history_info(), HistoryInfo)
+            .await
+            .unwrap();
+        assert_eq!(info.length, length);
+        assert_eq!(info.earliest_index, length - 2);
+        assert_eq!(info.retention, Some(policy));
+        assert!(LedgerData::get(length - 3).is_none());
+        assert!(LedgerData::get(length - 1).is_some());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.prune_transactions(), Result<u64, TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn compact_ledger() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        for _ in 0..50 {
+            canister_call!(canister.mint(bob(), None, 10.into()), TxReceipt)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        LedgerData::prune(&RetentionPolicy {
+            max_length: Some(1),
+            max_age_nanos: None,
+        });
+
+        let status = canister_call!(canister.get_maintenance_status(), MaintenanceStatus)
+            .await
+            .unwrap();
+        assert_eq!(status.history_length, 1);
+        assert!(status.spare_bytes > 0);
+
+        let report = canister_call!(canister.compact_ledger(), Result<CompactionReport, TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.spare_bytes_released, status.spare_bytes);
+
+        let status = canister_call!(canister.get_maintenance_status(), MaintenanceStatus)
+            .await
+            .unwrap();
+        assert_eq!(status.spare_bytes, 0);
+        assert_eq!(status.last_compaction, Some(report));
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.compact_ledger(), Result<CompactionReport, TxError>)
+            .await
+            .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn get_transactions_by_memo() {
+        let (ctx, canister) = test_context();
+        let transfer = |memo: &[u8]| TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 10.into(),
+            fee: None,
+            memo: Some(memo.to_vec()),
+            created_at_time: None,
+        };
+
+        ctx.update_id(alice());
+        let first = canister_call!(canister.icrc1_transfer(transfer(b"order-1")), Result<u128, TransferError>)
+            .await
+            .unwrap()
+            .unwrap();
+        canister_call!(canister.icrc1_transfer(transfer(b"order-2")), Result<u128, TransferError>)
+            .await
+            .unwrap()
+            .unwrap();
+
+        ctx.update_id(john());
+        canister_call!(canister.set_memo_index(true), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+
+        ctx.update_id(alice());
+        let second = canister_call!(canister.icrc1_transfer(transfer(b"order-1")), Result<u128, TransferError>)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let ids = |records: Vec<TxRecord>| {
+            records
+                .into_iter()
+                .map(|tx| tx.index as u128)
+                .collect::<Vec<_>>()
+        };
+        let indexed = canister_call!(
+            canister.get_transactions_by_memo(b"order-1".to_vec()),
+            Vec<TxRecord>
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(indexed), vec![second, first]);
+
+        ctx.update_id(john());
+        canister_call!(canister.set_memo_index(false), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let scanned = canister_call!(
+            canister.get_transactions_by_memo(b"order-1".to_vec()),
+            Vec<TxRecord>
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(scanned), vec![second, first]);
+
+        ctx.update_id(alice());
+        let res = canister_call!(canister.icrc1_transfer(transfer(&[0; 33])), Result<u128, TransferError>)
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TransferError::GenericError { .. })));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn pull_payment() {
+        let (ctx, canister) = test_context();
+        MerchantAuthorizations::clear();
+
+        ctx.update_id(alice());
+        canister_call!(canister.authorize_merchant(bob(), 100.into(), 150.into(), None), Result<MerchantAuthorization, TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let res = canister_call!(
+            canister.pull_payment(alice().into(), 50.into(), None),
+            TxReceipt
+        )
+        .await
+        .unwrap();
+        assert_eq!(res, Err(TxError::MerchantNotAuthorized));
+
+        ctx.update_id(bob());
+        let res = canister_call!(
+            canister.pull_payment(alice().into(), 101.into(), None),
+            TxReceipt
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            res,
+            Err(TxError::AmountExceedsPerTxCap {
+                per_tx_cap: 100.into()
+            })
+        );
+
+        let memo = vec![1, 2, 3];
+        let tx_id = canister_call!(
+            canister.pull_payment(alice().into(), 100.into(), Some(memo.clone())),
+            TxReceipt
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(bob().into()),
+            Tokens128::from(100)
+        );
+        let record = canister.get_transaction(tx_id as TxId);
+        assert_eq!(record.operation, Operation::TransferFrom);
+        assert_eq!(record.caller, bob());
+        assert_eq!(record.memo, Some(memo));
 
-        let res = canister.icrc1_transfer(transfer);
+        let res = canister_call!(
+            canister.pull_payment(alice().into(), 100.into(), None),
+            TxReceipt
+        )
+        .await
+        .unwrap();
         assert_eq!(
             res,
-            Err(TransferError::GenericError {
-                error_code: 500,
-                message: "self transfer".into()
+            Err(TxError::MonthlyCapExceeded {
+                remaining: 50.into()
             })
         );
-    }
-
-    #[cfg(feature = "claim")]
-    #[test]
-    fn test_claim() {
-        let bob_sub = gen_subaccount();
-        let alice_sub = gen_subaccount();
 
-        let alice_aid =
-            AccountIdentifier::new(alice().into(), Some(SubaccountIdentifier(alice_sub)));
-        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
+        ctx.update_id(alice());
+        canister_call!(canister.revoke_merchant(bob(), None), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let authorizations = canister_call!(
+            canister.get_merchant_authorizations(alice()),
+            Vec<MerchantAuthorization>
+        )
+        .await
+        .unwrap();
+        assert!(authorizations.is_empty());
+    }
 
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn approve_requires_balance() {
         let (ctx, canister) = test_context();
-        ctx.update_caller(john());
+        Allowances::clear();
 
-        assert!(canister
-            .mint(
-                canister.owner(),
-                Some(alice_aid.to_address()),
-                Tokens128::from(1000)
-            )
-            .is_ok());
-        assert!(canister
-            .mint(
-                canister.owner(),
-                Some(bob_aid.to_address()),
-                Tokens128::from(2000)
-            )
-            .is_ok());
+        ctx.update_id(Principal::anonymous());
+        let res =
+            canister_call!(canister.approve(bob(), 100.into(), None, None), Result<(), TxError>)
+                .await
+                .unwrap();
+        assert_eq!(res, Err(TxError::Unauthorized));
 
-        ctx.update_caller(alice());
+        ctx.update_id(bob());
+        let res =
+            canister_call!(canister.approve(john(), 100.into(), None, None), Result<(), TxError>)
+                .await
+                .unwrap();
         assert_eq!(
-            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
-            Tokens128::from(1000)
+            res,
+            Err(TxError::InsufficientFunds {
+                balance: Tokens128::ZERO
+            })
+        );
+        assert_eq!(
+            canister.get_allowance(bob().into(), john()),
+            Tokens128::ZERO
         );
 
-        let balance_before = canister.icrc1_balance_of(alice().into());
-        canister.claim(canister.owner(), Some(alice_sub)).unwrap();
+        // Removing an allowance doesn't need a balance.
+        canister_call!(canister.approve(john(), 0.into(), None, None), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn transfer_from_allowance() {
+        let (ctx, canister) = test_context();
+        Allowances::clear();
+
+        ctx.update_id(alice());
+        canister_call!(canister.approve(bob(), 100.into(), None, None), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
         assert_eq!(
-            canister.icrc1_balance_of(alice().into()),
-            (Tokens128::from(1000) + balance_before).unwrap()
+            canister.get_allowance(alice().into(), bob()),
+            Tokens128::from(100)
         );
+
+        ctx.update_id(bob());
+        let res = canister_call!(
+            canister.transfer_from_allowance(alice().into(), bob().into(), 101.into(), None),
+            TxReceipt
+        )
+        .await
+        .unwrap();
         assert_eq!(
-            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
-            0.into()
+            res,
+            Err(TxError::InsufficientAllowance {
+                allowance: 100.into()
+            })
         );
 
-        ctx.update_caller(bob());
+        let tx_id = canister_call!(
+            canister.transfer_from_allowance(alice().into(), bob().into(), 60.into(), None),
+            TxReceipt
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(canister.get_transaction(tx_id as TxId).caller, bob());
         assert_eq!(
-            canister.get_claimable_amount(canister.owner(), Some(bob_sub)),
-            Tokens128::from(2000)
+            canister.get_allowance(alice().into(), bob()),
+            Tokens128::from(40)
+        );
+        assert_eq!(canister.icrc1_balance_of(bob().into()), Tokens128::from(60));
+        assert_eq!(
+            canister.icrc1_balance_of(alice().into()),
+            Tokens128::from(940)
         );
     }
 
-    // **** APIs tests ****
-
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_name() {
+    async fn budget_transfer() {
         let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_name("War and Piece".to_string()), Result<(), TxError>)
+        Budgets::clear();
+
+        let budget = [7; 32];
+        StableBalances.insert(AccountInternal::new(alice(), Some(budget)), 100.into());
+        ctx.update_id(alice());
+        canister_call!(canister.create_budget(budget, "ops".into(), None, 50.into()), Result<Budget, TxError>)
             .await
             .unwrap()
             .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+        canister_call!(canister.set_budget_delegates(budget, vec![bob()]), Result<Budget, TxError>)
             .await
+            .unwrap()
             .unwrap();
 
-        assert_eq!(info.metadata.name, "War and Piece".to_string());
-
         ctx.update_id(bob());
-        let res = canister_call!(canister.set_name("Crime and Punishment".to_string()), Result<(), TxError>)
-            .await
-            .unwrap();
+        let res = canister_call!(
+            canister.budget_transfer(alice(), budget, john().into(), 51.into(), None),
+            TxReceipt
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            res,
+            Err(TxError::BudgetLimitExceeded {
+                remaining: 50.into()
+            })
+        );
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+        let tx_id = canister_call!(
+            canister.budget_transfer(alice(), budget, john().into(), 40.into(), None),
+            TxReceipt
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let record = canister.get_transaction(tx_id as TxId);
+        assert_eq!(record.operation, Operation::TransferFrom);
+        assert_eq!(record.caller, bob());
+
+        let reports = canister_call!(canister.get_budgets(alice()), Vec<BudgetReport>)
             .await
             .unwrap();
-
-        assert_eq!(info.metadata.name, "War and Piece".to_string());
-        let name = canister_call!(canister.icrc1_name(), String).await.unwrap();
-        assert_eq!(name, "War and Piece".to_string());
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].budget.spent, 40.into());
+        assert_eq!(reports[0].remaining, 10.into());
     }
 
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_symbol() {
+    async fn expiring_transfer() {
         let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_symbol("MAX".to_string()), Result<(), TxError>)
-            .await
-            .unwrap()
-            .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+        ExpiringTransfers::clear();
+        let expires_at = canister_sdk::ic_kit::ic::time() + 1_000;
+
+        ctx.update_id(alice());
+        let accepted = canister_call!(
+            canister.transfer_with_expiry(bob().into(), 100.into(), expires_at, None, None),
+            Result<ExpiringTransferId, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let reclaimed = canister_call!(
+            canister.transfer_with_expiry(bob().into(), 200.into(), expires_at, None, None),
+            Result<ExpiringTransferId, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 700.into());
+
+        let res = canister_call!(canister.accept_transfer(accepted), TxReceipt)
             .await
             .unwrap();
-
-        assert_eq!(info.metadata.symbol, "MAX".to_string());
+        assert_eq!(res, Err(TxError::Unauthorized));
 
         ctx.update_id(bob());
-        let res = canister_call!(canister.set_symbol("BOB".to_string()), Result<(), TxError>)
+        let tx_id = canister_call!(canister.accept_transfer(accepted), TxReceipt)
             .await
+            .unwrap()
             .unwrap();
+        assert_eq!(canister.get_transaction(tx_id as TxId).to, bob().into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+        ctx.add_time(1_001);
+        let res = canister_call!(canister.accept_transfer(reclaimed), TxReceipt)
             .await
             .unwrap();
+        assert_eq!(
+            res,
+            Err(TxError::TransferExpired {
+                expired_at: expires_at
+            })
+        );
 
-        assert_eq!(info.metadata.symbol, "MAX".to_string());
-        let symbol = canister_call!(canister.icrc1_symbol(), String)
+        ctx.update_id(alice());
+        canister_call!(canister.reclaim_transfer(reclaimed), TxReceipt)
             .await
+            .unwrap()
             .unwrap();
-        assert_eq!(symbol, "MAX".to_string());
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 900.into());
+        assert_eq!(
+            ExpiringTransfers::get(reclaimed).unwrap().status,
+            ExpiringTransferStatus::Reclaimed
+        );
     }
 
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_fee() {
+    async fn voting_snapshots() {
         let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_fee(100500.into()), Result<(), TxError>)
-            .await
-            .unwrap()
-            .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        Snapshots::clear();
 
-        assert_eq!(info.metadata.fee, 100500.into());
-
-        ctx.update_id(bob());
-        let res = canister_call!(canister.set_fee(0.into()), Result<(), TxError>)
+        ctx.update_id(alice());
+        let res = canister_call!(canister.take_snapshot(), Result<Snapshot, TxError>)
             .await
             .unwrap();
-
         assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+
+        ctx.update_id(john());
+        canister_call!(canister.set_voting_exclusions(vec![bob()]), Result<(), TxError>)
             .await
+            .unwrap()
             .unwrap();
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        canister.icrc1_transfer(transfer(alice(), 300)).unwrap();
+        canister.icrc1_transfer(transfer(bob(), 200)).unwrap();
 
-        assert_eq!(info.metadata.fee, 100500.into());
-        let fee = canister_call!(canister.icrc1_fee(), Tokens128)
+        let snapshot = canister_call!(canister.take_snapshot(), Result<Snapshot, TxError>)
             .await
+            .unwrap()
             .unwrap();
-        assert_eq!(fee, 100500.into());
+        assert_eq!(snapshot.excluded_supply, 200.into());
+        canister.icrc1_transfer(transfer(alice(), 500)).unwrap();
+
+        let power = canister_call!(
+            canister.get_voting_power(alice().into(), snapshot.id),
+            Result<Tokens128, TxError>
+        )
+        .await
+        .unwrap();
+        assert_eq!(power, Ok(300.into()));
+        let power = canister_call!(
+            canister.get_voting_power(john().into(), snapshot.id),
+            Result<Tokens128, TxError>
+        )
+        .await
+        .unwrap();
+        assert_eq!(power, Ok(500.into()));
+        let power = canister_call!(
+            canister.get_voting_power(bob().into(), snapshot.id),
+            Result<Tokens128, TxError>
+        )
+        .await
+        .unwrap();
+        assert_eq!(power, Ok(0.into()));
+        let total = canister_call!(
+            canister.total_voting_power(snapshot.id),
+            Result<Tokens128, TxError>
+        )
+        .await
+        .unwrap();
+        assert_eq!(total, Ok(800.into()));
+        let res = canister_call!(
+            canister.total_voting_power(snapshot.id + 1),
+            Result<Tokens128, TxError>
+        )
+        .await
+        .unwrap();
+        assert_eq!(res, Err(TxError::SnapshotNotFound));
     }
 
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_fee_to() {
+    async fn collect_subscription() {
         let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_fee_to(alice()), Result<(), TxError>)
+        PaymentSubscriptions::clear();
+
+        ctx.update_id(alice());
+        let id = canister_call!(canister.create_subscription(bob(), 100.into(), 1_000_000, None), Result<SubscriptionId, TxError>)
             .await
             .unwrap()
             .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+
+        let res = canister_call!(canister.collect_subscription(id, 50.into()), TxReceipt)
             .await
             .unwrap();
-
-        assert_eq!(info.metadata.fee_to, alice());
+        assert_eq!(res, Err(TxError::Unauthorized));
 
         ctx.update_id(bob());
-        let res = canister_call!(canister.set_fee_to(bob()), Result<(), TxError>)
+        let res = canister_call!(canister.collect_subscription(id, 101.into()), TxReceipt)
+            .await
+            .unwrap();
+        assert_eq!(
+            res,
+            Err(TxError::AmountExceedsSubscription {
+                max_amount: 100.into()
+            })
+        );
+
+        let tx_id = canister_call!(canister.collect_subscription(id, 100.into()), TxReceipt)
             .await
+            .unwrap()
             .unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(bob().into()),
+            Tokens128::from(100)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(alice().into()),
+            Tokens128::from(900)
+        );
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+        let record = canister.get_transaction(tx_id as TxId);
+        assert_eq!(record.operation, Operation::TransferFrom);
+        assert_eq!(record.caller, bob());
+
+        // The next payment is due only after the interval.
+        let res = canister_call!(canister.collect_subscription(id, 100.into()), TxReceipt)
             .await
             .unwrap();
+        assert!(matches!(res, Err(TxError::PaymentNotDue { .. })));
 
-        assert_eq!(info.metadata.fee_to, alice());
+        canister_call!(canister.cancel_subscription(id), Result<(), TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let subscriptions = canister_call!(
+            canister.get_subscriptions(alice()),
+            Vec<PaymentSubscription>
+        )
+        .await
+        .unwrap();
+        assert!(subscriptions.is_empty());
     }
 
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_owner() {
+    async fn standing_order_payments() {
         let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_owner(alice()), Result<(), TxError>)
+        StandingOrders::clear();
+
+        ctx.update_id(alice());
+        let id = canister_call!(
+            canister.create_standing_order(bob().into(), 100.into(), 60_000_000_000, Some(1), None),
+            Result<StandingOrderId, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        canister_call!(canister.pause_standing_order(id), Result<(), TxError>)
             .await
             .unwrap()
             .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
-
-        assert_eq!(info.metadata.owner, alice());
+        assert_eq!(standing_orders::execute_due_orders(ic::time(), 0.0), 0);
 
         ctx.update_id(bob());
-        let res = canister_call!(canister.set_owner(bob()), Result<(), TxError>)
+        let res = canister_call!(canister.resume_standing_order(id), Result<(), TxError>)
             .await
             .unwrap();
-
         assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
+
+        ctx.update_id(alice());
+        canister_call!(canister.resume_standing_order(id), Result<(), TxError>)
             .await
+            .unwrap()
             .unwrap();
+        assert_eq!(standing_orders::execute_due_orders(ic::time(), 0.0), 1);
+        assert_eq!(
+            canister.icrc1_balance_of(bob().into()),
+            Tokens128::from(100)
+        );
 
-        assert_eq!(info.metadata.owner, alice());
-        let owner = canister_call!(canister.owner(), Principal).await.unwrap();
-        assert_eq!(owner, alice());
+        let record = canister.get_transaction(LedgerData::len() - 1);
+        assert_eq!(record.operation, Operation::StandingOrder);
+        assert_eq!(record.caller, alice());
 
-        let minting_account = canister_call!(canister.icrc1_minting_account(), Principal)
+        // The order is removed after its last execution.
+        let orders = canister_call!(canister.get_standing_orders(alice()), Vec<StandingOrder>)
             .await
             .unwrap();
-        assert_eq!(minting_account, Some(alice().into()));
+        assert!(orders.is_empty());
     }
 
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn list_subaccounts() {
-        let canister = test_canister();
-        let subaccount: Subaccount = [1; 32];
-        canister
-            .transfer(TransferArgs {
-                from_subaccount: None,
-                to: Account::new(alice(), Some(subaccount)),
-                amount: 100.into(),
-                fee: None,
-                memo: None,
-                created_at_time: None,
-            })
+    async fn guardian_recovers_accounts() {
+        let (ctx, canister) = test_context();
+        Guardians::clear();
+        let admin_log_len = AdminLog::len();
+
+        let guardian = Guardian {
+            guardian: bob(),
+            delay_nanos: MIN_RECOVERY_DELAY_NANOS,
+        };
+        canister_call!(canister.set_guardian(Some(guardian)), Result<(), TxError>)
+            .await
+            .unwrap()
             .unwrap();
 
-        get_context().update_id(alice());
-        let list = canister_call!(canister.list_subaccounts(), std::collections::HashMap<Subaccount, Tokens128>).await.unwrap();
-        assert_eq!(list.len(), 2);
-        assert_eq!(list[&DEFAULT_SUBACCOUNT], 900.into());
-        assert_eq!(list[&subaccount], 100.into());
+        ctx.update_id(bob());
+        canister_call!(canister.initiate_recovery(alice(), john()), Result<Recovery, TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let res = canister_call!(
+            canister.complete_recovery(alice()),
+            Result<Vec<TxId>, TxError>
+        )
+        .await
+        .unwrap();
+        assert!(matches!(res, Err(TxError::RecoveryNotReady { .. })));
+
+        ctx.add_time(MIN_RECOVERY_DELAY_NANOS);
+        let ids = canister_call!(
+            canister.complete_recovery(alice()),
+            Result<Vec<TxId>, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(canister.icrc1_balance_of(alice().into()), Tokens128::ZERO);
+        assert_eq!(
+            canister.icrc1_balance_of(john().into()),
+            Tokens128::from(2000)
+        );
+
+        let record = canister.get_transaction(ids[0]);
+        assert_eq!(record.operation, Operation::Recovery);
+        assert_eq!(record.caller, bob());
+        assert_eq!(canister.get_guardian(alice()), None);
+
+        // The steps are logged apart from the administrative actions of the owner.
+        let log = canister_call!(canister.get_recovery_log(0, 10), Vec<RecoveryLogEntry>)
+            .await
+            .unwrap();
+        let actions = log.iter().map(|entry| entry.action).collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            vec![
+                RecoveryAction::SetGuardian {
+                    guardian: Some(guardian)
+                },
+                RecoveryAction::InitiateRecovery { new_owner: john() },
+                RecoveryAction::CompleteRecovery { new_owner: john() },
+            ]
+        );
+        assert!(log.iter().all(|entry| entry.owner == alice()));
+        assert_eq!(AdminLog::len(), admin_log_len);
     }
 }
@@ -0,0 +1,110 @@
+//! Erasure of the auxiliary data kept by the token about a principal, for the data minimization
+//! requests.
+//!
+//! `purge_account_data` removes the recorded account identifiers of the claims, the subaccount
+//! tags, the budgets of the principal, and the standing orders and payment subscriptions the
+//! principal takes part in. It's only
+//! allowed once all the accounts of the principal are empty. The ledger is not touched, as the
+//! transaction history is immutable.
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+
+use crate::error::TxError;
+use crate::state::account_ids::AccountIds;
+use crate::state::account_tags::AccountTags;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::budgets::Budgets;
+use crate::state::config::Value;
+use crate::state::payment_subscriptions::PaymentSubscriptions;
+use crate::state::standing_orders::StandingOrders;
+
+/// Number of the removed entries of every kind.
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub account_ids: u64,
+    pub tags: u64,
+    pub budgets: u64,
+    pub standing_orders: u64,
+    pub payment_subscriptions: u64,
+}
+
+/// Removes the auxiliary data of the `principal` on request of the owner `caller`.
+pub fn purge_account_data(caller: Principal, principal: Principal) -> Result<PurgeReport, TxError> {
+    let balance = StableBalances
+        .get_subaccounts(principal)
+        .into_values()
+        .fold(Tokens128::ZERO, |total, amount| {
+            (total + amount).unwrap_or(Tokens128::from(u128::MAX))
+        });
+    if !balance.is_zero() {
+        return Err(TxError::AccountNotEmpty { balance });
+    }
+
+    let report = PurgeReport {
+        account_ids: AccountIds::remove_owner(principal) as _,
+        tags: AccountTags::remove_owner(principal) as _,
+        budgets: Budgets::remove_organization(principal) as _,
+        standing_orders: StandingOrders::remove_involving(principal) as _,
+        payment_subscriptions: PaymentSubscriptions::remove_involving(principal) as _,
+    };
+    AdminLog::record(
+        caller,
+        AdminAction::PurgeAccountData { principal },
+        None,
+        Some(Value::Text(format!("{report:?}"))),
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::account::AccountInternal;
+
+    #[test]
+    fn purges_only_empty_accounts() {
+        MockContext::new().inject();
+        StableBalances.clear();
+        AccountIds::clear();
+        AccountTags::clear();
+
+        let account = AccountInternal::new(alice(), Some([1; 32]));
+        StableBalances.insert(account, 10.into());
+        AccountIds::record(account);
+        AccountIds::record(bob().into());
+        AccountTags::set(alice(), [1; 32], "savings".into()).unwrap();
+        AccountTags::set(alice(), [2; 32], "spending".into()).unwrap();
+
+        assert_eq!(
+            purge_account_data(john(), alice()),
+            Err(TxError::AccountNotEmpty { balance: 10.into() })
+        );
+
+        StableBalances.remove(&account);
+        assert_eq!(
+            purge_account_data(john(), alice()),
+            Ok(PurgeReport {
+                account_ids: 1,
+                tags: 2,
+                ..PurgeReport::default()
+            })
+        );
+        assert_eq!(AccountIds::resolve(AccountIds::of(account)), None);
+        assert!(AccountTags::get(alice()).is_empty());
+        assert_eq!(
+            AccountIds::resolve(AccountIds::of(bob().into())),
+            Some(bob().into())
+        );
+        assert_eq!(
+            purge_account_data(john(), alice()),
+            Ok(PurgeReport::default())
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Approvals with the notification of the spender canister (approve-and-call), see
+//! `state::allowances`.
+//!
+//! The allowance is recorded before the spender is called, so it stays set whatever the result of
+//! the call. The result is stored in the notification, which the holder can query and retry.
+//!
+//! The timer task removes the expired allowances, at most `MAX_EXPIRED_ALLOWANCES_PER_RUN` per
+//! run, and records the number of the removed ones in the sweep stats.
+
+use std::time::Duration;
+
+use candid::Principal;
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use super::is20_transactions::transfer_internal;
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::allowances::{
+    Allowances, ApprovalNotification, ApprovalNotificationId, APPROVAL_CALLBACK_METHOD,
+};
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::{FeeRatio, Timestamp, TokenConfig};
+use crate::state::large_transfers::LargeTransfers;
+use crate::state::ledger::{LedgerData, Memo, TxReceipt};
+
+pub const ALLOWANCE_SWEEP_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Maximum number of the expired allowances removed by one run of the timer task.
+pub const MAX_EXPIRED_ALLOWANCES_PER_RUN: usize = 1_000;
+
+/// Sets the allowance of the `spender` to transfer from the `owner` account. The account must hold
+/// tokens to set an allowance, so the allowances can't be created in bulk from empty accounts.
+/// Removing an allowance is always allowed.
+pub fn approve(
+    owner: AccountInternal,
+    spender: Principal,
+    amount: Tokens128,
+    expires_at: Option<Timestamp>,
+    now: Timestamp,
+) -> Result<(), TxError> {
+    if owner.owner == Principal::anonymous() {
+        return Err(TxError::Unauthorized);
+    }
+
+    if !amount.is_zero() && StableBalances.balance_of(&owner).is_zero() {
+        return Err(TxError::InsufficientFunds {
+            balance: Tokens128::ZERO,
+        });
+    }
+
+    Allowances::approve(owner, spender, amount, expires_at, now)
+}
+
+/// Sets the allowance of the `spender` canister to transfer from the `owner` account and calls its
+/// `on_approval` method with the `payload`.
+pub async fn approve_and_notify(
+    owner: AccountInternal,
+    spender: Principal,
+    amount: Tokens128,
+    payload: Vec<u8>,
+) -> Result<ApprovalNotification, TxError> {
+    Allowances::check_payload(&payload)?;
+    approve(owner, spender, amount, None, ic::time())?;
+
+    let notification = Allowances::add_notification(owner, spender, amount, payload, ic::time());
+    Ok(notify(notification).await)
+}
+
+/// Calls the spender canister again for the failed notification `id` of the caller.
+pub async fn retry_approval_notification(
+    caller: Principal,
+    id: ApprovalNotificationId,
+) -> Result<ApprovalNotification, TxError> {
+    let notification = Allowances::start_retry(caller, id)?;
+    Ok(notify(notification).await)
+}
+
+async fn notify(notification: ApprovalNotification) -> ApprovalNotification {
+    let id = notification.notice.id;
+    let result = ic::call::<_, (), _>(
+        notification.spender,
+        APPROVAL_CALLBACK_METHOD,
+        (notification.notice,),
+    )
+    .await
+    .map_err(|(_, message)| message);
+
+    Allowances::record_attempt(id, result).expect("the notification was added before the call")
+}
+
+/// Transfers `amount` from the `from` account to the `to` account on behalf of the caller, who must
+/// have the allowance of at least `amount`. The transfer fee is paid by the `from` account on top
+/// of the amount and doesn't decrease the allowance.
+pub fn transfer_from_allowance(
+    from: AccountInternal,
+    to: AccountInternal,
+    amount: Tokens128,
+    memo: Option<Memo>,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let spender = ic::caller();
+    let config = TokenConfig::get_stable();
+    config.input_limits().check_memo(memo.as_ref())?;
+
+    if from == to {
+        return Err(TxError::SelfTransfer);
+    }
+
+    Allowances::check_spending(from, spender, amount, ic::time())?;
+    LargeTransfers::check_amount(amount)?;
+    let (fee, fee_to) = config.fee_info(from, to, amount);
+    let burned = transfer_internal(
+        &mut StableBalances,
+        from,
+        to,
+        amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        config.burn_policy,
+    )?;
+
+    let tx_id = LedgerData::transfer_from(spender, from, to, amount, fee, memo);
+    LedgerData::record_transfer_burn(from, burned);
+    Allowances::record_spending(from, spender, amount);
+
+    Ok(tx_id.into())
+}
+
+/// Starts the timer task removing the expired allowances. Timers are not preserved on upgrade, so
+/// this must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_allowance_sweeper() {
+    ic_exports::ic_cdk_timers::set_timer_interval(ALLOWANCE_SWEEP_PERIOD, || {
+        run_allowance_sweep(ic::time());
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_allowance_sweeper() {}
+
+/// Removes the next batch of the allowances expired by `now`. Returns the number of the removed
+/// allowances.
+pub fn run_allowance_sweep(now: Timestamp) -> usize {
+    let removed = Allowances::remove_expired(now, MAX_EXPIRED_ALLOWANCES_PER_RUN);
+    Allowances::record_sweep(removed as u64, now);
+    removed
+}
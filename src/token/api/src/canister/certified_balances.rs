@@ -0,0 +1,94 @@
+//! Certified balances, see `state::certified_balances`.
+//!
+//! The timer task moves the changed balances to the certified tree and updates the certified data
+//! of the canister. `get_certified_balance` returns the balance of an account from the tree with
+//! the certificate of the subnet and the witness of the balance in the certified data, so the
+//! clients can check the response of a query instead of making an update call.
+
+use std::time::Duration;
+
+use candid::{CandidType, Deserialize};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use super::http;
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::certified_balances::{CertifiedBalances, MAX_CERTIFIED_UPDATES_PER_RUN};
+
+pub const BALANCE_CERTIFICATION_PERIOD: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct CertifiedBalance {
+    pub account: Account,
+    /// Balance at the last run of the certification task, which can lag behind the current one.
+    pub balance: Tokens128,
+    /// Certificate of the subnet, `None` if the query is called in the replicated mode.
+    pub certificate: Option<Vec<u8>>,
+    /// CBOR-encoded hash tree with the path `balances/<account key>` to the big-endian balance, or
+    /// proving its absence for a zero balance.
+    pub witness: Vec<u8>,
+}
+
+/// Starts the timer task certifying the changed balances. Timers are not preserved on upgrade, so
+/// this must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_balance_certification() {
+    ic_exports::ic_cdk_timers::set_timer_interval(
+        BALANCE_CERTIFICATION_PERIOD,
+        run_balance_certification,
+    );
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_balance_certification() {}
+
+/// Moves the next batch of the changed balances to the certified tree.
+pub fn run_balance_certification() {
+    if CertifiedBalances::update(MAX_CERTIFIED_UPDATES_PER_RUN) {
+        http::update_certified_data();
+    }
+}
+
+pub fn get_certified_balance(account: Account) -> Result<CertifiedBalance, TxError> {
+    let (balance, witness) =
+        CertifiedBalances::with_witness(account.into(), |balance, witness| {
+            let witness = http::encode_witness(&http::balance_witness(witness))
+                .expect("failed to encode balance witness");
+            (balance, witness)
+        })?;
+
+    Ok(CertifiedBalance {
+        account: AccountInternal::from(account).into(),
+        balance,
+        certificate: ic::data_certificate(),
+        witness,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::alice;
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::balances::{Balances, StableBalances};
+
+    #[test]
+    fn balance_is_certified_by_timer_task() {
+        MockContext::new().inject();
+        StableBalances.clear();
+        StableBalances.insert(alice().into(), 100.into());
+
+        assert_eq!(
+            get_certified_balance(alice().into()),
+            Err(TxError::BalanceNotCertifiedYet)
+        );
+        run_balance_certification();
+
+        let certified = get_certified_balance(alice().into()).unwrap();
+        assert_eq!(certified.balance, 100.into());
+        assert!(!certified.witness.is_empty());
+    }
+}
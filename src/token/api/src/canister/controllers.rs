@@ -0,0 +1,156 @@
+//! Management of the controllers of the token canister by its owner.
+//!
+//! The token calls the management canister on its own behalf, so it must be one of its
+//! controllers. The list of the controllers is always read from the management canister before it
+//! is changed, so the changes made with dfx in the meantime are not lost.
+
+use candid::Principal;
+use canister_sdk::ic_cdk::api::management_canister::main::{
+    CanisterIdRecord, CanisterSettings, CanisterStatusResponse, UpdateSettingsArgument,
+};
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::guard::StateGuard;
+
+/// Maximum number of the controllers of a canister allowed by the IC.
+pub const MAX_CONTROLLERS: usize = 10;
+
+/// Returns the controllers of the token canister.
+pub async fn list_controllers() -> Result<Vec<Principal>, TxError> {
+    let args = CanisterIdRecord {
+        canister_id: ic::id(),
+    };
+    ic::call::<_, (CanisterStatusResponse,), _>(
+        Principal::management_canister(),
+        "canister_status",
+        (args,),
+    )
+    .await
+    .map(|(status,)| status.settings.controllers)
+    .map_err(|(_, message)| TxError::ManagementCallFailed { message })
+}
+
+/// Adds the `controller` to the controllers of the token canister. Returns the new list.
+pub async fn add_controller(
+    caller: Principal,
+    controller: Principal,
+) -> Result<Vec<Principal>, TxError> {
+    let _guard = StateGuard::global("update_controllers")?;
+    let controllers = with_controller(list_controllers().await?, controller)?;
+    set_controllers(controllers.clone()).await?;
+
+    AdminLog::record(
+        caller,
+        AdminAction::AddController { controller },
+        None,
+        None,
+    );
+    Ok(controllers)
+}
+
+/// Removes the `controller` from the controllers of the token canister. Returns the new list.
+pub async fn remove_controller(
+    caller: Principal,
+    controller: Principal,
+) -> Result<Vec<Principal>, TxError> {
+    let _guard = StateGuard::global("update_controllers")?;
+    let controllers = without_controller(list_controllers().await?, controller)?;
+    set_controllers(controllers.clone()).await?;
+
+    AdminLog::record(
+        caller,
+        AdminAction::RemoveController { controller },
+        None,
+        None,
+    );
+    Ok(controllers)
+}
+
+fn with_controller(
+    mut controllers: Vec<Principal>,
+    controller: Principal,
+) -> Result<Vec<Principal>, TxError> {
+    if controllers.contains(&controller) {
+        return Err(TxError::AlreadyController);
+    }
+    if controllers.len() >= MAX_CONTROLLERS {
+        return Err(TxError::TooManyControllers {
+            max: MAX_CONTROLLERS as u64,
+        });
+    }
+
+    controllers.push(controller);
+    Ok(controllers)
+}
+
+/// The last controller cannot be removed, as the canister couldn't be upgraded or deleted anymore.
+fn without_controller(
+    mut controllers: Vec<Principal>,
+    controller: Principal,
+) -> Result<Vec<Principal>, TxError> {
+    let len = controllers.len();
+    controllers.retain(|&c| c != controller);
+    if controllers.len() == len {
+        return Err(TxError::NotController);
+    }
+    if controllers.is_empty() {
+        return Err(TxError::LastController);
+    }
+
+    Ok(controllers)
+}
+
+pub(crate) async fn set_controllers(controllers: Vec<Principal>) -> Result<(), TxError> {
+    let args = UpdateSettingsArgument {
+        canister_id: ic::id(),
+        settings: CanisterSettings {
+            controllers: Some(controllers),
+            compute_allocation: None,
+            memory_allocation: None,
+            freezing_threshold: None,
+        },
+    };
+
+    ic::call::<_, (), _>(Principal::management_canister(), "update_settings", (args,))
+        .await
+        .map_err(|(_, message)| TxError::ManagementCallFailed { message })
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn controllers_list_changes() {
+        assert_eq!(
+            with_controller(vec![alice()], bob()),
+            Ok(vec![alice(), bob()])
+        );
+        assert_eq!(
+            with_controller(vec![alice()], alice()),
+            Err(TxError::AlreadyController)
+        );
+        assert_eq!(
+            with_controller(vec![alice(); MAX_CONTROLLERS], bob()),
+            Err(TxError::TooManyControllers { max: 10 })
+        );
+
+        assert_eq!(
+            without_controller(vec![alice(), bob()], bob()),
+            Ok(vec![alice()])
+        );
+        assert_eq!(
+            without_controller(vec![alice()], bob()),
+            Err(TxError::NotController)
+        );
+        assert_eq!(
+            without_controller(vec![alice()], alice()),
+            Err(TxError::LastController)
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Withdrawal of the token cycles to the canister which installed the token.
+
+use candid::Principal;
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::state::config::TokenConfig;
+use crate::state::guard::StateGuard;
+
+/// Cycles left to the token after the withdrawal, to pay for the call itself and to stay above the
+/// freezing threshold until the canister is deleted.
+pub const WITHDRAWAL_CYCLES_RESERVE: u64 = 100_000_000_000;
+
+/// Sends the cycles above `WITHDRAWAL_CYCLES_RESERVE` to the `receive_cycles` method of the
+/// deployer. Only the deployer can request the withdrawal.
+pub async fn withdraw_to_deployer(caller: Principal) -> Result<u64, TxError> {
+    let deployer = TokenConfig::get_stable().deployer;
+    if deployer != Some(caller) {
+        return Err(TxError::Unauthorized);
+    }
+
+    // The balance must not be read by another withdrawal while the cycles are being sent.
+    let _guard = StateGuard::global("withdraw_cycles_to_deployer")?;
+    let amount = ic::balance().saturating_sub(WITHDRAWAL_CYCLES_RESERVE);
+    if amount == 0 {
+        return Ok(0);
+    }
+
+    let (accepted,) = ic::call_with_payment::<_, (u64,), _>(caller, "receive_cycles", (), amount)
+        .await
+        .map_err(|(_, message)| TxError::CyclesTransferFailed { message })?;
+
+    Ok(accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+
+    use super::*;
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn only_deployer_can_withdraw() {
+        MockContext::new().with_caller(bob()).inject();
+        TokenConfig::set_stable(TokenConfig {
+            deployer: Some(alice()),
+            ..TokenConfig::default()
+        });
+
+        assert_eq!(
+            withdraw_to_deployer(bob()).await,
+            Err(TxError::Unauthorized)
+        );
+    }
+}
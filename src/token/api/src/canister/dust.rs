@@ -0,0 +1,153 @@
+//! Maintenance task applying the dust policy, see `state::dust`.
+//!
+//! Every run checks at most `MAX_ACCOUNTS_PER_RUN` balance entries, continuing from where the
+//! previous run stopped, so a pass over a large balances map takes several runs.
+
+use std::time::Duration;
+
+use super::{auction_account, is_service_account};
+use crate::account::AccountInternal;
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::{Timestamp, TokenConfig};
+use crate::state::decimals::DecimalsMigration;
+use crate::state::dust::{DustMode, DustReports};
+use crate::state::frozen::FrozenAccounts;
+use crate::state::ledger::LedgerData;
+use crate::state::migrations::StateMigrations;
+use crate::tx_record::TxRecord;
+
+pub const DUST_MAINTENANCE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+pub const MAX_ACCOUNTS_PER_RUN: usize = 5_000;
+
+/// Starts the timer task applying the dust policy. Timers are not preserved on upgrade, so this
+/// must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_dust_maintenance() {
+    ic_exports::ic_cdk_timers::set_timer_interval(DUST_MAINTENANCE_PERIOD, || {
+        run_dust_maintenance(canister_sdk::ic_kit::ic::time());
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_dust_maintenance() {}
+
+/// Checks the next batch of the balances against the dust policy. Nothing is done if there is no
+/// policy or a migration of the state is running.
+pub fn run_dust_maintenance(now: Timestamp) {
+    let config = TokenConfig::get_stable();
+    let Some(policy) = config.dust_policy else {
+        DustReports::take_scan();
+        return;
+    };
+    if DecimalsMigration::check_not_running().is_err()
+        || StateMigrations::check_completed().is_err()
+    {
+        return;
+    }
+
+    let mut scan = DustReports::take_scan();
+    let balances = StableBalances.list_balances(scan.cursor, MAX_ACCOUNTS_PER_RUN);
+    let is_last_batch = balances.len() < MAX_ACCOUNTS_PER_RUN;
+
+    let fee_to = AccountInternal::from(config.fee_to);
+    let protected = [fee_to, config.minting_account(), auction_account()];
+    let mut records = vec![];
+    let mut removed = 0;
+    for (account, amount) in balances {
+        let is_dust = policy.is_dust(amount);
+        if policy.mode == DustMode::Sweep
+            && is_dust
+            && !protected.contains(&account)
+            && !is_service_account(account.owner)
+            && FrozenAccounts::check_outgoing(account).is_ok()
+        {
+            let Some(fee_to_balance) = StableBalances.balance_of(&fee_to) + amount else {
+                continue;
+            };
+
+            StableBalances.remove(&account);
+            StableBalances.insert(fee_to, fee_to_balance);
+            if !amount.is_zero() {
+                records.push(TxRecord::dust_sweep(0, account, fee_to, amount));
+            }
+            DustReports::record_sweep(amount);
+            removed += 1;
+            continue;
+        }
+
+        scan.check(account.owner, is_dust);
+    }
+
+    LedgerData::append(records);
+    if is_last_batch {
+        DustReports::finish_pass(scan.finish(), now);
+    } else {
+        scan.cursor += MAX_ACCOUNTS_PER_RUN - removed;
+        DustReports::set_scan(scan);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::dust::DustPolicy;
+    use crate::state::ledger::Operation;
+
+    fn init(mode: DustMode) {
+        MockContext::new().inject();
+        StableBalances.clear();
+        LedgerData::clear();
+        DustReports::clear();
+        TokenConfig::set_stable(TokenConfig {
+            fee_to: john(),
+            dust_policy: Some(DustPolicy {
+                threshold: 10.into(),
+                mode,
+            }),
+            ..TokenConfig::default()
+        });
+
+        StableBalances.insert(alice().into(), 5.into());
+        StableBalances.insert(AccountInternal::new(alice(), Some([1; 32])), 0.into());
+        StableBalances.insert(bob().into(), 100.into());
+        StableBalances.insert(AccountInternal::new(bob(), Some([1; 32])), 1.into());
+    }
+
+    #[test]
+    fn dust_is_swept_to_fee_to() {
+        init(DustMode::Sweep);
+
+        run_dust_maintenance(1);
+        assert_eq!(StableBalances.balance_of(&john().into()), 6.into());
+        assert_eq!(StableBalances.get(&alice().into()), None);
+        assert_eq!(StableBalances.get_subaccounts(bob()).len(), 1);
+        assert_eq!(StableBalances.holders_count(), 2);
+
+        let report = DustReports::get();
+        assert_eq!(report.swept_accounts, 2);
+        assert_eq!(report.swept_total, 6.into());
+        assert_eq!(report.removed_empty_accounts, 1);
+        assert_eq!(
+            LedgerData::get(LedgerData::len() - 1).unwrap().operation,
+            Operation::DustSweep
+        );
+    }
+
+    #[test]
+    fn dust_holders_are_excluded() {
+        init(DustMode::ExcludeFromHolders);
+
+        run_dust_maintenance(1);
+        assert_eq!(StableBalances.holders_count(), 2);
+        assert_eq!(DustReports::get().dust_holders, 1);
+        assert_eq!(
+            DustReports::excluded_holders(TokenConfig::get_stable().dust_policy),
+            1
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Deposit subaccounts for the exchanges.
+//!
+//! An exchange gives every user a deposit account of the token canister itself, with the
+//! subaccount derived from the index the exchange assigned to the user, see
+//! `exchange_deposit_account`. The owner moves the deposits to the hot wallet of the exchange with
+//! `sweep_subaccounts`. Every swept subaccount is a separate transfer in the ledger with the source
+//! subaccount as the memo, so the exchange can match the deposits to its users.
+
+use candid::{CandidType, Deserialize};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use crate::account::{Account, AccountInternal, Subaccount};
+use crate::error::TxError;
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::{FeeRatio, TokenConfig};
+use crate::state::ledger::LedgerData;
+use crate::tx_record::TxId;
+
+use super::is20_transactions::transfer_internal;
+
+const EXCHANGE_DEPOSIT_SUBACCOUNT_PREFIX: &[u8] = b"is20-exchange-deposit";
+
+/// Deposit subaccounts to sweep.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum SweepSelection {
+    /// Subaccounts with the indices in `[start, end)`.
+    Range {
+        start: u64,
+        end: u64,
+    },
+    List(Vec<u64>),
+}
+
+impl SweepSelection {
+    fn len(&self) -> usize {
+        match self {
+            Self::Range { start, end } => end.saturating_sub(*start) as usize,
+            Self::List(indices) => indices.len(),
+        }
+    }
+
+    fn indices(self) -> Vec<u64> {
+        match self {
+            Self::Range { start, end } => (start..end).collect(),
+            Self::List(indices) => indices,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct SweptDeposit {
+    /// Amount moved to the hot wallet, the fee is charged on top of it.
+    pub amount: Tokens128,
+    pub tx_id: TxId,
+}
+
+#[derive(Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct SweepResult {
+    pub index: u64,
+    pub result: Result<SweptDeposit, TxError>,
+}
+
+pub fn exchange_deposit_subaccount(index: u64) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    subaccount[..EXCHANGE_DEPOSIT_SUBACCOUNT_PREFIX.len()]
+        .copy_from_slice(EXCHANGE_DEPOSIT_SUBACCOUNT_PREFIX);
+    subaccount[24..].copy_from_slice(&index.to_be_bytes());
+    subaccount
+}
+
+/// Account the user with the `index` deposits the tokens to.
+pub fn exchange_deposit_account(index: u64) -> Account {
+    Account::new(ic::id(), Some(exchange_deposit_subaccount(index)))
+}
+
+/// Moves the whole balance of every selected deposit subaccount, minus the fee, to the `to`
+/// account. A failure of one subaccount (e.g. a balance not covering the fee) doesn't stop the
+/// others. The selection is limited by `InputLimits::max_batch_entries`.
+pub fn sweep_subaccounts(
+    selection: SweepSelection,
+    to: AccountInternal,
+    auction_fee_ratio: f64,
+) -> Result<Vec<SweepResult>, TxError> {
+    let config = TokenConfig::get_stable();
+    config.input_limits().check_batch(selection.len())?;
+    if to.owner == ic::id() {
+        return Err(TxError::SelfTransfer);
+    }
+
+    let now = ic::time();
+    let results = selection
+        .indices()
+        .into_iter()
+        .map(|index| {
+            let subaccount = exchange_deposit_subaccount(index);
+            let from = AccountInternal::new(ic::id(), Some(subaccount));
+            let balance = StableBalances.balance_of(&from);
+            // The fee is computed for the whole balance, so nothing is left in the subaccount.
+            let (fee, fee_to) = config.fee_info(from, to, balance);
+            let amount = balance.saturating_sub(fee);
+            let result = transfer_internal(
+                &mut StableBalances,
+                from,
+                to,
+                amount,
+                fee,
+                fee_to.into(),
+                FeeRatio::new(auction_fee_ratio),
+                None,
+            )
+            .map(|_| SweptDeposit {
+                amount,
+                tx_id: LedgerData::transfer(from, to, amount, fee, Some(subaccount.to_vec()), now),
+            });
+
+            SweepResult { index, result }
+        })
+        .collect();
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::config::{InputLimits, Metadata};
+
+    fn init() -> AccountInternal {
+        MockContext::new().inject().update_id(alice());
+        StableBalances.clear();
+        LedgerData::clear();
+        TokenConfig::set_stable(
+            Metadata {
+                name: "".into(),
+                symbol: "".into(),
+                decimals: 8,
+                owner: bob(),
+                fee: 1.into(),
+                fee_to: bob(),
+                is_test_token: None,
+                init_spec: None,
+            }
+            .into(),
+        );
+
+        AccountInternal::new(bob(), Some([1; 32]))
+    }
+
+    #[test]
+    fn sweeps_deposit_subaccounts() {
+        let hot_wallet = init();
+        for (index, amount) in [(0, 100), (2, 1), (3, 50)] {
+            StableBalances.insert(exchange_deposit_account(index).into(), amount.into());
+        }
+
+        let results =
+            sweep_subaccounts(SweepSelection::Range { start: 0, end: 3 }, hot_wallet, 0.0).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].result,
+            Ok(SweptDeposit {
+                amount: 99.into(),
+                tx_id: 0,
+            })
+        );
+        assert_eq!(results[1].result, Err(TxError::AmountTooSmall));
+        assert_eq!(results[2].result, Err(TxError::AmountTooSmall));
+
+        let results = sweep_subaccounts(SweepSelection::List(vec![3]), hot_wallet, 0.0).unwrap();
+        assert_eq!(results[0].result.as_ref().unwrap().amount, 49.into());
+        assert_eq!(StableBalances.balance_of(&hot_wallet), 148.into());
+
+        let tx = LedgerData::get(1).unwrap();
+        assert_eq!(tx.memo, Some(exchange_deposit_subaccount(3).to_vec()));
+        assert_eq!(tx.from, AccountInternal::from(exchange_deposit_account(3)));
+
+        assert_eq!(
+            sweep_subaccounts(
+                SweepSelection::List(vec![0]),
+                exchange_deposit_account(4).into(),
+                0.0
+            ),
+            Err(TxError::SelfTransfer)
+        );
+    }
+
+    #[test]
+    fn selection_is_limited() {
+        let hot_wallet = init();
+        let mut config = TokenConfig::get_stable();
+        config.input_limits = Some(InputLimits {
+            max_batch_entries: 2,
+            ..InputLimits::default()
+        });
+        TokenConfig::set_stable(config);
+
+        assert_eq!(
+            sweep_subaccounts(SweepSelection::Range { start: 0, end: 3 }, hot_wallet, 0.0),
+            Err(TxError::BatchTooLarge { max_entries: 2 })
+        );
+        assert!(exchange_deposit_subaccount(1).starts_with(EXCHANGE_DEPOSIT_SUBACCOUNT_PREFIX));
+        assert_ne!(
+            exchange_deposit_subaccount(1),
+            exchange_deposit_subaccount(2)
+        );
+    }
+}
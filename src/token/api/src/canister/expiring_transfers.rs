@@ -0,0 +1,144 @@
+//! Transfers which the recipient must accept before they expire, e.g. for OTC deals or invoices.
+//!
+//! 1. The sender calls `transfer_with_expiry`, which moves `amount` from the sender to the escrow
+//!    account of the transfer. The sender pays the transfer fee.
+//! 2. The recipient calls `accept_transfer` before `expires_at` to receive the escrowed tokens.
+//! 3. If the transfer was not accepted in time, the sender calls `reclaim_transfer` to take the
+//!    tokens back.
+//!
+//! Every step is recorded in the transaction history as a transfer to or from the escrow account.
+
+use candid::Principal;
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use super::is20_transactions::transfer_internal;
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::balances::StableBalances;
+use crate::state::config::{FeeRatio, Timestamp, TokenConfig};
+use crate::state::expiring_transfers::{
+    escrow_subaccount, ExpiringTransfer, ExpiringTransferId, ExpiringTransferStatus,
+    ExpiringTransfers, MAX_TRANSFER_EXPIRY_NANOS,
+};
+use crate::state::large_transfers::LargeTransfers;
+use crate::state::ledger::{LedgerData, Memo, TxReceipt};
+
+pub fn transfer_with_expiry(
+    from: AccountInternal,
+    to: AccountInternal,
+    amount: Tokens128,
+    memo: Option<Memo>,
+    expires_at: Timestamp,
+    auction_fee_ratio: f64,
+) -> Result<ExpiringTransferId, TxError> {
+    let now = ic::time();
+    if expires_at <= now || expires_at - now > MAX_TRANSFER_EXPIRY_NANOS {
+        return Err(TxError::InvalidTransferExpiry {
+            max_nanos: MAX_TRANSFER_EXPIRY_NANOS,
+        });
+    }
+
+    if from == to {
+        return Err(TxError::SelfTransfer);
+    }
+
+    let config = TokenConfig::get_stable();
+    config.input_limits().check_memo(memo.as_ref())?;
+    LargeTransfers::check_amount(amount)?;
+
+    let escrow = AccountInternal::new(
+        ic::id(),
+        Some(escrow_subaccount(ExpiringTransfers::next_id())),
+    );
+    let (fee, fee_to) = config.fee_info(from, escrow, amount);
+    transfer_internal(
+        &mut StableBalances,
+        from,
+        escrow,
+        amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        None,
+    )?;
+    LedgerData::transfer(from, escrow, amount, fee, memo.clone(), now);
+
+    let transfer = ExpiringTransfers::insert_new(from, to, amount, memo, now, expires_at);
+    Ok(transfer.id)
+}
+
+/// Releases the escrowed tokens to the recipient. Can be called only by the recipient before the
+/// transfer expires.
+pub fn accept_transfer(caller: Principal, id: ExpiringTransferId) -> TxReceipt {
+    let transfer = pending_transfer(id)?;
+    if transfer.to.owner != caller {
+        return Err(TxError::Unauthorized);
+    }
+
+    if ic::time() > transfer.expires_at {
+        return Err(TxError::TransferExpired {
+            expired_at: transfer.expires_at,
+        });
+    }
+
+    let tx_id = release_escrow(&transfer, transfer.to.into())?;
+    ExpiringTransfers::settle(id, ExpiringTransferStatus::Accepted)?;
+    Ok(tx_id)
+}
+
+/// Returns the escrowed tokens to the sender. Can be called only by the sender after the transfer
+/// expired.
+pub fn reclaim_transfer(caller: Principal, id: ExpiringTransferId) -> TxReceipt {
+    let transfer = pending_transfer(id)?;
+    if transfer.from.owner != caller {
+        return Err(TxError::Unauthorized);
+    }
+
+    if ic::time() <= transfer.expires_at {
+        return Err(TxError::TransferNotExpired {
+            expires_at: transfer.expires_at,
+        });
+    }
+
+    let tx_id = release_escrow(&transfer, transfer.from.into())?;
+    ExpiringTransfers::settle(id, ExpiringTransferStatus::Reclaimed)?;
+    Ok(tx_id)
+}
+
+fn pending_transfer(id: ExpiringTransferId) -> Result<ExpiringTransfer, TxError> {
+    let transfer = ExpiringTransfers::get(id).ok_or(TxError::ExpiringTransferNotFound)?;
+    if transfer.status != ExpiringTransferStatus::Pending {
+        return Err(TxError::InvalidExpiringTransferStatus {
+            status: transfer.status,
+        });
+    }
+
+    Ok(transfer)
+}
+
+/// Moves the escrowed tokens to the `to` account. The transfer fee was paid by the sender when the
+/// tokens were escrowed.
+fn release_escrow(transfer: &ExpiringTransfer, to: AccountInternal) -> TxReceipt {
+    let escrow = transfer.escrow_account();
+    let fee_to = TokenConfig::get_stable().fee_to;
+    transfer_internal(
+        &mut StableBalances,
+        escrow,
+        to,
+        transfer.amount,
+        Tokens128::ZERO,
+        fee_to.into(),
+        FeeRatio::new(0.0),
+        None,
+    )?;
+    let id = LedgerData::transfer(
+        escrow,
+        to,
+        transfer.amount,
+        Tokens128::ZERO,
+        transfer.memo.clone(),
+        ic::time(),
+    );
+    Ok(id.into())
+}
@@ -0,0 +1,293 @@
+//! Export of the transaction history for the accounting.
+//!
+//! `export_transactions` returns the transactions made in a time range as a chunk of CSV or JSON,
+//! oldest first. The chunk ends with the cursor of the next one, which is the id of the next
+//! transaction to check, so the chunks don't shift when new transactions are added. The
+//! transactions pruned from the history in the meantime are skipped.
+//!
+//! The exports taking many calls can be pinned to an export session, see `state::export_sessions`:
+//! `export_session_transactions` doesn't return the transactions made after the session started,
+//! and `export_holders` returns the balances at the start of the session.
+
+use candid::{CandidType, Deserialize};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use serde_json::Value as JsonValue;
+
+use crate::account::{Account, AccountInternal};
+use crate::canister::http::{hex_encode, tx_to_json};
+use crate::error::TxError;
+use crate::state::balances::{AccountKey, StableBalances};
+use crate::state::config::Timestamp;
+use crate::state::export_sessions::{ExportSessionId, ExportSessions};
+use crate::state::ledger::LedgerData;
+use crate::state::snapshots::Snapshots;
+use crate::tx_record::{TxId, TxRecord};
+
+/// Maximum number of the transactions returned in one chunk.
+pub const MAX_EXPORTED_TRANSACTIONS: usize = 1_000;
+
+/// Maximum number of the transactions checked by one call, so the calls for a short time range
+/// in a long history fit in the query instruction limit.
+pub const MAX_SCANNED_TRANSACTIONS: usize = 10_000;
+
+/// Maximum number of the accounts returned in one chunk of the holders.
+pub const MAX_EXPORTED_HOLDERS: usize = 1_000;
+
+const CSV_HEADER: &str = "index,timestamp,operation,status,caller,from_owner,from_subaccount,\
+to_owner,to_subaccount,amount,fee,total,memo";
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Rows of comma separated values. The first chunk starts with the header.
+    Csv,
+    /// JSON array of the transactions, the same as returned by the `/transactions` HTTP route
+    /// with the `total` field added.
+    Json,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TransactionsExport {
+    pub data: String,
+    pub count: u32,
+    /// Cursor of the next chunk, `None` if the whole history was checked.
+    pub next_cursor: Option<TxId>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct HoldersExport {
+    /// Accounts with non-zero balances at the start of the session, in the order of the keys.
+    pub holders: Vec<(Account, Tokens128)>,
+    /// Cursor of the next chunk, `None` if all the accounts were checked.
+    pub next_cursor: Option<Account>,
+}
+
+/// Exports the transactions with the timestamps in the range `[from_ts, to_ts)`, starting with
+/// the transaction `cursor`, or the oldest stored one if `None`.
+pub fn export_transactions(
+    format: ExportFormat,
+    from_ts: Timestamp,
+    to_ts: Timestamp,
+    cursor: Option<TxId>,
+) -> TransactionsExport {
+    export_transactions_until(format, from_ts, to_ts, cursor, TxId::MAX)
+}
+
+/// Same as `export_transactions`, but without the transactions made after the `session` started.
+pub fn export_session_transactions(
+    session: ExportSessionId,
+    format: ExportFormat,
+    from_ts: Timestamp,
+    to_ts: Timestamp,
+    cursor: Option<TxId>,
+    now: Timestamp,
+) -> Result<TransactionsExport, TxError> {
+    let session = ExportSessions::get(session, now)?;
+    Ok(export_transactions_until(
+        format,
+        from_ts,
+        to_ts,
+        cursor,
+        session.transactions_end,
+    ))
+}
+
+/// Exports the balances at the start of the `session`, starting after the account `cursor`, or
+/// from the first one if `None`.
+pub fn export_holders(
+    session: ExportSessionId,
+    cursor: Option<Account>,
+    now: Timestamp,
+) -> Result<HoldersExport, TxError> {
+    let session = ExportSessions::get(session, now)?;
+    let after = cursor.map(|account| AccountKey::from(AccountInternal::from(account)));
+
+    // The accounts at the snapshot are the current ones and the ones changed since then, which
+    // include the accounts removed since then. Both lists are sorted, so the first accounts of the
+    // merged list are among the first ones of each.
+    let mut accounts = StableBalances::accounts_after(after, MAX_EXPORTED_HOLDERS);
+    accounts.extend(Snapshots::changed_accounts(
+        session.id,
+        after,
+        MAX_EXPORTED_HOLDERS,
+    ));
+    accounts.sort();
+    accounts.dedup();
+    accounts.truncate(MAX_EXPORTED_HOLDERS);
+
+    let next_cursor = match accounts.last() {
+        Some(last) if accounts.len() == MAX_EXPORTED_HOLDERS => Some(last.account().into()),
+        _ => None,
+    };
+
+    let mut holders = Vec::with_capacity(accounts.len());
+    for key in accounts {
+        let account = key.account();
+        let balance = Snapshots::balance_at(account, session.id)?;
+        // The accounts created after the snapshot have zero balances at it.
+        if !balance.is_zero() {
+            holders.push((account.into(), balance));
+        }
+    }
+
+    Ok(HoldersExport {
+        holders,
+        next_cursor,
+    })
+}
+
+fn export_transactions_until(
+    format: ExportFormat,
+    from_ts: Timestamp,
+    to_ts: Timestamp,
+    cursor: Option<TxId>,
+    end: TxId,
+) -> TransactionsExport {
+    let mut scanned = LedgerData::get_range(cursor.unwrap_or(0), MAX_SCANNED_TRANSACTIONS);
+    let mut next_cursor = match scanned.last() {
+        Some(last) if scanned.len() == MAX_SCANNED_TRANSACTIONS => Some(last.index + 1),
+        _ => None,
+    };
+    scanned.retain(|tx| tx.index < end);
+    next_cursor = next_cursor.filter(|next| *next < end);
+
+    let mut exported = Vec::new();
+    for tx in scanned {
+        if exported.len() == MAX_EXPORTED_TRANSACTIONS {
+            next_cursor = Some(tx.index);
+            break;
+        }
+        if (from_ts..to_ts).contains(&tx.timestamp) {
+            exported.push(tx);
+        }
+    }
+
+    let data = match format {
+        ExportFormat::Csv => {
+            let mut rows: Vec<_> = exported.iter().map(csv_row).collect();
+            if cursor.is_none() {
+                rows.insert(0, CSV_HEADER.to_string());
+            }
+            rows.into_iter().map(|row| row + "\n").collect()
+        }
+        ExportFormat::Json => JsonValue::Array(exported.iter().map(json_row).collect()).to_string(),
+    };
+
+    TransactionsExport {
+        data,
+        count: exported.len() as u32,
+        next_cursor,
+    }
+}
+
+/// Amount debited from the sender: the amount and the fee.
+fn total(tx: &TxRecord) -> u128 {
+    tx.amount.amount.saturating_add(tx.fee.amount)
+}
+
+fn csv_row(tx: &TxRecord) -> String {
+    [
+        tx.index.to_string(),
+        tx.timestamp.to_string(),
+        format!("{:?}", tx.operation),
+        format!("{:?}", tx.status),
+        tx.caller.to_text(),
+        tx.from.owner.to_text(),
+        tx.from.subaccount.map(hex_encode).unwrap_or_default(),
+        tx.to.owner.to_text(),
+        tx.to.subaccount.map(hex_encode).unwrap_or_default(),
+        tx.amount.to_string(),
+        tx.fee.to_string(),
+        total(tx).to_string(),
+        tx.memo.as_ref().map(hex_encode).unwrap_or_default(),
+    ]
+    .join(",")
+}
+
+fn json_row(tx: &TxRecord) -> JsonValue {
+    let mut row = tx_to_json(tx);
+    row["total"] = JsonValue::String(total(tx).to_string());
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::balances::Balances;
+
+    #[test]
+    fn exports_time_range() {
+        MockContext::new().inject();
+        LedgerData::clear();
+
+        let alice = AccountInternal::from(alice());
+        let bob = AccountInternal::from(bob());
+        for timestamp in [10, 20, 30] {
+            LedgerData::transfer(alice, bob, 100.into(), 1.into(), None, timestamp);
+        }
+
+        let export = export_transactions(ExportFormat::Csv, 20, 30, None);
+        assert_eq!(export.count, 1);
+        assert_eq!(export.next_cursor, None);
+        let rows: Vec<_> = export.data.lines().collect();
+        assert_eq!(rows[0], CSV_HEADER);
+        assert!(rows[1].starts_with("1,20,Transfer,Succeeded,"));
+        assert!(rows[1].ends_with(",100,1,101,"));
+
+        let export = export_transactions(ExportFormat::Json, 0, 100, Some(2));
+        let json: JsonValue = serde_json::from_str(&export.data).unwrap();
+        assert_eq!(export.count, 1);
+        assert_eq!(json[0]["index"], 2);
+        assert_eq!(json[0]["total"], "101");
+    }
+
+    #[test]
+    fn session_pins_holders_and_transactions() {
+        MockContext::new().inject();
+        LedgerData::clear();
+        StableBalances.clear();
+        Snapshots::clear();
+        ExportSessions::clear();
+
+        let alice = AccountInternal::from(alice());
+        let bob = AccountInternal::from(bob());
+        let john = AccountInternal::from(john());
+        StableBalances.insert(alice, 100.into());
+        StableBalances.insert(bob, 50.into());
+        LedgerData::transfer(alice, bob, 50.into(), 0.into(), None, 10);
+
+        let session = ExportSessions::start(alice.owner, 20).unwrap();
+
+        // Changes after the session started: bob's account is removed and john's is created.
+        StableBalances.remove(&bob);
+        StableBalances.insert(john, 150.into());
+        StableBalances.insert(alice, 0.into());
+        LedgerData::transfer(bob, john, 50.into(), 0.into(), None, 30);
+
+        let mut expected = vec![
+            (Account::from(alice), Tokens128::from(100)),
+            (Account::from(bob), Tokens128::from(50)),
+        ];
+        expected.sort_by_key(|(account, _)| AccountKey::from(AccountInternal::from(*account)));
+        let export = export_holders(session.id, None, 40).unwrap();
+        assert_eq!(export.holders, expected);
+        assert_eq!(export.next_cursor, None);
+
+        let export =
+            export_session_transactions(session.id, ExportFormat::Json, 0, 100, None, 40).unwrap();
+        assert_eq!(export.count, 1);
+        assert_eq!(
+            export_transactions(ExportFormat::Json, 0, 100, None).count,
+            2
+        );
+
+        ExportSessions::release(alice.owner, session.id, 40).unwrap();
+        assert_eq!(
+            export_holders(session.id, None, 40),
+            Err(TxError::ExportSessionNotFound)
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Handover of the token to a new team in one step.
+//!
+//! `handover` replaces the owner, moves the fees to the new owner if they went to the old one, and
+//! replaces the old owner with the new one in the controllers of the token canister. The
+//! controllers are changed first, as it's the only step which can fail. If the configuration was
+//! changed while the management canister was called, the controllers are restored and nothing
+//! else is changed. The whole transition is recorded as one entry of the admin log.
+//!
+//! The handover is refused while a timelock delay is set, as it would bypass the delay of the
+//! owner change.
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_kit::ic;
+
+use super::controllers::{list_controllers, set_controllers, MAX_CONTROLLERS};
+use super::http;
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::config::{TokenConfig, Value};
+use crate::state::guard::StateGuard;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct HandoverSummary {
+    pub previous_owner: Principal,
+    pub new_owner: Principal,
+    pub fee_to: Principal,
+    pub controllers: Vec<Principal>,
+}
+
+/// Hands the token over from the owner `caller` to the `new_owner`.
+pub async fn handover(caller: Principal, new_owner: Principal) -> Result<HandoverSummary, TxError> {
+    check_handover(&TokenConfig::get_stable(), caller, new_owner)?;
+
+    let _guard = StateGuard::global("update_controllers")?;
+    let previous_controllers = list_controllers().await?;
+    let controllers = handover_controllers(&previous_controllers, caller, new_owner, ic::id())?;
+    set_controllers(controllers.clone()).await?;
+
+    let config = TokenConfig::get_stable();
+    if let Err(e) = check_handover(&config, caller, new_owner) {
+        set_controllers(previous_controllers).await?;
+        return Err(e);
+    }
+
+    Ok(apply_handover(config, new_owner, controllers))
+}
+
+fn check_handover(
+    config: &TokenConfig,
+    caller: Principal,
+    new_owner: Principal,
+) -> Result<(), TxError> {
+    if config.owner != caller || config.is_ownership_renounced() {
+        return Err(TxError::Unauthorized);
+    }
+
+    if new_owner == caller
+        || new_owner == Principal::anonymous()
+        || new_owner == Principal::management_canister()
+    {
+        return Err(TxError::InvalidHandover {
+            reason: "new owner must be another non-anonymous principal".into(),
+        });
+    }
+
+    if config.timelock_delay_nanos.is_some() {
+        return Err(TxError::InvalidHandover {
+            reason: "owner changes are timelocked".into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Controllers after the handover: the `old_owner` replaced with the `new_owner`. The `token`
+/// itself stays a controller, so it can still manage its settings.
+fn handover_controllers(
+    controllers: &[Principal],
+    old_owner: Principal,
+    new_owner: Principal,
+    token: Principal,
+) -> Result<Vec<Principal>, TxError> {
+    let mut controllers: Vec<_> = controllers
+        .iter()
+        .copied()
+        .filter(|&controller| controller != old_owner)
+        .collect();
+    for required in [token, new_owner] {
+        if !controllers.contains(&required) {
+            controllers.push(required);
+        }
+    }
+
+    if controllers.len() > MAX_CONTROLLERS {
+        return Err(TxError::TooManyControllers {
+            max: MAX_CONTROLLERS as u64,
+        });
+    }
+
+    Ok(controllers)
+}
+
+fn apply_handover(
+    mut config: TokenConfig,
+    new_owner: Principal,
+    controllers: Vec<Principal>,
+) -> HandoverSummary {
+    let previous_owner = std::mem::replace(&mut config.owner, new_owner);
+    let previous_fee_to = config.fee_to;
+    if config.fee_to == previous_owner {
+        config.fee_to = new_owner;
+    }
+
+    let summary = HandoverSummary {
+        previous_owner,
+        new_owner,
+        fee_to: config.fee_to,
+        controllers,
+    };
+    TokenConfig::set_stable(config);
+
+    let controllers = summary
+        .controllers
+        .iter()
+        .map(Principal::to_text)
+        .collect::<Vec<_>>()
+        .join(",");
+    AdminLog::record(
+        previous_owner,
+        AdminAction::Handover { new_owner },
+        Some(Value::Text(format!(
+            "owner={previous_owner} fee_to={previous_fee_to}"
+        ))),
+        Some(Value::Text(format!(
+            "owner={new_owner} fee_to={} controllers={controllers}",
+            summary.fee_to
+        ))),
+    );
+    http::update_certified_data();
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john, xtc};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn config() -> TokenConfig {
+        TokenConfig {
+            owner: alice(),
+            fee_to: alice(),
+            ..TokenConfig::default()
+        }
+    }
+
+    #[test]
+    fn handover_checks() {
+        assert_eq!(check_handover(&config(), alice(), bob()), Ok(()));
+        assert_eq!(
+            check_handover(&config(), bob(), john()),
+            Err(TxError::Unauthorized)
+        );
+        assert!(check_handover(&config(), alice(), alice()).is_err());
+        assert!(check_handover(&config(), alice(), Principal::anonymous()).is_err());
+
+        let timelocked = TokenConfig {
+            timelock_delay_nanos: Some(1),
+            ..config()
+        };
+        assert!(check_handover(&timelocked, alice(), bob()).is_err());
+    }
+
+    #[test]
+    fn controllers_are_replaced() {
+        assert_eq!(
+            handover_controllers(&[alice(), xtc(), john()], alice(), bob(), xtc()),
+            Ok(vec![xtc(), john(), bob()])
+        );
+        assert_eq!(
+            handover_controllers(&[bob()], alice(), bob(), xtc()),
+            Ok(vec![bob(), xtc()])
+        );
+        assert!(handover_controllers(&[john(); MAX_CONTROLLERS], alice(), bob(), xtc()).is_err());
+    }
+
+    #[test]
+    fn handover_is_recorded_once() {
+        MockContext::new().inject();
+        AdminLog::clear();
+
+        let summary = apply_handover(config(), bob(), vec![bob(), xtc()]);
+        assert_eq!(
+            summary,
+            HandoverSummary {
+                previous_owner: alice(),
+                new_owner: bob(),
+                fee_to: bob(),
+                controllers: vec![bob(), xtc()],
+            }
+        );
+        let config = TokenConfig::get_stable();
+        assert_eq!((config.owner, config.fee_to), (bob(), bob()));
+
+        let log = AdminLog::get(0, 10);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].action, AdminAction::Handover { new_owner: bob() });
+
+        // A separate fee recipient is kept.
+        let config = TokenConfig {
+            fee_to: john(),
+            ..config
+        };
+        assert_eq!(apply_handover(config, alice(), vec![]).fee_to, john());
+    }
+}
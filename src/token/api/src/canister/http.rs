@@ -0,0 +1,362 @@
+//! Read-only HTTP gateway for the token data.
+//!
+//! Serves JSON documents through the boundary node, so the explorers can read the token data
+//! without an agent:
+//! * `/metadata` - token metadata, certified with the `IC-Certificate` header;
+//! * `/balance/<principal>` - balance of the default subaccount of the principal;
+//! * `/transactions?start=<id>&limit=<count>` - transactions in ascending order of their ids;
+//! * `/metrics` - metrics in the Prometheus text exposition format.
+//!
+//! The certified data of the canister is the root of a tree with two labeled subtrees: the
+//! `http_assets` tree certifying the metadata document, and the `balances` tree certifying the
+//! balances returned by `get_certified_balance`, see `canister::certified_balances`.
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_kit::ic;
+use ic_certified_map::{
+    fork, fork_hash, labeled, labeled_hash, AsHashTree, Hash, HashTree, RbTree,
+};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+
+use crate::canister::MAX_TRANSACTION_REQUEST;
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::certified_balances::CertifiedBalances;
+use crate::state::config::TokenConfig;
+use crate::state::ledger::LedgerData;
+use crate::state::metrics::EndpointMetrics;
+use crate::tx_record::TxRecord;
+
+const METADATA_PATH: &str = "/metadata";
+const BALANCE_PATH_PREFIX: &str = "/balance/";
+const TRANSACTIONS_PATH: &str = "/transactions";
+const METRICS_PATH: &str = "/metrics";
+const CERTIFIED_ASSETS_LABEL: &[u8] = b"http_assets";
+const CERTIFIED_BALANCES_LABEL: &[u8] = b"balances";
+
+pub type HeaderField = (String, String);
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn json(status_code: u16, value: JsonValue) -> Self {
+        Self {
+            status_code,
+            headers: vec![("Content-Type".into(), "application/json".into())],
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn error(status_code: u16, message: &str) -> Self {
+        Self::json(status_code, json!({ "error": message }))
+    }
+}
+
+pub fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::error(405, "method not allowed");
+    }
+
+    let (path, query) = match request.url.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (request.url.as_str(), ""),
+    };
+
+    match path {
+        METADATA_PATH => metadata_response(),
+        TRANSACTIONS_PATH => transactions_response(query),
+        METRICS_PATH => metrics_response(),
+        path if path.starts_with(BALANCE_PATH_PREFIX) => {
+            balance_response(&path[BALANCE_PATH_PREFIX.len()..])
+        }
+        _ => HttpResponse::error(404, "not found"),
+    }
+}
+
+/// Updates the certified data of the canister with the hashes of the current metadata document
+/// and of the certified balances tree. Must be called on every change of the token metadata and
+/// of the certified balances.
+pub fn update_certified_data() {
+    ic::set_certified_data(&fork_hash(
+        &certified_balances_hash(),
+        &certified_assets_hash(),
+    ));
+}
+
+// The labels of the forked subtrees must be in the ascending order.
+fn certified_balances_hash() -> Hash {
+    labeled_hash(CERTIFIED_BALANCES_LABEL, &CertifiedBalances::root_hash())
+}
+
+fn certified_assets_hash() -> Hash {
+    let tree = metadata_tree(&metadata_body());
+    labeled_hash(CERTIFIED_ASSETS_LABEL, &tree.root_hash())
+}
+
+/// Returns the witness of the certified data for the `balance_witness` of the balances tree.
+pub(crate) fn balance_witness(balance_witness: HashTree<'_>) -> HashTree<'_> {
+    fork(
+        labeled(CERTIFIED_BALANCES_LABEL, balance_witness),
+        HashTree::Pruned(certified_assets_hash()),
+    )
+}
+
+/// Serializes the `witness` into the self-describing CBOR expected by the agents.
+pub(crate) fn encode_witness(witness: &HashTree<'_>) -> Option<Vec<u8>> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer.self_describe().ok()?;
+    witness.serialize(&mut serializer).ok()?;
+    Some(serializer.into_inner())
+}
+
+fn metadata_body() -> Vec<u8> {
+    let config = TokenConfig::get_stable();
+    json!({
+        "name": config.name,
+        "symbol": config.symbol,
+        "decimals": config.decimals,
+        "fee": config.fee.to_string(),
+        "fee_to": config.fee_to.to_text(),
+        "owner": config.owner.to_text(),
+        "is_test_token": config.is_test_token,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+fn metadata_tree(body: &[u8]) -> RbTree<&'static str, Hash> {
+    let mut tree = RbTree::new();
+    tree.insert(METADATA_PATH, Sha256::digest(body).into());
+    tree
+}
+
+fn metadata_response() -> HttpResponse {
+    let body = metadata_body();
+    let mut headers = vec![("Content-Type".into(), "application/json".into())];
+    if let Some(header) = certificate_header(&body) {
+        headers.push(header);
+    }
+
+    HttpResponse {
+        status_code: 200,
+        headers,
+        body,
+    }
+}
+
+fn certificate_header(body: &[u8]) -> Option<HeaderField> {
+    let certificate = ic::data_certificate()?;
+    let tree = metadata_tree(body);
+    let witness = fork(
+        HashTree::Pruned(certified_balances_hash()),
+        labeled(
+            CERTIFIED_ASSETS_LABEL,
+            tree.witness(METADATA_PATH.as_bytes()),
+        ),
+    );
+
+    Some((
+        "IC-Certificate".into(),
+        format!(
+            "certificate=:{}:, tree=:{}:",
+            base64::encode(certificate),
+            base64::encode(encode_witness(&witness)?)
+        ),
+    ))
+}
+
+fn balance_response(principal: &str) -> HttpResponse {
+    let principal = match Principal::from_text(principal) {
+        Ok(principal) => principal,
+        Err(_) => return HttpResponse::error(400, "invalid principal"),
+    };
+
+    let balance = StableBalances.balance_of(&principal.into());
+    HttpResponse::json(
+        200,
+        json!({
+            "owner": principal.to_text(),
+            "balance": balance.to_string(),
+        }),
+    )
+}
+
+fn transactions_response(query: &str) -> HttpResponse {
+    let mut start = 0u64;
+    let mut limit = MAX_TRANSACTION_REQUEST as u64;
+
+    for (name, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+        let value = match value.parse::<u64>() {
+            Ok(value) => value,
+            Err(_) => return HttpResponse::error(400, "invalid query parameter"),
+        };
+
+        match name {
+            "start" => start = value,
+            "limit" => limit = value.min(MAX_TRANSACTION_REQUEST as u64),
+            _ => {}
+        }
+    }
+
+    let end = start.saturating_add(limit).min(LedgerData::len());
+    let transactions = (start..end)
+        .filter_map(LedgerData::get)
+        .map(|tx| tx_to_json(&tx))
+        .collect::<Vec<_>>();
+
+    HttpResponse::json(
+        200,
+        json!({
+            "transactions": transactions,
+            "history_size": LedgerData::len(),
+        }),
+    )
+}
+
+fn metrics_response() -> HttpResponse {
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".into(), "text/plain; version=0.0.4".into())],
+        body: EndpointMetrics::prometheus().into_bytes(),
+    }
+}
+
+pub(crate) fn tx_to_json(tx: &TxRecord) -> JsonValue {
+    let account_to_json = |account: &crate::account::Account| {
+        json!({
+            "owner": account.owner.to_text(),
+            "subaccount": account.subaccount.map(hex_encode),
+        })
+    };
+
+    json!({
+        "index": tx.index,
+        "caller": tx.caller.to_text(),
+        "from": account_to_json(&tx.from),
+        "to": account_to_json(&tx.to),
+        "amount": tx.amount.to_string(),
+        "fee": tx.fee.to_string(),
+        "timestamp": tx.timestamp,
+        "operation": format!("{:?}", tx.operation),
+        "status": format!("{:?}", tx.status),
+        "memo": tx.memo.as_ref().map(hex_encode),
+    })
+}
+
+pub(crate) fn hex_encode(bytes: impl AsRef<[u8]>) -> String {
+    bytes.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::account::AccountInternal;
+
+    fn get(url: &str) -> HttpResponse {
+        http_request(HttpRequest {
+            method: "GET".into(),
+            url: url.into(),
+            headers: vec![],
+            body: vec![],
+        })
+    }
+
+    fn body_json(response: &HttpResponse) -> JsonValue {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    fn init() {
+        MockContext::new().with_caller(alice()).inject();
+        StableBalances.clear();
+        LedgerData::clear();
+
+        let mut config = TokenConfig::default();
+        config.name = "Testo".into();
+        config.symbol = "TST".into();
+        TokenConfig::set_stable(config);
+
+        StableBalances.insert(AccountInternal::from(alice()), 1000.into());
+        LedgerData::mint(alice().into(), alice().into(), 1000.into());
+        LedgerData::mint(alice().into(), bob().into(), 0.into());
+    }
+
+    #[test]
+    fn metadata_route() {
+        init();
+        let response = get("/metadata");
+        assert_eq!(response.status_code, 200);
+
+        let body = body_json(&response);
+        assert_eq!(body["name"], "Testo");
+        assert_eq!(body["symbol"], "TST");
+    }
+
+    #[test]
+    fn balance_route() {
+        init();
+        let response = get(&format!("/balance/{}", alice()));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(body_json(&response)["balance"], "1000");
+
+        let response = get("/balance/not-a-principal");
+        assert_eq!(response.status_code, 400);
+    }
+
+    #[test]
+    fn transactions_route() {
+        init();
+        let response = get("/transactions?start=1&limit=10");
+        assert_eq!(response.status_code, 200);
+
+        let body = body_json(&response);
+        assert_eq!(body["history_size"], 2);
+        assert_eq!(body["transactions"].as_array().unwrap().len(), 1);
+        assert_eq!(body["transactions"][0]["index"], 1);
+
+        let response = get("/transactions?start=abc");
+        assert_eq!(response.status_code, 400);
+    }
+
+    #[test]
+    fn metrics_route() {
+        init();
+        let response = get("/metrics");
+        assert_eq!(response.status_code, 200);
+
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.contains("is20_history_length 2\n"));
+        assert!(body.contains("is20_holders 1\n"));
+    }
+
+    #[test]
+    fn unknown_route_and_method() {
+        init();
+        assert_eq!(get("/unknown").status_code, 404);
+
+        let response = http_request(HttpRequest {
+            method: "POST".into(),
+            url: "/metadata".into(),
+            headers: vec![],
+            body: vec![],
+        });
+        assert_eq!(response.status_code, 405);
+    }
+}
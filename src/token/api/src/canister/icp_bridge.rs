@@ -0,0 +1,291 @@
+//! Bridge between ICP and the token, minting one token unit (e8s) per deposited ICP e8s.
+//!
+//! To deposit, the user transfers ICP to the account identifier returned by `deposit_address`
+//! and calls `deposit_icp` with the index of the transfer block. The block is verified with the
+//! ICP ledger `query_blocks` method, the tokens are minted to the user, and the deposit is swept
+//! to the bridge reserve (the default account of the token canister). The ICP transfer fee of the
+//! sweep is deducted from the minted amount.
+//!
+//! To withdraw, the user calls `withdraw_icp`, which burns the tokens and sends the ICP from the
+//! reserve, deducting the ICP transfer fee from the sent amount.
+//!
+//! All ICP transfers are stored as operations in `IcpBridge` and can be retried with
+//! `retry_bridge_operations` if the ledger call fails. The ICP ledger deduplicates the retries
+//! only within its 24 hours transaction window, so operations pending for longer than that are
+//! rejected as too old and must be resolved by the owner.
+
+use candid::{CandidType, Deserialize, Principal, Reserved};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
+
+use super::is20_transactions::{burn, mint};
+use crate::account::{deposit_subaccount, AccountInternal, Subaccount};
+use crate::error::TxError;
+use crate::state::config::TokenConfig;
+use crate::state::icp_bridge::{
+    BlockIndex, BridgeOperation, BridgeOperationKind, IcpAccountId, IcpBridge,
+};
+
+/// Fee of the ICP ledger transfers in e8s.
+pub const ICP_TRANSFER_FEE: u64 = 10_000;
+
+/// Returns the ICP account identifier the `owner` must transfer ICP to before calling
+/// `deposit_icp`.
+pub fn deposit_address(owner: Principal) -> IcpAccountId {
+    icp_account_id(ic::id(), Some(deposit_subaccount(owner)))
+}
+
+fn icp_account_id(owner: Principal, subaccount: Option<Subaccount>) -> IcpAccountId {
+    AccountIdentifier::new(
+        owner.into(),
+        Some(SubaccountIdentifier(subaccount.unwrap_or_default())),
+    )
+    .to_address()
+}
+
+fn icp_ledger() -> Result<Principal, TxError> {
+    TokenConfig::get_stable()
+        .icp_ledger
+        .ok_or(TxError::BridgeNotConfigured)
+}
+
+pub async fn deposit_icp(caller: Principal, block: BlockIndex) -> Result<u128, TxError> {
+    let ledger = icp_ledger()?;
+    IcpBridge::start_deposit(block)?;
+
+    let amount = match verify_deposit(ledger, caller, block).await {
+        Ok(amount) => amount,
+        Err(e) => {
+            IcpBridge::abort_deposit(block);
+            return Err(e);
+        }
+    };
+
+    let minted = Tokens128::from((amount - ICP_TRANSFER_FEE) as u128);
+    let to = AccountInternal::new(caller, None);
+    let tx_id = match mint(ic::id(), to, minted) {
+        Ok(tx_id) => tx_id,
+        Err(e) => {
+            IcpBridge::abort_deposit(block);
+            return Err(e);
+        }
+    };
+    IcpBridge::complete_deposit(block, tx_id as u64, minted);
+
+    let sweep = BridgeOperationKind::Sweep {
+        subaccount: deposit_subaccount(caller),
+        deposit_block: block,
+    };
+    let operation =
+        IcpBridge::add_operation(sweep, to.into(), amount - ICP_TRANSFER_FEE, ic::time());
+    // A failed sweep does not affect the minted tokens, it is retried later.
+    execute(ledger, &operation).await;
+
+    Ok(tx_id)
+}
+
+/// Returns the amount of the ICP deposited to the deposit address of the `caller` in the `block`.
+async fn verify_deposit(
+    ledger: Principal,
+    caller: Principal,
+    block: BlockIndex,
+) -> Result<u64, TxError> {
+    let invalid = |reason: &str| TxError::InvalidDeposit {
+        reason: reason.into(),
+    };
+
+    let args = GetBlocksArgs {
+        start: block,
+        length: 1,
+    };
+    let (response,) = ic::call::<_, (QueryBlocksResponse,), _>(ledger, "query_blocks", (args,))
+        .await
+        .map_err(|(_, message)| TxError::IcpLedgerError { message })?;
+
+    let block = response
+        .blocks
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("block is not found or archived"))?;
+
+    match block.transaction.operation {
+        Some(IcpOperation::Transfer { to, amount, .. }) => {
+            if to.as_slice() != deposit_address(caller) {
+                return Err(invalid(
+                    "transfer is not made to the deposit address of the caller",
+                ));
+            }
+
+            if amount.e8s <= ICP_TRANSFER_FEE {
+                return Err(invalid("amount does not cover the transfer fee"));
+            }
+
+            Ok(amount.e8s)
+        }
+        _ => Err(invalid("block is not a transfer")),
+    }
+}
+
+/// Burns `amount` of the caller's tokens and sends `amount` minus the ICP transfer fee to `to`.
+pub async fn withdraw_icp(
+    from: AccountInternal,
+    amount: Tokens128,
+    to: IcpAccountId,
+) -> Result<BridgeOperation, TxError> {
+    let ledger = icp_ledger()?;
+    let e8s = u64::try_from(amount.amount).map_err(|_| TxError::AmountOverflow)?;
+    if e8s <= ICP_TRANSFER_FEE {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    burn(from.owner, from, amount)?;
+
+    let withdrawal = BridgeOperationKind::Withdrawal { to };
+    let operation =
+        IcpBridge::add_operation(withdrawal, from.into(), e8s - ICP_TRANSFER_FEE, ic::time());
+    execute(ledger, &operation).await;
+
+    Ok(IcpBridge::get_operation(operation.id).unwrap_or(operation))
+}
+
+/// Retries all pending bridge operations. Returns the operations which are still pending.
+pub async fn retry_operations() -> Result<Vec<BridgeOperation>, TxError> {
+    let ledger = icp_ledger()?;
+    for operation in IcpBridge::pending_operations() {
+        execute(ledger, &operation).await;
+    }
+
+    Ok(IcpBridge::pending_operations())
+}
+
+async fn execute(ledger: Principal, operation: &BridgeOperation) {
+    let (from_subaccount, to) = match &operation.kind {
+        BridgeOperationKind::Sweep { subaccount, .. } => {
+            (Some(*subaccount), icp_account_id(ic::id(), None))
+        }
+        BridgeOperationKind::Withdrawal { to } => (None, *to),
+    };
+
+    let args = IcpTransferArgs {
+        memo: operation.id,
+        amount: IcpTokens {
+            e8s: operation.amount,
+        },
+        fee: IcpTokens {
+            e8s: ICP_TRANSFER_FEE,
+        },
+        from_subaccount: from_subaccount.map(|s| s.to_vec()),
+        to: to.to_vec(),
+        created_at_time: Some(IcpTimestamp {
+            timestamp_nanos: operation.created_at,
+        }),
+    };
+
+    let result = match ic::call::<_, (IcpTransferResult,), _>(ledger, "transfer", (args,)).await {
+        Ok((IcpTransferResult::Ok(block_index),)) => Ok(block_index),
+        // The operation was executed by one of the previous attempts.
+        Ok((IcpTransferResult::Err(IcpTransferError::TxDuplicate { duplicate_of }),)) => {
+            Ok(duplicate_of)
+        }
+        Ok((IcpTransferResult::Err(e),)) => Err(format!("{e:?}")),
+        Err((_, message)) => Err(message),
+    };
+
+    IcpBridge::record_attempt(operation.id, result);
+}
+
+// Types of the ICP ledger interface used by the bridge. Only the fields the bridge needs are
+// declared, the rest are skipped by the decoder.
+
+#[derive(CandidType, Deserialize)]
+struct GetBlocksArgs {
+    start: u64,
+    length: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct QueryBlocksResponse {
+    blocks: Vec<IcpBlock>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpBlock {
+    transaction: IcpTransaction,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpTransaction {
+    operation: Option<IcpOperation>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+struct IcpTokens {
+    e8s: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpTimestamp {
+    timestamp_nanos: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+enum IcpOperation {
+    Mint(Reserved),
+    Burn(Reserved),
+    Transfer {
+        from: Vec<u8>,
+        to: Vec<u8>,
+        amount: IcpTokens,
+        fee: IcpTokens,
+    },
+    Approve(Reserved),
+    TransferFrom(Reserved),
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpTransferArgs {
+    memo: u64,
+    amount: IcpTokens,
+    fee: IcpTokens,
+    from_subaccount: Option<Vec<u8>>,
+    to: Vec<u8>,
+    created_at_time: Option<IcpTimestamp>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum IcpTransferResult {
+    Ok(u64),
+    Err(IcpTransferError),
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum IcpTransferError {
+    BadFee { expected_fee: IcpTokens },
+    InsufficientFunds { balance: IcpTokens },
+    TxTooOld { allowed_window_nanos: u64 },
+    TxCreatedInFuture,
+    TxDuplicate { duplicate_of: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn deposit_addresses_are_unique() {
+        MockContext::new().inject();
+        assert_ne!(deposit_address(alice()), deposit_address(bob()));
+    }
+
+    #[test]
+    fn bridge_requires_ledger() {
+        MockContext::new().inject();
+        TokenConfig::set_stable(TokenConfig::default());
+        assert_eq!(icp_ledger(), Err(TxError::BridgeNotConfigured));
+    }
+}
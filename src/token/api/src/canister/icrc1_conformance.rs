@@ -0,0 +1,273 @@
+//! ICRC-1 conformance tests run against the mock canister.
+//!
+//! The scenarios follow the [ICRC-1 test suite](https://github.com/dfinity/ICRC-1/tree/main/test):
+//! metadata, fee handling, transaction deduplication and the minting account. The same scenarios
+//! are run against the compiled wasm by `src/tests/tests/icrc1_conformance.rs`.
+//!
+//! ICRC-2 (approvals) is not implemented by the token, so there are no ICRC-2 scenarios.
+
+use candid::{Nat, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+use canister_sdk::ic_kit::{ic, MockContext};
+use coverage_helper::test;
+
+use crate::account::Account;
+use crate::canister::icrc1_transfer::{PERMITTED_DRIFT, TX_WINDOW};
+use crate::canister::TokenCanisterAPI;
+use crate::error::TransferError;
+use crate::mock::TokenCanisterMock;
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::{Metadata, TokenConfig, Value};
+use crate::state::dedup::DedupIndex;
+use crate::state::ledger::{LedgerData, TransferArgs, MAX_MEMO_LENGTH};
+
+const FEE: u128 = 10;
+const INITIAL_BALANCE: u128 = 1000;
+
+#[cfg_attr(coverage_nightly, no_coverage)]
+fn conformance_context() -> (&'static MockContext, TokenCanisterMock) {
+    let context = MockContext::new().with_caller(john()).inject();
+
+    let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
+    let canister = TokenCanisterMock::from_principal(principal);
+    context.update_id(canister.principal());
+
+    TokenConfig::set_stable(TokenConfig::default());
+    StableBalances.clear();
+    LedgerData::clear();
+    DedupIndex::clear();
+
+    canister.init(
+        Metadata {
+            name: "Conformance".to_string(),
+            symbol: "CNF".to_string(),
+            decimals: 8,
+            owner: john(),
+            fee: FEE.into(),
+            fee_to: john(),
+            is_test_token: None,
+            init_spec: None,
+        },
+        Tokens128::from(0),
+    );
+
+    let mut config = TokenConfig::get_stable();
+    config.min_cycles = 0;
+    TokenConfig::set_stable(config);
+
+    canister
+        .mint(alice(), None, INITIAL_BALANCE.into())
+        .unwrap();
+    context.update_caller(alice());
+
+    (context, canister)
+}
+
+#[cfg_attr(coverage_nightly, no_coverage)]
+fn transfer(amount: u128) -> TransferArgs {
+    TransferArgs {
+        from_subaccount: None,
+        to: Account::from(bob()),
+        amount: amount.into(),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    }
+}
+
+#[cfg_attr(coverage_nightly, no_coverage)]
+fn balance(canister: &TokenCanisterMock, owner: Principal) -> u128 {
+    canister.icrc1_balance_of(owner.into()).amount
+}
+
+#[test]
+fn metadata_matches_token_info() {
+    let (_, canister) = conformance_context();
+    let metadata = canister.icrc1_metadata();
+    let entry = |key: &str| {
+        metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    };
+
+    assert_eq!(
+        entry("icrc1:name"),
+        Some(Value::Text(canister.icrc1_name()))
+    );
+    assert_eq!(
+        entry("icrc1:symbol"),
+        Some(Value::Text(canister.icrc1_symbol()))
+    );
+    assert_eq!(
+        entry("icrc1:decimals"),
+        Some(Value::Nat(Nat::from(canister.icrc1_decimals())))
+    );
+    assert_eq!(
+        entry("icrc1:fee"),
+        Some(Value::Nat(canister.icrc1_fee().amount.into()))
+    );
+
+    let standards = canister.icrc1_supported_standards();
+    assert!(standards.iter().any(|standard| standard.name == "ICRC-1"));
+}
+
+#[test]
+fn transfer_charges_fee() {
+    let (_, canister) = conformance_context();
+
+    let id = canister.icrc1_transfer(transfer(100)).unwrap();
+    assert_eq!(balance(&canister, alice()), INITIAL_BALANCE - 100 - FEE);
+    assert_eq!(balance(&canister, bob()), 100);
+
+    let record = canister.get_transaction(id as u64);
+    assert_eq!(record.amount, 100.into());
+    assert_eq!(record.fee, FEE.into());
+
+    // The explicitly given fee must be equal to the token fee.
+    let mut args = transfer(100);
+    args.fee = Some(FEE.into());
+    assert!(canister.icrc1_transfer(args).is_ok());
+}
+
+/// Transfers which must be rejected without changing the balances.
+#[test]
+fn invalid_transfers_are_rejected() {
+    let (ctx, canister) = conformance_context();
+    let now = ic::time();
+    ctx.add_time(TX_WINDOW + PERMITTED_DRIFT);
+    let later = ic::time();
+
+    let vectors: Vec<(&str, TransferArgs, TransferError)> = vec![
+        (
+            "bad fee",
+            TransferArgs {
+                fee: Some((FEE + 1).into()),
+                ..transfer(100)
+            },
+            TransferError::BadFee {
+                expected_fee: FEE.into(),
+            },
+        ),
+        (
+            "zero fee",
+            TransferArgs {
+                fee: Some(0.into()),
+                ..transfer(100)
+            },
+            TransferError::BadFee {
+                expected_fee: FEE.into(),
+            },
+        ),
+        (
+            "insufficient funds",
+            transfer(INITIAL_BALANCE),
+            TransferError::InsufficientFunds {
+                balance: INITIAL_BALANCE.into(),
+            },
+        ),
+        (
+            "too old",
+            TransferArgs {
+                created_at_time: Some(now),
+                ..transfer(100)
+            },
+            TransferError::TooOld,
+        ),
+        (
+            "created in future",
+            TransferArgs {
+                created_at_time: Some(later + PERMITTED_DRIFT + 1),
+                ..transfer(100)
+            },
+            TransferError::CreatedInFuture { ledger_time: later },
+        ),
+    ];
+
+    for (name, args, expected) in vectors {
+        assert_eq!(canister.icrc1_transfer(args), Err(expected), "{name}");
+    }
+
+    let args = TransferArgs {
+        memo: Some(vec![0; MAX_MEMO_LENGTH + 1]),
+        ..transfer(100)
+    };
+    assert!(matches!(
+        canister.icrc1_transfer(args),
+        Err(TransferError::GenericError { .. })
+    ));
+
+    assert_eq!(balance(&canister, alice()), INITIAL_BALANCE);
+    assert_eq!(balance(&canister, bob()), 0);
+}
+
+#[test]
+fn transfers_are_deduplicated() {
+    let (ctx, canister) = conformance_context();
+    let args = TransferArgs {
+        created_at_time: Some(ic::time()),
+        ..transfer(100)
+    };
+
+    let id = canister.icrc1_transfer(args.clone()).unwrap();
+    assert_eq!(
+        canister.icrc1_transfer(args.clone()),
+        Err(TransferError::Duplicate { duplicate_of: id })
+    );
+
+    // Any difference in the arguments makes the transfer a new one.
+    let with_memo = TransferArgs {
+        memo: Some(vec![1]),
+        ..args.clone()
+    };
+    assert!(canister.icrc1_transfer(with_memo).is_ok());
+    let with_fee = TransferArgs {
+        fee: Some(FEE.into()),
+        ..args.clone()
+    };
+    assert!(canister.icrc1_transfer(with_fee).is_ok());
+
+    // Transfers without `created_at_time` are never deduplicated.
+    assert!(canister.icrc1_transfer(transfer(100)).is_ok());
+    assert!(canister.icrc1_transfer(transfer(100)).is_ok());
+
+    // Out of the deduplication window the transfer is too old.
+    ctx.add_time(TX_WINDOW + PERMITTED_DRIFT + 1);
+    assert_eq!(canister.icrc1_transfer(args), Err(TransferError::TooOld));
+}
+
+#[test]
+fn minting_account_mints_and_burns() {
+    let (ctx, canister) = conformance_context();
+    let minter = canister.icrc1_minting_account().unwrap();
+    assert_eq!(minter, Account::from(john()));
+    let supply = canister.icrc1_total_supply().amount;
+
+    // Transfers from the minting account mint tokens without a fee.
+    ctx.update_caller(john());
+    canister.icrc1_transfer(transfer(100)).unwrap();
+    assert_eq!(balance(&canister, bob()), 100);
+    assert_eq!(canister.icrc1_total_supply().amount, supply + 100);
+
+    // Transfers to the minting account burn tokens without a fee.
+    ctx.update_caller(bob());
+    let burn = TransferArgs {
+        to: minter,
+        ..transfer(40)
+    };
+    canister.icrc1_transfer(burn.clone()).unwrap();
+    assert_eq!(balance(&canister, bob()), 60);
+    assert_eq!(canister.icrc1_total_supply().amount, supply + 60);
+
+    let burn_with_fee = TransferArgs {
+        fee: Some(FEE.into()),
+        ..burn
+    };
+    assert_eq!(
+        canister.icrc1_transfer(burn_with_fee),
+        Err(TransferError::BadFee {
+            expected_fee: 0.into()
+        })
+    );
+}
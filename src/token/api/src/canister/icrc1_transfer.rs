@@ -1,22 +1,25 @@
-use crate::account::{AccountInternal, CheckedAccount, WithRecipient};
+use crate::account::{CheckedAccount, WithRecipient};
 use crate::error::TxError;
 use crate::state::config::TokenConfig;
 use crate::state::ledger::{TransferArgs, TxReceipt};
 
+use super::burn_account;
 use super::is20_transactions::burn;
 use super::is20_transactions::is20_transfer;
 use super::is20_transactions::mint;
+use super::is20_transactions::validate_memo;
 
-pub const TX_WINDOW: u64 = 60_000_000_000;
-pub const PERMITTED_DRIFT: u64 = 2 * 60_000_000_000;
+pub use crate::state::config::{PERMITTED_DRIFT, TX_WINDOW};
 
 pub fn icrc1_transfer(
     caller: CheckedAccount<WithRecipient>,
     transfer: &TransferArgs,
     auction_fee_ratio: f64,
 ) -> TxReceipt {
+    validate_memo(transfer)?;
+
     let amount = transfer.amount;
-    let minter = AccountInternal::new(TokenConfig::get_stable().owner, None);
+    let minter = TokenConfig::get_stable().minting_account();
 
     // Checks and returns error if the fee is not zero
     let check_zero_fee = || {
@@ -42,6 +45,12 @@ pub fn icrc1_transfer(
         return burn(caller.recipient().owner, caller.inner(), amount);
     }
 
+    if caller.recipient() == burn_account() {
+        // Transfers to the burn address are burns, so they reduce the total supply.
+        check_zero_fee()?;
+        return burn(caller.inner().owner, caller.inner(), amount);
+    }
+
     is20_transfer(caller, transfer, auction_fee_ratio)
 }
 
@@ -63,7 +72,9 @@ mod tests {
     use crate::error::{TransferError, TxError};
     use crate::mock::*;
     use crate::state::balances::{Balances, StableBalances};
-    use crate::state::config::{Metadata, DEFAULT_MIN_CYCLES};
+    use crate::state::config::{
+        Metadata, TxWindow, DEFAULT_MIN_CYCLES, MAX_TX_WINDOW, MIN_TX_WINDOW,
+    };
     use crate::state::ledger::{LedgerData, Operation, TransactionStatus};
 
     use super::*;
@@ -100,6 +111,7 @@ mod tests {
                 fee: Tokens128::from(0),
                 fee_to: john(),
                 is_test_token: None,
+                init_spec: None,
             },
             Tokens128::from(1000),
         );
@@ -128,7 +140,7 @@ mod tests {
     fn minting_with_nonzero_fee() {
         let (_ctx, canister) = test_context();
 
-        let minter = AccountInternal::new(TokenConfig::get_stable().owner, None);
+        let minter = TokenConfig::get_stable().minting_account();
         let to = Account::from(bob());
 
         let transfer = TransferArgs {
@@ -168,6 +180,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn transfer_to_burn_account() {
+        let (_ctx, canister) = test_context();
+        let supply = canister.icrc1_total_supply();
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: canister.get_burn_account(),
+            amount: Tokens128::from(100),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        assert!(canister.icrc1_transfer(transfer.clone()).is_ok());
+
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(900)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(canister.get_burn_account()),
+            Tokens128::from(0)
+        );
+        assert_eq!(
+            canister.icrc1_total_supply(),
+            (supply - 100.into()).unwrap()
+        );
+        assert_eq!(canister.get_burned_total(), Tokens128::from(100));
+
+        let tx = LedgerData::get(LedgerData::len() - 1).unwrap();
+        assert_eq!(tx.operation, Operation::Burn);
+
+        let with_fee = TransferArgs {
+            fee: Some(1.into()),
+            ..transfer
+        };
+        assert_eq!(
+            canister.icrc1_transfer(with_fee),
+            Err(TransferError::BadFee {
+                expected_fee: 0.into()
+            })
+        );
+    }
+
     #[test]
     fn transfer_without_fee() {
         let (ctx, canister) = test_context();
@@ -935,6 +991,52 @@ mod tests {
         assert!(canister.icrc1_transfer(transfer).is_err());
     }
 
+    #[test]
+    fn configured_transaction_time_window() {
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        let window = TxWindow {
+            tx_window_nanos: MIN_TX_WINDOW,
+            permitted_drift_nanos: 0,
+        };
+        assert!(matches!(
+            canister.set_tx_window(TxWindow {
+                tx_window_nanos: MAX_TX_WINDOW + 1,
+                ..window
+            }),
+            Err(TxError::InvalidTxWindow { .. })
+        ));
+        canister.set_tx_window(window).unwrap();
+        assert_eq!(canister.get_token_info().tx_window, Some(window));
+
+        ctx.update_caller(alice());
+        let now = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(10),
+            fee: None,
+            memo: None,
+            created_at_time: Some(now - 2 * MIN_TX_WINDOW),
+        };
+        assert_eq!(
+            canister.icrc1_transfer(transfer.clone()),
+            Err(TransferError::TooOld)
+        );
+
+        let transfer = TransferArgs {
+            created_at_time: Some(now + PERMITTED_DRIFT),
+            ..transfer
+        };
+        assert!(matches!(
+            canister.icrc1_transfer(transfer),
+            Err(TransferError::CreatedInFuture { .. })
+        ));
+    }
+
     #[test]
     fn test_invalid_self_account_transfer() {
         let canister = test_canister();
@@ -1157,6 +1259,7 @@ mod proptests {
                 fee,
                 fee_to,
                 is_test_token: None,
+                init_spec: None,
             };
 
             let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
@@ -1237,7 +1340,7 @@ mod proptests {
                         get_context().update_caller(from);
                         let from_balance = canister.icrc1_balance_of(Account::new(from, None));
                         let to_balance = canister.icrc1_balance_of(Account::new(to, None));
-                        let (fee , fee_to) = TokenConfig::get_stable().fee_info();
+                        let (fee , fee_to) = TokenConfig::get_stable().fee_info(from.into(), to.into(), amount);
                         let amount_with_fee = (amount + fee).unwrap();
                         let transfer1 = TransferArgs {
                             from_subaccount: None,
@@ -1,22 +1,88 @@
 use candid::{Nat, Principal};
 
+#[cfg(feature = "transfer")]
+use crate::account::Subaccount;
+#[cfg(feature = "transfer")]
+use crate::state::ledger::{BatchOperation, BatchTransferArgs};
 use crate::state::{
     balances::{Balances, StableBalances},
     config::TokenConfig,
 };
 
-static OWNER_METHODS: &[&str] = &[
+/// Methods callable by the owner only.
+pub static OWNER_METHODS: &[&str] = &[
     "set_auction_period",
+    "set_auction_retention",
+    "set_auction_strategy",
+    "set_auction_vesting",
+    "set_bidder_blacklisted",
+    "set_min_bid_cycles",
+    "withdraw_auction_residual",
+    "add_controller",
+    "handover",
+    "remove_controller",
+    "list_controllers",
+    "compact_ledger",
+    "upload_upgrade_chunk",
+    "clear_upgrade_chunks",
+    "commit_upgrade",
+    "freeze_account",
+    "purge_account_data",
+    "prune_transactions",
     "set_fee",
     "set_fee_to",
+    "set_history_retention",
+    "set_icp_ledger",
+    "set_large_transfer_policy",
+    "set_dust_policy",
+    "set_burn_policy",
+    "set_fee_policy",
+    "set_fee_recipients",
+    "set_voting_exclusions",
+    "take_snapshot",
+    "set_faucet_policy",
+    "renounce_ownership",
     "set_logo",
+    "set_memo_index",
+    "set_metadata_entry",
+    "set_localized_metadata",
     "set_min_cycles",
+    "set_minting_account",
     "set_name",
     "set_symbol",
     "set_owner",
+    "set_wrapped_token",
+    "remove_metadata_entry",
+    "repair_invariants",
+    "reset_endpoint_stats",
+    "rescale_decimals",
+    "set_tx_window",
+    "set_input_limits",
+    "sweep_subaccounts",
+    "set_timelock_delay",
+    "cancel_proposal",
+    "retry_outbound_message",
+    "drop_outbound_message",
+    "run_state_migrations",
+    "unfreeze_account",
 ];
 
-static TRANSACTION_METHODS: &[&str] = &["burn", "icrc1_transfer"];
+/// Methods callable by the principals holding tokens only.
+pub static TRANSACTION_METHODS: &[&str] = &[
+    "approve",
+    "approve_and_notify",
+    "atomic_batch",
+    "authorize_merchant",
+    "burn",
+    "create_budget",
+    "create_standing_order",
+    "create_subscription",
+    "create_swap",
+    "icrc1_transfer",
+    "initiate_large_transfer",
+    "pay_request",
+    "transfer_with_expiry",
+];
 
 /// Reason why the method may be accepted.
 #[derive(Debug, Clone, Copy)]
@@ -27,6 +93,20 @@ pub enum AcceptReason {
     NotIS20Method,
 }
 
+/// Checks the number of the entries of the batch method call against the input limits.
+#[cfg(feature = "transfer")]
+fn is_batch_allowed(method: &str, config: &TokenConfig) -> bool {
+    use canister_sdk::ic_cdk::api::call::arg_data;
+
+    let entries = match method {
+        "batch_transfer" => arg_data::<(Option<Subaccount>, Vec<BatchTransferArgs>)>()
+            .1
+            .len(),
+        _ => arg_data::<(Vec<BatchOperation>,)>().0.len(),
+    };
+    config.input_limits().check_batch(entries).is_ok()
+}
+
 /// This function checks if the canister should accept ingress message or not. We allow query
 /// calls for anyone, but update calls have different checks to see, if it's reasonable to spend
 /// canister cycles on accepting this call. Check the comments in this method for details on
@@ -47,6 +127,14 @@ pub fn inspect_message(method: &str, caller: Principal) -> Result<AcceptReason,
         m if OWNER_METHODS.contains(&m) => {
             Err("Owner method is called not by an owner. Rejecting.")
         }
+        // The batches over the limit are rejected before the canister pays for their execution.
+        #[cfg(feature = "transfer")]
+        m @ ("batch_transfer" | "atomic_batch") if !is_batch_allowed(m, &stats) => {
+            Err("Batch has too many entries. Rejecting.")
+        }
+        // The owner may mint in a batch without holding tokens.
+        #[cfg(feature = "transfer")]
+        "atomic_batch" if caller == stats.owner => Ok(AcceptReason::Valid),
         #[cfg(any(feature = "transfer", feature = "mint_burn"))]
         m if TRANSACTION_METHODS.contains(&m) => {
             // These methods requires that the caller have tokens.
@@ -68,6 +156,69 @@ pub fn inspect_message(method: &str, caller: Principal) -> Result<AcceptReason,
 
             Ok(AcceptReason::Valid)
         }
+        // The signature is verified by the method itself, the relayer pays for the message.
+        #[cfg(feature = "transfer")]
+        "transfer_signed" => Ok(AcceptReason::Valid),
+        // The permissions are checked by the methods themselves.
+        "cancel_subscription"
+        | "revoke_merchant"
+        | "set_budget_limit"
+        | "set_budget_delegates"
+        | "remove_budget"
+        | "cancel_large_transfer"
+        | "cancel_payment_request"
+        | "pause_standing_order"
+        | "resume_standing_order"
+        | "cancel_standing_order"
+        | "initiate_recovery"
+        | "veto_recovery"
+        | "complete_recovery" => Ok(AcceptReason::Valid),
+        // Payment requests are created by the merchants, who don't have to hold tokens.
+        "create_payment_request" => Ok(AcceptReason::Valid),
+        #[cfg(feature = "transfer")]
+        "confirm_large_transfer" => Ok(AcceptReason::Valid),
+        #[cfg(feature = "transfer")]
+        "collect_subscription"
+        | "pull_payment"
+        | "transfer_from_allowance"
+        | "budget_transfer"
+        | "accept_swap"
+        | "refund_swap"
+        | "accept_transfer"
+        | "reclaim_transfer" => Ok(AcceptReason::Valid),
+        "set_account_tag" if StableBalances.get_subaccounts(caller).is_empty() => {
+            Err("Account tag is not set by a stakeholder. Rejecting.")
+        }
+        "set_account_tag" => Ok(AcceptReason::Valid),
+        "set_guardian" if StableBalances.get_subaccounts(caller).is_empty() => {
+            Err("Guardian is not set by a stakeholder. Rejecting.")
+        }
+        "set_guardian" => Ok(AcceptReason::Valid),
+        "retry_approval_notification" => Ok(AcceptReason::Valid),
+        // The signature is verified by the method itself, the spender pays for the message.
+        "permit" => Ok(AcceptReason::Valid),
+        // Export sessions are started by the indexers, who don't have to hold tokens.
+        "start_export" | "release_export" => Ok(AcceptReason::Valid),
+        #[cfg(feature = "auction")]
+        "set_bid_delegate" | "accept_bid_delegation" | "resign_bid_delegation" => {
+            Ok(AcceptReason::Valid)
+        }
+        #[cfg(feature = "icp_bridge")]
+        "deposit_icp" | "retry_bridge_operations" => Ok(AcceptReason::Valid),
+        #[cfg(feature = "icp_bridge")]
+        "withdraw_icp" if StableBalances.get_subaccounts(caller).is_empty() => {
+            Err("Withdrawal is not requested by a stakeholder. Rejecting.")
+        }
+        #[cfg(feature = "icp_bridge")]
+        "withdraw_icp" => Ok(AcceptReason::Valid),
+        #[cfg(feature = "icrc1_wrapper")]
+        "deposit" | "retry_wrapper_operations" => Ok(AcceptReason::Valid),
+        #[cfg(feature = "icrc1_wrapper")]
+        "withdraw" if StableBalances.get_subaccounts(caller).is_empty() => {
+            Err("Withdrawal is not requested by a stakeholder. Rejecting.")
+        }
+        #[cfg(feature = "icrc1_wrapper")]
+        "withdraw" => Ok(AcceptReason::Valid),
         "bid_cycles" => {
             // We reject this message, because a call with cycles cannot be made through ingress,
             // only from the wallet canister.
@@ -1,23 +1,155 @@
 //! This module contains APIs from IS20 standard providing cycle auction related functionality.
 
+use candid::{CandidType, Deserialize};
 use canister_sdk::{
     ic_auction::{
         error::AuctionError,
-        state::{AuctionInfo, AuctionState},
+        state::{AuctionInfo, AuctionState, MIN_BIDDING_AMOUNT},
     },
     ic_helpers::tokens::Tokens128,
     ic_kit::ic,
 };
 use ic_exports::Principal;
 
-use crate::state::ledger::{BatchTransferArgs, LedgerData};
+use crate::error::TxError;
+use crate::state::auction_history::{AuctionHistory, ResidualWithdrawal};
+use crate::state::auction_policy::AuctionPolicy;
+use crate::state::bid_delegates::BidDelegates;
+use crate::state::cycle_accounting::CycleAccounting;
+use crate::state::decimals::DecimalsMigration;
+use crate::state::ledger::{BatchTransferArgs, LedgerData, TxReceipt};
+use crate::state::vesting::{vesting_account, Vesting};
+use crate::tx_record::TxRecord;
 use crate::{
     account::AccountInternal,
     state::balances::{Balances, StableBalances},
 };
-use crate::{canister::auction_account, state::config::TokenConfig};
+use crate::{
+    canister::auction_account,
+    state::config::{AuctionStrategy, FeeRatio, Timestamp, TokenConfig},
+};
+
+use super::is20_transactions::{batch_transfer_internal, transfer_fees, transfer_internal};
+
+/// Estimated reward of a bid in the auction in progress.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq)]
+pub struct AuctionProjection {
+    /// Cycles of the bidder in the auction, including the estimated bid.
+    pub bidder_cycles: u64,
+    /// Cycles of all the rewarded bids, including the estimated bid.
+    pub total_cycles: u64,
+    /// Fees accumulated for the distribution so far.
+    pub accumulated_fees: Tokens128,
+    /// Reward of the bidder if the auction was held now, with the current bids and fees.
+    pub projected_reward: Tokens128,
+    /// Part of the accumulated fees the reward makes, from 0 to 1.
+    pub share: f64,
+}
+
+/// Bid of a bidder in the auction in progress.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct BidStatus {
+    /// Cycles rewarded to the bidder, bid either by the bidder or by its delegate.
+    pub cycles: u64,
+    /// Weight of the bid in the distribution by the auction strategy.
+    pub weight: u64,
+    /// Reward of the bidder if the auction was held now, with the current bids and fees.
+    pub projected_reward: Tokens128,
+    /// Time from which the next auction can be run.
+    pub next_auction_at: Timestamp,
+}
+
+/// Cycles of the token and their consumption, see `CycleAccounting`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq)]
+pub struct CycleConsumption {
+    pub balance: u64,
+    pub min_cycles: u64,
+    /// Average consumption in cycles per second, `None` if not measured yet.
+    pub average_per_second: Option<u64>,
+    /// Number of the auction periods the cycles above `min_cycles` last at the average
+    /// consumption, `None` if the consumption is not measured yet or is zero.
+    pub runway_periods: Option<f64>,
+    pub fee_ratio: f64,
+    pub accepts_bids: bool,
+}
+
+/// Traps if the caller, or the delegator it bids for, is not allowed to bid the attached cycles by
+/// the `AuctionPolicy`, or if the token has enough cycles. Called before `bid_cycles` accepts the
+/// cycles, so the trap returns them to the bidder.
+pub fn check_bid(auction_state: &AuctionState) {
+    let cycles = ic::msg_cycles_available();
+    let bidder = ic::caller();
+    let result = AuctionPolicy::check_bid(bidder, cycles)
+        .and_then(|_| AuctionPolicy::check_bid(BidDelegates::beneficiary(bidder), cycles))
+        .and_then(|_| check_cycles_needed(auction_state, ic::balance()));
+    if let Err(e) = result {
+        ic::trap(&e.to_string());
+    }
+
+    if cycles >= MIN_BIDDING_AMOUNT {
+        CycleAccounting::record_bid(cycles);
+    }
+}
+
+/// Bids are accepted while the cycles above `min_cycles` last at most
+/// `MAX_BIDDING_RUNWAY_PERIODS` auction periods.
+pub fn check_cycles_needed(auction_state: &AuctionState, balance: u64) -> Result<(), TxError> {
+    if !CycleAccounting::accepts_bids(
+        balance,
+        auction_state.min_cycles,
+        period_seconds(auction_state),
+    ) {
+        return Err(TxError::BidsNotNeeded);
+    }
+
+    Ok(())
+}
+
+/// The fees are not disbursed while the cycles balance is below `min_cycles`, so the auction
+/// keeps collecting the bids until the token is funded. The auction is also postponed while the
+/// decimals migration runs, since the rewards cannot be transferred until it's completed.
+pub fn check_disbursement(auction_state: &AuctionState, balance: u64) -> Result<(), TxError> {
+    DecimalsMigration::check_not_running()?;
+    if balance < auction_state.min_cycles {
+        return Err(TxError::CyclesBelowMinimum {
+            balance,
+            min_cycles: auction_state.min_cycles,
+        });
+    }
+
+    Ok(())
+}
 
-use super::is20_transactions::batch_transfer_internal;
+/// Samples the cycles `balance` and sets the auction fee ratio from the average consumption.
+/// Called before every update call. The fee ratio is left as set by the auction until the
+/// consumption is measured.
+pub fn update_cycle_accounting(auction_state: &mut AuctionState, now: Timestamp, balance: u64) {
+    CycleAccounting::observe(now, balance);
+    let period_seconds = period_seconds(auction_state);
+    if let Some(fee_ratio) =
+        CycleAccounting::fee_ratio(balance, auction_state.min_cycles, period_seconds)
+    {
+        auction_state.bidding_state.fee_ratio = fee_ratio;
+    }
+}
+
+pub fn cycle_consumption(auction_state: &AuctionState, balance: u64) -> CycleConsumption {
+    let min_cycles = auction_state.min_cycles;
+    let period_seconds = period_seconds(auction_state);
+    CycleConsumption {
+        balance,
+        min_cycles,
+        average_per_second: CycleAccounting::average_consumption(),
+        runway_periods: CycleAccounting::runway_periods(balance, min_cycles, period_seconds)
+            .filter(|runway| runway.is_finite()),
+        fee_ratio: auction_state.bidding_state.fee_ratio,
+        accepts_bids: check_cycles_needed(auction_state, balance).is_ok(),
+    }
+}
+
+fn period_seconds(auction_state: &AuctionState) -> u64 {
+    auction_state.bidding_state.auction_period / 1_000_000_000
+}
 
 pub fn disburse_rewards(auction_state: &AuctionState) -> Result<AuctionInfo, AuctionError> {
     let AuctionState {
@@ -29,35 +161,44 @@ pub fn disburse_rewards(auction_state: &AuctionState) -> Result<AuctionInfo, Auc
     let total_amount = accumulated_fees();
     let mut transferred_amount = Tokens128::from(0u128);
     let total_cycles = bidding_state.cycles_since_auction;
+    if total_cycles == 0 {
+        return Err(AuctionError::NoBids);
+    }
 
     let first_transaction_id = LedgerData::len();
 
+    let stats = TokenConfig::get_stable();
+    let strategy = stats.auction_strategy.unwrap_or_default();
+    let vesting_nanos = stats.auction_vesting_nanos();
+    let bids = rewarded_bids(auction_state);
+
     let mut transfers = vec![];
-    for (bidder, cycles) in &bidding_state.bids {
-        let amount = (total_amount * cycles / total_cycles)
-            .ok_or(AuctionError::NoBids)?
-            .to_tokens128()
-            .unwrap_or(Tokens128::MAX);
-        transfers.push(BatchTransferArgs {
-            receiver: (*bidder).into(),
-            amount,
-        });
-        LedgerData::record_auction(*bidder, amount);
+    for (bidder, amount) in distribute(strategy, total_amount, &bids) {
+        // Vested rewards are held by the vesting account until they are claimed.
+        let receiver = match vesting_nanos {
+            Some(duration) => {
+                Vesting::grant(bidder, amount, duration, ic::time());
+                vesting_account().into()
+            }
+            None => bidder.into(),
+        };
+        transfers.push(BatchTransferArgs { receiver, amount });
+        LedgerData::record_auction(bidder, amount);
         transferred_amount = (transferred_amount + amount)
             .ok_or_else(|| ic::trap("Token amount overflow on auction bids distribution."))
             .unwrap();
     }
 
-    let stats = TokenConfig::get_stable();
-    let (fee, fee_to) = stats.fee_info();
+    let fees = transfer_fees(&stats, auction_account(), &transfers);
 
     if let Err(e) = batch_transfer_internal(
         auction_account(),
         &transfers,
         &mut StableBalances,
-        fee,
-        fee_to,
+        &fees,
+        stats.fee_to,
         auction_state.bidding_state.fee_ratio,
+        None,
     ) {
         ic::trap(&format!("Failed to transfer tokens to the bidders: {e}"));
     }
@@ -72,22 +213,273 @@ pub fn disburse_rewards(auction_state: &AuctionState) -> Result<AuctionInfo, Auc
         first_transaction_id,
         last_transaction_id,
     };
+    AuctionHistory::record(&result, stats.auction_retention);
 
     Ok(result)
 }
 
+/// Bids taking part in the distribution of the rewards, which are all the bids except for the
+/// bids of the blacklisted bidders. The bids of the delegates are counted as the bids of their
+/// delegators, so the delegators receive the rewards.
+fn rewarded_bids(auction_state: &AuctionState) -> Vec<(Principal, u64)> {
+    let mut bids: Vec<(Principal, u64)> = vec![];
+    for (bidder, cycles) in &auction_state.bidding_state.bids {
+        let beneficiary = BidDelegates::beneficiary(*bidder);
+        if AuctionPolicy::is_blacklisted(*bidder) || AuctionPolicy::is_blacklisted(beneficiary) {
+            continue;
+        }
+
+        match bids.iter_mut().find(|(b, _)| *b == beneficiary) {
+            Some((_, total)) => *total = total.saturating_add(*cycles),
+            None => bids.push((beneficiary, *cycles)),
+        }
+    }
+    bids
+}
+
+/// Estimates the reward of the `bidder` if they bid `bid_cycles` more and the auction was held
+/// now. The blacklisted bidders are projected no reward. The bids of a delegate are projected for
+/// its delegator.
+pub fn project(
+    auction_state: &AuctionState,
+    bidder: Principal,
+    bid_cycles: u64,
+) -> AuctionProjection {
+    let bidder = BidDelegates::beneficiary(bidder);
+    let mut bids = rewarded_bids(auction_state);
+    let mut bidder_cycles = 0;
+    if !AuctionPolicy::is_blacklisted(bidder) {
+        match bids.iter_mut().find(|(b, _)| *b == bidder) {
+            Some((_, cycles)) => *cycles = cycles.saturating_add(bid_cycles),
+            None => bids.push((bidder, bid_cycles)),
+        }
+        bidder_cycles = bids
+            .iter()
+            .find(|(b, _)| *b == bidder)
+            .map_or(0, |(_, cycles)| *cycles);
+    }
+
+    let accumulated_fees = accumulated_fees();
+    let projected_reward = bidder_reward(&bids, bidder, accumulated_fees);
+    let share = if accumulated_fees.is_zero() {
+        0.0
+    } else {
+        f64::from(projected_reward) / f64::from(accumulated_fees)
+    };
+
+    AuctionProjection {
+        bidder_cycles,
+        total_cycles: bids
+            .iter()
+            .fold(0u64, |total, (_, cycles)| total.saturating_add(*cycles)),
+        accumulated_fees,
+        projected_reward,
+        share,
+    }
+}
+
+/// Returns the current bid of the `bidder`, including the bids of its delegate.
+pub fn bid_status(auction_state: &AuctionState, bidder: Principal) -> BidStatus {
+    let bidding_state = &auction_state.bidding_state;
+    let cycles = bidding_state
+        .bids
+        .iter()
+        .filter(|(b, _)| BidDelegates::beneficiary(**b) == bidder)
+        .fold(0u64, |total, (_, cycles)| total.saturating_add(*cycles));
+    let strategy = TokenConfig::get_stable()
+        .auction_strategy
+        .unwrap_or_default();
+    let weight = match strategy {
+        AuctionStrategy::Quadratic => integer_sqrt(cycles),
+        AuctionStrategy::Linear | AuctionStrategy::Capped { .. } => cycles,
+    };
+
+    BidStatus {
+        cycles,
+        weight,
+        projected_reward: bidder_reward(&rewarded_bids(auction_state), bidder, accumulated_fees()),
+        next_auction_at: bidding_state
+            .last_auction
+            .saturating_add(bidding_state.auction_period),
+    }
+}
+
+fn bidder_reward(bids: &[(Principal, u64)], bidder: Principal, total: Tokens128) -> Tokens128 {
+    let strategy = TokenConfig::get_stable()
+        .auction_strategy
+        .unwrap_or_default();
+    distribute(strategy, total, bids)
+        .into_iter()
+        .find(|(b, _)| *b == bidder)
+        .map_or(Tokens128::ZERO, |(_, amount)| amount)
+}
+
+/// Splits the `total` amount between the `bids` according to the `strategy`.
+pub fn distribute(
+    strategy: AuctionStrategy,
+    total: Tokens128,
+    bids: &[(Principal, u64)],
+) -> Vec<(Principal, Tokens128)> {
+    match strategy {
+        AuctionStrategy::Linear => proportional(total, bids),
+        AuctionStrategy::Quadratic => {
+            let weights: Vec<_> = bids
+                .iter()
+                .map(|(bidder, cycles)| (*bidder, integer_sqrt(*cycles)))
+                .collect();
+            proportional(total, &weights)
+        }
+        AuctionStrategy::Capped { max_share } => capped(total, bids, max_share),
+    }
+}
+
+fn proportional(total: Tokens128, weights: &[(Principal, u64)]) -> Vec<(Principal, Tokens128)> {
+    let total_weight = weights
+        .iter()
+        .map(|(_, weight)| *weight as u128)
+        .sum::<u128>();
+    // The total weight does not exceed the total number of bid cycles, which is `u64`.
+    let total_weight = u64::try_from(total_weight).unwrap_or(u64::MAX);
+    weights
+        .iter()
+        .map(|(bidder, weight)| {
+            let amount = (total * weight / total_weight)
+                .and_then(|amount| amount.to_tokens128())
+                .unwrap_or(Tokens128::ZERO);
+            (*bidder, amount)
+        })
+        .collect()
+}
+
+/// Distributes the `total` proportionally, capping the reward of every bidder at
+/// `max_share * total` and redistributing the excess between the bidders below the cap.
+fn capped(
+    total: Tokens128,
+    bids: &[(Principal, u64)],
+    max_share: f64,
+) -> Vec<(Principal, Tokens128)> {
+    let cap = Tokens128::from((f64::from(total) * max_share.clamp(0.0, 1.0)) as u128);
+    let mut result = vec![];
+    let mut remaining = total;
+    let mut uncapped = bids.to_vec();
+
+    loop {
+        let shares = proportional(remaining, &uncapped);
+        let (over_cap, under_cap): (Vec<_>, Vec<_>) = uncapped
+            .iter()
+            .zip(shares)
+            .partition(|(_, (_, amount))| *amount > cap);
+
+        if over_cap.is_empty() {
+            result.extend(under_cap.into_iter().map(|(_, share)| share));
+            return result;
+        }
+
+        for ((bidder, _), _) in &over_cap {
+            result.push((*bidder, cap));
+            remaining = remaining.saturating_sub(cap);
+        }
+        uncapped = under_cap.into_iter().map(|(bid, _)| *bid).collect();
+    }
+}
+
+fn integer_sqrt(value: u64) -> u64 {
+    let value = value as u128;
+    let mut root = (value as f64).sqrt() as u128;
+    // Fix possible floating point rounding errors.
+    while root * root > value {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= value {
+        root += 1;
+    }
+    root as u64
+}
+
 pub fn accumulated_fees() -> Tokens128 {
     let account = AccountInternal::new(Principal::management_canister(), None);
     StableBalances.balance_of(&account)
 }
 
+/// Moves `amount` of the tokens left on the auction account, or all of them, to the `fee_to`
+/// account. Only allowed while the auction in progress has no bids, as the accumulated fees are
+/// distributed to the bidders otherwise.
+pub fn withdraw_residual(
+    auction_state: &AuctionState,
+    caller: Principal,
+    amount: Option<Tokens128>,
+) -> Result<ResidualWithdrawal, TxError> {
+    let cycles = auction_state.bidding_state.cycles_since_auction;
+    if cycles > 0 {
+        return Err(TxError::AuctionBidsPending { cycles });
+    }
+
+    let amount = amount.unwrap_or_else(accumulated_fees);
+    let fee_to = AccountInternal::from(TokenConfig::get_stable().fee_to);
+    transfer_internal(
+        &mut StableBalances,
+        auction_account(),
+        fee_to,
+        amount,
+        Tokens128::ZERO,
+        fee_to,
+        FeeRatio::default(),
+        None,
+    )?;
+
+    let transaction_id = LedgerData::append(vec![TxRecord::auction_residual(
+        0,
+        caller,
+        auction_account(),
+        fee_to,
+        amount,
+    )])[0];
+    let withdrawal = ResidualWithdrawal {
+        amount,
+        to: fee_to.into(),
+        withdrawn_by: caller,
+        transaction_id,
+        timestamp: ic::time(),
+        auctions_held: AuctionHistory::totals().auctions,
+    };
+    AuctionHistory::record_residual_withdrawal(withdrawal.clone());
+
+    Ok(withdrawal)
+}
+
+/// Transfers the vested part of the auction rewards of the `beneficiary` to their default account.
+pub fn claim_vested_rewards(beneficiary: Principal) -> TxReceipt {
+    let now = ic::time();
+    let amount = Vesting::balance(beneficiary, now).claimable;
+    if amount.is_zero() {
+        return Err(TxError::NothingToClaim);
+    }
+
+    let to = AccountInternal::new(beneficiary, None);
+    let owner = AccountInternal::from(TokenConfig::get_stable().owner);
+    transfer_internal(
+        &mut StableBalances,
+        vesting_account(),
+        to,
+        amount,
+        Tokens128::ZERO,
+        owner,
+        FeeRatio::default(),
+        None,
+    )?;
+    Vesting::claim(beneficiary, now);
+
+    let id = LedgerData::claim(vesting_account(), to, amount);
+    Ok(id.into())
+}
+
 #[cfg(test)]
 mod tests {
     use canister_sdk::{
         ic_auction::{api::Auction, state::MIN_BIDDING_AMOUNT},
-        ic_canister::Canister,
+        ic_canister::{Canister, MethodType, PreUpdate},
         ic_kit::{
-            mock_principals::{alice, bob},
+            mock_principals::{alice, bob, john},
             MockContext,
         },
         ic_metrics::Interval,
@@ -95,6 +487,7 @@ mod tests {
 
     use crate::mock::*;
     use crate::state::config::Metadata;
+    use crate::state::cycle_accounting::SAMPLE_INTERVAL;
 
     use super::*;
 
@@ -120,6 +513,7 @@ mod tests {
                 fee: Tokens128::from(0),
                 fee_to: alice(),
                 is_test_token: None,
+                init_spec: None,
             },
             Tokens128::from(1000),
         );
@@ -200,6 +594,239 @@ mod tests {
         assert_eq!(retrieved_result, result);
     }
 
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn residual_is_withdrawn_between_auctions() {
+        let (context, canister) = test_context();
+        AuctionHistory::clear();
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(alice()).unwrap();
+        assert_eq!(
+            canister.withdraw_auction_residual(None),
+            Err(TxError::AuctionBidsPending { cycles: 2_000_000 })
+        );
+
+        context.add_time(10u64.pow(9) * 60 * 60 * 300);
+        canister.run_auction().unwrap();
+        StableBalances.insert(auction_account(), Tokens128::from(7));
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.withdraw_auction_residual(None),
+            Err(TxError::Unauthorized)
+        );
+
+        context.update_caller(alice());
+        let withdrawal = canister.withdraw_auction_residual(None).unwrap();
+        assert_eq!(withdrawal.amount, Tokens128::from(7));
+        assert_eq!(withdrawal.auctions_held, 1);
+        assert_eq!(accumulated_fees(), Tokens128::ZERO);
+        assert_eq!(
+            canister.list_auction_residual_withdrawals(0, 10),
+            vec![withdrawal]
+        );
+        assert_eq!(
+            canister.withdraw_auction_residual(None),
+            Err(TxError::AmountTooSmall)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn blacklisted_bidders_are_not_rewarded() {
+        let (context, canister) = test_context();
+        AuctionPolicy::clear();
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(alice()).unwrap();
+
+        context.update_msg_cycles(4_000_000);
+        canister.bid_cycles(bob()).unwrap();
+        canister.set_bidder_blacklisted(bob(), true).unwrap();
+
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+        context.add_time(10u64.pow(9) * 60 * 60 * 300);
+
+        let result = canister.run_auction().unwrap();
+        assert_eq!(result.cycles_collected, 6_000_000);
+        assert_eq!(result.tokens_distributed, Tokens128::from(6_000));
+        assert_eq!(StableBalances.balance_of(&bob().into()), Tokens128::ZERO);
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn delegated_bids_are_rewarded_to_delegator() {
+        let (context, canister) = test_context();
+        AuctionPolicy::clear();
+        BidDelegates::clear();
+        canister.set_bid_delegate(Some(bob())).unwrap();
+        context.update_caller(bob());
+        canister.accept_bid_delegation(alice()).unwrap();
+
+        context.update_msg_cycles(4_000_000);
+        canister.bid_cycles(bob()).unwrap();
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(john()).unwrap();
+        assert_eq!(canister.my_bid_status().cycles, 0);
+
+        context.update_caller(alice());
+        assert_eq!(canister.my_bid_status().cycles, 4_000_000);
+        assert_eq!(
+            canister.auction_projection(0).projected_reward,
+            canister.my_bid_status().projected_reward
+        );
+
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+        context.add_time(10u64.pow(9) * 60 * 60 * 300);
+
+        canister.run_auction().unwrap();
+        assert_eq!(StableBalances.balance_of(&bob().into()), Tokens128::ZERO);
+        assert_eq!(
+            StableBalances.balance_of(&john().into()),
+            Tokens128::from(2_000)
+        );
+        assert_eq!(
+            StableBalances.balance_of(&alice().into()),
+            Tokens128::from(5_000)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn vested_rewards_are_claimed_over_time() {
+        let (context, canister) = test_context();
+        Vesting::clear();
+        assert_eq!(
+            canister.set_auction_vesting(Some(0)),
+            Err(TxError::InvalidVestingPeriod { max_days: 365 })
+        );
+        canister.set_auction_vesting(Some(10)).unwrap();
+
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(bob()).unwrap();
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+        context.add_time(10u64.pow(9) * 60 * 60 * 300);
+        canister.run_auction().unwrap();
+        assert_eq!(StableBalances.balance_of(&bob().into()), Tokens128::ZERO);
+        assert_eq!(
+            StableBalances.balance_of(&vesting_account()),
+            Tokens128::from(6000)
+        );
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.claim_vested_rewards(),
+            Err(TxError::NothingToClaim)
+        );
+
+        context.add_time(10u64.pow(9) * 60 * 60 * 24 * 5);
+        let balance = canister.get_vested_rewards(bob());
+        assert_eq!(balance.claimable, Tokens128::from(3000));
+        assert_eq!(balance.locked, Tokens128::from(3000));
+
+        canister.claim_vested_rewards().unwrap();
+        assert_eq!(
+            StableBalances.balance_of(&bob().into()),
+            Tokens128::from(3000)
+        );
+        assert_eq!(
+            canister.get_vested_rewards(bob()).claimed,
+            Tokens128::from(3000)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn projected_rewards() {
+        let (context, canister) = test_context();
+        AuctionPolicy::clear();
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(alice()).unwrap();
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+
+        let projection = canister.auction_projection(4_000_000);
+        assert_eq!(projection.bidder_cycles, 6_000_000);
+        assert_eq!(projection.total_cycles, 6_000_000);
+        assert_eq!(projection.projected_reward, Tokens128::from(6000));
+
+        context.update_caller(bob());
+        let projection = canister.auction_projection(4_000_000);
+        assert_eq!(projection.bidder_cycles, 4_000_000);
+        assert_eq!(projection.total_cycles, 6_000_000);
+        assert_eq!(projection.projected_reward, Tokens128::from(4000));
+        assert!((projection.share - 4.0 / 6.0).abs() < 1e-9);
+
+        let status = canister.my_bid_status();
+        assert_eq!(status.cycles, 0);
+        assert_eq!(status.projected_reward, Tokens128::ZERO);
+
+        context.update_caller(alice());
+        let status = canister.my_bid_status();
+        assert_eq!(status.cycles, 2_000_000);
+        assert_eq!(status.weight, 2_000_000);
+        assert_eq!(status.projected_reward, Tokens128::from(6000));
+        let state = canister.auction_state();
+        let state = state.borrow();
+        assert_eq!(
+            status.next_auction_at,
+            state.bidding_state.last_auction + state.bidding_state.auction_period
+        );
+    }
+
+    fn bids() -> Vec<(Principal, u64)> {
+        vec![(alice(), 1_000_000), (bob(), 9_000_000)]
+    }
+
+    fn reward_of(rewards: &[(Principal, Tokens128)], bidder: Principal) -> Tokens128 {
+        rewards
+            .iter()
+            .find(|(p, _)| *p == bidder)
+            .map(|(_, amount)| *amount)
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn linear_distribution() {
+        let rewards = distribute(AuctionStrategy::Linear, 10_000.into(), &bids());
+        assert_eq!(reward_of(&rewards, alice()), Tokens128::from(1_000));
+        assert_eq!(reward_of(&rewards, bob()), Tokens128::from(9_000));
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn quadratic_distribution() {
+        // Weights are 1000 and 3000.
+        let rewards = distribute(AuctionStrategy::Quadratic, 10_000.into(), &bids());
+        assert_eq!(reward_of(&rewards, alice()), Tokens128::from(2_500));
+        assert_eq!(reward_of(&rewards, bob()), Tokens128::from(7_500));
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn capped_distribution() {
+        let strategy = AuctionStrategy::Capped { max_share: 0.6 };
+        let rewards = distribute(strategy, 10_000.into(), &bids());
+        assert_eq!(reward_of(&rewards, alice()), Tokens128::from(4_000));
+        assert_eq!(reward_of(&rewards, bob()), Tokens128::from(6_000));
+
+        // If everybody reaches the cap, the rest is left for the next auction.
+        let strategy = AuctionStrategy::Capped { max_share: 0.3 };
+        let rewards = distribute(strategy, 10_000.into(), &bids());
+        assert_eq!(reward_of(&rewards, alice()), Tokens128::from(3_000));
+        assert_eq!(reward_of(&rewards, bob()), Tokens128::from(3_000));
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn integer_sqrt_is_exact() {
+        assert_eq!(integer_sqrt(0), 0);
+        assert_eq!(integer_sqrt(15), 3);
+        assert_eq!(integer_sqrt(16), 4);
+        assert_eq!(integer_sqrt(u64::MAX), u32::MAX as u64);
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, no_coverage)]
     fn auction_without_bids() {
@@ -207,6 +834,42 @@ mod tests {
         assert_eq!(canister.run_auction(), Err(AuctionError::NoBids));
     }
 
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn auction_waits_for_decimals_migration() {
+        let (context, canister) = test_context();
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(bob()).unwrap();
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+        // More balances than rescaled by one batch, so the migration keeps running.
+        for i in 0..1000u32 {
+            let mut subaccount = [0; 32];
+            subaccount[..4].copy_from_slice(&i.to_be_bytes());
+            StableBalances.insert(
+                AccountInternal::new(john(), Some(subaccount)),
+                Tokens128::from(100),
+            );
+        }
+        context.add_time(10u64.pow(9) * 60 * 60 * 300);
+
+        assert!(DecimalsMigration::run(alice(), 6).unwrap().is_some());
+        let state = canister.auction_state();
+        assert_eq!(
+            check_disbursement(&state.borrow(), u64::MAX),
+            Err(TxError::DecimalsMigrationInProgress)
+        );
+        // The auction is not held by the update calls, so they don't trap.
+        canister.pre_update("transfer", MethodType::Update);
+        assert_eq!(state.borrow().bidding_state.cycles_since_auction, 2_000_000);
+
+        assert_eq!(DecimalsMigration::run(alice(), 6), Ok(None));
+        canister.pre_update("transfer", MethodType::Update);
+        assert_eq!(
+            StableBalances.balance_of(&bob().into()),
+            Tokens128::from(60)
+        );
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, no_coverage)]
     fn auction_not_in_time() {
@@ -233,6 +896,50 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn cycle_consumption_sets_fee_ratio() {
+        let (_, canister) = test_context();
+        CycleAccounting::clear();
+        canister.set_min_cycles(1_000_000).unwrap();
+        let state = canister.auction_state();
+        let initial_ratio = canister.bidding_info().fee_ratio;
+
+        // Not measured yet.
+        update_cycle_accounting(&mut state.borrow_mut(), 0, 1_867_600);
+        assert_eq!(canister.bidding_info().fee_ratio, initial_ratio);
+
+        // One cycle per second, so 864_000 cycles above the minimum last 10 auction periods.
+        update_cycle_accounting(&mut state.borrow_mut(), SAMPLE_INTERVAL, 1_864_000);
+        assert_eq!(canister.bidding_info().fee_ratio, 0.1);
+        assert_eq!(check_cycles_needed(&state.borrow(), 1_864_000), Ok(()));
+        assert_eq!(
+            cycle_consumption(&state.borrow(), 1_864_000).runway_periods,
+            Some(10.0)
+        );
+
+        // Idle after a top-up.
+        update_cycle_accounting(&mut state.borrow_mut(), 2 * SAMPLE_INTERVAL, 1_000_000_000);
+        assert_eq!(canister.bidding_info().fee_ratio, 0.0);
+        assert_eq!(
+            check_cycles_needed(&state.borrow(), 1_000_000_000),
+            Err(TxError::BidsNotNeeded)
+        );
+
+        // Below the minimum all the fees go to the auction, but they are not disbursed.
+        update_cycle_accounting(&mut state.borrow_mut(), 2 * SAMPLE_INTERVAL + 1, 500_000);
+        assert_eq!(canister.bidding_info().fee_ratio, 1.0);
+        assert!(cycle_consumption(&state.borrow(), 500_000).accepts_bids);
+        assert_eq!(
+            check_disbursement(&state.borrow(), 500_000),
+            Err(TxError::CyclesBelowMinimum {
+                balance: 500_000,
+                min_cycles: 1_000_000,
+            })
+        );
+        assert_eq!(check_disbursement(&state.borrow(), 1_000_000), Ok(()));
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, no_coverage)]
     fn setting_min_cycles() {
@@ -1,18 +1,33 @@
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use canister_sdk::ic_kit::ic;
-#[cfg(feature = "claim")]
-use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
 use ic_exports::Principal;
 
-use super::auction_account;
-use super::icrc1_transfer::{PERMITTED_DRIFT, TX_WINDOW};
+use super::{auction_account, is_service_account};
 use crate::account::{AccountInternal, CheckedAccount, Subaccount, WithRecipient};
 use crate::error::TxError;
 use crate::principal::{CheckedPrincipal, Owner, TestNet};
-use crate::state::balances::{Balances, LocalBalances, StableBalances};
-use crate::state::config::{FeeRatio, TokenConfig};
-use crate::state::ledger::{BatchTransferArgs, LedgerData, TransferArgs, TxReceipt};
-use crate::tx_record::TxId;
+#[cfg(feature = "claim")]
+use crate::state::account_ids::AccountIds;
+use crate::state::balances::{Balances, BalancesDelta, StableBalances};
+use crate::state::budgets::Budgets;
+use crate::state::config::{BurnPolicy, FeeRatio, TokenConfig};
+use crate::state::decimals::DecimalsMigration;
+use crate::state::dedup::DedupIndex;
+use crate::state::faucet::Faucet;
+use crate::state::fee_recipients::FeeRecipients;
+use crate::state::frozen::FrozenAccounts;
+#[cfg(feature = "claim")]
+use crate::state::guard::StateGuard;
+use crate::state::guardians::{Guardians, RecoveryAction, RecoveryLog};
+use crate::state::large_transfers::{LargeTransfers, PendingTransferId};
+use crate::state::ledger::{
+    BatchOperation, BatchTransferArgs, LedgerData, Memo, TransferArgs, TxReceipt,
+};
+use crate::state::merchants::MerchantAuthorizations;
+use crate::state::migrations::StateMigrations;
+use crate::state::payment_requests::{PaymentRequestId, PaymentRequests};
+use crate::state::payment_subscriptions::{PaymentSubscriptions, SubscriptionId};
+use crate::tx_record::{TxId, TxRecord};
 
 pub fn is20_transfer(
     caller: CheckedAccount<WithRecipient>,
@@ -23,9 +38,10 @@ pub fn is20_transfer(
     let to = caller.recipient();
     let created_at_time = validate_and_get_tx_ts(from.owner, transfer)?;
     let TransferArgs { amount, memo, .. } = transfer;
+    LargeTransfers::check_amount(*amount)?;
 
     let stats = TokenConfig::get_stable();
-    let (fee, fee_to) = stats.fee_info();
+    let (fee, fee_to) = stats.fee_info(from, to, *amount);
 
     if let Some(requested_fee) = transfer.fee {
         if fee != requested_fee {
@@ -33,7 +49,7 @@ pub fn is20_transfer(
         }
     }
 
-    transfer_internal(
+    let burned = transfer_internal(
         &mut StableBalances,
         from,
         to,
@@ -41,12 +57,25 @@ pub fn is20_transfer(
         fee,
         fee_to.into(),
         FeeRatio::new(auction_fee_ratio),
+        stats.burn_policy,
     )?;
 
-    let id = LedgerData::transfer(from, to, *amount, fee, *memo, created_at_time);
+    let id = LedgerData::transfer(from, to, *amount, fee, memo.clone(), created_at_time);
+    LedgerData::record_transfer_burn(from, burned);
+    if transfer.created_at_time.is_some() {
+        DedupIndex::insert(from, transfer, created_at_time, id);
+    }
+
     Ok(id.into())
 }
 
+/// Moves the `amount` from the `from` account to the `to` account, charging the `fee` on top of
+/// it. The owner part of the fee is credited to `fee_to`, or split between the `FeeRecipients` if
+/// they are set. If the `burn_policy` is given, its share of the amount is burned instead of being
+/// credited to the recipient, unless one of the accounts is a service account. Returns the burned
+/// amount, which must be recorded with `LedgerData::record_transfer_burn` after the transfer
+/// record.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn transfer_internal(
     balances: &mut impl Balances,
     from: AccountInternal,
@@ -55,19 +84,26 @@ pub(crate) fn transfer_internal(
     fee: Tokens128,
     fee_to: AccountInternal,
     auction_fee_ratio: FeeRatio,
-) -> Result<(), TxError> {
+    burn_policy: Option<BurnPolicy>,
+) -> Result<Tokens128, TxError> {
     if amount.is_zero() {
         return Err(TxError::AmountTooSmall);
     }
 
+    DecimalsMigration::check_not_running()?;
+    StateMigrations::check_completed()?;
+    FrozenAccounts::check_outgoing(from)?;
+    FrozenAccounts::check_incoming(to)?;
+
+    let (owner_fee, auction_fee) = auction_fee_ratio.get_value(fee);
+    let fee_shares = FeeRecipients::split(fee_to, owner_fee);
+
     // We use `updates` structure because sometimes from or to can be equal to fee_to or even to
     // auction_account, so we must take a carefull approach.
-    let mut updates = LocalBalances::from_iter([
-        (from, balances.balance_of(&from)),
-        (to, balances.balance_of(&to)),
-        (fee_to, balances.balance_of(&fee_to)),
-        (auction_account(), balances.balance_of(&auction_account())),
-    ]);
+    let accounts = [from, to, auction_account()]
+        .into_iter()
+        .chain(fee_shares.iter().map(|(account, _)| *account));
+    let mut updates = BalancesDelta::load(balances, accounts);
 
     // If `amount + fee` overflows max `Tokens128` value, the balance cannot be larger than this
     // value, so we can safely return `InsufficientFunds` error.
@@ -81,14 +117,21 @@ pub(crate) fn transfer_internal(
         })?;
     updates.insert(from, updated_from_balance);
 
-    let updated_to_balance = (updates.balance_of(&to) + amount).ok_or(TxError::AmountOverflow)?;
+    let burned = match burn_policy {
+        Some(policy) if !is_service_account(from.owner) && !is_service_account(to.owner) => {
+            policy.burned_amount(amount)
+        }
+        _ => Tokens128::ZERO,
+    };
+    let received = amount.saturating_sub(burned);
+    let updated_to_balance = (updates.balance_of(&to) + received).ok_or(TxError::AmountOverflow)?;
     updates.insert(to, updated_to_balance);
 
-    let (owner_fee, auction_fee) = auction_fee_ratio.get_value(fee);
-
-    let updated_fee_to_balance =
-        (updates.balance_of(&fee_to) + owner_fee).ok_or(TxError::AmountOverflow)?;
-    updates.insert(fee_to, updated_fee_to_balance);
+    for (recipient, share) in &fee_shares {
+        let updated_recipient_balance =
+            (updates.balance_of(recipient) + *share).ok_or(TxError::AmountOverflow)?;
+        updates.insert(*recipient, updated_recipient_balance);
+    }
 
     let updated_auction_balance =
         (updates.balance_of(&auction_account()) + auction_fee).ok_or(TxError::AmountOverflow)?;
@@ -96,45 +139,40 @@ pub(crate) fn transfer_internal(
 
     // At this point all the checks are done and no further errors are possible, so we modify the
     // canister state only at this point.
-    balances.apply_updates(updates.list_balances(0, usize::MAX));
+    balances.apply_delta(updates);
+    FeeRecipients::record(&fee_shares);
 
-    Ok(())
+    Ok(burned)
+}
+
+pub(crate) fn validate_memo(transfer_args: &TransferArgs) -> Result<(), TxError> {
+    TokenConfig::get_stable()
+        .input_limits()
+        .check_memo(transfer_args.memo.as_ref())
 }
 
 fn validate_and_get_tx_ts(caller: Principal, transfer_args: &TransferArgs) -> Result<u64, TxError> {
+    validate_memo(transfer_args)?;
+
     let now = ic::time();
     let from = AccountInternal::new(caller, transfer_args.from_subaccount);
-    let to = transfer_args.to.into();
 
     let created_at_time = match transfer_args.created_at_time {
         Some(created_at_time) => {
-            if now.saturating_sub(created_at_time) > TX_WINDOW {
+            let window = TokenConfig::get_stable().tx_window();
+            if now.saturating_sub(created_at_time) > window.tx_window_nanos {
                 return Err(TxError::TooOld {
-                    allowed_window_nanos: TX_WINDOW,
+                    allowed_window_nanos: window.tx_window_nanos,
                 });
             }
 
-            if created_at_time.saturating_sub(now) > PERMITTED_DRIFT {
+            if created_at_time.saturating_sub(now) > window.permitted_drift_nanos {
                 return Err(TxError::CreatedInFuture { ledger_time: now });
             }
 
-            let txs = LedgerData::list_transactions();
-            for tx in txs.iter().rev() {
-                if now.saturating_sub(tx.timestamp) > TX_WINDOW + PERMITTED_DRIFT {
-                    break;
-                }
-
-                if tx.timestamp == created_at_time
-                    && AccountInternal::from(tx.from) == from
-                    && AccountInternal::from(tx.to) == to
-                    && tx.memo == transfer_args.memo
-                    && tx.amount == transfer_args.amount
-                    && tx.fee == transfer_args.fee.unwrap_or(tx.fee)
-                {
-                    return Err(TxError::Duplicate {
-                        duplicate_of: tx.index,
-                    });
-                }
+            DedupIndex::prune(now.saturating_sub(window.dedup_window_nanos()));
+            if let Some(duplicate_of) = DedupIndex::find(from, transfer_args, created_at_time) {
+                return Err(TxError::Duplicate { duplicate_of });
             }
 
             created_at_time
@@ -154,6 +192,10 @@ pub fn mint(caller: Principal, to: AccountInternal, amount: Tokens128) -> TxRece
         return Err(TxError::AmountOverflow);
     }
 
+    DecimalsMigration::check_not_running()?;
+    StateMigrations::check_completed()?;
+    FrozenAccounts::check_incoming(to)?;
+
     let balance = StableBalances.balance_of(&to);
     let new_balance = (balance + amount).ok_or(TxError::AmountOverflow)?;
     StableBalances.insert(to, new_balance);
@@ -169,11 +211,15 @@ pub fn mint_test_token(
     to_subaccount: Option<Subaccount>,
     amount: Tokens128,
 ) -> TxReceipt {
-    mint(
+    let now = ic::time();
+    Faucet::check(caller.inner(), amount, now)?;
+    let id = mint(
         caller.inner(),
         AccountInternal::new(to, to_subaccount),
         amount,
-    )
+    )?;
+    Faucet::record(caller.inner(), amount, now);
+    Ok(id)
 }
 
 pub fn mint_as_owner(
@@ -190,6 +236,10 @@ pub fn mint_as_owner(
 }
 
 pub fn burn(caller: Principal, from: AccountInternal, amount: Tokens128) -> TxReceipt {
+    DecimalsMigration::check_not_running()?;
+    StateMigrations::check_completed()?;
+    FrozenAccounts::check_outgoing(from)?;
+
     let balance = StableBalances.balance_of(&from);
 
     if !amount.is_zero() && balance.is_zero() {
@@ -205,6 +255,7 @@ pub fn burn(caller: Principal, from: AccountInternal, amount: Tokens128) -> TxRe
     }
 
     let id = LedgerData::burn(caller.into(), from, amount);
+    LedgerData::add_burned(amount);
     Ok(id.into())
 }
 
@@ -235,12 +286,7 @@ pub fn get_claim_subaccount(
     claimer: Principal,
     claimer_subaccount: Option<Subaccount>,
 ) -> Subaccount {
-    let account_id = AccountIdentifier::new(
-        claimer.into(),
-        Some(SubaccountIdentifier(claimer_subaccount.unwrap_or_default())),
-    );
-
-    account_id.to_address()
+    AccountIds::of(AccountInternal::new(claimer, claimer_subaccount))
 }
 
 #[cfg(feature = "claim")]
@@ -248,6 +294,7 @@ pub fn claim(holder: Principal, subaccount: Option<Subaccount>) -> TxReceipt {
     let caller = canister_sdk::ic_kit::ic::caller();
     let claim_subaccount = get_claim_subaccount(caller, subaccount);
     let claim_account = AccountInternal::new(holder, Some(claim_subaccount));
+    let _guard = StateGuard::account(claim_account)?;
     let amount = StableBalances.balance_of(&claim_account);
     if amount.is_zero() {
         return Err(TxError::NothingToClaim);
@@ -262,11 +309,66 @@ pub fn claim(holder: Principal, subaccount: Option<Subaccount>) -> TxReceipt {
         0.into(),
         stats.owner.into(),
         FeeRatio::default(),
+        None,
     )?;
     let id = LedgerData::claim(claim_account, AccountInternal::new(caller, None), amount);
+    // The claim subaccount is the account identifier of the claimer, so it's resolvable now.
+    AccountIds::record(AccountInternal::new(caller, subaccount));
     Ok(id.into())
 }
 
+/// Completes the recovery of the `owner` accounts initiated by the `caller`, moving the balances of
+/// all subaccounts of the owner to the same subaccounts of the new owner. No fee is charged.
+pub fn complete_recovery(caller: Principal, owner: Principal) -> Result<Vec<TxId>, TxError> {
+    let recovery = Guardians::check_completion(caller, owner, ic::time())?;
+
+    let mut balances = StableBalances
+        .get_subaccounts(owner)
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(subaccount, amount)| {
+            let from = AccountInternal::new(owner, Some(subaccount));
+            let to = AccountInternal::new(recovery.new_owner, Some(subaccount));
+            (from, to, amount)
+        })
+        .collect::<Vec<_>>();
+    balances.sort_by_key(|(from, _, _)| from.subaccount);
+
+    // All accounts are checked before any balance is moved, so the recovery is not left half done.
+    for (from, to, _) in &balances {
+        FrozenAccounts::check_outgoing(*from)?;
+        FrozenAccounts::check_incoming(*to)?;
+    }
+
+    let stats = TokenConfig::get_stable();
+    let mut records = Vec::with_capacity(balances.len());
+    for (from, to, amount) in balances {
+        transfer_internal(
+            &mut StableBalances,
+            from,
+            to,
+            amount,
+            0.into(),
+            stats.owner.into(),
+            FeeRatio::default(),
+            None,
+        )?;
+        records.push(TxRecord::recovery(0, caller, from, to, amount));
+    }
+
+    let ids = LedgerData::append(records);
+    Guardians::finish(owner);
+    RecoveryLog::record(
+        caller,
+        owner,
+        RecoveryAction::CompleteRecovery {
+            new_owner: recovery.new_owner,
+        },
+    );
+
+    Ok(ids)
+}
+
 pub fn batch_transfer(
     from_subaccount: Option<Subaccount>,
     transfers: Vec<BatchTransferArgs>,
@@ -274,65 +376,441 @@ pub fn batch_transfer(
 ) -> Result<Vec<TxId>, TxError> {
     let caller = canister_sdk::ic_kit::ic::caller();
     let from = AccountInternal::new(caller, from_subaccount);
-
     let stats = TokenConfig::get_stable();
-    let (fee, fee_to) = stats.fee_info();
+    stats.input_limits().check_batch(transfers.len())?;
 
-    batch_transfer_internal(
+    LargeTransfers::check_amount(saturating_sum(
+        transfers.iter().map(|transfer| transfer.amount),
+    ))?;
+
+    let fees = transfer_fees(&stats, from, &transfers);
+    let burned = batch_transfer_internal(
         from,
         &transfers,
         &mut StableBalances,
-        fee,
-        fee_to,
+        &fees,
+        stats.fee_to,
         auction_fee_ratio,
+        stats.burn_policy,
     )?;
-    let id = LedgerData::batch_transfer(from, transfers, fee);
+    let id = LedgerData::batch_transfer(from, transfers, fees);
+    LedgerData::record_transfer_burn(from, burned);
     Ok(id)
 }
 
+/// Collects `amount` of the payment subscription `id` to the default account of the caller, who
+/// must be the spender of the subscription.
+pub fn collect_subscription(
+    id: SubscriptionId,
+    amount: Tokens128,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let spender = ic::caller();
+    let now = ic::time();
+    let subscription = PaymentSubscriptions::check_collection(spender, id, amount, now)?;
+
+    let from = subscription.subscriber.into();
+    let to = AccountInternal::new(spender, None);
+    if from == to {
+        return Err(TxError::SelfTransfer);
+    }
+
+    let config = TokenConfig::get_stable();
+    let (fee, fee_to) = config.fee_info(from, to, amount);
+    let burned = transfer_internal(
+        &mut StableBalances,
+        from,
+        to,
+        amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        config.burn_policy,
+    )?;
+
+    let tx_id = LedgerData::transfer_from(spender, from, to, amount, fee, None);
+    LedgerData::record_transfer_burn(from, burned);
+    PaymentSubscriptions::record_collection(id, amount, now);
+
+    Ok(tx_id.into())
+}
+
+/// Transfers `amount` from the `payer` account to the caller, who must be a merchant authorized by
+/// the payer, see `state::merchants`. The transfer fee is paid by the payer on top of the amount.
+pub fn pull_payment(
+    payer: AccountInternal,
+    amount: Tokens128,
+    memo: Option<Memo>,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let merchant = ic::caller();
+    let now = ic::time();
+    TokenConfig::get_stable()
+        .input_limits()
+        .check_memo(memo.as_ref())?;
+
+    MerchantAuthorizations::check_payment(payer, merchant, amount, now)?;
+    let to = AccountInternal::new(merchant, None);
+    LargeTransfers::check_amount(amount)?;
+
+    let config = TokenConfig::get_stable();
+    let (fee, fee_to) = config.fee_info(payer, to, amount);
+    let burned = transfer_internal(
+        &mut StableBalances,
+        payer,
+        to,
+        amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        config.burn_policy,
+    )?;
+
+    let tx_id = LedgerData::transfer_from(merchant, payer, to, amount, fee, memo);
+    LedgerData::record_transfer_burn(payer, burned);
+    MerchantAuthorizations::record_payment(payer, merchant, amount, now);
+
+    Ok(tx_id.into())
+}
+
+/// Transfers `amount` from the `budget` subaccount of the `organization` to the `to` account on
+/// behalf of the caller, who must be a delegate of the budget. The transfer fee is paid by the
+/// budget on top of the amount.
+pub fn budget_transfer(
+    organization: Principal,
+    budget: Subaccount,
+    to: AccountInternal,
+    amount: Tokens128,
+    memo: Option<Memo>,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let delegate = ic::caller();
+    let config = TokenConfig::get_stable();
+    config.input_limits().check_memo(memo.as_ref())?;
+
+    let from = Budgets::check_spending(organization, budget, delegate, amount)?.account();
+    if from == to {
+        return Err(TxError::SelfTransfer);
+    }
+
+    LargeTransfers::check_amount(amount)?;
+    let (fee, fee_to) = config.fee_info(from, to, amount);
+    let burned = transfer_internal(
+        &mut StableBalances,
+        from,
+        to,
+        amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        config.burn_policy,
+    )?;
+
+    let tx_id = LedgerData::transfer_from(delegate, from, to, amount, fee, memo);
+    LedgerData::record_transfer_burn(from, burned);
+    Budgets::record_spending(organization, budget, amount);
+
+    Ok(tx_id.into())
+}
+
+/// Pays the payment request `id` from the `payer` account and notifies the merchant. The transfer
+/// fee is paid by the payer on top of the requested amount.
+pub fn pay_request(
+    payer: AccountInternal,
+    id: PaymentRequestId,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let now = ic::time();
+    let request = PaymentRequests::check_payment(id, now)?;
+    let to = request.merchant.into();
+    if payer == to {
+        return Err(TxError::SelfTransfer);
+    }
+
+    LargeTransfers::check_amount(request.amount)?;
+    let config = TokenConfig::get_stable();
+    let (fee, fee_to) = config.fee_info(payer, to, request.amount);
+    let burned = transfer_internal(
+        &mut StableBalances,
+        payer,
+        to,
+        request.amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        config.burn_policy,
+    )?;
+
+    let tx_id = LedgerData::transfer(payer, to, request.amount, fee, request.memo, now);
+    LedgerData::record_transfer_burn(payer, burned);
+    if let Some(request) = PaymentRequests::record_payment(id, payer, tx_id, now) {
+        PaymentRequests::notify_merchant(&request, now);
+    }
+
+    Ok(tx_id.into())
+}
+
+/// Executes the pending large transfer `id` confirmed by the `caller`. The current transfer fee is
+/// charged.
+pub fn confirm_large_transfer(
+    caller: Principal,
+    id: PendingTransferId,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let now = ic::time();
+    let pending = LargeTransfers::check_confirmation(caller, id, now)?;
+    let from = pending.from.into();
+    let to = pending.to.into();
+
+    let config = TokenConfig::get_stable();
+    let (fee, fee_to) = config.fee_info(from, to, pending.amount);
+    let burned = transfer_internal(
+        &mut StableBalances,
+        from,
+        to,
+        pending.amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        config.burn_policy,
+    )?;
+
+    let tx_id = LedgerData::transfer(from, to, pending.amount, fee, pending.memo, now);
+    LedgerData::record_transfer_burn(from, burned);
+    LargeTransfers::remove(id);
+
+    Ok(tx_id.into())
+}
+
+/// Applies the `operations` all-or-nothing. All the operations are first applied to a working copy
+/// of the balances, and only if all of them succeed the balances are updated and the records are
+/// written to the ledger. Returns the ids of the records in the order of the operations.
+pub fn atomic_batch(
+    caller: Principal,
+    operations: Vec<BatchOperation>,
+    can_mint: bool,
+    auction_fee_ratio: f64,
+) -> Result<Vec<TxId>, TxError> {
+    DecimalsMigration::check_not_running()?;
+    StateMigrations::check_completed()?;
+    TokenConfig::get_stable()
+        .input_limits()
+        .check_batch(operations.len())?;
+
+    LargeTransfers::check_amount(saturating_sum(operations.iter().filter_map(|operation| {
+        match operation {
+            BatchOperation::Transfer { amount, .. } => Some(*amount),
+            _ => None,
+        }
+    })))?;
+
+    let minted = saturating_sum(operations.iter().filter_map(|operation| match operation {
+        BatchOperation::Mint { amount, .. } => Some(*amount),
+        _ => None,
+    }));
+    if can_mint && !minted.is_zero() {
+        Faucet::check(caller, minted, ic::time())?;
+    }
+
+    let config = TokenConfig::get_stable();
+    let mut updates = BalancesDelta::load(&StableBalances, [auction_account()]);
+    let mut total_supply = StableBalances.total_supply();
+    let mut transfer_burns = vec![];
+    let mut transfer_fees = vec![];
+
+    // The fees charged by the transfers of the batch are discarded together with the `updates`
+    // if any operation fails.
+    FeeRecipients::discard_on_error(|| {
+        for operation in &operations {
+            match *operation {
+                BatchOperation::Transfer {
+                    from_subaccount,
+                    to,
+                    amount,
+                } => {
+                    let from = AccountInternal::new(caller, from_subaccount);
+                    let to = to.into();
+                    if from == to {
+                        return Err(TxError::SelfTransfer);
+                    }
+
+                    // The fee is credited to the same recipient as by a single transfer.
+                    let (fee, fee_to) = config.fee_info(from, to, amount);
+                    let fee_to = AccountInternal::from(fee_to);
+                    let accounts = [from, to]
+                        .into_iter()
+                        .chain(FeeRecipients::accounts(fee_to));
+                    updates.extend_from(&StableBalances, accounts);
+                    let burned = transfer_internal(
+                        &mut updates,
+                        from,
+                        to,
+                        amount,
+                        fee,
+                        fee_to,
+                        FeeRatio::new(auction_fee_ratio),
+                        config.burn_policy,
+                    )?;
+                    transfer_burns.push((from, burned));
+                    transfer_fees.push(fee);
+                }
+                BatchOperation::Mint { to, amount } => {
+                    if !can_mint {
+                        return Err(TxError::Unauthorized);
+                    }
+
+                    let to = to.into();
+                    FrozenAccounts::check_incoming(to)?;
+                    total_supply = (total_supply + amount).ok_or(TxError::AmountOverflow)?;
+
+                    updates.extend_from(&StableBalances, [to]);
+                    let new_balance =
+                        (updates.balance_of(&to) + amount).ok_or(TxError::AmountOverflow)?;
+                    updates.insert(to, new_balance);
+                }
+                BatchOperation::Burn {
+                    from_subaccount,
+                    amount,
+                } => {
+                    let from = AccountInternal::new(caller, from_subaccount);
+                    FrozenAccounts::check_outgoing(from)?;
+
+                    updates.extend_from(&StableBalances, [from]);
+                    let balance = updates.balance_of(&from);
+                    let new_balance =
+                        (balance - amount).ok_or(TxError::InsufficientFunds { balance })?;
+                    if new_balance.is_zero() {
+                        updates.remove(&from);
+                    } else {
+                        updates.insert(from, new_balance);
+                    }
+                    total_supply = (total_supply - amount).unwrap_or_default();
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    StableBalances.apply_delta(updates);
+
+    // The records are appended in one go, as the batches used for airdrops may have thousands
+    // of operations. The ids are assigned by the ledger.
+    let now = ic::time();
+    let mut burned = Tokens128::ZERO;
+    let mut transfer_fees = transfer_fees.into_iter();
+    let records = operations
+        .into_iter()
+        .map(|operation| match operation {
+            BatchOperation::Transfer {
+                from_subaccount,
+                to,
+                amount,
+            } => {
+                let from = AccountInternal::new(caller, from_subaccount);
+                let fee = transfer_fees.next().unwrap_or_default();
+                TxRecord::transfer(0, from, to.into(), amount, fee, None, now)
+            }
+            BatchOperation::Mint { to, amount } => {
+                TxRecord::mint(0, caller.into(), to.into(), amount)
+            }
+            BatchOperation::Burn {
+                from_subaccount,
+                amount,
+            } => {
+                let from = AccountInternal::new(caller, from_subaccount);
+                burned = (burned + amount).unwrap_or(burned);
+                TxRecord::burn(0, caller.into(), from, amount)
+            }
+        })
+        .collect();
+    let ids = LedgerData::append(records);
+    if !burned.is_zero() {
+        LedgerData::add_burned(burned);
+    }
+    for (from, burned) in transfer_burns {
+        LedgerData::record_transfer_burn(from, burned);
+    }
+
+    if can_mint && !minted.is_zero() {
+        Faucet::record(caller, minted, ic::time());
+    }
+
+    Ok(ids)
+}
+
+/// Sum of the amounts transferred or minted by a batch, compared with the large transfer threshold
+/// and the faucet limits. The overflow is reported by the operations themselves.
+fn saturating_sum(amounts: impl Iterator<Item = Tokens128>) -> Tokens128 {
+    amounts.fold(Tokens128::ZERO, |total, amount| {
+        (total + amount).unwrap_or(Tokens128::from(u128::MAX))
+    })
+}
+
+/// Fees of the `transfers` from the `from` account, in the same order.
+pub(crate) fn transfer_fees(
+    config: &TokenConfig,
+    from: AccountInternal,
+    transfers: &[BatchTransferArgs],
+) -> Vec<Tokens128> {
+    transfers
+        .iter()
+        .map(|transfer| {
+            config
+                .fee_info(from, transfer.receiver.into(), transfer.amount)
+                .0
+        })
+        .collect()
+}
+
+/// Applies the `transfers` all-or-nothing, charging the `fees` of the transfers in the same order,
+/// see `transfer_fees`. Returns the total amount burned by the `burn_policy`.
 pub(crate) fn batch_transfer_internal(
     from: AccountInternal,
     transfers: &Vec<BatchTransferArgs>,
     balances: &mut impl Balances,
-    fee: Tokens128,
+    fees: &[Tokens128],
     fee_to: Principal,
     auction_fee_ratio: f64,
-) -> Result<(), TxError> {
+    burn_policy: Option<BurnPolicy>,
+) -> Result<Tokens128, TxError> {
     let fee_to = AccountInternal::new(fee_to, None);
     let auction_acc = auction_account();
 
-    let mut updates = LocalBalances::from_iter([
-        (from, balances.balance_of(&from)),
-        (fee_to, balances.balance_of(&fee_to)),
-        (auction_acc, balances.balance_of(&auction_acc)),
-    ]);
-
-    for transfer in transfers {
-        let receiver = transfer.receiver.into();
-        updates.insert(receiver, balances.balance_of(&receiver));
-    }
+    let accounts = [from, auction_acc]
+        .into_iter()
+        .chain(FeeRecipients::accounts(fee_to))
+        .chain(transfers.iter().map(|transfer| transfer.receiver.into()));
+    let mut updates = BalancesDelta::load(balances, accounts);
+
+    let mut burned = Tokens128::ZERO;
+    FeeRecipients::discard_on_error(|| {
+        for (transfer, fee) in transfers.iter().zip(fees) {
+            let receiver = transfer.receiver.into();
+            let transfer_burn = transfer_internal(
+                &mut updates,
+                from,
+                receiver,
+                transfer.amount,
+                *fee,
+                fee_to,
+                FeeRatio::new(auction_fee_ratio),
+                burn_policy,
+            )
+            .map_err(|err| match err {
+                TxError::InsufficientFunds { .. } => TxError::InsufficientFunds {
+                    balance: balances.balance_of(&from),
+                },
+                other => other,
+            })?;
+            burned = (burned + transfer_burn).unwrap_or(burned);
+        }
 
-    for transfer in transfers {
-        let receiver = transfer.receiver.into();
-        transfer_internal(
-            &mut updates,
-            from,
-            receiver,
-            transfer.amount,
-            fee,
-            fee_to,
-            FeeRatio::new(auction_fee_ratio),
-        )
-        .map_err(|err| match err {
-            TxError::InsufficientFunds { .. } => TxError::InsufficientFunds {
-                balance: balances.balance_of(&from),
-            },
-            other => other,
-        })?;
-    }
+        Ok(())
+    })?;
 
-    balances.apply_updates(updates.list_balances(0, usize::MAX));
-    Ok(())
+    balances.apply_delta(updates);
+    Ok(burned)
 }
 
 #[cfg(test)]
@@ -347,8 +825,15 @@ mod tests {
     use super::*;
     use crate::account::{Account, DEFAULT_SUBACCOUNT};
     use crate::canister::TokenCanisterAPI;
+    use crate::error::TransferError;
     use crate::mock::TokenCanisterMock;
-    use crate::state::config::Metadata;
+    use crate::state::balances::LocalBalances;
+    use crate::state::config::{InputLimits, Metadata};
+    use crate::state::faucet::FaucetPolicy;
+    use crate::state::fees::{FeePolicy, FeeSchedule, PercentageFee};
+    use crate::state::large_transfers::LargeTransferPolicy;
+    use crate::state::ledger::MAX_MEMO_LENGTH;
+    use crate::state::payment_requests::PaymentRequestStatus;
 
     fn test_canister() -> TokenCanisterMock {
         let context = MockContext::new().with_caller(alice()).inject();
@@ -371,6 +856,7 @@ mod tests {
                 fee: Tokens128::from(0),
                 fee_to: alice(),
                 is_test_token: None,
+                init_spec: None,
             },
             Tokens128::from(1000),
         );
@@ -419,6 +905,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn batch_transfer_over_limit() {
+        let canister = test_canister();
+        canister
+            .set_input_limits(InputLimits {
+                max_batch_entries: 1,
+                ..InputLimits::default()
+            })
+            .unwrap();
+
+        let transfer = BatchTransferArgs {
+            receiver: Account::new(bob(), None),
+            amount: Tokens128::from(100),
+        };
+        assert_eq!(
+            canister.batch_transfer(None, vec![transfer.clone(), transfer.clone()]),
+            Err(TxError::BatchTooLarge { max_entries: 1 })
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(1000)
+        );
+        assert!(canister.batch_transfer(None, vec![transfer]).is_ok());
+    }
+
     #[test]
     fn batch_transfer_with_fee() {
         let canister = test_canister();
@@ -462,6 +973,382 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fee_policy_is_charged_per_transfer() {
+        let canister = test_canister();
+
+        let mut stats = TokenConfig::get_stable();
+        stats.fee_to = john();
+        stats.fee_policy = Some(FeePolicy {
+            schedule: FeeSchedule::Percentage(PercentageFee {
+                rate_bps: 1_000,
+                min_fee: 5.into(),
+                max_fee: None,
+            }),
+            exempt: vec![xtc()],
+        });
+        TokenConfig::set_stable(stats);
+
+        let transfer = |to, amount: u128, fee: Option<u128>| TransferArgs {
+            from_subaccount: None,
+            to: Account::new(to, None),
+            amount: amount.into(),
+            fee: fee.map(Tokens128::from),
+            memo: None,
+            created_at_time: None,
+        };
+        assert_eq!(
+            canister.icrc1_transfer(transfer(bob(), 100, Some(5))),
+            Err(TransferError::BadFee {
+                expected_fee: 10.into()
+            })
+        );
+        canister
+            .icrc1_transfer(transfer(bob(), 100, Some(10)))
+            .unwrap();
+
+        let transfers = vec![
+            BatchTransferArgs {
+                receiver: Account::new(bob(), None),
+                amount: Tokens128::from(20),
+            },
+            BatchTransferArgs {
+                receiver: Account::new(xtc(), None),
+                amount: Tokens128::from(200),
+            },
+        ];
+        let ids = canister.batch_transfer(None, transfers).unwrap();
+        assert_eq!(LedgerData::get(ids[0]).unwrap().fee, 5.into());
+        assert_eq!(LedgerData::get(ids[1]).unwrap().fee, 0.into());
+
+        let balance = |owner| canister.icrc1_balance_of(Account::new(owner, None));
+        assert_eq!(balance(alice()), Tokens128::from(665));
+        assert_eq!(balance(bob()), Tokens128::from(120));
+        assert_eq!(balance(xtc()), Tokens128::from(200));
+        assert_eq!(balance(john()), Tokens128::from(15));
+    }
+
+    #[test]
+    fn batch_transfer_burns_share_of_amount() {
+        let canister = test_canister();
+
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = Tokens128::from(10);
+        stats.fee_to = john();
+        stats.burn_policy = Some(BurnPolicy { rate_bps: 1_000 });
+        TokenConfig::set_stable(stats);
+
+        let transfers = vec![
+            BatchTransferArgs {
+                receiver: Account::new(bob(), None),
+                amount: Tokens128::from(100),
+            },
+            BatchTransferArgs {
+                receiver: Account::new(xtc(), None),
+                amount: Tokens128::from(200),
+            },
+        ];
+        canister.batch_transfer(None, transfers).unwrap();
+
+        let balance = |owner| canister.icrc1_balance_of(Account::new(owner, None));
+        assert_eq!(balance(alice()), Tokens128::from(680));
+        assert_eq!(balance(bob()), Tokens128::from(90));
+        assert_eq!(balance(xtc()), Tokens128::from(180));
+        assert_eq!(balance(john()), Tokens128::from(20));
+        assert_eq!(StableBalances.total_supply(), Tokens128::from(970));
+        assert_eq!(LedgerData::burned_total(), Tokens128::from(30));
+
+        let burn = LedgerData::get(LedgerData::len() - 1).unwrap();
+        assert_eq!(burn.operation, crate::state::ledger::Operation::Burn);
+        assert_eq!(burn.amount, Tokens128::from(30));
+        assert_eq!(
+            crate::state::invariants::Invariants::verify().discrepancy,
+            None
+        );
+    }
+
+    #[test]
+    fn atomic_batch_applies_all_operations() {
+        let canister = test_canister();
+
+        let ids = canister
+            .atomic_batch(vec![
+                BatchOperation::Transfer {
+                    from_subaccount: None,
+                    to: Account::new(bob(), None),
+                    amount: 300.into(),
+                },
+                BatchOperation::Mint {
+                    to: Account::new(john(), None),
+                    amount: 50.into(),
+                },
+                BatchOperation::Burn {
+                    from_subaccount: None,
+                    amount: 100.into(),
+                },
+            ])
+            .unwrap();
+
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            600.into()
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            300.into()
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            50.into()
+        );
+        assert_eq!(canister.icrc1_total_supply(), 950.into());
+        assert_eq!(LedgerData::burned_total(), 100.into());
+    }
+
+    #[test]
+    fn atomic_batch_is_all_or_nothing() {
+        let canister = test_canister();
+
+        let result = canister.atomic_batch(vec![
+            BatchOperation::Transfer {
+                from_subaccount: None,
+                to: Account::new(bob(), None),
+                amount: 600.into(),
+            },
+            BatchOperation::Burn {
+                from_subaccount: None,
+                amount: 500.into(),
+            },
+        ]);
+
+        assert_eq!(
+            result,
+            Err(TxError::InsufficientFunds {
+                balance: 400.into()
+            })
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            1000.into()
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            0.into()
+        );
+        assert_eq!(LedgerData::len(), 1);
+
+        get_context().update_caller(bob());
+        let result = canister.atomic_batch(vec![BatchOperation::Mint {
+            to: Account::new(bob(), None),
+            amount: 100.into(),
+        }]);
+        assert_eq!(result, Err(TxError::Unauthorized));
+    }
+
+    #[test]
+    fn large_transfer_requires_confirmation() {
+        let canister = test_canister();
+        LargeTransfers::clear();
+        canister
+            .set_large_transfer_policy(Some(LargeTransferPolicy {
+                threshold: 100.into(),
+                confirmation_window_nanos: 1_000_000,
+            }))
+            .unwrap();
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 300.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        assert_eq!(
+            canister.transfer(transfer.clone()),
+            Err(TxError::ConfirmationRequired {
+                threshold: 100.into()
+            })
+        );
+        assert_eq!(
+            canister.batch_transfer(
+                None,
+                vec![
+                    BatchTransferArgs {
+                        receiver: Account::new(bob(), None),
+                        amount: 60.into(),
+                    },
+                    BatchTransferArgs {
+                        receiver: Account::new(john(), None),
+                        amount: 60.into(),
+                    },
+                ],
+            ),
+            Err(TxError::ConfirmationRequired {
+                threshold: 100.into()
+            })
+        );
+
+        let id = canister
+            .initiate_large_transfer(transfer, Some(john()))
+            .unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            0.into()
+        );
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.confirm_large_transfer(id),
+            Err(TxError::Unauthorized)
+        );
+
+        get_context().update_caller(john());
+        assert!(canister.confirm_large_transfer(id).is_ok());
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            300.into()
+        );
+        assert_eq!(
+            canister.confirm_large_transfer(id),
+            Err(TxError::PendingTransferNotFound)
+        );
+    }
+
+    #[test]
+    fn faucet_limits_test_token_mints() {
+        let canister = test_canister();
+        Faucet::clear();
+        let mut stats = TokenConfig::get_stable();
+        stats.is_test_token = true;
+        TokenConfig::set_stable(stats);
+        canister
+            .set_faucet_policy(Some(FaucetPolicy {
+                daily_cap: 100.into(),
+                cooldown_nanos: 0,
+            }))
+            .unwrap();
+
+        get_context().update_caller(bob());
+        canister.mint(bob(), None, 60.into()).unwrap();
+        assert_eq!(
+            canister.mint(bob(), None, 50.into()),
+            Err(TxError::FaucetLimitExceeded {
+                remaining: 40.into()
+            })
+        );
+        assert_eq!(
+            canister.atomic_batch(vec![BatchOperation::Mint {
+                to: bob().into(),
+                amount: 50.into(),
+            }]),
+            Err(TxError::FaucetLimitExceeded {
+                remaining: 40.into()
+            })
+        );
+        canister.mint(john(), None, 40.into()).unwrap();
+        assert_eq!(canister.faucet_status(bob()).remaining, Some(0.into()));
+
+        get_context().update_caller(alice());
+        canister.mint(alice(), None, 1000.into()).unwrap();
+    }
+
+    #[test]
+    fn pay_payment_request() {
+        let canister = test_canister();
+        PaymentRequests::clear();
+
+        get_context().update_caller(bob());
+        let now = ic::time();
+        let id = canister
+            .create_payment_request(300.into(), Some(vec![1; 8]), now + 1000, None)
+            .unwrap();
+
+        assert_eq!(canister.pay_request(id, None), Err(TxError::SelfTransfer));
+
+        get_context().update_caller(alice());
+        let tx_id = canister.pay_request(id, None).unwrap() as TxId;
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            300.into()
+        );
+        assert_eq!(LedgerData::get(tx_id).unwrap().memo, Some(vec![1; 8]));
+        assert_eq!(
+            canister.get_payment_request(id).unwrap().status,
+            PaymentRequestStatus::Paid {
+                payer: alice().into(),
+                tx_id,
+                paid_at: now,
+            }
+        );
+        assert_eq!(
+            canister.pay_request(id, None),
+            Err(TxError::PaymentRequestNotPending)
+        );
+    }
+
+    /// Balances storage counting the writes.
+    #[derive(Default)]
+    struct CountingBalances {
+        balances: LocalBalances,
+        writes: usize,
+    }
+
+    impl Balances for CountingBalances {
+        fn insert(&mut self, account: AccountInternal, token: Tokens128) {
+            self.writes += 1;
+            self.balances.insert(account, token);
+        }
+
+        fn get(&self, account: &AccountInternal) -> Option<Tokens128> {
+            self.balances.get(account)
+        }
+
+        fn remove(&mut self, account: &AccountInternal) -> Option<Tokens128> {
+            self.writes += 1;
+            self.balances.remove(account)
+        }
+
+        fn list_balances(&self, start: usize, limit: usize) -> Vec<(AccountInternal, Tokens128)> {
+            self.balances.list_balances(start, limit)
+        }
+    }
+
+    #[test]
+    fn batch_transfer_writes_only_touched_accounts() {
+        MockContext::new().inject();
+        let mut balances = CountingBalances::default();
+        balances.insert(alice().into(), 1_000_000.into());
+        for index in 0..1000u64 {
+            let holder = Principal::from_slice(&index.to_be_bytes());
+            balances.insert(holder.into(), 10.into());
+        }
+        balances.writes = 0;
+
+        let transfers = (0..150u64)
+            .map(|index| BatchTransferArgs {
+                receiver: Principal::from_slice(&index.to_be_bytes()).into(),
+                amount: 1.into(),
+            })
+            .collect::<Vec<_>>();
+
+        batch_transfer_internal(
+            alice().into(),
+            &transfers,
+            &mut balances,
+            &vec![0.into(); transfers.len()],
+            john(),
+            0.0,
+            None,
+        )
+        .unwrap();
+
+        // Sender and the receivers. Fee recipient and auction balances are unchanged.
+        assert_eq!(balances.writes, transfers.len() + 1);
+        assert_eq!(balances.balance_of(&alice().into()), 999_850.into());
+    }
+
     #[test]
     fn batch_transfer_insufficient_balance() {
         let canister = test_canister();
@@ -585,9 +1472,18 @@ mod tests {
         assert!(validate_and_get_tx_ts(john(), &tx).is_ok());
 
         let mut tx = transfer.clone();
-        tx.memo = Some([0; 32]);
+        tx.memo = Some(vec![0; 32]);
         assert!(validate_and_get_tx_ts(john(), &tx).is_ok());
 
+        let mut tx = transfer.clone();
+        tx.memo = Some(vec![0; MAX_MEMO_LENGTH + 1]);
+        assert_eq!(
+            validate_and_get_tx_ts(john(), &tx),
+            Err(TxError::MemoTooLong {
+                max_length: MAX_MEMO_LENGTH
+            })
+        );
+
         let mut tx = transfer.clone();
         tx.created_at_time = None;
         assert!(validate_and_get_tx_ts(john(), &tx).is_ok());
@@ -601,7 +1497,7 @@ mod tests {
             to: Account::new(bob(), None),
             amount: 10_000.into(),
             fee: None,
-            memo: Some([1; 32]),
+            memo: Some(vec![1; 32]),
             created_at_time: Some(curr_time),
         };
 
@@ -613,7 +1509,7 @@ mod tests {
         assert!(validate_and_get_tx_ts(john(), &tx).is_ok());
 
         let mut tx = transfer;
-        tx.memo = Some([2; 32]);
+        tx.memo = Some(vec![2; 4]);
         assert!(validate_and_get_tx_ts(john(), &tx).is_ok());
     }
 
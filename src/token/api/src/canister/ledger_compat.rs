@@ -0,0 +1,281 @@
+//! Transaction history in the block format of the ICP ledger, for the indexers which only speak
+//! the ICP ledger interface.
+//!
+//! `query_blocks` translates the ledger records into the ICP blocks, and `query_encoded_blocks`
+//! returns the same blocks encoded with candid, in place of the protobuf encoding of
+//! `get_blocks_pb`. The accounts are replaced with their ICP account identifiers.
+//!
+//! The records which have no counterpart in the ICP ledger (approvals, rescales, failed
+//! transactions and amounts exceeding `u64`) keep their place in the chain with the
+//! `Operation::Unmapped` operation. The ICP ledger clients don't know this variant and decode the
+//! operation as `null`, as the ICP ledger does for the unknown operations.
+//!
+//! The token doesn't archive the pruned transactions, so `archived_blocks` is always empty and
+//! the history starts at `first_block_index`.
+
+use candid::{CandidType, Decode, Deserialize, Encode, Func};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+
+use crate::account::{Account, AccountInternal};
+use crate::state::account_ids::AccountIds;
+use crate::state::ledger::{self, LedgerData, TransactionStatus};
+use crate::tx_record::{TxId, TxRecord};
+
+/// Maximum number of the blocks returned by one call.
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 2_000;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct GetBlocksArgs {
+    pub start: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Tokens {
+    pub e8s: u64,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TimeStamp {
+    pub timestamp_nanos: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum Operation {
+    Mint {
+        to: Vec<u8>,
+        amount: Tokens,
+    },
+    Burn {
+        from: Vec<u8>,
+        amount: Tokens,
+    },
+    Transfer {
+        from: Vec<u8>,
+        to: Vec<u8>,
+        amount: Tokens,
+        fee: Tokens,
+    },
+    /// The record has no ICP ledger counterpart; `operation` is the IS20 operation of the record.
+    Unmapped {
+        operation: ledger::Operation,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Transaction {
+    /// Numeric memos are not used by the token; the memo of the record is in `icrc1_memo`.
+    pub memo: u64,
+    pub icrc1_memo: Option<Vec<u8>>,
+    pub operation: Option<Operation>,
+    pub created_at_time: TimeStamp,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Block {
+    pub parent_hash: Option<Vec<u8>>,
+    pub transaction: Transaction,
+    pub timestamp: TimeStamp,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ArchivedBlocksRange {
+    pub start: u64,
+    pub length: u64,
+    pub callback: Func,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct QueryBlocksResponse {
+    pub chain_length: u64,
+    pub certificate: Option<Vec<u8>>,
+    pub blocks: Vec<Block>,
+    pub first_block_index: u64,
+    pub archived_blocks: Vec<ArchivedBlocksRange>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct QueryEncodedBlocksResponse {
+    pub chain_length: u64,
+    pub certificate: Option<Vec<u8>>,
+    /// Blocks encoded with candid, see `decode_block`.
+    pub blocks: Vec<Vec<u8>>,
+    pub first_block_index: u64,
+    pub archived_blocks: Vec<ArchivedBlocksRange>,
+}
+
+/// Returns up to `args.length` blocks starting from `args.start`, or from the earliest stored
+/// transaction if the earlier ones were pruned.
+pub fn query_blocks(args: GetBlocksArgs) -> QueryBlocksResponse {
+    let (first_block_index, records) = stored_records(args);
+    QueryBlocksResponse {
+        chain_length: LedgerData::len(),
+        certificate: None,
+        blocks: records.iter().map(to_block).collect(),
+        first_block_index,
+        archived_blocks: vec![],
+    }
+}
+
+/// The same as `query_blocks`, with the blocks encoded with candid.
+pub fn query_encoded_blocks(args: GetBlocksArgs) -> QueryEncodedBlocksResponse {
+    let (first_block_index, records) = stored_records(args);
+    QueryEncodedBlocksResponse {
+        chain_length: LedgerData::len(),
+        certificate: None,
+        blocks: records
+            .iter()
+            .map(|tx| encode_block(&to_block(tx)))
+            .collect(),
+        first_block_index,
+        archived_blocks: vec![],
+    }
+}
+
+pub fn encode_block(block: &Block) -> Vec<u8> {
+    Encode!(block).expect("failed to encode block")
+}
+
+pub fn decode_block(bytes: &[u8]) -> Option<Block> {
+    Decode!(bytes, Block).ok()
+}
+
+fn stored_records(args: GetBlocksArgs) -> (TxId, Vec<TxRecord>) {
+    let start = args.start.max(LedgerData::history_info().earliest_index);
+    let end = args.start.saturating_add(args.length);
+    let length = end.saturating_sub(start).min(MAX_BLOCKS_PER_REQUEST);
+    let records = LedgerData::get_range(start, length as usize);
+
+    (start, records)
+}
+
+pub fn to_block(tx: &TxRecord) -> Block {
+    let timestamp = TimeStamp {
+        timestamp_nanos: tx.timestamp,
+    };
+    Block {
+        parent_hash: tx.parent_hash.map(|hash| hash.to_vec()),
+        transaction: Transaction {
+            memo: 0,
+            icrc1_memo: tx.memo.clone(),
+            operation: Some(to_operation(tx)),
+            created_at_time: timestamp,
+        },
+        timestamp,
+    }
+}
+
+fn to_operation(tx: &TxRecord) -> Operation {
+    use ledger::Operation::*;
+
+    let unmapped = Operation::Unmapped {
+        operation: tx.operation,
+    };
+    let (Some(amount), Some(fee)) = (tokens(tx.amount), tokens(tx.fee)) else {
+        return unmapped;
+    };
+    if tx.status != TransactionStatus::Succeeded {
+        return unmapped;
+    }
+
+    match tx.operation {
+        Mint => Operation::Mint {
+            to: account_id(tx.to),
+            amount,
+        },
+        Burn => Operation::Burn {
+            from: account_id(tx.from),
+            amount,
+        },
+        Transfer | TransferFrom | StandingOrder | Auction | Claim | Recovery | DustSweep => {
+            Operation::Transfer {
+                from: account_id(tx.from),
+                to: account_id(tx.to),
+                amount,
+                fee,
+            }
+        }
+        Approve | Rescale => unmapped,
+    }
+}
+
+fn tokens(amount: Tokens128) -> Option<Tokens> {
+    u64::try_from(amount.amount).ok().map(|e8s| Tokens { e8s })
+}
+
+fn account_id(account: Account) -> Vec<u8> {
+    AccountIds::of(AccountInternal::from(account)).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn records_are_translated_to_blocks() {
+        MockContext::new().inject();
+        LedgerData::clear();
+
+        LedgerData::mint(alice().into(), bob().into(), 1_000.into());
+        LedgerData::transfer(
+            bob().into(),
+            alice().into(),
+            100.into(),
+            10.into(),
+            Some(vec![1, 2]),
+            0,
+        );
+        LedgerData::rescale(alice().into(), 1_000.into());
+
+        let response = query_blocks(GetBlocksArgs {
+            start: 0,
+            length: 10,
+        });
+        assert_eq!(response.chain_length, 3);
+        assert_eq!(response.first_block_index, 0);
+        assert_eq!(response.blocks.len(), 3);
+
+        let bob_id = AccountIds::of(bob().into()).to_vec();
+        assert_eq!(
+            response.blocks[0].transaction.operation,
+            Some(Operation::Mint {
+                to: bob_id.clone(),
+                amount: Tokens { e8s: 1_000 },
+            })
+        );
+        assert_eq!(
+            response.blocks[1].transaction.operation,
+            Some(Operation::Transfer {
+                from: bob_id,
+                to: AccountIds::of(alice().into()).to_vec(),
+                amount: Tokens { e8s: 100 },
+                fee: Tokens { e8s: 10 },
+            })
+        );
+        assert_eq!(response.blocks[1].transaction.icrc1_memo, Some(vec![1, 2]));
+        assert_eq!(
+            response.blocks[2].transaction.operation,
+            Some(Operation::Unmapped {
+                operation: ledger::Operation::Rescale,
+            })
+        );
+
+        let encoded = query_encoded_blocks(GetBlocksArgs {
+            start: 1,
+            length: 1,
+        });
+        assert_eq!(encoded.first_block_index, 1);
+        assert_eq!(
+            encoded
+                .blocks
+                .iter()
+                .map(|block| decode_block(block))
+                .collect::<Vec<_>>(),
+            vec![Some(response.blocks[1].clone())]
+        );
+    }
+}
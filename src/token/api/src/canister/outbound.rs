@@ -0,0 +1,64 @@
+//! Delivery of the outbound notifications queue, see `state::outbound`.
+//!
+//! The timer task runs every `OUTBOUND_DELIVERY_PERIOD`. It retries the event subscriptions whose
+//! events could not be queued (see `state::delivery`), and sends the messages whose retry delay
+//! has passed. The owner retries or drops the dead letters with `retry_outbound_message` and
+//! `drop_outbound_message`, which are recorded in the admin log.
+
+use std::time::Duration;
+
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::config::Value;
+use crate::state::outbound::{MessageId, Outbound};
+#[cfg(target_family = "wasm")]
+use crate::state::subscriptions::EventSubscriptions;
+
+pub const OUTBOUND_DELIVERY_PERIOD: Duration = Duration::from_secs(10);
+
+/// Starts the timer task delivering the queued messages. Timers are not preserved on upgrade, so
+/// this must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_outbound_delivery() {
+    ic_exports::ic_cdk_timers::set_timer_interval(OUTBOUND_DELIVERY_PERIOD, || {
+        let now = ic::time();
+        EventSubscriptions::flush(now);
+        Outbound::deliver_due(now);
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_outbound_delivery() {}
+
+pub(crate) fn retry_message(caller: CheckedPrincipal<Owner>, id: MessageId) -> Result<(), TxError> {
+    let message = Outbound::get(id).ok_or(TxError::OutboundMessageNotFound)?;
+    Outbound::retry(id, ic::time())?;
+    AdminLog::record(
+        caller.inner(),
+        AdminAction::RetryOutboundMessage { id },
+        Some(Value::Nat(message.attempts.into())),
+        None,
+    );
+
+    Ok(())
+}
+
+pub(crate) fn drop_message(caller: CheckedPrincipal<Owner>, id: MessageId) -> Result<(), TxError> {
+    let message = Outbound::drop(id)?;
+    AdminLog::record(
+        caller.inner(),
+        AdminAction::DropOutboundMessage { id },
+        Some(Value::Text(format!(
+            "{:?} {}.{}",
+            message.kind,
+            message.target.to_text(),
+            message.method
+        ))),
+        None,
+    );
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! Allowances granted by the owner of the tokens offline (permits, like EIP-2612).
+//!
+//! The owner signs the message produced by `permit_message` with the key of their
+//! self-authenticating principal, see `canister::signed_transfer` for the signature schemes, and
+//! passes the permit to the spender. The spender submits it with `permit`, which sets the
+//! allowance as if the owner called `approve`, so the owner never calls the canister.
+//!
+//! Every permit carries a nonce, which must be equal to the number of the permits of the owner
+//! executed so far (see `PermitNonces`), and a deadline after which it's refused, so a permit
+//! cannot be replayed or used long after it was signed.
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use super::approvals;
+use super::signed_transfer::{push_bytes, push_optional, verify_signature, SignatureScheme};
+use crate::account::{AccountInternal, Subaccount};
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+use crate::state::nonces::PermitNonces;
+
+/// Domain separator of the permit messages, prefixed with its length in the same way as the IC
+/// request domain separator.
+const DOMAIN_SEPARATOR: &[u8] = b"\x0bis20-permit";
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Permit {
+    /// Subaccount of the owner the allowance is granted from.
+    pub owner_subaccount: Option<Subaccount>,
+    pub spender: Principal,
+    pub amount: Tokens128,
+    /// Time after which the permit is refused, in nanoseconds since the epoch.
+    pub deadline: Timestamp,
+    pub nonce: u64,
+    pub scheme: SignatureScheme,
+    /// Public key of the owner.
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Returns the message to be signed to grant the allowance of the `permit` on the `token`
+/// canister.
+pub fn permit_message(token: Principal, permit: &Permit) -> Vec<u8> {
+    let mut message = DOMAIN_SEPARATOR.to_vec();
+    push_bytes(&mut message, token.as_slice());
+    message.extend_from_slice(&permit.nonce.to_be_bytes());
+    push_optional(&mut message, permit.owner_subaccount.as_ref());
+    push_bytes(&mut message, permit.spender.as_slice());
+    message.extend_from_slice(&permit.amount.amount.to_be_bytes());
+    message.extend_from_slice(&permit.deadline.to_be_bytes());
+    message
+}
+
+/// Sets the allowance signed by the owner in the `permit`. Returns the principal of the owner.
+pub fn permit(permit: &Permit, now: Timestamp) -> Result<Principal, TxError> {
+    if now > permit.deadline {
+        return Err(TxError::PermitExpired);
+    }
+
+    let message = permit_message(ic::id(), permit);
+    let owner = verify_signature(
+        permit.scheme,
+        &permit.public_key,
+        &message,
+        &permit.signature,
+    )?;
+
+    let expected = PermitNonces::get(owner);
+    if permit.nonce != expected {
+        return Err(TxError::BadNonce { expected });
+    }
+
+    approvals::approve(
+        AccountInternal::new(owner, permit.owner_subaccount),
+        permit.spender,
+        permit.amount,
+        None,
+        now,
+    )?;
+
+    PermitNonces::increment(owner);
+    Ok(owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::allowances::Allowances;
+    use crate::state::balances::{Balances, StableBalances};
+
+    fn sign(owner_subaccount: Option<Subaccount>, nonce: u64, deadline: Timestamp) -> Permit {
+        let key_pair = ed25519_compact::KeyPair::from_seed(ed25519_compact::Seed::new([7; 32]));
+        let mut permit = Permit {
+            owner_subaccount,
+            spender: bob(),
+            amount: 100.into(),
+            deadline,
+            nonce,
+            scheme: SignatureScheme::Ed25519,
+            public_key: key_pair.pk.to_vec(),
+            signature: vec![],
+        };
+        let message = permit_message(ic::id(), &permit);
+        permit.signature = key_pair.sk.sign(message, None).to_vec();
+        permit
+    }
+
+    #[test]
+    fn permit_sets_allowance_once() {
+        MockContext::new().with_caller(alice()).inject();
+        Allowances::clear();
+        PermitNonces::clear();
+
+        let signed = sign(None, 0, 100);
+        assert_eq!(permit(&signed, 101), Err(TxError::PermitExpired));
+
+        // The owner must hold tokens to set an allowance.
+        StableBalances.clear();
+        assert_eq!(
+            permit(&signed, 100),
+            Err(TxError::InsufficientFunds {
+                balance: Tokens128::ZERO
+            })
+        );
+        let message = permit_message(ic::id(), &signed);
+        let owner = verify_signature(
+            signed.scheme,
+            &signed.public_key,
+            &message,
+            &signed.signature,
+        )
+        .unwrap();
+        StableBalances.insert(owner.into(), 1.into());
+
+        let owner = permit(&signed, 100).unwrap();
+        assert_eq!(Allowances::get(owner.into(), bob(), 100), 100.into());
+        assert_eq!(PermitNonces::get(owner), 1);
+
+        // The same permit cannot be submitted twice.
+        assert_eq!(permit(&signed, 100), Err(TxError::BadNonce { expected: 1 }));
+
+        let mut tampered = sign(None, 1, 100);
+        tampered.amount = 1000.into();
+        assert_eq!(permit(&tampered, 100), Err(TxError::InvalidSignature));
+    }
+}
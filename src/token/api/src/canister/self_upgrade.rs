@@ -0,0 +1,48 @@
+//! Upgrade of the token by its owner, independent of the factory, e.g. after the factory which
+//! deployed the token was decommissioned.
+//!
+//! The owner uploads the wasm module in chunks with `upload_upgrade_chunk`, see
+//! `state::upgrade_chunks`, and then calls `commit_upgrade` with the hash of the module. The token
+//! installs the module on itself with the management canister, so it must be one of its
+//! controllers, see `canister::controllers`.
+
+use candid::Principal;
+use canister_sdk::ic_cdk::api::management_canister::main::{
+    CanisterInstallMode, InstallCodeArgument,
+};
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::config::Value;
+use crate::state::guard::StateGuard;
+use crate::state::upgrade_chunks::UpgradeChunks;
+
+/// Upgrades the token with the uploaded module if its hash is the `expected_module_hash`. The
+/// uploaded chunks are removed by the `post_upgrade` of the new module.
+pub async fn commit_upgrade(
+    caller: Principal,
+    expected_module_hash: Vec<u8>,
+) -> Result<(), TxError> {
+    let _guard = StateGuard::global("self_upgrade")?;
+    let wasm_module = UpgradeChunks::verified_module(&expected_module_hash)?;
+
+    // Recorded before the call, as the response of a successful upgrade is not delivered to the
+    // code which made the call.
+    AdminLog::record(
+        caller,
+        AdminAction::CommitUpgrade,
+        None,
+        Some(Value::Blob(expected_module_hash)),
+    );
+
+    let args = InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade,
+        canister_id: ic::id(),
+        wasm_module,
+        arg: candid::encode_args(()).expect("failed to encode upgrade arguments"),
+    };
+    ic::call::<_, (), _>(Principal::management_canister(), "install_code", (args,))
+        .await
+        .map_err(|(_, message)| TxError::ManagementCallFailed { message })
+}
@@ -0,0 +1,272 @@
+//! Transfers signed by the owner of the tokens offline and submitted by a relayer.
+//!
+//! The signer authorizes a transfer by signing the message produced by `signed_transfer_message`
+//! with an Ed25519 (the same keys as used to sign the ingress messages) or a secp256k1 key. The
+//! tokens are transferred from the account of the self-authenticating principal of the signer
+//! key, while the relayer submitting the transfer pays for the ingress message.
+//!
+//! Every signed transfer carries a nonce, which must be equal to the number of the signed
+//! transfers of the signer executed so far (see `TransferNonces`), so a signed payload cannot be
+//! replayed.
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_kit::ic;
+use k256::ecdsa::signature::Verifier;
+
+use super::is20_transactions::is20_transfer;
+use crate::account::CheckedAccount;
+use crate::error::TxError;
+use crate::state::ledger::{TransferArgs, TxReceipt};
+use crate::state::nonces::TransferNonces;
+
+/// Domain separator of the signed transfer messages, prefixed with its length in the same way
+/// as the IC request domain separator.
+const DOMAIN_SEPARATOR: &[u8] = b"\x14is20-signed-transfer";
+
+/// DER prefix of an Ed25519 public key in the `SubjectPublicKeyInfo` format.
+const ED25519_DER_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// DER prefix of an uncompressed secp256k1 public key in the `SubjectPublicKeyInfo` format.
+const SECP256K1_DER_PREFIX: &[u8] = &[
+    0x30, 0x56, 0x30, 0x10, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x05, 0x2b,
+    0x81, 0x04, 0x00, 0x0a, 0x03, 0x42, 0x00,
+];
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// Raw 32 bytes public key, signature over the message.
+    Ed25519,
+    /// SEC1 encoded public key, ECDSA signature over the SHA-256 hash of the message in the
+    /// 64 bytes `r || s` format.
+    Secp256k1,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct SignedTransfer {
+    pub transfer: TransferArgs,
+    pub nonce: u64,
+    pub scheme: SignatureScheme,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Returns the message to be signed to authorize the `transfer` on the `token` canister.
+pub fn signed_transfer_message(token: Principal, transfer: &TransferArgs, nonce: u64) -> Vec<u8> {
+    let mut message = DOMAIN_SEPARATOR.to_vec();
+    push_bytes(&mut message, token.as_slice());
+    message.extend_from_slice(&nonce.to_be_bytes());
+    push_optional(&mut message, transfer.from_subaccount.as_ref());
+    push_bytes(&mut message, transfer.to.owner.as_slice());
+    push_optional(&mut message, transfer.to.subaccount.as_ref());
+    message.extend_from_slice(&transfer.amount.amount.to_be_bytes());
+    push_optional(
+        &mut message,
+        transfer.fee.map(|fee| fee.amount.to_be_bytes()),
+    );
+    match &transfer.memo {
+        // Memo is of variable length, so it's prefixed with the length.
+        Some(memo) => {
+            message.push(1);
+            push_bytes(&mut message, memo);
+        }
+        None => message.push(0),
+    }
+    push_optional(&mut message, transfer.created_at_time.map(u64::to_be_bytes));
+    message
+}
+
+pub(super) fn push_bytes(message: &mut Vec<u8>, bytes: &[u8]) {
+    message.push(bytes.len() as u8);
+    message.extend_from_slice(bytes);
+}
+
+pub(super) fn push_optional(message: &mut Vec<u8>, value: Option<impl AsRef<[u8]>>) {
+    match value {
+        Some(value) => {
+            message.push(1);
+            message.extend_from_slice(value.as_ref());
+        }
+        None => message.push(0),
+    }
+}
+
+/// Verifies the signature and returns the principal of the signer.
+pub fn verify_signed_transfer(signed: &SignedTransfer) -> Result<Principal, TxError> {
+    let message = signed_transfer_message(ic::id(), &signed.transfer, signed.nonce);
+    verify_signature(
+        signed.scheme,
+        &signed.public_key,
+        &message,
+        &signed.signature,
+    )
+}
+
+/// Verifies the `signature` of the `message` with the `public_key` and returns the
+/// self-authenticating principal of the key.
+pub(crate) fn verify_signature(
+    scheme: SignatureScheme,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<Principal, TxError> {
+    let der_public_key = match scheme {
+        SignatureScheme::Ed25519 => {
+            let public_key = ed25519_compact::PublicKey::from_slice(public_key)
+                .map_err(|_| TxError::InvalidSignature)?;
+            let signature = ed25519_compact::Signature::from_slice(signature)
+                .map_err(|_| TxError::InvalidSignature)?;
+            public_key
+                .verify(message, &signature)
+                .map_err(|_| TxError::InvalidSignature)?;
+
+            [ED25519_DER_PREFIX, &public_key[..]].concat()
+        }
+        SignatureScheme::Secp256k1 => {
+            let public_key = k256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+                .map_err(|_| TxError::InvalidSignature)?;
+            let signature = k256::ecdsa::Signature::try_from(signature)
+                .map_err(|_| TxError::InvalidSignature)?;
+            public_key
+                .verify(message, &signature)
+                .map_err(|_| TxError::InvalidSignature)?;
+
+            // Principals are derived from the uncompressed key, even if the compressed one
+            // was provided.
+            let point = public_key.to_encoded_point(false);
+            [SECP256K1_DER_PREFIX, point.as_bytes()].concat()
+        }
+    };
+
+    Ok(Principal::self_authenticating(der_public_key))
+}
+
+/// Executes the transfer on behalf of the signer of the `signed` payload.
+pub fn transfer_signed(signed: &SignedTransfer, auction_fee_ratio: f64) -> TxReceipt {
+    let signer = verify_signed_transfer(signed)?;
+
+    let expected = TransferNonces::get(signer);
+    if signed.nonce != expected {
+        return Err(TxError::BadNonce { expected });
+    }
+
+    let transfer = &signed.transfer;
+    let account =
+        CheckedAccount::with_recipient_of(signer, transfer.to.into(), transfer.from_subaccount)?;
+    let id = is20_transfer(account, transfer, auction_fee_ratio)?;
+
+    TransferNonces::increment(signer);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_helpers::tokens::Tokens128;
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+    use k256::ecdsa::signature::Signer;
+
+    use super::*;
+    use crate::account::AccountInternal;
+    use crate::state::balances::{Balances, StableBalances};
+    use crate::state::config::TokenConfig;
+    use crate::state::ledger::LedgerData;
+
+    fn init() {
+        MockContext::new().with_caller(alice()).inject();
+        TokenConfig::set_stable(TokenConfig::default());
+        StableBalances.clear();
+        LedgerData::clear();
+        TransferNonces::clear();
+    }
+
+    fn transfer() -> TransferArgs {
+        TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    fn sign_ed25519(transfer: TransferArgs, nonce: u64) -> SignedTransfer {
+        let key_pair = ed25519_compact::KeyPair::from_seed(ed25519_compact::Seed::new([7; 32]));
+        let message = signed_transfer_message(ic::id(), &transfer, nonce);
+        SignedTransfer {
+            transfer,
+            nonce,
+            scheme: SignatureScheme::Ed25519,
+            public_key: key_pair.pk.to_vec(),
+            signature: key_pair.sk.sign(message, None).to_vec(),
+        }
+    }
+
+    #[test]
+    fn ed25519_signed_transfer() {
+        init();
+        let signed = sign_ed25519(transfer(), 0);
+        let signer = verify_signed_transfer(&signed).unwrap();
+        StableBalances.insert(AccountInternal::from(signer), 1000.into());
+
+        transfer_signed(&signed, 0.0).unwrap();
+        assert_eq!(
+            StableBalances.balance_of(&bob().into()),
+            Tokens128::from(100)
+        );
+        assert_eq!(TransferNonces::get(signer), 1);
+
+        // The same payload cannot be executed twice.
+        assert_eq!(
+            transfer_signed(&signed, 0.0),
+            Err(TxError::BadNonce { expected: 1 })
+        );
+    }
+
+    #[test]
+    fn secp256k1_signed_transfer() {
+        init();
+        let signing_key = k256::ecdsa::SigningKey::from_bytes(&[3; 32]).unwrap();
+        let message = signed_transfer_message(ic::id(), &transfer(), 0);
+        let signature: k256::ecdsa::Signature = signing_key.sign(&message);
+        let signed = SignedTransfer {
+            transfer: transfer(),
+            nonce: 0,
+            scheme: SignatureScheme::Secp256k1,
+            public_key: signing_key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+            signature: signature.to_bytes().to_vec(),
+        };
+
+        let signer = verify_signed_transfer(&signed).unwrap();
+        StableBalances.insert(AccountInternal::from(signer), 1000.into());
+        transfer_signed(&signed, 0.0).unwrap();
+        assert_eq!(
+            StableBalances.balance_of(&signer.into()),
+            Tokens128::from(900)
+        );
+    }
+
+    #[test]
+    fn tampered_transfer_is_rejected() {
+        init();
+        let mut signed = sign_ed25519(transfer(), 0);
+        signed.transfer.amount = 1000.into();
+        assert_eq!(
+            verify_signed_transfer(&signed),
+            Err(TxError::InvalidSignature)
+        );
+
+        let signed = sign_ed25519(transfer(), 5);
+        assert_eq!(
+            transfer_signed(&signed, 0.0),
+            Err(TxError::BadNonce { expected: 0 })
+        );
+    }
+}
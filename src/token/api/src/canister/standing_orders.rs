@@ -0,0 +1,133 @@
+//! Execution of the standing orders by a timer task of the token canister.
+//!
+//! The task runs every `STANDING_ORDERS_PERIOD` and executes at most
+//! `MAX_EXECUTIONS_PER_RUN` due orders, so a large number of orders due at the same time doesn't
+//! exceed the instruction limit of the task. The orders left over are executed at the next run.
+
+use std::time::Duration;
+
+use super::is20_transactions::transfer_internal;
+use crate::error::TxError;
+use crate::state::balances::StableBalances;
+use crate::state::config::{FeeRatio, Timestamp, TokenConfig};
+use crate::state::decimals::DecimalsMigration;
+use crate::state::large_transfers::LargeTransfers;
+use crate::state::ledger::LedgerData;
+use crate::state::migrations::StateMigrations;
+use crate::state::standing_orders::{StandingOrder, StandingOrders};
+use crate::tx_record::TxId;
+
+pub const STANDING_ORDERS_PERIOD: Duration = Duration::from_secs(60);
+
+pub const MAX_EXECUTIONS_PER_RUN: usize = 100;
+
+/// Starts the timer task executing the standing orders. Timers are not preserved on upgrade, so
+/// this must be called both on init and post upgrade. The `fee_ratio` returns the auction fee
+/// ratio at the time of the run.
+#[cfg(target_family = "wasm")]
+pub fn start_standing_orders(fee_ratio: impl Fn() -> f64 + 'static) {
+    ic_exports::ic_cdk_timers::set_timer_interval(STANDING_ORDERS_PERIOD, move || {
+        execute_due_orders(canister_sdk::ic_kit::ic::time(), fee_ratio());
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_standing_orders(_fee_ratio: impl Fn() -> f64 + 'static) {}
+
+/// Executes the orders due at the time `now`. Returns the number of the successful executions.
+///
+/// Nothing is executed while a migration of the state is running, so the orders are not counted
+/// as missed because of it.
+pub fn execute_due_orders(now: Timestamp, auction_fee_ratio: f64) -> usize {
+    if DecimalsMigration::check_not_running().is_err()
+        || StateMigrations::check_completed().is_err()
+    {
+        return 0;
+    }
+
+    let mut executed = 0;
+    for order in StandingOrders::due(now, MAX_EXECUTIONS_PER_RUN) {
+        let result = execute(&order, auction_fee_ratio);
+        StandingOrders::record_execution(order.id, result.is_ok(), now);
+        if result.is_ok() {
+            executed += 1;
+        }
+    }
+
+    executed
+}
+
+fn execute(order: &StandingOrder, auction_fee_ratio: f64) -> Result<TxId, TxError> {
+    let from = order.payer.into();
+    let to = order.to.into();
+
+    // The threshold could be lowered after the order was created.
+    LargeTransfers::check_amount(order.amount)?;
+    let config = TokenConfig::get_stable();
+    let (fee, fee_to) = config.fee_info(from, to, order.amount);
+    let burned = transfer_internal(
+        &mut StableBalances,
+        from,
+        to,
+        order.amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        config.burn_policy,
+    )?;
+
+    let id = LedgerData::standing_order(from, to, order.amount, fee);
+    LedgerData::record_transfer_burn(from, burned);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::account::AccountInternal;
+    use crate::state::balances::Balances;
+    use crate::state::ledger::Operation;
+    use crate::state::standing_orders::MIN_STANDING_ORDER_INTERVAL_NANOS;
+
+    #[test]
+    fn orders_are_paid_while_funds_are_available() {
+        MockContext::new().with_caller(alice()).inject();
+        StandingOrders::clear();
+        StableBalances.clear();
+        LedgerData::clear();
+        TokenConfig::set_stable(TokenConfig::default());
+
+        let alice_account = AccountInternal::from(alice());
+        StableBalances.insert(alice_account, 150.into());
+        StandingOrders::create(
+            alice_account,
+            bob().into(),
+            100.into(),
+            MIN_STANDING_ORDER_INTERVAL_NANOS,
+            None,
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(execute_due_orders(0, 0.0), 1);
+        assert_eq!(StableBalances.balance_of(&bob().into()), 100.into());
+        let record = LedgerData::get(LedgerData::len() - 1).unwrap();
+        assert_eq!(record.operation, Operation::StandingOrder);
+
+        // Not due yet.
+        assert_eq!(execute_due_orders(1, 0.0), 0);
+
+        // Not enough funds for the second payment.
+        assert_eq!(
+            execute_due_orders(MIN_STANDING_ORDER_INTERVAL_NANOS, 0.0),
+            0
+        );
+        assert_eq!(StableBalances.balance_of(&alice_account), 50.into());
+        let order = StandingOrders::list(alice()).pop().unwrap();
+        assert_eq!((order.executions, order.missed_executions), (1, 1));
+    }
+}
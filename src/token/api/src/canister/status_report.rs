@@ -0,0 +1,85 @@
+//! Status of the token canister as reported by the management canister, for the explorers which
+//! can't call `canister_status` without being a controller.
+//!
+//! The token is one of its own controllers, so it fetches its status on start and then every
+//! `STATUS_REPORT_PERIOD`, and serves the last fetched report with `get_canister_status_report`.
+//! The report is cached in the heap only, as it's fetched again after an upgrade.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use canister_sdk::ic_cdk::api::management_canister::main::{
+    CanisterIdRecord, CanisterStatusResponse,
+};
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+pub const STATUS_REPORT_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct CanisterStatusReport {
+    /// SHA-256 of the installed module.
+    pub module_hash: Option<Vec<u8>>,
+    pub controllers: Vec<Principal>,
+    pub freezing_threshold: Nat,
+    pub memory_size: Nat,
+    pub fetched_at: Timestamp,
+}
+
+impl CanisterStatusReport {
+    fn new(status: CanisterStatusResponse, now: Timestamp) -> Self {
+        Self {
+            module_hash: status.module_hash,
+            controllers: status.settings.controllers,
+            freezing_threshold: status.settings.freezing_threshold,
+            memory_size: status.memory_size,
+            fetched_at: now,
+        }
+    }
+}
+
+/// Starts the timer task refreshing the report. Timers are not preserved on upgrade, so this must
+/// be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_status_report() {
+    let refresh = || {
+        canister_sdk::ic_cdk::spawn(async {
+            let _ = refresh_status_report().await;
+        })
+    };
+    ic_exports::ic_cdk_timers::set_timer(Duration::ZERO, refresh);
+    ic_exports::ic_cdk_timers::set_timer_interval(STATUS_REPORT_PERIOD, refresh);
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_status_report() {}
+
+/// Fetches the status of the token canister and caches the report.
+pub async fn refresh_status_report() -> Result<CanisterStatusReport, TxError> {
+    let args = CanisterIdRecord {
+        canister_id: ic::id(),
+    };
+    let (status,) = ic::call::<_, (CanisterStatusResponse,), _>(
+        Principal::management_canister(),
+        "canister_status",
+        (args,),
+    )
+    .await
+    .map_err(|(_, message)| TxError::ManagementCallFailed { message })?;
+
+    let report = CanisterStatusReport::new(status, ic::time());
+    REPORT.with(|cached| cached.replace(Some(report.clone())));
+    Ok(report)
+}
+
+/// Returns the last fetched report, `None` if the status wasn't fetched since the last upgrade.
+pub fn status_report() -> Option<CanisterStatusReport> {
+    REPORT.with(|cached| cached.borrow().clone())
+}
+
+thread_local! {
+    static REPORT: RefCell<Option<CanisterStatusReport>> = RefCell::new(None);
+}
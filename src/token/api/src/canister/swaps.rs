@@ -0,0 +1,189 @@
+//! Atomic swaps of this token for another IS20 or ICRC-1 token.
+//!
+//! 1. The maker calls `create_swap`, which moves `amount` of this token from the maker to the
+//!    escrow account of the swap.
+//! 2. The counterparty transfers `other_amount` plus the transfer fee of the other token to the
+//!    account of this canister with the escrow subaccount of the swap (see `get_swap_escrow`) in
+//!    the other token canister.
+//! 3. The counterparty calls `accept_swap`. The deposit is verified with the other token canister
+//!    and transferred to the maker, after which the escrowed tokens are released to the
+//!    counterparty.
+//!
+//! If the swap is not accepted before the deadline, `refund_swap` returns the escrowed tokens to
+//! the maker and the deposit of the other token (minus its transfer fee) to the counterparty.
+
+use candid::Principal;
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use super::is20_transactions::transfer_internal;
+use crate::account::{Account, AccountInternal};
+use crate::error::{TransferError, TxError};
+use crate::state::balances::StableBalances;
+use crate::state::config::{FeeRatio, Timestamp, TokenConfig};
+use crate::state::frozen::FrozenAccounts;
+use crate::state::ledger::{LedgerData, TransferArgs};
+use crate::state::swaps::{escrow_subaccount, Swap, SwapId, SwapStatus, Swaps};
+
+pub fn create_swap(
+    maker: AccountInternal,
+    counterparty: Principal,
+    amount: Tokens128,
+    other_token: Principal,
+    other_amount: Tokens128,
+    deadline: Timestamp,
+    auction_fee_ratio: f64,
+) -> Result<SwapId, TxError> {
+    let now = ic::time();
+    if deadline <= now {
+        return Err(TxError::InvalidDeadline);
+    }
+
+    if other_amount.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    let escrow = AccountInternal::new(ic::id(), Some(escrow_subaccount(Swaps::next_id())));
+    let (fee, fee_to) = TokenConfig::get_stable().fee_info(maker, escrow, amount);
+    transfer_internal(
+        &mut StableBalances,
+        maker,
+        escrow,
+        amount,
+        fee,
+        fee_to.into(),
+        FeeRatio::new(auction_fee_ratio),
+        None,
+    )?;
+    LedgerData::transfer(maker, escrow, amount, fee, None, now);
+
+    let swap = Swaps::insert_new(
+        maker,
+        counterparty,
+        amount,
+        other_token,
+        other_amount,
+        deadline,
+    );
+    Ok(swap.id)
+}
+
+pub async fn accept_swap(caller: Principal, id: SwapId) -> Result<(), TxError> {
+    let swap = Swaps::get(id).ok_or(TxError::SwapNotFound)?;
+    if swap.counterparty != caller {
+        return Err(TxError::Unauthorized);
+    }
+
+    if ic::time() > swap.deadline {
+        return Err(TxError::SwapExpired);
+    }
+
+    // The escrowed tokens must be releasable before the deposit is sent to the maker.
+    FrozenAccounts::check_incoming(caller.into())?;
+
+    // Prevents accepting or refunding the swap while the other token calls are in progress.
+    let swap = Swaps::transition(id, SwapStatus::Open, SwapStatus::Accepting)?;
+    if let Err(e) = send_deposit_to_maker(&swap).await {
+        Swaps::transition(id, SwapStatus::Accepting, SwapStatus::Open)?;
+        return Err(e);
+    }
+
+    release_escrow(&swap, caller.into())?;
+    Swaps::transition(id, SwapStatus::Accepting, SwapStatus::Completed)?;
+    Ok(())
+}
+
+async fn send_deposit_to_maker(swap: &Swap) -> Result<(), TxError> {
+    let other_fee = icrc1_fee(swap.other_token).await?;
+    let deposited = icrc1_balance_of(swap.other_token, swap.escrow_account().into()).await?;
+    let required = (swap.other_amount + other_fee).ok_or(TxError::AmountOverflow)?;
+    if deposited < required {
+        return Err(TxError::SwapNotFunded { deposited });
+    }
+
+    icrc1_transfer(swap, swap.maker, swap.other_amount, other_fee).await
+}
+
+/// Refunds the swap which was not accepted before the deadline. Can be called by anyone.
+pub async fn refund_swap(id: SwapId) -> Result<(), TxError> {
+    let swap = Swaps::get(id).ok_or(TxError::SwapNotFound)?;
+    if ic::time() <= swap.deadline {
+        return Err(TxError::SwapNotExpired {
+            deadline: swap.deadline,
+        });
+    }
+
+    let swap = Swaps::transition(id, SwapStatus::Open, SwapStatus::Refunded)?;
+    release_escrow(&swap, swap.maker.into())?;
+
+    // The counterparty may have deposited the other token without accepting the swap.
+    let other_fee = icrc1_fee(swap.other_token).await?;
+    let deposited = icrc1_balance_of(swap.other_token, swap.escrow_account().into()).await?;
+    match deposited - other_fee {
+        Some(amount) if !amount.is_zero() => {
+            icrc1_transfer(&swap, swap.counterparty.into(), amount, other_fee).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Moves the escrowed tokens to the `to` account. The transfer fee was paid by the maker when the
+/// tokens were locked.
+fn release_escrow(swap: &Swap, to: AccountInternal) -> Result<(), TxError> {
+    let escrow = swap.escrow_account();
+    let fee_to = TokenConfig::get_stable().fee_to;
+    transfer_internal(
+        &mut StableBalances,
+        escrow,
+        to,
+        swap.amount,
+        Tokens128::ZERO,
+        fee_to.into(),
+        FeeRatio::new(0.0),
+        None,
+    )?;
+    LedgerData::transfer(escrow, to, swap.amount, Tokens128::ZERO, None, ic::time());
+    Ok(())
+}
+
+async fn icrc1_fee(token: Principal) -> Result<Tokens128, TxError> {
+    ic::call::<_, (Tokens128,), _>(token, "icrc1_fee", ())
+        .await
+        .map(|(fee,)| fee)
+        .map_err(|(_, message)| TxError::OtherTokenError { message })
+}
+
+async fn icrc1_balance_of(token: Principal, account: Account) -> Result<Tokens128, TxError> {
+    ic::call::<_, (Tokens128,), _>(token, "icrc1_balance_of", (account,))
+        .await
+        .map(|(balance,)| balance)
+        .map_err(|(_, message)| TxError::OtherTokenError { message })
+}
+
+async fn icrc1_transfer(
+    swap: &Swap,
+    to: Account,
+    amount: Tokens128,
+    fee: Tokens128,
+) -> Result<(), TxError> {
+    let transfer = TransferArgs {
+        from_subaccount: Some(escrow_subaccount(swap.id)),
+        to,
+        amount,
+        fee: Some(fee),
+        memo: None,
+        created_at_time: None,
+    };
+
+    let result = ic::call::<_, (Result<u128, TransferError>,), _>(
+        swap.other_token,
+        "icrc1_transfer",
+        (transfer,),
+    )
+    .await
+    .map_err(|(_, message)| TxError::OtherTokenError { message })?;
+
+    result.0.map(|_| ()).map_err(|e| TxError::OtherTokenError {
+        message: format!("{e:?}"),
+    })
+}
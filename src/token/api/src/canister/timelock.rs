@@ -0,0 +1,113 @@
+//! Application of the timelocked configuration changes, see `state::timelock`.
+//!
+//! The timer task runs every `TIMELOCK_PERIOD` and applies the changes whose delay has passed, in
+//! the order they were proposed. A change is applied as if the proposer called the setter at
+//! that time, and is recorded in the admin log with the proposer as the caller.
+
+use std::time::Duration;
+
+use candid::Principal;
+use canister_sdk::ic_kit::ic;
+
+use super::{apply_update, CanisterUpdate};
+use crate::error::TxError;
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::config::{Timestamp, TokenConfig, Value};
+use crate::state::fee_recipients::FeeRecipients;
+use crate::state::timelock::{ConfigChange, PendingChange, ProposalId, Timelock};
+
+pub const TIMELOCK_PERIOD: Duration = Duration::from_secs(60);
+
+pub const MAX_CHANGES_PER_RUN: usize = 100;
+
+/// Starts the timer task applying the due changes. Timers are not preserved on upgrade, so this
+/// must be called both on init and post upgrade.
+#[cfg(target_family = "wasm")]
+pub fn start_timelock() {
+    ic_exports::ic_cdk_timers::set_timer_interval(TIMELOCK_PERIOD, || {
+        apply_due_changes(ic::time());
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn start_timelock() {}
+
+/// Applies the `change` at once, or stores it as a proposal if it's timelocked.
+pub(crate) fn update_or_propose(
+    caller: CheckedPrincipal<Owner>,
+    change: ConfigChange,
+) -> Result<(), TxError> {
+    let config = TokenConfig::get_stable();
+    match config.timelock_delay_nanos {
+        Some(delay) if change.is_timelocked(&config) => {
+            let pending = Timelock::propose(caller.inner(), change, delay, ic::time());
+            AdminLog::record(
+                caller.inner(),
+                AdminAction::ProposeChange { id: pending.id },
+                None,
+                describe(&pending),
+            );
+        }
+        _ => apply_change(caller.inner(), change),
+    }
+
+    Ok(())
+}
+
+/// Removes the pending change without applying it.
+pub(crate) fn cancel(caller: CheckedPrincipal<Owner>, id: ProposalId) -> Result<(), TxError> {
+    let pending = Timelock::cancel(id)?;
+    AdminLog::record(
+        caller.inner(),
+        AdminAction::CancelChange { id },
+        describe(&pending),
+        None,
+    );
+    Ok(())
+}
+
+/// Applies the changes due at the time `now`. Returns the number of the applied changes. If the
+/// ownership was renounced in the meantime, the changes are dropped instead, as the owner setters
+/// are disabled for good.
+pub fn apply_due_changes(now: Timestamp) -> usize {
+    let mut applied = 0;
+    for pending in Timelock::due(now, MAX_CHANGES_PER_RUN) {
+        Timelock::remove(pending.id);
+        if TokenConfig::get_stable().is_ownership_renounced() {
+            continue;
+        }
+
+        apply_change(pending.proposed_by, pending.change);
+        applied += 1;
+    }
+
+    applied
+}
+
+fn describe(pending: &PendingChange) -> Option<Value> {
+    Some(Value::Text(format!(
+        "{:?} at {}",
+        pending.change, pending.executable_at
+    )))
+}
+
+/// Applies the `change` as if the `caller` called the setter.
+fn apply_change(caller: Principal, change: ConfigChange) {
+    let update = match change {
+        ConfigChange::Fee(fee) => CanisterUpdate::Fee(fee),
+        ConfigChange::Owner(owner) => CanisterUpdate::Owner(owner),
+        ConfigChange::TimelockDelay(delay) => CanisterUpdate::TimelockDelay(delay),
+        ConfigChange::MintingAccount(account) => CanisterUpdate::MintingAccount(account),
+        ConfigChange::BurnPolicy(policy) => CanisterUpdate::BurnPolicy(policy),
+        ConfigChange::FeePolicy(policy) => CanisterUpdate::FeePolicy(policy),
+        // Stored apart from the token config, see `state::fee_recipients`.
+        ConfigChange::FeeRecipients(recipients) => {
+            FeeRecipients::set(caller, recipients)
+                .expect("fee recipients are validated on proposal");
+            return;
+        }
+    };
+
+    apply_update(caller, update);
+}
@@ -0,0 +1,180 @@
+//! Wrapping mode, backing the supply of the token 1:1 with an external ICRC-1 token.
+//!
+//! To deposit, the user transfers the underlying tokens plus the underlying transfer fee to the
+//! account of the token canister with the deposit subaccount of the user (see
+//! `get_wrapper_deposit_account`), and calls `deposit` with the amount. The amount is swept to
+//! the wrapper reserve (the default account of the token canister), and the same amount of the
+//! tokens is minted to the user once the sweep is executed.
+//!
+//! To withdraw, the user calls `withdraw`, which burns the tokens and sends the underlying tokens
+//! from the reserve, deducting the underlying transfer fee from the sent amount.
+//!
+//! All transfers of the underlying token are stored as operations in `IcrcWrapper` and can be
+//! retried with `retry_wrapper_operations` if the ledger call fails. The underlying ledger
+//! deduplicates the retries only within its transaction window, so operations pending for longer
+//! than that are rejected as too old and must be resolved by the owner.
+
+use candid::Principal;
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use super::is20_transactions::{burn, mint};
+use crate::account::{deposit_subaccount, Account, AccountInternal};
+use crate::error::{TransferError, TxError};
+use crate::state::config::TokenConfig;
+use crate::state::ledger::TransferArgs;
+use crate::state::wrapper::{
+    IcrcWrapper, WrapperOperation, WrapperOperationKind, WrapperOperationStatus,
+};
+
+/// Returns the account of the underlying token the `owner` must transfer the tokens to before
+/// calling `deposit`.
+pub fn deposit_account(owner: Principal) -> Account {
+    Account::new(ic::id(), Some(deposit_subaccount(owner)))
+}
+
+fn wrapped_token() -> Result<Principal, TxError> {
+    TokenConfig::get_stable()
+        .wrapped_token
+        .ok_or(TxError::WrapperNotConfigured)
+}
+
+/// Sweeps `amount` of the underlying tokens from the deposit account of the `caller` to the
+/// reserve and mints the same amount of the tokens to the `caller`.
+pub async fn deposit(caller: Principal, amount: Tokens128) -> Result<WrapperOperation, TxError> {
+    let ledger = wrapped_token()?;
+    if amount.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    let fee = icrc1_fee(ledger).await?;
+    let sweep = WrapperOperationKind::Deposit {
+        subaccount: deposit_subaccount(caller),
+    };
+    let operation = IcrcWrapper::add_operation(sweep, caller.into(), amount, fee, ic::time());
+    execute(ledger, &operation).await;
+
+    let operation = IcrcWrapper::get_operation(operation.id).unwrap_or(operation);
+    match operation.status {
+        WrapperOperationStatus::Rejected { reason } => Err(TxError::InvalidDeposit { reason }),
+        _ => Ok(operation),
+    }
+}
+
+/// Burns `amount` of the caller's tokens and sends `amount` minus the underlying transfer fee to
+/// `to`.
+pub async fn withdraw(
+    from: AccountInternal,
+    amount: Tokens128,
+    to: Account,
+) -> Result<WrapperOperation, TxError> {
+    let ledger = wrapped_token()?;
+    let fee = icrc1_fee(ledger).await?;
+    if amount <= fee {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    let tx_id = burn(from.owner, from, amount)?;
+
+    let withdrawal = WrapperOperationKind::Withdrawal { to };
+    let sent = (amount - fee).expect("amount is greater than the fee");
+    let operation = IcrcWrapper::add_operation(withdrawal, from.into(), sent, fee, ic::time());
+    IcrcWrapper::set_tx_id(operation.id, tx_id);
+    execute(ledger, &operation).await;
+
+    Ok(IcrcWrapper::get_operation(operation.id).unwrap_or(operation))
+}
+
+/// Retries all pending wrapper operations. Returns the operations which are still pending.
+pub async fn retry_operations() -> Result<Vec<WrapperOperation>, TxError> {
+    let ledger = wrapped_token()?;
+    for operation in IcrcWrapper::pending_operations() {
+        execute(ledger, &operation).await;
+    }
+
+    Ok(IcrcWrapper::pending_operations())
+}
+
+async fn execute(ledger: Principal, operation: &WrapperOperation) {
+    let (from_subaccount, to) = match &operation.kind {
+        WrapperOperationKind::Deposit { subaccount } => (Some(*subaccount), ic::id().into()),
+        WrapperOperationKind::Withdrawal { to } => (None, *to),
+    };
+
+    let args = TransferArgs {
+        from_subaccount,
+        to,
+        amount: operation.amount,
+        fee: Some(operation.fee),
+        memo: Some(operation.id.to_be_bytes().to_vec()),
+        created_at_time: Some(operation.created_at),
+    };
+
+    let result =
+        ic::call::<_, (Result<u128, TransferError>,), _>(ledger, "icrc1_transfer", (args,)).await;
+    let status = match result {
+        Ok((Ok(index),)) => WrapperOperationStatus::Completed { index },
+        // The operation was executed by one of the previous attempts.
+        Ok((Err(TransferError::Duplicate { duplicate_of }),)) => {
+            WrapperOperationStatus::Completed {
+                index: duplicate_of,
+            }
+        }
+        // Nothing was moved, so a rejected deposit can be made again with a new operation. The
+        // tokens of a withdrawal are already burned, so it is kept pending.
+        Ok((Err(e),)) if matches!(operation.kind, WrapperOperationKind::Deposit { .. }) => {
+            WrapperOperationStatus::Rejected {
+                reason: format!("{e:?}"),
+            }
+        }
+        Ok((Err(e),)) => return IcrcWrapper::record_failure(operation.id, format!("{e:?}")),
+        Err((_, message)) => return IcrcWrapper::record_failure(operation.id, message),
+    };
+
+    // The operation could be finished by a concurrent attempt while the ledger was called.
+    if !IcrcWrapper::get_operation(operation.id).map_or(false, |op| op.is_pending()) {
+        return;
+    }
+
+    if let (WrapperOperationKind::Deposit { .. }, WrapperOperationStatus::Completed { .. }) =
+        (&operation.kind, &status)
+    {
+        // If the mint fails, the operation stays pending, and the retry mints the tokens for the
+        // already executed sweep.
+        match mint(ic::id(), operation.account.into(), operation.amount) {
+            Ok(tx_id) => IcrcWrapper::set_tx_id(operation.id, tx_id),
+            Err(e) => return IcrcWrapper::record_failure(operation.id, e.to_string()),
+        }
+    }
+
+    IcrcWrapper::finish(operation.id, status);
+}
+
+async fn icrc1_fee(ledger: Principal) -> Result<Tokens128, TxError> {
+    ic::call::<_, (Tokens128,), _>(ledger, "icrc1_fee", ())
+        .await
+        .map(|(fee,)| fee)
+        .map_err(|(_, message)| TxError::WrappedTokenError { message })
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn deposit_accounts_are_unique() {
+        MockContext::new().inject();
+        assert_ne!(deposit_account(alice()), deposit_account(bob()));
+    }
+
+    #[test]
+    fn wrapper_requires_token() {
+        MockContext::new().inject();
+        TokenConfig::set_stable(TokenConfig::default());
+        assert_eq!(wrapped_token(), Err(TxError::WrapperNotConfigured));
+    }
+}
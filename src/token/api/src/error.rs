@@ -1,5 +1,9 @@
+use crate::account::Account;
 use crate::state::config::Timestamp;
-use candid::{CandidType, Deserialize};
+use crate::state::expiring_transfers::ExpiringTransferStatus;
+use crate::state::standing_orders::StandingOrderStatus;
+use crate::state::swaps::SwapStatus;
+use candid::{CandidType, Deserialize, Principal};
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use thiserror::Error;
 
@@ -23,10 +27,234 @@ pub enum TxError {
     SelfTransfer,
     #[error("amount overflow")]
     AmountOverflow,
+    #[error("invalid amount: {reason}")]
+    InvalidAmount { reason: String },
     #[error("account is not found")]
     AccountNotFound,
     #[error("no claimable tokens are on the requested subaccount")]
     NothingToClaim,
+    #[error("the limit of event subscribers is reached")]
+    SubscribersLimitReached,
+    #[error("event filter is too large, max number of accounts is {max_accounts}")]
+    FilterTooLarge { max_accounts: usize },
+    #[error("invalid metadata entry: {reason}")]
+    InvalidMetadataEntry { reason: String },
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("bad nonce, expected {expected}")]
+    BadNonce { expected: u64 },
+    #[error("account {account:?} is frozen")]
+    AccountFrozen { account: Account },
+    #[error("subscription is not found")]
+    SubscriptionNotFound,
+    #[error("subscription interval must be greater than zero")]
+    InvalidSubscriptionInterval,
+    #[error("amount exceeds the subscription limit of {max_amount}")]
+    AmountExceedsSubscription { max_amount: Tokens128 },
+    #[error("the next payment can be collected at {next_payment_at}")]
+    PaymentNotDue { next_payment_at: Timestamp },
+    #[error("swap is not found")]
+    SwapNotFound,
+    #[error("swap is {status:?}")]
+    InvalidSwapStatus { status: SwapStatus },
+    #[error("swap deadline must be in the future")]
+    InvalidDeadline,
+    #[error("swap deadline has passed")]
+    SwapExpired,
+    #[error("swap can be refunded only after {deadline}")]
+    SwapNotExpired { deadline: Timestamp },
+    #[error("swap deposit of the other token is insufficient: {deposited}")]
+    SwapNotFunded { deposited: Tokens128 },
+    #[error("other token call failed: {message}")]
+    OtherTokenError { message: String },
+    #[error("spender must differ from the owner and the anonymous principal")]
+    InvalidSpender,
+    #[error("allowance expiry must be in the future")]
+    InvalidAllowanceExpiry,
+    #[error("ICP bridge is not configured")]
+    BridgeNotConfigured,
+    #[error("deposit is already processed")]
+    DepositAlreadyProcessed,
+    #[error("invalid deposit: {reason}")]
+    InvalidDeposit { reason: String },
+    #[error("ICP ledger call failed: {message}")]
+    IcpLedgerError { message: String },
+    #[error("wrapped token is not configured")]
+    WrapperNotConfigured,
+    #[error("wrapped token call failed: {message}")]
+    WrappedTokenError { message: String },
+    #[error("transfers above {threshold} must be confirmed, use initiate_large_transfer")]
+    ConfirmationRequired { threshold: Tokens128 },
+    #[error("large transfer confirmation is not configured")]
+    LargeTransfersNotConfigured,
+    #[error("invalid large transfer policy: {reason}")]
+    InvalidLargeTransferPolicy { reason: String },
+    #[error("pending transfer is not found")]
+    PendingTransferNotFound,
+    #[error("pending transfer expired at {expired_at}")]
+    PendingTransferExpired { expired_at: Timestamp },
+    #[error("faucet limit exceeded, remaining today: {remaining}")]
+    FaucetLimitExceeded { remaining: Tokens128 },
+    #[error("faucet cooldown, next mint is allowed at {next_mint_at}")]
+    FaucetCooldown { next_mint_at: Timestamp },
+    #[error("invalid faucet policy: {reason}")]
+    InvalidFaucetPolicy { reason: String },
+    #[error("invalid transaction window: {reason}")]
+    InvalidTxWindow { reason: String },
+    #[error("payment request is not found")]
+    PaymentRequestNotFound,
+    #[error("payment request is already paid or cancelled")]
+    PaymentRequestNotPending,
+    #[error("payment request expired at {expired_at}")]
+    PaymentRequestExpired { expired_at: Timestamp },
+    #[error("ownership of the token is renounced")]
+    OwnershipRenounced,
+    #[error("ownership renouncement is not confirmed: {reason}")]
+    RenouncementNotConfirmed { reason: String },
+    #[error("invalid auction strategy: {reason}")]
+    InvalidAuctionStrategy { reason: String },
+    #[error("bid is below the minimum of {min_bid_cycles} cycles")]
+    BidBelowThreshold { min_bid_cycles: u64 },
+    #[error("bidder is blacklisted")]
+    BidderBlacklisted,
+    #[error("memo is too long, max length is {max_length}")]
+    MemoTooLong { max_length: usize },
+    #[error("transaction history was pruned")]
+    HistoryIncomplete,
+    #[error("decimals migration is in progress")]
+    DecimalsMigrationInProgress,
+    #[error("state migration to version {to_version} is in progress")]
+    StateMigrationInProgress { to_version: u32 },
+    #[error("invalid decimals")]
+    InvalidDecimals,
+    #[error("invalid account tag: {reason}")]
+    InvalidAccountTag { reason: String },
+    #[error("cycles transfer failed: {message}")]
+    CyclesTransferFailed { message: String },
+    #[error("another operation on the same state is in progress")]
+    OperationInProgress,
+    #[error("principal is already a controller")]
+    AlreadyController,
+    #[error("principal is not a controller")]
+    NotController,
+    #[error("the last controller cannot be removed")]
+    LastController,
+    #[error("canister cannot have more than {max} controllers")]
+    TooManyControllers { max: u64 },
+    #[error("management canister call failed: {message}")]
+    ManagementCallFailed { message: String },
+    #[error("standing order is not found")]
+    StandingOrderNotFound,
+    #[error("standing order interval must be at least {min_interval_nanos} nanoseconds")]
+    StandingOrderIntervalTooShort { min_interval_nanos: u64 },
+    #[error("a principal cannot pay more than {max} standing orders")]
+    TooManyStandingOrders { max: u64 },
+    #[error("standing order is {status:?}")]
+    InvalidStandingOrderStatus { status: StandingOrderStatus },
+    #[error("guardian is not set")]
+    GuardianNotSet,
+    #[error("guardian and new owner must differ from the owner and the anonymous principal")]
+    InvalidGuardian,
+    #[error("recovery delay must be at least {min_delay_nanos} nanoseconds")]
+    RecoveryDelayTooShort { min_delay_nanos: u64 },
+    #[error("recovery is already initiated and can be completed at {executable_at}")]
+    RecoveryInProgress { executable_at: Timestamp },
+    #[error("recovery is not found")]
+    RecoveryNotFound,
+    #[error("recovery can be completed at {executable_at}")]
+    RecoveryNotReady { executable_at: Timestamp },
+    #[error("invalid dust policy: {reason}")]
+    InvalidDustPolicy { reason: String },
+    #[error("the auction in progress has bids of {cycles} cycles")]
+    AuctionBidsPending { cycles: u64 },
+    #[error("timelock delay must be at most {max_delay_nanos} nanoseconds")]
+    InvalidTimelockDelay { max_delay_nanos: u64 },
+    #[error("proposal not found")]
+    ProposalNotFound,
+    #[error("invalid burn policy: {reason}")]
+    InvalidBurnPolicy { reason: String },
+    #[error("invalid merchant authorization: {reason}")]
+    InvalidMerchantAuthorization { reason: String },
+    #[error("merchant is not authorized by the payer")]
+    MerchantNotAuthorized,
+    #[error("amount exceeds the per transaction cap of {per_tx_cap}")]
+    AmountExceedsPerTxCap { per_tx_cap: Tokens128 },
+    #[error("monthly cap exceeded, remaining in this period: {remaining}")]
+    MonthlyCapExceeded { remaining: Tokens128 },
+    #[error("at most {max} principals can be excluded from the voting")]
+    TooManyVotingExclusions { max: u64 },
+    #[error("snapshot is not found")]
+    SnapshotNotFound,
+    #[error("upgrade chunk must be non-empty and at most {max_size} bytes")]
+    InvalidUpgradeChunk { max_size: u64 },
+    #[error("upgrade module cannot be larger than {max_size} bytes")]
+    UpgradeModuleTooLarge { max_size: u64 },
+    #[error("upgrade module is not uploaded")]
+    NoUpgradeModule,
+    #[error("hash of the uploaded module {actual:?} doesn't match the expected one")]
+    ModuleHashMismatch { actual: Vec<u8> },
+    #[error("invalid input limits: {reason}")]
+    InvalidInputLimits { reason: String },
+    #[error("batch cannot have more than {max_entries} entries")]
+    BatchTooLarge { max_entries: u32 },
+    #[error("the token has enough cycles and doesn't accept bids")]
+    BidsNotNeeded,
+    #[error("cycles balance {balance} is below the minimum of {min_cycles}")]
+    CyclesBelowMinimum { balance: u64, min_cycles: u64 },
+    #[error("invalid fee policy: {reason}")]
+    InvalidFeePolicy { reason: String },
+    #[error("invalid handover: {reason}")]
+    InvalidHandover { reason: String },
+    #[error("accounts of the principal hold {balance} tokens")]
+    AccountNotEmpty { balance: Tokens128 },
+    #[error("vesting period must be between 1 and {max_days} days")]
+    InvalidVestingPeriod { max_days: u32 },
+    #[error("invalid budget: {reason}")]
+    InvalidBudget { reason: String },
+    #[error("budget not found")]
+    BudgetNotFound,
+    #[error("budget limit exceeded, remaining: {remaining}")]
+    BudgetLimitExceeded { remaining: Tokens128 },
+    #[error("outbound message not found")]
+    OutboundMessageNotFound,
+    #[error("expiring transfer not found")]
+    ExpiringTransferNotFound,
+    #[error("expiring transfer is {status:?}")]
+    InvalidExpiringTransferStatus { status: ExpiringTransferStatus },
+    #[error("transfer expiry must be in the future and at most {max_nanos} ns away")]
+    InvalidTransferExpiry { max_nanos: u64 },
+    #[error("transfer expired at {expired_at}")]
+    TransferExpired { expired_at: Timestamp },
+    #[error("transfer can be reclaimed only after {expires_at}")]
+    TransferNotExpired { expires_at: Timestamp },
+    #[error("bids can't be delegated to the delegator itself")]
+    SelfDelegation,
+    #[error("bid delegation is not found")]
+    BidDelegationNotFound,
+    #[error("principal already bids on behalf of {delegator}")]
+    BidDelegateTaken { delegator: Principal },
+    #[error("invalid fee recipients: {reason}")]
+    InvalidFeeRecipients { reason: String },
+    #[error("export session is not found or expired")]
+    ExportSessionNotFound,
+    #[error("at most {max} export sessions can be open at the same time")]
+    TooManyExportSessions { max: u32 },
+    #[error("invalid init spec: {reason}")]
+    InvalidInitSpec { reason: String },
+    #[error("insufficient allowance: {allowance}")]
+    InsufficientAllowance { allowance: Tokens128 },
+    #[error("approval payload must be at most {max} bytes")]
+    ApprovalPayloadTooLarge { max: u32 },
+    #[error("approval notification is not found")]
+    ApprovalNotificationNotFound,
+    #[error("only failed approval notifications can be retried")]
+    ApprovalNotificationNotFailed,
+    #[error("balance of the account is not certified yet, retry later")]
+    BalanceNotCertifiedYet,
+    #[error("the permit deadline has passed")]
+    PermitExpired,
+    #[error("the call must attach at least {required} cycles")]
+    InsufficientCycles { required: u64 },
 }
 
 // This type is the exact error type from ICRC-1 standard. We use it as the return type for
@@ -61,3 +289,35 @@ impl From<TxError> for TransferError {
         }
     }
 }
+
+/// Truncates the error `message` to at most `max_len` bytes, keeping it valid UTF-8 by cutting at
+/// the nearest char boundary below the limit.
+pub fn truncate_message(message: &mut String, max_len: usize) {
+    let mut len = message.len().min(max_len);
+    while !message.is_char_boundary(len) {
+        len -= 1;
+    }
+    message.truncate(len);
+}
+
+#[cfg(test)]
+mod tests {
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn message_is_truncated_at_char_boundary() {
+        let mut message = "héllo".to_string();
+        truncate_message(&mut message, 2);
+        assert_eq!(message, "h");
+
+        let mut message = "héllo".to_string();
+        truncate_message(&mut message, 3);
+        assert_eq!(message, "hé");
+
+        let mut message = "hello".to_string();
+        truncate_message(&mut message, 10);
+        assert_eq!(message, "hello");
+    }
+}
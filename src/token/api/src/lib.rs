@@ -1,6 +1,8 @@
 #![cfg_attr(coverage_nightly, feature(no_coverage))]
 
 pub mod account;
+pub mod amount;
+pub mod bindings;
 pub mod canister;
 pub mod principal;
 pub mod state;
@@ -8,4 +10,6 @@ pub mod state;
 pub mod error;
 #[cfg(test)]
 pub mod mock;
+#[cfg(feature = "multi_token")]
+pub mod multi_token;
 pub mod tx_record;
@@ -57,7 +57,9 @@ impl PreUpdate for TokenCanisterMock {
     #[cfg_attr(coverage_nightly, no_coverage)]
     fn pre_update(&self, method_name: &str, method_type: ic_canister::MethodType) {
         #[cfg(feature = "auction")]
-        <Self as Auction>::canister_pre_update(self, method_name, method_type);
+        if crate::state::decimals::DecimalsMigration::check_not_running().is_ok() {
+            <Self as Auction>::canister_pre_update(self, method_name, method_type);
+        }
     }
 }
 
@@ -0,0 +1,514 @@
+//! Integration layer for the canisters embedding the IS20 accounting of several tokens.
+//!
+//! The IS20 token canister keeps the balances and the transaction history of a single token in the
+//! stable memories listed in `state::memory::MEMORY_LAYOUT`. A canister holding several tokens,
+//! e.g. a DEX with its LP tokens, can instead keep the accounting of all of them in one canister:
+//!
+//! * `MultiTokenBalances` stores the balances of all the tokens keyed by the `TokenId`, and
+//!   `MultiTokenBalances::token` returns the `Balances` of one token.
+//! * `MultiTokenLedger` stores the hash-chained transaction history of every token, and
+//!   `MultiTokenLedger::token` returns the `TransactionLog` of one token.
+//! * `transfer`, `mint` and `burn` work with any `Balances` and `TransactionLog`, so the same
+//!   operations are used with the single-token `StableBalances` and `LedgerData`.
+//!
+//! Both stores live in the memories of a `MemoryNamespace`, which never overlaps with the memories
+//! used by the IS20 state, so the embedder may use the IS20 state modules next to them.
+
+use std::borrow::Cow;
+
+use candid::{Decode, Encode};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableMultimap, Storable};
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::balances::AccountKey;
+use crate::state::ledger::{LedgerData, Memo};
+use crate::state::memory::MEMORY_LAYOUT;
+
+pub use crate::state::balances::{Balances, BalancesDelta};
+pub use crate::tx_record::{TxId, TxRecord};
+
+/// Id of a token, chosen by the embedder.
+pub type TokenId = u32;
+
+/// Append-only transaction history of a token.
+pub trait TransactionLog {
+    /// Number of the records ever appended, which is the id of the next record.
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the record with the `id`, if it's stored.
+    fn get(&self, id: TxId) -> Option<TxRecord>;
+
+    /// Appends the `records`, assigning them consecutive ids. Returns the assigned ids.
+    fn append(&mut self, records: Vec<TxRecord>) -> Vec<TxId>;
+}
+
+impl TransactionLog for LedgerData {
+    fn len(&self) -> u64 {
+        LedgerData::len()
+    }
+
+    fn get(&self, id: TxId) -> Option<TxRecord> {
+        LedgerData::get(id)
+    }
+
+    fn append(&mut self, records: Vec<TxRecord>) -> Vec<TxId> {
+        LedgerData::append(records)
+    }
+}
+
+/// Stable memories used by the multi-token stores.
+///
+/// The IS20 state takes the memories from 0 upward, see `MEMORY_LAYOUT`, and new versions add
+/// their memories right after the last one. Embedders should pick the namespace from the end of
+/// the id range, so it stays free after the upgrade of the IS20 code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryNamespace {
+    first: u8,
+}
+
+impl MemoryNamespace {
+    /// Number of the memories in a namespace.
+    pub const SIZE: u8 = 3;
+
+    /// Creates the namespace of the memories `first..first + SIZE`. Returns `None` if any of them
+    /// is used by the IS20 state or is out of the range of the `MemoryManager`.
+    pub fn new(first: u8) -> Option<Self> {
+        let last = first.checked_add(Self::SIZE - 1)?;
+        // The id 255 is reserved by the `MemoryManager` for the unallocated buckets.
+        if last == u8::MAX {
+            return None;
+        }
+
+        let collides = MEMORY_LAYOUT
+            .iter()
+            .any(|(_, id)| (first..=last).contains(id));
+        (!collides).then_some(Self { first })
+    }
+
+    fn balances(&self) -> MemoryId {
+        MemoryId::new(self.first)
+    }
+
+    fn ledger(&self) -> MemoryId {
+        MemoryId::new(self.first + 1)
+    }
+
+    fn ledger_len(&self) -> MemoryId {
+        MemoryId::new(self.first + 2)
+    }
+}
+
+/// Balances of all the tokens.
+pub struct MultiTokenBalances {
+    map: StableMultimap<TokenKey, AccountKey, u128>,
+}
+
+impl MultiTokenBalances {
+    pub fn new(namespace: MemoryNamespace) -> Self {
+        Self {
+            map: StableMultimap::new(namespace.balances()),
+        }
+    }
+
+    /// Balances of the `token`.
+    pub fn token(&mut self, token: TokenId) -> TokenBalances<'_> {
+        TokenBalances {
+            token: TokenKey(token),
+            map: &mut self.map,
+        }
+    }
+}
+
+/// Balances of one token stored in the `MultiTokenBalances`.
+pub struct TokenBalances<'a> {
+    token: TokenKey,
+    map: &'a mut StableMultimap<TokenKey, AccountKey, u128>,
+}
+
+impl Balances for TokenBalances<'_> {
+    fn insert(&mut self, account: AccountInternal, token: Tokens128) {
+        self.map
+            .insert(&self.token, &AccountKey::from(account), &token.amount);
+    }
+
+    fn get(&self, account: &AccountInternal) -> Option<Tokens128> {
+        self.map
+            .get(&self.token, &AccountKey::from(*account))
+            .map(Tokens128::from)
+    }
+
+    fn remove(&mut self, account: &AccountInternal) -> Option<Tokens128> {
+        self.map
+            .remove(&self.token, &AccountKey::from(*account))
+            .map(Tokens128::from)
+    }
+
+    fn list_balances(&self, start: usize, limit: usize) -> Vec<(AccountInternal, Tokens128)> {
+        self.map
+            .range(&self.token)
+            .skip(start)
+            .take(limit)
+            .map(|(account, amount)| (account.account(), Tokens128::from(amount)))
+            .collect()
+    }
+}
+
+/// Transaction histories of all the tokens.
+pub struct MultiTokenLedger {
+    records: StableMultimap<TokenKey, TxKey, StorableTxRecord>,
+    lengths: StableBTreeMap<TokenKey, u64>,
+}
+
+impl MultiTokenLedger {
+    pub fn new(namespace: MemoryNamespace) -> Self {
+        Self {
+            records: StableMultimap::new(namespace.ledger()),
+            lengths: StableBTreeMap::new(namespace.ledger_len()),
+        }
+    }
+
+    /// Transaction history of the `token`.
+    pub fn token(&mut self, token: TokenId) -> TokenLedger<'_> {
+        TokenLedger {
+            token: TokenKey(token),
+            ledger: self,
+        }
+    }
+}
+
+/// Transaction history of one token stored in the `MultiTokenLedger`.
+pub struct TokenLedger<'a> {
+    token: TokenKey,
+    ledger: &'a mut MultiTokenLedger,
+}
+
+impl TransactionLog for TokenLedger<'_> {
+    fn len(&self) -> u64 {
+        self.ledger.lengths.get(&self.token).unwrap_or_default()
+    }
+
+    fn get(&self, id: TxId) -> Option<TxRecord> {
+        self.ledger
+            .records
+            .get(&self.token, &TxKey(id))
+            .map(|record| record.0)
+    }
+
+    /// Every record is chained to the previous record of the same token by its hash, as in the
+    /// IS20 ledger.
+    fn append(&mut self, records: Vec<TxRecord>) -> Vec<TxId> {
+        let first_id = self.len();
+        let mut tip_hash = first_id
+            .checked_sub(1)
+            .and_then(|id| self.get(id))
+            .and_then(|record| record.hash)
+            .unwrap_or_default();
+
+        let mut ids = Vec::with_capacity(records.len());
+        for (mut record, id) in records.into_iter().zip(first_id..) {
+            record.index = id;
+            let hash = record.compute_hash(&tip_hash);
+            record.parent_hash = Some(tip_hash);
+            record.hash = Some(hash);
+            tip_hash = hash;
+
+            self.ledger
+                .records
+                .insert(&self.token, &TxKey(id), &StorableTxRecord(record));
+            ids.push(id);
+        }
+
+        self.ledger
+            .lengths
+            .insert(self.token, first_id + ids.len() as u64);
+        ids
+    }
+}
+
+/// Moves `amount` from the `from` account to the `to` account, paying the `fee` to the `fee_to`
+/// account, and records the transfer. Nothing is changed if the operation fails.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer(
+    balances: &mut impl Balances,
+    log: &mut impl TransactionLog,
+    from: AccountInternal,
+    to: AccountInternal,
+    amount: Tokens128,
+    fee: Tokens128,
+    fee_to: AccountInternal,
+    memo: Option<Memo>,
+) -> Result<TxId, TxError> {
+    if amount.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    if from == to {
+        return Err(TxError::SelfTransfer);
+    }
+
+    let mut updates = BalancesDelta::load(balances, [from, to, fee_to]);
+    let balance = updates.balance_of(&from);
+    let amount_with_fee = (amount + fee).ok_or(TxError::InsufficientFunds { balance })?;
+    let from_balance = (balance - amount_with_fee).ok_or(TxError::InsufficientFunds { balance })?;
+    updates.insert(from, from_balance);
+
+    let to_balance = (updates.balance_of(&to) + amount).ok_or(TxError::AmountOverflow)?;
+    updates.insert(to, to_balance);
+
+    let fee_to_balance = (updates.balance_of(&fee_to) + fee).ok_or(TxError::AmountOverflow)?;
+    updates.insert(fee_to, fee_to_balance);
+
+    balances.apply_delta(updates);
+    let record = TxRecord::transfer(0, from, to, amount, fee, memo, ic::time());
+    Ok(log.append(vec![record])[0])
+}
+
+/// Creates `amount` new tokens on the `to` account and records the minting.
+pub fn mint(
+    balances: &mut impl Balances,
+    log: &mut impl TransactionLog,
+    caller: AccountInternal,
+    to: AccountInternal,
+    amount: Tokens128,
+) -> Result<TxId, TxError> {
+    if amount.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    (balances.total_supply() + amount).ok_or(TxError::AmountOverflow)?;
+    let balance = (balances.balance_of(&to) + amount).ok_or(TxError::AmountOverflow)?;
+    balances.insert(to, balance);
+
+    Ok(log.append(vec![TxRecord::mint(0, caller, to, amount)])[0])
+}
+
+/// Destroys `amount` tokens of the `from` account and records the burning.
+pub fn burn(
+    balances: &mut impl Balances,
+    log: &mut impl TransactionLog,
+    caller: AccountInternal,
+    from: AccountInternal,
+    amount: Tokens128,
+) -> Result<TxId, TxError> {
+    if amount.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    let balance = balances.balance_of(&from);
+    let remaining = (balance - amount).ok_or(TxError::InsufficientFunds { balance })?;
+    if remaining.is_zero() {
+        balances.remove(&from);
+    } else {
+        balances.insert(from, remaining);
+    }
+
+    Ok(log.append(vec![TxRecord::burn(0, caller, from, amount)])[0])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct TokenKey(TokenId);
+
+impl Storable for TokenKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.to_be_bytes().to_vec().into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(TokenId::from_be_bytes(
+            bytes.as_ref().try_into().expect("invalid token key length"),
+        ))
+    }
+}
+
+impl BoundedStorable for TokenKey {
+    const MAX_SIZE: u32 = 4;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+/// Big-endian transaction id, so the records of a token are ordered by the id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct TxKey(TxId);
+
+impl Storable for TxKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.to_be_bytes().to_vec().into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(TxId::from_be_bytes(
+            bytes
+                .as_ref()
+                .try_into()
+                .expect("invalid transaction key length"),
+        ))
+    }
+}
+
+impl BoundedStorable for TxKey {
+    const MAX_SIZE: u32 = 8;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+struct StorableTxRecord(TxRecord);
+
+impl Storable for StorableTxRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(&self.0)
+            .expect("failed to encode transaction record")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Decode!(&bytes, TxRecord).expect("failed to decode transaction record"))
+    }
+}
+
+impl BoundedStorable for StorableTxRecord {
+    // Three principals, two subaccounts, two amounts, a timestamp, a memo of at most
+    // `MAX_MEMO_LENGTH` bytes and two hashes with the type table.
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn namespace() -> MemoryNamespace {
+        MemoryNamespace::new(200).unwrap()
+    }
+
+    #[test]
+    fn namespace_does_not_overlap_token_state() {
+        assert_eq!(MemoryNamespace::new(0), None);
+        assert_eq!(MemoryNamespace::new(MEMORY_LAYOUT.len() as u8 - 2), None);
+        assert_eq!(MemoryNamespace::new(253), None);
+        assert!(MemoryNamespace::new(MEMORY_LAYOUT.len() as u8).is_some());
+        assert!(MemoryNamespace::new(252).is_some());
+    }
+
+    #[test]
+    fn tokens_are_isolated() {
+        MockContext::new().inject();
+        let mut balances = MultiTokenBalances::new(namespace());
+        let mut ledger = MultiTokenLedger::new(namespace());
+
+        let owner = AccountInternal::from(alice());
+        mint(
+            &mut balances.token(1),
+            &mut ledger.token(1),
+            owner,
+            owner,
+            100.into(),
+        )
+        .unwrap();
+        mint(
+            &mut balances.token(2),
+            &mut ledger.token(2),
+            owner,
+            owner,
+            5.into(),
+        )
+        .unwrap();
+
+        assert_eq!(balances.token(1).balance_of(&owner), 100.into());
+        assert_eq!(balances.token(2).balance_of(&owner), 5.into());
+        assert_eq!(balances.token(3).balance_of(&owner), 0.into());
+        assert_eq!(balances.token(1).total_supply(), 100.into());
+        assert_eq!(ledger.token(1).len(), 1);
+        assert_eq!(ledger.token(2).len(), 1);
+        assert!(ledger.token(3).is_empty());
+
+        assert_eq!(
+            burn(
+                &mut balances.token(2),
+                &mut ledger.token(2),
+                owner,
+                owner,
+                10.into()
+            ),
+            Err(TxError::InsufficientFunds { balance: 5.into() })
+        );
+        burn(
+            &mut balances.token(2),
+            &mut ledger.token(2),
+            owner,
+            owner,
+            5.into(),
+        )
+        .unwrap();
+        assert_eq!(balances.token(2).get(&owner), None);
+        assert_eq!(balances.token(1).balance_of(&owner), 100.into());
+    }
+
+    #[test]
+    fn transfer_is_recorded_in_token_ledger() {
+        MockContext::new().inject();
+        let mut balances = MultiTokenBalances::new(namespace());
+        let mut ledger = MultiTokenLedger::new(namespace());
+
+        let alice = AccountInternal::from(alice());
+        let bob = AccountInternal::from(bob());
+        let fee_to = AccountInternal::from(john());
+        mint(
+            &mut balances.token(7),
+            &mut ledger.token(7),
+            alice,
+            alice,
+            100.into(),
+        )
+        .unwrap();
+
+        let id = transfer(
+            &mut balances.token(7),
+            &mut ledger.token(7),
+            alice,
+            bob,
+            40.into(),
+            2.into(),
+            fee_to,
+            None,
+        )
+        .unwrap();
+        assert_eq!(id, 1);
+
+        let mut token = balances.token(7);
+        assert_eq!(token.balance_of(&alice), 58.into());
+        assert_eq!(token.balance_of(&bob), 40.into());
+        assert_eq!(token.balance_of(&fee_to), 2.into());
+
+        assert_eq!(
+            transfer(
+                &mut token,
+                &mut ledger.token(7),
+                bob,
+                alice,
+                41.into(),
+                0.into(),
+                fee_to,
+                None,
+            ),
+            Err(TxError::InsufficientFunds { balance: 40.into() })
+        );
+        assert_eq!(token.balance_of(&bob), 40.into());
+
+        let log = ledger.token(7);
+        let minted = log.get(0).unwrap();
+        let transferred = log.get(1).unwrap();
+        assert_eq!(transferred.amount, 40.into());
+        assert_eq!(transferred.parent_hash, minted.hash);
+        assert!(transferred.verify_hash(&minted.hash.unwrap()));
+        assert!(log.get(2).is_none());
+    }
+}
@@ -21,12 +21,26 @@ impl<T> CheckedPrincipal<T> {
 impl CheckedPrincipal<Owner> {
     pub fn owner(config: &TokenConfig) -> Result<Self, TxError> {
         let caller = ic::caller();
-        if caller == config.owner {
+        if config.is_ownership_renounced() {
+            Err(TxError::OwnershipRenounced)
+        } else if caller == config.owner {
             Ok(Self(caller, Owner))
         } else {
             Err(TxError::Unauthorized)
         }
     }
+
+    /// Checks that the caller is the deployer of the token, calling on behalf of the `owner`, who
+    /// authorized the call with the deployer.
+    pub fn owner_via_deployer(config: &TokenConfig, owner: Principal) -> Result<Self, TxError> {
+        if config.is_ownership_renounced() {
+            Err(TxError::OwnershipRenounced)
+        } else if config.deployer == Some(ic::caller()) && owner == config.owner {
+            Ok(Self(owner, Owner))
+        } else {
+            Err(TxError::Unauthorized)
+        }
+    }
 }
 
 impl CheckedPrincipal<TestNet> {
@@ -1,3 +1,56 @@
+pub type StateVersion = u32;
+
+/// Version of the stable state layout of the token. Must be increased on every layout change the
+/// previous versions cannot be upgraded from without a migration, together with adding the
+/// migration to `migrations::MIGRATIONS`. The factory checks it before upgrading the deployed
+/// tokens.
+pub const STATE_VERSION: StateVersion = 1;
+
+pub mod account_ids;
+pub mod account_tags;
+pub mod admin_log;
+pub mod allowances;
+#[cfg(feature = "auction")]
+pub mod auction_history;
+#[cfg(feature = "auction")]
+pub mod auction_policy;
 pub mod balances;
+#[cfg(feature = "auction")]
+pub mod bid_delegates;
+pub mod budgets;
+pub mod certified_balances;
 pub mod config;
+#[cfg(feature = "auction")]
+pub mod cycle_accounting;
+pub mod decimals;
+pub mod dedup;
+pub mod delivery;
+pub mod dust;
+pub mod expiring_transfers;
+pub mod export_sessions;
+pub mod faucet;
+pub mod fee_recipients;
+pub mod fees;
+pub mod frozen;
+pub mod guard;
+pub mod guardians;
+pub mod icp_bridge;
+pub mod invariants;
+pub mod large_transfers;
 pub mod ledger;
+pub mod memory;
+pub mod merchants;
+pub mod metrics;
+pub mod migrations;
+pub mod nonces;
+pub mod outbound;
+pub mod payment_requests;
+pub mod payment_subscriptions;
+pub mod snapshots;
+pub mod standing_orders;
+pub mod subscriptions;
+pub mod swaps;
+pub mod timelock;
+pub mod upgrade_chunks;
+pub mod vesting;
+pub mod wrapper;
@@ -0,0 +1,140 @@
+//! ICP ledger account identifiers of the token accounts, for the exchanges which address the
+//! deposits by account identifiers only.
+//!
+//! An account identifier is a hash of the account, so it can be resolved back only if the account
+//! was seen by the token. The accounts of the claimers are recorded on every claim.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{Decode, Encode, Principal};
+use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::state::icp_bridge::IcpAccountId;
+
+const ACCOUNT_IDS_MEMORY_ID: MemoryId = MemoryId::new(28);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct AccountIdKey(IcpAccountId);
+
+impl Storable for AccountIdKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_slice().into()
+    }
+
+    /// Expected `bytes.len() == 32`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let mut buf = IcpAccountId::default();
+        buf.copy_from_slice(&bytes);
+        Self(buf)
+    }
+}
+
+impl BoundedStorable for AccountIdKey {
+    const MAX_SIZE: u32 = 32;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+struct StorableAccount(Account);
+
+impl Storable for StorableAccount {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(&self.0).expect("failed to encode account").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Decode!(&bytes, Account).expect("failed to decode account"))
+    }
+}
+
+impl BoundedStorable for StorableAccount {
+    // A principal and a subaccount with the type table.
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct AccountIds;
+
+impl AccountIds {
+    /// Returns the account identifier of the `account`, as the ICP ledger computes it.
+    pub fn of(account: AccountInternal) -> IcpAccountId {
+        AccountIdentifier::new(
+            account.owner.into(),
+            Some(SubaccountIdentifier(account.subaccount)),
+        )
+        .to_address()
+    }
+
+    /// Stores the account identifier of the `account`, so it can be resolved later.
+    pub fn record(account: AccountInternal) {
+        ACCOUNT_IDS.with(|map| {
+            map.borrow_mut().insert(
+                AccountIdKey(Self::of(account)),
+                StorableAccount(account.into()),
+            )
+        });
+    }
+
+    /// Returns the account of the `account_id`, if it was recorded.
+    pub fn resolve(account_id: IcpAccountId) -> Option<Account> {
+        ACCOUNT_IDS.with(|map| {
+            map.borrow()
+                .get(&AccountIdKey(account_id))
+                .map(|account| account.0)
+        })
+    }
+
+    /// Removes the recorded accounts of the `owner`. Returns the number of the removed ones.
+    pub fn remove_owner(owner: Principal) -> usize {
+        ACCOUNT_IDS.with(|map| {
+            let mut map = map.borrow_mut();
+            let keys = map
+                .iter()
+                .filter(|(_, account)| account.0.owner == owner)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>();
+            for key in &keys {
+                map.remove(key);
+            }
+
+            keys.len()
+        })
+    }
+
+    pub fn clear() {
+        ACCOUNT_IDS.with(|map| map.borrow_mut().clear());
+    }
+}
+
+thread_local! {
+    static ACCOUNT_IDS: RefCell<StableBTreeMap<AccountIdKey, StorableAccount>> =
+        RefCell::new(StableBTreeMap::new(ACCOUNT_IDS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn resolve_recorded_accounts() {
+        MockContext::new().inject();
+        AccountIds::clear();
+
+        let account = AccountInternal::new(alice(), Some([1; 32]));
+        assert_ne!(AccountIds::of(account), AccountIds::of(alice().into()));
+        assert_eq!(AccountIds::resolve(AccountIds::of(account)), None);
+
+        AccountIds::record(account);
+        assert_eq!(
+            AccountIds::resolve(AccountIds::of(account)),
+            Some(account.into())
+        );
+        assert_eq!(AccountIds::resolve(AccountIds::of(bob().into())), None);
+    }
+}
@@ -0,0 +1,208 @@
+//! Private labels the holders attach to their own subaccounts.
+//!
+//! The tags are only visible to the owner of the subaccounts, and are limited in size and number
+//! so a principal cannot grow the stable memory without bound.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableMultimap, Storable};
+
+use crate::account::Subaccount;
+use crate::error::TxError;
+use crate::state::balances::{Balances, PrincipalKey, StableBalances, SubaccountKey};
+
+/// Maximum length of a tag in bytes.
+pub const MAX_TAG_LENGTH: usize = 64;
+/// Maximum number of the tagged subaccounts of one principal.
+pub const MAX_TAGS_PER_PRINCIPAL: usize = 100;
+
+const ACCOUNT_TAGS_MEMORY_ID: MemoryId = MemoryId::new(20);
+
+/// Subaccount of the caller as returned by `list_tagged_subaccounts`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TaggedSubaccount {
+    pub balance: Tokens128,
+    pub tag: Option<String>,
+}
+
+struct StorableTag(String);
+
+impl Storable for StorableTag {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_bytes().into()
+    }
+
+    /// Expected `bytes` is a valid UTF-8 string.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(String::from_utf8(bytes.into_owned()).expect("account tag is not valid UTF-8"))
+    }
+}
+
+impl BoundedStorable for StorableTag {
+    const MAX_SIZE: u32 = MAX_TAG_LENGTH as _;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct AccountTags;
+
+impl AccountTags {
+    /// Sets the tag of the `subaccount` of the `owner`, or removes it if the `label` is empty.
+    pub fn set(owner: Principal, subaccount: Subaccount, label: String) -> Result<(), TxError> {
+        let principal_key = PrincipalKey::from(owner);
+        let subaccount_key = SubaccountKey(subaccount);
+
+        if label.is_empty() {
+            TAGS.with(|map| map.borrow_mut().remove(&principal_key, &subaccount_key));
+            return Ok(());
+        }
+
+        if label.len() > MAX_TAG_LENGTH {
+            return Err(TxError::InvalidAccountTag {
+                reason: format!("tag is longer than {MAX_TAG_LENGTH} bytes"),
+            });
+        }
+
+        if label.chars().any(char::is_control) {
+            return Err(TxError::InvalidAccountTag {
+                reason: "tag contains control characters".into(),
+            });
+        }
+
+        TAGS.with(|map| {
+            let mut map = map.borrow_mut();
+            let is_new = map.get(&principal_key, &subaccount_key).is_none();
+            if is_new && map.range(&principal_key).count() >= MAX_TAGS_PER_PRINCIPAL {
+                return Err(TxError::InvalidAccountTag {
+                    reason: format!("at most {MAX_TAGS_PER_PRINCIPAL} subaccounts can be tagged"),
+                });
+            }
+
+            map.insert(&principal_key, &subaccount_key, &StorableTag(label));
+            Ok(())
+        })
+    }
+
+    pub fn get(owner: Principal) -> HashMap<Subaccount, String> {
+        TAGS.with(|map| {
+            map.borrow()
+                .range(&PrincipalKey::from(owner))
+                .map(|(subaccount, tag)| (subaccount.0, tag.0))
+                .collect()
+        })
+    }
+
+    /// Subaccounts of the `owner` which either hold tokens or are tagged.
+    pub fn list_subaccounts(owner: Principal) -> HashMap<Subaccount, TaggedSubaccount> {
+        let mut tags = Self::get(owner);
+        let mut subaccounts = StableBalances
+            .get_subaccounts(owner)
+            .into_iter()
+            .map(|(subaccount, balance)| {
+                let tag = tags.remove(&subaccount);
+                (subaccount, TaggedSubaccount { balance, tag })
+            })
+            .collect::<HashMap<_, _>>();
+        subaccounts.extend(tags.into_iter().map(|(subaccount, tag)| {
+            (
+                subaccount,
+                TaggedSubaccount {
+                    balance: Tokens128::ZERO,
+                    tag: Some(tag),
+                },
+            )
+        }));
+
+        subaccounts
+    }
+
+    /// Removes the tags of the `owner`. Returns the number of the removed tags.
+    pub fn remove_owner(owner: Principal) -> usize {
+        let principal_key = PrincipalKey::from(owner);
+        TAGS.with(|map| {
+            let mut map = map.borrow_mut();
+            let subaccounts = map
+                .range(&principal_key)
+                .map(|(subaccount, _)| subaccount)
+                .collect::<Vec<_>>();
+            for subaccount in &subaccounts {
+                map.remove(&principal_key, subaccount);
+            }
+
+            subaccounts.len()
+        })
+    }
+
+    pub fn clear() {
+        TAGS.with(|map| {
+            let mut map = map.borrow_mut();
+            let keys = map
+                .iter()
+                .map(|(principal, subaccount, _)| (principal, subaccount))
+                .collect::<Vec<_>>();
+            for (principal, subaccount) in keys {
+                map.remove(&principal, &subaccount);
+            }
+        });
+    }
+}
+
+thread_local! {
+    static TAGS: RefCell<StableMultimap<PrincipalKey, SubaccountKey, StorableTag>> =
+        RefCell::new(StableMultimap::new(ACCOUNT_TAGS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::account::AccountInternal;
+
+    #[test]
+    fn account_tags() {
+        MockContext::new().inject();
+        AccountTags::clear();
+        StableBalances.clear();
+        StableBalances.insert(AccountInternal::new(alice(), Some([1; 32])), 100.into());
+
+        AccountTags::set(alice(), [1; 32], "savings".into()).unwrap();
+        AccountTags::set(alice(), [2; 32], "empty".into()).unwrap();
+        assert_eq!(AccountTags::get(alice()).len(), 2);
+        assert!(AccountTags::get(bob()).is_empty());
+
+        let subaccounts = AccountTags::list_subaccounts(alice());
+        assert_eq!(
+            subaccounts[&[1; 32]],
+            TaggedSubaccount {
+                balance: 100.into(),
+                tag: Some("savings".into()),
+            }
+        );
+        assert_eq!(subaccounts[&[2; 32]].balance, Tokens128::ZERO);
+
+        AccountTags::set(alice(), [2; 32], String::new()).unwrap();
+        assert_eq!(AccountTags::get(alice()).len(), 1);
+    }
+
+    #[test]
+    fn account_tag_limits() {
+        MockContext::new().inject();
+        AccountTags::clear();
+
+        let too_long = "a".repeat(MAX_TAG_LENGTH + 1);
+        assert!(AccountTags::set(alice(), [0; 32], too_long).is_err());
+        assert!(AccountTags::set(alice(), [0; 32], "a\nb".into()).is_err());
+
+        for i in 0..MAX_TAGS_PER_PRINCIPAL {
+            AccountTags::set(alice(), [i as u8; 32], "tag".into()).unwrap();
+        }
+        assert!(AccountTags::set(alice(), [255; 32], "tag".into()).is_err());
+        AccountTags::set(alice(), [0; 32], "renamed".into()).unwrap();
+    }
+}
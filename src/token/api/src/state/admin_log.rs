@@ -0,0 +1,192 @@
+//! Audit log of the administrative actions.
+//!
+//! Every change of the token configuration and of the freeze state of the accounts made by the
+//! owner is recorded with the caller, the time and the values before and after the change. The
+//! steps of the account recoveries by the guardians are recorded in the separate recovery log, see
+//! `state::guardians`.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+
+use crate::account::Account;
+use crate::state::config::{Timestamp, Value};
+use crate::state::outbound::MessageId;
+use crate::state::snapshots::SnapshotId;
+use crate::state::timelock::ProposalId;
+
+/// Values larger than this are logged as their SHA-256 hash.
+const MAX_LOGGED_VALUE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum AdminAction {
+    SetName,
+    SetSymbol,
+    SetFee,
+    SetFeeTo,
+    SetOwner,
+    RenounceOwnership { keep_admin_rights: bool },
+    SetMinCycles,
+    SetMintingAccount,
+    SetHistoryRetention,
+    PruneTransactions,
+    CompactLedger,
+    SetIcpLedger,
+    SetWrappedToken,
+    SetLargeTransferPolicy,
+    SetFaucetPolicy,
+    SetDustPolicy,
+    SetTxWindow,
+    SetAuctionStrategy,
+    SetAuctionRetention,
+    SetAuctionVesting,
+    SetMinBidCycles,
+    BlacklistBidder { bidder: Principal },
+    UnblacklistBidder { bidder: Principal },
+    SetMemoIndex,
+    RepairSupply,
+    RescaleDecimals,
+    SetMetadataEntry { key: String },
+    RemoveMetadataEntry { key: String },
+    SetLocalizedMetadata { locale: String },
+    FreezeAccount { account: Account },
+    UnfreezeAccount { account: Account },
+    AddController { controller: Principal },
+    RemoveController { controller: Principal },
+    SetTimelockDelay,
+    SetBurnPolicy,
+    SetFeePolicy,
+    SetVotingExclusions,
+    TakeSnapshot { id: SnapshotId },
+    ClearUpgradeChunks,
+    SetInputLimits,
+    CommitUpgrade,
+    ProposeChange { id: ProposalId },
+    CancelChange { id: ProposalId },
+    Handover { new_owner: Principal },
+    PurgeAccountData { principal: Principal },
+    RetryOutboundMessage { id: MessageId },
+    DropOutboundMessage { id: MessageId },
+    ResetEndpointStats,
+    SetFeeRecipients,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct AdminLogEntry {
+    pub caller: Principal,
+    pub timestamp: Timestamp,
+    pub action: AdminAction,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+}
+
+impl Storable for AdminLogEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode admin log entry")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode admin log entry")
+    }
+}
+
+impl BoundedStorable for AdminLogEntry {
+    // Two values of `MAX_LOGGED_VALUE_SIZE`, a metadata key or an account, and the type table.
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct AdminLog;
+
+impl AdminLog {
+    pub fn record(
+        caller: Principal,
+        action: AdminAction,
+        old_value: Option<Value>,
+        new_value: Option<Value>,
+    ) {
+        let entry = AdminLogEntry {
+            caller,
+            timestamp: canister_sdk::ic_kit::ic::time(),
+            action,
+            old_value: old_value.map(truncate_value),
+            new_value: new_value.map(truncate_value),
+        };
+
+        ADMIN_LOG.with(|log| {
+            let mut log = log.borrow_mut();
+            let index = log.len();
+            log.insert(index, entry);
+        });
+    }
+
+    /// Returns `count` entries of the log starting from the `offset`.
+    pub fn get(offset: u64, count: usize) -> Vec<AdminLogEntry> {
+        ADMIN_LOG.with(|log| {
+            log.borrow()
+                .iter()
+                .skip(offset as usize)
+                .take(count)
+                .map(|(_, entry)| entry)
+                .collect()
+        })
+    }
+
+    pub fn len() -> u64 {
+        ADMIN_LOG.with(|log| log.borrow().len())
+    }
+
+    pub fn clear() {
+        ADMIN_LOG.with(|log| log.borrow_mut().clear());
+    }
+}
+
+fn truncate_value(value: Value) -> Value {
+    let hash = |bytes: &[u8]| Value::Text(format!("sha256:{:x}", Sha256::digest(bytes)));
+    match value {
+        Value::Text(text) if text.len() > MAX_LOGGED_VALUE_SIZE => hash(text.as_bytes()),
+        Value::Blob(blob) if blob.len() > MAX_LOGGED_VALUE_SIZE => hash(&blob),
+        value => value,
+    }
+}
+
+const ADMIN_LOG_MEMORY_ID: MemoryId = MemoryId::new(7);
+
+thread_local! {
+    static ADMIN_LOG: RefCell<StableBTreeMap<u64, AdminLogEntry>> =
+        RefCell::new(StableBTreeMap::new(ADMIN_LOG_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::alice;
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn large_values_are_hashed() {
+        MockContext::new().inject();
+        AdminLog::clear();
+
+        let logo = Value::Text("a".repeat(MAX_LOGGED_VALUE_SIZE + 1));
+        AdminLog::record(
+            alice(),
+            AdminAction::SetMetadataEntry {
+                key: "icrc1:logo".into(),
+            },
+            None,
+            Some(logo),
+        );
+
+        let entry = &AdminLog::get(0, 10)[0];
+        assert_eq!(entry.caller, alice());
+        assert!(matches!(&entry.new_value, Some(Value::Text(text)) if text.starts_with("sha256:")));
+    }
+}
@@ -0,0 +1,711 @@
+//! Allowances of the spenders to transfer the tokens of the holders, and the notifications of the
+//! spender canisters about the new allowances.
+//!
+//! A holder sets the allowance with `approve`, or with `approve_and_notify`, which then calls the
+//! `on_approval` method of the spender canister with the payload given by the holder, e.g. to
+//! deposit the tokens to a DEX in one call. The spender then moves the tokens with
+//! `transfer_from_allowance`. The allowance stays set if the notification fails, and the holder
+//! can see the status of the notification and retry it.
+//!
+//! An allowance set by `approve` can expire. The expired allowances can't be spent and are removed
+//! by the timer task in batches, see `canister::approvals`. They are found by the expiry queue
+//! ordered by the expiration time, so a run only reads the entries it removes.
+//!
+//! The allowances are keyed by the owner account, and indexed by the spender, so the wallets can
+//! list the allowances given by an account or to a spender page by page.
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{
+    BoundedStorable, MemoryId, StableBTreeMap, StableCell, StableMultimap, Storable,
+};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::{truncate_message, TxError};
+use crate::state::balances::{AccountKey, PrincipalKey, ACCOUNT_KEY_SIZE, PRINCIPAL_KEY_SIZE};
+use crate::state::config::Timestamp;
+use crate::state::ledger::LedgerData;
+
+pub type ApprovalNotificationId = u64;
+
+/// Method of the spender canister called by `approve_and_notify` with an `ApprovalNotice`.
+pub const APPROVAL_CALLBACK_METHOD: &str = "on_approval";
+
+/// Maximum size of the payload passed to the spender canister.
+pub const MAX_APPROVAL_PAYLOAD_SIZE: usize = 1024;
+
+/// Maximum number of the allowances returned by one call of the bulk queries.
+pub const MAX_ALLOWANCES_PAGE_SIZE: usize = 100;
+
+/// Maximum length of the failure reason stored in the notification. The reject message of the
+/// spender canister is truncated to it.
+pub const MAX_APPROVAL_FAILURE_REASON_LEN: usize = 256;
+
+/// Argument of the `on_approval` call of the spender canister.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ApprovalNotice {
+    pub id: ApprovalNotificationId,
+    pub owner: Account,
+    /// Allowance of the spender set by the approval.
+    pub amount: Tokens128,
+    pub payload: Vec<u8>,
+}
+
+/// Allowance returned by the bulk queries.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct AllowanceEntry {
+    pub owner: Account,
+    pub spender: Principal,
+    pub amount: Tokens128,
+    pub expires_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum ApprovalNotificationStatus {
+    /// The call of the spender canister is in progress.
+    Pending,
+    Delivered,
+    /// The spender canister rejected the call or couldn't be called. The holder can retry it.
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ApprovalNotification {
+    pub notice: ApprovalNotice,
+    pub spender: Principal,
+    pub created_at: Timestamp,
+    pub attempts: u32,
+    pub status: ApprovalNotificationStatus,
+}
+
+impl Storable for ApprovalNotification {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode approval notification")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode approval notification")
+    }
+}
+
+impl BoundedStorable for ApprovalNotification {
+    // The payload, the failure reason limited by `MAX_APPROVAL_FAILURE_REASON_LEN`, two
+    // principals, a subaccount, an amount and the counters with the type table.
+    const MAX_SIZE: u32 = (MAX_APPROVAL_PAYLOAD_SIZE + 2048) as _;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Number of the expired allowances removed by the sweeper.
+#[derive(Debug, Clone, Copy, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub struct AllowanceSweepStats {
+    /// Removed by the last run.
+    pub last_removed: u64,
+    /// Removed since the last upgrade.
+    pub total_removed: u64,
+    pub last_run_at: Option<Timestamp>,
+}
+
+/// Allowance in the expiry queue: the expiration time as a big-endian integer, so the keys are
+/// ordered by it, followed by the owner and the spender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExpiryKey(Timestamp, AccountKey, PrincipalKey);
+
+const EXPIRY_KEY_SIZE: usize = 8 + ACCOUNT_KEY_SIZE + PRINCIPAL_KEY_SIZE;
+
+impl Storable for ExpiryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(EXPIRY_KEY_SIZE);
+        bytes.extend_from_slice(&self.0.to_be_bytes());
+        bytes.extend_from_slice(&self.1.to_bytes());
+        bytes.extend_from_slice(&self.2.to_bytes());
+        bytes.into()
+    }
+
+    /// Expected `bytes.len() == EXPIRY_KEY_SIZE`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (expires_at, rest) = bytes.split_at(8);
+        let (owner, spender) = rest.split_at(ACCOUNT_KEY_SIZE);
+        Self(
+            Timestamp::from_be_bytes(expires_at.try_into().expect("invalid expiry key length")),
+            AccountKey::from_bytes(owner.to_vec().into()),
+            PrincipalKey::from_bytes(spender.to_vec().into()),
+        )
+    }
+}
+
+impl BoundedStorable for ExpiryKey {
+    const MAX_SIZE: u32 = EXPIRY_KEY_SIZE as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+pub struct Allowances;
+
+impl Allowances {
+    /// Sets the allowance of the `spender` to transfer from the `owner` account until
+    /// `expires_at`, or removes it if `amount` is zero. The allowance without the expiration time
+    /// doesn't expire. The change is recorded in the transaction history.
+    pub fn approve(
+        owner: AccountInternal,
+        spender: Principal,
+        amount: Tokens128,
+        expires_at: Option<Timestamp>,
+        now: Timestamp,
+    ) -> Result<(), TxError> {
+        if spender == owner.owner || spender == Principal::anonymous() {
+            return Err(TxError::InvalidSpender);
+        }
+
+        if expires_at.map_or(false, |expires_at| expires_at <= now) {
+            return Err(TxError::InvalidAllowanceExpiry);
+        }
+
+        let (owner_key, spender_key) = (AccountKey::from(owner), PrincipalKey::from(spender));
+        Self::set_amount(owner_key, spender_key, amount);
+        Self::set_expiry(
+            owner_key,
+            spender_key,
+            expires_at.filter(|_| !amount.is_zero()),
+        );
+        LedgerData::approve(owner, spender, amount);
+        Ok(())
+    }
+
+    fn set_amount(owner: AccountKey, spender: PrincipalKey, amount: Tokens128) {
+        ALLOWANCES.with(|map| {
+            let mut map = map.borrow_mut();
+            let is_new = map.get(&owner, &spender).is_none();
+            if amount.is_zero() {
+                if !is_new {
+                    map.remove(&owner, &spender);
+                    SPENDERS.with(|index| index.borrow_mut().remove(&spender, &owner));
+                    Self::update_count(|count| count.saturating_sub(1));
+                }
+            } else {
+                map.insert(&owner, &spender, &amount.amount);
+                if is_new {
+                    SPENDERS.with(|index| index.borrow_mut().insert(&spender, &owner, &()));
+                    Self::update_count(|count| count + 1);
+                }
+            }
+        });
+    }
+
+    fn set_expiry(owner: AccountKey, spender: PrincipalKey, expires_at: Option<Timestamp>) {
+        let previous = EXPIRATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            let previous = map.get(&owner, &spender);
+            if let Some(expires_at) = expires_at {
+                map.insert(&owner, &spender, &expires_at);
+            } else if previous.is_some() {
+                map.remove(&owner, &spender);
+            }
+            previous
+        });
+        EXPIRY_QUEUE.with(|queue| {
+            let mut queue = queue.borrow_mut();
+            if let Some(previous) = previous {
+                queue.remove(&ExpiryKey(previous, owner, spender));
+            }
+            if let Some(expires_at) = expires_at {
+                queue.insert(ExpiryKey(expires_at, owner, spender), ());
+            }
+        });
+    }
+
+    fn update_count(f: impl FnOnce(u64) -> u64) {
+        ALLOWANCE_COUNT.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let count = f(*cell.get());
+            cell.set(count).expect("failed to write allowance count");
+        });
+    }
+
+    /// Returns the allowance of the `spender`, zero if it expired by `now`.
+    pub fn get(owner: AccountInternal, spender: Principal, now: Timestamp) -> Tokens128 {
+        let (owner, spender) = (AccountKey::from(owner), PrincipalKey::from(spender));
+        let expires_at = EXPIRATIONS.with(|map| map.borrow().get(&owner, &spender));
+        if expires_at.map_or(false, |expires_at| expires_at <= now) {
+            return Tokens128::ZERO;
+        }
+
+        Self::stored_amount(owner, spender)
+    }
+
+    fn stored_amount(owner: AccountKey, spender: PrincipalKey) -> Tokens128 {
+        ALLOWANCES
+            .with(|map| map.borrow().get(&owner, &spender))
+            .unwrap_or_default()
+            .into()
+    }
+
+    /// Returns the expiration time of the allowance, `None` if it doesn't expire or isn't set.
+    pub fn expires_at(owner: AccountInternal, spender: Principal) -> Option<Timestamp> {
+        EXPIRATIONS.with(|map| map.borrow().get(&owner.into(), &spender.into()))
+    }
+
+    /// Number of the stored allowances, including the expired ones not removed yet.
+    pub fn count() -> u64 {
+        ALLOWANCE_COUNT.with(|cell| *cell.borrow().get())
+    }
+
+    /// Returns at most `limit` allowances given by the `owner` account, skipping the first `offset`
+    /// ones, in the order of the spender keys. The expired allowances are skipped.
+    pub fn list_by_owner(
+        owner: AccountInternal,
+        offset: usize,
+        limit: usize,
+        now: Timestamp,
+    ) -> Vec<AllowanceEntry> {
+        let owner = AccountKey::from(owner);
+        ALLOWANCES.with(|map| {
+            let map = map.borrow();
+            let keys = map.range(&owner).map(|(spender, _)| (owner, spender));
+            Self::page(keys, offset, limit, now)
+        })
+    }
+
+    /// Returns at most `limit` allowances given to the `spender`, skipping the first `offset`
+    /// ones, in the order of the owner keys. The expired allowances are skipped.
+    pub fn list_by_spender(
+        spender: Principal,
+        offset: usize,
+        limit: usize,
+        now: Timestamp,
+    ) -> Vec<AllowanceEntry> {
+        let spender = PrincipalKey::from(spender);
+        SPENDERS.with(|index| {
+            let index = index.borrow();
+            let keys = index.range(&spender).map(|(owner, _)| (owner, spender));
+            Self::page(keys, offset, limit, now)
+        })
+    }
+
+    fn page(
+        keys: impl Iterator<Item = (AccountKey, PrincipalKey)>,
+        offset: usize,
+        limit: usize,
+        now: Timestamp,
+    ) -> Vec<AllowanceEntry> {
+        keys.filter_map(|(owner, spender)| {
+            let expires_at = EXPIRATIONS.with(|map| map.borrow().get(&owner, &spender));
+            if expires_at.map_or(false, |expires_at| expires_at <= now) {
+                return None;
+            }
+
+            Some(AllowanceEntry {
+                owner: owner.account().into(),
+                spender: spender.principal(),
+                amount: Self::stored_amount(owner, spender),
+                expires_at,
+            })
+        })
+        .skip(offset)
+        .take(limit.min(MAX_ALLOWANCES_PAGE_SIZE))
+        .collect()
+    }
+
+    /// Checks that the `spender` can transfer `amount` from the `owner` account.
+    pub fn check_spending(
+        owner: AccountInternal,
+        spender: Principal,
+        amount: Tokens128,
+        now: Timestamp,
+    ) -> Result<(), TxError> {
+        let allowance = Self::get(owner, spender, now);
+        if allowance < amount {
+            return Err(TxError::InsufficientAllowance { allowance });
+        }
+
+        Ok(())
+    }
+
+    /// Decreases the allowance by the transferred `amount`, which must be checked by
+    /// `check_spending`. The spending is recorded by the transfer itself.
+    pub fn record_spending(owner: AccountInternal, spender: Principal, amount: Tokens128) {
+        let (owner, spender) = (AccountKey::from(owner), PrincipalKey::from(spender));
+        let remaining = Self::stored_amount(owner, spender).saturating_sub(amount);
+        Self::set_amount(owner, spender, remaining);
+        if remaining.is_zero() {
+            Self::set_expiry(owner, spender, None);
+        }
+    }
+
+    /// Removes at most `limit` allowances expired by `now`, the earliest first. Returns the number
+    /// of the removed allowances.
+    pub fn remove_expired(now: Timestamp, limit: usize) -> usize {
+        let expired = EXPIRY_QUEUE.with(|queue| {
+            queue
+                .borrow()
+                .iter()
+                .map(|(key, _)| key)
+                .take_while(|key| key.0 <= now)
+                .take(limit)
+                .collect::<Vec<_>>()
+        });
+        for ExpiryKey(_, owner, spender) in &expired {
+            Self::set_amount(*owner, *spender, Tokens128::ZERO);
+            Self::set_expiry(*owner, *spender, None);
+        }
+
+        expired.len()
+    }
+
+    /// Records the number of the allowances removed by a run of the sweeper at `now`.
+    pub fn record_sweep(removed: u64, now: Timestamp) {
+        SWEEP_STATS.with(|cell| {
+            let stats = cell.get();
+            cell.set(AllowanceSweepStats {
+                last_removed: removed,
+                total_removed: stats.total_removed.saturating_add(removed),
+                last_run_at: Some(now),
+            })
+        });
+    }
+
+    pub fn sweep_stats() -> AllowanceSweepStats {
+        SWEEP_STATS.with(Cell::get)
+    }
+
+    pub fn check_payload(payload: &[u8]) -> Result<(), TxError> {
+        if payload.len() > MAX_APPROVAL_PAYLOAD_SIZE {
+            return Err(TxError::ApprovalPayloadTooLarge {
+                max: MAX_APPROVAL_PAYLOAD_SIZE as _,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Creates the pending notification of the `spender` about its allowance `amount` set by the
+    /// `owner`. The `payload` must be checked by `check_payload`.
+    pub fn add_notification(
+        owner: AccountInternal,
+        spender: Principal,
+        amount: Tokens128,
+        payload: Vec<u8>,
+        now: Timestamp,
+    ) -> ApprovalNotification {
+        let id = NEXT_NOTIFICATION_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1)
+                .expect("failed to write next approval notification id");
+            id
+        });
+        let notification = ApprovalNotification {
+            notice: ApprovalNotice {
+                id,
+                owner: owner.into(),
+                amount,
+                payload,
+            },
+            spender,
+            created_at: now,
+            attempts: 0,
+            status: ApprovalNotificationStatus::Pending,
+        };
+        NOTIFICATIONS.with(|map| map.borrow_mut().insert(id, notification.clone()));
+        notification
+    }
+
+    pub fn get_notification(id: ApprovalNotificationId) -> Option<ApprovalNotification> {
+        NOTIFICATIONS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns the failed notification to be retried by its owner, marking it pending.
+    pub fn start_retry(
+        caller: Principal,
+        id: ApprovalNotificationId,
+    ) -> Result<ApprovalNotification, TxError> {
+        let mut notification =
+            Self::get_notification(id).ok_or(TxError::ApprovalNotificationNotFound)?;
+        if notification.notice.owner.owner != caller {
+            return Err(TxError::Unauthorized);
+        }
+
+        if !matches!(
+            notification.status,
+            ApprovalNotificationStatus::Failed { .. }
+        ) {
+            return Err(TxError::ApprovalNotificationNotFailed);
+        }
+
+        notification.status = ApprovalNotificationStatus::Pending;
+        NOTIFICATIONS.with(|map| map.borrow_mut().insert(id, notification.clone()));
+        Ok(notification)
+    }
+
+    /// Stores the result of a call of the spender canister and returns the updated notification.
+    pub fn record_attempt(
+        id: ApprovalNotificationId,
+        result: Result<(), String>,
+    ) -> Option<ApprovalNotification> {
+        NOTIFICATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            let mut notification = map.get(&id)?;
+            notification.attempts = notification.attempts.saturating_add(1);
+            notification.status = match result {
+                Ok(()) => ApprovalNotificationStatus::Delivered,
+                Err(mut reason) => {
+                    truncate_message(&mut reason, MAX_APPROVAL_FAILURE_REASON_LEN);
+                    ApprovalNotificationStatus::Failed { reason }
+                }
+            };
+            map.insert(id, notification.clone());
+            Some(notification)
+        })
+    }
+
+    pub fn clear() {
+        ALLOWANCES.with(|map| {
+            let mut map = map.borrow_mut();
+            let keys = map
+                .iter()
+                .map(|(owner, spender, _)| (owner, spender))
+                .collect::<Vec<_>>();
+            for (owner, spender) in keys {
+                map.remove(&owner, &spender);
+            }
+        });
+        EXPIRATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            let keys = map
+                .iter()
+                .map(|(owner, spender, _)| (owner, spender))
+                .collect::<Vec<_>>();
+            for (owner, spender) in keys {
+                map.remove(&owner, &spender);
+            }
+        });
+        SPENDERS.with(|index| {
+            let mut index = index.borrow_mut();
+            let keys = index
+                .iter()
+                .map(|(spender, owner, _)| (spender, owner))
+                .collect::<Vec<_>>();
+            for (spender, owner) in keys {
+                index.remove(&spender, &owner);
+            }
+        });
+        EXPIRY_QUEUE.with(|queue| queue.borrow_mut().clear());
+        ALLOWANCE_COUNT.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write allowance count")
+        });
+        SWEEP_STATS.with(|cell| cell.set(AllowanceSweepStats::default()));
+        NOTIFICATIONS.with(|map| map.borrow_mut().clear());
+        NEXT_NOTIFICATION_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next approval notification id")
+        });
+    }
+}
+
+const ALLOWANCES_MEMORY_ID: MemoryId = MemoryId::new(57);
+const APPROVAL_NOTIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(58);
+const NEXT_APPROVAL_NOTIFICATION_ID_MEMORY_ID: MemoryId = MemoryId::new(59);
+const ALLOWANCE_EXPIRATIONS_MEMORY_ID: MemoryId = MemoryId::new(61);
+const ALLOWANCE_EXPIRY_QUEUE_MEMORY_ID: MemoryId = MemoryId::new(62);
+const ALLOWANCE_COUNT_MEMORY_ID: MemoryId = MemoryId::new(63);
+const ALLOWANCE_SPENDERS_MEMORY_ID: MemoryId = MemoryId::new(64);
+
+thread_local! {
+    static ALLOWANCES: RefCell<StableMultimap<AccountKey, PrincipalKey, u128>> =
+        RefCell::new(StableMultimap::new(ALLOWANCES_MEMORY_ID));
+    static NOTIFICATIONS: RefCell<StableBTreeMap<ApprovalNotificationId, ApprovalNotification>> =
+        RefCell::new(StableBTreeMap::new(APPROVAL_NOTIFICATIONS_MEMORY_ID));
+    static NEXT_NOTIFICATION_ID: RefCell<StableCell<ApprovalNotificationId>> =
+        RefCell::new(StableCell::new(NEXT_APPROVAL_NOTIFICATION_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next approval notification id"));
+    static EXPIRATIONS: RefCell<StableMultimap<AccountKey, PrincipalKey, Timestamp>> =
+        RefCell::new(StableMultimap::new(ALLOWANCE_EXPIRATIONS_MEMORY_ID));
+    static SPENDERS: RefCell<StableMultimap<PrincipalKey, AccountKey, ()>> =
+        RefCell::new(StableMultimap::new(ALLOWANCE_SPENDERS_MEMORY_ID));
+    static EXPIRY_QUEUE: RefCell<StableBTreeMap<ExpiryKey, ()>> =
+        RefCell::new(StableBTreeMap::new(ALLOWANCE_EXPIRY_QUEUE_MEMORY_ID));
+    static ALLOWANCE_COUNT: RefCell<StableCell<u64>> =
+        RefCell::new(StableCell::new(ALLOWANCE_COUNT_MEMORY_ID, 0)
+            .expect("unable to initialize allowance count"));
+    static SWEEP_STATS: Cell<AllowanceSweepStats> = Cell::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::ledger::Operation;
+
+    #[test]
+    fn allowance_is_spent() {
+        MockContext::new().inject();
+        Allowances::clear();
+        LedgerData::clear();
+
+        let owner = AccountInternal::from(alice());
+        assert_eq!(
+            Allowances::approve(owner, alice(), 100.into(), None, 0),
+            Err(TxError::InvalidSpender)
+        );
+        Allowances::approve(owner, bob(), 100.into(), None, 0).unwrap();
+        assert_eq!(Allowances::get(owner, bob(), 0), 100.into());
+        assert_eq!(Allowances::get(owner, john(), 0), 0.into());
+
+        let record = LedgerData::get(0).unwrap();
+        assert_eq!(record.operation, Operation::Approve);
+        assert_eq!(record.to, Account::from(bob()));
+        assert_eq!(record.amount, 100.into());
+
+        assert_eq!(
+            Allowances::check_spending(owner, bob(), 101.into(), 0),
+            Err(TxError::InsufficientAllowance {
+                allowance: 100.into()
+            })
+        );
+        Allowances::check_spending(owner, bob(), 60.into(), 0).unwrap();
+        Allowances::record_spending(owner, bob(), 60.into());
+        assert_eq!(Allowances::get(owner, bob(), 0), 40.into());
+        assert_eq!(Allowances::count(), 1);
+
+        Allowances::approve(owner, bob(), 0.into(), None, 0).unwrap();
+        assert_eq!(Allowances::get(owner, bob(), 0), 0.into());
+        assert_eq!(Allowances::count(), 0);
+        assert_eq!(LedgerData::len(), 2);
+    }
+
+    #[test]
+    fn expired_allowances_are_removed_in_order() {
+        MockContext::new().inject();
+        Allowances::clear();
+        LedgerData::clear();
+
+        let owner = AccountInternal::from(alice());
+        assert_eq!(
+            Allowances::approve(owner, bob(), 100.into(), Some(10), 10),
+            Err(TxError::InvalidAllowanceExpiry)
+        );
+        Allowances::approve(owner, bob(), 100.into(), Some(30), 0).unwrap();
+        Allowances::approve(owner, john(), 100.into(), Some(20), 0).unwrap();
+        // Replacing the allowance moves it in the expiry queue.
+        Allowances::approve(owner, bob(), 50.into(), Some(40), 0).unwrap();
+        Allowances::approve(AccountInternal::from(bob()), john(), 10.into(), None, 0).unwrap();
+        assert_eq!(Allowances::count(), 3);
+        assert_eq!(Allowances::expires_at(owner, bob()), Some(40));
+
+        assert_eq!(Allowances::get(owner, john(), 20), 0.into());
+        assert_eq!(
+            Allowances::check_spending(owner, john(), 1.into(), 20),
+            Err(TxError::InsufficientAllowance {
+                allowance: 0.into()
+            })
+        );
+        assert_eq!(Allowances::remove_expired(35, 10), 1);
+        assert_eq!(Allowances::count(), 2);
+        assert_eq!(Allowances::expires_at(owner, john()), None);
+        assert_eq!(Allowances::get(owner, bob(), 35), 50.into());
+
+        assert_eq!(Allowances::remove_expired(u64::MAX, 10), 1);
+        assert_eq!(Allowances::remove_expired(u64::MAX, 10), 0);
+        assert_eq!(Allowances::count(), 1);
+        assert_eq!(
+            Allowances::get(AccountInternal::from(bob()), john(), u64::MAX),
+            10.into()
+        );
+    }
+
+    #[test]
+    fn allowances_are_listed_by_owner_and_spender() {
+        MockContext::new().inject();
+        Allowances::clear();
+
+        let alice = AccountInternal::from(alice());
+        let bob_savings = AccountInternal::new(bob(), Some([1; 32]));
+        Allowances::approve(alice, john(), 100.into(), None, 0).unwrap();
+        Allowances::approve(alice, bob(), 50.into(), Some(10), 0).unwrap();
+        Allowances::approve(bob_savings, john(), 20.into(), None, 0).unwrap();
+
+        let approvals = Allowances::list_by_owner(alice, 0, 10, 0);
+        assert_eq!(approvals.len(), 2);
+        assert!(approvals.contains(&AllowanceEntry {
+            owner: alice.into(),
+            spender: bob(),
+            amount: 50.into(),
+            expires_at: Some(10),
+        }));
+        assert_eq!(Allowances::list_by_owner(alice, 1, 10, 0).len(), 1);
+        // The expired allowance is skipped before it's removed.
+        assert_eq!(
+            Allowances::list_by_owner(alice, 0, 10, 10),
+            vec![AllowanceEntry {
+                owner: alice.into(),
+                spender: john(),
+                amount: 100.into(),
+                expires_at: None,
+            }]
+        );
+
+        let allowances = Allowances::list_by_spender(john(), 0, 10, 0);
+        assert_eq!(allowances.len(), 2);
+        assert_eq!(Allowances::list_by_spender(john(), 0, 1, 0).len(), 1);
+
+        Allowances::approve(alice, john(), 0.into(), None, 0).unwrap();
+        assert_eq!(
+            Allowances::list_by_spender(john(), 0, 10, 0),
+            vec![AllowanceEntry {
+                owner: bob_savings.into(),
+                spender: john(),
+                amount: 20.into(),
+                expires_at: None,
+            }]
+        );
+        assert!(Allowances::list_by_spender(alice.owner, 0, 10, 0).is_empty());
+    }
+
+    #[test]
+    fn failed_notification_is_retried_by_owner() {
+        MockContext::new().inject();
+        Allowances::clear();
+
+        let owner = AccountInternal::from(alice());
+        assert_eq!(
+            Allowances::check_payload(&[0; MAX_APPROVAL_PAYLOAD_SIZE + 1]),
+            Err(TxError::ApprovalPayloadTooLarge {
+                max: MAX_APPROVAL_PAYLOAD_SIZE as _
+            })
+        );
+
+        let notification = Allowances::add_notification(owner, bob(), 100.into(), vec![1, 2], 10);
+        let id = notification.notice.id;
+        assert_eq!(notification.status, ApprovalNotificationStatus::Pending);
+        assert_eq!(
+            Allowances::start_retry(alice(), id),
+            Err(TxError::ApprovalNotificationNotFailed)
+        );
+
+        let notification = Allowances::record_attempt(id, Err("é".repeat(200))).unwrap();
+        let ApprovalNotificationStatus::Failed { reason } = notification.status else {
+            panic!("the notification is not failed");
+        };
+        assert_eq!(reason, "é".repeat(MAX_APPROVAL_FAILURE_REASON_LEN / 2));
+        assert_eq!(
+            Allowances::start_retry(bob(), id),
+            Err(TxError::Unauthorized)
+        );
+        Allowances::start_retry(alice(), id).unwrap();
+
+        let notification = Allowances::record_attempt(id, Ok(())).unwrap();
+        assert_eq!(notification.attempts, 2);
+        assert_eq!(notification.status, ApprovalNotificationStatus::Delivered);
+    }
+}
@@ -0,0 +1,298 @@
+//! History of the cycle auctions of the token.
+//!
+//! The auction results are stored by the token in addition to the auction state of `ic_auction`,
+//! so they can be listed and pruned by the retention policy. The totals over all the auctions
+//! are kept separately and are not affected by the pruning. The withdrawals of the residual
+//! tokens from the auction account are logged here as well, and are never pruned.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_auction::state::AuctionInfo;
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::Account;
+use crate::state::config::Timestamp;
+use crate::state::ledger::RetentionPolicy;
+use crate::tx_record::TxId;
+
+/// Maximum number of the auctions returned by one `list_auctions` call.
+pub const MAX_AUCTIONS_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct AuctionTotals {
+    /// Number of the auctions held, including the pruned ones.
+    pub auctions: u64,
+    pub cycles_collected: u128,
+    pub tokens_distributed: Tokens128,
+}
+
+impl Storable for AuctionTotals {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode auction totals")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode auction totals")
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct AuctionsPage {
+    /// Auctions ordered by id, oldest first.
+    pub auctions: Vec<AuctionInfo>,
+    /// Number of the stored auctions, the pruned ones are not counted.
+    pub stored: u64,
+    pub totals: AuctionTotals,
+}
+
+/// Withdrawal of the tokens left on the auction account, see `withdraw_auction_residual`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ResidualWithdrawal {
+    pub amount: Tokens128,
+    pub to: Account,
+    pub withdrawn_by: Principal,
+    pub transaction_id: TxId,
+    pub timestamp: Timestamp,
+    /// Number of the auctions held before the withdrawal.
+    pub auctions_held: u64,
+}
+
+impl Storable for ResidualWithdrawal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode residual withdrawal")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode residual withdrawal")
+    }
+}
+
+impl BoundedStorable for ResidualWithdrawal {
+    // Two principals, a subaccount, an amount and three numbers with the type table.
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+struct StorableAuctionInfo(AuctionInfo);
+
+impl Storable for StorableAuctionInfo {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(&self.0)
+            .expect("failed to encode auction info")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Decode!(&bytes, AuctionInfo).expect("failed to decode auction info"))
+    }
+}
+
+impl BoundedStorable for StorableAuctionInfo {
+    // Six numbers, an amount and a float with the type table.
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct AuctionHistory;
+
+impl AuctionHistory {
+    /// Stores the result of the auction and prunes the history by the `retention` policy.
+    pub fn record(info: &AuctionInfo, retention: Option<RetentionPolicy>) {
+        HISTORY.with(|map| {
+            map.borrow_mut()
+                .insert(info.auction_id as u64, StorableAuctionInfo(info.clone()))
+        });
+
+        let mut totals = Self::totals();
+        totals.auctions += 1;
+        totals.cycles_collected = totals
+            .cycles_collected
+            .saturating_add(info.cycles_collected as u128);
+        totals.tokens_distributed = (totals.tokens_distributed + info.tokens_distributed)
+            .unwrap_or(Tokens128::from(u128::MAX));
+        TOTALS
+            .with(|cell| cell.borrow_mut().set(totals))
+            .expect("unable to set auction totals to stable memory");
+
+        if let Some(policy) = retention {
+            Self::prune(&policy, info.auction_time);
+        }
+    }
+
+    pub fn list(offset: usize, limit: usize) -> AuctionsPage {
+        let limit = limit.min(MAX_AUCTIONS_PAGE_SIZE);
+        HISTORY.with(|map| {
+            let map = map.borrow();
+            AuctionsPage {
+                auctions: map
+                    .iter()
+                    .skip(offset)
+                    .take(limit)
+                    .map(|(_, info)| info.0)
+                    .collect(),
+                stored: map.len(),
+                totals: Self::totals(),
+            }
+        })
+    }
+
+    pub fn totals() -> AuctionTotals {
+        TOTALS.with(|cell| cell.borrow().get().clone())
+    }
+
+    /// Removes the oldest auctions exceeding any of the limits of the `policy`. Returns the number
+    /// of removed auctions.
+    pub fn prune(policy: &RetentionPolicy, now: Timestamp) -> u64 {
+        HISTORY.with(|map| {
+            let mut map = map.borrow_mut();
+            let by_length = policy
+                .max_length
+                .map_or(0, |max_length| map.len().saturating_sub(max_length));
+            let min_time = policy
+                .max_age_nanos
+                .map_or(0, |max_age| now.saturating_sub(max_age));
+
+            let prunable = map
+                .iter()
+                .enumerate()
+                .take_while(|(index, (_, info))| {
+                    (*index as u64) < by_length || info.0.auction_time < min_time
+                })
+                .map(|(_, (id, _))| id)
+                .collect::<Vec<_>>();
+
+            for id in &prunable {
+                map.remove(id);
+            }
+
+            prunable.len() as u64
+        })
+    }
+
+    pub fn record_residual_withdrawal(withdrawal: ResidualWithdrawal) {
+        RESIDUAL_WITHDRAWALS.with(|map| {
+            let mut map = map.borrow_mut();
+            let id = map.len();
+            map.insert(id, withdrawal)
+        });
+    }
+
+    /// Returns up to `limit` residual withdrawals starting from `offset`, oldest first.
+    pub fn list_residual_withdrawals(offset: usize, limit: usize) -> Vec<ResidualWithdrawal> {
+        let limit = limit.min(MAX_AUCTIONS_PAGE_SIZE);
+        RESIDUAL_WITHDRAWALS.with(|map| {
+            map.borrow()
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .map(|(_, withdrawal)| withdrawal)
+                .collect()
+        })
+    }
+
+    pub fn clear() {
+        RESIDUAL_WITHDRAWALS.with(|map| map.borrow_mut().clear());
+        HISTORY.with(|map| {
+            let mut map = map.borrow_mut();
+            let ids = map.iter().map(|(id, _)| id).collect::<Vec<_>>();
+            for id in ids {
+                map.remove(&id);
+            }
+        });
+        TOTALS
+            .with(|cell| cell.borrow_mut().set(AuctionTotals::default()))
+            .expect("unable to reset auction totals");
+    }
+}
+
+const AUCTION_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(17);
+const AUCTION_TOTALS_MEMORY_ID: MemoryId = MemoryId::new(18);
+const RESIDUAL_WITHDRAWALS_MEMORY_ID: MemoryId = MemoryId::new(37);
+
+thread_local! {
+    static HISTORY: RefCell<StableBTreeMap<u64, StorableAuctionInfo>> =
+        RefCell::new(StableBTreeMap::new(AUCTION_HISTORY_MEMORY_ID));
+    static TOTALS: RefCell<StableCell<AuctionTotals>> =
+        RefCell::new(StableCell::new(AUCTION_TOTALS_MEMORY_ID, AuctionTotals::default())
+            .expect("unable to initialize auction totals"));
+    static RESIDUAL_WITHDRAWALS: RefCell<StableBTreeMap<u64, ResidualWithdrawal>> =
+        RefCell::new(StableBTreeMap::new(RESIDUAL_WITHDRAWALS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn auction(id: usize, time: Timestamp) -> AuctionInfo {
+        AuctionInfo {
+            auction_id: id,
+            auction_time: time,
+            tokens_distributed: 100.into(),
+            cycles_collected: 1_000,
+            fee_ratio: 1.0,
+            first_transaction_id: 0,
+            last_transaction_id: 0,
+        }
+    }
+
+    #[test]
+    fn list_auctions() {
+        MockContext::new().inject();
+        AuctionHistory::clear();
+        for id in 0..5 {
+            AuctionHistory::record(&auction(id, id as u64), None);
+        }
+
+        let page = AuctionHistory::list(1, 2);
+        let ids = page
+            .auctions
+            .iter()
+            .map(|info| info.auction_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(page.stored, 5);
+        assert_eq!(
+            page.totals,
+            AuctionTotals {
+                auctions: 5,
+                cycles_collected: 5_000,
+                tokens_distributed: 500.into(),
+            }
+        );
+        assert!(AuctionHistory::list(5, 10).auctions.is_empty());
+    }
+
+    #[test]
+    fn prune_auctions() {
+        MockContext::new().inject();
+        AuctionHistory::clear();
+        let by_length = RetentionPolicy {
+            max_length: Some(3),
+            max_age_nanos: None,
+        };
+        for id in 0..5 {
+            AuctionHistory::record(&auction(id, id as u64 * 10), Some(by_length));
+        }
+        assert_eq!(AuctionHistory::list(0, 10).auctions[0].auction_id, 2);
+
+        let by_age = RetentionPolicy {
+            max_length: None,
+            max_age_nanos: Some(15),
+        };
+        assert_eq!(AuctionHistory::prune(&by_age, 40), 1);
+
+        let page = AuctionHistory::list(0, 10);
+        assert_eq!(page.stored, 2);
+        assert_eq!(page.totals.auctions, 5);
+    }
+}
@@ -0,0 +1,151 @@
+//! Limits of the participation in the cycle auctions, set by the token owner in addition to the
+//! auction state of `ic_auction`.
+//!
+//! Bids below the minimum amount of cycles and bids of the blacklisted bidders are rejected before
+//! the cycles are accepted. The blacklisted bidders are also left out of the distribution of the
+//! auction in progress. Every change is recorded in the `AdminLog`.
+
+use std::cell::RefCell;
+
+use candid::Principal;
+use ic_stable_structures::{MemoryId, StableBTreeMap, StableCell};
+
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::balances::PrincipalKey;
+use crate::state::config::{Timestamp, Value};
+
+/// Maximum number of the blacklisted bidders returned by one `get_bidder_blacklist` call.
+pub const MAX_BLACKLIST_PAGE_SIZE: usize = 1000;
+
+const MIN_BID_CYCLES_MEMORY_ID: MemoryId = MemoryId::new(29);
+const BIDDER_BLACKLIST_MEMORY_ID: MemoryId = MemoryId::new(30);
+
+pub struct AuctionPolicy;
+
+impl AuctionPolicy {
+    /// Minimum amount of cycles of a bid. Zero if not limited.
+    pub fn min_bid_cycles() -> u64 {
+        MIN_BID_CYCLES.with(|cell| *cell.borrow().get())
+    }
+
+    pub fn set_min_bid_cycles(caller: Principal, cycles: u64) {
+        let old = Self::min_bid_cycles();
+        MIN_BID_CYCLES
+            .with(|cell| cell.borrow_mut().set(cycles))
+            .expect("unable to set min bid cycles to stable memory");
+        AdminLog::record(
+            caller,
+            AdminAction::SetMinBidCycles,
+            Some(Value::Nat(old.into())),
+            Some(Value::Nat(cycles.into())),
+        );
+    }
+
+    pub fn is_blacklisted(bidder: Principal) -> bool {
+        BLACKLIST.with(|map| map.borrow().contains_key(&bidder.into()))
+    }
+
+    /// Adds the `bidder` to the blacklist, or removes it if `blacklisted` is false.
+    pub fn set_blacklisted(
+        caller: Principal,
+        bidder: Principal,
+        blacklisted: bool,
+        now: Timestamp,
+    ) {
+        let old = BLACKLIST.with(|map| {
+            let mut map = map.borrow_mut();
+            if blacklisted {
+                map.insert(bidder.into(), now)
+            } else {
+                map.remove(&bidder.into())
+            }
+        });
+
+        let action = if blacklisted {
+            AdminAction::BlacklistBidder { bidder }
+        } else {
+            AdminAction::UnblacklistBidder { bidder }
+        };
+        let value = |blacklisted: bool| Some(Value::Text(blacklisted.to_string()));
+        AdminLog::record(caller, action, value(old.is_some()), value(blacklisted));
+    }
+
+    /// Returns the blacklisted bidders with the time they were added, ordered by the principal.
+    pub fn blacklist(offset: usize, limit: usize) -> Vec<(Principal, Timestamp)> {
+        BLACKLIST.with(|map| {
+            map.borrow()
+                .iter()
+                .skip(offset)
+                .take(limit.min(MAX_BLACKLIST_PAGE_SIZE))
+                .map(|(key, added_at)| (key.principal(), added_at))
+                .collect()
+        })
+    }
+
+    /// Checks that the `bidder` can bid `cycles`.
+    pub fn check_bid(bidder: Principal, cycles: u64) -> Result<(), TxError> {
+        if Self::is_blacklisted(bidder) {
+            return Err(TxError::BidderBlacklisted);
+        }
+
+        let min_bid_cycles = Self::min_bid_cycles();
+        if cycles < min_bid_cycles {
+            return Err(TxError::BidBelowThreshold { min_bid_cycles });
+        }
+
+        Ok(())
+    }
+
+    pub fn clear() {
+        BLACKLIST.with(|map| map.borrow_mut().clear());
+        MIN_BID_CYCLES
+            .with(|cell| cell.borrow_mut().set(0))
+            .expect("unable to set min bid cycles to stable memory");
+    }
+}
+
+thread_local! {
+    static MIN_BID_CYCLES: RefCell<StableCell<u64>> =
+        RefCell::new(StableCell::new(MIN_BID_CYCLES_MEMORY_ID, 0)
+            .expect("unable to initialize min bid cycles"));
+    static BLACKLIST: RefCell<StableBTreeMap<PrincipalKey, Timestamp>> =
+        RefCell::new(StableBTreeMap::new(BIDDER_BLACKLIST_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn bids_are_checked() {
+        MockContext::new().inject();
+        AuctionPolicy::clear();
+        AdminLog::clear();
+
+        assert_eq!(AuctionPolicy::check_bid(alice(), 1), Ok(()));
+
+        AuctionPolicy::set_min_bid_cycles(john(), 1000);
+        AuctionPolicy::set_blacklisted(john(), bob(), true, 10);
+        assert_eq!(
+            AuctionPolicy::check_bid(alice(), 999),
+            Err(TxError::BidBelowThreshold {
+                min_bid_cycles: 1000
+            })
+        );
+        assert_eq!(AuctionPolicy::check_bid(alice(), 1000), Ok(()));
+        assert_eq!(
+            AuctionPolicy::check_bid(bob(), 1000),
+            Err(TxError::BidderBlacklisted)
+        );
+        assert_eq!(AuctionPolicy::blacklist(0, 10), vec![(bob(), 10)]);
+
+        AuctionPolicy::set_blacklisted(john(), bob(), false, 20);
+        assert_eq!(AuctionPolicy::check_bid(bob(), 1000), Ok(()));
+        assert_eq!(AdminLog::get(0, 10).len(), 3);
+    }
+}
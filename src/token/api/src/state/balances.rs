@@ -1,12 +1,15 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ops::Bound;
 
 use candid::{CandidType, Deserialize, Principal};
 use canister_sdk::ic_helpers::tokens::Tokens128;
-use ic_stable_structures::{BoundedStorable, MemoryId, StableMultimap, Storable};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableCell, StableMultimap, Storable};
 
 use crate::account::{AccountInternal, Subaccount};
+use crate::state::certified_balances::CertifiedBalances;
+use crate::state::snapshots::Snapshots;
 
 pub trait Balances {
     /// Write or re-write amount of tokens for specified account.
@@ -27,10 +30,16 @@ pub trait Balances {
         self.get(account).unwrap_or_default()
     }
 
-    /// Update balances according to `updates` iterator.
-    fn apply_updates(&mut self, updates: impl IntoIterator<Item = (AccountInternal, Tokens128)>) {
-        for (account, amount) in updates {
-            self.insert(account, amount);
+    /// Write the changes collected in the `delta`. Only the accounts which balance was changed
+    /// are written.
+    fn apply_delta(&mut self, delta: BalancesDelta) {
+        for (account, amount) in delta.into_changes() {
+            match amount {
+                Some(amount) => self.insert(account, amount),
+                None => {
+                    self.remove(&account);
+                }
+            }
         }
     }
 
@@ -52,6 +61,11 @@ pub trait Balances {
             })
     }
 
+    /// Number of the distinct principals holding tokens.
+    fn holders_count(&self) -> usize {
+        self.get_holders().len()
+    }
+
     /// Get balances map: holder -> subaccount -> tokens.
     fn get_holders(&self) -> HashMap<Principal, HashMap<Subaccount, Tokens128>> {
         let mut holders: HashMap<Principal, HashMap<Subaccount, Tokens128>> = HashMap::new();
@@ -93,13 +107,116 @@ impl StableBalances {
         let account = AccountInternal::new(holder, Some(claim_subaccount));
         Self.balance_of(&account)
     }
+
+    /// Moves at most `limit` balances stored with the variable-length principal keys to the map
+    /// with the fixed-width keys. Run by the state migration to the version 1; does nothing if
+    /// there is nothing to migrate. Returns the number of migrated balances.
+    pub fn migrate_legacy_keys(limit: usize) -> usize {
+        let legacy = LEGACY_MAP.with(|map| map.borrow().iter().take(limit).collect::<Vec<_>>());
+
+        MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            for (principal, subaccount, amount) in &legacy {
+                map.insert(&PrincipalKey::from(principal.0), subaccount, amount);
+            }
+        });
+
+        LEGACY_MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            for (principal, subaccount, _) in &legacy {
+                map.remove(principal, subaccount);
+            }
+        });
+
+        legacy.len()
+    }
+
+    /// Writes the balance with the variable-length key, as the versions before the state
+    /// version 1 did.
+    #[cfg(test)]
+    pub(crate) fn insert_legacy(account: AccountInternal, amount: u128) {
+        LEGACY_MAP.with(|map| {
+            map.borrow_mut().insert(
+                &LegacyPrincipalKey(account.owner),
+                &SubaccountKey(account.subaccount),
+                &amount,
+            )
+        });
+    }
+
+    /// Counts the holders from scratch. Run by the state migration to the version 1, as the
+    /// versions before the holders counter was introduced didn't maintain it.
+    pub fn recount_holders() -> u64 {
+        let count = MAP.with(|map| {
+            let map = map.borrow();
+            let mut count = 0;
+            let mut last = None;
+            for (principal, _, _) in map.iter() {
+                if last != Some(principal) {
+                    count += 1;
+                    last = Some(principal);
+                }
+            }
+            count
+        });
+
+        Self::set_holders_count(count);
+        count
+    }
+
+    fn set_holders_count(count: u64) {
+        HOLDERS_COUNT
+            .with(|cell| cell.borrow_mut().set(count))
+            .expect("unable to set holders count to stable memory");
+    }
+
+    /// Returns up to `limit` subaccounts of the `owner` with balances, the first ones in the byte
+    /// order.
+    pub fn first_subaccounts(owner: Principal, limit: usize) -> HashMap<Subaccount, Tokens128> {
+        MAP.with(|map| {
+            map.borrow()
+                .range(&PrincipalKey::from(owner))
+                .take(limit)
+                .map(|(subaccount, amount)| (subaccount.0, Tokens128::from(amount)))
+                .collect()
+        })
+    }
+
+    /// Up to `limit` accounts with the keys greater than `after`, in the order of the keys. The
+    /// map is read from the key after `after`, so the walks over all the balances in batches only
+    /// read one batch per call.
+    pub(crate) fn accounts_after(after: Option<AccountKey>, limit: usize) -> Vec<AccountKey> {
+        let start = match after {
+            Some(AccountKey(principal, subaccount)) => {
+                Bound::Excluded((principal, SubaccountKey(subaccount)))
+            }
+            None => Bound::Unbounded,
+        };
+        MAP.with(|map| {
+            map.borrow()
+                .range((start, Bound::Unbounded))
+                .take(limit)
+                .map(|(principal, subaccount, _)| AccountKey(principal, subaccount.0))
+                .collect()
+        })
+    }
+
+    fn has_subaccounts(principal_key: &PrincipalKey) -> bool {
+        MAP.with(|map| map.borrow().range(principal_key).next().is_some())
+    }
 }
 
 impl Balances for StableBalances {
     /// Write or re-write amount of tokens for specified account to stable memory.
     fn insert(&mut self, account: AccountInternal, token: Tokens128) {
-        let principal_key = PrincipalKey(account.owner);
+        Snapshots::record_before_change(account, self.balance_of(&account));
+        CertifiedBalances::mark_changed(account);
+        let principal_key = PrincipalKey::from(account.owner);
         let subaccount_key = SubaccountKey(account.subaccount);
+        if !Self::has_subaccounts(&principal_key) {
+            Self::set_holders_count(self.holders_count() as u64 + 1);
+        }
+
         MAP.with(|map| {
             map.borrow_mut()
                 .insert(&principal_key, &subaccount_key, &token.amount)
@@ -108,7 +225,7 @@ impl Balances for StableBalances {
 
     /// Get amount of tokens for the specified account from stable memory.
     fn get(&self, account: &AccountInternal) -> Option<Tokens128> {
-        let principal_key = PrincipalKey(account.owner);
+        let principal_key = PrincipalKey::from(account.owner);
         let subaccount_key = SubaccountKey(account.subaccount);
         MAP.with(|map| map.borrow_mut().get(&principal_key, &subaccount_key))
             .map(Tokens128::from)
@@ -116,16 +233,46 @@ impl Balances for StableBalances {
 
     /// Remove specified account balance from the stable memory.
     fn remove(&mut self, account: &AccountInternal) -> Option<Tokens128> {
-        let principal_key = PrincipalKey(account.owner);
+        let principal_key = PrincipalKey::from(account.owner);
         let subaccount_key = SubaccountKey(account.subaccount);
-        MAP.with(|map| map.borrow_mut().remove(&principal_key, &subaccount_key))
-            .map(Tokens128::from)
+        let removed = MAP
+            .with(|map| map.borrow_mut().remove(&principal_key, &subaccount_key))
+            .map(Tokens128::from);
+        if let Some(removed) = removed {
+            Snapshots::record_before_change(*account, removed);
+            CertifiedBalances::mark_changed(*account);
+        }
+        if removed.is_some() && !Self::has_subaccounts(&principal_key) {
+            Self::set_holders_count((self.holders_count() as u64).saturating_sub(1));
+        }
+
+        removed
+    }
+
+    /// Maintained on insert and remove, so it doesn't scan the balances.
+    fn holders_count(&self) -> usize {
+        HOLDERS_COUNT.with(|cell| *cell.borrow().get()) as usize
+    }
+
+    fn clear(&mut self) {
+        MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            let keys = map
+                .iter()
+                .map(|(principal, subaccount, _)| (principal, subaccount))
+                .collect::<Vec<_>>();
+            for (principal, subaccount) in keys {
+                map.remove(&principal, &subaccount);
+            }
+        });
+        Self::set_holders_count(0);
+        CertifiedBalances::reset();
     }
 
     fn get_subaccounts(&self, owner: Principal) -> HashMap<Subaccount, Tokens128> {
         MAP.with(|map| {
             map.borrow()
-                .range(&PrincipalKey(owner))
+                .range(&PrincipalKey::from(owner))
                 .map(|(subaccount, amount)| (subaccount.0, Tokens128::from(amount)))
                 .collect()
         })
@@ -139,7 +286,7 @@ impl Balances for StableBalances {
                 .take(limit)
                 .map(|(principal, subaccount, amount)| {
                     (
-                        AccountInternal::new(principal.0, Some(subaccount.0)),
+                        AccountInternal::new(principal.principal(), Some(subaccount.0)),
                         Tokens128::from(amount),
                     )
                 })
@@ -201,26 +348,149 @@ impl Balances for LocalBalances {
     }
 }
 
-const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(1);
+/// Working copy of the balances of the accounts touched by an operation.
+///
+/// The balances are read from the underlying storage once, when the delta is created. All the
+/// changes are done in the heap and then written to the storage with `Balances::apply_delta`, so
+/// the storage is modified only if the operation succeeds.
+#[derive(Debug, Default)]
+pub struct BalancesDelta {
+    initial: HashMap<AccountInternal, Option<Tokens128>>,
+    current: LocalBalances,
+}
+
+impl BalancesDelta {
+    /// Loads the balances of the given `accounts` from the `balances` storage.
+    pub fn load(
+        balances: &impl Balances,
+        accounts: impl IntoIterator<Item = AccountInternal>,
+    ) -> Self {
+        let mut delta = Self::default();
+        delta.extend_from(balances, accounts);
+        delta
+    }
+
+    /// Loads the balances of the `accounts` not loaded yet, so the delta can be extended while an
+    /// operation runs.
+    pub fn extend_from(
+        &mut self,
+        balances: &impl Balances,
+        accounts: impl IntoIterator<Item = AccountInternal>,
+    ) {
+        for account in accounts {
+            if self.initial.contains_key(&account) {
+                continue;
+            }
+
+            let amount = balances.get(&account);
+            self.initial.insert(account, amount);
+            if let Some(amount) = amount {
+                self.current.insert(account, amount);
+            }
+        }
+    }
+
+    /// Returns the accounts which balance was changed with their new balances. `None` means the
+    /// account entry must be removed.
+    pub fn into_changes(self) -> impl Iterator<Item = (AccountInternal, Option<Tokens128>)> {
+        let Self {
+            initial,
+            mut current,
+        } = self;
+
+        let mut changes = initial
+            .into_iter()
+            .filter_map(|(account, initial)| {
+                let amount = current.remove(&account);
+                let unchanged = match (initial, amount) {
+                    (initial, Some(amount)) => initial.unwrap_or_default() == amount,
+                    (initial, None) => initial.is_none(),
+                };
+                (!unchanged).then_some((account, amount))
+            })
+            .collect::<Vec<_>>();
+
+        // Accounts not loaded with the delta, but inserted later.
+        changes.extend(
+            current
+                .0
+                .into_iter()
+                .map(|(account, amount)| (account, Some(amount))),
+        );
+        changes.into_iter()
+    }
+}
+
+impl Balances for BalancesDelta {
+    fn insert(&mut self, account: AccountInternal, token: Tokens128) {
+        self.current.insert(account, token);
+    }
+
+    fn get(&self, account: &AccountInternal) -> Option<Tokens128> {
+        self.current.get(account)
+    }
+
+    fn remove(&mut self, account: &AccountInternal) -> Option<Tokens128> {
+        self.current.remove(account)
+    }
+
+    fn list_balances(&self, start: usize, limit: usize) -> Vec<(AccountInternal, Tokens128)> {
+        self.current.list_balances(start, limit)
+    }
+
+    fn total_supply(&self) -> Tokens128 {
+        self.current.total_supply()
+    }
+
+    fn clear(&mut self) {
+        self.current.clear()
+    }
+}
+
+const LEGACY_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(1);
+const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(14);
+const HOLDERS_COUNT_MEMORY_ID: MemoryId = MemoryId::new(19);
 const PRINCIPAL_MAX_LENGTH_IN_BYTES: usize = 29;
-const SUBACCOUNT_MAX_LENGTH_IN_BYTES: usize = 32;
+pub(crate) const PRINCIPAL_KEY_SIZE: usize = 1 + PRINCIPAL_MAX_LENGTH_IN_BYTES;
+pub(crate) const SUBACCOUNT_MAX_LENGTH_IN_BYTES: usize = 32;
+
+/// Principal encoded as its length and the principal bytes padded with zeros, so all the keys have
+/// the same size and the stable map doesn't need to store the key length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PrincipalKey([u8; PRINCIPAL_KEY_SIZE]);
+
+impl PrincipalKey {
+    pub(crate) fn principal(&self) -> Principal {
+        let len = self.0[0] as usize;
+        Principal::from_slice(&self.0[1..1 + len])
+    }
+}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct PrincipalKey(Principal);
+impl From<Principal> for PrincipalKey {
+    fn from(principal: Principal) -> Self {
+        let principal = principal.as_slice();
+        let mut key = [0u8; PRINCIPAL_KEY_SIZE];
+        key[0] = principal.len() as u8;
+        key[1..1 + principal.len()].copy_from_slice(principal);
+        Self(key)
+    }
+}
 
 impl Storable for PrincipalKey {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
         self.0.as_slice().into()
     }
 
-    /// Expected `Principal::from_slice(&bytes)` is a correct operation.
+    /// Expected `bytes.len() == PRINCIPAL_KEY_SIZE`.
     fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
-        PrincipalKey(Principal::from_slice(&bytes))
+        let mut buf = [0u8; PRINCIPAL_KEY_SIZE];
+        buf.copy_from_slice(&bytes);
+        Self(buf)
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct SubaccountKey(Subaccount);
+pub(crate) struct SubaccountKey(pub(crate) Subaccount);
 
 impl Storable for SubaccountKey {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
@@ -236,8 +506,8 @@ impl Storable for SubaccountKey {
 }
 
 impl BoundedStorable for PrincipalKey {
-    const MAX_SIZE: u32 = PRINCIPAL_MAX_LENGTH_IN_BYTES as _;
-    const IS_FIXED_SIZE: bool = false;
+    const MAX_SIZE: u32 = PRINCIPAL_KEY_SIZE as _;
+    const IS_FIXED_SIZE: bool = true;
 }
 
 impl BoundedStorable for SubaccountKey {
@@ -245,7 +515,197 @@ impl BoundedStorable for SubaccountKey {
     const IS_FIXED_SIZE: bool = true;
 }
 
+/// Account encoded as its principal key followed by the subaccount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct AccountKey(PrincipalKey, Subaccount);
+
+pub(crate) const ACCOUNT_KEY_SIZE: usize = PRINCIPAL_KEY_SIZE + SUBACCOUNT_MAX_LENGTH_IN_BYTES;
+
+impl AccountKey {
+    pub(crate) fn account(&self) -> AccountInternal {
+        AccountInternal::new(self.0.principal(), Some(self.1))
+    }
+}
+
+impl From<AccountInternal> for AccountKey {
+    fn from(account: AccountInternal) -> Self {
+        Self(account.owner.into(), account.subaccount)
+    }
+}
+
+impl Storable for AccountKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(ACCOUNT_KEY_SIZE);
+        bytes.extend_from_slice(&self.0.to_bytes());
+        bytes.extend_from_slice(&self.1);
+        bytes.into()
+    }
+
+    /// Expected `bytes.len() == ACCOUNT_KEY_SIZE`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (principal, subaccount) = bytes.split_at(PRINCIPAL_KEY_SIZE);
+        Self(
+            PrincipalKey::from_bytes(principal.to_vec().into()),
+            subaccount.try_into().expect("invalid subaccount length"),
+        )
+    }
+}
+
+impl BoundedStorable for AccountKey {
+    const MAX_SIZE: u32 = ACCOUNT_KEY_SIZE as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+/// Variable-length principal key used by the balances map before the keys were made fixed-width.
+/// Only used to migrate the balances on upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LegacyPrincipalKey(Principal);
+
+impl Storable for LegacyPrincipalKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_slice().into()
+    }
+
+    /// Expected `Principal::from_slice(&bytes)` is a correct operation.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        LegacyPrincipalKey(Principal::from_slice(&bytes))
+    }
+}
+
+impl BoundedStorable for LegacyPrincipalKey {
+    const MAX_SIZE: u32 = PRINCIPAL_MAX_LENGTH_IN_BYTES as _;
+    const IS_FIXED_SIZE: bool = false;
+}
+
 thread_local! {
     static MAP: RefCell<StableMultimap<PrincipalKey, SubaccountKey, u128>> =
         RefCell::new(StableMultimap::new(BALANCES_MEMORY_ID));
+
+    static LEGACY_MAP: RefCell<StableMultimap<LegacyPrincipalKey, SubaccountKey, u128>> =
+        RefCell::new(StableMultimap::new(LEGACY_BALANCES_MEMORY_ID));
+
+    static HOLDERS_COUNT: RefCell<StableCell<u64>> =
+        RefCell::new(StableCell::new(HOLDERS_COUNT_MEMORY_ID, 0)
+            .expect("unable to initialize holders count"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn delta_contains_only_changed_accounts() {
+        let mut balances =
+            LocalBalances::from_iter([(alice().into(), 100.into()), (bob().into(), 50.into())]);
+
+        let mut delta =
+            BalancesDelta::load(&balances, [alice().into(), bob().into(), john().into()]);
+        delta.insert(alice().into(), 90.into());
+        delta.insert(bob().into(), 50.into());
+        delta.insert(john().into(), 0.into());
+
+        let changes = BalancesDelta::load(&balances, [])
+            .into_changes()
+            .collect::<Vec<_>>();
+        assert!(changes.is_empty());
+
+        let changes = delta.into_changes().collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![(AccountInternal::from(alice()), Some(90.into()))]
+        );
+
+        balances.apply_delta(BalancesDelta::load(&balances, [alice().into()]));
+        assert_eq!(balances.balance_of(&alice().into()), 100.into());
+    }
+
+    #[test]
+    fn delta_removes_and_inserts_accounts() {
+        let mut balances = LocalBalances::from_iter([(alice().into(), 100.into())]);
+
+        let mut delta = BalancesDelta::load(&balances, [alice().into()]);
+        delta.remove(&alice().into());
+        delta.insert(bob().into(), 10.into());
+        balances.apply_delta(delta);
+
+        assert_eq!(balances.get(&alice().into()), None);
+        assert_eq!(balances.get(&bob().into()), Some(10.into()));
+    }
+
+    #[test]
+    fn principal_key_is_fixed_width() {
+        for principal in [
+            Principal::management_canister(),
+            Principal::anonymous(),
+            alice(),
+        ] {
+            let key = PrincipalKey::from(principal);
+            assert_eq!(key.to_bytes().len(), PRINCIPAL_KEY_SIZE);
+            assert_eq!(
+                PrincipalKey::from_bytes(key.to_bytes()).principal(),
+                principal
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_balances_are_migrated() {
+        MockContext::new().inject();
+        StableBalances.clear();
+
+        let subaccount = [1; 32];
+        LEGACY_MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            map.insert(&LegacyPrincipalKey(alice()), &SubaccountKey([0; 32]), &100);
+            map.insert(
+                &LegacyPrincipalKey(alice()),
+                &SubaccountKey(subaccount),
+                &10,
+            );
+            map.insert(&LegacyPrincipalKey(bob()), &SubaccountKey([0; 32]), &50);
+        });
+
+        assert_eq!(StableBalances::migrate_legacy_keys(2), 2);
+        assert_eq!(StableBalances::migrate_legacy_keys(2), 1);
+        assert_eq!(StableBalances::migrate_legacy_keys(2), 0);
+
+        assert_eq!(StableBalances.balance_of(&alice().into()), 100.into());
+        assert_eq!(StableBalances.balance_of(&bob().into()), 50.into());
+        assert_eq!(
+            StableBalances.get_subaccounts(alice()),
+            HashMap::from([([0; 32], 100.into()), (subaccount, 10.into())])
+        );
+        assert_eq!(StableBalances::recount_holders(), 2);
+    }
+
+    #[test]
+    fn holders_are_counted() {
+        MockContext::new().inject();
+        StableBalances.clear();
+
+        StableBalances.insert(alice().into(), 100.into());
+        StableBalances.insert(AccountInternal::new(alice(), Some([1; 32])), 10.into());
+        StableBalances.insert(bob().into(), 50.into());
+        StableBalances.insert(bob().into(), 40.into());
+        assert_eq!(StableBalances.holders_count(), 2);
+        assert_eq!(
+            StableBalances.holders_count(),
+            StableBalances.get_holders().len()
+        );
+
+        StableBalances.remove(&alice().into());
+        assert_eq!(StableBalances.holders_count(), 2);
+        StableBalances.remove(&AccountInternal::new(alice(), Some([1; 32])));
+        assert_eq!(StableBalances.holders_count(), 1);
+        StableBalances.remove(&john().into());
+        assert_eq!(StableBalances.holders_count(), 1);
+
+        assert_eq!(StableBalances::recount_holders(), 1);
+        StableBalances.clear();
+        assert_eq!(StableBalances.holders_count(), 0);
+    }
 }
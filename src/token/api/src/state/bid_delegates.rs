@@ -0,0 +1,169 @@
+//! Delegation of the auction bidding to another principal, e.g. a canister pooling the cycles of
+//! several bidders.
+//!
+//! The delegator offers the delegation with `set_bid_delegate`, and it takes effect once the
+//! delegate accepts it with `accept_bid_delegation`. From then on, the cycles are still bid by the
+//! delegate, but the rewards of its bids are distributed to the delegator. A delegate acts for one
+//! delegator at a time, so nobody can claim the rewards of a delegate without its consent.
+
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{MemoryId, StableBTreeMap};
+
+use crate::error::TxError;
+use crate::state::balances::PrincipalKey;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct BidDelegation {
+    pub delegate: Principal,
+    /// The rewards of the delegate are distributed to the delegator only once it accepts the
+    /// delegation.
+    pub accepted: bool,
+}
+
+pub struct BidDelegates;
+
+impl BidDelegates {
+    /// Offers the `delegate` to bid on behalf of the `delegator`, or removes the delegation if
+    /// `None`. Replaces the previous delegation of the `delegator`.
+    pub fn set(delegator: Principal, delegate: Option<Principal>) -> Result<(), TxError> {
+        if delegate == Some(delegator) {
+            return Err(TxError::SelfDelegation);
+        }
+
+        if let Some(old) = Self::get(delegator) {
+            if old.accepted {
+                DELEGATORS.with(|map| map.borrow_mut().remove(&old.delegate.into()));
+            }
+        }
+
+        DELEGATES.with(|map| {
+            let mut map = map.borrow_mut();
+            match delegate {
+                Some(delegate) => map.insert(delegator.into(), delegate.into()),
+                None => map.remove(&delegator.into()),
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Accepts the delegation offered by the `delegator` to the `delegate`.
+    pub fn accept(delegate: Principal, delegator: Principal) -> Result<(), TxError> {
+        match Self::get(delegator) {
+            Some(delegation) if delegation.delegate == delegate => {}
+            _ => return Err(TxError::BidDelegationNotFound),
+        }
+
+        match Self::delegator_of(delegate) {
+            Some(current) if current != delegator => {
+                Err(TxError::BidDelegateTaken { delegator: current })
+            }
+            _ => {
+                DELEGATORS.with(|map| map.borrow_mut().insert(delegate.into(), delegator.into()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Ends the accepted delegation of the `delegate`. The offer of the delegator is removed too.
+    pub fn resign(delegate: Principal) -> Result<(), TxError> {
+        let delegator = Self::delegator_of(delegate).ok_or(TxError::BidDelegationNotFound)?;
+        DELEGATORS.with(|map| map.borrow_mut().remove(&delegate.into()));
+        DELEGATES.with(|map| map.borrow_mut().remove(&delegator.into()));
+        Ok(())
+    }
+
+    pub fn get(delegator: Principal) -> Option<BidDelegation> {
+        let delegate = DELEGATES.with(|map| map.borrow().get(&delegator.into()))?;
+        let delegate = delegate.principal();
+        Some(BidDelegation {
+            delegate,
+            accepted: Self::delegator_of(delegate) == Some(delegator),
+        })
+    }
+
+    /// Principal the rewards of the bids of the `bidder` are distributed to.
+    pub fn beneficiary(bidder: Principal) -> Principal {
+        Self::delegator_of(bidder).unwrap_or(bidder)
+    }
+
+    fn delegator_of(delegate: Principal) -> Option<Principal> {
+        DELEGATORS
+            .with(|map| map.borrow().get(&delegate.into()))
+            .map(|key| key.principal())
+    }
+
+    pub fn clear() {
+        DELEGATES.with(|map| map.borrow_mut().clear());
+        DELEGATORS.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const BID_DELEGATES_MEMORY_ID: MemoryId = MemoryId::new(52);
+const BID_DELEGATORS_MEMORY_ID: MemoryId = MemoryId::new(53);
+
+thread_local! {
+    /// Delegate offered by every delegator, accepted or not.
+    static DELEGATES: RefCell<StableBTreeMap<PrincipalKey, PrincipalKey>> =
+        RefCell::new(StableBTreeMap::new(BID_DELEGATES_MEMORY_ID));
+    /// Delegator of every delegate which accepted the delegation.
+    static DELEGATORS: RefCell<StableBTreeMap<PrincipalKey, PrincipalKey>> =
+        RefCell::new(StableBTreeMap::new(BID_DELEGATORS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn delegation_takes_effect_when_accepted() {
+        MockContext::new().inject();
+        BidDelegates::clear();
+
+        assert_eq!(
+            BidDelegates::set(alice(), Some(alice())),
+            Err(TxError::SelfDelegation)
+        );
+        BidDelegates::set(alice(), Some(bob())).unwrap();
+        assert_eq!(BidDelegates::beneficiary(bob()), bob());
+        assert_eq!(
+            BidDelegates::accept(bob(), john()),
+            Err(TxError::BidDelegationNotFound)
+        );
+
+        BidDelegates::accept(bob(), alice()).unwrap();
+        assert_eq!(BidDelegates::beneficiary(bob()), alice());
+        assert_eq!(
+            BidDelegates::get(alice()),
+            Some(BidDelegation {
+                delegate: bob(),
+                accepted: true
+            })
+        );
+
+        BidDelegates::set(john(), Some(bob())).unwrap();
+        assert_eq!(
+            BidDelegates::accept(bob(), john()),
+            Err(TxError::BidDelegateTaken { delegator: alice() })
+        );
+
+        BidDelegates::set(alice(), None).unwrap();
+        assert_eq!(BidDelegates::beneficiary(bob()), bob());
+        BidDelegates::accept(bob(), john()).unwrap();
+        assert_eq!(BidDelegates::beneficiary(bob()), john());
+
+        BidDelegates::resign(bob()).unwrap();
+        assert_eq!(BidDelegates::beneficiary(bob()), bob());
+        assert_eq!(BidDelegates::get(john()), None);
+        assert_eq!(
+            BidDelegates::resign(bob()),
+            Err(TxError::BidDelegationNotFound)
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Budgets of the organizations: named subaccounts with spending limits and delegated spenders.
+//!
+//! An organization creates a budget on one of its subaccounts with `create_budget`, optionally
+//! under a parent budget, and funds it with the regular transfers. The delegates of the budget
+//! spend from it with `budget_transfer` up to the spending limit. A spending counts towards the
+//! limits of the budget and all its ancestors, so a parent budget caps the total spendings of its
+//! sub-budgets. The organization itself moves the tokens of its subaccounts without limits.
+//! Every budget keeps the total spent by its delegates, for the per-budget reports.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::account::{AccountInternal, Subaccount, DEFAULT_SUBACCOUNT};
+use crate::error::TxError;
+use crate::state::balances::{PrincipalKey, PRINCIPAL_KEY_SIZE, SUBACCOUNT_MAX_LENGTH_IN_BYTES};
+use crate::state::config::Timestamp;
+
+/// Maximum number of the budgets of one organization.
+pub const MAX_BUDGETS_PER_ORGANIZATION: usize = 100;
+
+/// Maximum number of the delegates of one budget.
+pub const MAX_BUDGET_DELEGATES: usize = 16;
+
+/// Maximum number of the levels of the budget hierarchy.
+pub const MAX_BUDGET_DEPTH: usize = 4;
+
+/// Maximum length of a budget name in bytes.
+pub const MAX_BUDGET_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Budget {
+    pub organization: Principal,
+    /// Subaccount of the organization holding the tokens of the budget.
+    pub subaccount: Subaccount,
+    pub name: String,
+    pub parent: Option<Subaccount>,
+    /// Maximum total amount the delegates can spend from the budget and its sub-budgets. The
+    /// transfer fees are paid by the budget on top of it.
+    pub spending_limit: Tokens128,
+    pub spent: Tokens128,
+    pub delegates: Vec<Principal>,
+    /// Number of the transfers made from the budget itself.
+    pub transfers: u64,
+    pub created_at: Timestamp,
+}
+
+impl Budget {
+    pub fn account(&self) -> AccountInternal {
+        AccountInternal::new(self.organization, Some(self.subaccount))
+    }
+
+    pub fn remaining(&self) -> Tokens128 {
+        self.spending_limit.saturating_sub(self.spent)
+    }
+}
+
+impl Storable for Budget {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode budget").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode budget")
+    }
+}
+
+impl BoundedStorable for Budget {
+    // `MAX_BUDGET_DELEGATES + 1` principals, two subaccounts, the name, two amounts and two
+    // integers with the type table.
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Budget with its balance, as reported to the organization.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct BudgetReport {
+    pub budget: Budget,
+    pub balance: Tokens128,
+    pub remaining: Tokens128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct BudgetKey(PrincipalKey, Subaccount);
+
+const BUDGET_KEY_SIZE: usize = PRINCIPAL_KEY_SIZE + SUBACCOUNT_MAX_LENGTH_IN_BYTES;
+
+impl BudgetKey {
+    fn new(organization: Principal, subaccount: Subaccount) -> Self {
+        Self(organization.into(), subaccount)
+    }
+}
+
+impl Storable for BudgetKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(BUDGET_KEY_SIZE);
+        bytes.extend_from_slice(&self.0.to_bytes());
+        bytes.extend_from_slice(&self.1);
+        bytes.into()
+    }
+
+    /// Expected `bytes.len() == BUDGET_KEY_SIZE`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (organization, subaccount) = bytes.split_at(PRINCIPAL_KEY_SIZE);
+        Self(
+            PrincipalKey::from_bytes(organization.to_vec().into()),
+            subaccount.try_into().expect("invalid subaccount length"),
+        )
+    }
+}
+
+impl BoundedStorable for BudgetKey {
+    const MAX_SIZE: u32 = BUDGET_KEY_SIZE as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+pub struct Budgets;
+
+impl Budgets {
+    pub fn create(
+        organization: Principal,
+        subaccount: Subaccount,
+        name: String,
+        parent: Option<Subaccount>,
+        spending_limit: Tokens128,
+        now: Timestamp,
+    ) -> Result<Budget, TxError> {
+        if subaccount == DEFAULT_SUBACCOUNT {
+            return Err(invalid("budget cannot use the default subaccount"));
+        }
+
+        if name.is_empty() || name.len() > MAX_BUDGET_NAME_LENGTH {
+            return Err(invalid(format!(
+                "name must be between 1 and {MAX_BUDGET_NAME_LENGTH} bytes long"
+            )));
+        }
+
+        if Self::get(organization, subaccount).is_some() {
+            return Err(invalid("subaccount already has a budget"));
+        }
+
+        let budgets = Self::list(organization);
+        if budgets.len() >= MAX_BUDGETS_PER_ORGANIZATION {
+            return Err(invalid(format!(
+                "at most {MAX_BUDGETS_PER_ORGANIZATION} budgets can be created"
+            )));
+        }
+
+        if let Some(parent) = parent {
+            let ancestors = Self::ancestors(organization, parent)?;
+            if ancestors.len() >= MAX_BUDGET_DEPTH {
+                return Err(invalid(format!(
+                    "budgets can be nested at most {MAX_BUDGET_DEPTH} levels deep"
+                )));
+            }
+        }
+
+        let budget = Budget {
+            organization,
+            subaccount,
+            name,
+            parent,
+            spending_limit,
+            spent: Tokens128::ZERO,
+            delegates: vec![],
+            transfers: 0,
+            created_at: now,
+        };
+        Self::insert(&budget);
+
+        Ok(budget)
+    }
+
+    pub fn get(organization: Principal, subaccount: Subaccount) -> Option<Budget> {
+        BUDGETS.with(|map| map.borrow().get(&BudgetKey::new(organization, subaccount)))
+    }
+
+    pub fn list(organization: Principal) -> Vec<Budget> {
+        BUDGETS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, budget)| budget)
+                .filter(|budget| budget.organization == organization)
+                .collect()
+        })
+    }
+
+    /// Sets the spending limit of the budget. The amount already spent counts towards the new
+    /// limit.
+    pub fn set_limit(
+        organization: Principal,
+        subaccount: Subaccount,
+        spending_limit: Tokens128,
+    ) -> Result<Budget, TxError> {
+        let mut budget = Self::get(organization, subaccount).ok_or(TxError::BudgetNotFound)?;
+        budget.spending_limit = spending_limit;
+        Self::insert(&budget);
+
+        Ok(budget)
+    }
+
+    pub fn set_delegates(
+        organization: Principal,
+        subaccount: Subaccount,
+        mut delegates: Vec<Principal>,
+    ) -> Result<Budget, TxError> {
+        let mut budget = Self::get(organization, subaccount).ok_or(TxError::BudgetNotFound)?;
+        delegates.sort();
+        delegates.dedup();
+        if delegates.len() > MAX_BUDGET_DELEGATES {
+            return Err(invalid(format!(
+                "at most {MAX_BUDGET_DELEGATES} delegates can be set"
+            )));
+        }
+
+        if delegates.contains(&Principal::anonymous()) {
+            return Err(invalid("anonymous principal cannot be a delegate"));
+        }
+
+        budget.delegates = delegates;
+        Self::insert(&budget);
+
+        Ok(budget)
+    }
+
+    /// Removes the budget. The tokens stay on the subaccount of the organization.
+    pub fn remove(organization: Principal, subaccount: Subaccount) -> Result<(), TxError> {
+        Self::get(organization, subaccount).ok_or(TxError::BudgetNotFound)?;
+        let has_children = Self::list(organization)
+            .iter()
+            .any(|budget| budget.parent == Some(subaccount));
+        if has_children {
+            return Err(invalid("budget has sub-budgets"));
+        }
+
+        BUDGETS.with(|map| {
+            map.borrow_mut()
+                .remove(&BudgetKey::new(organization, subaccount))
+        });
+        Ok(())
+    }
+
+    /// Checks that the `delegate` can spend `amount` from the budget, within the limits of the
+    /// budget and all its ancestors.
+    pub fn check_spending(
+        organization: Principal,
+        subaccount: Subaccount,
+        delegate: Principal,
+        amount: Tokens128,
+    ) -> Result<Budget, TxError> {
+        let budget = Self::get(organization, subaccount).ok_or(TxError::BudgetNotFound)?;
+        if !budget.delegates.contains(&delegate) {
+            return Err(TxError::Unauthorized);
+        }
+
+        if amount.is_zero() {
+            return Err(TxError::AmountTooSmall);
+        }
+
+        let remaining = Self::ancestors(organization, subaccount)?
+            .iter()
+            .map(Budget::remaining)
+            .min_by_key(|remaining| remaining.amount)
+            .unwrap_or_default();
+        if amount > remaining {
+            return Err(TxError::BudgetLimitExceeded { remaining });
+        }
+
+        Ok(budget)
+    }
+
+    /// Records the spending of `amount` from the budget in the budget and all its ancestors.
+    pub fn record_spending(organization: Principal, subaccount: Subaccount, amount: Tokens128) {
+        let Ok(budgets) = Self::ancestors(organization, subaccount) else {
+            return;
+        };
+
+        for mut budget in budgets {
+            budget.spent = (budget.spent + amount).unwrap_or_else(|| Tokens128::from(u128::MAX));
+            if budget.subaccount == subaccount {
+                budget.transfers += 1;
+            }
+            Self::insert(&budget);
+        }
+    }
+
+    /// Removes the budgets of the `organization`. Returns the number of the removed ones.
+    pub fn remove_organization(organization: Principal) -> usize {
+        let budgets = Self::list(organization);
+        BUDGETS.with(|map| {
+            let mut map = map.borrow_mut();
+            for budget in &budgets {
+                map.remove(&BudgetKey::new(organization, budget.subaccount));
+            }
+        });
+
+        budgets.len()
+    }
+
+    pub fn clear() {
+        BUDGETS.with(|map| map.borrow_mut().clear());
+    }
+
+    /// Returns the budget and its ancestors, the budget first.
+    fn ancestors(organization: Principal, subaccount: Subaccount) -> Result<Vec<Budget>, TxError> {
+        let mut budgets = vec![];
+        let mut next = Some(subaccount);
+        while let Some(subaccount) = next {
+            let budget = Self::get(organization, subaccount).ok_or(TxError::BudgetNotFound)?;
+            next = budget.parent;
+            budgets.push(budget);
+        }
+
+        Ok(budgets)
+    }
+
+    fn insert(budget: &Budget) {
+        BUDGETS.with(|map| {
+            map.borrow_mut().insert(
+                BudgetKey::new(budget.organization, budget.subaccount),
+                budget.clone(),
+            )
+        });
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> TxError {
+    TxError::InvalidBudget {
+        reason: reason.into(),
+    }
+}
+
+const BUDGETS_MEMORY_ID: MemoryId = MemoryId::new(47);
+
+thread_local! {
+    static BUDGETS: RefCell<StableBTreeMap<BudgetKey, Budget>> =
+        RefCell::new(StableBTreeMap::new(BUDGETS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn spending_is_limited_by_the_hierarchy() {
+        MockContext::new().inject();
+        Budgets::clear();
+
+        let marketing = [1; 32];
+        let events = [2; 32];
+        Budgets::create(alice(), marketing, "marketing".into(), None, 100.into(), 0).unwrap();
+        Budgets::create(
+            alice(),
+            events,
+            "events".into(),
+            Some(marketing),
+            80.into(),
+            0,
+        )
+        .unwrap();
+        Budgets::set_delegates(alice(), events, vec![bob()]).unwrap();
+
+        assert!(Budgets::create(alice(), events, "again".into(), None, 1.into(), 0).is_err());
+        assert_eq!(
+            Budgets::check_spending(alice(), events, john(), 10.into()),
+            Err(TxError::Unauthorized)
+        );
+
+        Budgets::record_spending(alice(), marketing, 30.into());
+        assert_eq!(
+            Budgets::check_spending(alice(), events, bob(), 71.into()),
+            Err(TxError::BudgetLimitExceeded {
+                remaining: 70.into()
+            })
+        );
+
+        Budgets::check_spending(alice(), events, bob(), 70.into()).unwrap();
+        Budgets::record_spending(alice(), events, 70.into());
+        let events_budget = Budgets::get(alice(), events).unwrap();
+        assert_eq!(
+            (events_budget.spent, events_budget.transfers),
+            (70.into(), 1)
+        );
+        assert_eq!(Budgets::get(alice(), marketing).unwrap().spent, 100.into());
+
+        assert!(Budgets::remove(alice(), marketing).is_err());
+        Budgets::remove(alice(), events).unwrap();
+        Budgets::remove(alice(), marketing).unwrap();
+        assert!(Budgets::list(alice()).is_empty());
+    }
+}
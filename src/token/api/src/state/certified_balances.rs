@@ -0,0 +1,230 @@
+//! Hash tree of the balances certified in the certified data of the canister, so the balance of any
+//! account can be returned by a query with a certificate, see `canister::certified_balances`.
+//!
+//! The tree is keyed by the `AccountKey` bytes of the accounts, and its leaves are the balances as
+//! 16 bytes big-endian integers. The accounts without balances are not in the tree, so their zero
+//! balances are certified by the witnesses of absence.
+//!
+//! The balance writes only mark the accounts as changed, and the tree is updated by the timer task
+//! in batches of at most `MAX_CERTIFIED_UPDATES_PER_RUN` accounts to bound the cost of a transfer.
+//! The certified balances can therefore lag behind the current ones by a few seconds. The tree is
+//! kept in the heap memory and is built anew in batches after every upgrade, and when more than
+//! `MAX_CHANGED_ACCOUNTS` accounts wait for the update, so the changed set doesn't grow without
+//! limit if the timer task falls behind.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_certified_map::{AsHashTree, Hash, HashTree, RbTree};
+use ic_stable_structures::Storable;
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::balances::{AccountKey, Balances, StableBalances};
+
+/// Maximum number of the accounts updated in the tree by one run of the timer task.
+pub const MAX_CERTIFIED_UPDATES_PER_RUN: usize = 1_000;
+
+/// Maximum number of the changed accounts not moved to the tree yet.
+pub const MAX_CHANGED_ACCOUNTS: usize = 100_000;
+
+#[derive(Debug, Default)]
+struct TreeBuild {
+    /// Last account added by the build, `None` if it didn't start.
+    cursor: Option<AccountKey>,
+    completed: bool,
+}
+
+pub struct CertifiedBalances;
+
+impl CertifiedBalances {
+    /// Marks the balance of the `account` as changed. Called by `StableBalances` on every write.
+    pub(crate) fn mark_changed(account: AccountInternal) {
+        let is_full = CHANGED.with(|changed| {
+            let mut changed = changed.borrow_mut();
+            changed.insert(account.into());
+            changed.len() > MAX_CHANGED_ACCOUNTS
+        });
+        if is_full {
+            Self::reset();
+        }
+    }
+
+    /// Returns `true` if the tree is behind the balances.
+    pub fn is_pending() -> bool {
+        !BUILD.with(|build| build.borrow().completed)
+            || CHANGED.with(|changed| !changed.borrow().is_empty())
+    }
+
+    /// Updates the tree with at most `limit` balances: the ones not added by the build yet, then
+    /// the changed ones. Returns `true` if the root hash changed.
+    pub fn update(limit: usize) -> bool {
+        let mut updated = 0;
+        BUILD.with(|build| {
+            let mut build = build.borrow_mut();
+            if build.completed {
+                return;
+            }
+
+            // The accounts written before the cursor during the build are marked as changed, and
+            // the ones after it are read by the next batches.
+            let accounts = StableBalances::accounts_after(build.cursor, limit);
+            build.completed = accounts.len() < limit;
+            build.cursor = accounts.last().copied().or(build.cursor);
+            updated += accounts.len();
+            for account in accounts {
+                Self::update_leaf(account);
+            }
+        });
+
+        let changed = CHANGED.with(|changed| {
+            let mut changed = changed.borrow_mut();
+            let mut accounts = Vec::new();
+            while accounts.len() < limit - updated {
+                let Some(account) = changed.pop_first() else {
+                    break;
+                };
+                accounts.push(account);
+            }
+            accounts
+        });
+        updated += changed.len();
+        for account in changed {
+            Self::update_leaf(account);
+        }
+
+        updated > 0
+    }
+
+    fn update_leaf(account: AccountKey) {
+        let balance = StableBalances.balance_of(&account.account());
+        TREE.with(|tree| {
+            let mut tree = tree.borrow_mut();
+            let key = account.to_bytes().into_owned();
+            if balance.is_zero() {
+                tree.delete(&key);
+            } else {
+                tree.insert(key, balance.amount.to_be_bytes().to_vec());
+            }
+        });
+    }
+
+    pub fn root_hash() -> Hash {
+        TREE.with(|tree| tree.borrow().root_hash())
+    }
+
+    /// Returns the certified balance of the `account` and the witness of it passed to `f`. Fails
+    /// if the account is not added to the tree by the build after an upgrade yet.
+    pub fn with_witness<R>(
+        account: AccountInternal,
+        f: impl FnOnce(Tokens128, HashTree<'_>) -> R,
+    ) -> Result<R, TxError> {
+        let account = AccountKey::from(account);
+        let is_built = BUILD.with(|build| {
+            let build = build.borrow();
+            build.completed || Some(account) <= build.cursor
+        });
+        if !is_built {
+            return Err(TxError::BalanceNotCertifiedYet);
+        }
+
+        let key = account.to_bytes();
+        Ok(TREE.with(|tree| {
+            let tree = tree.borrow();
+            let balance = tree.get(&key).map_or(0, |leaf| {
+                u128::from_be_bytes(leaf.as_slice().try_into().expect("invalid balance leaf"))
+            });
+            f(balance.into(), tree.witness(&key))
+        }))
+    }
+
+    /// Drops the tree, so it's built anew from the current balances.
+    pub fn reset() {
+        TREE.with(|tree| *tree.borrow_mut() = RbTree::new());
+        CHANGED.with(|changed| changed.borrow_mut().clear());
+        BUILD.with(|build| *build.borrow_mut() = TreeBuild::default());
+    }
+}
+
+thread_local! {
+    static TREE: RefCell<RbTree<Vec<u8>, Vec<u8>>> = RefCell::new(RbTree::new());
+    static CHANGED: RefCell<BTreeSet<AccountKey>> = RefCell::default();
+    static BUILD: RefCell<TreeBuild> = RefCell::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn certified_balance(account: AccountInternal) -> Result<Tokens128, TxError> {
+        CertifiedBalances::with_witness(account, |balance, _| balance)
+    }
+
+    #[test]
+    fn tree_follows_balances_in_batches() {
+        MockContext::new().inject();
+        StableBalances.clear();
+
+        let (alice, bob) = (AccountInternal::from(alice()), AccountInternal::from(bob()));
+        StableBalances.insert(alice, 100.into());
+        StableBalances.insert(bob, 50.into());
+        // Built anew, e.g. after an upgrade.
+        CertifiedBalances::reset();
+        assert!(CertifiedBalances::is_pending());
+        assert_eq!(
+            certified_balance(alice),
+            Err(TxError::BalanceNotCertifiedYet)
+        );
+
+        let empty_hash = CertifiedBalances::root_hash();
+        assert!(CertifiedBalances::update(1));
+        assert_ne!(CertifiedBalances::root_hash(), empty_hash);
+        while CertifiedBalances::update(1) {}
+        assert!(!CertifiedBalances::is_pending());
+        assert_eq!(certified_balance(alice), Ok(100.into()));
+        assert_eq!(certified_balance(bob), Ok(50.into()));
+
+        StableBalances.insert(alice, 70.into());
+        StableBalances.remove(&bob);
+        // Not certified until the tree is updated.
+        assert_eq!(certified_balance(alice), Ok(100.into()));
+        assert!(CertifiedBalances::update(MAX_CERTIFIED_UPDATES_PER_RUN));
+        assert_eq!(certified_balance(alice), Ok(70.into()));
+        assert_eq!(certified_balance(bob), Ok(0.into()));
+        assert!(!CertifiedBalances::update(MAX_CERTIFIED_UPDATES_PER_RUN));
+    }
+
+    #[test]
+    fn tree_is_rebuilt_when_too_many_accounts_changed() {
+        MockContext::new().inject();
+        StableBalances.clear();
+        CertifiedBalances::reset();
+
+        let alice = AccountInternal::from(alice());
+        StableBalances.insert(alice, 100.into());
+        while CertifiedBalances::update(MAX_CERTIFIED_UPDATES_PER_RUN) {}
+        assert_eq!(certified_balance(alice), Ok(100.into()));
+
+        for i in 0..MAX_CHANGED_ACCOUNTS {
+            let mut subaccount = [0; 32];
+            subaccount[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            CertifiedBalances::mark_changed(AccountInternal::new(bob(), Some(subaccount)));
+        }
+        assert_eq!(certified_balance(alice), Ok(100.into()));
+
+        CertifiedBalances::mark_changed(alice);
+        assert!(CertifiedBalances::is_pending());
+        assert_eq!(
+            certified_balance(alice),
+            Err(TxError::BalanceNotCertifiedYet)
+        );
+        assert!(CertifiedBalances::update(MAX_CERTIFIED_UPDATES_PER_RUN));
+        assert!(!CertifiedBalances::is_pending());
+        assert_eq!(certified_balance(alice), Ok(100.into()));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, HashSet};
 use std::{borrow::Cow, cell::RefCell};
 
 use canister_sdk::ic_helpers::tokens::Tokens128;
@@ -5,6 +6,56 @@ use ic_exports::candid::{CandidType, Decode, Deserialize, Encode, Int, Nat};
 use ic_exports::Principal;
 use ic_stable_structures::{MemoryId, StableCell, Storable};
 
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::dust::DustPolicy;
+use crate::state::faucet::FaucetPolicy;
+use crate::state::fees::{self, FeePolicy};
+use crate::state::large_transfers::LargeTransferPolicy;
+use crate::state::ledger::{Memo, RetentionPolicy, MAX_MEMO_LENGTH};
+use crate::state::{StateVersion, STATE_VERSION};
+
+pub const LOGO_METADATA_KEY: &str = "icrc1:logo";
+pub const MAX_METADATA_ENTRIES: usize = 32;
+pub const MAX_METADATA_KEY_SIZE: usize = 64;
+pub const MAX_METADATA_VALUE_SIZE: usize = 1024;
+pub const MAX_LOGO_SIZE: usize = 32 * 1024;
+/// Maximum number of the locales with the localized metadata.
+pub const MAX_LOCALES: usize = 32;
+/// Maximum length of a locale tag, enough for the usual BCP 47 tags like `zh-Hant-TW`.
+pub const MAX_LOCALE_SIZE: usize = 35;
+/// Prefix of the `icrc1_metadata` keys of the localized metadata, which are
+/// `is20:localized:<locale>:name`, `is20:localized:<locale>:symbol` and
+/// `is20:localized:<locale>:description`.
+pub const LOCALIZED_METADATA_KEY_PREFIX: &str = "is20:localized:";
+
+/// Default maximum age of the `created_at_time` of a transfer, in nanoseconds.
+pub const TX_WINDOW: u64 = 60_000_000_000;
+/// Default maximum difference by which the `created_at_time` of a transfer can be ahead of the
+/// token time, in nanoseconds.
+pub const PERMITTED_DRIFT: u64 = 2 * 60_000_000_000;
+/// Bounds of `TxWindow::tx_window_nanos`. The deduplication index keeps the transfers for the
+/// whole window, so it can't be arbitrarily long.
+pub const MIN_TX_WINDOW: u64 = 10_000_000_000;
+pub const MAX_TX_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Upper bound of `TxWindow::permitted_drift_nanos`.
+pub const MAX_PERMITTED_DRIFT: u64 = 10 * 60_000_000_000;
+
+/// Upper bound of `TokenConfig::auction_vesting_days`.
+pub const MAX_AUCTION_VESTING_DAYS: u32 = 365;
+
+/// Default `InputLimits::max_batch_entries`.
+pub const DEFAULT_MAX_BATCH_ENTRIES: u32 = 500;
+/// Default `InputLimits::max_listed_accounts`.
+pub const DEFAULT_MAX_LISTED_ACCOUNTS: u32 = 1_000;
+/// Upper bound of `InputLimits::max_batch_entries` and `InputLimits::max_listed_accounts`, so a
+/// call fits in the instruction limit.
+pub const MAX_INPUT_LIMIT: u32 = 10_000;
+
+/// Metadata keys which values are taken from the token configuration fields.
+const RESERVED_METADATA_KEYS: &[&str] =
+    &["icrc1:symbol", "icrc1:name", "icrc1:decimals", "icrc1:fee"];
+
 #[derive(Deserialize, CandidType, Clone, Debug)]
 pub struct TokenConfig {
     pub name: String,
@@ -16,9 +67,222 @@ pub struct TokenConfig {
     pub deploy_time: u64,
     pub min_cycles: u64,
     pub is_test_token: bool,
+    /// Additional entries returned by `icrc1_metadata`. Optional to keep the config compatible
+    /// with the stored state of the older versions.
+    pub metadata_entries: Option<BTreeMap<String, Value>>,
+    /// Account used to mint and burn tokens with `icrc1_transfer`. If not set, the default
+    /// account of the owner is used.
+    pub minting_account: Option<Account>,
+    /// Limits of the transaction history. If not set, the history is not pruned by the token.
+    pub history_retention: Option<RetentionPolicy>,
+    /// ICP ledger used by the ICP bridge. If not set, ICP deposits and withdrawals are disabled.
+    pub icp_ledger: Option<Principal>,
+    /// Distribution of the auction rewards between the bidders. If not set, `Linear` is used.
+    pub auction_strategy: Option<AuctionStrategy>,
+    /// Index of the transactions by memo used by `get_transactions_by_memo`. Disabled if not set.
+    pub memo_index: Option<bool>,
+    /// Limits of the stored auction history. If not set, the auctions are not pruned.
+    pub auction_retention: Option<RetentionPolicy>,
+    /// Canister which installed the token, usually the token factory. Only it can withdraw the
+    /// cycles of the token. Not set for the tokens installed by the older versions.
+    pub deployer: Option<Principal>,
+    /// ICRC-1 token backing the supply 1:1 in the wrapping mode. If not set, deposits and
+    /// withdrawals of the underlying token are disabled.
+    pub wrapped_token: Option<Principal>,
+    /// Threshold above which the transfers must be confirmed in two steps. If not set, transfers
+    /// of any amount are executed immediately.
+    pub large_transfer_policy: Option<LargeTransferPolicy>,
+    /// Limits of the minting of a test token by the users. If not set, anyone can mint any amount.
+    pub faucet_policy: Option<FaucetPolicy>,
+    /// Set when the ownership is renounced without keeping the administration rights. The owner
+    /// setters are disabled then for good.
+    pub ownership_renounced: Option<bool>,
+    /// Version of the stable state layout, see `state::migrations`. Not set by the versions
+    /// before the migrations were introduced, which is the version 0.
+    pub state_version: Option<StateVersion>,
+    /// Transaction window used to reject the old transfers and to deduplicate the transfers. If
+    /// not set, `TX_WINDOW` and `PERMITTED_DRIFT` are used.
+    pub tx_window: Option<TxWindow>,
+    /// Translations of the token metadata by the locale, e.g. `fr` or `pt-BR`. Returned by
+    /// `icrc1_metadata` in addition to the other entries.
+    pub localized_metadata: Option<BTreeMap<String, LocalizedMetadata>>,
+    /// Handling of the balances below the dust threshold by the maintenance task. If not set,
+    /// the dust balances are kept and counted as usual.
+    pub dust_policy: Option<DustPolicy>,
+    /// Delay of the changes of the fee and of the owner, see `state::timelock`. If not set, the
+    /// changes are applied at once.
+    pub timelock_delay_nanos: Option<u64>,
+    /// Share of the transfer amounts burned on every transfer. If not set, nothing is burned.
+    pub burn_policy: Option<BurnPolicy>,
+    /// Principals which accounts have no voting power at the snapshots taken while they are
+    /// listed, e.g. the treasury, see `state::snapshots`.
+    pub voting_exclusions: Option<Vec<Principal>>,
+    /// Limits of the sizes of the call arguments. If not set, `InputLimits::default()` is used.
+    pub input_limits: Option<InputLimits>,
+    /// Computation of the transfer fees, see `state::fees`. If not set, the flat `fee` is charged.
+    pub fee_policy: Option<FeePolicy>,
+    /// Number of days the auction rewards vest over, see `state::vesting`. If not set, the rewards
+    /// are credited to the bidders at once.
+    pub auction_vesting_days: Option<u32>,
+}
+
+/// Translated token metadata for one locale. The fields which are not set are not translated.
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct LocalizedMetadata {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub description: Option<String>,
+}
+
+impl LocalizedMetadata {
+    fn entries(&self) -> impl Iterator<Item = (&'static str, &String)> {
+        [
+            ("name", &self.name),
+            ("symbol", &self.symbol),
+            ("description", &self.description),
+        ]
+        .into_iter()
+        .filter_map(|(field, value)| Some((field, value.as_ref()?)))
+    }
+}
+
+/// Time bounds of the `created_at_time` of the transfers. Transfers created more than
+/// `tx_window_nanos` ago or more than `permitted_drift_nanos` in the future are rejected, and
+/// duplicates are detected within the sum of both.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TxWindow {
+    pub tx_window_nanos: u64,
+    pub permitted_drift_nanos: u64,
+}
+
+impl Default for TxWindow {
+    fn default() -> Self {
+        Self {
+            tx_window_nanos: TX_WINDOW,
+            permitted_drift_nanos: PERMITTED_DRIFT,
+        }
+    }
+}
+
+/// Limits of the sizes of the call arguments, checked before anything is changed, so a large call
+/// is rejected instead of trapping midway after burning the cycles.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct InputLimits {
+    /// Maximum number of the entries of `batch_transfer` and `atomic_batch`.
+    pub max_batch_entries: u32,
+    /// Maximum length of a transfer memo, at most `MAX_MEMO_LENGTH`.
+    pub max_memo_length: u32,
+    /// Maximum number of the accounts returned by one listing call, e.g. `get_holders`.
+    pub max_listed_accounts: u32,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        Self {
+            max_batch_entries: DEFAULT_MAX_BATCH_ENTRIES,
+            max_memo_length: MAX_MEMO_LENGTH as _,
+            max_listed_accounts: DEFAULT_MAX_LISTED_ACCOUNTS,
+        }
+    }
+}
+
+impl InputLimits {
+    pub fn validate(&self) -> Result<(), TxError> {
+        let invalid = |reason: String| Err(TxError::InvalidInputLimits { reason });
+        if !(1..=MAX_INPUT_LIMIT).contains(&self.max_batch_entries) {
+            return invalid(format!(
+                "maximum batch entries must be between 1 and {MAX_INPUT_LIMIT}"
+            ));
+        }
+
+        if !(1..=MAX_INPUT_LIMIT).contains(&self.max_listed_accounts) {
+            return invalid(format!(
+                "maximum listed accounts must be between 1 and {MAX_INPUT_LIMIT}"
+            ));
+        }
+
+        if self.max_memo_length as usize > MAX_MEMO_LENGTH {
+            return invalid(format!(
+                "maximum memo length must be at most {MAX_MEMO_LENGTH}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_batch(&self, entries: usize) -> Result<(), TxError> {
+        if entries > self.max_batch_entries as usize {
+            return Err(TxError::BatchTooLarge {
+                max_entries: self.max_batch_entries,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn check_memo(&self, memo: Option<&Memo>) -> Result<(), TxError> {
+        match memo {
+            Some(memo) if memo.len() > self.max_memo_length as usize => Err(TxError::MemoTooLong {
+                max_length: self.max_memo_length as _,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of the accounts to list if `requested` ones are requested.
+    pub fn listed_accounts(&self, requested: usize) -> usize {
+        requested.min(self.max_listed_accounts as usize)
+    }
+}
+
+impl TxWindow {
+    pub fn validate(&self) -> Result<(), TxError> {
+        let invalid = |reason: String| Err(TxError::InvalidTxWindow { reason });
+        if !(MIN_TX_WINDOW..=MAX_TX_WINDOW).contains(&self.tx_window_nanos) {
+            return invalid(format!(
+                "transaction window must be between {MIN_TX_WINDOW} and {MAX_TX_WINDOW} nanoseconds"
+            ));
+        }
+
+        if self.permitted_drift_nanos > MAX_PERMITTED_DRIFT {
+            return invalid(format!(
+                "permitted drift must be at most {MAX_PERMITTED_DRIFT} nanoseconds"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Age after which the transfers can't be duplicated anymore.
+    pub fn dedup_window_nanos(&self) -> u64 {
+        self.tx_window_nanos + self.permitted_drift_nanos
+    }
 }
 
 impl TokenConfig {
+    /// Returns true if the owner can't use the owner methods after the ownership was renounced.
+    pub fn is_ownership_renounced(&self) -> bool {
+        self.ownership_renounced.unwrap_or_default()
+    }
+
+    pub fn state_version(&self) -> StateVersion {
+        self.state_version.unwrap_or_default()
+    }
+
+    pub fn tx_window(&self) -> TxWindow {
+        self.tx_window.unwrap_or_default()
+    }
+
+    pub fn input_limits(&self) -> InputLimits {
+        self.input_limits.unwrap_or_default()
+    }
+
+    /// Duration of the vesting of the auction rewards, `None` if they are not vested.
+    pub fn auction_vesting_nanos(&self) -> Option<u64> {
+        self.auction_vesting_days
+            .map(|days| days as u64 * 24 * 60 * 60 * 1_000_000_000)
+    }
+
     /// Get config data stored in stable memory.
     pub fn get_stable() -> TokenConfig {
         CELL.with(|c| c.borrow().get().clone())
@@ -30,8 +294,21 @@ impl TokenConfig {
             .expect("unable to set token config to stable memory")
     }
 
-    pub fn fee_info(&self) -> (Tokens128, Principal) {
-        (self.fee, self.fee_to)
+    /// Returns the fee of the transfer of the `amount` from the `from` account to the `to` account,
+    /// and the principal receiving it.
+    pub fn fee_info(
+        &self,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+    ) -> (Tokens128, Principal) {
+        (fees::transfer_fee(self, from, to, amount), self.fee_to)
+    }
+
+    pub fn minting_account(&self) -> AccountInternal {
+        self.minting_account
+            .map(AccountInternal::from)
+            .unwrap_or_else(|| self.owner.into())
     }
 
     pub fn supported_standards(&self) -> Vec<StandardRecord> {
@@ -48,7 +325,7 @@ impl TokenConfig {
     }
 
     pub fn icrc1_metadata(&self) -> Vec<(String, Value)> {
-        vec![
+        let mut metadata = vec![
             ("icrc1:symbol".to_string(), Value::Text(self.symbol.clone())),
             ("icrc1:name".to_string(), Value::Text(self.name.clone())),
             (
@@ -56,7 +333,112 @@ impl TokenConfig {
                 Value::Nat(Nat::from(self.decimals)),
             ),
             ("icrc1:fee".to_string(), Value::Nat(self.fee.amount.into())),
-        ]
+        ];
+
+        if let Some(entries) = &self.metadata_entries {
+            metadata.extend(entries.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+
+        for (locale, localized) in self.localized_metadata.iter().flatten() {
+            metadata.extend(localized.entries().map(|(field, value)| {
+                (
+                    format!("{LOCALIZED_METADATA_KEY_PREFIX}{locale}:{field}"),
+                    Value::Text(value.clone()),
+                )
+            }));
+        }
+
+        metadata
+    }
+
+    /// Checks if the translations can be set for the `locale`.
+    pub fn validate_localized_metadata(
+        &self,
+        locale: &str,
+        metadata: &LocalizedMetadata,
+    ) -> Result<(), TxError> {
+        let invalid = |reason: &str| {
+            Err(TxError::InvalidMetadataEntry {
+                reason: reason.to_string(),
+            })
+        };
+
+        if locale.is_empty()
+            || locale.len() > MAX_LOCALE_SIZE
+            || !locale
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-')
+        {
+            return invalid("locale must be a language tag like `en` or `pt-BR`");
+        }
+
+        let locales = self.localized_metadata.as_ref();
+        if !locales.map_or(false, |l| l.contains_key(locale))
+            && locales.map_or(0, |l| l.len()) >= MAX_LOCALES
+        {
+            return invalid("too many locales");
+        }
+
+        if metadata.entries().next().is_none() {
+            return invalid("no translated fields");
+        }
+
+        if metadata
+            .entries()
+            .any(|(_, value)| value.len() > MAX_METADATA_VALUE_SIZE)
+        {
+            return invalid("value is too large");
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the entry can be added to the `icrc1_metadata` of the token.
+    pub fn validate_metadata_entry(&self, key: &str, value: &Value) -> Result<(), TxError> {
+        let invalid = |reason: &str| {
+            Err(TxError::InvalidMetadataEntry {
+                reason: reason.to_string(),
+            })
+        };
+
+        if key.is_empty() || key.len() > MAX_METADATA_KEY_SIZE {
+            return invalid("key length is out of bounds");
+        }
+
+        if RESERVED_METADATA_KEYS.contains(&key) {
+            return invalid("key is reserved for the token configuration fields");
+        }
+
+        if key.starts_with(LOCALIZED_METADATA_KEY_PREFIX) {
+            return invalid("key is reserved for the localized metadata");
+        }
+
+        let entries = self.metadata_entries.as_ref();
+        if !entries.map_or(false, |e| e.contains_key(key))
+            && entries.map_or(0, |e| e.len()) >= MAX_METADATA_ENTRIES
+        {
+            return invalid("too many metadata entries");
+        }
+
+        if key == LOGO_METADATA_KEY {
+            return match value {
+                Value::Text(logo) if logo.len() > MAX_LOGO_SIZE => invalid("logo is too large"),
+                Value::Text(logo) if is_data_url(logo) => Ok(()),
+                _ => invalid("logo must be a data URL"),
+            };
+        }
+
+        let value_size = match value {
+            Value::Text(text) => text.len(),
+            Value::Blob(blob) => blob.len(),
+            Value::Nat(_) | Value::Int(_) => 0,
+        };
+
+        if value_size > MAX_METADATA_VALUE_SIZE {
+            return invalid("value is too large");
+        }
+
+        Ok(())
     }
 
     pub fn get_metadata(&self) -> Metadata {
@@ -68,6 +450,7 @@ impl TokenConfig {
             fee: self.fee,
             fee_to: self.fee_to,
             is_test_token: Some(self.is_test_token),
+            init_spec: None,
         }
     }
 }
@@ -84,6 +467,29 @@ impl Default for TokenConfig {
             deploy_time: 0,
             min_cycles: 0,
             is_test_token: false,
+            metadata_entries: None,
+            minting_account: None,
+            history_retention: None,
+            icp_ledger: None,
+            auction_strategy: None,
+            memo_index: None,
+            auction_retention: None,
+            deployer: None,
+            wrapped_token: None,
+            large_transfer_policy: None,
+            faucet_policy: None,
+            ownership_renounced: None,
+            // Nothing is stored in a new canister, so there is nothing to migrate.
+            state_version: Some(STATE_VERSION),
+            tx_window: None,
+            localized_metadata: None,
+            dust_policy: None,
+            timelock_delay_nanos: None,
+            burn_policy: None,
+            voting_exclusions: None,
+            input_limits: None,
+            fee_policy: None,
+            auction_vesting_days: None,
         }
     }
 }
@@ -112,6 +518,21 @@ impl StandardRecord {
     }
 }
 
+/// Arguments of `renounce_ownership`. The confirmation fields must be filled explicitly, so the
+/// ownership can't be renounced by accident.
+#[derive(Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct RenounceOwnershipArgs {
+    /// Governance canister which becomes the owner, or the management canister (`aaaaa-aa`) to
+    /// make the token immutable.
+    pub new_owner: Principal,
+    /// If set, the new owner can use the owner methods. Otherwise they are disabled for good.
+    pub keep_admin_rights: bool,
+    /// Must be equal to the symbol of the token.
+    pub confirm_symbol: String,
+    /// Must be set to `true`.
+    pub confirm_irreversible: bool,
+}
+
 #[allow(non_snake_case)]
 #[derive(Deserialize, CandidType, Clone, Debug)]
 pub struct Metadata {
@@ -122,6 +543,99 @@ pub struct Metadata {
     pub fee: Tokens128,
     pub fee_to: Principal,
     pub is_test_token: Option<bool>,
+    /// Configuration applied by the init of the token. `None` in the metadata returned by the
+    /// token.
+    pub init_spec: Option<InitSpec>,
+}
+
+/// Maximum number of the accounts in the initial distribution.
+pub const MAX_INITIAL_DISTRIBUTION: usize = 1_000;
+
+/// Configuration applied by the token init in addition to the metadata, so the token is launched
+/// fully configured instead of being set up with mints and setters after the deployment.
+#[derive(Deserialize, CandidType, Clone, Debug, Default, PartialEq, Eq)]
+pub struct InitSpec {
+    /// Initial balances, which must sum up to the initial supply. If empty, the whole initial
+    /// supply is minted to the owner.
+    pub distribution: Vec<(Account, Tokens128)>,
+    pub fee_policy: Option<FeePolicy>,
+    pub features: TokenFeatures,
+}
+
+/// Optional features of the token, which the owner can change later with the corresponding
+/// setters.
+#[derive(Deserialize, CandidType, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenFeatures {
+    /// Index the transactions by memo, see `set_memo_index`.
+    pub memo_index: bool,
+    pub faucet_policy: Option<FaucetPolicy>,
+    pub large_transfer_policy: Option<LargeTransferPolicy>,
+}
+
+impl InitSpec {
+    /// Checks that the spec can be applied to a token with the initial `supply`.
+    pub fn validate(&self, supply: Tokens128) -> Result<(), TxError> {
+        let invalid = |reason: &str| {
+            Err(TxError::InvalidInitSpec {
+                reason: reason.to_string(),
+            })
+        };
+
+        if self.distribution.len() > MAX_INITIAL_DISTRIBUTION {
+            return invalid("too many accounts in the distribution");
+        }
+
+        let mut accounts = HashSet::with_capacity(self.distribution.len());
+        let mut total = Tokens128::ZERO;
+        for (account, amount) in &self.distribution {
+            if amount.is_zero() {
+                return invalid("distributed amount must be greater than zero");
+            }
+
+            if !accounts.insert(AccountInternal::from(*account)) {
+                return invalid("duplicate account in the distribution");
+            }
+
+            total = match total + *amount {
+                Some(total) => total,
+                None => return invalid("distribution overflows the total supply"),
+            };
+        }
+
+        if !self.distribution.is_empty() && total != supply {
+            return invalid("distribution must sum up to the initial supply");
+        }
+
+        if let Some(policy) = &self.fee_policy {
+            policy.validate()?;
+        }
+
+        if let Some(policy) = &self.features.faucet_policy {
+            policy.validate()?;
+        }
+
+        if let Some(policy) = &self.features.large_transfer_policy {
+            policy.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Changes of the token metadata requested by the owner through the deployer of the token, see
+/// `update_metadata_from_deployer`. The fields which are not set are left unchanged.
+#[derive(Deserialize, CandidType, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataPatch {
+    pub name: Option<String>,
+    /// Data URL of the logo image, stored as the `icrc1:logo` metadata entry.
+    pub logo: Option<String>,
+    pub fee: Option<Tokens128>,
+}
+
+impl MetadataPatch {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.logo.is_none() && self.fee.is_none()
+    }
 }
 
 // 10T cycles is an equivalent of approximately $10. This should be enough to last the canister
@@ -130,6 +644,7 @@ pub const DEFAULT_MIN_CYCLES: u64 = 10_000_000_000_000;
 
 impl From<Metadata> for TokenConfig {
     fn from(md: Metadata) -> Self {
+        let spec = md.init_spec.unwrap_or_default();
         Self {
             name: md.name,
             symbol: md.symbol,
@@ -140,6 +655,29 @@ impl From<Metadata> for TokenConfig {
             deploy_time: canister_sdk::ic_kit::ic::time(),
             min_cycles: DEFAULT_MIN_CYCLES,
             is_test_token: md.is_test_token.unwrap_or(false),
+            metadata_entries: None,
+            minting_account: None,
+            history_retention: None,
+            icp_ledger: None,
+            auction_strategy: None,
+            memo_index: spec.features.memo_index.then_some(true),
+            auction_retention: None,
+            deployer: None,
+            wrapped_token: None,
+            large_transfer_policy: spec.features.large_transfer_policy,
+            faucet_policy: spec.features.faucet_policy,
+            ownership_renounced: None,
+            // A new token has the current layout, there is nothing to migrate.
+            state_version: Some(STATE_VERSION),
+            tx_window: None,
+            localized_metadata: None,
+            dust_policy: None,
+            timelock_delay_nanos: None,
+            burn_policy: None,
+            voting_exclusions: None,
+            input_limits: None,
+            fee_policy: spec.fee_policy,
+            auction_vesting_days: None,
         }
     }
 }
@@ -153,6 +691,8 @@ pub struct TokenInfo {
     pub deployTime: Timestamp,
     pub holderNumber: usize,
     pub cycles: u64,
+    /// Optional to decode the info of the older versions, which don't return it.
+    pub tx_window: Option<TxWindow>,
 }
 
 /// Variant type for the metadata endpoint
@@ -166,23 +706,81 @@ pub enum Value {
 
 pub type Timestamp = u64;
 
-#[derive(CandidType, Default, Debug, Copy, Clone, Deserialize, PartialEq)]
-pub struct FeeRatio(f64);
+/// Checks that the string is a data URL of an image, e.g. `data:image/png;base64,iVBORw0KGgo=`.
+fn is_data_url(url: &str) -> bool {
+    match url
+        .strip_prefix("data:image/")
+        .and_then(|rest| rest.split_once(','))
+    {
+        Some((media_type, data)) => !media_type.is_empty() && !data.is_empty(),
+        None => false,
+    }
+}
+
+/// Ratio from 0 to 1 stored as a fixed-point number with `FixedRatio::DENOMINATOR` as the
+/// denominator, so the amounts are split by the integer arithmetic without rounding drift.
+#[derive(CandidType, Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FixedRatio(u64);
+
+impl FixedRatio {
+    pub const DENOMINATOR: u64 = 1_000_000_000_000;
+    pub const ONE: Self = Self(Self::DENOMINATOR);
+
+    /// Creates the ratio `numerator / DENOMINATOR`, clamped to 1.
+    pub fn new(numerator: u64) -> Self {
+        Self(numerator.min(Self::DENOMINATOR))
+    }
+
+    /// Converts the `value` clamped to the [0, 1] range, rounding to the nearest representable
+    /// ratio. `NaN` is converted to zero.
+    pub fn from_f64(value: f64) -> Self {
+        if value.is_nan() {
+            return Self::default();
+        }
+
+        Self::new((value.clamp(0.0, 1.0) * Self::DENOMINATOR as f64).round() as u64)
+    }
+
+    pub fn numerator(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns `amount * self` rounded down. Never overflows, as the ratio is at most 1.
+    pub fn apply(&self, amount: Tokens128) -> Tokens128 {
+        let numerator = self.0 as u128;
+        let denominator = Self::DENOMINATOR as u128;
+        // Both products are less than `amount` and `denominator^2` respectively, which fit u128.
+        let whole = amount.amount / denominator * numerator;
+        let remainder = amount.amount % denominator * numerator / denominator;
+        Tokens128::from(whole + remainder)
+    }
+}
+
+impl From<FixedRatio> for f64 {
+    fn from(v: FixedRatio) -> Self {
+        v.0 as f64 / FixedRatio::DENOMINATOR as f64
+    }
+}
+
+/// Share of the transaction fees which goes to the cycle auction.
+#[derive(CandidType, Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+pub struct FeeRatio(FixedRatio);
 
 impl FeeRatio {
+    /// The auction provides the ratio as `f64`, it's converted to the fixed-point ratio once here.
     pub fn new(value: f64) -> Self {
-        let adj_value = value.clamp(0.0, 1.0);
-        Self(adj_value)
+        Self(FixedRatio::from_f64(value))
+    }
+
+    pub fn from_fixed(ratio: FixedRatio) -> Self {
+        Self(ratio)
     }
 
     /// Returns the tupple (raw_fee, auction_fee). Raw fee is the fee amount to be transferred to
-    /// the canister owner, and auction_fee is the portion of the fee for the cycle auction.
+    /// the canister owner, and auction_fee is the portion of the fee for the cycle auction. The
+    /// sum of the two is always equal to the `fee`.
     pub(crate) fn get_value(&self, fee: Tokens128) -> (Tokens128, Tokens128) {
-        // Both auction fee and owner fee have the same purpose of providing the tokens to pay for
-        // the canister operations. As such we do not care much about rounding errors in this case.
-        // The only important thing to make sure that the sum of auction fee and the owner fee is
-        // equal to the total fee amount.
-        let auction_fee_amount = Tokens128::from((f64::from(fee) * self.0) as u128);
+        let auction_fee_amount = self.0.apply(fee);
         let owner_fee_amount = fee.saturating_sub(auction_fee_amount);
 
         (owner_fee_amount, auction_fee_amount)
@@ -191,7 +789,69 @@ impl FeeRatio {
 
 impl From<FeeRatio> for f64 {
     fn from(v: FeeRatio) -> Self {
-        v.0
+        v.0.into()
+    }
+}
+
+/// Share of the amount burned on every transfer of a deflationary token, see
+/// `transfer_internal`. The burn is charged from the transferred amount, so the recipient receives
+/// the amount without the burned share, while the fee is charged as usual. To burn instead of
+/// charging the fee, the owner sets the fee to zero.
+#[derive(CandidType, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+pub struct BurnPolicy {
+    /// Burned share in basis points, i.e. hundredths of a percent.
+    pub rate_bps: u16,
+}
+
+impl BurnPolicy {
+    pub const MAX_RATE_BPS: u16 = 5_000;
+
+    pub fn validate(&self) -> Result<(), TxError> {
+        if self.rate_bps == 0 || self.rate_bps > Self::MAX_RATE_BPS {
+            return Err(TxError::InvalidBurnPolicy {
+                reason: format!("rate must be between 1 and {} bps", Self::MAX_RATE_BPS),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Share of the transfer `amount` to burn, rounded down.
+    pub fn burned_amount(&self, amount: Tokens128) -> Tokens128 {
+        FixedRatio::new(self.rate_bps as u64 * (FixedRatio::DENOMINATOR / 10_000)).apply(amount)
+    }
+}
+
+/// Distribution of the accumulated fees between the bidders of a cycle auction.
+#[derive(CandidType, Debug, Copy, Clone, Deserialize, PartialEq)]
+pub enum AuctionStrategy {
+    /// Rewards are proportional to the bid cycles.
+    Linear,
+    /// Rewards are proportional to the square root of the bid cycles, as in quadratic funding,
+    /// which reduces the advantage of the largest bidders.
+    Quadratic,
+    /// Rewards are proportional to the bid cycles, but no bidder receives more than `max_share`
+    /// (from 0 to 1) of the accumulated fees. The excess is distributed between the other
+    /// bidders, or left for the next auction if every bidder reached the cap.
+    Capped { max_share: f64 },
+}
+
+impl Default for AuctionStrategy {
+    fn default() -> Self {
+        Self::Linear
+    }
+}
+
+impl AuctionStrategy {
+    pub fn validate(&self) -> Result<(), TxError> {
+        match self {
+            Self::Capped { max_share } if !(*max_share > 0.0 && *max_share <= 1.0) => {
+                Err(TxError::InvalidAuctionStrategy {
+                    reason: "max share must be in (0, 1] range".into(),
+                })
+            }
+            _ => Ok(()),
+        }
     }
 }
 
@@ -203,3 +863,139 @@ thread_local! {
                 .expect("stable memory token config initialization failed"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn fixed_ratio_conversion() {
+        assert_eq!(
+            FixedRatio::from_f64(0.5).numerator(),
+            FixedRatio::DENOMINATOR / 2
+        );
+        assert_eq!(FixedRatio::from_f64(2.0), FixedRatio::ONE);
+        assert_eq!(FixedRatio::from_f64(-1.0), FixedRatio::default());
+        assert_eq!(FixedRatio::from_f64(f64::NAN), FixedRatio::default());
+        assert_eq!(f64::from(FixedRatio::from_f64(0.25)), 0.25);
+    }
+
+    #[test]
+    fn fee_split_is_exact() {
+        let ratio = FeeRatio::new(0.1);
+        assert_eq!(ratio.get_value(100.into()), (90.into(), 10.into()));
+        assert_eq!(ratio.get_value(9.into()), (9.into(), 0.into()));
+        assert_eq!(
+            FeeRatio::new(1.0).get_value(u128::MAX.into()),
+            (0.into(), u128::MAX.into())
+        );
+    }
+
+    #[test]
+    fn burned_amount_is_rounded_down() {
+        let policy = BurnPolicy { rate_bps: 250 };
+        assert_eq!(policy.burned_amount(1_000.into()), 25.into());
+        assert_eq!(policy.burned_amount(39.into()), 0.into());
+        assert!(policy.validate().is_ok());
+        assert!(BurnPolicy { rate_bps: 0 }.validate().is_err());
+        assert!(BurnPolicy { rate_bps: 5_001 }.validate().is_err());
+    }
+
+    #[test]
+    fn init_spec_distribution_sums_to_supply() {
+        let alice = Account::new(Principal::management_canister(), None);
+        let bob = Account::new(Principal::anonymous(), None);
+        let spec = |distribution: Vec<(Account, Tokens128)>| InitSpec {
+            distribution,
+            ..Default::default()
+        };
+        let invalid = |reason: &str| {
+            Err(TxError::InvalidInitSpec {
+                reason: reason.into(),
+            })
+        };
+
+        assert!(spec(vec![]).validate(1_000.into()).is_ok());
+        assert!(spec(vec![(alice, 600.into()), (bob, 400.into())])
+            .validate(1_000.into())
+            .is_ok());
+        assert_eq!(
+            spec(vec![(alice, 600.into())]).validate(1_000.into()),
+            invalid("distribution must sum up to the initial supply")
+        );
+        assert_eq!(
+            spec(vec![(alice, 500.into()), (alice, 500.into())]).validate(1_000.into()),
+            invalid("duplicate account in the distribution")
+        );
+        assert_eq!(
+            spec(vec![(alice, u128::MAX.into()), (bob, 1.into())]).validate(1_000.into()),
+            invalid("distribution overflows the total supply")
+        );
+
+        let spec = InitSpec {
+            features: TokenFeatures {
+                faucet_policy: Some(FaucetPolicy {
+                    daily_cap: 0.into(),
+                    cooldown_nanos: 0,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            spec.validate(0.into()),
+            Err(TxError::InvalidFaucetPolicy { .. })
+        ));
+    }
+
+    /// `amount * numerator / denominator` rounded down, computed independently of
+    /// `FixedRatio::apply` by the long multiplication and division of the 64-bit limbs.
+    fn mul_div_floor(amount: u128, numerator: u64, denominator: u64) -> u128 {
+        let low = (amount as u64 as u128) * numerator as u128;
+        let high = (amount >> 64) * numerator as u128;
+        let middle = (low >> 64) + (high as u64 as u128);
+        let limbs = [
+            ((high >> 64) + (middle >> 64)) as u64,
+            middle as u64,
+            low as u64,
+        ];
+
+        let mut quotient = [0u64; 3];
+        let mut remainder = 0u128;
+        for (i, limb) in limbs.into_iter().enumerate() {
+            let value = (remainder << 64) | limb as u128;
+            quotient[i] = (value / denominator as u128) as u64;
+            remainder = value % denominator as u128;
+        }
+
+        assert_eq!(quotient[0], 0, "the ratio is at most 1");
+        ((quotient[1] as u128) << 64) | quotient[2] as u128
+    }
+
+    proptest! {
+        #[test]
+        fn fee_parts_match_reference(
+            fee in any::<u128>(),
+            numerator in 0..=FixedRatio::DENOMINATOR,
+        ) {
+            let (owner_fee, auction_fee) =
+                FeeRatio::from_fixed(FixedRatio::new(numerator)).get_value(fee.into());
+            let expected = mul_div_floor(fee, numerator, FixedRatio::DENOMINATOR);
+            prop_assert_eq!(auction_fee, Tokens128::from(expected));
+            prop_assert_eq!(owner_fee, Tokens128::from(fee - expected));
+        }
+
+        #[test]
+        fn larger_ratio_gives_larger_auction_fee(
+            fee in any::<u128>(),
+            a in 0..=FixedRatio::DENOMINATOR,
+            b in 0..=FixedRatio::DENOMINATOR,
+        ) {
+            let fee = Tokens128::from(fee);
+            let (low, high) = (a.min(b), a.max(b));
+            prop_assert!(FixedRatio::new(low).apply(fee) <= FixedRatio::new(high).apply(fee));
+        }
+    }
+}
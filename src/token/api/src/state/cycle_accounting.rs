@@ -0,0 +1,248 @@
+//! Rolling average of the cycles consumed by the token, used to decide how much of the fees goes
+//! to the cycle auction and whether the token needs the bids at all.
+//!
+//! The balance of the canister is sampled on the update calls, at most once per
+//! `SAMPLE_INTERVAL`. The consumption of an interval is the decrease of the balance, with the bids
+//! accepted in the interval added back, so the bids don't hide the consumption. The other top-ups
+//! (e.g. `deposit_cycles` of the management canister) can't be seen by the token and make the
+//! consumption of their interval zero.
+//!
+//! The consumption is measured in the auction periods of "runway": the number of periods the
+//! cycles above `min_cycles` last at the average consumption. The auction fee ratio is the inverse
+//! of the runway, so all the fees go to the bidders when the runway is shorter than one period,
+//! and the bids are rejected when the runway is longer than `MAX_BIDDING_RUNWAY_PERIODS`.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::state::config::Timestamp;
+
+/// Minimum time between two samples of the balance, in nanoseconds.
+pub const SAMPLE_INTERVAL: Timestamp = 60 * 60 * 1_000_000_000;
+
+/// Number of the latest samples the average consumption is computed from.
+pub const MAX_CONSUMPTION_SAMPLES: usize = 24;
+
+/// Bids are rejected while the cycles above `min_cycles` last longer than this number of the
+/// auction periods.
+pub const MAX_BIDDING_RUNWAY_PERIODS: f64 = 100.0;
+
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+struct AccountingState {
+    /// Time and balance of the latest sample.
+    last_sample: Option<(Timestamp, u64)>,
+    /// Cycles of the bids accepted since the latest sample.
+    received: u64,
+    /// Consumption of the latest intervals in cycles per second, oldest first.
+    rates: Vec<u64>,
+}
+
+impl Storable for AccountingState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode cycle accounting")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode cycle accounting")
+    }
+}
+
+pub struct CycleAccounting;
+
+impl CycleAccounting {
+    /// Samples the `balance` if `SAMPLE_INTERVAL` passed since the latest sample.
+    pub fn observe(now: Timestamp, balance: u64) {
+        Self::update(|state| {
+            let Some((sampled_at, sampled_balance)) = state.last_sample else {
+                state.last_sample = Some((now, balance));
+                return;
+            };
+
+            let elapsed = now.saturating_sub(sampled_at);
+            if elapsed < SAMPLE_INTERVAL {
+                return;
+            }
+
+            let consumed =
+                (sampled_balance as u128 + state.received as u128).saturating_sub(balance as u128);
+            let rate = consumed * 1_000_000_000 / elapsed as u128;
+            state.rates.push(rate.min(u64::MAX as u128) as u64);
+            if state.rates.len() > MAX_CONSUMPTION_SAMPLES {
+                state.rates.remove(0);
+            }
+
+            state.last_sample = Some((now, balance));
+            state.received = 0;
+        });
+    }
+
+    /// Accounts the `cycles` of an accepted bid, so they are not taken for a decrease of the
+    /// consumption.
+    pub fn record_bid(cycles: u64) {
+        Self::update(|state| state.received = state.received.saturating_add(cycles));
+    }
+
+    /// Average consumption over the latest `MAX_CONSUMPTION_SAMPLES` intervals in cycles per
+    /// second, `None` if no interval was sampled yet.
+    pub fn average_consumption() -> Option<u64> {
+        let rates = STATE.with(|cell| cell.borrow().get().rates.clone());
+        if rates.is_empty() {
+            return None;
+        }
+
+        let total: u128 = rates.iter().map(|rate| *rate as u128).sum();
+        Some((total / rates.len() as u128) as u64)
+    }
+
+    pub fn samples() -> usize {
+        STATE.with(|cell| cell.borrow().get().rates.len())
+    }
+
+    /// Number of the auction periods of `period_seconds` the cycles of the `balance` above the
+    /// `min_cycles` last at the average consumption. Zero if the balance is at most `min_cycles`,
+    /// infinite if nothing is consumed, and `None` if the consumption is not known yet.
+    pub fn runway_periods(balance: u64, min_cycles: u64, period_seconds: u64) -> Option<f64> {
+        if balance <= min_cycles {
+            return Some(0.0);
+        }
+
+        let consumed_per_period = Self::average_consumption()? as f64 * period_seconds as f64;
+        Some((balance - min_cycles) as f64 / consumed_per_period)
+    }
+
+    /// Part of the fees going to the auction: the inverse of the runway, up to 1. `None` if the
+    /// consumption is not known yet.
+    pub fn fee_ratio(balance: u64, min_cycles: u64, period_seconds: u64) -> Option<f64> {
+        Self::runway_periods(balance, min_cycles, period_seconds).map(|runway| {
+            if runway <= 1.0 {
+                1.0
+            } else {
+                1.0 / runway
+            }
+        })
+    }
+
+    /// Whether the token needs the bids: the runway is at most `MAX_BIDDING_RUNWAY_PERIODS`, or
+    /// not known yet.
+    pub fn accepts_bids(balance: u64, min_cycles: u64, period_seconds: u64) -> bool {
+        Self::runway_periods(balance, min_cycles, period_seconds)
+            .map_or(true, |runway| runway <= MAX_BIDDING_RUNWAY_PERIODS)
+    }
+
+    pub fn clear() {
+        Self::update(|state| *state = AccountingState::default());
+    }
+
+    fn update(f: impl FnOnce(&mut AccountingState)) {
+        STATE.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let mut state = cell.get().clone();
+            f(&mut state);
+            cell.set(state).expect("failed to write cycle accounting");
+        });
+    }
+}
+
+const CYCLE_ACCOUNTING_MEMORY_ID: MemoryId = MemoryId::new(44);
+
+thread_local! {
+    static STATE: RefCell<StableCell<AccountingState>> =
+        RefCell::new(StableCell::new(CYCLE_ACCOUNTING_MEMORY_ID, AccountingState::default())
+            .expect("unable to initialize cycle accounting"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    const HOUR: Timestamp = SAMPLE_INTERVAL;
+    const DAY_SECONDS: u64 = 24 * 60 * 60;
+
+    fn init() {
+        MockContext::new().inject();
+        CycleAccounting::clear();
+    }
+
+    #[test]
+    fn steady_consumption() {
+        init();
+
+        let mut balance = 10_000_000_000;
+        CycleAccounting::observe(0, balance);
+        assert_eq!(CycleAccounting::average_consumption(), None);
+        assert_eq!(CycleAccounting::fee_ratio(balance, 0, DAY_SECONDS), None);
+
+        // 3_600_000 cycles per hour, sampled every hour and more often.
+        for hour in 1..=30 {
+            balance -= 3_600_000;
+            CycleAccounting::observe(hour * HOUR - HOUR / 2, balance + 1_800_000);
+            CycleAccounting::observe(hour * HOUR, balance);
+        }
+
+        assert_eq!(CycleAccounting::samples(), MAX_CONSUMPTION_SAMPLES);
+        assert_eq!(CycleAccounting::average_consumption(), Some(1_000));
+
+        // 86_400_000 cycles per day.
+        let min_cycles = balance - 864_000_000;
+        assert_eq!(
+            CycleAccounting::runway_periods(balance, min_cycles, DAY_SECONDS),
+            Some(10.0)
+        );
+        assert_eq!(
+            CycleAccounting::fee_ratio(balance, min_cycles, DAY_SECONDS),
+            Some(0.1)
+        );
+        assert!(CycleAccounting::accepts_bids(
+            balance,
+            min_cycles,
+            DAY_SECONDS
+        ));
+    }
+
+    #[test]
+    fn bids_are_not_taken_for_lower_consumption() {
+        init();
+
+        CycleAccounting::observe(0, 1_000_000);
+        CycleAccounting::record_bid(500_000);
+        CycleAccounting::observe(HOUR, 1_500_000 - 3_600);
+        assert_eq!(CycleAccounting::average_consumption(), Some(1));
+
+        // A top-up not seen by the token counts as no consumption.
+        CycleAccounting::observe(2 * HOUR, 5_000_000);
+        assert_eq!(CycleAccounting::average_consumption(), Some(0));
+    }
+
+    #[test]
+    fn runway_limits_fee_ratio_and_bids() {
+        init();
+
+        CycleAccounting::observe(0, 1_000_000_000);
+        CycleAccounting::observe(HOUR, 1_000_000_000 - 36_000);
+        assert_eq!(CycleAccounting::average_consumption(), Some(10));
+
+        // Below `min_cycles` all the fees go to the bidders.
+        assert_eq!(CycleAccounting::fee_ratio(100, 200, 1), Some(1.0));
+        assert_eq!(CycleAccounting::fee_ratio(200, 100, 100), Some(1.0));
+        assert!(CycleAccounting::accepts_bids(100, 200, 1));
+
+        // Runway of 200 periods is too long for the bids.
+        assert_eq!(CycleAccounting::fee_ratio(2_100, 100, 1), Some(0.005));
+        assert!(!CycleAccounting::accepts_bids(2_100, 100, 1));
+
+        // Nothing consumed.
+        CycleAccounting::clear();
+        CycleAccounting::observe(0, 1_000);
+        CycleAccounting::observe(HOUR, 1_000);
+        assert_eq!(CycleAccounting::fee_ratio(1_000, 100, 1), Some(0.0));
+        assert!(!CycleAccounting::accepts_bids(1_000, 100, 1));
+    }
+}
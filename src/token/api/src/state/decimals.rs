@@ -0,0 +1,297 @@
+//! Migration of the token to a different number of decimals.
+//!
+//! All the balances and the fee are multiplied or divided by the power of ten of the difference.
+//! The balances are rescaled in batches by the repeated `rescale_decimals` calls, and all the
+//! transfers, mints and burns are rejected until the last batch is done. The decimals and the fee
+//! in the token config are changed together with the last batch, and a `Rescale` record with the
+//! new total supply is written to the ledger. The balances rounded down to zero are removed.
+//!
+//! The total of the burned tokens is rescaled as well. The amounts in the transaction history,
+//! the subscriptions and the swaps are not rescaled.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::balances::{AccountKey, Balances, StableBalances};
+use crate::state::config::{Timestamp, TokenConfig, Value};
+use crate::state::ledger::LedgerData;
+
+/// Number of the balances rescaled by one `rescale_decimals` call.
+const RESCALE_BATCH_SIZE: usize = 1000;
+const DECIMALS_MIGRATION_MEMORY_ID: MemoryId = MemoryId::new(15);
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct DecimalsMigration {
+    pub from_decimals: u8,
+    pub to_decimals: u8,
+    /// Number of the balance entries already rescaled.
+    pub processed: u64,
+    /// Last account rescaled, `None` before the first batch.
+    pub cursor: Option<Account>,
+    /// Sum of the rescaled balances, which is the total supply once the migration is completed.
+    pub rescaled_supply: Tokens128,
+    pub started_at: Timestamp,
+}
+
+impl DecimalsMigration {
+    /// Rescales the amount, rounding down if the number of decimals decreases. Returns `None` on
+    /// overflow.
+    pub fn rescale(&self, amount: Tokens128) -> Option<Tokens128> {
+        rescale_amount(amount.amount, self.from_decimals, self.to_decimals).map(Tokens128::from)
+    }
+
+    /// Starts the migration to `to_decimals`, or continues the running one, and rescales the next
+    /// batch of the balances. Returns the state of the migration, or `None` if it's completed.
+    pub fn run(caller: Principal, to_decimals: u8) -> Result<Option<Self>, TxError> {
+        Self::run_batch(caller, to_decimals, RESCALE_BATCH_SIZE)
+    }
+
+    fn run_batch(
+        caller: Principal,
+        to_decimals: u8,
+        batch_size: usize,
+    ) -> Result<Option<Self>, TxError> {
+        let mut migration = match Self::get() {
+            Some(migration) if migration.to_decimals == to_decimals => migration,
+            Some(_) => return Err(TxError::DecimalsMigrationInProgress),
+            None => Self::start(to_decimals)?,
+        };
+
+        let cursor = migration
+            .cursor
+            .map(|account| AccountKey::from(AccountInternal::from(account)));
+        let batch = StableBalances::accounts_after(cursor, batch_size);
+
+        for key in &batch {
+            let account = key.account();
+            let rescaled = migration
+                .rescale(StableBalances.balance_of(&account))
+                .expect("total supply is checked for overflow on start");
+            if rescaled.is_zero() {
+                StableBalances.remove(&account);
+            } else {
+                StableBalances.insert(account, rescaled);
+            }
+            migration.rescaled_supply = (migration.rescaled_supply + rescaled)
+                .expect("total supply is checked for overflow on start");
+        }
+        migration.processed += batch.len() as u64;
+        migration.cursor = batch
+            .last()
+            .map(|key| key.account().into())
+            .or(migration.cursor);
+
+        if batch.len() < batch_size {
+            migration.complete(caller);
+            Self::set(None);
+            Ok(None)
+        } else {
+            Self::set(Some(migration));
+            Ok(Some(migration))
+        }
+    }
+
+    fn start(to_decimals: u8) -> Result<Self, TxError> {
+        let config = TokenConfig::get_stable();
+        let from_decimals = config.decimals;
+        if from_decimals == to_decimals {
+            return Err(TxError::InvalidDecimals);
+        }
+
+        let migration = Self {
+            from_decimals,
+            to_decimals,
+            processed: 0,
+            cursor: None,
+            rescaled_supply: Tokens128::ZERO,
+            started_at: ic::time(),
+        };
+
+        // Every balance fits if the sum of them does.
+        migration
+            .rescale(StableBalances.total_supply())
+            .ok_or(TxError::AmountOverflow)?;
+        migration
+            .rescale(config.fee)
+            .ok_or(TxError::AmountOverflow)?;
+        if let Some(policy) = &config.fee_policy {
+            policy
+                .rescale(|amount| migration.rescale(amount))
+                .ok_or(TxError::AmountOverflow)?;
+        }
+
+        Ok(migration)
+    }
+
+    fn complete(&self, caller: Principal) {
+        let mut config = TokenConfig::get_stable();
+        config.decimals = self.to_decimals;
+        config.fee = self
+            .rescale(config.fee)
+            .expect("fee is checked for overflow on start");
+        config.fee_policy = config.fee_policy.map(|policy| {
+            policy
+                .rescale(|amount| self.rescale(amount))
+                .expect("fee policy is checked for overflow on start")
+        });
+        TokenConfig::set_stable(config);
+        LedgerData::set_burned_total(
+            self.rescale(LedgerData::burned_total())
+                .unwrap_or(u128::MAX.into()),
+        );
+
+        LedgerData::rescale(caller.into(), self.rescaled_supply);
+        AdminLog::record(
+            caller,
+            AdminAction::RescaleDecimals,
+            Some(Value::Nat(self.from_decimals.into())),
+            Some(Value::Nat(self.to_decimals.into())),
+        );
+    }
+
+    pub fn get() -> Option<Self> {
+        CELL.with(|cell| cell.borrow().get().0)
+    }
+
+    fn set(migration: Option<Self>) {
+        CELL.with(|cell| cell.borrow_mut().set(StorableMigration(migration)))
+            .expect("unable to set decimals migration to stable memory");
+    }
+
+    /// Transfers, mints and burns are not allowed while the migration runs.
+    pub fn check_not_running() -> Result<(), TxError> {
+        match Self::get() {
+            Some(_) => Err(TxError::DecimalsMigrationInProgress),
+            None => Ok(()),
+        }
+    }
+}
+
+fn rescale_amount(amount: u128, from_decimals: u8, to_decimals: u8) -> Option<u128> {
+    if to_decimals >= from_decimals {
+        let factor = 10u128.checked_pow((to_decimals - from_decimals) as u32);
+        match (amount, factor) {
+            (0, _) => Some(0),
+            (amount, Some(factor)) => amount.checked_mul(factor),
+            (_, None) => None,
+        }
+    } else {
+        match 10u128.checked_pow((from_decimals - to_decimals) as u32) {
+            Some(factor) => Some(amount / factor),
+            None => Some(0),
+        }
+    }
+}
+
+#[derive(Debug, Default, CandidType, Deserialize)]
+struct StorableMigration(Option<DecimalsMigration>);
+
+impl Storable for StorableMigration {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode decimals migration")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode decimals migration")
+    }
+}
+
+thread_local! {
+    static CELL: RefCell<StableCell<StorableMigration>> =
+        RefCell::new(StableCell::new(DECIMALS_MIGRATION_MEMORY_ID, StorableMigration::default())
+            .expect("unable to initialize decimals migration"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::ledger::Operation;
+
+    fn init(decimals: u8, fee: u128) {
+        MockContext::new().with_caller(john()).inject();
+        TokenConfig::set_stable(TokenConfig {
+            decimals,
+            fee: fee.into(),
+            ..TokenConfig::default()
+        });
+        StableBalances.clear();
+        LedgerData::clear();
+        DecimalsMigration::set(None);
+    }
+
+    #[test]
+    fn rescale_amounts() {
+        assert_eq!(rescale_amount(123_456, 8, 6), Some(1_234));
+        assert_eq!(rescale_amount(1_234, 6, 8), Some(123_400));
+        assert_eq!(rescale_amount(99, 8, 6), Some(0));
+        assert_eq!(rescale_amount(u128::MAX, 0, 1), None);
+        assert_eq!(rescale_amount(1, 0, 200), None);
+        assert_eq!(rescale_amount(0, 0, 200), Some(0));
+        assert_eq!(rescale_amount(u128::MAX, 200, 0), Some(0));
+    }
+
+    #[test]
+    fn balances_are_rescaled_in_batches() {
+        init(8, 10_000);
+        StableBalances.insert(alice().into(), 1_000_000.into());
+        StableBalances.insert(bob().into(), 50.into());
+        StableBalances.insert(john().into(), 300.into());
+
+        let migration = DecimalsMigration::run_batch(john(), 6, 2).unwrap().unwrap();
+        assert_eq!(migration.processed, 2);
+        assert!(migration.cursor.is_some());
+        assert_eq!(
+            DecimalsMigration::check_not_running(),
+            Err(TxError::DecimalsMigrationInProgress)
+        );
+        assert_eq!(
+            DecimalsMigration::run_batch(john(), 4, 2),
+            Err(TxError::DecimalsMigrationInProgress)
+        );
+        assert_eq!(TokenConfig::get_stable().decimals, 8);
+
+        assert_eq!(DecimalsMigration::run_batch(john(), 6, 2), Ok(None));
+        assert_eq!(DecimalsMigration::check_not_running(), Ok(()));
+
+        let config = TokenConfig::get_stable();
+        assert_eq!(config.decimals, 6);
+        assert_eq!(config.fee, 100.into());
+        assert_eq!(StableBalances.balance_of(&alice().into()), 10_000.into());
+        assert_eq!(StableBalances.get(&bob().into()), None);
+        assert_eq!(StableBalances.balance_of(&john().into()), 3.into());
+
+        let record = LedgerData::get(LedgerData::len() - 1).unwrap();
+        assert_eq!(record.operation, Operation::Rescale);
+        assert_eq!(record.amount, 10_003.into());
+    }
+
+    #[test]
+    fn rescale_checks() {
+        init(8, 0);
+        assert_eq!(
+            DecimalsMigration::run(john(), 8),
+            Err(TxError::InvalidDecimals)
+        );
+
+        StableBalances.insert(alice().into(), u128::MAX.into());
+        assert_eq!(
+            DecimalsMigration::run(john(), 10),
+            Err(TxError::AmountOverflow)
+        );
+        assert_eq!(DecimalsMigration::get(), None);
+    }
+}
@@ -0,0 +1,188 @@
+//! Index of the recent transfers used for the transactions deduplication.
+//!
+//! Every transfer made with `created_at_time` set is recorded in the index by the key consisting
+//! of its `created_at_time` and the hash of the caller account and transfer arguments. Since the
+//! keys are ordered by time first, the entries older than the deduplication window are pruned by
+//! removing the smallest keys.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+
+use crate::account::AccountInternal;
+use crate::state::config::Timestamp;
+use crate::state::ledger::TransferArgs;
+use crate::tx_record::TxId;
+
+const TIMESTAMP_SIZE: usize = 8;
+const HASH_SIZE: usize = 32;
+const DEDUP_KEY_SIZE: usize = TIMESTAMP_SIZE + HASH_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct DedupKey([u8; DEDUP_KEY_SIZE]);
+
+impl DedupKey {
+    fn new(from: AccountInternal, transfer: &TransferArgs, created_at_time: Timestamp) -> Self {
+        let to = AccountInternal::from(transfer.to);
+
+        let mut hasher = Sha256::new();
+        hasher.update(from.owner.as_slice());
+        hasher.update(from.subaccount);
+        hasher.update(to.owner.as_slice());
+        hasher.update(to.subaccount);
+        hasher.update(transfer.amount.amount.to_be_bytes());
+        match transfer.fee {
+            Some(fee) => {
+                hasher.update([1]);
+                hasher.update(fee.amount.to_be_bytes());
+            }
+            None => hasher.update([0]),
+        }
+        match &transfer.memo {
+            Some(memo) => {
+                hasher.update([1]);
+                hasher.update(memo);
+            }
+            None => hasher.update([0]),
+        }
+
+        let mut key = [0u8; DEDUP_KEY_SIZE];
+        // Big endian encoding keeps the keys ordered by the timestamp.
+        key[..TIMESTAMP_SIZE].copy_from_slice(&created_at_time.to_be_bytes());
+        key[TIMESTAMP_SIZE..].copy_from_slice(&hasher.finalize());
+        Self(key)
+    }
+
+    /// Smallest key with the given timestamp.
+    fn min_for_time(created_at_time: Timestamp) -> Self {
+        let mut key = [0u8; DEDUP_KEY_SIZE];
+        key[..TIMESTAMP_SIZE].copy_from_slice(&created_at_time.to_be_bytes());
+        Self(key)
+    }
+}
+
+impl Storable for DedupKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_slice().into()
+    }
+
+    /// Expected `bytes.len() == DEDUP_KEY_SIZE`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let mut buf = [0u8; DEDUP_KEY_SIZE];
+        buf.copy_from_slice(&bytes);
+        Self(buf)
+    }
+}
+
+impl BoundedStorable for DedupKey {
+    const MAX_SIZE: u32 = DEDUP_KEY_SIZE as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+pub struct DedupIndex;
+
+impl DedupIndex {
+    /// Returns the id of the transaction made with the same arguments, if there is one.
+    pub fn find(
+        from: AccountInternal,
+        transfer: &TransferArgs,
+        created_at_time: Timestamp,
+    ) -> Option<TxId> {
+        let key = DedupKey::new(from, transfer, created_at_time);
+        INDEX.with(|index| index.borrow().get(&key))
+    }
+
+    pub fn insert(
+        from: AccountInternal,
+        transfer: &TransferArgs,
+        created_at_time: Timestamp,
+        id: TxId,
+    ) {
+        let key = DedupKey::new(from, transfer, created_at_time);
+        INDEX.with(|index| index.borrow_mut().insert(key, id));
+    }
+
+    /// Removes all the entries created before the `min_time`.
+    pub fn prune(min_time: Timestamp) {
+        let threshold = DedupKey::min_for_time(min_time);
+        INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            let expired = index
+                .iter()
+                .map(|(key, _)| key)
+                .take_while(|key| *key < threshold)
+                .collect::<Vec<_>>();
+
+            for key in expired {
+                index.remove(&key);
+            }
+        });
+    }
+
+    pub fn len() -> u64 {
+        INDEX.with(|index| index.borrow().len())
+    }
+
+    pub fn clear() {
+        INDEX.with(|index| index.borrow_mut().clear());
+    }
+}
+
+const DEDUP_INDEX_MEMORY_ID: MemoryId = MemoryId::new(4);
+
+thread_local! {
+    static INDEX: RefCell<StableBTreeMap<DedupKey, TxId>> =
+        RefCell::new(StableBTreeMap::new(DEDUP_INDEX_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn transfer() -> TransferArgs {
+        TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            memo: None,
+            created_at_time: Some(1000),
+        }
+    }
+
+    #[test]
+    fn find_inserted_transfer() {
+        MockContext::new().inject();
+        DedupIndex::clear();
+
+        DedupIndex::insert(alice().into(), &transfer(), 1000, 7);
+        assert_eq!(DedupIndex::find(alice().into(), &transfer(), 1000), Some(7));
+        assert_eq!(DedupIndex::find(alice().into(), &transfer(), 1001), None);
+        assert_eq!(DedupIndex::find(bob().into(), &transfer(), 1000), None);
+
+        let mut other = transfer();
+        other.fee = Some(0.into());
+        assert_eq!(DedupIndex::find(alice().into(), &other, 1000), None);
+    }
+
+    #[test]
+    fn prune_old_entries() {
+        MockContext::new().inject();
+        DedupIndex::clear();
+
+        DedupIndex::insert(alice().into(), &transfer(), 1000, 1);
+        DedupIndex::insert(alice().into(), &transfer(), 2000, 2);
+        DedupIndex::insert(alice().into(), &transfer(), 3000, 3);
+
+        DedupIndex::prune(2000);
+        assert_eq!(DedupIndex::len(), 2);
+        assert_eq!(DedupIndex::find(alice().into(), &transfer(), 1000), None);
+        assert_eq!(DedupIndex::find(alice().into(), &transfer(), 2000), Some(2));
+    }
+}
@@ -0,0 +1,117 @@
+//! Retries of the event deliveries to the subscriber canisters, shared by the event subscriptions
+//! of the token (see `state::subscriptions`) and the event log of the factory.
+//!
+//! The new events are sent right after the message that recorded them. If a delivery to a
+//! subscriber fails, the subscriber is skipped until its retry delay passes, which grows with the
+//! failures in a row as in `outbound::retry_delay`, and the delivery is retried by the periodic
+//! timer task of the feed. After `MAX_FAILED_DELIVERIES` failures in a row the subscriber is
+//! suspended: its events are not sent until it subscribes again.
+//!
+//! The retry times are kept in the heap, so after an upgrade the failed subscribers are retried by
+//! the first run of the timer task.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
+
+use candid::Principal;
+
+use crate::state::config::Timestamp;
+use crate::state::outbound::retry_delay;
+
+pub const MAX_FAILED_DELIVERIES: u32 = 10;
+
+pub fn is_suspended(failed_attempts: u32) -> bool {
+    failed_attempts >= MAX_FAILED_DELIVERIES
+}
+
+/// Retry times of the subscribers of a feed whose last delivery failed.
+#[derive(Debug, Default)]
+pub struct DeliveryRetries {
+    next_attempt_at: BTreeMap<Principal, Timestamp>,
+}
+
+impl DeliveryRetries {
+    /// Whether the events are sent to the `subscriber` with `failed_attempts` failures in a row at
+    /// the time `now`.
+    pub fn is_due(&self, subscriber: Principal, failed_attempts: u32, now: Timestamp) -> bool {
+        !is_suspended(failed_attempts)
+            && self
+                .next_attempt_at
+                .get(&subscriber)
+                .map_or(true, |next_attempt_at| *next_attempt_at <= now)
+    }
+
+    /// Records the result of a delivery to the `subscriber` and returns its new number of the
+    /// failures in a row.
+    pub fn record(
+        &mut self,
+        subscriber: Principal,
+        failed_attempts: u32,
+        delivered: bool,
+        now: Timestamp,
+    ) -> u32 {
+        if delivered {
+            self.next_attempt_at.remove(&subscriber);
+            return 0;
+        }
+
+        let failed_attempts = failed_attempts.saturating_add(1);
+        self.next_attempt_at
+            .insert(subscriber, now.saturating_add(retry_delay(failed_attempts)));
+        failed_attempts
+    }
+
+    pub fn remove(&mut self, subscriber: Principal) {
+        self.next_attempt_at.remove(&subscriber);
+    }
+
+    pub fn clear(&mut self) {
+        self.next_attempt_at.clear();
+    }
+}
+
+/// Runs `flush` right after the current message. The calls made before it runs are merged into one
+/// by the `scheduled` flag.
+#[cfg(target_family = "wasm")]
+pub fn schedule_flush(scheduled: &'static LocalKey<Cell<bool>>, flush: fn()) {
+    scheduled.with(|flag| {
+        if !flag.replace(true) {
+            ic_exports::ic_cdk_timers::set_timer(std::time::Duration::ZERO, move || {
+                scheduled.with(|flag| flag.set(false));
+                flush();
+            });
+        }
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn schedule_flush(_scheduled: &'static LocalKey<Cell<bool>>, _flush: fn()) {}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::outbound::BASE_RETRY_DELAY_NANOS;
+
+    #[test]
+    fn failed_subscriber_backs_off_and_is_suspended() {
+        let mut retries = DeliveryRetries::default();
+        assert!(retries.is_due(alice(), 0, 0));
+
+        let failed_attempts = retries.record(alice(), 0, false, 0);
+        assert_eq!(failed_attempts, 1);
+        assert!(!retries.is_due(alice(), failed_attempts, BASE_RETRY_DELAY_NANOS - 1));
+        assert!(retries.is_due(alice(), failed_attempts, BASE_RETRY_DELAY_NANOS));
+        assert!(retries.is_due(bob(), 0, 0));
+
+        let failed_attempts = retries.record(alice(), MAX_FAILED_DELIVERIES - 1, false, 0);
+        assert!(is_suspended(failed_attempts));
+        assert!(!retries.is_due(alice(), failed_attempts, u64::MAX));
+
+        assert_eq!(retries.record(alice(), failed_attempts, true, 0), 0);
+        assert!(retries.is_due(alice(), 0, 0));
+    }
+}
@@ -0,0 +1,222 @@
+//! Handling of the balances below the dust threshold.
+//!
+//! The accounts with balances too small to be transferred (usually the remainders smaller than
+//! the fee) stay in the balances map forever. The owner can set a `DustPolicy` which makes the
+//! maintenance task of the token, see `canister::dust`, either sweep these balances to the
+//! `fee_to` account, or exclude the principals holding only dust from the holders count. The
+//! results of the task are returned by `get_dust_report`.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::{Timestamp, TokenConfig};
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum DustMode {
+    /// Balances below the threshold are moved to the `fee_to` account and their entries are
+    /// removed.
+    Sweep,
+    /// Principals holding only balances below the threshold are not counted as holders.
+    ExcludeFromHolders,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct DustPolicy {
+    /// Balances strictly below this amount are dust.
+    pub threshold: Tokens128,
+    pub mode: DustMode,
+}
+
+impl DustPolicy {
+    pub fn is_dust(&self, amount: Tokens128) -> bool {
+        amount < self.threshold
+    }
+}
+
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct DustReport {
+    /// Number of the non-empty balances swept since the policy was introduced.
+    pub swept_accounts: u64,
+    pub swept_total: Tokens128,
+    /// Number of the empty balance entries removed by the sweeps.
+    pub removed_empty_accounts: u64,
+    /// Number of the principals holding only dust found by the last complete pass over the
+    /// balances. Subtracted from the holders count in the `ExcludeFromHolders` mode.
+    pub dust_holders: u64,
+    pub last_pass_completed_at: Option<Timestamp>,
+}
+
+impl Storable for DustReport {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode dust report").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode dust report")
+    }
+}
+
+/// Progress of the pass over the balances, which takes several runs of the maintenance task. Kept
+/// in the heap, so a pass interrupted by an upgrade is started over.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct DustScan {
+    /// Index of the next balance entry to check.
+    pub cursor: usize,
+    /// The principal of the last checked entry, and whether all its entries checked so far are
+    /// dust.
+    pub current: Option<(Principal, bool)>,
+    pub dust_holders: u64,
+}
+
+impl DustScan {
+    /// Accounts the entry of the `owner`. The entries of one principal are checked in a row, as
+    /// the balances are ordered by the principal.
+    pub fn check(&mut self, owner: Principal, is_dust: bool) {
+        match &mut self.current {
+            Some((principal, all_dust)) if *principal == owner => *all_dust &= is_dust,
+            current => {
+                if let Some((_, true)) = current {
+                    self.dust_holders += 1;
+                }
+                *current = Some((owner, is_dust));
+            }
+        }
+    }
+
+    /// Returns the number of the dust holders found by the complete pass.
+    pub fn finish(mut self) -> u64 {
+        if let Some((_, true)) = self.current.take() {
+            self.dust_holders += 1;
+        }
+        self.dust_holders
+    }
+}
+
+pub struct DustReports;
+
+impl DustReports {
+    pub fn get() -> DustReport {
+        REPORT.with(|cell| cell.borrow().get().clone())
+    }
+
+    pub fn record_sweep(amount: Tokens128) {
+        Self::update(|report| {
+            if amount.is_zero() {
+                report.removed_empty_accounts += 1;
+            } else {
+                report.swept_accounts += 1;
+                report.swept_total =
+                    (report.swept_total + amount).unwrap_or_else(|| Tokens128::from(u128::MAX));
+            }
+        });
+    }
+
+    pub fn finish_pass(dust_holders: u64, now: Timestamp) {
+        Self::update(|report| {
+            report.dust_holders = dust_holders;
+            report.last_pass_completed_at = Some(now);
+        });
+    }
+
+    /// Number of the principals excluded from the holders count by the `policy`.
+    pub fn excluded_holders(policy: Option<DustPolicy>) -> u64 {
+        match policy {
+            Some(DustPolicy {
+                mode: DustMode::ExcludeFromHolders,
+                ..
+            }) => Self::get().dust_holders,
+            _ => 0,
+        }
+    }
+
+    /// Number of the principals holding tokens, without the principals excluded by the dust
+    /// policy.
+    pub fn holders_count() -> usize {
+        let excluded = Self::excluded_holders(TokenConfig::get_stable().dust_policy);
+        StableBalances
+            .holders_count()
+            .saturating_sub(excluded as usize)
+    }
+
+    pub(crate) fn take_scan() -> DustScan {
+        SCAN.with(|scan| scan.take())
+    }
+
+    pub(crate) fn set_scan(scan: DustScan) {
+        SCAN.with(|cell| cell.replace(scan));
+    }
+
+    fn update(f: impl FnOnce(&mut DustReport)) {
+        REPORT.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let mut report = cell.get().clone();
+            f(&mut report);
+            cell.set(report).expect("failed to write dust report");
+        });
+    }
+
+    pub fn clear() {
+        REPORT.with(|cell| {
+            cell.borrow_mut()
+                .set(DustReport::default())
+                .expect("failed to write dust report")
+        });
+        SCAN.with(|scan| scan.take());
+    }
+}
+
+const DUST_REPORT_MEMORY_ID: MemoryId = MemoryId::new(36);
+
+thread_local! {
+    static REPORT: RefCell<StableCell<DustReport>> =
+        RefCell::new(StableCell::new(DUST_REPORT_MEMORY_ID, DustReport::default())
+            .expect("unable to initialize dust report"));
+    static SCAN: std::cell::Cell<DustScan> = std::cell::Cell::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn dust_holders_are_counted_per_principal() {
+        let mut scan = DustScan::default();
+        scan.check(alice(), true);
+        scan.check(alice(), false);
+        scan.check(bob(), true);
+        scan.check(bob(), true);
+        scan.check(john(), true);
+        assert_eq!(scan.finish(), 2);
+    }
+
+    #[test]
+    fn report_is_updated() {
+        MockContext::new().inject();
+        DustReports::clear();
+
+        DustReports::record_sweep(5.into());
+        DustReports::record_sweep(0.into());
+        DustReports::finish_pass(3, 100);
+
+        let report = DustReports::get();
+        assert_eq!(report.swept_accounts, 1);
+        assert_eq!(report.swept_total, 5.into());
+        assert_eq!(report.removed_empty_accounts, 1);
+
+        let policy = DustPolicy {
+            threshold: 10.into(),
+            mode: DustMode::ExcludeFromHolders,
+        };
+        assert_eq!(DustReports::excluded_holders(Some(policy)), 3);
+        assert_eq!(DustReports::excluded_holders(None), 0);
+    }
+}
@@ -0,0 +1,213 @@
+//! Transfers with expiration, which the recipient must accept before they expire.
+//!
+//! The sender's tokens are moved to the escrow account of the transfer on creation. The recipient
+//! receives them by calling `accept_transfer` before `expires_at`. After that time the transfer
+//! can't be accepted anymore, and the sender takes the tokens back with `reclaim_transfer`.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal, Subaccount};
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+use crate::state::ledger::Memo;
+
+pub type ExpiringTransferId = u64;
+
+/// Maximum time between the creation and the expiration of a transfer.
+pub const MAX_TRANSFER_EXPIRY_NANOS: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+const ESCROW_SUBACCOUNT_PREFIX: &[u8] = b"is20-expiring-escrow";
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum ExpiringTransferStatus {
+    Pending,
+    Accepted,
+    Reclaimed,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ExpiringTransfer {
+    pub id: ExpiringTransferId,
+    pub from: Account,
+    pub to: Account,
+    /// Amount held in the escrow. The transfer fee was paid by the sender on creation.
+    pub amount: Tokens128,
+    pub memo: Option<Memo>,
+    pub created_at: Timestamp,
+    pub expires_at: Timestamp,
+    pub status: ExpiringTransferStatus,
+}
+
+impl ExpiringTransfer {
+    pub fn escrow_account(&self) -> AccountInternal {
+        AccountInternal::new(ic::id(), Some(escrow_subaccount(self.id)))
+    }
+}
+
+pub fn escrow_subaccount(id: ExpiringTransferId) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    subaccount[..ESCROW_SUBACCOUNT_PREFIX.len()].copy_from_slice(ESCROW_SUBACCOUNT_PREFIX);
+    subaccount[24..].copy_from_slice(&id.to_be_bytes());
+    subaccount
+}
+
+impl Storable for ExpiringTransfer {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode expiring transfer")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode expiring transfer")
+    }
+}
+
+impl BoundedStorable for ExpiringTransfer {
+    // Two principals, two subaccounts, an amount, two timestamps and a memo of at most
+    // `MAX_MEMO_LENGTH` bytes with the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct ExpiringTransfers;
+
+impl ExpiringTransfers {
+    /// Returns the id the next created transfer will have.
+    pub fn next_id() -> ExpiringTransferId {
+        NEXT_ID.with(|cell| *cell.borrow().get())
+    }
+
+    /// Stores a new pending transfer with the id returned by `next_id`.
+    pub fn insert_new(
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        memo: Option<Memo>,
+        created_at: Timestamp,
+        expires_at: Timestamp,
+    ) -> ExpiringTransfer {
+        let id = Self::next_id();
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(id + 1)
+                .expect("failed to write next expiring transfer id")
+        });
+
+        let transfer = ExpiringTransfer {
+            id,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            memo,
+            created_at,
+            expires_at,
+            status: ExpiringTransferStatus::Pending,
+        };
+        TRANSFERS.with(|map| map.borrow_mut().insert(id, transfer.clone()));
+        transfer
+    }
+
+    pub fn get(id: ExpiringTransferId) -> Option<ExpiringTransfer> {
+        TRANSFERS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns the transfers in which `who` is the sender or the recipient.
+    pub fn list(who: Principal) -> Vec<ExpiringTransfer> {
+        TRANSFERS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, transfer)| transfer)
+                .filter(|transfer| transfer.from.owner == who || transfer.to.owner == who)
+                .collect()
+        })
+    }
+
+    /// Changes the status of the pending transfer to `to`. Returns the transfer with the old
+    /// status.
+    pub fn settle(
+        id: ExpiringTransferId,
+        to: ExpiringTransferStatus,
+    ) -> Result<ExpiringTransfer, TxError> {
+        let transfer = Self::get(id).ok_or(TxError::ExpiringTransferNotFound)?;
+        if transfer.status != ExpiringTransferStatus::Pending {
+            return Err(TxError::InvalidExpiringTransferStatus {
+                status: transfer.status,
+            });
+        }
+
+        let mut updated = transfer.clone();
+        updated.status = to;
+        TRANSFERS.with(|map| map.borrow_mut().insert(id, updated));
+        Ok(transfer)
+    }
+
+    pub fn clear() {
+        TRANSFERS.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next expiring transfer id")
+        });
+    }
+}
+
+const EXPIRING_TRANSFERS_MEMORY_ID: MemoryId = MemoryId::new(50);
+const NEXT_EXPIRING_TRANSFER_ID_MEMORY_ID: MemoryId = MemoryId::new(51);
+
+thread_local! {
+    static TRANSFERS: RefCell<StableBTreeMap<ExpiringTransferId, ExpiringTransfer>> =
+        RefCell::new(StableBTreeMap::new(EXPIRING_TRANSFERS_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<ExpiringTransferId>> =
+        RefCell::new(StableCell::new(NEXT_EXPIRING_TRANSFER_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next expiring transfer id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn transfers_are_settled_once() {
+        MockContext::new().inject();
+        ExpiringTransfers::clear();
+
+        let transfer =
+            ExpiringTransfers::insert_new(alice().into(), bob().into(), 10.into(), None, 0, 100);
+        assert_eq!(transfer.id, 0);
+        assert_eq!(ExpiringTransfers::next_id(), 1);
+        assert_eq!(ExpiringTransfers::list(bob()), vec![transfer.clone()]);
+        assert!(ExpiringTransfers::list(john()).is_empty());
+
+        ExpiringTransfers::settle(transfer.id, ExpiringTransferStatus::Accepted).unwrap();
+        assert_eq!(
+            ExpiringTransfers::settle(transfer.id, ExpiringTransferStatus::Reclaimed),
+            Err(TxError::InvalidExpiringTransferStatus {
+                status: ExpiringTransferStatus::Accepted
+            })
+        );
+        assert_eq!(
+            ExpiringTransfers::settle(1, ExpiringTransferStatus::Reclaimed),
+            Err(TxError::ExpiringTransferNotFound)
+        );
+    }
+
+    #[test]
+    fn escrow_subaccounts_differ_from_swaps() {
+        assert_ne!(
+            escrow_subaccount(1),
+            crate::state::swaps::escrow_subaccount(1)
+        );
+        assert_eq!(&escrow_subaccount(1)[..20], ESCROW_SUBACCOUNT_PREFIX);
+    }
+}
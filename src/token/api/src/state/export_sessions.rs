@@ -0,0 +1,173 @@
+//! Export sessions pinning a consistent view of the balances and the transaction history for the
+//! exports taking many calls, e.g. of all the holders of a large token.
+//!
+//! Starting a session takes a balances snapshot, see `state::snapshots`, and records the length of
+//! the history. The chunks of the session read the balances at the snapshot and stop at the
+//! recorded length, so the transfers and the upgrades between the calls don't change the exported
+//! data. Both the snapshot and the session are stored in the stable memory. A session ends when its
+//! owner releases it or when it expires.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+use crate::state::ledger::LedgerData;
+use crate::state::snapshots::{SnapshotId, Snapshots};
+use crate::tx_record::TxId;
+
+/// Id of the session, which is the id of its balances snapshot.
+pub type ExportSessionId = SnapshotId;
+
+/// Time after which an unreleased session expires.
+pub const EXPORT_SESSION_TTL_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Maximum number of the sessions open at the same time.
+pub const MAX_EXPORT_SESSIONS: usize = 16;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ExportSession {
+    pub id: ExportSessionId,
+    pub owner: Principal,
+    /// Id of the first transaction made after the session started. The transactions of the session
+    /// end before it.
+    pub transactions_end: TxId,
+    pub started_at: Timestamp,
+    pub expires_at: Timestamp,
+}
+
+impl Storable for ExportSession {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode export session")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode export session")
+    }
+}
+
+impl BoundedStorable for ExportSession {
+    // A principal and four integers with the type table.
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct ExportSessions;
+
+impl ExportSessions {
+    /// Starts a session of the `owner`, pinning the current balances and transaction history.
+    pub fn start(owner: Principal, now: Timestamp) -> Result<ExportSession, TxError> {
+        Self::remove_expired(now);
+        if SESSIONS.with(|map| map.borrow().len()) as usize >= MAX_EXPORT_SESSIONS {
+            return Err(TxError::TooManyExportSessions {
+                max: MAX_EXPORT_SESSIONS as _,
+            });
+        }
+
+        let snapshot = Snapshots::take(now, Vec::new());
+        let session = ExportSession {
+            id: snapshot.id,
+            owner,
+            transactions_end: LedgerData::len(),
+            started_at: now,
+            expires_at: now.saturating_add(EXPORT_SESSION_TTL_NANOS),
+        };
+        SESSIONS.with(|map| map.borrow_mut().insert(session.id, session.clone()));
+        Ok(session)
+    }
+
+    /// Returns the session if it's not released or expired at `now`.
+    pub fn get(id: ExportSessionId, now: Timestamp) -> Result<ExportSession, TxError> {
+        SESSIONS
+            .with(|map| map.borrow().get(&id))
+            .filter(|session| now <= session.expires_at)
+            .ok_or(TxError::ExportSessionNotFound)
+    }
+
+    /// Ends the session. Only the owner of the session can release it.
+    pub fn release(caller: Principal, id: ExportSessionId, now: Timestamp) -> Result<(), TxError> {
+        let session = Self::get(id, now)?;
+        if session.owner != caller {
+            return Err(TxError::Unauthorized);
+        }
+
+        SESSIONS.with(|map| map.borrow_mut().remove(&id));
+        Ok(())
+    }
+
+    fn remove_expired(now: Timestamp) {
+        SESSIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            let expired = map
+                .iter()
+                .filter(|(_, session)| now > session.expires_at)
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            for id in expired {
+                map.remove(&id);
+            }
+        });
+    }
+
+    pub fn clear() {
+        SESSIONS.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const EXPORT_SESSIONS_MEMORY_ID: MemoryId = MemoryId::new(56);
+
+thread_local! {
+    static SESSIONS: RefCell<StableBTreeMap<ExportSessionId, ExportSession>> =
+        RefCell::new(StableBTreeMap::new(EXPORT_SESSIONS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn sessions_expire_and_are_released_by_owner() {
+        MockContext::new().inject();
+        ExportSessions::clear();
+        Snapshots::clear();
+
+        let session = ExportSessions::start(alice(), 10).unwrap();
+        assert_eq!(session.expires_at, 10 + EXPORT_SESSION_TTL_NANOS);
+        assert_eq!(ExportSessions::get(session.id, 20), Ok(session.clone()));
+        assert_eq!(
+            ExportSessions::get(session.id, session.expires_at + 1),
+            Err(TxError::ExportSessionNotFound)
+        );
+
+        assert_eq!(
+            ExportSessions::release(bob(), session.id, 20),
+            Err(TxError::Unauthorized)
+        );
+        ExportSessions::release(alice(), session.id, 20).unwrap();
+        assert_eq!(
+            ExportSessions::get(session.id, 20),
+            Err(TxError::ExportSessionNotFound)
+        );
+
+        for _ in 0..MAX_EXPORT_SESSIONS {
+            ExportSessions::start(bob(), 10).unwrap();
+        }
+        assert_eq!(
+            ExportSessions::start(alice(), 20),
+            Err(TxError::TooManyExportSessions {
+                max: MAX_EXPORT_SESSIONS as _
+            })
+        );
+        // The expired sessions don't count towards the limit.
+        ExportSessions::start(alice(), 20 + EXPORT_SESSION_TTL_NANOS).unwrap();
+    }
+}
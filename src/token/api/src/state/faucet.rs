@@ -0,0 +1,231 @@
+//! Limits of the minting of the test tokens.
+//!
+//! Anyone can mint a test token. If the faucet policy is set by the owner, every principal except
+//! the owner can mint at most `daily_cap` tokens per UTC day, with at least `cooldown_nanos`
+//! between the mints. The usage is stored per principal only for the current day, the entries of
+//! the previous days are removed in small batches on every mint.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::error::TxError;
+use crate::state::balances::PrincipalKey;
+use crate::state::config::{Timestamp, TokenConfig};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Number of the stale usage entries removed by one mint.
+const CLEANUP_BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FaucetPolicy {
+    /// Maximum amount one principal can mint per UTC day.
+    pub daily_cap: Tokens128,
+    /// Minimum time between two mints of one principal.
+    pub cooldown_nanos: u64,
+}
+
+impl FaucetPolicy {
+    pub fn validate(&self) -> Result<(), TxError> {
+        if self.daily_cap.is_zero() {
+            return Err(TxError::InvalidFaucetPolicy {
+                reason: "daily cap must be greater than zero".into(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FaucetStatus {
+    pub policy: Option<FaucetPolicy>,
+    /// Amount minted by the principal in the current day.
+    pub minted_today: Tokens128,
+    /// Amount the principal can still mint in the current day. `None` if there is no limit.
+    pub remaining: Option<Tokens128>,
+    /// Time from which the principal can mint again.
+    pub next_mint_at: Timestamp,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+struct FaucetUsage {
+    day: u64,
+    minted: Tokens128,
+    last_mint_at: Timestamp,
+}
+
+impl Storable for FaucetUsage {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode faucet usage").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode faucet usage")
+    }
+}
+
+impl BoundedStorable for FaucetUsage {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct Faucet;
+
+impl Faucet {
+    /// Checks that the `caller` can mint `amount` at the time `now`.
+    pub fn check(caller: Principal, amount: Tokens128, now: Timestamp) -> Result<(), TxError> {
+        let config = TokenConfig::get_stable();
+        let Some(policy) = config.faucet_policy else {
+            return Ok(());
+        };
+        if caller == config.owner {
+            return Ok(());
+        }
+
+        let status = Self::status_with(Some(policy), caller, now);
+        if now < status.next_mint_at {
+            return Err(TxError::FaucetCooldown {
+                next_mint_at: status.next_mint_at,
+            });
+        }
+
+        let remaining = status.remaining.unwrap_or(Tokens128::from(u128::MAX));
+        if amount > remaining {
+            return Err(TxError::FaucetLimitExceeded { remaining });
+        }
+
+        Ok(())
+    }
+
+    /// Stores the mint of `amount` by the `caller`, which must be checked with `check` before.
+    pub fn record(caller: Principal, amount: Tokens128, now: Timestamp) {
+        let config = TokenConfig::get_stable();
+        if config.faucet_policy.is_none() || caller == config.owner {
+            return;
+        }
+
+        let day = now / NANOS_PER_DAY;
+        USAGE.with(|map| {
+            let mut map = map.borrow_mut();
+            let minted = match map.get(&caller.into()) {
+                Some(usage) if usage.day == day => usage.minted,
+                _ => Tokens128::ZERO,
+            };
+            let usage = FaucetUsage {
+                day,
+                minted: (minted + amount).unwrap_or(Tokens128::from(u128::MAX)),
+                last_mint_at: now,
+            };
+            map.insert(caller.into(), usage);
+
+            let stale = map
+                .iter()
+                .filter(|(_, usage)| usage.day < day)
+                .take(CLEANUP_BATCH_SIZE)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>();
+            for key in stale {
+                map.remove(&key);
+            }
+        });
+    }
+
+    pub fn status(who: Principal, now: Timestamp) -> FaucetStatus {
+        Self::status_with(TokenConfig::get_stable().faucet_policy, who, now)
+    }
+
+    fn status_with(policy: Option<FaucetPolicy>, who: Principal, now: Timestamp) -> FaucetStatus {
+        let day = now / NANOS_PER_DAY;
+        let usage = USAGE.with(|map| map.borrow().get(&who.into()));
+        let minted_today = match usage {
+            Some(usage) if usage.day == day => usage.minted,
+            _ => Tokens128::ZERO,
+        };
+        let next_mint_at = match (policy, usage) {
+            (Some(policy), Some(usage)) => usage.last_mint_at.saturating_add(policy.cooldown_nanos),
+            _ => 0,
+        };
+
+        FaucetStatus {
+            policy,
+            minted_today,
+            remaining: policy
+                .map(|policy| (policy.daily_cap - minted_today).unwrap_or(Tokens128::ZERO)),
+            next_mint_at,
+        }
+    }
+
+    pub fn clear() {
+        USAGE.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const FAUCET_USAGE_MEMORY_ID: MemoryId = MemoryId::new(24);
+
+thread_local! {
+    static USAGE: RefCell<StableBTreeMap<PrincipalKey, FaucetUsage>> =
+        RefCell::new(StableBTreeMap::new(FAUCET_USAGE_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn init() {
+        MockContext::new().inject();
+        Faucet::clear();
+        TokenConfig::set_stable(TokenConfig {
+            owner: john(),
+            is_test_token: true,
+            faucet_policy: Some(FaucetPolicy {
+                daily_cap: 100.into(),
+                cooldown_nanos: 10,
+            }),
+            ..TokenConfig::default()
+        });
+    }
+
+    #[test]
+    fn daily_cap_and_cooldown() {
+        init();
+        Faucet::check(alice(), 60.into(), 1000).unwrap();
+        Faucet::record(alice(), 60.into(), 1000);
+
+        assert_eq!(
+            Faucet::check(alice(), 10.into(), 1005),
+            Err(TxError::FaucetCooldown { next_mint_at: 1010 })
+        );
+        assert_eq!(
+            Faucet::check(alice(), 50.into(), 1010),
+            Err(TxError::FaucetLimitExceeded {
+                remaining: 40.into()
+            })
+        );
+        Faucet::check(alice(), 40.into(), 1010).unwrap();
+        Faucet::check(bob(), 100.into(), 1005).unwrap();
+        Faucet::check(john(), 1000.into(), 1005).unwrap();
+
+        let next_day = NANOS_PER_DAY + 1000;
+        Faucet::check(alice(), 100.into(), next_day).unwrap();
+        let status = Faucet::status(alice(), next_day);
+        assert_eq!(status.minted_today, 0.into());
+        assert_eq!(status.remaining, Some(100.into()));
+    }
+
+    #[test]
+    fn stale_usage_is_removed() {
+        init();
+        Faucet::record(alice(), 10.into(), 0);
+        Faucet::record(bob(), 10.into(), NANOS_PER_DAY);
+
+        assert_eq!(USAGE.with(|map| map.borrow().len()), 1);
+    }
+}
@@ -0,0 +1,288 @@
+//! Split of the owner part of the transfer fees between several accounts, e.g. 70% to the treasury
+//! and 30% to the development fund.
+//!
+//! If no recipients are set, the whole owner fee goes to `fee_to`. Otherwise every recipient
+//! receives the share of the fee proportional to its weight, rounded down, and the rounding
+//! remainder goes to the first recipient, so the split of the same fee is always the same. The
+//! amounts received by every account are accumulated and returned by `get_fee_distribution`.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::balances::AccountKey;
+use crate::state::config::Value;
+
+pub const MAX_FEE_RECIPIENTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FeeRecipient {
+    pub account: Account,
+    /// Share of the owner fee relative to the weights of the other recipients.
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FeeDistribution {
+    /// Current recipients, empty if the owner fee goes to `fee_to`.
+    pub recipients: Vec<FeeRecipient>,
+    /// Total owner fees received by every account, including the former recipients and `fee_to`.
+    pub received: Vec<(Account, Tokens128)>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct StorableRecipients(Vec<FeeRecipient>);
+
+impl Storable for StorableRecipients {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(&self.0)
+            .expect("failed to encode fee recipients")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Decode!(&bytes, Vec<FeeRecipient>).expect("failed to decode fee recipients"))
+    }
+}
+
+pub struct FeeRecipients;
+
+impl FeeRecipients {
+    pub fn get() -> Vec<FeeRecipient> {
+        RECIPIENTS.with(|cell| cell.borrow().get().0.clone())
+    }
+
+    /// Sets the recipients of the owner fee, or restores `fee_to` if `recipients` is empty.
+    pub fn set(caller: Principal, recipients: Vec<FeeRecipient>) -> Result<(), TxError> {
+        Self::validate(&recipients)?;
+
+        let old = Self::get();
+        RECIPIENTS
+            .with(|cell| {
+                cell.borrow_mut()
+                    .set(StorableRecipients(recipients.clone()))
+            })
+            .expect("unable to set fee recipients to stable memory");
+        AdminLog::record(
+            caller,
+            AdminAction::SetFeeRecipients,
+            Some(Value::Text(format!("{old:?}"))),
+            Some(Value::Text(format!("{recipients:?}"))),
+        );
+        Ok(())
+    }
+
+    pub fn validate(recipients: &[FeeRecipient]) -> Result<(), TxError> {
+        let invalid = |reason: &str| {
+            Err(TxError::InvalidFeeRecipients {
+                reason: reason.to_string(),
+            })
+        };
+
+        if recipients.len() > MAX_FEE_RECIPIENTS {
+            return invalid("too many recipients");
+        }
+
+        if recipients.iter().any(|recipient| recipient.weight == 0) {
+            return invalid("weight must be positive");
+        }
+
+        for (i, recipient) in recipients.iter().enumerate() {
+            let account = AccountInternal::from(recipient.account);
+            if recipients[..i]
+                .iter()
+                .any(|other| AccountInternal::from(other.account) == account)
+            {
+                return invalid("duplicate recipient");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Splits the owner fee `amount` between the recipients, or gives it all to `fee_to` if no
+    /// recipients are set.
+    pub fn split(fee_to: AccountInternal, amount: Tokens128) -> Vec<(AccountInternal, Tokens128)> {
+        let recipients = Self::get();
+        if recipients.is_empty() {
+            return vec![(fee_to, amount)];
+        }
+
+        // At most `MAX_FEE_RECIPIENTS` weights of `u32`, so the sum and the products with the
+        // remainder below don't overflow.
+        let total_weight = recipients
+            .iter()
+            .map(|recipient| recipient.weight as u128)
+            .sum::<u128>();
+        let (quotient, remainder) = (amount.amount / total_weight, amount.amount % total_weight);
+        let mut shares = recipients
+            .iter()
+            .map(|recipient| {
+                let weight = recipient.weight as u128;
+                let share = quotient * weight + remainder * weight / total_weight;
+                (AccountInternal::from(recipient.account), share)
+            })
+            .collect::<Vec<_>>();
+
+        let distributed = shares.iter().map(|(_, share)| share).sum::<u128>();
+        shares[0].1 += amount.amount - distributed;
+
+        shares
+            .into_iter()
+            .map(|(account, share)| (account, Tokens128::from(share)))
+            .collect()
+    }
+
+    /// Accounts receiving the owner fees.
+    pub fn accounts(fee_to: AccountInternal) -> Vec<AccountInternal> {
+        Self::split(fee_to, Tokens128::ZERO)
+            .into_iter()
+            .map(|(account, _)| account)
+            .collect()
+    }
+
+    /// Adds the `shares` of a charged fee to the received totals.
+    pub fn record(shares: &[(AccountInternal, Tokens128)]) {
+        RECEIVED.with(|map| {
+            let mut map = map.borrow_mut();
+            for (account, share) in shares.iter().filter(|(_, share)| !share.is_zero()) {
+                let key = AccountKey::from(*account);
+                let total = map.get(&key).unwrap_or_default();
+                map.insert(key, total.saturating_add(share.amount));
+            }
+        });
+    }
+
+    /// Runs the `operation` which changes the balances in a `BalancesDelta` and discards them on
+    /// error, and discards the fees it recorded as well then.
+    pub fn discard_on_error<T>(
+        operation: impl FnOnce() -> Result<T, TxError>,
+    ) -> Result<T, TxError> {
+        let received = RECEIVED.with(|map| map.borrow().iter().collect::<Vec<_>>());
+        let result = operation();
+        if result.is_err() {
+            RECEIVED.with(|map| {
+                let mut map = map.borrow_mut();
+                map.clear();
+                for (key, total) in received {
+                    map.insert(key, total);
+                }
+            });
+        }
+
+        result
+    }
+
+    pub fn distribution() -> FeeDistribution {
+        FeeDistribution {
+            recipients: Self::get(),
+            received: RECEIVED.with(|map| {
+                map.borrow()
+                    .iter()
+                    .map(|(key, total)| (key.account().into(), Tokens128::from(total)))
+                    .collect()
+            }),
+        }
+    }
+
+    pub fn clear() {
+        RECIPIENTS
+            .with(|cell| cell.borrow_mut().set(StorableRecipients::default()))
+            .expect("unable to set fee recipients to stable memory");
+        RECEIVED.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const FEE_RECIPIENTS_MEMORY_ID: MemoryId = MemoryId::new(54);
+const FEES_RECEIVED_MEMORY_ID: MemoryId = MemoryId::new(55);
+
+thread_local! {
+    static RECIPIENTS: RefCell<StableCell<StorableRecipients>> =
+        RefCell::new(StableCell::new(FEE_RECIPIENTS_MEMORY_ID, StorableRecipients::default())
+            .expect("unable to initialize fee recipients"));
+    static RECEIVED: RefCell<StableBTreeMap<AccountKey, u128>> =
+        RefCell::new(StableBTreeMap::new(FEES_RECEIVED_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn recipient(owner: Principal, weight: u32) -> FeeRecipient {
+        FeeRecipient {
+            account: Account::new(owner, None),
+            weight,
+        }
+    }
+
+    #[test]
+    fn fee_is_split_by_weight() {
+        MockContext::new().inject();
+        FeeRecipients::clear();
+
+        let fee_to = AccountInternal::from(john());
+        assert_eq!(
+            FeeRecipients::split(fee_to, 10.into()),
+            vec![(fee_to, 10.into())]
+        );
+
+        FeeRecipients::set(john(), vec![recipient(alice(), 7), recipient(bob(), 3)]).unwrap();
+        assert_eq!(
+            FeeRecipients::split(fee_to, 100.into()),
+            vec![(alice().into(), 70.into()), (bob().into(), 30.into())]
+        );
+        // The rounding remainder goes to the first recipient.
+        assert_eq!(
+            FeeRecipients::split(fee_to, 9.into()),
+            vec![(alice().into(), 7.into()), (bob().into(), 2.into())]
+        );
+        assert_eq!(
+            FeeRecipients::split(fee_to, u128::MAX.into())
+                .iter()
+                .fold(0u128, |total, (_, share)| total + share.amount),
+            u128::MAX
+        );
+
+        assert_eq!(
+            FeeRecipients::set(john(), vec![recipient(alice(), 0)]),
+            Err(TxError::InvalidFeeRecipients {
+                reason: "weight must be positive".into()
+            })
+        );
+        assert_eq!(
+            FeeRecipients::set(john(), vec![recipient(alice(), 1), recipient(alice(), 2)]),
+            Err(TxError::InvalidFeeRecipients {
+                reason: "duplicate recipient".into()
+            })
+        );
+    }
+
+    #[test]
+    fn received_fees_are_accumulated() {
+        MockContext::new().inject();
+        FeeRecipients::clear();
+
+        FeeRecipients::record(&[(alice().into(), 5.into()), (bob().into(), 0.into())]);
+        FeeRecipients::record(&[(alice().into(), 2.into())]);
+        let result: Result<(), TxError> = FeeRecipients::discard_on_error(|| {
+            FeeRecipients::record(&[(alice().into(), 100.into())]);
+            Err(TxError::AmountTooSmall)
+        });
+        assert!(result.is_err());
+
+        assert_eq!(
+            FeeRecipients::distribution().received,
+            vec![(Account::new(alice(), None), 7.into())]
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! Fees charged on the transfers.
+//!
+//! The fee of a transfer is computed by a `FeeStrategy`. The token uses the `FeePolicy` set by the
+//! owner with `set_fee_policy`, or the flat `fee` of the config if no policy is set. Canisters
+//! built on this crate can replace it with their own strategy with `set_custom_fee_strategy`.
+//! The custom strategy is not stored in the stable memory, so it must be set both on init and post
+//! upgrade.
+//!
+//! The `fee` of the config is the base fee of the strategies and is returned by `icrc1_fee`. The
+//! transfers specifying another fee than the one computed by the strategy are rejected with
+//! `BadFee`, so the clients of a token with a non-flat policy should get the fee with
+//! `get_transfer_fee` first. Mints and burns are never charged.
+
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::config::{FixedRatio, TokenConfig};
+
+/// Upper bound of `PercentageFee::rate_bps`.
+pub const MAX_FEE_RATE_BPS: u16 = 1_000;
+pub const MAX_FEE_TIERS: usize = 16;
+pub const MAX_FEE_EXEMPTIONS: usize = 64;
+
+pub trait FeeStrategy {
+    /// Fee of the transfer of the `amount` from the `from` account to the `to` account. The
+    /// `base_fee` is the `fee` of the token config.
+    fn fee(
+        &self,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        base_fee: Tokens128,
+    ) -> Tokens128;
+}
+
+/// The base fee for every transfer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlatFee;
+
+impl FeeStrategy for FlatFee {
+    fn fee(
+        &self,
+        _from: AccountInternal,
+        _to: AccountInternal,
+        _amount: Tokens128,
+        base_fee: Tokens128,
+    ) -> Tokens128 {
+        base_fee
+    }
+}
+
+/// Share of the transferred amount, bounded by `min_fee` and `max_fee`.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct PercentageFee {
+    /// Share in basis points, i.e. hundredths of a percent.
+    pub rate_bps: u16,
+    pub min_fee: Tokens128,
+    /// If not set, the fee is not capped.
+    pub max_fee: Option<Tokens128>,
+}
+
+impl FeeStrategy for PercentageFee {
+    fn fee(
+        &self,
+        _from: AccountInternal,
+        _to: AccountInternal,
+        amount: Tokens128,
+        _base_fee: Tokens128,
+    ) -> Tokens128 {
+        let fee = FixedRatio::new(self.rate_bps as u64 * (FixedRatio::DENOMINATOR / 10_000))
+            .apply(amount);
+        let fee = if fee < self.min_fee {
+            self.min_fee
+        } else {
+            fee
+        };
+
+        match self.max_fee {
+            Some(max_fee) if fee > max_fee => max_fee,
+            _ => fee,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FeeTier {
+    /// Smallest transferred amount the tier applies to.
+    pub min_amount: Tokens128,
+    pub fee: Tokens128,
+}
+
+/// Fee of the highest tier the transferred amount reaches, or the base fee for the amounts below
+/// the lowest tier. The tiers are sorted by `min_amount`, see `FeePolicy::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TieredFee(pub Vec<FeeTier>);
+
+impl FeeStrategy for TieredFee {
+    fn fee(
+        &self,
+        _from: AccountInternal,
+        _to: AccountInternal,
+        amount: Tokens128,
+        base_fee: Tokens128,
+    ) -> Tokens128 {
+        tier_fee(&self.0, amount, base_fee)
+    }
+}
+
+fn tier_fee(tiers: &[FeeTier], amount: Tokens128, base_fee: Tokens128) -> Tokens128 {
+    tiers
+        .iter()
+        .rev()
+        .find(|tier| amount >= tier.min_amount)
+        .map_or(base_fee, |tier| tier.fee)
+}
+
+/// No fee for the transfers from or to the `exempt` principals, e.g. an exchange, and the fee of
+/// the `inner` strategy for the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExemptFee<S> {
+    pub exempt: Vec<Principal>,
+    pub inner: S,
+}
+
+impl<S: FeeStrategy> FeeStrategy for ExemptFee<S> {
+    fn fee(
+        &self,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        base_fee: Tokens128,
+    ) -> Tokens128 {
+        if self.exempt.contains(&from.owner) || self.exempt.contains(&to.owner) {
+            return Tokens128::ZERO;
+        }
+
+        self.inner.fee(from, to, amount, base_fee)
+    }
+}
+
+/// Built-in strategy used by a `FeePolicy`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum FeeSchedule {
+    Flat,
+    Percentage(PercentageFee),
+    Tiered(Vec<FeeTier>),
+}
+
+/// Fee strategy configured by the owner.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FeePolicy {
+    pub schedule: FeeSchedule,
+    /// Principals which transfers, in or out, are not charged.
+    pub exempt: Vec<Principal>,
+}
+
+impl FeePolicy {
+    pub fn validate(&self) -> Result<(), TxError> {
+        let invalid = |reason: String| Err(TxError::InvalidFeePolicy { reason });
+        match &self.schedule {
+            FeeSchedule::Flat => {}
+            FeeSchedule::Percentage(percentage) => {
+                if percentage.rate_bps > MAX_FEE_RATE_BPS {
+                    return invalid(format!("rate must be at most {MAX_FEE_RATE_BPS} bps"));
+                }
+
+                if matches!(percentage.max_fee, Some(max_fee) if max_fee < percentage.min_fee) {
+                    return invalid("maximum fee must not be less than the minimum fee".into());
+                }
+            }
+            FeeSchedule::Tiered(tiers) => {
+                if tiers.is_empty() || tiers.len() > MAX_FEE_TIERS {
+                    return invalid(format!("there must be between 1 and {MAX_FEE_TIERS} tiers"));
+                }
+
+                if tiers
+                    .windows(2)
+                    .any(|pair| pair[0].min_amount >= pair[1].min_amount)
+                {
+                    return invalid("tiers must be sorted by the minimum amount".into());
+                }
+            }
+        }
+
+        if self.exempt.len() > MAX_FEE_EXEMPTIONS {
+            return invalid(format!(
+                "at most {MAX_FEE_EXEMPTIONS} principals can be exempt"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Applies the `rescale` to the fee amounts of the policy, see `state::decimals`. Returns
+    /// `None` if any of them overflows.
+    pub fn rescale(&self, rescale: impl Fn(Tokens128) -> Option<Tokens128>) -> Option<Self> {
+        let schedule = match &self.schedule {
+            FeeSchedule::Flat => FeeSchedule::Flat,
+            FeeSchedule::Percentage(percentage) => FeeSchedule::Percentage(PercentageFee {
+                rate_bps: percentage.rate_bps,
+                min_fee: rescale(percentage.min_fee)?,
+                max_fee: match percentage.max_fee {
+                    Some(max_fee) => Some(rescale(max_fee)?),
+                    None => None,
+                },
+            }),
+            FeeSchedule::Tiered(tiers) => FeeSchedule::Tiered(
+                tiers
+                    .iter()
+                    .map(|tier| {
+                        Some(FeeTier {
+                            min_amount: rescale(tier.min_amount)?,
+                            fee: rescale(tier.fee)?,
+                        })
+                    })
+                    .collect::<Option<_>>()?,
+            ),
+        };
+
+        Some(Self {
+            schedule,
+            exempt: self.exempt.clone(),
+        })
+    }
+}
+
+impl FeeStrategy for FeePolicy {
+    fn fee(
+        &self,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        base_fee: Tokens128,
+    ) -> Tokens128 {
+        if self.exempt.contains(&from.owner) || self.exempt.contains(&to.owner) {
+            return Tokens128::ZERO;
+        }
+
+        match &self.schedule {
+            FeeSchedule::Flat => FlatFee.fee(from, to, amount, base_fee),
+            FeeSchedule::Percentage(percentage) => percentage.fee(from, to, amount, base_fee),
+            FeeSchedule::Tiered(tiers) => tier_fee(tiers, amount, base_fee),
+        }
+    }
+}
+
+/// Replaces the fee strategy of the token config with the `strategy`, or restores it if `None`.
+pub fn set_custom_fee_strategy(strategy: Option<Box<dyn FeeStrategy>>) {
+    CUSTOM_STRATEGY.with(|cell| *cell.borrow_mut() = strategy);
+}
+
+/// Fee of the transfer of the `amount` from the `from` account to the `to` account.
+pub fn transfer_fee(
+    config: &TokenConfig,
+    from: AccountInternal,
+    to: AccountInternal,
+    amount: Tokens128,
+) -> Tokens128 {
+    CUSTOM_STRATEGY.with(|cell| match &*cell.borrow() {
+        Some(strategy) => strategy.fee(from, to, amount, config.fee),
+        None => match &config.fee_policy {
+            Some(policy) => policy.fee(from, to, amount, config.fee),
+            None => FlatFee.fee(from, to, amount, config.fee),
+        },
+    })
+}
+
+thread_local! {
+    static CUSTOM_STRATEGY: RefCell<Option<Box<dyn FeeStrategy>>> = RefCell::new(None);
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn fee_of(strategy: &impl FeeStrategy, to: Principal, amount: u128) -> Tokens128 {
+        strategy.fee(alice().into(), to.into(), amount.into(), 10.into())
+    }
+
+    #[test]
+    fn built_in_strategies() {
+        assert_eq!(fee_of(&FlatFee, bob(), 1_000), 10.into());
+
+        let percentage = PercentageFee {
+            rate_bps: 100,
+            min_fee: 5.into(),
+            max_fee: Some(50.into()),
+        };
+        assert_eq!(fee_of(&percentage, bob(), 100), 5.into());
+        assert_eq!(fee_of(&percentage, bob(), 2_000), 20.into());
+        assert_eq!(fee_of(&percentage, bob(), 1_000_000), 50.into());
+
+        let tiered = TieredFee(vec![
+            FeeTier {
+                min_amount: 100.into(),
+                fee: 20.into(),
+            },
+            FeeTier {
+                min_amount: 1_000.into(),
+                fee: 30.into(),
+            },
+        ]);
+        assert_eq!(fee_of(&tiered, bob(), 99), 10.into());
+        assert_eq!(fee_of(&tiered, bob(), 100), 20.into());
+        assert_eq!(fee_of(&tiered, bob(), 5_000), 30.into());
+
+        let exempt = ExemptFee {
+            exempt: vec![john()],
+            inner: tiered,
+        };
+        assert_eq!(fee_of(&exempt, john(), 5_000), 0.into());
+        assert_eq!(fee_of(&exempt, bob(), 5_000), 30.into());
+    }
+
+    #[test]
+    fn policy_validation() {
+        let policy = |schedule| FeePolicy {
+            schedule,
+            exempt: vec![],
+        };
+        let tier = |min_amount: u128| FeeTier {
+            min_amount: min_amount.into(),
+            fee: 1.into(),
+        };
+
+        assert!(policy(FeeSchedule::Flat).validate().is_ok());
+        assert!(policy(FeeSchedule::Tiered(vec![tier(1), tier(2)]))
+            .validate()
+            .is_ok());
+        assert!(policy(FeeSchedule::Tiered(vec![tier(2), tier(2)]))
+            .validate()
+            .is_err());
+        assert!(policy(FeeSchedule::Tiered(vec![])).validate().is_err());
+        assert!(policy(FeeSchedule::Percentage(PercentageFee {
+            rate_bps: MAX_FEE_RATE_BPS + 1,
+            min_fee: 0.into(),
+            max_fee: None,
+        }))
+        .validate()
+        .is_err());
+        assert!(policy(FeeSchedule::Percentage(PercentageFee {
+            rate_bps: 10,
+            min_fee: 2.into(),
+            max_fee: Some(1.into()),
+        }))
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn custom_strategy_overrides_config() {
+        struct Double;
+
+        impl FeeStrategy for Double {
+            fn fee(
+                &self,
+                _from: AccountInternal,
+                _to: AccountInternal,
+                _amount: Tokens128,
+                base_fee: Tokens128,
+            ) -> Tokens128 {
+                (base_fee + base_fee).unwrap_or(base_fee)
+            }
+        }
+
+        let mut config = TokenConfig {
+            fee: 10.into(),
+            ..TokenConfig::default()
+        };
+        let transfer_fee =
+            |config: &TokenConfig| transfer_fee(config, alice().into(), bob().into(), 1_000.into());
+        assert_eq!(transfer_fee(&config), 10.into());
+
+        config.fee_policy = Some(FeePolicy {
+            schedule: FeeSchedule::Flat,
+            exempt: vec![bob()],
+        });
+        assert_eq!(transfer_fee(&config), 0.into());
+
+        set_custom_fee_strategy(Some(Box::new(Double)));
+        assert_eq!(transfer_fee(&config), 20.into());
+        set_custom_fee_strategy(None);
+        assert_eq!(transfer_fee(&config), 0.into());
+    }
+}
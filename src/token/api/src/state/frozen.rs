@@ -0,0 +1,191 @@
+//! Accounts frozen by the token owner.
+//!
+//! A frozen account cannot send tokens, receive tokens or both, depending on the freeze mode.
+//! Every change of the freeze state is recorded in the `AdminLog`.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::config::Value;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum FreezeMode {
+    /// The account cannot send or burn tokens.
+    Outgoing,
+    /// The account cannot receive tokens.
+    Incoming,
+    Both,
+}
+
+impl FreezeMode {
+    fn blocks_outgoing(self) -> bool {
+        matches!(self, Self::Outgoing | Self::Both)
+    }
+
+    fn blocks_incoming(self) -> bool {
+        matches!(self, Self::Incoming | Self::Both)
+    }
+}
+
+impl Storable for FreezeMode {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let byte = match self {
+            Self::Outgoing => 0u8,
+            Self::Incoming => 1,
+            Self::Both => 2,
+        };
+        vec![byte].into()
+    }
+
+    /// Expected `bytes.len() == 1`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match bytes[0] {
+            0 => Self::Outgoing,
+            1 => Self::Incoming,
+            _ => Self::Both,
+        }
+    }
+}
+
+impl BoundedStorable for FreezeMode {
+    const MAX_SIZE: u32 = 1;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+const PRINCIPAL_MAX_LENGTH_IN_BYTES: usize = 29;
+const ACCOUNT_KEY_SIZE: usize = 1 + PRINCIPAL_MAX_LENGTH_IN_BYTES + 32;
+
+/// Account encoded as principal length, principal bytes padded with zeros and subaccount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct AccountKey([u8; ACCOUNT_KEY_SIZE]);
+
+impl From<AccountInternal> for AccountKey {
+    fn from(account: AccountInternal) -> Self {
+        let principal = account.owner.as_slice();
+        let mut key = [0u8; ACCOUNT_KEY_SIZE];
+        key[0] = principal.len() as u8;
+        key[1..1 + principal.len()].copy_from_slice(principal);
+        key[1 + PRINCIPAL_MAX_LENGTH_IN_BYTES..].copy_from_slice(&account.subaccount);
+        Self(key)
+    }
+}
+
+impl Storable for AccountKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_slice().into()
+    }
+
+    /// Expected `bytes.len() == ACCOUNT_KEY_SIZE`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let mut buf = [0u8; ACCOUNT_KEY_SIZE];
+        buf.copy_from_slice(&bytes);
+        Self(buf)
+    }
+}
+
+impl BoundedStorable for AccountKey {
+    const MAX_SIZE: u32 = ACCOUNT_KEY_SIZE as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+pub struct FrozenAccounts;
+
+impl FrozenAccounts {
+    pub fn get(account: AccountInternal) -> Option<FreezeMode> {
+        FROZEN.with(|map| map.borrow().get(&account.into()))
+    }
+
+    /// Sets the freeze mode of the `account`, or unfreezes it if `mode` is `None`, and records
+    /// the change in the admin log.
+    pub fn set(caller: Principal, account: AccountInternal, mode: Option<FreezeMode>) {
+        let old_mode = FROZEN.with(|map| {
+            let mut map = map.borrow_mut();
+            match mode {
+                Some(mode) => map.insert(account.into(), mode),
+                None => map.remove(&account.into()),
+            }
+        });
+
+        let action = match mode {
+            Some(_) => AdminAction::FreezeAccount {
+                account: account.into(),
+            },
+            None => AdminAction::UnfreezeAccount {
+                account: account.into(),
+            },
+        };
+        let to_value = |mode: FreezeMode| Value::Text(format!("{mode:?}"));
+        AdminLog::record(caller, action, old_mode.map(to_value), mode.map(to_value));
+    }
+
+    /// Returns an error if the `account` is not allowed to send tokens.
+    pub fn check_outgoing(account: AccountInternal) -> Result<(), TxError> {
+        match Self::get(account) {
+            Some(mode) if mode.blocks_outgoing() => Err(TxError::AccountFrozen {
+                account: account.into(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if the `account` is not allowed to receive tokens.
+    pub fn check_incoming(account: AccountInternal) -> Result<(), TxError> {
+        match Self::get(account) {
+            Some(mode) if mode.blocks_incoming() => Err(TxError::AccountFrozen {
+                account: account.into(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn clear() {
+        FROZEN.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const FROZEN_ACCOUNTS_MEMORY_ID: MemoryId = MemoryId::new(6);
+
+thread_local! {
+    static FROZEN: RefCell<StableBTreeMap<AccountKey, FreezeMode>> =
+        RefCell::new(StableBTreeMap::new(FROZEN_ACCOUNTS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn freeze_modes() {
+        MockContext::new().inject();
+        FrozenAccounts::clear();
+        AdminLog::clear();
+
+        let account = AccountInternal::from(bob());
+        FrozenAccounts::set(alice(), account, Some(FreezeMode::Outgoing));
+        assert!(FrozenAccounts::check_outgoing(account).is_err());
+        assert!(FrozenAccounts::check_incoming(account).is_ok());
+
+        FrozenAccounts::set(alice(), account, Some(FreezeMode::Incoming));
+        assert!(FrozenAccounts::check_outgoing(account).is_ok());
+        assert!(FrozenAccounts::check_incoming(account).is_err());
+
+        FrozenAccounts::set(alice(), account, None);
+        assert_eq!(FrozenAccounts::get(account), None);
+
+        let log = AdminLog::get(1, 10);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].old_value, Some(Value::Text("Outgoing".into())));
+        assert_eq!(log[1].new_value, None);
+        assert_eq!(log[1].caller, alice());
+    }
+}
@@ -0,0 +1,134 @@
+//! Locks preventing the endpoints making inter-canister calls from interleaving with the calls
+//! which change the same state.
+//!
+//! An endpoint takes a lock on the account (or a global lock on an intent, e.g. the cycles
+//! withdrawal) before it reads the state its call depends on, and holds the returned `StateGuard`
+//! until the call is finished. Other calls trying to take the same lock are rejected with
+//! `TxError::OperationInProgress` in the meantime.
+//!
+//! The lock is released when the guard is dropped. If the canister traps after an `await`, the
+//! guard is never dropped, so every lock also expires after `LOCK_TIMEOUT_NANOS`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use canister_sdk::ic_kit::ic;
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+/// Time after which a lock is released even if its guard was not dropped. Longer than the usual
+/// inter-canister call takes.
+pub const LOCK_TIMEOUT_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum LockKey {
+    Account(AccountInternal),
+    Global(&'static str),
+}
+
+/// Lock held until the guard is dropped.
+#[must_use]
+#[derive(Debug)]
+pub struct StateGuard {
+    key: LockKey,
+    acquired_at: Timestamp,
+}
+
+impl StateGuard {
+    /// Locks the `account`.
+    pub fn account(account: AccountInternal) -> Result<Self, TxError> {
+        Self::acquire(LockKey::Account(account), ic::time())
+    }
+
+    /// Locks the `intent` for all callers.
+    pub fn global(intent: &'static str) -> Result<Self, TxError> {
+        Self::acquire(LockKey::Global(intent), ic::time())
+    }
+
+    fn acquire(key: LockKey, now: Timestamp) -> Result<Self, TxError> {
+        LOCKS.with(|locks| {
+            let mut locks = locks.borrow_mut();
+            match locks.get(&key) {
+                Some(&acquired_at) if now < acquired_at.saturating_add(LOCK_TIMEOUT_NANOS) => {
+                    Err(TxError::OperationInProgress)
+                }
+                _ => {
+                    locks.insert(key, now);
+                    Ok(Self {
+                        key,
+                        acquired_at: now,
+                    })
+                }
+            }
+        })
+    }
+
+    pub fn clear() {
+        LOCKS.with(|locks| locks.borrow_mut().clear());
+    }
+}
+
+impl Drop for StateGuard {
+    fn drop(&mut self) {
+        LOCKS.with(|locks| {
+            let mut locks = locks.borrow_mut();
+            // The lock could expire and be taken by another call in the meantime.
+            if locks.get(&self.key) == Some(&self.acquired_at) {
+                locks.remove(&self.key);
+            }
+        });
+    }
+}
+
+thread_local! {
+    static LOCKS: RefCell<HashMap<LockKey, Timestamp>> = RefCell::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn locks_are_exclusive() {
+        MockContext::new().inject();
+        StateGuard::clear();
+
+        let guard = StateGuard::account(alice().into()).unwrap();
+        assert_eq!(
+            StateGuard::account(alice().into()).unwrap_err(),
+            TxError::OperationInProgress
+        );
+        let other = StateGuard::account(bob().into()).unwrap();
+        let global = StateGuard::global("withdrawal").unwrap();
+        assert!(StateGuard::global("withdrawal").is_err());
+
+        drop(guard);
+        drop(other);
+        drop(global);
+        assert!(StateGuard::account(alice().into()).is_ok());
+        assert!(StateGuard::global("withdrawal").is_ok());
+    }
+
+    #[test]
+    fn locks_expire() {
+        MockContext::new().inject();
+        StateGuard::clear();
+
+        let key = LockKey::Account(alice().into());
+        let stale = StateGuard::acquire(key, 0).unwrap();
+        let fresh = StateGuard::acquire(key, LOCK_TIMEOUT_NANOS).unwrap();
+        assert!(StateGuard::acquire(key, LOCK_TIMEOUT_NANOS + 1).is_err());
+
+        // Dropping the expired guard doesn't release the lock taken after it.
+        drop(stale);
+        assert!(StateGuard::acquire(key, LOCK_TIMEOUT_NANOS + 1).is_err());
+        drop(fresh);
+        assert!(StateGuard::acquire(key, LOCK_TIMEOUT_NANOS + 1).is_ok());
+    }
+}
@@ -0,0 +1,353 @@
+//! Recovery of the accounts by a guardian designated by their owner.
+//!
+//! A principal may set a guardian with a recovery delay. If the principal loses its key, the
+//! guardian initiates the recovery to a new principal with `initiate_recovery`. The owner can veto
+//! the recovery until the delay passes, after which the guardian completes it with
+//! `complete_recovery`, moving the balances of all subaccounts of the owner to the same
+//! subaccounts of the new principal. Every step is recorded in the recovery log, which is kept
+//! apart from the admin log, as the steps are taken by the holders and their guardians rather than
+//! by the owner of the token.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+/// Minimum recovery delay, so the owner has time to notice and veto an unwanted recovery.
+pub const MIN_RECOVERY_DELAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Guardian {
+    pub guardian: Principal,
+    /// Time between the initiation of a recovery and the moment it can be completed.
+    pub delay_nanos: u64,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Recovery {
+    pub owner: Principal,
+    pub guardian: Principal,
+    pub new_owner: Principal,
+    pub initiated_at: Timestamp,
+    pub executable_at: Timestamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct OwnerKey(Principal);
+
+impl Storable for OwnerKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_slice().into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Principal::from_slice(&bytes))
+    }
+}
+
+impl BoundedStorable for OwnerKey {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Guardian {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode guardian").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode guardian")
+    }
+}
+
+impl BoundedStorable for Guardian {
+    // A principal and an integer with the type table.
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Recovery {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode recovery").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode recovery")
+    }
+}
+
+impl BoundedStorable for Recovery {
+    // Three principals and two timestamps with the type table.
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum RecoveryAction {
+    SetGuardian { guardian: Option<Guardian> },
+    InitiateRecovery { new_owner: Principal },
+    VetoRecovery,
+    CompleteRecovery { new_owner: Principal },
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct RecoveryLogEntry {
+    pub caller: Principal,
+    /// Principal whose accounts are guarded or recovered.
+    pub owner: Principal,
+    pub timestamp: Timestamp,
+    pub action: RecoveryAction,
+}
+
+impl Storable for RecoveryLogEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode recovery log entry")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode recovery log entry")
+    }
+}
+
+impl BoundedStorable for RecoveryLogEntry {
+    // Three principals and the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct Guardians;
+
+impl Guardians {
+    pub fn get(owner: Principal) -> Option<Guardian> {
+        GUARDIANS.with(|map| map.borrow().get(&OwnerKey(owner)))
+    }
+
+    /// Sets the guardian of the `owner`, or removes it if `guardian` is `None`. A pending recovery
+    /// of the owner is cancelled, as the owner evidently controls its key. Returns the previous
+    /// guardian.
+    pub fn set(owner: Principal, guardian: Option<Guardian>) -> Result<Option<Guardian>, TxError> {
+        if let Some(guardian) = guardian {
+            if guardian.guardian == owner || guardian.guardian == Principal::anonymous() {
+                return Err(TxError::InvalidGuardian);
+            }
+            if guardian.delay_nanos < MIN_RECOVERY_DELAY_NANOS {
+                return Err(TxError::RecoveryDelayTooShort {
+                    min_delay_nanos: MIN_RECOVERY_DELAY_NANOS,
+                });
+            }
+        }
+
+        RECOVERIES.with(|map| map.borrow_mut().remove(&OwnerKey(owner)));
+        Ok(GUARDIANS.with(|map| {
+            let mut map = map.borrow_mut();
+            match guardian {
+                Some(guardian) => map.insert(OwnerKey(owner), guardian),
+                None => map.remove(&OwnerKey(owner)),
+            }
+        }))
+    }
+
+    pub fn get_recovery(owner: Principal) -> Option<Recovery> {
+        RECOVERIES.with(|map| map.borrow().get(&OwnerKey(owner)))
+    }
+
+    /// Starts the recovery of the `owner` accounts to the `new_owner`. Must be called by the
+    /// guardian of the owner.
+    pub fn initiate(
+        caller: Principal,
+        owner: Principal,
+        new_owner: Principal,
+        now: Timestamp,
+    ) -> Result<Recovery, TxError> {
+        let guardian = Self::get(owner).ok_or(TxError::GuardianNotSet)?;
+        if guardian.guardian != caller {
+            return Err(TxError::Unauthorized);
+        }
+        if new_owner == owner || new_owner == Principal::anonymous() {
+            return Err(TxError::InvalidGuardian);
+        }
+        if let Some(recovery) = Self::get_recovery(owner) {
+            return Err(TxError::RecoveryInProgress {
+                executable_at: recovery.executable_at,
+            });
+        }
+
+        let recovery = Recovery {
+            owner,
+            guardian: caller,
+            new_owner,
+            initiated_at: now,
+            executable_at: now.saturating_add(guardian.delay_nanos),
+        };
+        RECOVERIES.with(|map| map.borrow_mut().insert(OwnerKey(owner), recovery));
+        Ok(recovery)
+    }
+
+    /// Cancels the pending recovery of the `owner` accounts.
+    pub fn veto(owner: Principal) -> Result<Recovery, TxError> {
+        RECOVERIES
+            .with(|map| map.borrow_mut().remove(&OwnerKey(owner)))
+            .ok_or(TxError::RecoveryNotFound)
+    }
+
+    /// Checks that the `caller` can complete the recovery of the `owner` accounts at the time
+    /// `now`, and returns the recovery.
+    pub fn check_completion(
+        caller: Principal,
+        owner: Principal,
+        now: Timestamp,
+    ) -> Result<Recovery, TxError> {
+        let recovery = Self::get_recovery(owner).ok_or(TxError::RecoveryNotFound)?;
+        if recovery.guardian != caller {
+            return Err(TxError::Unauthorized);
+        }
+        if now < recovery.executable_at {
+            return Err(TxError::RecoveryNotReady {
+                executable_at: recovery.executable_at,
+            });
+        }
+
+        Ok(recovery)
+    }
+
+    /// Removes the completed recovery together with the guardian of the recovered principal. The
+    /// new principal can set its own guardian.
+    pub fn finish(owner: Principal) {
+        RECOVERIES.with(|map| map.borrow_mut().remove(&OwnerKey(owner)));
+        GUARDIANS.with(|map| map.borrow_mut().remove(&OwnerKey(owner)));
+    }
+
+    pub fn clear() {
+        GUARDIANS.with(|map| map.borrow_mut().clear());
+        RECOVERIES.with(|map| map.borrow_mut().clear());
+        RECOVERY_LOG.with(|log| log.borrow_mut().clear());
+    }
+}
+
+/// Log of the guardian changes and of the recovery steps.
+pub struct RecoveryLog;
+
+impl RecoveryLog {
+    pub fn record(caller: Principal, owner: Principal, action: RecoveryAction) {
+        let entry = RecoveryLogEntry {
+            caller,
+            owner,
+            timestamp: canister_sdk::ic_kit::ic::time(),
+            action,
+        };
+
+        RECOVERY_LOG.with(|log| {
+            let mut log = log.borrow_mut();
+            let index = log.len();
+            log.insert(index, entry);
+        });
+    }
+
+    /// Returns `count` entries of the log starting from the `offset`.
+    pub fn get(offset: u64, count: usize) -> Vec<RecoveryLogEntry> {
+        RECOVERY_LOG.with(|log| {
+            log.borrow()
+                .range(offset..)
+                .take(count)
+                .map(|(_, entry)| entry)
+                .collect()
+        })
+    }
+}
+
+const GUARDIANS_MEMORY_ID: MemoryId = MemoryId::new(34);
+const RECOVERIES_MEMORY_ID: MemoryId = MemoryId::new(35);
+const RECOVERY_LOG_MEMORY_ID: MemoryId = MemoryId::new(65);
+
+thread_local! {
+    static GUARDIANS: RefCell<StableBTreeMap<OwnerKey, Guardian>> =
+        RefCell::new(StableBTreeMap::new(GUARDIANS_MEMORY_ID));
+    static RECOVERIES: RefCell<StableBTreeMap<OwnerKey, Recovery>> =
+        RefCell::new(StableBTreeMap::new(RECOVERIES_MEMORY_ID));
+    static RECOVERY_LOG: RefCell<StableBTreeMap<u64, RecoveryLogEntry>> =
+        RefCell::new(StableBTreeMap::new(RECOVERY_LOG_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    const DELAY: u64 = MIN_RECOVERY_DELAY_NANOS;
+
+    fn guardian(guardian: Principal) -> Option<Guardian> {
+        Some(Guardian {
+            guardian,
+            delay_nanos: DELAY,
+        })
+    }
+
+    #[test]
+    fn recovery_after_delay() {
+        MockContext::new().inject();
+        Guardians::clear();
+
+        assert_eq!(
+            Guardians::initiate(bob(), alice(), john(), 0),
+            Err(TxError::GuardianNotSet)
+        );
+        assert_eq!(
+            Guardians::set(alice(), guardian(alice())),
+            Err(TxError::InvalidGuardian)
+        );
+        Guardians::set(alice(), guardian(bob())).unwrap();
+
+        assert_eq!(
+            Guardians::initiate(john(), alice(), john(), 0),
+            Err(TxError::Unauthorized)
+        );
+        let recovery = Guardians::initiate(bob(), alice(), john(), 0).unwrap();
+        assert_eq!(recovery.executable_at, DELAY);
+        assert_eq!(
+            Guardians::initiate(bob(), alice(), john(), 0),
+            Err(TxError::RecoveryInProgress {
+                executable_at: DELAY
+            })
+        );
+
+        assert_eq!(
+            Guardians::check_completion(bob(), alice(), DELAY - 1),
+            Err(TxError::RecoveryNotReady {
+                executable_at: DELAY
+            })
+        );
+        assert_eq!(
+            Guardians::check_completion(bob(), alice(), DELAY),
+            Ok(recovery)
+        );
+
+        Guardians::finish(alice());
+        assert_eq!(Guardians::get(alice()), None);
+        assert_eq!(Guardians::get_recovery(alice()), None);
+    }
+
+    #[test]
+    fn owner_vetoes_recovery() {
+        MockContext::new().inject();
+        Guardians::clear();
+
+        Guardians::set(alice(), guardian(bob())).unwrap();
+        Guardians::initiate(bob(), alice(), john(), 0).unwrap();
+        Guardians::veto(alice()).unwrap();
+        assert_eq!(Guardians::veto(alice()), Err(TxError::RecoveryNotFound));
+
+        // Changing the guardian also cancels the pending recovery.
+        Guardians::initiate(bob(), alice(), john(), 0).unwrap();
+        Guardians::set(alice(), guardian(john())).unwrap();
+        assert_eq!(Guardians::get_recovery(alice()), None);
+    }
+}
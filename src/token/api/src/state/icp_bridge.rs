@@ -0,0 +1,266 @@
+//! State of the ICP bridge: processed deposits and the outgoing ICP transfers.
+//!
+//! Every ICP transfer made by the bridge is stored as a `BridgeOperation` before the ledger is
+//! called. If the call fails, the operation stays pending and is retried later. The retries use
+//! the same memo and `created_at_time`, so the ICP ledger deduplicates them and an operation is
+//! never executed twice.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::account::{Account, Subaccount};
+use crate::error::{truncate_message, TxError};
+use crate::state::config::Timestamp;
+
+pub type OperationId = u64;
+pub type BlockIndex = u64;
+/// ICP ledger account identifier with the checksum.
+pub type IcpAccountId = [u8; 32];
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum BridgeOperationKind {
+    /// Moves a deposit from the deposit subaccount to the bridge reserve account.
+    Sweep {
+        subaccount: Subaccount,
+        deposit_block: BlockIndex,
+    },
+    /// Sends the ICP of the burned tokens to the user.
+    Withdrawal { to: IcpAccountId },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct BridgeOperation {
+    pub id: OperationId,
+    pub kind: BridgeOperationKind,
+    /// Token account the operation was made for.
+    pub account: Account,
+    /// ICP amount in e8s to be received, the ledger fee is paid on top of it.
+    pub amount: u64,
+    pub created_at: Timestamp,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Index of the ICP ledger block of the executed transfer. `None` while pending.
+    pub block_index: Option<BlockIndex>,
+}
+
+impl BridgeOperation {
+    pub fn is_pending(&self) -> bool {
+        self.block_index.is_none()
+    }
+}
+
+impl Storable for BridgeOperation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode bridge operation")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode bridge operation")
+    }
+}
+
+impl BoundedStorable for BridgeOperation {
+    // The error message is truncated to `MAX_ERROR_LENGTH`.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+const MAX_ERROR_LENGTH: usize = 200;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum DepositStatus {
+    /// The block is being verified with the ICP ledger.
+    Verifying,
+    Minted {
+        tx_id: u64,
+        amount: Tokens128,
+    },
+}
+
+impl Storable for DepositStatus {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode deposit").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode deposit")
+    }
+}
+
+impl BoundedStorable for DepositStatus {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct IcpBridge;
+
+impl IcpBridge {
+    /// Marks the deposit block as being processed, so it cannot be minted twice.
+    pub fn start_deposit(block: BlockIndex) -> Result<(), TxError> {
+        DEPOSITS.with(|map| {
+            let mut map = map.borrow_mut();
+            if map.get(&block).is_some() {
+                return Err(TxError::DepositAlreadyProcessed);
+            }
+
+            map.insert(block, DepositStatus::Verifying);
+            Ok(())
+        })
+    }
+
+    /// Allows the deposit block to be processed again after a failed verification.
+    pub fn abort_deposit(block: BlockIndex) {
+        DEPOSITS.with(|map| map.borrow_mut().remove(&block));
+    }
+
+    pub fn complete_deposit(block: BlockIndex, tx_id: u64, amount: Tokens128) {
+        DEPOSITS.with(|map| {
+            map.borrow_mut()
+                .insert(block, DepositStatus::Minted { tx_id, amount })
+        });
+    }
+
+    pub fn get_deposit(block: BlockIndex) -> Option<DepositStatus> {
+        DEPOSITS.with(|map| map.borrow().get(&block))
+    }
+
+    pub fn add_operation(
+        kind: BridgeOperationKind,
+        account: Account,
+        amount: u64,
+        now: Timestamp,
+    ) -> BridgeOperation {
+        OPERATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            let operation = BridgeOperation {
+                id: map.len(),
+                kind,
+                account,
+                amount,
+                created_at: now,
+                attempts: 0,
+                last_error: None,
+                block_index: None,
+            };
+            map.insert(operation.id, operation.clone());
+            operation
+        })
+    }
+
+    pub fn get_operation(id: OperationId) -> Option<BridgeOperation> {
+        OPERATIONS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns `count` operations starting from the `offset`.
+    pub fn get_operations(offset: u64, count: usize) -> Vec<BridgeOperation> {
+        OPERATIONS.with(|map| {
+            map.borrow()
+                .iter()
+                .skip(offset as usize)
+                .take(count)
+                .map(|(_, operation)| operation)
+                .collect()
+        })
+    }
+
+    pub fn pending_operations() -> Vec<BridgeOperation> {
+        OPERATIONS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, operation)| operation)
+                .filter(BridgeOperation::is_pending)
+                .collect()
+        })
+    }
+
+    /// Stores the result of an attempt to execute the operation.
+    pub fn record_attempt(id: OperationId, result: Result<BlockIndex, String>) {
+        OPERATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            if let Some(mut operation) = map.get(&id) {
+                operation.attempts += 1;
+                match result {
+                    Ok(block_index) => {
+                        operation.block_index = Some(block_index);
+                        operation.last_error = None;
+                    }
+                    Err(mut error) => {
+                        truncate_message(&mut error, MAX_ERROR_LENGTH);
+                        operation.last_error = Some(error);
+                    }
+                }
+                map.insert(id, operation);
+            }
+        });
+    }
+
+    pub fn clear() {
+        DEPOSITS.with(|map| map.borrow_mut().clear());
+        OPERATIONS.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(12);
+const OPERATIONS_MEMORY_ID: MemoryId = MemoryId::new(13);
+
+thread_local! {
+    static DEPOSITS: RefCell<StableBTreeMap<BlockIndex, DepositStatus>> =
+        RefCell::new(StableBTreeMap::new(DEPOSITS_MEMORY_ID));
+    static OPERATIONS: RefCell<StableBTreeMap<OperationId, BridgeOperation>> =
+        RefCell::new(StableBTreeMap::new(OPERATIONS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::alice;
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn deposit_is_processed_once() {
+        MockContext::new().inject();
+        IcpBridge::clear();
+
+        IcpBridge::start_deposit(5).unwrap();
+        assert_eq!(
+            IcpBridge::start_deposit(5),
+            Err(TxError::DepositAlreadyProcessed)
+        );
+
+        IcpBridge::abort_deposit(5);
+        IcpBridge::start_deposit(5).unwrap();
+        IcpBridge::complete_deposit(5, 1, 100.into());
+        assert_eq!(
+            IcpBridge::get_deposit(5),
+            Some(DepositStatus::Minted {
+                tx_id: 1,
+                amount: 100.into()
+            })
+        );
+    }
+
+    #[test]
+    fn failed_operations_stay_pending() {
+        MockContext::new().inject();
+        IcpBridge::clear();
+
+        let kind = BridgeOperationKind::Withdrawal { to: [1; 32] };
+        let operation = IcpBridge::add_operation(kind, alice().into(), 100, 0);
+        IcpBridge::record_attempt(operation.id, Err("ledger is stopped".into()));
+        assert_eq!(IcpBridge::pending_operations().len(), 1);
+
+        IcpBridge::record_attempt(operation.id, Ok(10));
+        let operation = IcpBridge::get_operation(operation.id).unwrap();
+        assert_eq!(operation.attempts, 2);
+        assert_eq!(operation.block_index, Some(10));
+        assert!(IcpBridge::pending_operations().is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! Accounting invariants of the token.
+//!
+//! The total supply is the sum of all balances, and every change of it is recorded in the ledger
+//! as a mint or a burn. So the supply derived from the balances must be equal to the difference of
+//! the minted and burned amounts in the history. The check is meant to be run after upgrades, and
+//! is possible only while the history wasn't pruned.
+
+use std::cmp::Ordering;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::admin_log::{AdminAction, AdminLog};
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::{TokenConfig, Value};
+use crate::state::ledger::LedgerData;
+use crate::tx_record::TxId;
+
+/// Difference between the supply derived from the balances and the supply derived from the
+/// ledger.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum SupplyDiscrepancy {
+    /// The balances hold more tokens than were minted according to the ledger.
+    Surplus(Tokens128),
+    /// The balances hold less tokens than were minted according to the ledger.
+    Deficit(Tokens128),
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct InvariantsReport {
+    /// Sum of all balances.
+    pub total_supply: Tokens128,
+    /// Total amount of the mint records in the stored history.
+    pub minted: Tokens128,
+    /// Total amount of the burn records in the stored history.
+    pub burned: Tokens128,
+    /// False if some records were pruned, in which case the ledger totals cannot be cross-checked.
+    pub history_complete: bool,
+    pub discrepancy: Option<SupplyDiscrepancy>,
+}
+
+impl InvariantsReport {
+    /// Supply derived from the mint and burn records in the stored history.
+    pub fn ledger_supply(&self) -> Tokens128 {
+        self.minted.amount.saturating_sub(self.burned.amount).into()
+    }
+}
+
+pub struct Invariants;
+
+impl Invariants {
+    pub fn verify() -> InvariantsReport {
+        let total_supply = StableBalances.total_supply();
+        let (minted, burned) = LedgerData::supply_totals();
+        let history_complete = LedgerData::history_info().earliest_index == 0;
+
+        let mut report = InvariantsReport {
+            total_supply,
+            minted,
+            burned,
+            history_complete,
+            discrepancy: None,
+        };
+
+        if history_complete {
+            let ledger_supply = report.ledger_supply().amount;
+            report.discrepancy = match total_supply.amount.cmp(&ledger_supply) {
+                Ordering::Greater => Some(SupplyDiscrepancy::Surplus(
+                    (total_supply.amount - ledger_supply).into(),
+                )),
+                Ordering::Less => Some(SupplyDiscrepancy::Deficit(
+                    (ledger_supply - total_supply.amount).into(),
+                )),
+                Ordering::Equal => None,
+            };
+        }
+
+        report
+    }
+
+    /// Writes a mint or a burn record to the ledger with the amount of the discrepancy, so the
+    /// ledger totals match the balances again. The balances are not changed. Returns the id of the
+    /// corrective record, or `None` if there is no discrepancy.
+    pub fn repair(caller: Principal) -> Result<Option<TxId>, TxError> {
+        let report = Self::verify();
+        if !report.history_complete {
+            return Err(TxError::HistoryIncomplete);
+        }
+
+        let Some(discrepancy) = report.discrepancy else {
+            return Ok(None);
+        };
+
+        let minting_account = TokenConfig::get_stable().minting_account();
+        let caller_account = AccountInternal::from(caller);
+        let id = match discrepancy {
+            SupplyDiscrepancy::Surplus(amount) => {
+                LedgerData::mint(caller_account, minting_account, amount)
+            }
+            SupplyDiscrepancy::Deficit(amount) => {
+                LedgerData::burn(caller_account, minting_account, amount)
+            }
+        };
+
+        AdminLog::record(
+            caller,
+            AdminAction::RepairSupply,
+            Some(Value::Nat(report.ledger_supply().amount.into())),
+            Some(Value::Nat(Self::verify().ledger_supply().amount.into())),
+        );
+
+        Ok(Some(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::ledger::RetentionPolicy;
+    use crate::tx_record::TxRecord;
+
+    fn init() {
+        MockContext::new().with_caller(john()).inject();
+        TokenConfig::set_stable(TokenConfig::default());
+        StableBalances.clear();
+        LedgerData::clear();
+    }
+
+    #[test]
+    fn supply_matches_ledger() {
+        init();
+        StableBalances.insert(alice().into(), 100.into());
+        LedgerData::mint(john().into(), alice().into(), 150.into());
+        LedgerData::burn(alice().into(), alice().into(), 50.into());
+
+        let report = Invariants::verify();
+        assert_eq!(report.total_supply, 100.into());
+        assert_eq!(report.minted, 150.into());
+        assert_eq!(report.burned, 50.into());
+        assert!(report.history_complete);
+        assert_eq!(report.discrepancy, None);
+        assert_eq!(Invariants::repair(john()), Ok(None));
+    }
+
+    #[test]
+    fn discrepancy_is_repaired() {
+        init();
+        StableBalances.insert(alice().into(), 100.into());
+        LedgerData::mint(john().into(), alice().into(), 70.into());
+        assert_eq!(
+            Invariants::verify().discrepancy,
+            Some(SupplyDiscrepancy::Surplus(30.into()))
+        );
+
+        let id = Invariants::repair(john()).unwrap().unwrap();
+        let record: TxRecord = LedgerData::get(id).unwrap();
+        assert_eq!(record.amount, 30.into());
+        assert_eq!(Invariants::verify().discrepancy, None);
+        assert_eq!(StableBalances.balance_of(&alice().into()), 100.into());
+
+        StableBalances.insert(alice().into(), 60.into());
+        assert_eq!(
+            Invariants::verify().discrepancy,
+            Some(SupplyDiscrepancy::Deficit(40.into()))
+        );
+        Invariants::repair(john()).unwrap();
+        assert_eq!(Invariants::verify().discrepancy, None);
+    }
+
+    #[test]
+    fn pruned_history_is_not_checked() {
+        init();
+        StableBalances.insert(alice().into(), 100.into());
+        LedgerData::mint(john().into(), alice().into(), 50.into());
+        LedgerData::mint(john().into(), alice().into(), 50.into());
+        LedgerData::prune(&RetentionPolicy {
+            max_length: Some(1),
+            max_age_nanos: None,
+        });
+
+        let report = Invariants::verify();
+        assert!(!report.history_complete);
+        assert_eq!(report.discrepancy, None);
+        assert_eq!(Invariants::repair(john()), Err(TxError::HistoryIncomplete));
+    }
+}
@@ -0,0 +1,311 @@
+//! Transfers exceeding the threshold set by the token owner, which must be confirmed in two steps.
+//!
+//! If the large transfer policy is set, the direct transfers (including the batches, by the sum of
+//! their amounts) above the threshold are rejected. Instead, the sender calls
+//! `initiate_large_transfer`, which stores the transfer as pending, and then confirms it with
+//! `confirm_large_transfer` within the confirmation window. The transfer can be confirmed or
+//! cancelled by the sender or by the co-signer designated on initiation. The fee is charged at the
+//! time of the confirmation.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::config::{Timestamp, TokenConfig};
+use crate::state::ledger::Memo;
+
+pub type PendingTransferId = u64;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct LargeTransferPolicy {
+    /// Transfers of larger amounts must be confirmed.
+    pub threshold: Tokens128,
+    /// Time after the initiation within which the transfer must be confirmed.
+    pub confirmation_window_nanos: u64,
+}
+
+impl LargeTransferPolicy {
+    pub fn validate(&self) -> Result<(), TxError> {
+        if self.confirmation_window_nanos == 0 {
+            return Err(TxError::InvalidLargeTransferPolicy {
+                reason: "confirmation window must be greater than zero".into(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct PendingTransfer {
+    pub id: PendingTransferId,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Tokens128,
+    pub memo: Option<Memo>,
+    /// Principal which can confirm or cancel the transfer in addition to the sender.
+    pub cosigner: Option<Principal>,
+    pub created_at: Timestamp,
+    pub expires_at: Timestamp,
+}
+
+impl PendingTransfer {
+    fn can_be_signed_by(&self, caller: Principal) -> bool {
+        self.from.owner == caller || self.cosigner == Some(caller)
+    }
+}
+
+impl Storable for PendingTransfer {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode pending transfer")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode pending transfer")
+    }
+}
+
+impl BoundedStorable for PendingTransfer {
+    // Three principals, two subaccounts, an amount, two timestamps and a memo of at most
+    // `MAX_MEMO_LENGTH` bytes with the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct LargeTransfers;
+
+impl LargeTransfers {
+    /// Returns an error if the `amount` must be transferred with the two steps confirmation.
+    pub fn check_amount(amount: Tokens128) -> Result<(), TxError> {
+        match TokenConfig::get_stable().large_transfer_policy {
+            Some(policy) if amount > policy.threshold => Err(TxError::ConfirmationRequired {
+                threshold: policy.threshold,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores the transfer as pending until it's confirmed. The expired pending transfers are
+    /// removed at the same time.
+    pub fn initiate(
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        memo: Option<Memo>,
+        cosigner: Option<Principal>,
+        now: Timestamp,
+    ) -> Result<PendingTransferId, TxError> {
+        let policy = TokenConfig::get_stable()
+            .large_transfer_policy
+            .ok_or(TxError::LargeTransfersNotConfigured)?;
+        if amount.is_zero() {
+            return Err(TxError::AmountTooSmall);
+        }
+
+        Self::remove_expired(now);
+
+        let id = NEXT_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1)
+                .expect("failed to write next pending transfer id");
+            id
+        });
+
+        let transfer = PendingTransfer {
+            id,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            memo,
+            cosigner,
+            created_at: now,
+            expires_at: now.saturating_add(policy.confirmation_window_nanos),
+        };
+        TRANSFERS.with(|map| map.borrow_mut().insert(id, transfer));
+
+        Ok(id)
+    }
+
+    pub fn get(id: PendingTransferId) -> Option<PendingTransfer> {
+        TRANSFERS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns the pending transfers in which `who` is the sender or the co-signer.
+    pub fn list(who: Principal) -> Vec<PendingTransfer> {
+        TRANSFERS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, transfer)| transfer)
+                .filter(|transfer| transfer.can_be_signed_by(who))
+                .collect()
+        })
+    }
+
+    /// Checks that the `caller` can confirm the transfer at the time `now`, and returns the
+    /// transfer. The transfer is removed with `remove` once executed.
+    pub fn check_confirmation(
+        caller: Principal,
+        id: PendingTransferId,
+        now: Timestamp,
+    ) -> Result<PendingTransfer, TxError> {
+        let transfer = Self::get(id).ok_or(TxError::PendingTransferNotFound)?;
+        if !transfer.can_be_signed_by(caller) {
+            return Err(TxError::Unauthorized);
+        }
+
+        if now > transfer.expires_at {
+            return Err(TxError::PendingTransferExpired {
+                expired_at: transfer.expires_at,
+            });
+        }
+
+        Ok(transfer)
+    }
+
+    /// Removes the transfer. Both the sender and the co-signer can cancel it.
+    pub fn cancel(caller: Principal, id: PendingTransferId) -> Result<(), TxError> {
+        let transfer = Self::get(id).ok_or(TxError::PendingTransferNotFound)?;
+        if !transfer.can_be_signed_by(caller) {
+            return Err(TxError::Unauthorized);
+        }
+
+        Self::remove(id);
+        Ok(())
+    }
+
+    pub fn remove(id: PendingTransferId) {
+        TRANSFERS.with(|map| map.borrow_mut().remove(&id));
+    }
+
+    fn remove_expired(now: Timestamp) {
+        TRANSFERS.with(|map| {
+            let mut map = map.borrow_mut();
+            let expired = map
+                .iter()
+                .filter(|(_, transfer)| now > transfer.expires_at)
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            for id in expired {
+                map.remove(&id);
+            }
+        });
+    }
+
+    pub fn clear() {
+        TRANSFERS.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next pending transfer id")
+        });
+    }
+}
+
+const PENDING_TRANSFERS_MEMORY_ID: MemoryId = MemoryId::new(22);
+const NEXT_PENDING_TRANSFER_ID_MEMORY_ID: MemoryId = MemoryId::new(23);
+
+thread_local! {
+    static TRANSFERS: RefCell<StableBTreeMap<PendingTransferId, PendingTransfer>> =
+        RefCell::new(StableBTreeMap::new(PENDING_TRANSFERS_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<PendingTransferId>> =
+        RefCell::new(StableCell::new(NEXT_PENDING_TRANSFER_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next pending transfer id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john, xtc};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    fn init() {
+        MockContext::new().inject();
+        LargeTransfers::clear();
+        TokenConfig::set_stable(TokenConfig {
+            large_transfer_policy: Some(LargeTransferPolicy {
+                threshold: 1000.into(),
+                confirmation_window_nanos: 100,
+            }),
+            ..TokenConfig::default()
+        });
+    }
+
+    #[test]
+    fn amounts_above_threshold_require_confirmation() {
+        init();
+        assert_eq!(LargeTransfers::check_amount(1000.into()), Ok(()));
+        assert_eq!(
+            LargeTransfers::check_amount(1001.into()),
+            Err(TxError::ConfirmationRequired {
+                threshold: 1000.into()
+            })
+        );
+
+        TokenConfig::set_stable(TokenConfig::default());
+        assert_eq!(LargeTransfers::check_amount(u128::MAX.into()), Ok(()));
+        assert_eq!(
+            LargeTransfers::initiate(alice().into(), bob().into(), 1.into(), None, None, 0),
+            Err(TxError::LargeTransfersNotConfigured)
+        );
+    }
+
+    #[test]
+    fn confirmation_by_sender_or_cosigner() {
+        init();
+        let id = LargeTransfers::initiate(
+            alice().into(),
+            bob().into(),
+            5000.into(),
+            None,
+            Some(john()),
+            1000,
+        )
+        .unwrap();
+
+        assert_eq!(
+            LargeTransfers::check_confirmation(xtc(), id, 1050),
+            Err(TxError::Unauthorized)
+        );
+        assert!(LargeTransfers::check_confirmation(alice(), id, 1050).is_ok());
+        assert!(LargeTransfers::check_confirmation(john(), id, 1100).is_ok());
+        assert_eq!(
+            LargeTransfers::check_confirmation(alice(), id, 1101),
+            Err(TxError::PendingTransferExpired { expired_at: 1100 })
+        );
+        assert_eq!(LargeTransfers::list(john()).len(), 1);
+
+        assert_eq!(
+            LargeTransfers::cancel(bob(), id),
+            Err(TxError::Unauthorized)
+        );
+        LargeTransfers::cancel(john(), id).unwrap();
+        assert_eq!(
+            LargeTransfers::check_confirmation(alice(), id, 1050),
+            Err(TxError::PendingTransferNotFound)
+        );
+    }
+
+    #[test]
+    fn expired_transfers_are_removed() {
+        init();
+        let expired =
+            LargeTransfers::initiate(alice().into(), bob().into(), 5000.into(), None, None, 0)
+                .unwrap();
+        let pending =
+            LargeTransfers::initiate(alice().into(), bob().into(), 5000.into(), None, None, 200)
+                .unwrap();
+
+        assert_eq!(LargeTransfers::get(expired), None);
+        assert!(LargeTransfers::get(pending).is_some());
+    }
+}
@@ -1,25 +1,67 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::mem::size_of;
 
 use candid::{CandidType, Deserialize, Principal};
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use canister_sdk::ic_kit::ic;
-use ic_stable_structures::{MemoryId, StableCell};
+use ic_stable_structures::{MemoryId, StableCell, Storable};
 
 use crate::account::{Account, AccountInternal, Subaccount};
 use crate::error::TxError;
-use crate::state::config::Timestamp;
-use crate::tx_record::{TxId, TxRecord};
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::{Timestamp, TokenConfig};
+use crate::state::dedup::DedupIndex;
+use crate::state::subscriptions::EventSubscriptions;
+use crate::tx_record::{TxHash, TxId, TxRecord};
 
 const MAX_HISTORY_LENGTH: usize = 1_000_000;
 const HISTORY_REMOVAL_BATCH_SIZE: usize = 10_000;
+/// Automatic pruning by the retention policy is done once this number of records can be removed,
+/// to prevent often relocation of the history vec.
+const RETENTION_PRUNING_BATCH_SIZE: usize = 1_000;
 const TOTAL_TX_COUNT_MEMORY_ID: MemoryId = MemoryId::new(2);
+const BURNED_TOTAL_MEMORY_ID: MemoryId = MemoryId::new(16);
+const TIP_HASH_MEMORY_ID: MemoryId = MemoryId::new(31);
+/// Maximum number of records checked by one `verify_chain` call.
+pub const MAX_VERIFIED_RECORDS: usize = 10_000;
+/// Maximum length of a transfer memo, as in the ICRC-1 standard.
+pub const MAX_MEMO_LENGTH: usize = 32;
 
 thread_local! {
     static LEDGER: RefCell<HashMap<Principal, Ledger>> = RefCell::default();
     static TOTAL_TX_COUNT: RefCell<StableCell<u64>> =
         RefCell::new(StableCell::new(TOTAL_TX_COUNT_MEMORY_ID, 0)
             .expect("unable to initialize index offset for ledger"));
+    /// Total amount of the burned tokens. Kept separately from the history, as it may be pruned.
+    static BURNED_TOTAL: RefCell<StableCell<u128>> =
+        RefCell::new(StableCell::new(BURNED_TOTAL_MEMORY_ID, 0)
+            .expect("unable to initialize burned total"));
+    /// Hash of the last record, kept in the stable memory so the chain continues after the
+    /// upgrade and after the pruning.
+    static TIP_HASH: RefCell<StableCell<StorableHash>> =
+        RefCell::new(StableCell::new(TIP_HASH_MEMORY_ID, StorableHash::default())
+            .expect("unable to initialize ledger tip hash"));
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct StorableHash(TxHash);
+
+impl Storable for StorableHash {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(
+            bytes
+                .as_ref()
+                .try_into()
+                .expect("invalid ledger tip hash length"),
+        )
+    }
 }
 
 pub struct LedgerData;
@@ -45,6 +87,11 @@ impl LedgerData {
         Self::with_ledger(|ledger| ledger.get_transactions(who, count, transaction_id))
     }
 
+    /// Returns up to `count` stored transactions with ids starting from `start`, oldest first.
+    pub fn get_range(start: TxId, count: usize) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.get_range(start, count))
+    }
+
     pub fn list_transactions() -> Vec<TxRecord> {
         Self::with_ledger(|ledger| ledger.iter().cloned().collect())
     }
@@ -53,6 +100,28 @@ impl LedgerData {
         Self::with_ledger(|ledger| ledger.get_len_user_history(user))
     }
 
+    pub fn get_subaccount_transactions(
+        account: AccountInternal,
+        count: usize,
+        offset: usize,
+    ) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.get_subaccount_transactions(account, count, offset))
+    }
+
+    /// Returns a page of the `args.account` history together with its current balance.
+    pub fn get_account_transactions(args: GetAccountTransactionsArgs) -> GetAccountTransactions {
+        let account = AccountInternal::from(args.account);
+        let (transactions, oldest_tx_id) = Self::with_ledger(|ledger| {
+            ledger.get_account_transactions(account, args.start, args.max_results as usize)
+        });
+
+        GetAccountTransactions {
+            balance: StableBalances.balance_of(&account),
+            transactions,
+            oldest_tx_id,
+        }
+    }
+
     pub fn transfer(
         from: AccountInternal,
         to: AccountInternal,
@@ -64,22 +133,64 @@ impl LedgerData {
         Self::with_ledger(|ledger| ledger.transfer(from, to, amount, fee, memo, created_at_time))
     }
 
+    pub fn transfer_from(
+        caller: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.transfer_from(caller, from, to, amount, fee, memo))
+    }
+
+    pub fn approve(owner: AccountInternal, spender: Principal, amount: Tokens128) -> TxId {
+        Self::with_ledger(|ledger| ledger.approve(owner, spender, amount))
+    }
+
+    pub fn standing_order(
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.standing_order(from, to, amount, fee))
+    }
+
     pub fn batch_transfer(
         from: AccountInternal,
         transfers: Vec<BatchTransferArgs>,
-        fee: Tokens128,
+        fees: Vec<Tokens128>,
     ) -> Vec<TxId> {
-        Self::with_ledger(|ledger| ledger.batch_transfer(from, transfers, fee))
+        Self::with_ledger(|ledger| ledger.batch_transfer(from, transfers, fees))
     }
 
     pub fn mint(from: AccountInternal, to: AccountInternal, amount: Tokens128) -> TxId {
         Self::with_ledger(|ledger| ledger.mint(from, to, amount))
     }
 
+    /// Appends the `records` in one go, see `Ledger::append`.
+    pub fn append(records: Vec<TxRecord>) -> Vec<TxId> {
+        Self::with_ledger(|ledger| ledger.append(records))
+    }
+
     pub fn burn(caller: AccountInternal, from: AccountInternal, amount: Tokens128) -> TxId {
         Self::with_ledger(|ledger| ledger.burn(caller, from, amount))
     }
 
+    /// Records the `amount` burned by the burn policy from the transfer of the `from` account, if
+    /// any. Must be called right after the transfer record is written, so the burn record follows
+    /// it.
+    pub fn record_transfer_burn(from: AccountInternal, amount: Tokens128) -> Option<TxId> {
+        if amount.is_zero() {
+            return None;
+        }
+
+        let id = Self::burn(from, from, amount);
+        Self::add_burned(amount);
+        Some(id)
+    }
+
     pub fn record_auction(to: Principal, amount: Tokens128) {
         Self::with_ledger(|ledger| ledger.record_auction(to, amount))
     }
@@ -88,10 +199,75 @@ impl LedgerData {
         Self::with_ledger(|ledger| ledger.claim(claim_account, to, amount))
     }
 
+    pub fn rescale(caller: AccountInternal, total_supply: Tokens128) -> TxId {
+        Self::with_ledger(|ledger| ledger.rescale(caller, total_supply))
+    }
+
+    pub fn burned_total() -> Tokens128 {
+        BURNED_TOTAL.with(|total| Tokens128::from(*total.borrow().get()))
+    }
+
+    pub fn add_burned(amount: Tokens128) {
+        Self::set_burned_total(
+            Self::burned_total()
+                .amount
+                .saturating_add(amount.amount)
+                .into(),
+        );
+    }
+
+    pub fn set_burned_total(amount: Tokens128) {
+        BURNED_TOTAL.with(|total| {
+            total
+                .borrow_mut()
+                .set(amount.amount)
+                .expect("fail to write burned total")
+        });
+    }
+
+    /// Removes the records exceeding the limits of the `policy`. Returns the number of removed
+    /// records.
+    pub fn prune(policy: &RetentionPolicy) -> u64 {
+        Self::with_ledger(|ledger| ledger.prune(policy, ic::time()))
+    }
+
+    pub fn history_info() -> HistoryInfo {
+        Self::with_ledger(|ledger| ledger.history_info())
+    }
+
+    pub fn supply_totals() -> (Tokens128, Tokens128) {
+        Self::with_ledger(|ledger| ledger.supply_totals())
+    }
+
     pub fn clear() {
         Self::with_ledger(|ledger| ledger.clear())
     }
 
+    /// Releases the memory left unused in the history and the memo index after the pruning.
+    pub fn compact() -> CompactionReport {
+        Self::with_ledger(|ledger| ledger.compact(ic::time()))
+    }
+
+    pub fn maintenance_status() -> MaintenanceStatus {
+        Self::with_ledger(|ledger| ledger.maintenance_status())
+    }
+
+    pub fn get_transactions_by_memo(memo: &[u8], count: usize) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.get_transactions_by_memo(memo, count))
+    }
+
+    pub fn set_memo_index(enabled: bool) {
+        Self::with_ledger(|ledger| ledger.set_memo_index(enabled))
+    }
+
+    pub fn tip() -> LedgerTip {
+        Self::with_ledger(|ledger| ledger.tip())
+    }
+
+    pub fn verify_chain(start: TxId, length: usize) -> ChainVerification {
+        Self::with_ledger(|ledger| ledger.verify_chain(start, length))
+    }
+
     fn with_ledger<F, R>(f: F) -> R
     where
         F: FnOnce(&mut Ledger) -> R,
@@ -108,6 +284,15 @@ impl LedgerData {
 #[derive(Debug, Default, CandidType, Deserialize)]
 pub struct Ledger {
     history: Vec<TxRecord>,
+    /// Ids of the transactions with the given memo. Filled only if the memo index is enabled in
+    /// the token config.
+    memo_index: HashMap<Memo, Vec<TxId>>,
+    /// Ids of the stored transactions related to the principal (see `TxRecord::contains`),
+    /// oldest first.
+    user_index: HashMap<Principal, Vec<TxId>>,
+    /// Ids of the stored transactions sent from or to the account, oldest first.
+    account_index: HashMap<AccountInternal, Vec<TxId>>,
+    last_compaction: Option<CompactionReport>,
 }
 
 impl Ledger {
@@ -133,15 +318,24 @@ impl Ledger {
         count: usize,
         transaction_id: Option<TxId>,
     ) -> PaginatedResult {
-        let mut transactions = self
-            .history
-            .iter()
-            .rev()
-            .filter(|&tx| who.map_or(true, |c| tx.contains(c)))
-            .filter(|tx| transaction_id.map_or(true, |id| id >= tx.index))
-            .take(count + 1)
-            .cloned()
-            .collect::<Vec<_>>();
+        let mut transactions = match who {
+            Some(who) => self
+                .user_ids(who)
+                .iter()
+                .rev()
+                .filter(|&&id| transaction_id.map_or(true, |start| start >= id))
+                .take(count + 1)
+                .filter_map(|&id| self.get(id))
+                .collect::<Vec<_>>(),
+            None => self
+                .history
+                .iter()
+                .rev()
+                .filter(|tx| transaction_id.map_or(true, |id| id >= tx.index))
+                .take(count + 1)
+                .cloned()
+                .collect::<Vec<_>>(),
+        };
 
         let next_id = if transactions.len() == count + 1 {
             Some(transactions.remove(count).index)
@@ -155,12 +349,113 @@ impl Ledger {
         }
     }
 
+    /// Returns up to `max_results` transactions of the `account` with ids smaller than `start`,
+    /// newest first, and the id of the oldest stored transaction of the account.
+    pub fn get_account_transactions(
+        &self,
+        account: AccountInternal,
+        start: Option<TxId>,
+        max_results: usize,
+    ) -> (Vec<AccountTransaction>, Option<TxId>) {
+        let ids = self.account_ids(account);
+        let transactions = ids
+            .iter()
+            .rev()
+            .filter(|&&id| start.map_or(true, |start| id < start))
+            .take(max_results)
+            .filter_map(|&id| self.get(id))
+            .map(|transaction| AccountTransaction {
+                id: transaction.index,
+                transaction,
+            })
+            .collect();
+
+        (transactions, ids.first().copied())
+    }
+
+    /// Returns up to `count` transactions of the `account`, newest first, skipping the `offset`
+    /// latest ones.
+    pub fn get_subaccount_transactions(
+        &self,
+        account: AccountInternal,
+        count: usize,
+        offset: usize,
+    ) -> Vec<TxRecord> {
+        self.account_ids(account)
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(count)
+            .filter_map(|&id| self.get(id))
+            .collect()
+    }
+
+    fn user_ids(&self, user: Principal) -> &[TxId] {
+        self.user_index.get(&user).map_or(&[], Vec::as_slice)
+    }
+
+    fn account_ids(&self, account: AccountInternal) -> &[TxId] {
+        self.account_index.get(&account).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns up to `count` transactions with the given `memo`, newest first. Uses the memo
+    /// index if it's enabled, otherwise scans the history.
+    pub fn get_transactions_by_memo(&self, memo: &[u8], count: usize) -> Vec<TxRecord> {
+        if TokenConfig::get_stable().memo_index.unwrap_or(false) {
+            return self
+                .memo_index
+                .get(memo)
+                .into_iter()
+                .flat_map(|ids| ids.iter().rev())
+                .filter_map(|&id| self.get(id))
+                .take(count)
+                .collect();
+        }
+
+        self.history
+            .iter()
+            .rev()
+            .filter(|tx| tx.memo.as_deref() == Some(memo))
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Builds the memo index from the stored history, or drops it if `enabled` is false.
+    pub fn set_memo_index(&mut self, enabled: bool) {
+        self.memo_index.clear();
+        if enabled {
+            for tx in &self.history {
+                if let Some(memo) = &tx.memo {
+                    self.memo_index
+                        .entry(memo.clone())
+                        .or_default()
+                        .push(tx.index);
+                }
+            }
+        }
+    }
+
+    pub fn get_range(&self, start: TxId, count: usize) -> Vec<TxRecord> {
+        let start = start.max(self.first_stored_tx_id());
+        match self.get_index(start) {
+            Some(index) => self
+                .history
+                .iter()
+                .skip(index)
+                .take(count)
+                .cloned()
+                .collect(),
+            None => vec![],
+        }
+    }
+
     pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TxRecord> {
         self.history.iter()
     }
 
     fn get_index(&self, id: TxId) -> Option<usize> {
-        let first_stored_tx_id = Self::read_total_tx_count() - self.history.len() as u64; // Always >= 0
+        let first_stored_tx_id = self.first_stored_tx_id();
         if id < first_stored_tx_id || id > usize::MAX as TxId {
             None
         } else {
@@ -168,8 +463,171 @@ impl Ledger {
         }
     }
 
+    /// Id of the first record stored in the history. All records with smaller ids were pruned.
+    fn first_stored_tx_id(&self) -> TxId {
+        Self::read_total_tx_count() - self.history.len() as u64 // Always >= 0
+    }
+
+    pub fn history_info(&self) -> HistoryInfo {
+        let earliest_index = self.first_stored_tx_id();
+        HistoryInfo {
+            length: self.len(),
+            earliest_index,
+            pruned_count: earliest_index,
+            retention: TokenConfig::get_stable().history_retention,
+        }
+    }
+
+    /// Total amounts of the mint and of the burn records in the stored history. A rescale record
+    /// resets the totals to the supply after the rescaling.
+    pub fn supply_totals(&self) -> (Tokens128, Tokens128) {
+        let (minted, burned) = self
+            .history
+            .iter()
+            .fold((0u128, 0u128), |(minted, burned), tx| match tx.operation {
+                Operation::Mint => (minted.saturating_add(tx.amount.amount), burned),
+                Operation::Burn => (minted, burned.saturating_add(tx.amount.amount)),
+                Operation::Rescale => (tx.amount.amount, 0),
+                _ => (minted, burned),
+            });
+
+        (minted.into(), burned.into())
+    }
+
+    /// Bytes allocated for the history and the indexes, but not used by the records.
+    fn spare_bytes(&self) -> u64 {
+        let history = (self.history.capacity() - self.history.len()) * size_of::<TxRecord>();
+        let indexes = self
+            .memo_index
+            .values()
+            .chain(self.user_index.values())
+            .chain(self.account_index.values())
+            .map(|ids| (ids.capacity() - ids.len()) * size_of::<TxId>())
+            .sum::<usize>();
+
+        (history + indexes) as u64
+    }
+
+    pub fn compact(&mut self, now: Timestamp) -> CompactionReport {
+        let spare_bytes = self.spare_bytes();
+        self.history.shrink_to_fit();
+        self.memo_index.shrink_to_fit();
+        self.user_index.shrink_to_fit();
+        self.account_index.shrink_to_fit();
+        for ids in self
+            .memo_index
+            .values_mut()
+            .chain(self.user_index.values_mut())
+            .chain(self.account_index.values_mut())
+        {
+            ids.shrink_to_fit();
+        }
+
+        let report = CompactionReport {
+            timestamp: now,
+            spare_bytes_released: spare_bytes.saturating_sub(self.spare_bytes()),
+        };
+        self.last_compaction = Some(report);
+        report
+    }
+
+    pub fn maintenance_status(&self) -> MaintenanceStatus {
+        MaintenanceStatus {
+            history_length: self.history.len() as u64,
+            history_capacity: self.history.capacity() as u64,
+            spare_bytes: self.spare_bytes(),
+            last_compaction: self.last_compaction,
+        }
+    }
+
+    /// Number of the records exceeding the limits of the `policy`.
+    fn prunable_count(&self, policy: &RetentionPolicy, now: Timestamp) -> usize {
+        let by_length = policy.max_length.map_or(0, |max_length| {
+            self.history.len().saturating_sub(max_length as usize)
+        });
+        let by_age = policy.max_age_nanos.map_or(0, |max_age| {
+            let min_time = now.saturating_sub(max_age);
+            self.history
+                .iter()
+                .take_while(|tx| tx.timestamp < min_time)
+                .count()
+        });
+
+        by_length.max(by_age)
+    }
+
+    pub fn prune(&mut self, policy: &RetentionPolicy, now: Timestamp) -> u64 {
+        let count = self.prunable_count(policy, now);
+        self.remove_oldest(count);
+        count as u64
+    }
+
+    /// Removes the `count` oldest records from the history and from the indexes.
+    fn remove_oldest(&mut self, count: usize) {
+        let mut users = HashSet::new();
+        let mut accounts = HashSet::new();
+        for tx in self.history.drain(..count) {
+            users.extend(tx.participants());
+            accounts.extend(tx.accounts());
+            let Some(memo) = tx.memo else {
+                continue;
+            };
+
+            if let Some(ids) = self.memo_index.get_mut(&memo) {
+                ids.retain(|&id| id != tx.index);
+                if ids.is_empty() {
+                    self.memo_index.remove(&memo);
+                }
+            }
+        }
+
+        let first_stored_tx_id = self.first_stored_tx_id();
+        remove_ids_before(&mut self.user_index, users, first_stored_tx_id);
+        remove_ids_before(&mut self.account_index, accounts, first_stored_tx_id);
+    }
+
+    /// Length of the ledger and the hash of its last record.
+    pub fn tip(&self) -> LedgerTip {
+        LedgerTip {
+            length: self.len(),
+            hash: Self::read_tip_hash(),
+        }
+    }
+
+    /// Checks the hashes of up to `length` (at most `MAX_VERIFIED_RECORDS`) stored records
+    /// starting from the id `start`: every record must be hashed over its contents and the hash
+    /// of the previous record. The first checked record is trusted to have the right parent hash,
+    /// as the previous record may be pruned.
+    pub fn verify_chain(&self, start: TxId, length: usize) -> ChainVerification {
+        let start = start.max(self.first_stored_tx_id());
+        let records = self
+            .get_index(start)
+            .and_then(|index| self.history.get(index..))
+            .unwrap_or_default();
+
+        let mut verified = 0;
+        let mut first_invalid = None;
+        let mut parent_hash = records.first().and_then(|tx| tx.parent_hash);
+        for tx in records.iter().take(length.min(MAX_VERIFIED_RECORDS)) {
+            let valid = parent_hash.map_or(false, |parent_hash| tx.verify_hash(&parent_hash));
+            if !valid {
+                first_invalid = Some(tx.index);
+                break;
+            }
+
+            parent_hash = tx.hash;
+            verified += 1;
+        }
+
+        ChainVerification {
+            start,
+            verified,
+            first_invalid,
+        }
+    }
+
     pub fn get_len_user_history(&self, user: Principal) -> usize {
-        self.history.iter().filter(|&tx| tx.contains(user)).count()
+        self.user_ids(user).len()
     }
 
     pub fn transfer(
@@ -195,16 +653,63 @@ impl Ledger {
         id
     }
 
+    pub fn transfer_from(
+        &mut self,
+        caller: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::transfer_from(
+            id, caller, from, to, amount, fee, memo,
+        ));
+
+        id
+    }
+
+    pub fn approve(
+        &mut self,
+        owner: AccountInternal,
+        spender: Principal,
+        amount: Tokens128,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::approve(id, owner, spender, amount));
+
+        id
+    }
+
+    pub fn standing_order(
+        &mut self,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::standing_order(id, from, to, amount, fee));
+
+        id
+    }
+
     pub fn batch_transfer(
         &mut self,
         from: AccountInternal,
         transfers: Vec<BatchTransferArgs>,
-        fee: Tokens128,
+        fees: Vec<Tokens128>,
     ) -> Vec<TxId> {
-        transfers
+        let now = ic::time();
+        let records = transfers
             .into_iter()
-            .map(|x| self.transfer(from, x.receiver.into(), x.amount, fee, None, ic::time()))
-            .collect()
+            .zip(fees)
+            .map(|(x, fee)| {
+                TxRecord::transfer(0, from, x.receiver.into(), x.amount, fee, None, now)
+            })
+            .collect();
+        self.append(records)
     }
 
     pub fn mint(&mut self, from: AccountInternal, to: AccountInternal, amount: Tokens128) -> TxId {
@@ -232,16 +737,61 @@ impl Ledger {
     }
 
     fn push(&mut self, record: TxRecord) {
-        self.history.push(record);
-        Self::increase_total_tx_count();
-        if self.history.len() > MAX_HISTORY_LENGTH + HISTORY_REMOVAL_BATCH_SIZE {
+        self.append(vec![record]);
+    }
+
+    /// Appends the `records`, assigning them consecutive ids. The ids the records were created
+    /// with are ignored. The token config is read and the stable transactions count is written
+    /// once for the whole batch, so appending a large batch is much cheaper than pushing the
+    /// records one by one. Every record is chained to the previous one by its hash.
+    pub fn append(&mut self, records: Vec<TxRecord>) -> Vec<TxId> {
+        let config = TokenConfig::get_stable();
+        let first_id = self.next_id();
+        let mut tip_hash = Self::read_tip_hash();
+        let mut ids = Vec::with_capacity(records.len());
+        for (mut record, id) in records.into_iter().zip(first_id..) {
+            record.index = id;
+            let hash = record.compute_hash(&tip_hash);
+            record.parent_hash = Some(tip_hash);
+            record.hash = Some(hash);
+            tip_hash = hash;
+            EventSubscriptions::on_record(&record);
+            if let (Some(memo), Some(true)) = (&record.memo, config.memo_index) {
+                self.memo_index
+                    .entry(memo.clone())
+                    .or_default()
+                    .push(record.index);
+            }
+            for user in record.participants() {
+                self.user_index.entry(user).or_default().push(id);
+            }
+            for account in record.accounts() {
+                self.account_index.entry(account).or_default().push(id);
+            }
+
+            self.history.push(record);
+            ids.push(id);
+        }
+
+        Self::increase_total_tx_count(ids.len() as u64);
+        Self::write_tip_hash(tip_hash);
+        while self.history.len() > MAX_HISTORY_LENGTH + HISTORY_REMOVAL_BATCH_SIZE {
             // We remove first `HISTORY_REMOVAL_BATCH_SIZE` from the history at one go, to prevent
             // often relocation of the history vec.
             // This removal code can later be changed to moving old history records into another
             // storage.
 
-            self.history = self.history[HISTORY_REMOVAL_BATCH_SIZE..].into();
+            self.remove_oldest(HISTORY_REMOVAL_BATCH_SIZE);
         }
+
+        if let Some(policy) = config.history_retention {
+            let now = ic::time();
+            if self.prunable_count(&policy, now) >= RETENTION_PRUNING_BATCH_SIZE {
+                self.prune(&policy, now);
+            }
+        }
+
+        ids
     }
 
     pub fn claim(
@@ -256,8 +806,21 @@ impl Ledger {
         id
     }
 
+    pub fn rescale(&mut self, caller: AccountInternal, total_supply: Tokens128) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::rescale(id, caller, total_supply));
+
+        id
+    }
+
     pub fn clear(&mut self) {
         self.history.clear();
+        self.memo_index.clear();
+        self.user_index.clear();
+        self.account_index.clear();
+        DedupIndex::clear();
+        LedgerData::set_burned_total(0.into());
+        Self::write_tip_hash(TxHash::default());
         TOTAL_TX_COUNT.with(|count| {
             count
                 .borrow_mut()
@@ -266,12 +829,12 @@ impl Ledger {
         });
     }
 
-    fn increase_total_tx_count() {
+    fn increase_total_tx_count(added: u64) {
         TOTAL_TX_COUNT.with(|count| {
             let mut count_mut = count.borrow_mut();
             let prev_count = *count_mut.get();
             count_mut
-                .set(prev_count + 1)
+                .set(prev_count + added)
                 .expect("fail to write total tx count")
         });
     }
@@ -279,6 +842,35 @@ impl Ledger {
     fn read_total_tx_count() -> u64 {
         TOTAL_TX_COUNT.with(|offset| *offset.borrow().get())
     }
+
+    fn read_tip_hash() -> TxHash {
+        TIP_HASH.with(|hash| hash.borrow().get().0)
+    }
+
+    fn write_tip_hash(hash: TxHash) {
+        TIP_HASH.with(|cell| {
+            cell.borrow_mut()
+                .set(StorableHash(hash))
+                .expect("fail to write ledger tip hash")
+        });
+    }
+}
+
+/// Removes the ids smaller than `first_id` from the index entries of the `keys`, and the entries
+/// left empty.
+fn remove_ids_before<K: Eq + Hash>(
+    index: &mut HashMap<K, Vec<TxId>>,
+    keys: HashSet<K>,
+    first_id: TxId,
+) {
+    for key in keys {
+        if let Some(ids) = index.get_mut(&key) {
+            ids.drain(..ids.partition_point(|&id| id < first_id));
+            if ids.is_empty() {
+                index.remove(&key);
+            }
+        }
+    }
 }
 
 pub type TxReceipt = Result<u128, TxError>;
@@ -298,6 +890,70 @@ pub enum Operation {
     Burn,
     Auction,
     Claim,
+    Rescale,
+    StandingOrder,
+    Recovery,
+    DustSweep,
+}
+
+/// Limits of the transaction history. The oldest records exceeding any of the limits are removed
+/// from the history.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_length: Option<u64>,
+    pub max_age_nanos: Option<u64>,
+}
+
+/// State of the transaction history, so the indexers know if some of the records were pruned.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct HistoryInfo {
+    /// Total number of the transactions, including the pruned ones.
+    pub length: u64,
+    /// Id of the earliest transaction available in the history.
+    pub earliest_index: TxId,
+    pub pruned_count: u64,
+    pub retention: Option<RetentionPolicy>,
+}
+
+/// Result of the ledger compaction.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub timestamp: Timestamp,
+    /// Estimated number of the spare heap bytes returned to the allocator, so they can be reused by
+    /// the later allocations. The Wasm memory never shrinks, so the memory size of the canister
+    /// stays the same.
+    pub spare_bytes_released: u64,
+}
+
+/// Memory usage of the transaction history.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct MaintenanceStatus {
+    /// Number of the stored records.
+    pub history_length: u64,
+    /// Number of the records the history can hold without reallocation.
+    pub history_capacity: u64,
+    /// Estimated number of the allocated heap bytes not used by the records. Grows when the
+    /// history is pruned and is returned to the allocator by the compaction.
+    pub spare_bytes: u64,
+    pub last_compaction: Option<CompactionReport>,
+}
+
+/// Length of the ledger and the hash of its last record, all zeros if the ledger is empty.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct LedgerTip {
+    pub length: u64,
+    pub hash: TxHash,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ChainVerification {
+    /// Id of the first checked record. Greater than the requested one if the records before it
+    /// were pruned.
+    pub start: TxId,
+    /// Number of the records with valid hashes, starting from `start`.
+    pub verified: u64,
+    /// Id of the first record with an invalid or missing hash, if any.
+    pub first_invalid: Option<TxId>,
 }
 
 /// `PaginatedResult` is returned by paginated queries i.e `get_transactions`.
@@ -310,6 +966,33 @@ pub struct PaginatedResult {
     pub next: Option<TxId>,
 }
 
+/// Arguments of `get_account_transactions`, as in the API of the ICRC index canister.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GetAccountTransactionsArgs {
+    pub account: Account,
+    /// Id of the last transaction seen by the client. The transactions older than it are
+    /// returned, or the latest ones if not set.
+    pub start: Option<TxId>,
+    /// Capped at `MAX_ACCOUNT_TRANSACTION_REQUEST`.
+    pub max_results: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct AccountTransaction {
+    pub id: TxId,
+    pub transaction: TxRecord,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GetAccountTransactions {
+    pub balance: Tokens128,
+    /// Transactions of the account, newest first.
+    pub transactions: Vec<AccountTransaction>,
+    /// Id of the oldest stored transaction of the account. The client has seen the whole history
+    /// once it receives this transaction.
+    pub oldest_tx_id: Option<TxId>,
+}
+
 // Batch transfer arguments.
 #[derive(Debug, Clone, CandidType, Deserialize)]
 pub struct BatchTransferArgs {
@@ -317,6 +1000,23 @@ pub struct BatchTransferArgs {
     pub amount: Tokens128,
 }
 
+/// Operation of the `atomic_batch` call. The transfers and burns are done from the subaccounts of
+/// the caller.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum BatchOperation {
+    Transfer {
+        from_subaccount: Option<Subaccount>,
+        to: Account,
+        amount: Tokens128,
+    },
+    /// Allowed only for the owner, or for anyone if the token is a test token.
+    Mint { to: Account, amount: Tokens128 },
+    Burn {
+        from_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+    },
+}
+
 /// These are the arguments which are taken in the `icrc1_transfer`
 #[derive(Debug, Clone, CandidType, Deserialize)]
 pub struct TransferArgs {
@@ -337,4 +1037,123 @@ impl TransferArgs {
     }
 }
 
-pub type Memo = [u8; 32];
+/// Transfer memo of up to `MAX_MEMO_LENGTH` bytes.
+pub type Memo = Vec<u8>;
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn records_are_chained() {
+        MockContext::new().inject();
+        LedgerData::clear();
+        assert_eq!(LedgerData::tip().hash, TxHash::default());
+
+        LedgerData::mint(john().into(), alice().into(), 100.into());
+        LedgerData::transfer(alice().into(), bob().into(), 10.into(), 0.into(), None, 0);
+        LedgerData::burn(bob().into(), bob().into(), 5.into());
+
+        let records = LedgerData::list_transactions();
+        assert_eq!(records[0].parent_hash, Some(TxHash::default()));
+        for pair in records.windows(2) {
+            assert_eq!(pair[1].parent_hash, pair[0].hash);
+        }
+        assert_eq!(
+            LedgerData::tip(),
+            LedgerTip {
+                length: 3,
+                hash: records[2].hash.unwrap(),
+            }
+        );
+        assert_eq!(
+            LedgerData::verify_chain(0, 10),
+            ChainVerification {
+                start: 0,
+                verified: 3,
+                first_invalid: None,
+            }
+        );
+
+        LedgerData::with_ledger(|ledger| ledger.history[1].amount = 1000.into());
+        assert_eq!(LedgerData::verify_chain(0, 10).first_invalid, Some(1));
+        assert_eq!(LedgerData::verify_chain(2, 10).first_invalid, None);
+    }
+
+    #[test]
+    fn account_transactions_are_indexed() {
+        MockContext::new().inject();
+        LedgerData::clear();
+
+        let alice_sub = AccountInternal::new(alice(), Some([1; 32]));
+        LedgerData::mint(john().into(), alice().into(), 100.into());
+        LedgerData::transfer(alice().into(), bob().into(), 10.into(), 0.into(), None, 0);
+        LedgerData::transfer(alice().into(), alice_sub, 10.into(), 0.into(), None, 0);
+        LedgerData::transfer(bob().into(), john().into(), 1.into(), 0.into(), None, 0);
+        assert_eq!(LedgerData::get_len_user_history(alice()), 3);
+        assert_eq!(LedgerData::get_len_user_history(bob()), 2);
+
+        let page = |account: AccountInternal, start, max_results| {
+            LedgerData::get_account_transactions(GetAccountTransactionsArgs {
+                account: account.into(),
+                start,
+                max_results,
+            })
+        };
+        let ids = |result: GetAccountTransactions| {
+            result
+                .transactions
+                .iter()
+                .map(|tx| tx.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(page(alice().into(), None, 10)), vec![2, 1, 0]);
+        assert_eq!(ids(page(alice().into(), None, 2)), vec![2, 1]);
+        assert_eq!(ids(page(alice().into(), Some(1), 10)), vec![0]);
+        assert_eq!(ids(page(alice_sub, None, 10)), vec![2]);
+        assert_eq!(page(bob().into(), None, 10).oldest_tx_id, Some(1));
+
+        let who = LedgerData::get_transactions(Some(bob()), 1, None);
+        assert_eq!(who.result[0].index, 3);
+        assert_eq!(who.next, Some(1));
+
+        LedgerData::with_ledger(|ledger| ledger.remove_oldest(2));
+        assert_eq!(LedgerData::get_len_user_history(alice()), 1);
+        assert_eq!(LedgerData::get_len_user_history(bob()), 1);
+        assert_eq!(page(bob().into(), None, 10).oldest_tx_id, Some(3));
+    }
+
+    #[test]
+    fn subaccount_transactions_are_paginated() {
+        MockContext::new().inject();
+        LedgerData::clear();
+
+        let treasury = AccountInternal::new(alice(), Some([1; 32]));
+        LedgerData::mint(john().into(), treasury, 100.into());
+        for _ in 0..3 {
+            LedgerData::transfer(treasury, bob().into(), 10.into(), 0.into(), None, 0);
+        }
+        LedgerData::transfer(alice().into(), bob().into(), 1.into(), 0.into(), None, 0);
+
+        let ids = |count, offset| {
+            LedgerData::get_subaccount_transactions(treasury, count, offset)
+                .iter()
+                .map(|tx| tx.index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(10, 0), vec![3, 2, 1, 0]);
+        assert_eq!(ids(2, 1), vec![2, 1]);
+        assert_eq!(ids(10, 4), Vec::<TxId>::new());
+        assert_eq!(
+            LedgerData::get_subaccount_transactions(alice().into(), 10, 0).len(),
+            1
+        );
+
+        LedgerData::with_ledger(|ledger| ledger.remove_oldest(2));
+        assert_eq!(ids(10, 0), vec![3, 2]);
+    }
+}
@@ -0,0 +1,173 @@
+//! Usage of the stable memory by the state of the token.
+//!
+//! Every stable structure of the token lives in its own virtual memory of the `MemoryManager`,
+//! which grows in buckets of `BUCKET_SIZE_PAGES` pages. `MemoryReport` lists the pages used by
+//! every memory, so the operators can see which structure grows and plan the archiving before the
+//! canister runs out of the stable memory.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{get_memory_by_id, Memory, MemoryId};
+
+/// Size of the stable memory page in bytes.
+pub const PAGE_SIZE_BYTES: u64 = 64 * 1024;
+
+/// Number of pages the `MemoryManager` allocates to a virtual memory at once.
+pub const BUCKET_SIZE_PAGES: u64 = 128;
+
+/// Maximum number of the buckets the `MemoryManager` can allocate, shared by all the memories.
+pub const MAX_BUCKETS: u64 = 32_768;
+
+/// Names of the memories allocated by the token, by their ids. Must be updated together with
+/// adding a new stable structure.
+pub const MEMORY_LAYOUT: &[(&str, u8)] = &[
+    ("config", 0),
+    ("legacy_balances", 1),
+    ("ledger_tx_count", 2),
+    ("event_subscriptions", 3),
+    ("dedup_index", 4),
+    ("nonces", 5),
+    ("frozen_accounts", 6),
+    ("admin_log", 7),
+    ("payment_subscriptions", 8),
+    ("next_payment_subscription_id", 9),
+    ("swaps", 10),
+    ("next_swap_id", 11),
+    ("icp_bridge_deposits", 12),
+    ("icp_bridge_operations", 13),
+    ("balances", 14),
+    ("decimals_migration", 15),
+    ("ledger_burned_total", 16),
+    ("auction_history", 17),
+    ("auction_totals", 18),
+    ("holders_count", 19),
+    ("account_tags", 20),
+    ("wrapper_operations", 21),
+    ("pending_transfers", 22),
+    ("next_pending_transfer_id", 23),
+    ("faucet_usage", 24),
+    ("payment_requests", 25),
+    ("next_payment_request_id", 26),
+    ("migration_progress", 27),
+    ("claim_account_ids", 28),
+    ("auction_min_bid_cycles", 29),
+    ("auction_bidder_blacklist", 30),
+    ("ledger_tip_hash", 31),
+    ("standing_orders", 32),
+    ("next_standing_order_id", 33),
+    ("guardians", 34),
+    ("recoveries", 35),
+    ("dust_report", 36),
+    ("auction_residual_withdrawals", 37),
+    ("timelock_proposals", 38),
+    ("next_proposal_id", 39),
+    ("merchant_authorizations", 40),
+    ("snapshots", 41),
+    ("snapshot_balances", 42),
+    ("upgrade_chunks", 43),
+    ("cycle_accounting", 44),
+    ("vesting_schedules", 45),
+    ("next_vesting_id", 46),
+    ("budgets", 47),
+    ("outbound_messages", 48),
+    ("next_outbound_message_id", 49),
+    ("expiring_transfers", 50),
+    ("next_expiring_transfer_id", 51),
+    ("bid_delegates", 52),
+    ("bid_delegators", 53),
+    ("fee_recipients", 54),
+    ("fees_received", 55),
+    ("export_sessions", 56),
+    ("allowances", 57),
+    ("approval_notifications", 58),
+    ("next_approval_notification_id", 59),
+    ("permit_nonces", 60),
+    ("allowance_expirations", 61),
+    ("allowance_expiry_queue", 62),
+    ("allowance_count", 63),
+    ("allowance_spenders", 64),
+    ("recovery_log", 65),
+];
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct StructureMemory {
+    pub name: String,
+    pub memory_id: u8,
+    /// Pages used by the memory, a multiple of `BUCKET_SIZE_PAGES`.
+    pub pages: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub structures: Vec<StructureMemory>,
+    /// Pages of the stable memory of the canister, including the header of the `MemoryManager`.
+    pub allocated_pages: u64,
+    /// Maximum number of pages the `MemoryManager` can allocate.
+    pub max_pages: u64,
+    /// Pages which can still be allocated before the `MemoryManager` runs out of the buckets.
+    pub headroom_pages: u64,
+    pub page_size_bytes: u64,
+}
+
+impl MemoryReport {
+    pub fn get() -> Self {
+        let structures: Vec<_> = MEMORY_LAYOUT
+            .iter()
+            .map(|&(name, memory_id)| StructureMemory {
+                name: name.to_string(),
+                memory_id,
+                pages: get_memory_by_id(MemoryId::new(memory_id)).size(),
+            })
+            .collect();
+
+        let used_pages = structures
+            .iter()
+            .map(|structure| structure.pages)
+            .sum::<u64>();
+        let max_pages = MAX_BUCKETS * BUCKET_SIZE_PAGES;
+        Self {
+            allocated_pages: stable_size().max(used_pages),
+            max_pages,
+            headroom_pages: max_pages.saturating_sub(used_pages),
+            page_size_bytes: PAGE_SIZE_BYTES,
+            structures,
+        }
+    }
+}
+
+#[cfg(target_family = "wasm")]
+fn stable_size() -> u64 {
+    canister_sdk::ic_cdk::api::stable::stable64_size()
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn stable_size() -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn memory_layout_is_unique() {
+        let ids: HashSet<_> = MEMORY_LAYOUT.iter().map(|(_, id)| id).collect();
+        let names: HashSet<_> = MEMORY_LAYOUT.iter().map(|(name, _)| name).collect();
+        assert_eq!(ids.len(), MEMORY_LAYOUT.len());
+        assert_eq!(names.len(), MEMORY_LAYOUT.len());
+    }
+
+    #[test]
+    fn report_covers_all_memories() {
+        MockContext::new().inject();
+
+        let report = MemoryReport::get();
+        assert_eq!(report.structures.len(), MEMORY_LAYOUT.len());
+        assert_eq!(report.max_pages, MAX_BUCKETS * BUCKET_SIZE_PAGES);
+        assert!(report.headroom_pages <= report.max_pages);
+    }
+}
@@ -0,0 +1,341 @@
+//! Pull payments by the merchants pre-authorized by the token holders.
+//!
+//! A payer authorizes a merchant with `authorize_merchant`, setting the maximum amount of a single
+//! payment and the maximum total amount per `CAP_PERIOD_NANOS`. The merchant then pulls the
+//! payments with `pull_payment` whenever it needs to, e.g. for the usage-based subscriptions,
+//! without the payer approving an unbounded allowance. The period starts with the first payment
+//! pulled after the previous period ended. Every payment is recorded in the ledger as a
+//! `TransferFrom` transaction made by the merchant.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::account::{Account, AccountInternal, Subaccount};
+use crate::error::TxError;
+use crate::state::balances::{PrincipalKey, PRINCIPAL_KEY_SIZE, SUBACCOUNT_MAX_LENGTH_IN_BYTES};
+use crate::state::config::Timestamp;
+
+/// Length of the period of the `monthly_cap`.
+pub const CAP_PERIOD_NANOS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Maximum number of the merchants authorized by one principal.
+pub const MAX_MERCHANTS_PER_PAYER: usize = 100;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct MerchantAuthorization {
+    pub payer: Account,
+    pub merchant: Principal,
+    /// Maximum amount of a single payment. The transfer fee is paid by the payer on top of it.
+    pub per_tx_cap: Tokens128,
+    /// Maximum total amount of the payments in one period of `CAP_PERIOD_NANOS`.
+    pub monthly_cap: Tokens128,
+    pub authorized_at: Timestamp,
+    /// Start of the current period, `None` until the first payment.
+    pub period_start: Option<Timestamp>,
+    /// Total amount of the payments in the current period.
+    pub period_spent: Tokens128,
+    pub pulled_total: Tokens128,
+}
+
+impl MerchantAuthorization {
+    /// Amount the merchant can still pull in the period current at the time `now`.
+    pub fn remaining_at(&self, now: Timestamp) -> Tokens128 {
+        if self.is_period_over(now) {
+            return self.monthly_cap;
+        }
+
+        self.monthly_cap.saturating_sub(self.period_spent)
+    }
+
+    fn is_period_over(&self, now: Timestamp) -> bool {
+        self.period_start
+            .map_or(true, |start| now >= start.saturating_add(CAP_PERIOD_NANOS))
+    }
+}
+
+impl Storable for MerchantAuthorization {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode merchant authorization")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode merchant authorization")
+    }
+}
+
+impl BoundedStorable for MerchantAuthorization {
+    // Two principals, a subaccount, four amounts and two timestamps with the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Payer account followed by the merchant, so the authorizations of one payer are adjacent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct AuthorizationKey(PrincipalKey, Subaccount, PrincipalKey);
+
+const AUTHORIZATION_KEY_SIZE: usize = 2 * PRINCIPAL_KEY_SIZE + SUBACCOUNT_MAX_LENGTH_IN_BYTES;
+
+impl AuthorizationKey {
+    fn new(payer: AccountInternal, merchant: Principal) -> Self {
+        Self(payer.owner.into(), payer.subaccount, merchant.into())
+    }
+}
+
+impl Storable for AuthorizationKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(AUTHORIZATION_KEY_SIZE);
+        bytes.extend_from_slice(&self.0.to_bytes());
+        bytes.extend_from_slice(&self.1);
+        bytes.extend_from_slice(&self.2.to_bytes());
+        bytes.into()
+    }
+
+    /// Expected `bytes.len() == AUTHORIZATION_KEY_SIZE`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (payer, rest) = bytes.split_at(PRINCIPAL_KEY_SIZE);
+        let (subaccount, merchant) = rest.split_at(SUBACCOUNT_MAX_LENGTH_IN_BYTES);
+        Self(
+            PrincipalKey::from_bytes(payer.to_vec().into()),
+            subaccount.try_into().expect("invalid subaccount length"),
+            PrincipalKey::from_bytes(merchant.to_vec().into()),
+        )
+    }
+}
+
+impl BoundedStorable for AuthorizationKey {
+    const MAX_SIZE: u32 = AUTHORIZATION_KEY_SIZE as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+pub struct MerchantAuthorizations;
+
+impl MerchantAuthorizations {
+    /// Authorizes the `merchant` to pull payments from the `payer` account, or replaces the caps
+    /// of the existing authorization. The payments already pulled in the current period count
+    /// towards the new `monthly_cap`.
+    pub fn authorize(
+        payer: AccountInternal,
+        merchant: Principal,
+        per_tx_cap: Tokens128,
+        monthly_cap: Tokens128,
+        now: Timestamp,
+    ) -> Result<MerchantAuthorization, TxError> {
+        if merchant == payer.owner || merchant == Principal::anonymous() {
+            return Err(TxError::InvalidMerchantAuthorization {
+                reason: "merchant must differ from the payer and the anonymous principal".into(),
+            });
+        }
+
+        if per_tx_cap.is_zero() || per_tx_cap > monthly_cap {
+            return Err(TxError::InvalidMerchantAuthorization {
+                reason: "per transaction cap must be positive and at most the monthly cap".into(),
+            });
+        }
+
+        let key = AuthorizationKey::new(payer, merchant);
+        let authorization = match AUTHORIZATIONS.with(|map| map.borrow().get(&key)) {
+            Some(existing) => MerchantAuthorization {
+                per_tx_cap,
+                monthly_cap,
+                ..existing
+            },
+            None => {
+                if Self::list_by_payer(payer.owner).len() >= MAX_MERCHANTS_PER_PAYER {
+                    return Err(TxError::InvalidMerchantAuthorization {
+                        reason: format!(
+                            "at most {MAX_MERCHANTS_PER_PAYER} merchants can be authorized"
+                        ),
+                    });
+                }
+
+                MerchantAuthorization {
+                    payer: payer.into(),
+                    merchant,
+                    per_tx_cap,
+                    monthly_cap,
+                    authorized_at: now,
+                    period_start: None,
+                    period_spent: Tokens128::ZERO,
+                    pulled_total: Tokens128::ZERO,
+                }
+            }
+        };
+
+        AUTHORIZATIONS.with(|map| map.borrow_mut().insert(key, authorization.clone()));
+        Ok(authorization)
+    }
+
+    pub fn get(payer: AccountInternal, merchant: Principal) -> Option<MerchantAuthorization> {
+        AUTHORIZATIONS.with(|map| map.borrow().get(&AuthorizationKey::new(payer, merchant)))
+    }
+
+    pub fn revoke(payer: AccountInternal, merchant: Principal) -> Result<(), TxError> {
+        AUTHORIZATIONS
+            .with(|map| {
+                map.borrow_mut()
+                    .remove(&AuthorizationKey::new(payer, merchant))
+            })
+            .map(|_| ())
+            .ok_or(TxError::MerchantNotAuthorized)
+    }
+
+    /// Returns the authorizations in which `who` is the payer or the merchant.
+    pub fn list(who: Principal) -> Vec<MerchantAuthorization> {
+        AUTHORIZATIONS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, authorization)| authorization)
+                .filter(|authorization| {
+                    authorization.payer.owner == who || authorization.merchant == who
+                })
+                .collect()
+        })
+    }
+
+    fn list_by_payer(payer: Principal) -> Vec<MerchantAuthorization> {
+        Self::list(payer)
+            .into_iter()
+            .filter(|authorization| authorization.payer.owner == payer)
+            .collect()
+    }
+
+    /// Checks that the `merchant` can pull `amount` from the `payer` account at the time `now`.
+    pub fn check_payment(
+        payer: AccountInternal,
+        merchant: Principal,
+        amount: Tokens128,
+        now: Timestamp,
+    ) -> Result<MerchantAuthorization, TxError> {
+        let authorization = Self::get(payer, merchant).ok_or(TxError::MerchantNotAuthorized)?;
+        if amount.is_zero() {
+            return Err(TxError::AmountTooSmall);
+        }
+
+        if amount > authorization.per_tx_cap {
+            return Err(TxError::AmountExceedsPerTxCap {
+                per_tx_cap: authorization.per_tx_cap,
+            });
+        }
+
+        let remaining = authorization.remaining_at(now);
+        if amount > remaining {
+            return Err(TxError::MonthlyCapExceeded { remaining });
+        }
+
+        Ok(authorization)
+    }
+
+    pub fn record_payment(
+        payer: AccountInternal,
+        merchant: Principal,
+        amount: Tokens128,
+        now: Timestamp,
+    ) {
+        let key = AuthorizationKey::new(payer, merchant);
+        AUTHORIZATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            if let Some(mut authorization) = map.get(&key) {
+                if authorization.is_period_over(now) {
+                    authorization.period_start = Some(now);
+                    authorization.period_spent = Tokens128::ZERO;
+                }
+                authorization.period_spent = (authorization.period_spent + amount)
+                    .unwrap_or_else(|| Tokens128::from(u128::MAX));
+                authorization.pulled_total = (authorization.pulled_total + amount)
+                    .unwrap_or_else(|| Tokens128::from(u128::MAX));
+                map.insert(key, authorization);
+            }
+        });
+    }
+
+    pub fn clear() {
+        AUTHORIZATIONS.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const MERCHANT_AUTHORIZATIONS_MEMORY_ID: MemoryId = MemoryId::new(40);
+
+thread_local! {
+    static AUTHORIZATIONS: RefCell<StableBTreeMap<AuthorizationKey, MerchantAuthorization>> =
+        RefCell::new(StableBTreeMap::new(MERCHANT_AUTHORIZATIONS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn payments_are_capped() {
+        MockContext::new().inject();
+        MerchantAuthorizations::clear();
+
+        let payer = AccountInternal::from(alice());
+        MerchantAuthorizations::authorize(payer, bob(), 100.into(), 250.into(), 0).unwrap();
+
+        assert_eq!(
+            MerchantAuthorizations::check_payment(payer, john(), 10.into(), 0),
+            Err(TxError::MerchantNotAuthorized)
+        );
+        assert_eq!(
+            MerchantAuthorizations::check_payment(payer, bob(), 101.into(), 0),
+            Err(TxError::AmountExceedsPerTxCap {
+                per_tx_cap: 100.into()
+            })
+        );
+
+        MerchantAuthorizations::record_payment(payer, bob(), 100.into(), 10);
+        MerchantAuthorizations::record_payment(payer, bob(), 100.into(), 20);
+        assert_eq!(
+            MerchantAuthorizations::check_payment(payer, bob(), 51.into(), 30),
+            Err(TxError::MonthlyCapExceeded {
+                remaining: 50.into()
+            })
+        );
+        assert!(MerchantAuthorizations::check_payment(payer, bob(), 50.into(), 30).is_ok());
+
+        let next_period = 10 + CAP_PERIOD_NANOS;
+        assert!(
+            MerchantAuthorizations::check_payment(payer, bob(), 100.into(), next_period).is_ok()
+        );
+        MerchantAuthorizations::record_payment(payer, bob(), 100.into(), next_period);
+        let authorization = MerchantAuthorizations::get(payer, bob()).unwrap();
+        assert_eq!(authorization.period_start, Some(next_period));
+        assert_eq!(authorization.period_spent, 100.into());
+        assert_eq!(authorization.pulled_total, 300.into());
+    }
+
+    #[test]
+    fn authorization_is_validated_and_revoked() {
+        MockContext::new().inject();
+        MerchantAuthorizations::clear();
+
+        let payer = AccountInternal::from(alice());
+        assert!(matches!(
+            MerchantAuthorizations::authorize(payer, alice(), 1.into(), 1.into(), 0),
+            Err(TxError::InvalidMerchantAuthorization { .. })
+        ));
+        assert!(matches!(
+            MerchantAuthorizations::authorize(payer, bob(), 2.into(), 1.into(), 0),
+            Err(TxError::InvalidMerchantAuthorization { .. })
+        ));
+
+        MerchantAuthorizations::authorize(payer, bob(), 1.into(), 1.into(), 0).unwrap();
+        assert_eq!(MerchantAuthorizations::list(bob()).len(), 1);
+        MerchantAuthorizations::revoke(payer, bob()).unwrap();
+        assert_eq!(
+            MerchantAuthorizations::revoke(payer, bob()),
+            Err(TxError::MerchantNotAuthorized)
+        );
+        assert!(MerchantAuthorizations::list(alice()).is_empty());
+    }
+}
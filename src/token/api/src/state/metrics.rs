@@ -0,0 +1,363 @@
+//! Operational metrics of the token, returned by `get_metrics` and served in the Prometheus text
+//! exposition format on the `/metrics` HTTP route.
+//!
+//! The counters are kept in the heap, so they are cheap to update on every call, and are reset on
+//! upgrade as usual for the Prometheus counters. The gauges are read from the state when the
+//! metrics are requested.
+//!
+//! The transaction methods are also wrapped with `EndpointMetrics::instrument`, which keeps the
+//! number of calls, errors and the instructions used per method, and traces the calls using more
+//! than `SLOW_CALL_INSTRUCTIONS`. These statistics are returned by `get_endpoint_stats`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::state::allowances::Allowances;
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::config::Timestamp;
+use crate::state::dust::DustReports;
+use crate::state::ledger::LedgerData;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TokenMetrics {
+    /// Number of the update calls per method since the last upgrade.
+    pub calls: Vec<(String, u64)>,
+    /// Number of the errors returned by the transaction methods per error type since the last
+    /// upgrade.
+    pub errors: Vec<(String, u64)>,
+    /// Instructions consumed by the periodic work done before the last update call, which is the
+    /// cycle auction check.
+    pub last_maintenance_instructions: u64,
+    pub history_length: u64,
+    pub holders: u64,
+    pub total_supply: Tokens128,
+    pub cycles: u64,
+    /// Number of the stored allowances, including the expired ones not removed yet.
+    pub allowances: u64,
+    /// Number of the expired allowances removed by the last run of the sweeper.
+    pub last_allowance_sweep_removed: u64,
+    /// Number of the expired allowances removed since the last upgrade.
+    pub expired_allowances_removed: u64,
+}
+
+/// Calls using more instructions than this are recorded in the slow calls trace.
+pub const SLOW_CALL_INSTRUCTIONS: u64 = 50_000_000;
+
+/// Number of the last slow calls kept in the trace.
+pub const MAX_SLOW_CALLS: usize = 100;
+
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct EndpointStats {
+    pub method: String,
+    pub calls: u64,
+    pub errors: u64,
+    pub max_instructions: u64,
+    pub total_instructions: u64,
+    pub mean_instructions: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct SlowCall {
+    pub method: String,
+    pub caller: Principal,
+    pub instructions: u64,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct EndpointStatsReport {
+    /// Statistics of the instrumented methods since the last upgrade or reset.
+    pub endpoints: Vec<EndpointStats>,
+    /// Last `MAX_SLOW_CALLS` calls using more than `SLOW_CALL_INSTRUCTIONS`, oldest first.
+    pub slow_calls: Vec<SlowCall>,
+}
+
+pub struct EndpointMetrics;
+
+impl EndpointMetrics {
+    pub fn record_call(method: &str) {
+        CALLS.with(|calls| *calls.borrow_mut().entry(method.to_string()).or_default() += 1);
+    }
+
+    /// Counts the error of the `result` by the error variant.
+    pub fn record_result<T>(result: &Result<T, TxError>) {
+        if let Err(e) = result {
+            ERRORS.with(|errors| *errors.borrow_mut().entry(error_kind(e)).or_default() += 1);
+        }
+    }
+
+    /// Runs the periodic work `f` and stores the number of instructions it consumed.
+    pub fn measure_maintenance<R>(f: impl FnOnce() -> R) -> R {
+        let start = instruction_counter();
+        let result = f();
+        MAINTENANCE_INSTRUCTIONS.with(|cell| cell.set(instruction_counter().saturating_sub(start)));
+        result
+    }
+
+    /// Runs the body `f` of the `method` and records its result and the instructions it used.
+    pub fn instrument<T, E>(method: &str, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let start = instruction_counter();
+        let result = f();
+        let instructions = instruction_counter().saturating_sub(start);
+
+        ENDPOINT_STATS.with(|stats| {
+            let mut stats = stats.borrow_mut();
+            let entry = stats
+                .entry(method.to_string())
+                .or_insert_with(|| EndpointStats {
+                    method: method.to_string(),
+                    ..Default::default()
+                });
+            entry.calls += 1;
+            if result.is_err() {
+                entry.errors += 1;
+            }
+            entry.max_instructions = entry.max_instructions.max(instructions);
+            entry.total_instructions = entry.total_instructions.saturating_add(instructions);
+            entry.mean_instructions = entry.total_instructions / entry.calls;
+        });
+
+        if instructions > SLOW_CALL_INSTRUCTIONS {
+            SLOW_CALLS.with(|calls| {
+                let mut calls = calls.borrow_mut();
+                if calls.len() >= MAX_SLOW_CALLS {
+                    calls.pop_front();
+                }
+                calls.push_back(SlowCall {
+                    method: method.to_string(),
+                    caller: ic::caller(),
+                    instructions,
+                    timestamp: ic::time(),
+                });
+            });
+        }
+
+        result
+    }
+
+    pub fn endpoint_stats() -> EndpointStatsReport {
+        EndpointStatsReport {
+            endpoints: ENDPOINT_STATS.with(|stats| stats.borrow().values().cloned().collect()),
+            slow_calls: SLOW_CALLS.with(|calls| calls.borrow().iter().cloned().collect()),
+        }
+    }
+
+    pub fn reset_endpoint_stats() {
+        ENDPOINT_STATS.with(|stats| stats.borrow_mut().clear());
+        SLOW_CALLS.with(|calls| calls.borrow_mut().clear());
+    }
+
+    pub fn get() -> TokenMetrics {
+        TokenMetrics {
+            calls: CALLS.with(|calls| calls.borrow().clone().into_iter().collect()),
+            errors: ERRORS.with(|errors| errors.borrow().clone().into_iter().collect()),
+            last_maintenance_instructions: MAINTENANCE_INSTRUCTIONS.with(Cell::get),
+            history_length: LedgerData::len(),
+            holders: DustReports::holders_count() as u64,
+            total_supply: StableBalances.total_supply(),
+            cycles: ic::balance(),
+            allowances: Allowances::count(),
+            last_allowance_sweep_removed: Allowances::sweep_stats().last_removed,
+            expired_allowances_removed: Allowances::sweep_stats().total_removed,
+        }
+    }
+
+    /// Formats the metrics in the Prometheus text exposition format.
+    pub fn prometheus() -> String {
+        let metrics = Self::get();
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, label: &str, values: &[(String, u64)]| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (key, value) in values {
+                let _ = writeln!(out, "{name}{{{label}=\"{key}\"}} {value}");
+            }
+        };
+        counter(
+            "is20_calls_total",
+            "Number of update calls per method.",
+            "method",
+            &metrics.calls,
+        );
+        counter(
+            "is20_errors_total",
+            "Number of errors returned by the transaction methods per type.",
+            "error",
+            &metrics.errors,
+        );
+        let _ = writeln!(
+            out,
+            "# HELP is20_expired_allowances_removed_total Number of expired allowances removed."
+        );
+        let _ = writeln!(out, "# TYPE is20_expired_allowances_removed_total counter");
+        let _ = writeln!(
+            out,
+            "is20_expired_allowances_removed_total {}",
+            metrics.expired_allowances_removed
+        );
+
+        let gauges = [
+            (
+                "is20_last_maintenance_instructions",
+                "Instructions consumed by the last periodic work.",
+                metrics.last_maintenance_instructions as u128,
+            ),
+            (
+                "is20_history_length",
+                "Number of stored transactions.",
+                metrics.history_length as u128,
+            ),
+            (
+                "is20_holders",
+                "Number of token holders.",
+                metrics.holders as u128,
+            ),
+            (
+                "is20_total_supply",
+                "Total supply in the smallest units.",
+                metrics.total_supply.amount,
+            ),
+            (
+                "is20_cycles",
+                "Cycles balance of the canister.",
+                metrics.cycles as u128,
+            ),
+            (
+                "is20_allowances",
+                "Number of stored allowances.",
+                metrics.allowances as u128,
+            ),
+            (
+                "is20_last_allowance_sweep_removed",
+                "Expired allowances removed by the last sweep.",
+                metrics.last_allowance_sweep_removed as u128,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        out
+    }
+
+    pub fn clear() {
+        CALLS.with(|calls| calls.borrow_mut().clear());
+        ERRORS.with(|errors| errors.borrow_mut().clear());
+        MAINTENANCE_INSTRUCTIONS.with(|cell| cell.set(0));
+        Self::reset_endpoint_stats();
+    }
+}
+
+/// Name of the error variant, without the fields.
+fn error_kind(error: &TxError) -> String {
+    format!("{error:?}")
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(target_family = "wasm")]
+pub(crate) fn instruction_counter() -> u64 {
+    canister_sdk::ic_cdk::api::performance_counter(0)
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn instruction_counter() -> u64 {
+    0
+}
+
+thread_local! {
+    static CALLS: RefCell<BTreeMap<String, u64>> = RefCell::default();
+    static ERRORS: RefCell<BTreeMap<String, u64>> = RefCell::default();
+    static MAINTENANCE_INSTRUCTIONS: Cell<u64> = Cell::new(0);
+    static ENDPOINT_STATS: RefCell<BTreeMap<String, EndpointStats>> = RefCell::default();
+    static SLOW_CALLS: RefCell<VecDeque<SlowCall>> = RefCell::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn counters() {
+        MockContext::new().inject();
+        EndpointMetrics::clear();
+
+        EndpointMetrics::record_call("icrc1_transfer");
+        EndpointMetrics::record_call("icrc1_transfer");
+        EndpointMetrics::record_call("mint");
+        EndpointMetrics::record_result::<()>(&Ok(()));
+        EndpointMetrics::record_result::<()>(&Err(TxError::InsufficientFunds {
+            balance: 10.into(),
+        }));
+
+        let metrics = EndpointMetrics::get();
+        assert_eq!(
+            metrics.calls,
+            vec![("icrc1_transfer".into(), 2), ("mint".into(), 1)]
+        );
+        assert_eq!(metrics.errors, vec![("InsufficientFunds".into(), 1)]);
+    }
+
+    #[test]
+    fn endpoint_stats() {
+        MockContext::new().inject();
+        EndpointMetrics::clear();
+
+        let _ = EndpointMetrics::instrument("mint", || Ok::<_, TxError>(1));
+        let _ = EndpointMetrics::instrument("mint", || Err::<(), _>(TxError::Unauthorized));
+        let _ = EndpointMetrics::instrument("burn", || Ok::<_, TxError>(()));
+
+        let report = EndpointMetrics::endpoint_stats();
+        assert_eq!(
+            report.endpoints,
+            vec![
+                EndpointStats {
+                    method: "burn".into(),
+                    calls: 1,
+                    ..Default::default()
+                },
+                EndpointStats {
+                    method: "mint".into(),
+                    calls: 2,
+                    errors: 1,
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(report.slow_calls.is_empty());
+
+        EndpointMetrics::reset_endpoint_stats();
+        assert!(EndpointMetrics::endpoint_stats().endpoints.is_empty());
+    }
+
+    #[test]
+    fn prometheus_format() {
+        MockContext::new().inject();
+        EndpointMetrics::clear();
+        StableBalances.clear();
+        LedgerData::clear();
+        Allowances::clear();
+        EndpointMetrics::record_call("burn");
+        Allowances::record_sweep(2, 0);
+
+        let text = EndpointMetrics::prometheus();
+        assert!(text.contains("# TYPE is20_calls_total counter\n"));
+        assert!(text.contains("is20_calls_total{method=\"burn\"} 1\n"));
+        assert!(text.contains("is20_history_length 0\n"));
+        assert!(text.contains("is20_allowances 0\n"));
+        assert!(text.contains("is20_expired_allowances_removed_total 2\n"));
+    }
+}
@@ -0,0 +1,240 @@
+//! Migrations of the stable state between the layout versions.
+//!
+//! The version of the stored state is kept in `TokenConfig::state_version`. On upgrade,
+//! `post_upgrade` runs the migrations from `MIGRATIONS` up to the `STATE_VERSION` of the
+//! installed wasm, in order. A migration runs in batches while the instruction budget allows. If
+//! it's not finished, its progress is stored and it's resumed by the next upgrade or by the
+//! `run_state_migrations` calls. Transfers, mints and burns are rejected until all the
+//! migrations are done.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::error::TxError;
+use crate::state::balances::StableBalances;
+use crate::state::config::{Timestamp, TokenConfig};
+use crate::state::metrics::instruction_counter;
+use crate::state::{StateVersion, STATE_VERSION};
+
+/// Instructions the migrations can use in `post_upgrade`, leaving room for the rest of the
+/// upgrade.
+pub const UPGRADE_INSTRUCTION_LIMIT: u64 = 100_000_000_000;
+/// Instructions the migrations can use in one `run_state_migrations` call.
+pub const CALL_INSTRUCTION_LIMIT: u64 = 10_000_000_000;
+/// Number of the items processed by one batch of a migration.
+const MIGRATION_BATCH_SIZE: usize = 1000;
+const MIGRATION_PROGRESS_MEMORY_ID: MemoryId = MemoryId::new(27);
+
+struct Migration {
+    /// Version of the state after the migration.
+    to_version: StateVersion,
+    name: &'static str,
+    /// Processes the next batch of at most `batch_size` items.
+    run_batch: fn(batch_size: usize) -> Batch,
+}
+
+struct Batch {
+    processed: u64,
+    done: bool,
+}
+
+/// Migrations ordered by the version. The last one must migrate to `STATE_VERSION`.
+const MIGRATIONS: &[Migration] = &[Migration {
+    to_version: 1,
+    name: "fixed_width_balance_keys",
+    run_batch: fixed_width_balance_keys,
+}];
+
+/// Moves the balances to the fixed-width key map and counts the holders, which the versions
+/// before didn't do.
+fn fixed_width_balance_keys(batch_size: usize) -> Batch {
+    let migrated = StableBalances::migrate_legacy_keys(batch_size);
+    let done = migrated < batch_size;
+    if done {
+        StableBalances::recount_holders();
+    }
+
+    Batch {
+        processed: migrated as u64,
+        done,
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct MigrationProgress {
+    pub to_version: StateVersion,
+    pub name: String,
+    /// Number of the items processed so far.
+    pub processed: u64,
+    pub started_at: Timestamp,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct StateMigrationStatus {
+    /// Version of the stored state.
+    pub version: StateVersion,
+    /// Version of the state layout of the installed wasm.
+    pub target_version: StateVersion,
+    /// The unfinished migration, if it was interrupted.
+    pub running: Option<MigrationProgress>,
+}
+
+pub struct StateMigrations;
+
+impl StateMigrations {
+    /// Runs the pending migrations until they are done or `instruction_limit` is used, and returns
+    /// the status after the run.
+    ///
+    /// Panics if the stored state is newer than the installed wasm supports, which makes
+    /// `post_upgrade` fail and the replica revert the upgrade.
+    pub fn run(instruction_limit: u64) -> StateMigrationStatus {
+        Self::run_batches(instruction_limit, MIGRATION_BATCH_SIZE)
+    }
+
+    fn run_batches(instruction_limit: u64, batch_size: usize) -> StateMigrationStatus {
+        let start = instruction_counter();
+        loop {
+            let mut config = TokenConfig::get_stable();
+            let version = config.state_version();
+            assert!(
+                version <= STATE_VERSION,
+                "stored state version {version} is newer than the supported {STATE_VERSION}"
+            );
+
+            let Some(migration) = MIGRATIONS.iter().find(|m| m.to_version > version) else {
+                break;
+            };
+
+            let mut progress = match Self::progress() {
+                Some(progress) if progress.to_version == migration.to_version => progress,
+                _ => MigrationProgress {
+                    to_version: migration.to_version,
+                    name: migration.name.to_string(),
+                    processed: 0,
+                    started_at: ic::time(),
+                },
+            };
+
+            loop {
+                let batch = (migration.run_batch)(batch_size);
+                progress.processed += batch.processed;
+                if batch.done {
+                    break;
+                }
+
+                if instruction_counter().saturating_sub(start) >= instruction_limit {
+                    Self::set_progress(Some(progress));
+                    return Self::status();
+                }
+            }
+
+            Self::set_progress(None);
+            config.state_version = Some(migration.to_version);
+            TokenConfig::set_stable(config);
+        }
+
+        Self::status()
+    }
+
+    pub fn status() -> StateMigrationStatus {
+        StateMigrationStatus {
+            version: TokenConfig::get_stable().state_version(),
+            target_version: STATE_VERSION,
+            running: Self::progress(),
+        }
+    }
+
+    /// Transfers, mints and burns are not allowed until the state is migrated.
+    pub fn check_completed() -> Result<(), TxError> {
+        let version = TokenConfig::get_stable().state_version();
+        match MIGRATIONS.iter().find(|m| m.to_version > version) {
+            Some(migration) => Err(TxError::StateMigrationInProgress {
+                to_version: migration.to_version,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn progress() -> Option<MigrationProgress> {
+        PROGRESS.with(|cell| cell.borrow().get().0.clone())
+    }
+
+    fn set_progress(progress: Option<MigrationProgress>) {
+        PROGRESS
+            .with(|cell| cell.borrow_mut().set(StorableProgress(progress)))
+            .expect("unable to set migration progress to stable memory");
+    }
+}
+
+#[derive(Debug, Default, CandidType, Deserialize)]
+struct StorableProgress(Option<MigrationProgress>);
+
+impl Storable for StorableProgress {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode migration progress")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode migration progress")
+    }
+}
+
+thread_local! {
+    static PROGRESS: RefCell<StableCell<StorableProgress>> =
+        RefCell::new(StableCell::new(MIGRATION_PROGRESS_MEMORY_ID, StorableProgress::default())
+            .expect("unable to initialize migration progress"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::balances::Balances;
+
+    #[test]
+    fn registry_ends_at_current_version() {
+        assert!(MIGRATIONS
+            .windows(2)
+            .all(|pair| pair[0].to_version < pair[1].to_version));
+        assert_eq!(MIGRATIONS.last().map(|m| m.to_version), Some(STATE_VERSION));
+    }
+
+    #[test]
+    fn migrations_resume() {
+        MockContext::new().inject();
+        StableBalances.clear();
+        StateMigrations::set_progress(None);
+        TokenConfig::set_stable(TokenConfig {
+            state_version: None,
+            ..TokenConfig::default()
+        });
+        StableBalances::insert_legacy(alice().into(), 100);
+        StableBalances::insert_legacy(bob().into(), 50);
+        assert_eq!(
+            StateMigrations::check_completed(),
+            Err(TxError::StateMigrationInProgress { to_version: 1 })
+        );
+
+        // The instruction counter is always zero outside of the IC, so a zero limit stops the
+        // migration after every batch.
+        let status = StateMigrations::run_batches(0, 1);
+        assert_eq!(status.version, 0);
+        assert_eq!(status.running.map(|progress| progress.processed), Some(1));
+
+        let status = StateMigrations::run_batches(u64::MAX, 1);
+        assert_eq!(status.version, STATE_VERSION);
+        assert_eq!(status.running, None);
+        assert_eq!(StateMigrations::check_completed(), Ok(()));
+        assert_eq!(StableBalances.holders_count(), 2);
+        assert_eq!(StableBalances.balance_of(&bob().into()), 50.into());
+    }
+}
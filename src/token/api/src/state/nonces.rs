@@ -0,0 +1,79 @@
+//! Nonces of the signed transfers and of the permits.
+//!
+//! Every signer has a counter of the signed transfers executed on their behalf. A signed transfer
+//! is accepted only if its nonce equals the current value of the counter, so every signed payload
+//! can be executed at most once and in the order the signer created them. The permits, see
+//! `canister::permit`, have a separate counter, so a permit doesn't invalidate the signed
+//! transfers of the same owner waiting to be relayed.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::Principal;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct NonceKey(Principal);
+
+impl Storable for NonceKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_slice().into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Principal::from_slice(&bytes))
+    }
+}
+
+impl BoundedStorable for NonceKey {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct TransferNonces;
+
+impl TransferNonces {
+    /// Returns the nonce the next signed transfer of the `signer` must have.
+    pub fn get(signer: Principal) -> u64 {
+        NONCES.with(|map| map.borrow().get(&NonceKey(signer)).unwrap_or_default())
+    }
+
+    /// Marks the current nonce of the `signer` as used.
+    pub fn increment(signer: Principal) {
+        let next = Self::get(signer) + 1;
+        NONCES.with(|map| map.borrow_mut().insert(NonceKey(signer), next));
+    }
+
+    pub fn clear() {
+        NONCES.with(|map| map.borrow_mut().clear());
+    }
+}
+
+pub struct PermitNonces;
+
+impl PermitNonces {
+    /// Returns the nonce the next permit of the `owner` must have.
+    pub fn get(owner: Principal) -> u64 {
+        PERMIT_NONCES.with(|map| map.borrow().get(&NonceKey(owner)).unwrap_or_default())
+    }
+
+    /// Marks the current nonce of the `owner` as used.
+    pub fn increment(owner: Principal) {
+        let next = Self::get(owner) + 1;
+        PERMIT_NONCES.with(|map| map.borrow_mut().insert(NonceKey(owner), next));
+    }
+
+    pub fn clear() {
+        PERMIT_NONCES.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const NONCES_MEMORY_ID: MemoryId = MemoryId::new(5);
+const PERMIT_NONCES_MEMORY_ID: MemoryId = MemoryId::new(60);
+
+thread_local! {
+    static NONCES: RefCell<StableBTreeMap<NonceKey, u64>> =
+        RefCell::new(StableBTreeMap::new(NONCES_MEMORY_ID));
+    static PERMIT_NONCES: RefCell<StableBTreeMap<NonceKey, u64>> =
+        RefCell::new(StableBTreeMap::new(PERMIT_NONCES_MEMORY_ID));
+}
@@ -0,0 +1,344 @@
+//! Queue of the outbound notifications of the token.
+//!
+//! The features notifying other canisters (the event subscriptions and the payment requests) put
+//! their one-way calls in this queue instead of calling the target directly. The queue is kept in
+//! stable memory and delivered right after the message that added the notifications, and by the
+//! timer task of `canister::outbound`.
+//!
+//! If a call cannot be sent, it's retried with an exponential backoff starting from
+//! `BASE_RETRY_DELAY_NANOS`. After `MAX_DELIVERY_ATTEMPTS` failures the message becomes a dead
+//! letter, which stays in the queue until the owner retries or drops it.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+pub type MessageId = u64;
+
+/// Maximum number of the messages in the queue, including the dead letters.
+pub const MAX_OUTBOUND_MESSAGES: u64 = 1_000;
+
+/// Maximum size of the encoded arguments of a message.
+pub const MAX_MESSAGE_ARGS_SIZE: usize = 64 * 1024;
+
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 10;
+
+pub const BASE_RETRY_DELAY_NANOS: u64 = 10 * 1_000_000_000;
+
+pub const MAX_RETRY_DELAY_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+/// Maximum number of the messages sent in one delivery run.
+const MAX_DELIVERIES_PER_RUN: usize = 100;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum NotificationKind {
+    TokenEvents,
+    PaymentRequest,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct OutboundMessage {
+    pub id: MessageId,
+    pub kind: NotificationKind,
+    pub target: Principal,
+    pub method: String,
+    /// Candid encoded arguments of the call.
+    pub args: Vec<u8>,
+    pub created_at: Timestamp,
+    /// Number of the failed delivery attempts.
+    pub attempts: u32,
+    pub next_attempt_at: Timestamp,
+    pub last_error: Option<String>,
+    /// Set after `MAX_DELIVERY_ATTEMPTS` failures. Dead letters are not retried automatically.
+    pub dead: bool,
+}
+
+impl Storable for OutboundMessage {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode outbound message")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode outbound message")
+    }
+}
+
+impl BoundedStorable for OutboundMessage {
+    // The arguments, the method name and the last error, a principal and the counters with the
+    // type table.
+    const MAX_SIZE: u32 = (MAX_MESSAGE_ARGS_SIZE + 2048) as _;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Default, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct OutboundStatus {
+    /// Messages waiting for the delivery or a retry.
+    pub pending: u64,
+    pub dead: u64,
+}
+
+/// Delay before the next attempt after `attempts` failures.
+pub fn retry_delay(attempts: u32) -> u64 {
+    let factor = 1u64
+        .checked_shl(attempts.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    BASE_RETRY_DELAY_NANOS
+        .saturating_mul(factor)
+        .min(MAX_RETRY_DELAY_NANOS)
+}
+
+pub struct Outbound;
+
+impl Outbound {
+    /// Adds the call of `method` of the `target` canister with the encoded `args` to the queue.
+    /// Returns `None` if the queue is full or the arguments are too large.
+    pub fn enqueue(
+        kind: NotificationKind,
+        target: Principal,
+        method: &str,
+        args: Vec<u8>,
+        now: Timestamp,
+    ) -> Option<MessageId> {
+        if args.len() > MAX_MESSAGE_ARGS_SIZE
+            || MESSAGES.with(|map| map.borrow().len()) >= MAX_OUTBOUND_MESSAGES
+        {
+            return None;
+        }
+
+        let id = NEXT_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1)
+                .expect("failed to write next outbound message id");
+            id
+        });
+        let message = OutboundMessage {
+            id,
+            kind,
+            target,
+            method: method.to_string(),
+            args,
+            created_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            dead: false,
+        };
+        MESSAGES.with(|map| map.borrow_mut().insert(id, message));
+        schedule_delivery();
+
+        Some(id)
+    }
+
+    pub fn get(id: MessageId) -> Option<OutboundMessage> {
+        MESSAGES.with(|map| map.borrow().get(&id))
+    }
+
+    pub fn status() -> OutboundStatus {
+        MESSAGES.with(|map| {
+            map.borrow()
+                .iter()
+                .fold(OutboundStatus::default(), |mut status, (_, message)| {
+                    if message.dead {
+                        status.dead += 1;
+                    } else {
+                        status.pending += 1;
+                    }
+                    status
+                })
+        })
+    }
+
+    pub fn dead_letters(offset: usize, count: usize) -> Vec<OutboundMessage> {
+        MESSAGES.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, message)| message)
+                .filter(|message| message.dead)
+                .skip(offset)
+                .take(count)
+                .collect()
+        })
+    }
+
+    /// Sends the messages due at the time `now`. Returns the number of the sent ones.
+    pub fn deliver_due(now: Timestamp) -> usize {
+        let due = MESSAGES.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, message)| message)
+                .filter(|message| !message.dead && message.next_attempt_at <= now)
+                .take(MAX_DELIVERIES_PER_RUN)
+                .collect::<Vec<_>>()
+        });
+
+        let mut sent = 0;
+        for message in due {
+            match send(&message) {
+                Ok(()) => {
+                    MESSAGES.with(|map| map.borrow_mut().remove(&message.id));
+                    sent += 1;
+                }
+                Err(error) => Self::record_failure(message, error, now),
+            }
+        }
+
+        sent
+    }
+
+    /// Makes the message due at the time `now` with a new series of attempts.
+    pub fn retry(id: MessageId, now: Timestamp) -> Result<(), TxError> {
+        let mut message = Self::get(id).ok_or(TxError::OutboundMessageNotFound)?;
+        message.attempts = 0;
+        message.next_attempt_at = now;
+        message.dead = false;
+        MESSAGES.with(|map| map.borrow_mut().insert(id, message));
+        schedule_delivery();
+
+        Ok(())
+    }
+
+    pub fn drop(id: MessageId) -> Result<OutboundMessage, TxError> {
+        MESSAGES
+            .with(|map| map.borrow_mut().remove(&id))
+            .ok_or(TxError::OutboundMessageNotFound)
+    }
+
+    pub fn clear() {
+        MESSAGES.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next outbound message id")
+        });
+    }
+
+    fn record_failure(mut message: OutboundMessage, error: String, now: Timestamp) {
+        message.attempts = message.attempts.saturating_add(1);
+        message.last_error = Some(error);
+        if message.attempts >= MAX_DELIVERY_ATTEMPTS {
+            message.dead = true;
+        } else {
+            message.next_attempt_at = now.saturating_add(retry_delay(message.attempts));
+        }
+
+        MESSAGES.with(|map| map.borrow_mut().insert(message.id, message));
+    }
+}
+
+#[cfg(target_family = "wasm")]
+fn send(message: &OutboundMessage) -> Result<(), String> {
+    canister_sdk::ic_cdk::api::call::notify_raw(message.target, &message.method, &message.args, 0)
+        .map_err(|code| format!("{code:?}"))
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn send(_message: &OutboundMessage) -> Result<(), String> {
+    // There are no inter-canister calls outside of the IC, so the messages are considered sent.
+    Ok(())
+}
+
+#[cfg(target_family = "wasm")]
+fn schedule_delivery() {
+    DELIVERY_SCHEDULED.with(|scheduled| {
+        if !scheduled.replace(true) {
+            ic_exports::ic_cdk_timers::set_timer(std::time::Duration::ZERO, || {
+                DELIVERY_SCHEDULED.with(|scheduled| scheduled.set(false));
+                Outbound::deliver_due(canister_sdk::ic_kit::ic::time());
+            });
+        }
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn schedule_delivery() {}
+
+const OUTBOUND_MESSAGES_MEMORY_ID: MemoryId = MemoryId::new(48);
+const NEXT_OUTBOUND_MESSAGE_ID_MEMORY_ID: MemoryId = MemoryId::new(49);
+
+thread_local! {
+    static MESSAGES: RefCell<StableBTreeMap<MessageId, OutboundMessage>> =
+        RefCell::new(StableBTreeMap::new(OUTBOUND_MESSAGES_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<MessageId>> =
+        RefCell::new(StableCell::new(NEXT_OUTBOUND_MESSAGE_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next outbound message id"));
+    #[cfg(target_family = "wasm")]
+    static DELIVERY_SCHEDULED: std::cell::Cell<bool> = std::cell::Cell::new(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::alice;
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn retry_delay_grows_exponentially() {
+        assert_eq!(retry_delay(1), BASE_RETRY_DELAY_NANOS);
+        assert_eq!(retry_delay(3), 4 * BASE_RETRY_DELAY_NANOS);
+        assert_eq!(retry_delay(20), MAX_RETRY_DELAY_NANOS);
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY_NANOS);
+    }
+
+    #[test]
+    fn failed_messages_become_dead_letters() {
+        MockContext::new().inject();
+        Outbound::clear();
+
+        let id =
+            Outbound::enqueue(NotificationKind::TokenEvents, alice(), "m", vec![1], 0).unwrap();
+        let mut now = 0;
+        for _ in 0..MAX_DELIVERY_ATTEMPTS {
+            let message = Outbound::get(id).unwrap();
+            assert!(!message.dead);
+            now = message.next_attempt_at;
+            Outbound::record_failure(message, "rejected".into(), now);
+        }
+
+        let message = Outbound::get(id).unwrap();
+        assert!(message.dead);
+        assert_eq!(message.last_error, Some("rejected".into()));
+        assert_eq!(
+            Outbound::status(),
+            OutboundStatus {
+                pending: 0,
+                dead: 1
+            }
+        );
+        assert_eq!(Outbound::dead_letters(0, 10), vec![message]);
+        assert_eq!(Outbound::deliver_due(u64::MAX), 0);
+
+        Outbound::retry(id, now).unwrap();
+        assert_eq!(
+            Outbound::status(),
+            OutboundStatus {
+                pending: 1,
+                dead: 0
+            }
+        );
+        assert_eq!(Outbound::deliver_due(now), 1);
+        assert_eq!(Outbound::status(), OutboundStatus::default());
+        assert_eq!(Outbound::drop(id), Err(TxError::OutboundMessageNotFound));
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        MockContext::new().inject();
+        Outbound::clear();
+
+        let args = vec![0; MAX_MESSAGE_ARGS_SIZE + 1];
+        assert_eq!(
+            Outbound::enqueue(NotificationKind::PaymentRequest, alice(), "m", args, 0),
+            None
+        );
+    }
+}
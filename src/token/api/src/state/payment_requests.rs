@@ -0,0 +1,297 @@
+//! Payment requests created by merchants and paid by the payers.
+//!
+//! A merchant (usually a canister) creates a request for `amount` tokens with
+//! `create_payment_request`, and passes its id to the payer. The payer pays it with
+//! `pay_request` before the expiry, which transfers the amount to the merchant account and
+//! notifies the merchant canister with the paid request. The merchant can also check the status of
+//! the request with `get_payment_request` instead of relying on the notification.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::config::{Timestamp, TokenConfig};
+use crate::state::ledger::Memo;
+use crate::state::outbound::{NotificationKind, Outbound};
+use crate::tx_record::TxId;
+
+pub type PaymentRequestId = u64;
+
+/// Name of the method called on the merchant canister with the paid request.
+pub const PAYMENT_CALLBACK_METHOD: &str = "on_payment_request_paid";
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum PaymentRequestStatus {
+    Pending,
+    Paid {
+        payer: Account,
+        tx_id: TxId,
+        paid_at: Timestamp,
+    },
+    Cancelled,
+    Expired,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct PaymentRequest {
+    pub id: PaymentRequestId,
+    /// Account receiving the payment. Its owner can cancel the request and is notified when it's
+    /// paid.
+    pub merchant: Account,
+    /// Amount received by the merchant, the transfer fee is paid by the payer on top of it.
+    pub amount: Tokens128,
+    pub memo: Option<Memo>,
+    pub created_at: Timestamp,
+    pub expires_at: Timestamp,
+    /// Status at the time the request was returned. `Expired` is never stored, a pending request
+    /// is returned as expired once the expiry passes.
+    pub status: PaymentRequestStatus,
+}
+
+impl PaymentRequest {
+    fn with_status_at(mut self, now: Timestamp) -> Self {
+        if self.status == PaymentRequestStatus::Pending && now > self.expires_at {
+            self.status = PaymentRequestStatus::Expired;
+        }
+
+        self
+    }
+}
+
+impl Storable for PaymentRequest {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode payment request")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode payment request")
+    }
+}
+
+impl BoundedStorable for PaymentRequest {
+    // Two principals, two subaccounts, an amount, three timestamps, a transaction id and a memo of
+    // at most `MAX_MEMO_LENGTH` bytes with the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct PaymentRequests;
+
+impl PaymentRequests {
+    pub fn create(
+        merchant: AccountInternal,
+        amount: Tokens128,
+        memo: Option<Memo>,
+        expires_at: Timestamp,
+        now: Timestamp,
+    ) -> Result<PaymentRequestId, TxError> {
+        if amount.is_zero() {
+            return Err(TxError::AmountTooSmall);
+        }
+
+        TokenConfig::get_stable()
+            .input_limits()
+            .check_memo(memo.as_ref())?;
+
+        if expires_at <= now {
+            return Err(TxError::PaymentRequestExpired {
+                expired_at: expires_at,
+            });
+        }
+
+        let id = NEXT_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1)
+                .expect("failed to write next payment request id");
+            id
+        });
+
+        let request = PaymentRequest {
+            id,
+            merchant: merchant.into(),
+            amount,
+            memo,
+            created_at: now,
+            expires_at,
+            status: PaymentRequestStatus::Pending,
+        };
+        REQUESTS.with(|map| map.borrow_mut().insert(id, request));
+
+        Ok(id)
+    }
+
+    /// Returns the request with its status at the time `now`.
+    pub fn get(id: PaymentRequestId, now: Timestamp) -> Option<PaymentRequest> {
+        REQUESTS.with(|map| {
+            map.borrow()
+                .get(&id)
+                .map(|request| request.with_status_at(now))
+        })
+    }
+
+    /// Returns the requests created by the `merchant`, with their status at the time `now`.
+    pub fn list(merchant: Principal, now: Timestamp) -> Vec<PaymentRequest> {
+        REQUESTS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, request)| request)
+                .filter(|request| request.merchant.owner == merchant)
+                .map(|request| request.with_status_at(now))
+                .collect()
+        })
+    }
+
+    /// Checks that the request can be paid at the time `now`, and returns it.
+    pub fn check_payment(id: PaymentRequestId, now: Timestamp) -> Result<PaymentRequest, TxError> {
+        let request = Self::get(id, now).ok_or(TxError::PaymentRequestNotFound)?;
+        match request.status {
+            PaymentRequestStatus::Pending => Ok(request),
+            PaymentRequestStatus::Expired => Err(TxError::PaymentRequestExpired {
+                expired_at: request.expires_at,
+            }),
+            _ => Err(TxError::PaymentRequestNotPending),
+        }
+    }
+
+    /// Stores the payment of the request and returns the paid request.
+    pub fn record_payment(
+        id: PaymentRequestId,
+        payer: AccountInternal,
+        tx_id: TxId,
+        now: Timestamp,
+    ) -> Option<PaymentRequest> {
+        Self::set_status(
+            id,
+            PaymentRequestStatus::Paid {
+                payer: payer.into(),
+                tx_id,
+                paid_at: now,
+            },
+        )
+    }
+
+    /// Cancels the pending request. Only the merchant can cancel it.
+    pub fn cancel(caller: Principal, id: PaymentRequestId, now: Timestamp) -> Result<(), TxError> {
+        let request = Self::check_payment(id, now)?;
+        if request.merchant.owner != caller {
+            return Err(TxError::Unauthorized);
+        }
+
+        Self::set_status(id, PaymentRequestStatus::Cancelled);
+        Ok(())
+    }
+
+    fn set_status(id: PaymentRequestId, status: PaymentRequestStatus) -> Option<PaymentRequest> {
+        REQUESTS.with(|map| {
+            let mut map = map.borrow_mut();
+            let mut request = map.get(&id)?;
+            request.status = status;
+            map.insert(id, request.clone());
+            Some(request)
+        })
+    }
+
+    /// Sends the paid request to the merchant canister through the outbound queue. The
+    /// notification is retried if it can't be sent, but the merchant must check the status of the
+    /// request if it's not received.
+    pub fn notify_merchant(request: &PaymentRequest, now: Timestamp) {
+        let args = Encode!(request).expect("failed to encode payment request");
+        Outbound::enqueue(
+            NotificationKind::PaymentRequest,
+            request.merchant.owner,
+            PAYMENT_CALLBACK_METHOD,
+            args,
+            now,
+        );
+    }
+
+    pub fn clear() {
+        REQUESTS.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next payment request id")
+        });
+    }
+}
+
+const PAYMENT_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(25);
+const NEXT_PAYMENT_REQUEST_ID_MEMORY_ID: MemoryId = MemoryId::new(26);
+
+thread_local! {
+    static REQUESTS: RefCell<StableBTreeMap<PaymentRequestId, PaymentRequest>> =
+        RefCell::new(StableBTreeMap::new(PAYMENT_REQUESTS_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<PaymentRequestId>> =
+        RefCell::new(StableCell::new(NEXT_PAYMENT_REQUEST_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next payment request id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn request_status() {
+        MockContext::new().inject();
+        PaymentRequests::clear();
+
+        assert_eq!(
+            PaymentRequests::create(alice().into(), 100.into(), None, 1000, 1000),
+            Err(TxError::PaymentRequestExpired { expired_at: 1000 })
+        );
+        let id = PaymentRequests::create(alice().into(), 100.into(), None, 2000, 1000).unwrap();
+        assert!(PaymentRequests::check_payment(id, 2000).is_ok());
+        assert_eq!(
+            PaymentRequests::check_payment(id, 2001),
+            Err(TxError::PaymentRequestExpired { expired_at: 2000 })
+        );
+        assert_eq!(
+            PaymentRequests::get(id, 2001).unwrap().status,
+            PaymentRequestStatus::Expired
+        );
+
+        PaymentRequests::record_payment(id, bob().into(), 7, 1500);
+        assert_eq!(
+            PaymentRequests::check_payment(id, 1600),
+            Err(TxError::PaymentRequestNotPending)
+        );
+        assert_eq!(
+            PaymentRequests::get(id, 3000).unwrap().status,
+            PaymentRequestStatus::Paid {
+                payer: bob().into(),
+                tx_id: 7,
+                paid_at: 1500
+            }
+        );
+    }
+
+    #[test]
+    fn only_merchant_cancels() {
+        MockContext::new().inject();
+        PaymentRequests::clear();
+
+        let id = PaymentRequests::create(alice().into(), 100.into(), None, 2000, 1000).unwrap();
+        assert_eq!(
+            PaymentRequests::cancel(bob(), id, 1000),
+            Err(TxError::Unauthorized)
+        );
+        PaymentRequests::cancel(alice(), id, 1000).unwrap();
+        assert_eq!(PaymentRequests::list(alice(), 1000).len(), 1);
+        assert_eq!(
+            PaymentRequests::check_payment(id, 1000),
+            Err(TxError::PaymentRequestNotPending)
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Recurring payments authorized by the token holders.
+//!
+//! A subscriber allows a spender (e.g. a service) to pull up to `amount` tokens from the
+//! subscriber's account once per `interval_nanos`. The spender collects the payments with the
+//! `collect_subscription` method, and the token enforces both the interval and the maximum amount.
+//! Every collection is recorded in the ledger as a `TransferFrom` transaction made by the spender.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+pub type SubscriptionId = u64;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct PaymentSubscription {
+    pub id: SubscriptionId,
+    pub subscriber: Account,
+    pub spender: Principal,
+    /// Maximum amount the spender can collect per interval. The transfer fee is paid by the
+    /// subscriber on top of it.
+    pub amount: Tokens128,
+    pub interval_nanos: u64,
+    pub created_at: Timestamp,
+    pub last_collected_at: Option<Timestamp>,
+    pub collected_total: Tokens128,
+}
+
+impl PaymentSubscription {
+    /// Time from which the next payment can be collected. The first payment can be collected
+    /// right after the subscription is created.
+    pub fn next_payment_at(&self) -> Timestamp {
+        match self.last_collected_at {
+            Some(last) => last.saturating_add(self.interval_nanos),
+            None => self.created_at,
+        }
+    }
+}
+
+impl Storable for PaymentSubscription {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode payment subscription")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode payment subscription")
+    }
+}
+
+impl BoundedStorable for PaymentSubscription {
+    // Two principals, a subaccount, two amounts and four timestamps with the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct PaymentSubscriptions;
+
+impl PaymentSubscriptions {
+    pub fn create(
+        subscriber: AccountInternal,
+        spender: Principal,
+        amount: Tokens128,
+        interval_nanos: u64,
+        now: Timestamp,
+    ) -> Result<SubscriptionId, TxError> {
+        if amount.is_zero() {
+            return Err(TxError::AmountTooSmall);
+        }
+
+        if interval_nanos == 0 {
+            return Err(TxError::InvalidSubscriptionInterval);
+        }
+
+        let id = NEXT_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1)
+                .expect("failed to write next subscription id");
+            id
+        });
+
+        let subscription = PaymentSubscription {
+            id,
+            subscriber: subscriber.into(),
+            spender,
+            amount,
+            interval_nanos,
+            created_at: now,
+            last_collected_at: None,
+            collected_total: Tokens128::ZERO,
+        };
+        SUBSCRIPTIONS.with(|map| map.borrow_mut().insert(id, subscription));
+
+        Ok(id)
+    }
+
+    pub fn get(id: SubscriptionId) -> Option<PaymentSubscription> {
+        SUBSCRIPTIONS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns the subscriptions in which `who` is the subscriber or the spender.
+    pub fn list(who: Principal) -> Vec<PaymentSubscription> {
+        SUBSCRIPTIONS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, subscription)| subscription)
+                .filter(|subscription| {
+                    subscription.subscriber.owner == who || subscription.spender == who
+                })
+                .collect()
+        })
+    }
+
+    /// Removes the subscription. Both the subscriber and the spender can cancel it.
+    pub fn cancel(caller: Principal, id: SubscriptionId) -> Result<(), TxError> {
+        let subscription = Self::get(id).ok_or(TxError::SubscriptionNotFound)?;
+        if subscription.subscriber.owner != caller && subscription.spender != caller {
+            return Err(TxError::Unauthorized);
+        }
+
+        SUBSCRIPTIONS.with(|map| map.borrow_mut().remove(&id));
+        Ok(())
+    }
+
+    /// Removes the subscriptions in which `who` is the subscriber or the spender. Returns the
+    /// number of the removed subscriptions.
+    pub fn remove_involving(who: Principal) -> usize {
+        let subscriptions = Self::list(who);
+        SUBSCRIPTIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            for subscription in &subscriptions {
+                map.remove(&subscription.id);
+            }
+        });
+
+        subscriptions.len()
+    }
+
+    /// Checks that the `spender` can collect `amount` from the subscription at the time `now`, and
+    /// returns the subscription.
+    pub fn check_collection(
+        spender: Principal,
+        id: SubscriptionId,
+        amount: Tokens128,
+        now: Timestamp,
+    ) -> Result<PaymentSubscription, TxError> {
+        let subscription = Self::get(id).ok_or(TxError::SubscriptionNotFound)?;
+        if subscription.spender != spender {
+            return Err(TxError::Unauthorized);
+        }
+
+        if amount > subscription.amount {
+            return Err(TxError::AmountExceedsSubscription {
+                max_amount: subscription.amount,
+            });
+        }
+
+        let next_payment_at = subscription.next_payment_at();
+        if now < next_payment_at {
+            return Err(TxError::PaymentNotDue { next_payment_at });
+        }
+
+        Ok(subscription)
+    }
+
+    pub fn record_collection(id: SubscriptionId, amount: Tokens128, now: Timestamp) {
+        SUBSCRIPTIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            if let Some(mut subscription) = map.get(&id) {
+                subscription.last_collected_at = Some(now);
+                subscription.collected_total = (subscription.collected_total + amount)
+                    .unwrap_or_else(|| Tokens128::from(u128::MAX));
+                map.insert(id, subscription);
+            }
+        });
+    }
+
+    pub fn clear() {
+        SUBSCRIPTIONS.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next subscription id")
+        });
+    }
+}
+
+const SUBSCRIPTIONS_MEMORY_ID: MemoryId = MemoryId::new(8);
+const NEXT_SUBSCRIPTION_ID_MEMORY_ID: MemoryId = MemoryId::new(9);
+
+thread_local! {
+    static SUBSCRIPTIONS: RefCell<StableBTreeMap<SubscriptionId, PaymentSubscription>> =
+        RefCell::new(StableBTreeMap::new(SUBSCRIPTIONS_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<SubscriptionId>> =
+        RefCell::new(StableCell::new(NEXT_SUBSCRIPTION_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next subscription id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn collection_interval_and_amount() {
+        MockContext::new().inject();
+        PaymentSubscriptions::clear();
+
+        let id =
+            PaymentSubscriptions::create(alice().into(), bob(), 100.into(), 1000, 5000).unwrap();
+
+        assert_eq!(
+            PaymentSubscriptions::check_collection(john(), id, 100.into(), 5000),
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(
+            PaymentSubscriptions::check_collection(bob(), id, 101.into(), 5000),
+            Err(TxError::AmountExceedsSubscription {
+                max_amount: 100.into()
+            })
+        );
+        assert!(PaymentSubscriptions::check_collection(bob(), id, 100.into(), 5000).is_ok());
+
+        PaymentSubscriptions::record_collection(id, 100.into(), 5500);
+        assert_eq!(
+            PaymentSubscriptions::check_collection(bob(), id, 10.into(), 6000),
+            Err(TxError::PaymentNotDue {
+                next_payment_at: 6500
+            })
+        );
+        assert!(PaymentSubscriptions::check_collection(bob(), id, 10.into(), 6500).is_ok());
+        assert_eq!(
+            PaymentSubscriptions::get(id).unwrap().collected_total,
+            Tokens128::from(100)
+        );
+    }
+
+    #[test]
+    fn cancel_subscription() {
+        MockContext::new().inject();
+        PaymentSubscriptions::clear();
+
+        let id = PaymentSubscriptions::create(alice().into(), bob(), 100.into(), 1000, 0).unwrap();
+        assert_eq!(PaymentSubscriptions::list(bob()).len(), 1);
+        assert_eq!(
+            PaymentSubscriptions::cancel(john(), id),
+            Err(TxError::Unauthorized)
+        );
+        PaymentSubscriptions::cancel(alice(), id).unwrap();
+        assert_eq!(
+            PaymentSubscriptions::cancel(bob(), id),
+            Err(TxError::SubscriptionNotFound)
+        );
+        assert!(PaymentSubscriptions::list(alice()).is_empty());
+    }
+}
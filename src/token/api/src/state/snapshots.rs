@@ -0,0 +1,302 @@
+//! Snapshots of the balances for the governance voting.
+//!
+//! The owner takes a snapshot with `take_snapshot`, and the DAOs read the voting power of the
+//! accounts at that snapshot with `get_voting_power` and `total_voting_power`, so the tokens moved
+//! after a proposal was created can't be counted twice. The balances are not copied on the
+//! snapshot. Instead, the first change of a balance after the latest snapshot stores its previous
+//! value under the id of that snapshot, which is then the balance at every earlier snapshot not
+//! followed by another stored change.
+//!
+//! The accounts of the principals in `TokenConfig::voting_exclusions` at the time of the snapshot,
+//! e.g. the treasury, have no voting power at that snapshot.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::Bound;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableMultimap, Storable};
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::balances::{AccountKey, Balances, StableBalances};
+use crate::state::config::Timestamp;
+
+pub type SnapshotId = u64;
+
+/// Maximum number of the principals excluded from the voting.
+pub const MAX_VOTING_EXCLUSIONS: usize = 50;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Snapshot {
+    pub id: SnapshotId,
+    pub taken_at: Timestamp,
+    pub total_supply: Tokens128,
+    /// Principals excluded from the voting at this snapshot.
+    pub excluded: Vec<Principal>,
+    /// Total balance of the accounts of the `excluded` principals at this snapshot.
+    pub excluded_supply: Tokens128,
+}
+
+impl Snapshot {
+    pub fn total_voting_power(&self) -> Tokens128 {
+        self.total_supply.saturating_sub(self.excluded_supply)
+    }
+}
+
+impl Storable for Snapshot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode snapshot").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode snapshot")
+    }
+}
+
+impl BoundedStorable for Snapshot {
+    // `MAX_VOTING_EXCLUSIONS` principals, two amounts and two integers with the type table.
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Big-endian snapshot id, so the balances of an account are ordered by the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct SnapshotKey(SnapshotId);
+
+impl Storable for SnapshotKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.to_be_bytes().to_vec().into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(SnapshotId::from_be_bytes(
+            bytes
+                .as_ref()
+                .try_into()
+                .expect("invalid snapshot key length"),
+        ))
+    }
+}
+
+impl BoundedStorable for SnapshotKey {
+    const MAX_SIZE: u32 = 8;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+pub struct Snapshots;
+
+impl Snapshots {
+    pub fn validate_exclusions(excluded: &[Principal]) -> Result<(), TxError> {
+        if excluded.len() > MAX_VOTING_EXCLUSIONS {
+            return Err(TxError::TooManyVotingExclusions {
+                max: MAX_VOTING_EXCLUSIONS as _,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Takes a snapshot of the current balances. The balances of the `excluded` principals don't
+    /// count towards the total voting power of the snapshot.
+    pub fn take(now: Timestamp, mut excluded: Vec<Principal>) -> Snapshot {
+        excluded.sort();
+        excluded.dedup();
+
+        let excluded_supply = excluded
+            .iter()
+            .flat_map(|owner| StableBalances.get_subaccounts(*owner).into_values())
+            .fold(Tokens128::ZERO, |total, amount| {
+                (total + amount).expect("total supply integer overflow") // Checked at mint
+            });
+
+        SNAPSHOTS.with(|map| {
+            let mut map = map.borrow_mut();
+            let snapshot = Snapshot {
+                id: map.len(),
+                taken_at: now,
+                total_supply: StableBalances.total_supply(),
+                excluded,
+                excluded_supply,
+            };
+            map.insert(snapshot.id, snapshot.clone());
+            snapshot
+        })
+    }
+
+    pub fn get(id: SnapshotId) -> Result<Snapshot, TxError> {
+        SNAPSHOTS
+            .with(|map| map.borrow().get(&id))
+            .ok_or(TxError::SnapshotNotFound)
+    }
+
+    pub fn latest() -> Option<SnapshotId> {
+        SNAPSHOTS.with(|map| map.borrow().len().checked_sub(1))
+    }
+
+    /// Stores the `current` balance of the `account` before it's changed, if it's the first change
+    /// since the latest snapshot. Called by `StableBalances` on every write.
+    pub(crate) fn record_before_change(account: AccountInternal, current: Tokens128) {
+        let Some(latest) = Self::latest() else {
+            return;
+        };
+
+        let account_key = AccountKey::from(account);
+        let snapshot_key = SnapshotKey(latest);
+        BALANCES.with(|map| {
+            let mut map = map.borrow_mut();
+            if map.get(&account_key, &snapshot_key).is_none() {
+                map.insert(&account_key, &snapshot_key, &current.amount);
+            }
+        });
+    }
+
+    /// Balance of the `account` at the snapshot `id`.
+    pub fn balance_at(account: AccountInternal, id: SnapshotId) -> Result<Tokens128, TxError> {
+        Self::get(id)?;
+
+        let recorded = BALANCES.with(|map| {
+            map.borrow()
+                .range(&AccountKey::from(account))
+                .find(|(snapshot, _)| snapshot.0 >= id)
+                .map(|(_, amount)| Tokens128::from(amount))
+        });
+
+        // Not changed since the snapshot.
+        Ok(recorded.unwrap_or_else(|| StableBalances.balance_of(&account)))
+    }
+
+    /// Up to `limit` accounts changed since the snapshot `id` with the keys greater than `after`,
+    /// in the order of the keys. Together with the current accounts, these are all the accounts
+    /// which could have a balance at the snapshot.
+    pub(crate) fn changed_accounts(
+        id: SnapshotId,
+        after: Option<AccountKey>,
+        limit: usize,
+    ) -> Vec<AccountKey> {
+        // The entries of the `after` account end with the greatest snapshot key.
+        let start = match after {
+            Some(account) => Bound::Excluded((account, SnapshotKey(SnapshotId::MAX))),
+            None => Bound::Unbounded,
+        };
+        BALANCES.with(|map| {
+            let mut accounts = Vec::new();
+            for (account, snapshot, _) in map.borrow().range((start, Bound::Unbounded)) {
+                if accounts.len() == limit {
+                    break;
+                }
+                if snapshot.0 >= id && accounts.last() != Some(&account) {
+                    accounts.push(account);
+                }
+            }
+            accounts
+        })
+    }
+
+    /// Voting power of the `account` at the snapshot `id`: its balance, or zero if its owner was
+    /// excluded from the voting.
+    pub fn voting_power(account: AccountInternal, id: SnapshotId) -> Result<Tokens128, TxError> {
+        let snapshot = Self::get(id)?;
+        if snapshot.excluded.binary_search(&account.owner).is_ok() {
+            return Ok(Tokens128::ZERO);
+        }
+
+        Self::balance_at(account, id)
+    }
+
+    pub fn total_voting_power(id: SnapshotId) -> Result<Tokens128, TxError> {
+        Ok(Self::get(id)?.total_voting_power())
+    }
+
+    pub fn clear() {
+        SNAPSHOTS.with(|map| map.borrow_mut().clear());
+        BALANCES.with(|map| {
+            let mut map = map.borrow_mut();
+            let keys = map
+                .iter()
+                .map(|(account, snapshot, _)| (account, snapshot))
+                .collect::<Vec<_>>();
+            for (account, snapshot) in keys {
+                map.remove(&account, &snapshot);
+            }
+        });
+    }
+}
+
+const SNAPSHOTS_MEMORY_ID: MemoryId = MemoryId::new(41);
+const SNAPSHOT_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(42);
+
+thread_local! {
+    static SNAPSHOTS: RefCell<StableBTreeMap<SnapshotId, Snapshot>> =
+        RefCell::new(StableBTreeMap::new(SNAPSHOTS_MEMORY_ID));
+    static BALANCES: RefCell<StableMultimap<AccountKey, SnapshotKey, u128>> =
+        RefCell::new(StableMultimap::new(SNAPSHOT_BALANCES_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn balances_at_snapshots() {
+        MockContext::new().inject();
+        Snapshots::clear();
+        StableBalances.clear();
+
+        let alice = AccountInternal::from(alice());
+        let bob = AccountInternal::from(bob());
+        StableBalances.insert(alice, 100.into());
+        assert_eq!(Snapshots::latest(), None);
+
+        let first = Snapshots::take(1, vec![]);
+        StableBalances.insert(alice, 60.into());
+        StableBalances.insert(bob, 40.into());
+        let second = Snapshots::take(2, vec![]);
+        let third = Snapshots::take(3, vec![]);
+        StableBalances.remove(&alice);
+        StableBalances.insert(bob, 100.into());
+
+        assert_eq!(Snapshots::balance_at(alice, first.id), Ok(100.into()));
+        assert_eq!(Snapshots::balance_at(bob, first.id), Ok(0.into()));
+        assert_eq!(Snapshots::balance_at(alice, second.id), Ok(60.into()));
+        assert_eq!(Snapshots::balance_at(alice, third.id), Ok(60.into()));
+        assert_eq!(Snapshots::balance_at(bob, third.id), Ok(40.into()));
+        assert_eq!(first.total_supply, 100.into());
+        assert_eq!(second.total_supply, 100.into());
+        assert_eq!(
+            Snapshots::balance_at(alice, 10),
+            Err(TxError::SnapshotNotFound)
+        );
+
+        // Not changed since the snapshot.
+        let fourth = Snapshots::take(4, vec![]);
+        assert_eq!(Snapshots::balance_at(bob, fourth.id), Ok(100.into()));
+    }
+
+    #[test]
+    fn excluded_accounts_have_no_voting_power() {
+        MockContext::new().inject();
+        Snapshots::clear();
+        StableBalances.clear();
+
+        let treasury = AccountInternal::new(john(), Some([1; 32]));
+        StableBalances.insert(john().into(), 300.into());
+        StableBalances.insert(treasury, 200.into());
+        StableBalances.insert(alice().into(), 100.into());
+
+        let snapshot = Snapshots::take(1, vec![john(), john()]);
+        assert_eq!(snapshot.excluded, vec![john()]);
+        assert_eq!(snapshot.excluded_supply, 500.into());
+        assert_eq!(Snapshots::total_voting_power(snapshot.id), Ok(100.into()));
+        assert_eq!(Snapshots::voting_power(treasury, snapshot.id), Ok(0.into()));
+        assert_eq!(
+            Snapshots::voting_power(alice().into(), snapshot.id),
+            Ok(100.into())
+        );
+    }
+}
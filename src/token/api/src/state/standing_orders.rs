@@ -0,0 +1,363 @@
+//! Scheduled payments (standing orders) created by the token holders.
+//!
+//! A payer orders the token to transfer `amount` tokens from the payer's account to the recipient
+//! once per `interval_nanos`, at most `max_executions` times. The orders are executed by a timer
+//! task of the token canister, see `canister::standing_orders`. An execution which fails (e.g.
+//! because of insufficient funds) is skipped and counted as missed, and the order is retried at
+//! the next interval. Every executed payment is recorded in the ledger with the `StandingOrder`
+//! operation.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+pub type StandingOrderId = u64;
+
+/// Minimum interval between the executions of an order. Equal to the period of the timer task
+/// executing the orders.
+pub const MIN_STANDING_ORDER_INTERVAL_NANOS: u64 = 60 * 1_000_000_000;
+
+/// Maximum number of the orders paid from the accounts of one principal.
+pub const MAX_STANDING_ORDERS_PER_PAYER: usize = 16;
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum StandingOrderStatus {
+    Active,
+    Paused,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct StandingOrder {
+    pub id: StandingOrderId,
+    pub payer: Account,
+    pub to: Account,
+    /// Amount received by the recipient per execution. The transfer fee is paid by the payer on
+    /// top of it.
+    pub amount: Tokens128,
+    pub interval_nanos: u64,
+    /// The order is removed after this number of successful executions. Unlimited if not set.
+    pub max_executions: Option<u64>,
+    pub executions: u64,
+    pub missed_executions: u64,
+    pub created_at: Timestamp,
+    pub next_execution_at: Timestamp,
+    pub status: StandingOrderStatus,
+}
+
+impl StandingOrder {
+    fn is_due(&self, now: Timestamp) -> bool {
+        self.status == StandingOrderStatus::Active && self.next_execution_at <= now
+    }
+
+    /// Moves the next execution to the following interval. The intervals missed while the order
+    /// was paused or not executed in time are not caught up.
+    fn advance(&mut self, now: Timestamp) {
+        let next = self.next_execution_at.saturating_add(self.interval_nanos);
+        self.next_execution_at = if next > now {
+            next
+        } else {
+            now.saturating_add(self.interval_nanos)
+        };
+    }
+
+    fn is_completed(&self) -> bool {
+        matches!(self.max_executions, Some(max) if self.executions >= max)
+    }
+}
+
+impl Storable for StandingOrder {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode standing order")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode standing order")
+    }
+}
+
+impl BoundedStorable for StandingOrder {
+    // Two principals, two subaccounts, an amount, six integers and the status with the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct StandingOrders;
+
+impl StandingOrders {
+    pub fn create(
+        payer: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        interval_nanos: u64,
+        max_executions: Option<u64>,
+        now: Timestamp,
+    ) -> Result<StandingOrderId, TxError> {
+        if amount.is_zero() || max_executions == Some(0) {
+            return Err(TxError::AmountTooSmall);
+        }
+
+        if payer == to {
+            return Err(TxError::SelfTransfer);
+        }
+
+        if interval_nanos < MIN_STANDING_ORDER_INTERVAL_NANOS {
+            return Err(TxError::StandingOrderIntervalTooShort {
+                min_interval_nanos: MIN_STANDING_ORDER_INTERVAL_NANOS,
+            });
+        }
+
+        if Self::list(payer.owner)
+            .iter()
+            .filter(|order| order.payer.owner == payer.owner)
+            .count()
+            >= MAX_STANDING_ORDERS_PER_PAYER
+        {
+            return Err(TxError::TooManyStandingOrders {
+                max: MAX_STANDING_ORDERS_PER_PAYER as u64,
+            });
+        }
+
+        let id = NEXT_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1)
+                .expect("failed to write next standing order id");
+            id
+        });
+
+        let order = StandingOrder {
+            id,
+            payer: payer.into(),
+            to: to.into(),
+            amount,
+            interval_nanos,
+            max_executions,
+            executions: 0,
+            missed_executions: 0,
+            created_at: now,
+            next_execution_at: now,
+            status: StandingOrderStatus::Active,
+        };
+        ORDERS.with(|map| map.borrow_mut().insert(id, order));
+
+        Ok(id)
+    }
+
+    pub fn get(id: StandingOrderId) -> Option<StandingOrder> {
+        ORDERS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns the orders in which `who` is the payer or the recipient.
+    pub fn list(who: Principal) -> Vec<StandingOrder> {
+        ORDERS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, order)| order)
+                .filter(|order| order.payer.owner == who || order.to.owner == who)
+                .collect()
+        })
+    }
+
+    /// Returns at most `limit` orders due at the time `now`, in the order of their ids.
+    pub fn due(now: Timestamp, limit: usize) -> Vec<StandingOrder> {
+        ORDERS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, order)| order)
+                .filter(|order| order.is_due(now))
+                .take(limit)
+                .collect()
+        })
+    }
+
+    /// Stores the result of the execution of the order at the time `now`. The order is removed
+    /// once it reaches its maximum number of executions.
+    pub fn record_execution(id: StandingOrderId, executed: bool, now: Timestamp) {
+        ORDERS.with(|map| {
+            let mut map = map.borrow_mut();
+            let Some(mut order) = map.get(&id) else {
+                return;
+            };
+
+            if executed {
+                order.executions += 1;
+            } else {
+                order.missed_executions += 1;
+            }
+
+            if order.is_completed() {
+                map.remove(&id);
+            } else {
+                order.advance(now);
+                map.insert(id, order);
+            }
+        });
+    }
+
+    /// Suspends the executions of the order. Only the payer can pause it.
+    pub fn pause(caller: Principal, id: StandingOrderId) -> Result<(), TxError> {
+        let mut order = Self::get_as_payer(caller, id)?;
+        if order.status != StandingOrderStatus::Active {
+            return Err(TxError::InvalidStandingOrderStatus {
+                status: order.status,
+            });
+        }
+
+        order.status = StandingOrderStatus::Paused;
+        ORDERS.with(|map| map.borrow_mut().insert(id, order));
+        Ok(())
+    }
+
+    /// Resumes the executions of the paused order. If the next execution time has passed, the
+    /// order is executed at the next run of the timer task.
+    pub fn resume(caller: Principal, id: StandingOrderId) -> Result<(), TxError> {
+        let mut order = Self::get_as_payer(caller, id)?;
+        if order.status != StandingOrderStatus::Paused {
+            return Err(TxError::InvalidStandingOrderStatus {
+                status: order.status,
+            });
+        }
+
+        order.status = StandingOrderStatus::Active;
+        ORDERS.with(|map| map.borrow_mut().insert(id, order));
+        Ok(())
+    }
+
+    /// Removes the order. Both the payer and the recipient can cancel it.
+    pub fn cancel(caller: Principal, id: StandingOrderId) -> Result<(), TxError> {
+        let order = Self::get(id).ok_or(TxError::StandingOrderNotFound)?;
+        if order.payer.owner != caller && order.to.owner != caller {
+            return Err(TxError::Unauthorized);
+        }
+
+        ORDERS.with(|map| map.borrow_mut().remove(&id));
+        Ok(())
+    }
+
+    /// Removes the orders in which `who` is the payer or the recipient. Returns the number of the
+    /// removed orders.
+    pub fn remove_involving(who: Principal) -> usize {
+        let orders = Self::list(who);
+        ORDERS.with(|map| {
+            let mut map = map.borrow_mut();
+            for order in &orders {
+                map.remove(&order.id);
+            }
+        });
+
+        orders.len()
+    }
+
+    fn get_as_payer(caller: Principal, id: StandingOrderId) -> Result<StandingOrder, TxError> {
+        let order = Self::get(id).ok_or(TxError::StandingOrderNotFound)?;
+        if order.payer.owner != caller {
+            return Err(TxError::Unauthorized);
+        }
+
+        Ok(order)
+    }
+
+    pub fn clear() {
+        ORDERS.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next standing order id")
+        });
+    }
+}
+
+const STANDING_ORDERS_MEMORY_ID: MemoryId = MemoryId::new(32);
+const NEXT_STANDING_ORDER_ID_MEMORY_ID: MemoryId = MemoryId::new(33);
+
+thread_local! {
+    static ORDERS: RefCell<StableBTreeMap<StandingOrderId, StandingOrder>> =
+        RefCell::new(StableBTreeMap::new(STANDING_ORDERS_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<StandingOrderId>> =
+        RefCell::new(StableCell::new(NEXT_STANDING_ORDER_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next standing order id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    const INTERVAL: u64 = MIN_STANDING_ORDER_INTERVAL_NANOS;
+
+    #[test]
+    fn orders_are_scheduled() {
+        MockContext::new().inject();
+        StandingOrders::clear();
+
+        assert_eq!(
+            StandingOrders::create(alice().into(), bob().into(), 10.into(), 1, None, 0),
+            Err(TxError::StandingOrderIntervalTooShort {
+                min_interval_nanos: INTERVAL
+            })
+        );
+        let id = StandingOrders::create(
+            alice().into(),
+            bob().into(),
+            10.into(),
+            INTERVAL,
+            Some(2),
+            0,
+        )
+        .unwrap();
+        assert_eq!(StandingOrders::due(0, 10).len(), 1);
+
+        StandingOrders::record_execution(id, false, 0);
+        assert!(StandingOrders::due(INTERVAL - 1, 10).is_empty());
+        assert_eq!(StandingOrders::due(INTERVAL, 10).len(), 1);
+
+        // The intervals passed since the next execution time are skipped.
+        StandingOrders::record_execution(id, true, 5 * INTERVAL);
+        let order = StandingOrders::get(id).unwrap();
+        assert_eq!(order.next_execution_at, 6 * INTERVAL);
+        assert_eq!((order.executions, order.missed_executions), (1, 1));
+
+        StandingOrders::record_execution(id, true, 6 * INTERVAL);
+        assert_eq!(StandingOrders::get(id), None);
+    }
+
+    #[test]
+    fn only_payer_pauses() {
+        MockContext::new().inject();
+        StandingOrders::clear();
+
+        let id = StandingOrders::create(alice().into(), bob().into(), 10.into(), INTERVAL, None, 0)
+            .unwrap();
+        assert_eq!(StandingOrders::pause(bob(), id), Err(TxError::Unauthorized));
+        StandingOrders::pause(alice(), id).unwrap();
+        assert!(StandingOrders::due(INTERVAL, 10).is_empty());
+        assert_eq!(
+            StandingOrders::pause(alice(), id),
+            Err(TxError::InvalidStandingOrderStatus {
+                status: StandingOrderStatus::Paused
+            })
+        );
+
+        StandingOrders::resume(alice(), id).unwrap();
+        assert_eq!(StandingOrders::due(INTERVAL, 10).len(), 1);
+
+        assert_eq!(
+            StandingOrders::cancel(john(), id),
+            Err(TxError::Unauthorized)
+        );
+        StandingOrders::cancel(bob(), id).unwrap();
+        assert!(StandingOrders::list(alice()).is_empty());
+    }
+}
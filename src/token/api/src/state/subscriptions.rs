@@ -0,0 +1,451 @@
+//! Registry of canisters subscribed to the token events.
+//!
+//! When a transaction record is added to the ledger, it is added to the pending queue of every
+//! subscriber whose filter matches the record. The pending events are pushed to the subscribers in
+//! batches by one-way calls of the `on_token_events : (vec TxRecord) -> ()` method, which is
+//! scheduled to run right after the message that wrote the records. The calls are made through
+//! the outbound queue (see `state::outbound`), which retries the failed ones.
+//!
+//! If the outbound queue is full, the events stay in the pending queue and are retried by the
+//! timer task of `canister::outbound` with a backoff, see `state::delivery`. The queue of each
+//! subscriber is bounded by `MAX_PENDING_EVENTS`. When a subscriber does not keep up, new events
+//! are dropped and counted, so the subscriber can resync using the `get_transactions` method.
+//!
+//! The filters of the subscribers are also kept in the heap, so a new record is matched without
+//! reading the subscriptions from the stable memory.
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+use crate::state::delivery::{self, DeliveryRetries};
+use crate::state::ledger::{LedgerData, Operation};
+use crate::state::outbound::{NotificationKind, Outbound};
+use crate::tx_record::{TxId, TxRecord};
+
+/// Name of the method called on the subscriber canister with the batch of new events.
+pub const EVENTS_CALLBACK_METHOD: &str = "on_token_events";
+
+pub const MAX_SUBSCRIBERS: u64 = 100;
+pub const MAX_FILTER_ACCOUNTS: usize = 16;
+pub const MAX_PENDING_EVENTS: usize = 1000;
+const MAX_EVENTS_BATCH_SIZE: usize = 100;
+
+/// Cycles charged for a new subscription of a principal other than the owner. Changing the filter
+/// of an existing subscription is free.
+pub const EVENT_SUBSCRIPTION_FEE_CYCLES: u64 = 10_000_000_000;
+
+/// Filter of the events a subscriber is notified about.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct EventFilter {
+    /// If not empty, only the events in which one of these accounts participated are reported.
+    pub accounts: Vec<Account>,
+    pub transfers: bool,
+    pub mints: bool,
+    pub burns: bool,
+}
+
+impl EventFilter {
+    pub fn matches(&self, record: &TxRecord) -> bool {
+        let operation_matches = match record.operation {
+            Operation::Transfer | Operation::TransferFrom | Operation::StandingOrder => {
+                self.transfers
+            }
+            Operation::Mint => self.mints,
+            Operation::Burn => self.burns,
+            _ => false,
+        };
+
+        operation_matches
+            && (self.accounts.is_empty()
+                || self.accounts.iter().any(|account| {
+                    let account = AccountInternal::from(*account);
+                    account == AccountInternal::from(record.from)
+                        || account == AccountInternal::from(record.to)
+                }))
+    }
+}
+
+/// Subscription state of a single subscriber.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Subscription {
+    pub filter: EventFilter,
+    /// Ids of the events not yet delivered to the subscriber.
+    pub pending: Vec<TxId>,
+    /// Number of events dropped because the pending queue was full.
+    pub dropped_events: u64,
+    /// Number of failed notification attempts since the last successful one. The subscription is
+    /// suspended after `delivery::MAX_FAILED_DELIVERIES` of them, until the subscriber subscribes
+    /// again.
+    pub failed_attempts: u32,
+}
+
+impl Subscription {
+    fn new(filter: EventFilter) -> Self {
+        Self {
+            filter,
+            pending: vec![],
+            dropped_events: 0,
+            failed_attempts: 0,
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        delivery::is_suspended(self.failed_attempts)
+    }
+}
+
+impl Storable for Subscription {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode event subscription")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode event subscription")
+    }
+}
+
+impl BoundedStorable for Subscription {
+    // Pending queue takes up to 9 bytes per event in candid encoding, and each filter account
+    // up to 65 bytes. The rest is reserved for the type table and the counters.
+    const MAX_SIZE: u32 = (MAX_PENDING_EVENTS * 9 + MAX_FILTER_ACCOUNTS * 65 + 1024) as _;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct SubscriberKey(Principal);
+
+impl Storable for SubscriberKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        self.0.as_slice().into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Principal::from_slice(&bytes))
+    }
+}
+
+impl BoundedStorable for SubscriberKey {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Part of a subscription kept in the heap index.
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    filter: EventFilter,
+    has_pending: bool,
+    failed_attempts: u32,
+}
+
+impl From<&Subscription> for IndexEntry {
+    fn from(subscription: &Subscription) -> Self {
+        Self {
+            filter: subscription.filter.clone(),
+            has_pending: !subscription.pending.is_empty(),
+            failed_attempts: subscription.failed_attempts,
+        }
+    }
+}
+
+pub struct EventSubscriptions;
+
+impl EventSubscriptions {
+    /// Adds or replaces the subscription of the `subscriber`. Replacing the subscription resumes
+    /// it if it was suspended.
+    pub fn subscribe(subscriber: Principal, filter: EventFilter) -> Result<(), TxError> {
+        if filter.accounts.len() > MAX_FILTER_ACCOUNTS {
+            return Err(TxError::FilterTooLarge {
+                max_accounts: MAX_FILTER_ACCOUNTS,
+            });
+        }
+
+        let subscription = match Self::get(subscriber) {
+            Some(subscription) => Subscription {
+                filter,
+                failed_attempts: 0,
+                ..subscription
+            },
+            None if SUBSCRIPTIONS.with(|map| map.borrow().len()) >= MAX_SUBSCRIBERS => {
+                return Err(TxError::SubscribersLimitReached)
+            }
+            None => Subscription::new(filter),
+        };
+
+        RETRIES.with(|retries| retries.borrow_mut().remove(subscriber));
+        let has_pending = !subscription.pending.is_empty();
+        store(subscriber, subscription);
+        if has_pending {
+            schedule_flush();
+        }
+
+        Ok(())
+    }
+
+    pub fn unsubscribe(subscriber: Principal) -> Option<Subscription> {
+        with_index(|index| index.remove(&subscriber));
+        RETRIES.with(|retries| retries.borrow_mut().remove(subscriber));
+        SUBSCRIPTIONS.with(|map| map.borrow_mut().remove(&SubscriberKey(subscriber)))
+    }
+
+    pub fn get(subscriber: Principal) -> Option<Subscription> {
+        SUBSCRIPTIONS.with(|map| map.borrow().get(&SubscriberKey(subscriber)))
+    }
+
+    /// Adds the record to the pending queues of the matching subscribers.
+    pub fn on_record(record: &TxRecord) {
+        let matching = with_index(|index| {
+            index
+                .iter()
+                .filter(|(_, entry)| entry.filter.matches(record))
+                .map(|(subscriber, _)| *subscriber)
+                .collect::<Vec<_>>()
+        });
+
+        for subscriber in &matching {
+            let mut subscription = Self::get(*subscriber).expect("indexed subscription is stored");
+            if subscription.pending.len() >= MAX_PENDING_EVENTS {
+                subscription.dropped_events += 1;
+            } else {
+                subscription.pending.push(record.index);
+            }
+
+            store(*subscriber, subscription);
+        }
+
+        if !matching.is_empty() {
+            schedule_flush();
+        }
+    }
+
+    /// Sends the next batch of the pending events to the subscribers due at the time `now`. If
+    /// some batches were sent and more events are pending, the next flush is scheduled right
+    /// away. The failed subscribers are retried by the timer task.
+    pub fn flush(now: Timestamp) {
+        let due = with_index(|index| {
+            RETRIES.with(|retries| {
+                let retries = retries.borrow();
+                index
+                    .iter()
+                    .filter(|(subscriber, entry)| {
+                        entry.has_pending
+                            && retries.is_due(**subscriber, entry.failed_attempts, now)
+                    })
+                    .map(|(subscriber, _)| *subscriber)
+                    .collect::<Vec<_>>()
+            })
+        });
+
+        let mut has_more = false;
+        for subscriber in due {
+            let mut subscription = Self::get(subscriber).expect("indexed subscription is stored");
+            let batch_len = subscription.pending.len().min(MAX_EVENTS_BATCH_SIZE);
+            let events = subscription.pending[..batch_len]
+                .iter()
+                .filter_map(|id| LedgerData::get(*id))
+                .collect::<Vec<_>>();
+
+            let delivered = send_events(subscriber, events);
+            if delivered {
+                subscription.pending.drain(..batch_len);
+                has_more |= !subscription.pending.is_empty();
+            }
+
+            subscription.failed_attempts = RETRIES.with(|retries| {
+                retries.borrow_mut().record(
+                    subscriber,
+                    subscription.failed_attempts,
+                    delivered,
+                    now,
+                )
+            });
+            store(subscriber, subscription);
+        }
+
+        if has_more {
+            schedule_flush();
+        }
+    }
+
+    pub fn clear() {
+        SUBSCRIPTIONS.with(|map| map.borrow_mut().clear());
+        INDEX.with(|index| *index.borrow_mut() = None);
+        RETRIES.with(|retries| retries.borrow_mut().clear());
+    }
+}
+
+/// Runs `f` with the heap index of the subscriptions, loading it from the stable memory after an
+/// upgrade.
+fn with_index<R>(f: impl FnOnce(&mut BTreeMap<Principal, IndexEntry>) -> R) -> R {
+    INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        let index = index.get_or_insert_with(|| {
+            SUBSCRIPTIONS.with(|map| {
+                map.borrow()
+                    .iter()
+                    .map(|(key, subscription)| (key.0, IndexEntry::from(&subscription)))
+                    .collect()
+            })
+        });
+        f(index)
+    })
+}
+
+fn store(subscriber: Principal, subscription: Subscription) {
+    with_index(|index| index.insert(subscriber, IndexEntry::from(&subscription)));
+    SUBSCRIPTIONS.with(|map| {
+        map.borrow_mut()
+            .insert(SubscriberKey(subscriber), subscription)
+    });
+}
+
+fn send_events(subscriber: Principal, events: Vec<TxRecord>) -> bool {
+    let args = Encode!(&events).expect("failed to encode token events");
+    Outbound::enqueue(
+        NotificationKind::TokenEvents,
+        subscriber,
+        EVENTS_CALLBACK_METHOD,
+        args,
+        ic::time(),
+    )
+    .is_some()
+}
+
+fn schedule_flush() {
+    delivery::schedule_flush(&FLUSH_SCHEDULED, || EventSubscriptions::flush(ic::time()));
+}
+
+const SUBSCRIPTIONS_MEMORY_ID: MemoryId = MemoryId::new(3);
+
+thread_local! {
+    static SUBSCRIPTIONS: RefCell<StableBTreeMap<SubscriberKey, Subscription>> =
+        RefCell::new(StableBTreeMap::new(SUBSCRIPTIONS_MEMORY_ID));
+
+    static INDEX: RefCell<Option<BTreeMap<Principal, IndexEntry>>> = RefCell::default();
+    static RETRIES: RefCell<DeliveryRetries> = RefCell::default();
+    static FLUSH_SCHEDULED: Cell<bool> = Cell::new(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+    use crate::state::delivery::MAX_FAILED_DELIVERIES;
+    use crate::state::outbound::{BASE_RETRY_DELAY_NANOS, MAX_OUTBOUND_MESSAGES};
+
+    fn filter(accounts: Vec<Account>) -> EventFilter {
+        EventFilter {
+            accounts,
+            transfers: true,
+            mints: false,
+            burns: false,
+        }
+    }
+
+    fn transfer_record(index: TxId, from: Principal, to: Principal) -> TxRecord {
+        TxRecord::transfer(index, from.into(), to.into(), 100.into(), 0.into(), None, 0)
+    }
+
+    #[test]
+    fn filter_matches_operation_and_accounts() {
+        MockContext::new().inject();
+
+        let record = transfer_record(0, alice(), bob());
+        assert!(filter(vec![]).matches(&record));
+        assert!(filter(vec![bob().into()]).matches(&record));
+        assert!(!filter(vec![john().into()]).matches(&record));
+
+        let mints_only = EventFilter {
+            transfers: false,
+            mints: true,
+            ..filter(vec![])
+        };
+        assert!(!mints_only.matches(&record));
+    }
+
+    #[test]
+    fn pending_events_are_bounded() {
+        MockContext::new().inject();
+        EventSubscriptions::clear();
+        Outbound::clear();
+
+        EventSubscriptions::subscribe(john(), filter(vec![])).unwrap();
+        for index in 0..(MAX_PENDING_EVENTS as u64 + 5) {
+            EventSubscriptions::on_record(&transfer_record(index, alice(), bob()));
+        }
+
+        let subscription = EventSubscriptions::get(john()).unwrap();
+        assert_eq!(subscription.pending.len(), MAX_PENDING_EVENTS);
+        assert_eq!(subscription.dropped_events, 5);
+
+        EventSubscriptions::flush(0);
+        let subscription = EventSubscriptions::get(john()).unwrap();
+        assert_eq!(
+            subscription.pending.len(),
+            MAX_PENDING_EVENTS - MAX_EVENTS_BATCH_SIZE
+        );
+        assert_eq!(Outbound::status().pending, 1);
+    }
+
+    #[test]
+    fn failed_delivery_backs_off_and_suspends() {
+        MockContext::new().inject();
+        EventSubscriptions::clear();
+        Outbound::clear();
+
+        EventSubscriptions::subscribe(john(), filter(vec![])).unwrap();
+        EventSubscriptions::on_record(&transfer_record(0, alice(), bob()));
+        for _ in 0..MAX_OUTBOUND_MESSAGES {
+            Outbound::enqueue(NotificationKind::PaymentRequest, alice(), "m", vec![], 0).unwrap();
+        }
+
+        EventSubscriptions::flush(0);
+        assert_eq!(EventSubscriptions::get(john()).unwrap().failed_attempts, 1);
+        EventSubscriptions::flush(BASE_RETRY_DELAY_NANOS - 1);
+        assert_eq!(EventSubscriptions::get(john()).unwrap().failed_attempts, 1);
+
+        for _ in 1..=MAX_FAILED_DELIVERIES {
+            EventSubscriptions::flush(u64::MAX);
+        }
+        let subscription = EventSubscriptions::get(john()).unwrap();
+        assert!(subscription.is_suspended());
+        assert_eq!(subscription.failed_attempts, MAX_FAILED_DELIVERIES);
+
+        Outbound::clear();
+        EventSubscriptions::flush(u64::MAX);
+        assert_eq!(EventSubscriptions::get(john()).unwrap().pending.len(), 1);
+
+        EventSubscriptions::subscribe(john(), filter(vec![])).unwrap();
+        EventSubscriptions::flush(0);
+        let subscription = EventSubscriptions::get(john()).unwrap();
+        assert!(subscription.pending.is_empty());
+        assert_eq!(subscription.failed_attempts, 0);
+    }
+
+    #[test]
+    fn filter_size_is_limited() {
+        MockContext::new().inject();
+        EventSubscriptions::clear();
+
+        let accounts = vec![Account::from(alice()); MAX_FILTER_ACCOUNTS + 1];
+        assert_eq!(
+            EventSubscriptions::subscribe(john(), filter(accounts)),
+            Err(TxError::FilterTooLarge {
+                max_accounts: MAX_FILTER_ACCOUNTS
+            })
+        );
+        assert_eq!(EventSubscriptions::get(john()), None);
+    }
+}
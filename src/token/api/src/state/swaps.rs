@@ -0,0 +1,204 @@
+//! Escrow of the atomic swaps between this token and another ICRC-1 token.
+//!
+//! The maker locks the tokens of this canister in the escrow account of the swap. The counterparty
+//! deposits the other token to the account of this canister with the same escrow subaccount in the
+//! other token canister. When the counterparty accepts the swap, the deposit is verified and sent
+//! to the maker, and the escrowed tokens are released to the counterparty. If the swap is not
+//! accepted before the deadline, both sides are refunded.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{Account, AccountInternal, Subaccount};
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+pub type SwapId = u64;
+
+const ESCROW_SUBACCOUNT_PREFIX: &[u8] = b"is20-swap-escrow";
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum SwapStatus {
+    Open,
+    /// The counterparty deposit is being transferred to the maker.
+    Accepting,
+    Completed,
+    Refunded,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Swap {
+    pub id: SwapId,
+    pub maker: Account,
+    pub counterparty: Principal,
+    /// Amount of this token locked in the escrow.
+    pub amount: Tokens128,
+    pub other_token: Principal,
+    /// Amount of the other token the maker receives.
+    pub other_amount: Tokens128,
+    pub deadline: Timestamp,
+    pub status: SwapStatus,
+}
+
+impl Swap {
+    /// Account holding the escrowed tokens of the swap, in this token and in the other token
+    /// canister.
+    pub fn escrow_account(&self) -> AccountInternal {
+        AccountInternal::new(ic::id(), Some(escrow_subaccount(self.id)))
+    }
+}
+
+pub fn escrow_subaccount(id: SwapId) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    subaccount[..ESCROW_SUBACCOUNT_PREFIX.len()].copy_from_slice(ESCROW_SUBACCOUNT_PREFIX);
+    subaccount[24..].copy_from_slice(&id.to_be_bytes());
+    subaccount
+}
+
+impl Storable for Swap {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self).expect("failed to encode swap").into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode swap")
+    }
+}
+
+impl BoundedStorable for Swap {
+    // Three principals, a subaccount, two amounts and two numbers with the type table.
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct Swaps;
+
+impl Swaps {
+    /// Returns the id the next created swap will have.
+    pub fn next_id() -> SwapId {
+        NEXT_ID.with(|cell| *cell.borrow().get())
+    }
+
+    /// Stores a new swap with the id returned by `next_id`.
+    pub fn insert_new(
+        maker: AccountInternal,
+        counterparty: Principal,
+        amount: Tokens128,
+        other_token: Principal,
+        other_amount: Tokens128,
+        deadline: Timestamp,
+    ) -> Swap {
+        let id = Self::next_id();
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(id + 1)
+                .expect("failed to write next swap id")
+        });
+
+        let swap = Swap {
+            id,
+            maker: maker.into(),
+            counterparty,
+            amount,
+            other_token,
+            other_amount,
+            deadline,
+            status: SwapStatus::Open,
+        };
+        SWAPS.with(|map| map.borrow_mut().insert(id, swap.clone()));
+        swap
+    }
+
+    pub fn get(id: SwapId) -> Option<Swap> {
+        SWAPS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns the swaps in which `who` is the maker or the counterparty.
+    pub fn list(who: Principal) -> Vec<Swap> {
+        SWAPS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, swap)| swap)
+                .filter(|swap| swap.maker.owner == who || swap.counterparty == who)
+                .collect()
+        })
+    }
+
+    /// Changes the status of the swap from `from` to `to`. Returns the swap with the old status.
+    pub fn transition(id: SwapId, from: SwapStatus, to: SwapStatus) -> Result<Swap, TxError> {
+        let swap = Self::get(id).ok_or(TxError::SwapNotFound)?;
+        if swap.status != from {
+            return Err(TxError::InvalidSwapStatus {
+                status: swap.status,
+            });
+        }
+
+        let mut updated = swap.clone();
+        updated.status = to;
+        SWAPS.with(|map| map.borrow_mut().insert(id, updated));
+        Ok(swap)
+    }
+
+    pub fn clear() {
+        SWAPS.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next swap id")
+        });
+    }
+}
+
+const SWAPS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const NEXT_SWAP_ID_MEMORY_ID: MemoryId = MemoryId::new(11);
+
+thread_local! {
+    static SWAPS: RefCell<StableBTreeMap<SwapId, Swap>> =
+        RefCell::new(StableBTreeMap::new(SWAPS_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<SwapId>> =
+        RefCell::new(StableCell::new(NEXT_SWAP_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next swap id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn swap_status_transitions() {
+        MockContext::new().inject();
+        Swaps::clear();
+
+        let swap = Swaps::insert_new(alice().into(), bob(), 10.into(), john(), 20.into(), 100);
+        assert_eq!(swap.id, 0);
+        assert_eq!(Swaps::next_id(), 1);
+        assert_eq!(Swaps::list(bob()), vec![swap.clone()]);
+
+        Swaps::transition(swap.id, SwapStatus::Open, SwapStatus::Accepting).unwrap();
+        assert_eq!(
+            Swaps::transition(swap.id, SwapStatus::Open, SwapStatus::Refunded),
+            Err(TxError::InvalidSwapStatus {
+                status: SwapStatus::Accepting
+            })
+        );
+        assert_eq!(
+            Swaps::transition(1, SwapStatus::Open, SwapStatus::Refunded),
+            Err(TxError::SwapNotFound)
+        );
+    }
+
+    #[test]
+    fn escrow_subaccounts_are_unique() {
+        assert_ne!(escrow_subaccount(1), escrow_subaccount(2));
+        assert_eq!(&escrow_subaccount(1)[..16], ESCROW_SUBACCOUNT_PREFIX);
+    }
+}
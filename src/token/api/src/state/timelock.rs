@@ -0,0 +1,214 @@
+//! Changes of the token configuration delayed by the timelock.
+//!
+//! If the owner sets a timelock delay, the changes of the fee, of the fee and burn policies, of
+//! the fee recipients, of the owner and of the minting account are not applied at once. Instead
+//! they are stored as proposals, which are applied by the timer task of the token, see
+//! `canister::timelock`, once the delay passes. Until then the holders can see them with
+//! `list_pending_changes`, and the owner can cancel them. Shortening or removing the delay is a
+//! timelocked change as well, so the timelock can't be bypassed by disabling it first.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::Account;
+use crate::error::TxError;
+use crate::state::config::{BurnPolicy, Timestamp, TokenConfig};
+use crate::state::fee_recipients::FeeRecipient;
+use crate::state::fees::FeePolicy;
+
+pub type ProposalId = u64;
+
+/// Upper bound of the timelock delay, so the owner can't lock the configuration for good.
+pub const MAX_TIMELOCK_DELAY_NANOS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum ConfigChange {
+    Fee(Tokens128),
+    Owner(Principal),
+    TimelockDelay(Option<u64>),
+    MintingAccount(Account),
+    BurnPolicy(Option<BurnPolicy>),
+    FeePolicy(Option<FeePolicy>),
+    FeeRecipients(Vec<FeeRecipient>),
+}
+
+impl ConfigChange {
+    /// Whether the change must wait for the timelock delay of the `config`.
+    pub fn is_timelocked(&self, config: &TokenConfig) -> bool {
+        let Some(delay) = config.timelock_delay_nanos else {
+            return false;
+        };
+
+        match self {
+            Self::Fee(_)
+            | Self::Owner(_)
+            | Self::MintingAccount(_)
+            | Self::BurnPolicy(_)
+            | Self::FeePolicy(_)
+            | Self::FeeRecipients(_) => true,
+            // Extending the delay only protects the holders more.
+            Self::TimelockDelay(new_delay) => new_delay.unwrap_or(0) < delay,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct PendingChange {
+    pub id: ProposalId,
+    pub change: ConfigChange,
+    pub proposed_by: Principal,
+    pub proposed_at: Timestamp,
+    pub executable_at: Timestamp,
+}
+
+impl Storable for PendingChange {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode pending change")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode pending change")
+    }
+}
+
+impl BoundedStorable for PendingChange {
+    // The largest change is the fee policy with `MAX_FEE_EXEMPTIONS` principals and
+    // `MAX_FEE_TIERS` tiers.
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct Timelock;
+
+impl Timelock {
+    pub fn validate_delay(delay_nanos: Option<u64>) -> Result<(), TxError> {
+        match delay_nanos {
+            Some(delay) if delay > MAX_TIMELOCK_DELAY_NANOS => Err(TxError::InvalidTimelockDelay {
+                max_delay_nanos: MAX_TIMELOCK_DELAY_NANOS,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores the `change` to be applied after the `delay_nanos`.
+    pub fn propose(
+        proposed_by: Principal,
+        change: ConfigChange,
+        delay_nanos: u64,
+        now: Timestamp,
+    ) -> PendingChange {
+        let id = NEXT_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1).expect("failed to write next proposal id");
+            id
+        });
+
+        let pending = PendingChange {
+            id,
+            change,
+            proposed_by,
+            proposed_at: now,
+            executable_at: now.saturating_add(delay_nanos),
+        };
+        PROPOSALS.with(|map| map.borrow_mut().insert(id, pending.clone()));
+        pending
+    }
+
+    /// Returns the pending changes in the order they were proposed.
+    pub fn list() -> Vec<PendingChange> {
+        PROPOSALS.with(|map| map.borrow().iter().map(|(_, change)| change).collect())
+    }
+
+    /// Returns up to `limit` changes which can be applied at the time `now`, in the order they
+    /// were proposed.
+    pub fn due(now: Timestamp, limit: usize) -> Vec<PendingChange> {
+        PROPOSALS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, change)| change)
+                .filter(|change| change.executable_at <= now)
+                .take(limit)
+                .collect()
+        })
+    }
+
+    pub fn cancel(id: ProposalId) -> Result<PendingChange, TxError> {
+        PROPOSALS
+            .with(|map| map.borrow_mut().remove(&id))
+            .ok_or(TxError::ProposalNotFound)
+    }
+
+    pub fn remove(id: ProposalId) {
+        PROPOSALS.with(|map| map.borrow_mut().remove(&id));
+    }
+
+    pub fn clear() {
+        PROPOSALS.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next proposal id")
+        });
+    }
+}
+
+const PROPOSALS_MEMORY_ID: MemoryId = MemoryId::new(38);
+const NEXT_PROPOSAL_ID_MEMORY_ID: MemoryId = MemoryId::new(39);
+
+thread_local! {
+    static PROPOSALS: RefCell<StableBTreeMap<ProposalId, PendingChange>> =
+        RefCell::new(StableBTreeMap::new(PROPOSALS_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<ProposalId>> =
+        RefCell::new(StableCell::new(NEXT_PROPOSAL_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next proposal id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn changes_are_due_after_delay() {
+        MockContext::new().inject();
+        Timelock::clear();
+
+        let fee = Timelock::propose(alice(), ConfigChange::Fee(10.into()), 100, 0);
+        let owner = Timelock::propose(alice(), ConfigChange::Owner(bob()), 200, 0);
+        assert_eq!(Timelock::list(), vec![fee.clone(), owner.clone()]);
+        assert_eq!(Timelock::due(99, 10), vec![]);
+        assert_eq!(Timelock::due(200, 10), vec![fee.clone(), owner.clone()]);
+        assert_eq!(Timelock::due(200, 1), vec![fee.clone()]);
+
+        assert_eq!(Timelock::cancel(owner.id), Ok(owner.clone()));
+        assert_eq!(Timelock::cancel(owner.id), Err(TxError::ProposalNotFound));
+        assert_eq!(Timelock::list(), vec![fee]);
+    }
+
+    #[test]
+    fn shortening_delay_is_timelocked() {
+        let config = TokenConfig {
+            timelock_delay_nanos: Some(100),
+            ..TokenConfig::default()
+        };
+        assert!(ConfigChange::Fee(1.into()).is_timelocked(&config));
+        assert!(ConfigChange::MintingAccount(alice().into()).is_timelocked(&config));
+        assert!(ConfigChange::BurnPolicy(None).is_timelocked(&config));
+        assert!(ConfigChange::FeePolicy(None).is_timelocked(&config));
+        assert!(ConfigChange::FeeRecipients(vec![]).is_timelocked(&config));
+        assert!(ConfigChange::TimelockDelay(None).is_timelocked(&config));
+        assert!(ConfigChange::TimelockDelay(Some(99)).is_timelocked(&config));
+        assert!(!ConfigChange::TimelockDelay(Some(200)).is_timelocked(&config));
+        assert!(!ConfigChange::Fee(1.into()).is_timelocked(&TokenConfig::default()));
+    }
+}
@@ -0,0 +1,167 @@
+//! Wasm module uploaded by the owner for the self-upgrade of the token, see
+//! `canister::self_upgrade`.
+//!
+//! The module doesn't fit in one ingress message, so it's uploaded in chunks of at most
+//! `MAX_UPGRADE_CHUNK_SIZE` bytes, which are concatenated in the order they were uploaded.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+
+use crate::error::TxError;
+
+/// Maximum size of one uploaded chunk.
+pub const MAX_UPGRADE_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Maximum size of the whole module, so the `install_code` call fits in the 2 MiB message limit.
+pub const MAX_UPGRADE_MODULE_SIZE: usize = 2_000_000;
+
+#[derive(Debug, Clone, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub struct UpgradeModuleStatus {
+    pub chunks: u32,
+    pub size: u64,
+    /// SHA-256 hash of the uploaded chunks concatenated, the same as the module hash reported by
+    /// the management canister once the module is installed.
+    pub module_hash: Vec<u8>,
+}
+
+struct Chunk(Vec<u8>);
+
+impl Storable for Chunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Chunk {
+    const MAX_SIZE: u32 = MAX_UPGRADE_CHUNK_SIZE as _;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct UpgradeChunks;
+
+impl UpgradeChunks {
+    /// Appends the `chunk` to the uploaded module.
+    pub fn upload(chunk: Vec<u8>) -> Result<UpgradeModuleStatus, TxError> {
+        if chunk.is_empty() || chunk.len() > MAX_UPGRADE_CHUNK_SIZE {
+            return Err(TxError::InvalidUpgradeChunk {
+                max_size: MAX_UPGRADE_CHUNK_SIZE as _,
+            });
+        }
+
+        let size = Self::size();
+        if size as usize + chunk.len() > MAX_UPGRADE_MODULE_SIZE {
+            return Err(TxError::UpgradeModuleTooLarge {
+                max_size: MAX_UPGRADE_MODULE_SIZE as _,
+            });
+        }
+
+        CHUNKS.with(|map| {
+            let mut map = map.borrow_mut();
+            let index = map.len() as u32;
+            map.insert(index, Chunk(chunk));
+        });
+
+        Ok(Self::status())
+    }
+
+    pub fn status() -> UpgradeModuleStatus {
+        let module = Self::module();
+        if module.is_empty() {
+            return UpgradeModuleStatus::default();
+        }
+
+        UpgradeModuleStatus {
+            chunks: CHUNKS.with(|map| map.borrow().len()) as _,
+            size: module.len() as _,
+            module_hash: Sha256::digest(&module).to_vec(),
+        }
+    }
+
+    /// Returns the uploaded module if its hash is the `expected_hash`.
+    pub fn verified_module(expected_hash: &[u8]) -> Result<Vec<u8>, TxError> {
+        let module = Self::module();
+        if module.is_empty() {
+            return Err(TxError::NoUpgradeModule);
+        }
+
+        let actual = Sha256::digest(&module).to_vec();
+        if actual != expected_hash {
+            return Err(TxError::ModuleHashMismatch { actual });
+        }
+
+        Ok(module)
+    }
+
+    pub fn clear() {
+        CHUNKS.with(|map| map.borrow_mut().clear());
+    }
+
+    fn module() -> Vec<u8> {
+        CHUNKS.with(|map| map.borrow().iter().flat_map(|(_, chunk)| chunk.0).collect())
+    }
+
+    fn size() -> u64 {
+        CHUNKS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, chunk)| chunk.0.len() as u64)
+                .sum()
+        })
+    }
+}
+
+const UPGRADE_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(43);
+
+thread_local! {
+    static CHUNKS: RefCell<StableBTreeMap<u32, Chunk>> =
+        RefCell::new(StableBTreeMap::new(UPGRADE_CHUNKS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn chunks_are_concatenated() {
+        MockContext::new().inject();
+        UpgradeChunks::clear();
+
+        assert_eq!(
+            UpgradeChunks::verified_module(&[]),
+            Err(TxError::NoUpgradeModule)
+        );
+        UpgradeChunks::upload(vec![1, 2]).unwrap();
+        let status = UpgradeChunks::upload(vec![3]).unwrap();
+        let hash = Sha256::digest([1, 2, 3]).to_vec();
+        assert_eq!(
+            status,
+            UpgradeModuleStatus {
+                chunks: 2,
+                size: 3,
+                module_hash: hash.clone(),
+            }
+        );
+        assert_eq!(UpgradeChunks::verified_module(&hash), Ok(vec![1, 2, 3]));
+        assert_eq!(
+            UpgradeChunks::verified_module(&[0; 32]),
+            Err(TxError::ModuleHashMismatch { actual: hash })
+        );
+
+        assert!(UpgradeChunks::upload(vec![]).is_err());
+        assert!(UpgradeChunks::upload(vec![0; MAX_UPGRADE_CHUNK_SIZE + 1]).is_err());
+
+        UpgradeChunks::clear();
+        assert_eq!(UpgradeChunks::status(), UpgradeModuleStatus::default());
+    }
+}
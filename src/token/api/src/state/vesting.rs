@@ -0,0 +1,243 @@
+//! Tokens released to their beneficiaries linearly over time.
+//!
+//! The vested tokens are held by the `vesting_account` of the token canister. Every grant is a
+//! separate schedule, which releases its amount linearly from the `start` over `duration_nanos`.
+//! The beneficiary claims the released part of all their schedules at once, see
+//! `canister::is20_auction::claim_vested_rewards`, and the fully claimed schedules are removed.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, StableCell, Storable};
+
+use crate::account::{AccountInternal, Subaccount};
+use crate::state::config::Timestamp;
+
+pub type VestingId = u64;
+
+const VESTING_SUBACCOUNT_PREFIX: &[u8] = b"is20-vesting";
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct VestingSchedule {
+    pub beneficiary: Principal,
+    pub total: Tokens128,
+    /// Part of the total already claimed by the beneficiary.
+    pub claimed: Tokens128,
+    pub start: Timestamp,
+    pub duration_nanos: u64,
+}
+
+impl VestingSchedule {
+    /// Part of the total released at the time `now`.
+    pub fn vested(&self, now: Timestamp) -> Tokens128 {
+        let elapsed = now.saturating_sub(self.start);
+        if elapsed >= self.duration_nanos {
+            return self.total;
+        }
+
+        // The product of a u128 amount and a u64 time can overflow, so the whole periods are
+        // split off the same way as in `FixedRatio::apply`.
+        let (elapsed, duration) = (elapsed as u128, self.duration_nanos as u128);
+        let amount = self.total.amount;
+        let vested = amount / duration * elapsed + amount % duration * elapsed / duration;
+        vested.into()
+    }
+
+    pub fn claimable(&self, now: Timestamp) -> Tokens128 {
+        self.vested(now).saturating_sub(self.claimed)
+    }
+}
+
+impl Storable for VestingSchedule {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode vesting schedule")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode vesting schedule")
+    }
+}
+
+impl BoundedStorable for VestingSchedule {
+    // A principal, two amounts and two integers with the type table.
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Totals of the vesting schedules of a beneficiary.
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct VestingBalance {
+    pub total: Tokens128,
+    pub vested: Tokens128,
+    pub claimed: Tokens128,
+    /// Vested and not claimed yet.
+    pub claimable: Tokens128,
+    /// Not vested yet.
+    pub locked: Tokens128,
+}
+
+/// Account of the token canister holding the vested tokens.
+pub fn vesting_account() -> AccountInternal {
+    let mut subaccount: Subaccount = [0; 32];
+    subaccount[..VESTING_SUBACCOUNT_PREFIX.len()].copy_from_slice(VESTING_SUBACCOUNT_PREFIX);
+    AccountInternal::new(ic::id(), Some(subaccount))
+}
+
+pub struct Vesting;
+
+impl Vesting {
+    /// Adds a schedule releasing the `amount` to the `beneficiary` from `now` over
+    /// `duration_nanos`. The amount must already be in the `vesting_account`.
+    pub fn grant(
+        beneficiary: Principal,
+        amount: Tokens128,
+        duration_nanos: u64,
+        now: Timestamp,
+    ) -> VestingId {
+        let id = NEXT_ID.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let id = *cell.get();
+            cell.set(id + 1).expect("failed to write next vesting id");
+            id
+        });
+
+        let schedule = VestingSchedule {
+            beneficiary,
+            total: amount,
+            claimed: Tokens128::ZERO,
+            start: now,
+            duration_nanos,
+        };
+        SCHEDULES.with(|map| map.borrow_mut().insert(id, schedule));
+
+        id
+    }
+
+    pub fn list(beneficiary: Principal) -> Vec<(VestingId, VestingSchedule)> {
+        SCHEDULES.with(|map| {
+            map.borrow()
+                .iter()
+                .filter(|(_, schedule)| schedule.beneficiary == beneficiary)
+                .collect()
+        })
+    }
+
+    pub fn balance(beneficiary: Principal, now: Timestamp) -> VestingBalance {
+        let sum = |total: Tokens128, amount: Tokens128| (total + amount).unwrap_or(total);
+        Self::list(beneficiary).into_iter().fold(
+            VestingBalance::default(),
+            |balance, (_, schedule)| {
+                let vested = schedule.vested(now);
+                VestingBalance {
+                    total: sum(balance.total, schedule.total),
+                    vested: sum(balance.vested, vested),
+                    claimed: sum(balance.claimed, schedule.claimed),
+                    claimable: sum(balance.claimable, schedule.claimable(now)),
+                    locked: sum(balance.locked, schedule.total.saturating_sub(vested)),
+                }
+            },
+        )
+    }
+
+    /// Marks the claimable part of all the schedules of the `beneficiary` as claimed, and removes
+    /// the completed schedules. Returns the claimed amount, which the caller must transfer from
+    /// the `vesting_account`.
+    pub fn claim(beneficiary: Principal, now: Timestamp) -> Tokens128 {
+        let mut claimed = Tokens128::ZERO;
+        SCHEDULES.with(|map| {
+            let mut map = map.borrow_mut();
+            for (id, mut schedule) in Self::list(beneficiary) {
+                let claimable = schedule.claimable(now);
+                claimed = (claimed + claimable).unwrap_or(claimed);
+                schedule.claimed = schedule.vested(now);
+                if schedule.claimed == schedule.total {
+                    map.remove(&id);
+                } else {
+                    map.insert(id, schedule);
+                }
+            }
+        });
+
+        claimed
+    }
+
+    pub fn clear() {
+        SCHEDULES.with(|map| map.borrow_mut().clear());
+        NEXT_ID.with(|cell| {
+            cell.borrow_mut()
+                .set(0)
+                .expect("failed to write next vesting id")
+        });
+    }
+}
+
+const VESTING_SCHEDULES_MEMORY_ID: MemoryId = MemoryId::new(45);
+const NEXT_VESTING_ID_MEMORY_ID: MemoryId = MemoryId::new(46);
+
+thread_local! {
+    static SCHEDULES: RefCell<StableBTreeMap<VestingId, VestingSchedule>> =
+        RefCell::new(StableBTreeMap::new(VESTING_SCHEDULES_MEMORY_ID));
+    static NEXT_ID: RefCell<StableCell<VestingId>> =
+        RefCell::new(StableCell::new(NEXT_VESTING_ID_MEMORY_ID, 0)
+            .expect("unable to initialize next vesting id"));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn schedules_vest_linearly() {
+        MockContext::new().inject();
+        Vesting::clear();
+
+        Vesting::grant(alice(), 1_000.into(), 100, 0);
+        Vesting::grant(alice(), 300.into(), 300, 50);
+        Vesting::grant(bob(), 10.into(), 10, 0);
+
+        assert_eq!(
+            Vesting::balance(alice(), 50),
+            VestingBalance {
+                total: 1_300.into(),
+                vested: 500.into(),
+                claimed: 0.into(),
+                claimable: 500.into(),
+                locked: 800.into(),
+            }
+        );
+
+        assert_eq!(Vesting::claim(alice(), 50), 500.into());
+        assert_eq!(Vesting::claim(alice(), 50), 0.into());
+        assert_eq!(Vesting::claim(alice(), 110), 560.into());
+        assert_eq!(Vesting::list(alice()).len(), 1);
+
+        let balance = Vesting::balance(alice(), 1_000);
+        assert_eq!(balance.claimable, 240.into());
+        assert_eq!(balance.locked, 0.into());
+        assert_eq!(Vesting::claim(alice(), 1_000), 240.into());
+        assert!(Vesting::list(alice()).is_empty());
+        assert_eq!(Vesting::list(bob()).len(), 1);
+    }
+
+    #[test]
+    fn vesting_does_not_overflow() {
+        let schedule = VestingSchedule {
+            beneficiary: alice(),
+            total: u128::MAX.into(),
+            claimed: 0.into(),
+            start: 0,
+            duration_nanos: 4,
+        };
+        assert_eq!(schedule.vested(2), (u128::MAX / 2).into());
+        assert_eq!(schedule.vested(4), u128::MAX.into());
+    }
+}
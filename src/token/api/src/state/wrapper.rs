@@ -0,0 +1,248 @@
+//! State of the wrapping mode: the transfers of the underlying ICRC-1 token made by the wrapper.
+//!
+//! Every transfer of the underlying token is stored as a `WrapperOperation` before the ledger is
+//! called. If the call fails, the operation stays pending and is retried later. The retries use
+//! the same memo and `created_at_time`, so the underlying ledger deduplicates them and an
+//! operation is never executed twice.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableBTreeMap, Storable};
+
+use crate::account::{Account, Subaccount};
+use crate::error::truncate_message;
+use crate::state::config::Timestamp;
+use crate::tx_record::TxId;
+
+pub type OperationId = u64;
+/// Index of the transaction in the underlying ledger.
+pub type UnderlyingTxIndex = u128;
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum WrapperOperationKind {
+    /// Moves a deposit from the deposit subaccount to the reserve account. The tokens are minted
+    /// once the transfer is executed.
+    Deposit { subaccount: Subaccount },
+    /// Sends the underlying tokens of the burned tokens to the user.
+    Withdrawal { to: Account },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum WrapperOperationStatus {
+    Pending,
+    Completed {
+        index: UnderlyingTxIndex,
+    },
+    /// The underlying ledger rejected the deposit transfer, nothing was moved or minted.
+    Rejected {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct WrapperOperation {
+    pub id: OperationId,
+    pub kind: WrapperOperationKind,
+    /// Token account the operation was made for.
+    pub account: Account,
+    /// Amount of the underlying tokens to be received, the ledger fee is paid on top of it.
+    pub amount: Tokens128,
+    /// Fee of the underlying ledger at the time the operation was created.
+    pub fee: Tokens128,
+    pub created_at: Timestamp,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub status: WrapperOperationStatus,
+    /// Transaction of this token: the mint of a deposit or the burn of a withdrawal.
+    pub tx_id: Option<TxId>,
+}
+
+impl WrapperOperation {
+    pub fn is_pending(&self) -> bool {
+        self.status == WrapperOperationStatus::Pending
+    }
+}
+
+impl Storable for WrapperOperation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Encode!(self)
+            .expect("failed to encode wrapper operation")
+            .into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode wrapper operation")
+    }
+}
+
+impl BoundedStorable for WrapperOperation {
+    // The error messages are truncated to `MAX_ERROR_LENGTH`.
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+const MAX_ERROR_LENGTH: usize = 200;
+
+pub struct IcrcWrapper;
+
+impl IcrcWrapper {
+    pub fn add_operation(
+        kind: WrapperOperationKind,
+        account: Account,
+        amount: Tokens128,
+        fee: Tokens128,
+        now: Timestamp,
+    ) -> WrapperOperation {
+        OPERATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            let operation = WrapperOperation {
+                id: map.len(),
+                kind,
+                account,
+                amount,
+                fee,
+                created_at: now,
+                attempts: 0,
+                last_error: None,
+                status: WrapperOperationStatus::Pending,
+                tx_id: None,
+            };
+            map.insert(operation.id, operation.clone());
+            operation
+        })
+    }
+
+    pub fn get_operation(id: OperationId) -> Option<WrapperOperation> {
+        OPERATIONS.with(|map| map.borrow().get(&id))
+    }
+
+    /// Returns `count` operations starting from the `offset`.
+    pub fn get_operations(offset: u64, count: usize) -> Vec<WrapperOperation> {
+        OPERATIONS.with(|map| {
+            map.borrow()
+                .iter()
+                .skip(offset as usize)
+                .take(count)
+                .map(|(_, operation)| operation)
+                .collect()
+        })
+    }
+
+    pub fn pending_operations() -> Vec<WrapperOperation> {
+        OPERATIONS.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(_, operation)| operation)
+                .filter(WrapperOperation::is_pending)
+                .collect()
+        })
+    }
+
+    pub fn set_tx_id(id: OperationId, tx_id: TxId) {
+        Self::update(id, |operation| operation.tx_id = Some(tx_id));
+    }
+
+    /// Stores a failed attempt to execute the operation. The operation stays pending.
+    pub fn record_failure(id: OperationId, mut error: String) {
+        truncate_message(&mut error, MAX_ERROR_LENGTH);
+        Self::update(id, |operation| {
+            operation.attempts += 1;
+            operation.last_error = Some(error);
+        });
+    }
+
+    /// Stores the final status of the operation. Returns the operation if it was pending before,
+    /// so the concurrent attempts of the same operation are completed only once.
+    pub fn finish(id: OperationId, mut status: WrapperOperationStatus) -> Option<WrapperOperation> {
+        if let WrapperOperationStatus::Rejected { reason } = &mut status {
+            truncate_message(reason, MAX_ERROR_LENGTH);
+        }
+
+        let operation = Self::get_operation(id).filter(WrapperOperation::is_pending)?;
+        Self::update(id, |operation| {
+            operation.attempts += 1;
+            operation.last_error = None;
+            operation.status = status;
+        });
+        Some(operation)
+    }
+
+    fn update(id: OperationId, f: impl FnOnce(&mut WrapperOperation)) {
+        OPERATIONS.with(|map| {
+            let mut map = map.borrow_mut();
+            if let Some(mut operation) = map.get(&id) {
+                f(&mut operation);
+                map.insert(id, operation);
+            }
+        });
+    }
+
+    pub fn clear() {
+        OPERATIONS.with(|map| map.borrow_mut().clear());
+    }
+}
+
+const OPERATIONS_MEMORY_ID: MemoryId = MemoryId::new(21);
+
+thread_local! {
+    static OPERATIONS: RefCell<StableBTreeMap<OperationId, WrapperOperation>> =
+        RefCell::new(StableBTreeMap::new(OPERATIONS_MEMORY_ID));
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob};
+    use canister_sdk::ic_kit::MockContext;
+    use coverage_helper::test;
+
+    use super::*;
+
+    #[test]
+    fn failed_operations_stay_pending() {
+        MockContext::new().inject();
+        IcrcWrapper::clear();
+
+        let kind = WrapperOperationKind::Withdrawal { to: bob().into() };
+        let operation = IcrcWrapper::add_operation(kind, alice().into(), 100.into(), 10.into(), 0);
+        IcrcWrapper::record_failure(operation.id, "ledger is stopped".into());
+        assert_eq!(IcrcWrapper::pending_operations().len(), 1);
+
+        let status = WrapperOperationStatus::Completed { index: 7 };
+        assert!(IcrcWrapper::finish(operation.id, status.clone()).is_some());
+        let operation = IcrcWrapper::get_operation(operation.id).unwrap();
+        assert_eq!(operation.attempts, 2);
+        assert_eq!(operation.status, status);
+        assert_eq!(operation.last_error, None);
+        assert!(IcrcWrapper::pending_operations().is_empty());
+    }
+
+    #[test]
+    fn operations_are_finished_once() {
+        MockContext::new().inject();
+        IcrcWrapper::clear();
+
+        let kind = WrapperOperationKind::Deposit {
+            subaccount: [1; 32],
+        };
+        let operation = IcrcWrapper::add_operation(kind, alice().into(), 100.into(), 0.into(), 0);
+        let rejected = WrapperOperationStatus::Rejected {
+            reason: "x".repeat(MAX_ERROR_LENGTH + 1),
+        };
+        assert!(IcrcWrapper::finish(operation.id, rejected).is_some());
+        assert!(
+            IcrcWrapper::finish(operation.id, WrapperOperationStatus::Completed { index: 1 })
+                .is_none()
+        );
+
+        let operation = IcrcWrapper::get_operation(operation.id).unwrap();
+        assert_eq!(
+            operation.status,
+            WrapperOperationStatus::Rejected {
+                reason: "x".repeat(MAX_ERROR_LENGTH)
+            }
+        );
+    }
+}
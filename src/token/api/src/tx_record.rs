@@ -1,6 +1,7 @@
 use candid::{CandidType, Deserialize, Principal};
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use canister_sdk::ic_kit::ic;
+use sha2::{Digest, Sha256};
 
 use crate::{
     account::{Account, AccountInternal},
@@ -9,6 +10,7 @@ use crate::{
 };
 
 pub type TxId = u64;
+pub type TxHash = [u8; 32];
 
 // We use `Account` instead of `AccountInternal` in this structure for two reasons:
 // 1. It was there before `AccountInternal` was introduced, so if we want to change this type, we
@@ -26,6 +28,11 @@ pub struct TxRecord {
     pub status: TransactionStatus,
     pub operation: Operation,
     pub memo: Option<Memo>,
+    /// Hash of the previous record, all zeros for the first record. Set when the record is
+    /// appended to the ledger; not set by the versions before the hash chain was introduced.
+    pub parent_hash: Option<TxHash>,
+    /// Hash of the record, see `TxRecord::compute_hash`.
+    pub hash: Option<TxHash>,
 }
 
 impl TxRecord {
@@ -49,6 +56,82 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Transfer,
             memo,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Transfer made by the `caller` on behalf of the owner of the `from` account.
+    pub fn transfer_from(
+        index: TxId,
+        caller: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        memo: Option<Memo>,
+    ) -> Self {
+        Self {
+            caller,
+            index,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee,
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::TransferFrom,
+            memo,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Allowance of the `spender` set by the owner of the `owner` account. The `amount` is the new
+    /// allowance, zero if it was removed.
+    pub fn approve(
+        index: TxId,
+        owner: AccountInternal,
+        spender: Principal,
+        amount: Tokens128,
+    ) -> Self {
+        Self {
+            caller: owner.owner,
+            index,
+            from: owner.into(),
+            to: spender.into(),
+            amount,
+            fee: 0.into(),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Approve,
+            memo: None,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Scheduled transfer made by the token on behalf of the payer of a standing order.
+    pub fn standing_order(
+        index: TxId,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+    ) -> Self {
+        Self {
+            caller: from.owner,
+            index,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee,
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::StandingOrder,
+            memo: None,
+            parent_hash: None,
+            hash: None,
         }
     }
 
@@ -69,6 +152,8 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Mint,
             memo: None,
+            parent_hash: None,
+            hash: None,
         }
     }
 
@@ -89,6 +174,8 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Burn,
             memo: None,
+            parent_hash: None,
+            hash: None,
         }
     }
 
@@ -104,7 +191,56 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Auction,
             memo: None,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Computes the hash of the record chained to the `parent_hash`: SHA-256 of the
+    /// concatenation of
+    /// - `parent_hash`,
+    /// - `index`, `amount`, `fee` and `timestamp` as big-endian integers (`u64`, `u128`, `u128`,
+    ///   `u64`),
+    /// - `caller`, `from.owner` and `to.owner` as the principal length byte and the principal
+    ///   bytes, each owner followed by its 32-byte subaccount (zeros if not set),
+    /// - the index of the `status` and of the `operation` variant as one byte each,
+    /// - the memo length as a big-endian `u32` followed by the memo bytes, or nothing if there is
+    ///   no memo.
+    ///
+    /// The `parent_hash` and `hash` fields are not hashed.
+    pub fn compute_hash(&self, parent_hash: &TxHash) -> TxHash {
+        let mut hasher = Sha256::new();
+        let principal = |hasher: &mut Sha256, principal: &Principal| {
+            let bytes = principal.as_slice();
+            hasher.update([bytes.len() as u8]);
+            hasher.update(bytes);
+        };
+        let account = |hasher: &mut Sha256, account: &Account| {
+            principal(hasher, &account.owner);
+            hasher.update(account.subaccount.unwrap_or_default());
+        };
+
+        hasher.update(parent_hash);
+        hasher.update(self.index.to_be_bytes());
+        principal(&mut hasher, &self.caller);
+        account(&mut hasher, &self.from);
+        account(&mut hasher, &self.to);
+        hasher.update(self.amount.amount.to_be_bytes());
+        hasher.update(self.fee.amount.to_be_bytes());
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update([self.status as u8, self.operation as u8]);
+        if let Some(memo) = &self.memo {
+            hasher.update((memo.len() as u32).to_be_bytes());
+            hasher.update(memo);
         }
+
+        hasher.finalize().into()
+    }
+
+    /// Returns true if the record hash matches its contents and the `parent_hash`.
+    pub fn verify_hash(&self, parent_hash: &TxHash) -> bool {
+        self.parent_hash.as_ref() == Some(parent_hash)
+            && self.hash == Some(self.compute_hash(parent_hash))
     }
 
     // This is a helper funntion to compare the principal of a transaction record.
@@ -112,6 +248,25 @@ impl TxRecord {
         self.caller == pid || self.from.owner == pid || self.to.owner == pid
     }
 
+    /// Distinct principals the record is related to, see `contains`.
+    pub fn participants(&self) -> Vec<Principal> {
+        let mut participants = vec![self.caller, self.from.owner, self.to.owner];
+        participants.sort();
+        participants.dedup();
+        participants
+    }
+
+    /// Distinct accounts the record moves the tokens between.
+    pub fn accounts(&self) -> Vec<AccountInternal> {
+        let from = AccountInternal::from(self.from);
+        let to = AccountInternal::from(self.to);
+        if from == to {
+            vec![from]
+        } else {
+            vec![from, to]
+        }
+    }
+
     pub fn claim(id: u64, from: AccountInternal, to: AccountInternal, amount: Tokens128) -> Self {
         Self {
             caller: to.owner,
@@ -124,6 +279,98 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Claim,
             memo: None,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Balance moved by the `guardian` from an account of a recovered principal to the same
+    /// subaccount of the new principal.
+    pub fn recovery(
+        index: TxId,
+        guardian: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+    ) -> Self {
+        Self {
+            caller: guardian,
+            index,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee: 0.into(),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Recovery,
+            memo: None,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Tokens left on the auction account moved to the `fee_to` account by the owner.
+    pub fn auction_residual(
+        index: TxId,
+        caller: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+    ) -> Self {
+        Self {
+            caller,
+            index,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee: 0.into(),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Auction,
+            memo: None,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Dust balance swept by the maintenance task to the `fee_to` account.
+    pub fn dust_sweep(
+        index: TxId,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+    ) -> Self {
+        Self {
+            caller: ic::id(),
+            index,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee: 0.into(),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::DustSweep,
+            memo: None,
+            parent_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Change of the token decimals. The `amount` is the total supply after the rescaling.
+    pub fn rescale(id: u64, caller: AccountInternal, total_supply: Tokens128) -> Self {
+        Self {
+            caller: caller.owner,
+            index: id,
+            from: caller.into(),
+            to: caller.into(),
+            amount: total_supply,
+            fee: 0.into(),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Rescale,
+            memo: None,
+            parent_hash: None,
+            hash: None,
         }
     }
 }
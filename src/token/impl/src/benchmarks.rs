@@ -0,0 +1,97 @@
+//! Endpoints used by the benchmarks in `src/benchmarks` to count the instructions of the token
+//! operations.
+//!
+//! Every `bench_*` method runs one operation and returns the number of instructions it took,
+//! measured inside the canister, so the numbers don't include the candid decoding or the system
+//! overhead of the call. Only available with the `benchmarks` feature: `bench_setup` writes
+//! arbitrary balances, so the benchmark wasm must never be deployed.
+
+use std::cell::Cell;
+
+use canister_sdk::ic_canister::{query, update};
+use ic_exports::Principal;
+use token_api::{
+    account::AccountInternal,
+    canister::TokenCanisterAPI,
+    state::{
+        balances::{Balances, StableBalances},
+        ledger::{BatchTransferArgs, LedgerData, TransferArgs},
+    },
+    tx_record::{TxId, TxRecord},
+};
+
+use crate::canister::TokenCanister;
+
+/// Balance of every holder created by `bench_setup`.
+const HOLDER_BALANCE: u128 = 1_000_000;
+
+impl TokenCanister {
+    /// Creates the holders `first..first + count` with `HOLDER_BALANCE` each, and writes a mint
+    /// record for every holder to the ledger. Called in chunks to build a large state.
+    #[update]
+    fn bench_setup(&self, first: u32, count: u32) {
+        let owner = AccountInternal::from(canister_sdk::ic_kit::ic::caller());
+        let mut records = Vec::with_capacity(count as usize);
+        for id in first..first.saturating_add(count) {
+            let holder = AccountInternal::from(bench_holder(id));
+            StableBalances.insert(holder, HOLDER_BALANCE.into());
+            records.push(TxRecord::mint(0, owner, holder, HOLDER_BALANCE.into()));
+        }
+        LedgerData::append(records);
+    }
+
+    #[update]
+    fn bench_icrc1_transfer(&self, transfer: TransferArgs) -> u64 {
+        measure(|| {
+            self.icrc1_transfer(transfer)
+                .expect("benchmark transfer failed");
+        })
+    }
+
+    #[update]
+    fn bench_batch_transfer(&self, transfers: Vec<BatchTransferArgs>) -> u64 {
+        measure(|| {
+            self.batch_transfer(None, transfers)
+                .expect("benchmark batch transfer failed");
+        })
+    }
+
+    #[query]
+    fn bench_get_transactions(&self, count: usize, transaction_id: Option<TxId>) -> u64 {
+        measure(|| {
+            self.get_transactions(None, count, transaction_id);
+        })
+    }
+
+    /// Instructions spent by the last `post_upgrade`.
+    #[query]
+    fn bench_post_upgrade(&self) -> u64 {
+        UPGRADE_INSTRUCTIONS.with(Cell::get)
+    }
+}
+
+/// Principal of the holder with the given id, the same as the one used by the benchmarks harness.
+pub fn bench_holder(id: u32) -> Principal {
+    let mut bytes = [0xbe; 29];
+    bytes[..4].copy_from_slice(&id.to_be_bytes());
+    Principal::from_slice(&bytes)
+}
+
+/// Stores the instructions spent by `post_upgrade` so far. Must be called at the end of it.
+pub fn record_upgrade() {
+    UPGRADE_INSTRUCTIONS.with(|cell| cell.set(instruction_counter()));
+}
+
+fn measure(f: impl FnOnce()) -> u64 {
+    let start = instruction_counter();
+    f();
+    instruction_counter().saturating_sub(start)
+}
+
+fn instruction_counter() -> u64 {
+    canister_sdk::ic_cdk::api::performance_counter(0)
+}
+
+thread_local! {
+    static UPGRADE_INSTRUCTIONS: Cell<u64> = Cell::new(0);
+}
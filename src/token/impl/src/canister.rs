@@ -4,8 +4,9 @@ use canister_sdk::{
         error::AuctionError,
         state::{AuctionInfo, AuctionState},
     },
-    ic_canister::{self, init, post_upgrade, pre_upgrade, Canister, PreUpdate},
+    ic_canister::{self, init, post_upgrade, pre_upgrade, query, Canister, PreUpdate},
     ic_helpers::tokens::Tokens128,
+    ic_kit::ic,
     ic_metrics::{Interval, Metrics, MetricsStorage},
     ic_storage::IcStorage,
 };
@@ -15,11 +16,17 @@ use ic_exports::Principal;
 use std::{cell::RefCell, rc::Rc};
 use token_api::{
     account::AccountInternal,
-    canister::{TokenCanisterAPI, DEFAULT_AUCTION_PERIOD_SECONDS},
+    canister::{
+        approvals, certified_balances, dust, http, is20_auction, outbound, standing_orders,
+        status_report, timelock, TokenCanisterAPI, DEFAULT_AUCTION_PERIOD_SECONDS,
+    },
     state::{
         balances::{Balances, StableBalances},
         config::{Metadata, TokenConfig},
         ledger::LedgerData,
+        metrics::EndpointMetrics,
+        migrations::{StateMigrations, UPGRADE_INSTRUCTION_LIMIT},
+        upgrade_chunks::UpgradeChunks,
     },
 };
 
@@ -31,21 +38,42 @@ pub struct TokenCanister {
 }
 
 impl TokenCanister {
+    #[query]
+    fn pkg_version(&self) -> &'static str {
+        option_env!("CARGO_PKG_VERSION").unwrap_or("NOT_FOUND")
+    }
+
     #[init]
     pub fn init(&self, metadata: Metadata, amount: Tokens128) {
         let owner = metadata.owner;
         let owner_account = AccountInternal::new(owner, None);
 
-        StableBalances.clear();
-        StableBalances.insert(owner_account, amount);
-
-        LedgerData::mint(
-            AccountInternal::from(owner),
-            AccountInternal::from(owner),
-            amount,
-        );
+        let distribution = match &metadata.init_spec {
+            Some(spec) => {
+                if let Err(e) = spec.validate(amount) {
+                    ic::trap(&e.to_string());
+                }
+                spec.distribution.clone()
+            }
+            None => Vec::new(),
+        };
 
-        TokenConfig::set_stable(metadata.into());
+        StableBalances.clear();
+        if distribution.is_empty() {
+            StableBalances.insert(owner_account, amount);
+            LedgerData::mint(owner_account, owner_account, amount);
+        } else {
+            for (account, amount) in distribution {
+                StableBalances.insert(account.into(), amount);
+                LedgerData::mint(owner_account, account.into(), amount);
+            }
+        }
+
+        TokenConfig::set_stable(TokenConfig {
+            deployer: Some(canister_sdk::ic_kit::ic::caller()),
+            ..metadata.into()
+        });
+        http::update_certified_data();
 
         let auction_state = self.auction_state();
         auction_state.replace(AuctionState::new(
@@ -54,6 +82,8 @@ impl TokenCanister {
             },
             owner,
         ));
+
+        self.start_timers();
     }
 
     #[pre_upgrade]
@@ -64,6 +94,33 @@ impl TokenCanister {
     #[post_upgrade]
     fn post_upgrade(&self) {
         // All required canister state stored in stable memory, so no need to save/load anything.
+        // The state written by the older versions is migrated to the current layout. The
+        // migrations which don't fit in the instruction limit are continued by
+        // `run_state_migrations`.
+        StateMigrations::run(UPGRADE_INSTRUCTION_LIMIT);
+
+        // The module uploaded for the self-upgrade is not needed anymore.
+        UpgradeChunks::clear();
+
+        // Certified data is not preserved on upgrade though, so it must be set again.
+        http::update_certified_data();
+
+        // Timers are not preserved on upgrade either.
+        self.start_timers();
+
+        #[cfg(feature = "benchmarks")]
+        crate::benchmarks::record_upgrade();
+    }
+
+    fn start_timers(&self) {
+        let canister = self.clone();
+        standing_orders::start_standing_orders(move || canister.fee_ratio());
+        dust::start_dust_maintenance();
+        timelock::start_timelock();
+        outbound::start_outbound_delivery();
+        status_report::start_status_report();
+        certified_balances::start_balance_certification();
+        approvals::start_allowance_sweeper();
     }
 }
 
@@ -83,13 +140,40 @@ fn inspect_message() {
 
     match accept_reason {
         AcceptReason::Valid => ic_cdk::api::call::accept_message(),
+        #[cfg(feature = "benchmarks")]
+        AcceptReason::NotIS20Method if method.starts_with("bench_") => {
+            ic_cdk::api::call::accept_message()
+        }
         AcceptReason::NotIS20Method => ic_cdk::trap("Unknown method"),
     }
 }
 
 impl PreUpdate for TokenCanister {
     fn pre_update(&self, method_name: &str, method_type: ic_canister::MethodType) {
-        <Self as Auction>::canister_pre_update(self, method_name, method_type);
+        EndpointMetrics::record_call(method_name);
+        let auction_state = self.auction_state();
+        is20_auction::update_cycle_accounting(
+            &mut auction_state.borrow_mut(),
+            ic::time(),
+            ic::balance(),
+        );
+        let disbursement = is20_auction::check_disbursement(&auction_state.borrow(), ic::balance());
+        match method_name {
+            "bid_cycles" => is20_auction::check_bid(&auction_state.borrow()),
+            "run_auction" => {
+                if let Err(e) = &disbursement {
+                    ic::trap(&e.to_string());
+                }
+            }
+            _ => {}
+        }
+
+        // The auction is held when the token has the cycles only, the bids are kept till then.
+        if disbursement.is_ok() {
+            EndpointMetrics::measure_maintenance(|| {
+                <Self as Auction>::canister_pre_update(self, method_name, method_type)
+            });
+        }
         self.update_metrics();
     }
 }
@@ -102,7 +186,7 @@ impl Auction for TokenCanister {
     }
 
     fn disburse_rewards(&self) -> Result<AuctionInfo, AuctionError> {
-        token_api::canister::is20_auction::disburse_rewards(&self.auction_state().borrow())
+        is20_auction::disburse_rewards(&self.auction_state().borrow())
     }
 }
 
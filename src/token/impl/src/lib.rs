@@ -1,4 +1,6 @@
 #![cfg_attr(coverage_nightly, feature(no_coverage))]
+#[cfg(feature = "benchmarks")]
+pub mod benchmarks;
 pub mod canister;
 
 /// This is a marker added to the token wasm to distinguish it from other canisters
@@ -6,9 +8,18 @@ pub mod canister;
 #[no_mangle]
 pub static TOKEN_CANISTER_MARKER: &str = "IS20_TOKEN_CANISTER";
 
+pub use token_api::bindings::BindingsFormat;
+
 pub fn idl() -> String {
+    bindings(BindingsFormat::Candid)
+}
+
+/// Renders the interface of the token in the `format`, see `token_api::bindings`.
+pub fn bindings(format: BindingsFormat) -> String {
     use crate::canister::TokenCanister;
     use canister_sdk::{ic_auction::api::Auction, ic_canister::Idl, ic_helpers::tokens::Tokens128};
+    use token_api::bindings::Annotation;
+    use token_api::canister::inspect::{OWNER_METHODS, TRANSACTION_METHODS};
     use token_api::canister::TokenCanisterAPI;
     use token_api::state::config::Metadata;
 
@@ -18,7 +29,17 @@ pub fn idl() -> String {
     trait_idl.merge(&canister_idl);
     trait_idl.merge(&auction_idl);
 
-    candid::bindings::candid::compile(&trait_idl.env.env, &Some(trait_idl.actor))
+    let annotations = [
+        Annotation {
+            name: "owner_only",
+            methods: OWNER_METHODS,
+        },
+        Annotation {
+            name: "holders_only",
+            methods: TRANSACTION_METHODS,
+        },
+    ];
+    token_api::bindings::render(&trait_idl.env.env, trait_idl.actor, format, &annotations)
 }
 
 #[cfg(test)]
@@ -31,11 +52,21 @@ mod tests {
         let idl = idl();
         let methods = [
             "icrc1_balance_of",
+            "get_certified_balance",
             "decimals",
             "get_holders",
             "get_token_info",
             "get_transaction",
             "get_transactions",
+            "export_transactions",
+            "start_export",
+            "release_export",
+            "export_holders",
+            "export_session_transactions",
+            "query_blocks",
+            "query_encoded_blocks",
+            "get_account_transactions",
+            "get_subaccount_transactions",
             "get_user_transaction_count",
             "history_size",
             "icrc1_name",
@@ -45,6 +76,24 @@ mod tests {
             "is_test_token",
             "set_fee",
             "set_fee_to",
+            "set_tx_window",
+            "set_input_limits",
+            "get_input_limits",
+            "set_timelock_delay",
+            "set_burn_policy",
+            "get_burn_policy",
+            "set_fee_policy",
+            "get_fee_policy",
+            "set_fee_recipients",
+            "get_fee_distribution",
+            "get_transfer_fee",
+            "get_timelock_delay",
+            "list_pending_changes",
+            "cancel_proposal",
+            "get_outbound_status",
+            "get_dead_letters",
+            "retry_outbound_message",
+            "drop_outbound_message",
             "set_name",
             "set_symbol",
             "set_owner",
@@ -57,6 +106,154 @@ mod tests {
             "set_auction_period",
             "set_controller",
             "set_min_cycles",
+            "http_request",
+            "transfer_signed",
+            "get_transfer_nonce",
+            "approve",
+            "get_allowance",
+            "get_allowance_count",
+            "get_user_approvals",
+            "get_spender_allowances",
+            "get_allowance_sweep_stats",
+            "freeze_account",
+            "unfreeze_account",
+            "is_frozen",
+            "purge_account_data",
+            "get_admin_log",
+            "set_minting_account",
+            "get_history_info",
+            "set_history_retention",
+            "prune_transactions",
+            "create_subscription",
+            "cancel_subscription",
+            "collect_subscription",
+            "authorize_merchant",
+            "revoke_merchant",
+            "pull_payment",
+            "get_merchant_authorizations",
+            "approve_and_notify",
+            "retry_approval_notification",
+            "permit",
+            "get_permit_nonce",
+            "get_approval_notification",
+            "transfer_from_allowance",
+            "create_budget",
+            "set_budget_limit",
+            "set_budget_delegates",
+            "remove_budget",
+            "budget_transfer",
+            "get_budgets",
+            "set_voting_exclusions",
+            "get_voting_exclusions",
+            "take_snapshot",
+            "get_snapshot",
+            "get_voting_power",
+            "total_voting_power",
+            "get_subscription",
+            "get_subscriptions",
+            "create_standing_order",
+            "pause_standing_order",
+            "resume_standing_order",
+            "cancel_standing_order",
+            "get_standing_order",
+            "get_standing_orders",
+            "set_guardian",
+            "get_guardian",
+            "initiate_recovery",
+            "veto_recovery",
+            "complete_recovery",
+            "get_recovery",
+            "get_recovery_log",
+            "set_dust_policy",
+            "get_dust_report",
+            "create_swap",
+            "accept_swap",
+            "refund_swap",
+            "get_swap",
+            "get_swaps",
+            "get_swap_escrow",
+            "transfer_with_expiry",
+            "accept_transfer",
+            "reclaim_transfer",
+            "get_expiring_transfer",
+            "get_expiring_transfers",
+            "set_auction_strategy",
+            "get_auction_strategy",
+            "list_auctions",
+            "withdraw_auction_residual",
+            "list_auction_residual_withdrawals",
+            "set_auction_retention",
+            "set_auction_vesting",
+            "get_auction_vesting",
+            "get_vested_rewards",
+            "claim_vested_rewards",
+            "set_min_bid_cycles",
+            "get_min_bid_cycles",
+            "get_cycle_consumption",
+            "set_bidder_blacklisted",
+            "get_bidder_blacklist",
+            "set_bid_delegate",
+            "accept_bid_delegation",
+            "resign_bid_delegation",
+            "get_bid_delegate",
+            "auction_projection",
+            "my_bid_status",
+            "set_localized_metadata",
+            "get_transactions_by_memo",
+            "set_memo_index",
+            "verify_invariants",
+            "repair_invariants",
+            "rescale_decimals",
+            "get_decimals_migration",
+            "get_burn_account",
+            "get_burned_total",
+            "get_state_version",
+            "get_state_migration_status",
+            "run_state_migrations",
+            "list_tagged_subaccounts",
+            "get_account_identifier",
+            "resolve_account_identifier",
+            "get_account_tags",
+            "set_account_tag",
+            "atomic_batch",
+            "withdraw_cycles_to_deployer",
+            "update_metadata_from_deployer",
+            "get_balances",
+            "compact_ledger",
+            "get_maintenance_status",
+            "set_large_transfer_policy",
+            "get_large_transfer_policy",
+            "initiate_large_transfer",
+            "confirm_large_transfer",
+            "cancel_large_transfer",
+            "get_pending_transfers",
+            "set_faucet_policy",
+            "faucet_status",
+            "renounce_ownership",
+            "create_payment_request",
+            "pay_request",
+            "cancel_payment_request",
+            "get_payment_request",
+            "get_payment_requests",
+            "parse_amount",
+            "format_amount",
+            "get_metrics",
+            "get_endpoint_stats",
+            "reset_endpoint_stats",
+            "get_memory_report",
+            "get_ledger_tip_hash",
+            "verify_chain",
+            "list_controllers",
+            "get_canister_status_report",
+            "add_controller",
+            "handover",
+            "remove_controller",
+            "upload_upgrade_chunk",
+            "clear_upgrade_chunks",
+            "get_upgrade_module_status",
+            "commit_upgrade",
+            "get_exchange_deposit_account",
+            "sweep_subaccounts",
         ];
 
         for method in methods {
@@ -67,4 +264,24 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn json_abi_annotates_owner_methods() {
+        let abi = bindings(BindingsFormat::Json);
+        let abi: serde_json::Value = serde_json::from_str(&abi).unwrap();
+        let methods = abi["methods"].as_array().unwrap();
+        let method = |name: &str| {
+            methods
+                .iter()
+                .find(|method| method["name"] == name)
+                .unwrap_or_else(|| panic!("method {name} is missing"))
+        };
+
+        assert_eq!(method("set_fee")["annotations"][0], "owner_only");
+        assert_eq!(method("icrc1_balance_of")["mode"], "query");
+        assert!(method("icrc1_balance_of")["annotations"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+    }
 }
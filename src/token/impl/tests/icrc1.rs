@@ -40,6 +40,7 @@ fn init() -> (Metadata, TokenCanister, &'static mut MockContext) {
         symbol: "TST".into(),
         owner: alice(),
         is_test_token: None,
+        init_spec: None,
     };
     canister.init(meta.clone(), 1_000_000_000.into());
     (meta, canister, context)